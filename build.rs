@@ -4,4 +4,18 @@
 
 fn main() {
     println!("cargo:rerun-if-changed=src/bldb.ld");
+    if std::env::var_os("CARGO_FEATURE_BUILTIN_RAMDISK").is_some() {
+        builtin_ramdisk();
+    }
+}
+
+/// Tells the compiler where to find the built-in ramdisk image.
+fn builtin_ramdisk() {
+    println!("cargo:rerun-if-env-changed=BLDB_BUILTIN_CPIO");
+    let path = std::env::var("BLDB_BUILTIN_CPIO").unwrap_or_else(|_| {
+        let root = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        format!("{root}/builtin.cpio")
+    });
+    println!("cargo:rerun-if-changed={path}");
+    println!("cargo:rustc-env=BLDB_BUILTIN_CPIO={path}");
 }
@@ -135,6 +135,11 @@ impl BumpAlloc {
         self.arena.as_ptr()
     }
 
+    /// Returns the number of bytes not yet handed out.
+    pub(crate) fn remaining(&self) -> usize {
+        self.arena.len() - self.cursor.load(Ordering::Relaxed)
+    }
+
     /// Returns the range of addresses in the heap, for
     /// validating that an integral value lies within
     /// the heap.
@@ -227,6 +232,25 @@ impl Header {
     }
 }
 
+/// A summary of how a QuickFit heap is being used: its total
+/// size, how much of the tail has never been allocated, and how
+/// much has been freed to the quick and misc lists.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Usage {
+    pub(crate) size: usize,
+    pub(crate) tail: usize,
+    pub(crate) quick: usize,
+    pub(crate) misc: usize,
+}
+
+impl Usage {
+    /// Returns the number of bytes that are free to allocate,
+    /// though not necessarily in a single block.
+    pub(crate) fn free(&self) -> usize {
+        self.tail + self.quick + self.misc
+    }
+}
+
 /// The QuickFit allocator itself.  The allocator takes
 /// ownership of a bump allocator for the tail, and contains a
 /// set of lists for the quick blocks, as well as a misc list
@@ -262,6 +286,26 @@ impl QuickFit {
             .unwrap_or(ptr::null_mut())
     }
 
+    /// Returns a summary of the heap's use.  This walks the
+    /// free lists, but does not allocate.
+    pub(crate) fn usage(&self) -> Usage {
+        fn sum(mut list: Option<NonNull<Header>>) -> usize {
+            let mut total = 0;
+            while let Some(node) = list {
+                let node = unsafe { node.as_ref() };
+                total += node.size;
+                list = node.next;
+            }
+            total
+        }
+        Usage {
+            size: self.tail.arena.len(),
+            tail: self.tail.remaining(),
+            quick: self.qlists.iter().map(|&list| sum(list)).sum(),
+            misc: sum(self.misc),
+        }
+    }
+
     /// Adjusts the given layout so that blocks allocated from
     /// one of the quick lists are appropriately sized and
     /// aligned.  Otherwise, returns the original size and
@@ -546,6 +590,28 @@ impl QuickFit {
     }
 }
 
+#[cfg(test)]
+mod quick_tests {
+    use super::{AlignedHeap, Block, BumpAlloc, Layout, QuickFit};
+
+    #[test]
+    fn usage() {
+        let mut heap = AlignedHeap::<4096>::new();
+        let block =
+            unsafe { Block::new_from_raw_parts((&raw mut heap).cast(), 4096) };
+        let mut quick = QuickFit::new(BumpAlloc::new(block));
+        let base = quick.usage();
+        assert_eq!(base.size, 4096);
+        assert_eq!(base.free(), base.tail + base.quick);
+        let layout = Layout::from_size_align(64, 64).unwrap();
+        let p = quick.malloc(layout);
+        assert!(!p.is_null());
+        assert_eq!(quick.usage().free(), base.free() - 64);
+        quick.free(p, layout);
+        assert_eq!(quick.usage().free(), base.free());
+    }
+}
+
 #[cfg(test)]
 mod bump_tests {
     use super::{Block, BumpAlloc};
@@ -574,6 +640,8 @@ impl<const SIZE: usize> AlignedHeap<SIZE> {
     }
 }
 
+pub(crate) use global::usage;
+
 mod global {
     use super::{Block, BumpAlloc, QuickFit};
     use alloc::alloc::{GlobalAlloc, Layout};
@@ -618,6 +686,19 @@ mod global {
         }
     }
 
+    /// Returns a summary of the global heap's use, or `None`
+    /// if the allocator is busy, as when we fail while inside
+    /// of it.
+    pub(crate) fn usage() -> Option<super::Usage> {
+        let a = GLOBAL_ALLOCATOR.0.swap(ptr::null_mut(), Ordering::Relaxed);
+        if a.is_null() {
+            return None;
+        }
+        let usage = unsafe { &*a }.usage();
+        GLOBAL_ALLOCATOR.0.swap(a, Ordering::Relaxed);
+        Some(usage)
+    }
+
     #[cfg_attr(not(test), global_allocator)]
     static GLOBAL_ALLOCATOR: GlobalQuickAlloc =
         GlobalQuickAlloc(AtomicPtr::new({
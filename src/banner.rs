@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The banner printed as the REPL starts.
+//!
+//! Console logs are often captured and read long after the
+//! fact, by someone without access to the machine, so the
+//! banner records what is needed to interpret the rest: the
+//! processor, the clock the loader is timing things with, where
+//! everything is in memory, the console's settings, and the
+//! results of some quick checks that the loader's own
+//! foundations are sound.
+
+use crate::bldb;
+use crate::clock;
+use crate::cpuid;
+use crate::ident;
+use crate::uart;
+use crate::wdt;
+use crate::{print, println};
+use alloc::vec;
+
+/// The range of TSC frequencies we believe, in Hertz.
+const PLAUSIBLE_HZ: core::ops::RangeInclusive<u128> =
+    100_000_000..=10_000_000_000;
+
+/// Returns true iff the clock's frequency is plausible and two
+/// successive readings of the TSC show it advancing.
+fn clock_sane(hz: u128, t0: u64, t1: u64) -> bool {
+    PLAUSIBLE_HZ.contains(&hz) && t1 > t0
+}
+
+/// Checks that the heap can allocate, hold, and free memory.
+fn heap_ok() -> bool {
+    const LEN: usize = 64 * 1024;
+    let mut buf = vec![0u8; LEN];
+    for (k, b) in buf.iter_mut().enumerate() {
+        *b = k as u8 ^ 0x5a;
+    }
+    buf.iter().enumerate().all(|(k, &b)| b == k as u8 ^ 0x5a)
+}
+
+/// Checks that each of the regions the loader maps for itself is
+/// mapped as it should be.
+fn page_table_ok(config: &bldb::Config) -> bool {
+    config.regions().into_iter().all(|(_, region)| {
+        let range = region.start()..region.end();
+        config.page_table.is_region_mapped(range, region.attrs())
+    })
+}
+
+fn verdict(ok: bool) -> &'static str {
+    if ok { "ok" } else { "FAILED" }
+}
+
+pub(crate) fn print(config: &bldb::Config) {
+    println!();
+    println!("Oxide Boot Loader/Debugger");
+    let ident = ident::ident();
+    if ident.is_packaged() {
+        println!("build: {}", ident.build_id);
+    } else {
+        println!("build: unpackaged");
+    }
+    match cpuid::cpuinfo() {
+        Some((family, model, stepping, pkg)) => {
+            print!("cpu: family {family:#x} model {model:#x} ");
+            print!("stepping {stepping:#x}");
+            match pkg {
+                Some(pkg) => println!(" package type {pkg:#x}"),
+                None => println!(),
+            }
+        }
+        None => println!("cpu: unknown"),
+    }
+    match &config.board {
+        Some(board) => println!("board: {board}"),
+        None => println!("board: no board ID profile for this platform"),
+    }
+    if wdt::petting() {
+        println!("watchdog: running; petted during long operations");
+    }
+    let hz = clock::frequency();
+    let source =
+        if cpuid::tscinfo().is_some_and(|tsc| tsc.tsc_frequency().is_some()) {
+            "from cpuid"
+        } else {
+            "assumed"
+        };
+    println!("tsc: {} MHz ({source})", hz / 1_000_000);
+    println!(
+        "console: uart at {:#x}, {}",
+        config.cons.addr(),
+        uart::cons_line()
+    );
+    println!("memory:");
+    for (name, region) in config.regions() {
+        println!(
+            "    {name:<8} {:#x}..{:#x}",
+            region.start().addr(),
+            region.end().addr()
+        );
+    }
+    let t0 = clock::rdtsc();
+    let t1 = clock::rdtsc();
+    let checks = [
+        ("heap", heap_ok()),
+        ("page table", page_table_ok(config)),
+        ("clock", clock_sane(hz, t0, t1)),
+    ];
+    print!("self-check:");
+    for (name, ok) in checks {
+        print!(" {name} {}", verdict(ok));
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks() {
+        assert!(heap_ok());
+        assert!(clock_sane(2_000_000_000, 1, 2));
+        assert!(!clock_sane(2_000_000_000, 2, 2));
+        assert!(!clock_sane(1_000, 1, 2));
+        assert_eq!(verdict(false), "FAILED");
+    }
+}
@@ -0,0 +1,159 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Boot progress beacon.
+//!
+//! When no serial console is attached, it is still useful to
+//! know how far the loader got.  If a GPIO pin is configured
+//! for the beacon (typically one driving an LED), we emit a
+//! distinct number of pulses on it as we enter each phase of
+//! boot, and then leave it lit.  The pin defaults to the one
+//! named by the board's profile, if any, and may be changed or
+//! disabled from the REPL.
+//!
+//! Signalling a phase must not hold up boot, so a pattern lasts
+//! no more than a few milliseconds: too short to follow by eye
+//! on an LED, which merely flickers, but easily read with a
+//! logic analyzer or oscilloscope on the pin.
+
+use crate::clock;
+use crate::gpio;
+use crate::iomux;
+use core::fmt;
+use core::time::Duration;
+
+/// How long the beacon pin is held high, then low, per pulse.
+const PULSE: Duration = Duration::from_micros(250);
+
+/// The pause before and after each pattern, so that
+/// back-to-back phases remain distinguishable.
+const GAP: Duration = Duration::from_millis(1);
+
+/// The most pulses a phase may be given, which bounds how long
+/// signalling it takes.
+pub(crate) const MAX_PULSES: u8 = 8;
+
+/// Phases of boot that the beacon signals.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Phase {
+    EnteredRust,
+    ConsoleUp,
+    ReplReady,
+    Receiving,
+    Loading,
+    Handoff,
+}
+
+impl Phase {
+    pub(crate) const ALL: [Phase; 6] = [
+        Phase::EnteredRust,
+        Phase::ConsoleUp,
+        Phase::ReplReady,
+        Phase::Receiving,
+        Phase::Loading,
+        Phase::Handoff,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Phase::EnteredRust => "rust",
+            Phase::ConsoleUp => "console",
+            Phase::ReplReady => "repl",
+            Phase::Receiving => "receiving",
+            Phase::Loading => "loading",
+            Phase::Handoff => "handoff",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Phase> {
+        Self::ALL.into_iter().find(|phase| phase.name() == name)
+    }
+}
+
+/// A configured beacon.
+pub(crate) struct Beacon {
+    pin: u8,
+    function: iomux::PinFunction,
+    pulses: [u8; Phase::ALL.len()],
+}
+
+impl Beacon {
+    /// Returns a new beacon on the given pin, with the default
+    /// pattern of one pulse for the first phase, two for the
+    /// second, and so on.
+    pub(crate) fn new(pin: u8, function: iomux::PinFunction) -> Beacon {
+        Beacon { pin, function, pulses: [1, 2, 3, 4, 5, 6] }
+    }
+
+    /// Configures the IO mux and GPIO for the beacon pin as a
+    /// low output.
+    ///
+    /// # Safety
+    /// The caller must ensure that the pin is not otherwise in
+    /// use, and that driving it does no harm.
+    pub(crate) unsafe fn init(
+        &self,
+        iomux: &mut iomux::IoMux,
+        gpios: &mut gpio::Gpios,
+    ) {
+        unsafe {
+            iomux.set_pin(self.pin, self.function);
+        }
+        self.set(gpios, gpio::PinStatus::Low);
+    }
+
+    /// Sets the number of pulses for the given phase, at most
+    /// `MAX_PULSES`.  Zero silences the phase.
+    pub(crate) fn set_pulses(&mut self, phase: Phase, pulses: u8) {
+        self.pulses[phase as usize] = pulses.min(MAX_PULSES);
+    }
+
+    /// Signals entry to the given phase.  This takes at most
+    /// `2 * GAP + 2 * MAX_PULSES * PULSE`, or 6ms.
+    pub(crate) fn signal(&self, gpios: &mut gpio::Gpios, phase: Phase) {
+        let pulses = self.pulses[phase as usize];
+        if pulses == 0 {
+            return;
+        }
+        self.set(gpios, gpio::PinStatus::Low);
+        clock::delay(GAP);
+        for _ in 0..pulses {
+            self.set(gpios, gpio::PinStatus::High);
+            clock::delay(PULSE);
+            self.set(gpios, gpio::PinStatus::Low);
+            clock::delay(PULSE);
+        }
+        clock::delay(GAP);
+        self.set(gpios, gpio::PinStatus::High);
+    }
+
+    /// Turns the beacon off.
+    pub(crate) fn off(&self, gpios: &mut gpio::Gpios) {
+        self.set(gpios, gpio::PinStatus::Low);
+    }
+
+    fn set(&self, gpios: &mut gpio::Gpios, status: gpio::PinStatus) {
+        let reg = gpios
+            .get_pin(self.pin)
+            .with_pull_up_enable(false)
+            .with_pull_down_enable(false)
+            .with_active_level(gpio::ActiveLevel::High)
+            .with_output_value(status)
+            .with_output_enable(true);
+        unsafe {
+            gpios.set_pin(self.pin, reg);
+        }
+    }
+}
+
+impl fmt::Debug for Beacon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Beacon(pin {} {:?}) {{", self.pin, self.function)?;
+        for phase in Phase::ALL {
+            let pulses = self.pulses[phase as usize];
+            write!(f, " {}: {pulses}", phase.name())?;
+        }
+        write!(f, " }}")
+    }
+}
@@ -38,19 +38,29 @@
 
 extern crate alloc;
 
+use crate::beacon;
+use crate::board;
 use crate::cons;
 use crate::gpio;
+use crate::hw;
 use crate::idt;
 use crate::iomux;
+use crate::ipcc;
+use crate::loader;
 use crate::mem;
 use crate::mmu;
+use crate::post;
 use crate::ramdisk;
 use crate::repl;
 use crate::result::Error;
+use crate::symbols;
+use crate::tpm;
 use crate::uart::{self, Uart};
+use crate::wdt;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt;
 use core::ops::Range;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -63,18 +73,160 @@ pub(crate) struct Config {
     pub(crate) cons: Uart,
     pub(crate) iomux: &'static mut iomux::IoMux,
     pub(crate) gpios: &'static mut gpio::Gpios,
+    /// I/O ports and MSRs, as reached from the REPL.
+    pub(crate) hw: &'static dyn hw::Hardware,
+    /// The board, as identified by its straps at init, if the
+    /// platform has a profile.
+    pub(crate) board: Option<board::Ident>,
     pub(crate) loader_region: Range<mem::V4KA>,
+    pub(crate) xfer_region: Range<mem::V4KA>,
+    pub(crate) ramdisk_region: Range<mem::V4KA>,
+    /// Memory set aside for experiments, so that they need not
+    /// borrow the transfer or ramdisk regions and clobber what
+    /// is staged there.
+    pub(crate) scratch_region: Range<mem::V4KA>,
+    pub(crate) regions_claimed: bool,
     pub(crate) page_table: mmu::LoaderPageTable,
-    pub(crate) ramdisk: Option<Box<dyn ramdisk::FileSystem>>,
+    /// The ramdisks mounted, and where.
+    pub(crate) mounts: ramdisk::Mounts,
+    /// The image of diagnostic payloads built into the loader,
+    /// if any, whose files appear under `/builtin`.
+    pub(crate) builtin: Option<Box<dyn ramdisk::FileSystem>>,
     pub(crate) prompt: cons::Prompt,
+    pub(crate) prompt_segments: Vec<repl::Segment>,
+    /// Whether the last command line failed.
+    pub(crate) last_failed: bool,
     pub(crate) aliases: BTreeMap<String, String>,
+    pub(crate) beacon: Option<beacon::Beacon>,
+    /// The NUL-terminated boot environment string passed to
+    /// images entered via `call`, if one has been set.
+    pub(crate) bootenv: Option<Box<[u8]>>,
+    pub(crate) idle: Option<repl::Idle>,
+    pub(crate) access_log: repl::AccessLog,
+    /// Images loaded from the REPL, whose text ranges are used
+    /// to sanity check the targets of `call`.
+    pub(crate) images: Vec<loader::Loaded>,
+    /// Registers modified from the REPL.
+    pub(crate) modified: repl::Modified,
+    /// Whether destructive operations must be confirmed.
+    pub(crate) confirm: bool,
+    /// The IPCC channel to the SP, once opened.
+    pub(crate) ipcc: Option<ipcc::Ipcc>,
+    /// The UART over which files are transferred, if not the
+    /// console.
+    pub(crate) xfer: Option<Uart>,
+    /// Measurements of images loaded and called from the REPL.
+    pub(crate) measurements: tpm::Measurements,
+    /// Regions of memory saved by `dump`.
+    pub(crate) dumps: repl::Dumps,
+    /// What happened during the last ZMODEM receive.
+    pub(crate) rz_stats: repl::RzStats,
+    /// The most recent results of command lines.
+    pub(crate) results: repl::Results,
+    /// The symbol table read with `syms`, if any.
+    pub(crate) symbols: symbols::Symbols,
 }
 
 impl Config {
-    pub fn mount(&mut self, ramdisk: &'static [u8]) -> Result<(), Error> {
-        self.ramdisk = Some(ramdisk::mount(ramdisk)?);
+    /// Mounts the ramdisk held in the given memory at the given
+    /// directory, read-only.
+    pub fn mount(
+        &mut self,
+        ramdisk: &'static [u8],
+        dir: &str,
+    ) -> Result<(), Error> {
+        let buf = self.page_table.buf(ramdisk);
+        let fs = ramdisk::mount(ramdisk)?;
+        self.mounts.mount(dir, ramdisk::Mounted::new(fs, buf))
+    }
+
+    /// Mounts the ramdisk held in the given writable memory at
+    /// the given directory.  A UFS ramdisk may then be written.
+    pub fn mount_mut(
+        &mut self,
+        ramdisk: &'static mut [u8],
+        dir: &str,
+    ) -> Result<(), Error> {
+        let buf = self.page_table.buf(ramdisk);
+        let fs = ramdisk::mount_mut(ramdisk)?;
+        self.mounts.mount(dir, ramdisk::Mounted::new(fs, buf))
+    }
+
+    /// Signals entry to the given boot phase on the beacon, if
+    /// one is configured.
+    pub(crate) fn signal(&mut self, phase: beacon::Phase) {
+        if let Some(beacon) = &self.beacon {
+            beacon.signal(self.gpios, phase);
+        }
+    }
+
+    /// Zeroes and returns a mutable slice over the ramdisk region.
+    pub(crate) fn ramdisk_region_init_mut(&mut self) -> &'static mut [u8] {
+        self.regions_claimed = true;
+        let range = &self.ramdisk_region;
+        zeroed_region_mut(range.start.addr(), range.end.addr())
+    }
+
+    /// Zeroes and returns a mutable slice over the transfer region.
+    pub(crate) fn xfer_region_init_mut(&mut self) -> &'static mut [u8] {
+        self.regions_claimed = true;
+        let range = &self.xfer_region;
+        zeroed_region_mut(range.start.addr(), range.end.addr())
+    }
+
+    /// Zeroes and returns a mutable slice over the scratch region.
+    pub(crate) fn scratch_region_init_mut(&mut self) -> &'static mut [u8] {
+        self.regions_claimed = true;
+        let range = &self.scratch_region;
+        zeroed_region_mut(range.start.addr(), range.end.addr())
+    }
+
+    /// Returns the current placement of the transfer, ramdisk,
+    /// and scratch regions.
+    pub(crate) fn layout(&self) -> Layout {
+        Layout {
+            xfer: self.xfer_region.clone(),
+            ramdisk: self.ramdisk_region.clone(),
+            scratch: self.scratch_region.clone(),
+        }
+    }
+
+    /// Moves the transfer, ramdisk, and scratch regions to the
+    /// given layout.  This is only permitted before any region
+    /// has been handed out, as slices over the old regions would
+    /// otherwise dangle.  The layout is checked in full before
+    /// the page table is touched.
+    pub(crate) fn set_layout(&mut self, layout: Layout) -> Result<(), Error> {
+        if self.regions_claimed {
+            return Err(Error::RegionBusy);
+        }
+        layout.validate()?;
+        let old = self.layout().regions();
+        let new = layout.regions();
+        unsafe {
+            self.page_table.replace_reserved(
+                &old,
+                &new,
+                mem::Attrs::new_data(),
+            )?;
+        }
+        let Layout { xfer, ramdisk, scratch } = layout;
+        self.xfer_region = xfer;
+        self.ramdisk_region = ramdisk;
+        self.scratch_region = scratch;
         Ok(())
     }
+
+    /// Returns the regions of the address space that the loader
+    /// maps for itself, by name, as they should currently be.
+    pub(crate) fn regions(&self) -> [(&'static str, mem::Region); NREGIONS] {
+        regions(
+            self.xfer_region.clone(),
+            self.ramdisk_region.clone(),
+            self.scratch_region.clone(),
+            mem::V4KA::new(self.cons.addr()),
+        )
+    }
 }
 
 impl fmt::Debug for Config {
@@ -83,16 +235,70 @@ impl fmt::Debug for Config {
         writeln!(f, "    cons:   Uart({:x}),", self.cons.addr())?;
         writeln!(f, "    iomux:  {:#x?}", self.iomux)?;
         writeln!(f, "    gpios:  {:#x?}", self.gpios)?;
+        match &self.board {
+            Some(board) => writeln!(f, "    board:  {board}")?,
+            None => writeln!(f, "    board:  unidentified")?,
+        }
         let vstart = self.loader_region.start.addr();
         let vend = self.loader_region.end.addr();
         writeln!(f, "    loader: {:#x?}", vstart..vend)?;
+        let xstart = self.xfer_region.start.addr();
+        let xend = self.xfer_region.end.addr();
+        writeln!(f, "    xfer:   {:#x?}", xstart..xend)?;
+        let rstart = self.ramdisk_region.start.addr();
+        let rend = self.ramdisk_region.end.addr();
+        writeln!(f, "    rdisk:  {:#x?}", rstart..rend)?;
+        let sstart = self.scratch_region.start.addr();
+        let send = self.scratch_region.end.addr();
+        writeln!(f, "    scratch: {:#x?}", sstart..send)?;
         writeln!(f, "    pageroot: P4KA({:#x}),", self.page_table.phys_addr())?;
+        if self.mounts.is_empty() {
+            writeln!(f, "    ramdisk: None")?;
+        }
+        for (dir, mounted) in self.mounts.iter() {
+            writeln!(f, "    ramdisk: {} at {dir}", mounted.as_str())?;
+        }
+        writeln!(
+            f,
+            "    builtin: {:?}",
+            self.builtin.as_ref().map(|fs| fs.as_str())
+        )?;
         writeln!(
             f,
-            "    ramdisk: {:?}",
-            self.ramdisk.as_ref().map(|fs| fs.as_str())
+            "    prompt: {:?} {:?}",
+            self.prompt, self.prompt_segments
         )?;
-        writeln!(f, "    prompt: {:?}", self.prompt)?;
+        writeln!(f, "    beacon: {:?}", self.beacon)?;
+        let bootenv = self.bootenv.as_ref().map(|env| {
+            let env = env.strip_suffix(&[0]).unwrap_or(env);
+            core::str::from_utf8(env).unwrap_or("<invalid>")
+        });
+        writeln!(f, "    bootenv: {bootenv:?}")?;
+        writeln!(f, "    idle: {:?}", self.idle)?;
+        writeln!(
+            f,
+            "    audit: {} ({} accesses)",
+            if self.access_log.enabled() { "on" } else { "off" },
+            self.access_log.len(),
+        )?;
+        writeln!(f, "    images: {}", self.images.len())?;
+        writeln!(f, "    measurements: {}", self.measurements.log().len())?;
+        let (dumps, bytes) = self.dumps.usage();
+        writeln!(f, "    dumps: {dumps} ({bytes:#x} bytes)")?;
+        writeln!(f, "    confirm: {}", self.confirm)?;
+        writeln!(f, "    results: {}", self.results.len())?;
+        writeln!(f, "    symbols: {}", self.symbols.len())?;
+        writeln!(
+            f,
+            "    ipcc: {}",
+            if self.ipcc.is_some() { "open" } else { "closed" }
+        )?;
+        match &self.xfer {
+            Some(uart) => {
+                writeln!(f, "    xfer port: Uart({:x})", uart.addr())?
+            }
+            None => writeln!(f, "    xfer port: cons")?,
+        }
         write!(f, "}}")
     }
 }
@@ -109,35 +315,67 @@ pub(crate) unsafe extern "C" fn init(bist: u32) -> &'static mut Config {
     if INITED.swap(true, Ordering::AcqRel) {
         panic!("Init already called");
     }
+    post::post(post::Code::EnterRust);
     let iomux;
     unsafe {
         iomux = iomux::init();
+        post::post(post::Code::IoMuxInit);
         uart::init();
+        post::post(post::Code::UartInit);
     }
     idt::init();
+    post::post(post::Code::IdtInit);
     if bist != 0 {
         panic!("bist failed: {bist:#x}");
     }
     let cons = Uart::uart0();
     let cons_addr = mem::V4KA::new(cons.addr());
     let page_table = remap(cons_addr);
-    let xfer_region = xfer_addr()..ramdisk_addr();
-    let ramdisk_region = ramdisk_addr()..saddr();
+    post::post(post::Code::Remapped);
+    let Layout {
+        xfer: xfer_region,
+        ramdisk: ramdisk_region,
+        scratch: scratch_region,
+    } = Layout::default();
     let loader_region = saddr()..eaddr();
     let mmio_region = [mmio_addr()..mmio_end()];
     let gpios = unsafe { gpio::init() };
+    unsafe {
+        wdt::init();
+    }
+    let board = unsafe { board::identify(iomux, gpios) };
+    if let Some(board) = board.and_then(|ident| ident.board) {
+        unsafe {
+            board::apply(board, iomux);
+        }
+    }
+    let beacon =
+        board.and_then(|ident| ident.board?.beacon).map(|(pin, function)| {
+            let beacon = beacon::Beacon::new(pin, function);
+            unsafe {
+                beacon.init(iomux, gpios);
+            }
+            beacon.signal(gpios, beacon::Phase::EnteredRust);
+            beacon
+        });
 
     let cons_region = range_4k(cons_addr);
+    let fallback_region = range_4k(mem::V4KA::new(uart::fallback_addr()));
     let iomux_region = iomux_page_addr()..gpio_page_addr();
     let gpio_region = range_4k(gpio_page_addr());
-    let reserved_regions = [
+    let mut reserved_regions = Vec::from([
         loader_region.clone(),
-        xfer_region,
-        ramdisk_region,
+        xfer_region.clone(),
+        ramdisk_region.clone(),
+        scratch_region.clone(),
         cons_region,
+        fallback_region,
         iomux_region,
         gpio_region,
-    ];
+    ]);
+    if let Some(board) = board.and_then(|ident| ident.board) {
+        reserved_regions.extend_from_slice(board.reserved);
+    }
     let aliases = BTreeMap::from_iter(
         repl::DEF_ALIASES.iter().map(|&(k, v)| (k.into(), v.into())),
     );
@@ -145,19 +383,44 @@ pub(crate) unsafe extern "C" fn init(bist: u32) -> &'static mut Config {
         cons,
         iomux,
         gpios,
+        hw: &hw::Machine,
+        board,
         loader_region,
+        xfer_region,
+        ramdisk_region,
+        scratch_region,
+        regions_claimed: false,
         page_table: mmu::LoaderPageTable::new(
             page_table,
             &reserved_regions,
             &mmio_region,
         ),
-        ramdisk: None,
+        mounts: ramdisk::Mounts::default(),
+        builtin: ramdisk::mount_builtin(),
         prompt: cons::DEFAULT_PROMPT,
+        prompt_segments: Vec::new(),
+        last_failed: false,
         aliases,
+        beacon,
+        bootenv: None,
+        idle: None,
+        access_log: repl::AccessLog::default(),
+        images: Vec::new(),
+        modified: repl::Modified::default(),
+        confirm: true,
+        ipcc: None,
+        xfer: None,
+        measurements: tpm::Measurements::default(),
+        dumps: repl::Dumps::default(),
+        rz_stats: repl::RzStats::default(),
+        results: repl::Results::default(),
+        symbols: symbols::Symbols::default(),
     });
     if false {
         say_hi_sp(&mut config, 4);
     }
+    config.signal(beacon::Phase::ConsoleUp);
+    post::post(post::Code::ConfigInit);
     Box::leak(config)
 }
 
@@ -193,16 +456,101 @@ unsafe extern "C" {
     pub fn dnr() -> !;
 }
 
-/// Returns the address of the start of the transfer region.
-fn xfer_addr() -> mem::V4KA {
-    const XFER_LEN: usize = 64 * mem::MIB;
-    mem::V4KA::new(ramdisk_addr().addr() - XFER_LEN)
+/// The lowest address that the loader may use for its own
+/// regions.
+const PHBL_MIN: usize = 2 * mem::GIB - 256 * mem::MIB;
+
+/// The default lengths of the transfer, ramdisk, and scratch
+/// regions.  These may be changed with `Config::set_layout`.
+const XFER_LEN: usize = 64 * mem::MIB;
+const RAMDISK_LEN: usize = 128 * mem::MIB;
+const SCRATCH_LEN: usize = 16 * mem::MIB;
+
+/// The placement of the regions that the loader sets aside for
+/// transfers, ramdisks, and experiments.  Each must lie between
+/// `PHBL_MIN` and the loader, and none may overlap another.
+#[derive(Clone, Debug)]
+pub(crate) struct Layout {
+    pub(crate) xfer: Range<mem::V4KA>,
+    pub(crate) ramdisk: Range<mem::V4KA>,
+    pub(crate) scratch: Range<mem::V4KA>,
 }
 
-/// Returns the address of the start of the ramdisk region.
-fn ramdisk_addr() -> mem::V4KA {
-    const RAMDISK_LEN: usize = 128 * mem::MIB;
-    mem::V4KA::new(saddr().addr() - RAMDISK_LEN)
+impl Layout {
+    /// Returns a layout with regions of the given lengths placed
+    /// contiguously immediately below the loader: the ramdisk
+    /// highest, then the transfer region, then scratch.
+    pub(crate) fn packed(
+        xfer_len: usize,
+        ramdisk_len: usize,
+        scratch_len: usize,
+    ) -> Result<Layout, Error> {
+        let lens = [xfer_len, ramdisk_len, scratch_len];
+        if lens.iter().any(|&len| !len.is_multiple_of(mem::V4KA::SIZE)) {
+            return Err(Error::PageAlign);
+        }
+        let end = saddr().addr();
+        let total = lens
+            .iter()
+            .try_fold(0usize, |total, &len| total.checked_add(len))
+            .ok_or(Error::NumRange)?;
+        if end.checked_sub(PHBL_MIN).is_none_or(|avail| avail < total) {
+            return Err(Error::NumRange);
+        }
+        let ramdisk_addr = end - ramdisk_len;
+        let xfer_addr = ramdisk_addr - xfer_len;
+        let scratch_addr = xfer_addr - scratch_len;
+        Ok(Layout {
+            xfer: mem::V4KA::new(xfer_addr)..mem::V4KA::new(ramdisk_addr),
+            ramdisk: mem::V4KA::new(ramdisk_addr)..saddr(),
+            scratch: mem::V4KA::new(scratch_addr)..mem::V4KA::new(xfer_addr),
+        })
+    }
+
+    /// Returns the region of the given length at the given
+    /// address, both of which must be page aligned.
+    pub(crate) fn region(
+        addr: usize,
+        len: usize,
+    ) -> Result<Range<mem::V4KA>, Error> {
+        if !addr.is_multiple_of(mem::V4KA::SIZE)
+            || !len.is_multiple_of(mem::V4KA::SIZE)
+        {
+            return Err(Error::PageAlign);
+        }
+        let end = addr.checked_add(len).ok_or(Error::NumRange)?;
+        Ok(mem::V4KA::new(addr)..mem::V4KA::new(end))
+    }
+
+    /// Checks that each region is non-empty, lies between
+    /// `PHBL_MIN` and the loader, and overlaps no other.
+    fn validate(&self) -> Result<(), Error> {
+        let regions = self.regions();
+        for (k, range) in regions.iter().enumerate() {
+            let (start, end) = (range.start.addr(), range.end.addr());
+            if start >= end || start < PHBL_MIN || end > saddr().addr() {
+                return Err(Error::NumRange);
+            }
+            let overlaps = regions[..k].iter().any(|other| {
+                start < other.end.addr() && other.start.addr() < end
+            });
+            if overlaps {
+                return Err(Error::NumRange);
+            }
+        }
+        Ok(())
+    }
+
+    fn regions(&self) -> [Range<mem::V4KA>; 3] {
+        [self.xfer.clone(), self.ramdisk.clone(), self.scratch.clone()]
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout::packed(XFER_LEN, RAMDISK_LEN, SCRATCH_LEN)
+            .expect("default regions fit below the loader")
+    }
 }
 
 /// Returns the address of the start of the loader text segment.
@@ -276,7 +624,6 @@ pub fn gpio_page_addr() -> mem::V4KA {
 
 /// Returns a zeroed slice over the given region.
 fn zeroed_region_mut(start: usize, end: usize) -> &'static mut [u8] {
-    const PHBL_MIN: usize = 2 * mem::GIB - 256 * mem::MIB;
     let phbl_base = core::ptr::with_exposed_provenance_mut::<u8>(PHBL_MIN);
     assert!(PHBL_MIN <= start && start < end && end <= saddr().addr());
     let len = end - start;
@@ -287,16 +634,6 @@ fn zeroed_region_mut(start: usize, end: usize) -> &'static mut [u8] {
     }
 }
 
-/// Zeroes and returns a mutable slice over the ramdisk region.
-pub(crate) fn ramdisk_region_init_mut() -> &'static mut [u8] {
-    zeroed_region_mut(ramdisk_addr().addr(), saddr().addr())
-}
-
-/// Zeroes and returns a mutable slice over the transfer region.
-pub(crate) fn xfer_region_init_mut() -> &'static mut [u8] {
-    zeroed_region_mut(xfer_addr().addr(), ramdisk_addr().addr())
-}
-
 fn range_4k(start: mem::V4KA) -> Range<mem::V4KA> {
     let end = mem::V4KA::new(start.addr() + mem::V4KA::SIZE);
     start..end
@@ -308,6 +645,15 @@ pub(crate) fn loader_text() -> Range<u64> {
     start..end
 }
 
+/// Returns the loader's text and read-only data, which do not
+/// change as the loader runs.
+pub(crate) fn loader_readonly() -> &'static [u8] {
+    let start = text_addr().addr();
+    let len = data_addr().addr() - start;
+    let ptr = core::ptr::with_exposed_provenance::<u8>(start);
+    unsafe { core::slice::from_raw_parts(ptr, len) }
+}
+
 /// When the loader enters Rust code, we know that we have a
 /// minimal virtual memory environment where the loader itself
 /// is mapped rwx, and the UART registers region is mapped
@@ -315,8 +661,29 @@ pub(crate) fn loader_text() -> Range<u64> {
 /// properly, enforcing appropriate protections for sections
 /// and so on.
 fn remap(cons_addr: mem::V4KA) -> &'static mut mmu::PageTable {
-    let xfer = xfer_addr()..ramdisk_addr();
-    let ramdisk = ramdisk_addr()..saddr();
+    let Layout { xfer, ramdisk, scratch } = Layout::default();
+    let regions =
+        regions(xfer, ramdisk, scratch, cons_addr).map(|(_, region)| region);
+    let page_table = mmu::PageTable::new();
+    unsafe {
+        page_table.identity_map(&regions);
+        page_table.activate()
+    }
+}
+
+/// The number of regions the loader maps for itself.
+pub(crate) const NREGIONS: usize = 12;
+
+/// Returns the regions of the address space that the loader
+/// maps for itself, by name, derived from the linker-provided
+/// segment bounds, the transfer, ramdisk, and scratch regions,
+/// and the MMIO pages the loader uses.
+fn regions(
+    xfer: Range<mem::V4KA>,
+    ramdisk: Range<mem::V4KA>,
+    scratch: Range<mem::V4KA>,
+    cons_addr: mem::V4KA,
+) -> [(&'static str, mem::Region); NREGIONS] {
     let text = text_addr()..rodata_addr();
     let rodata = rodata_addr()..data_addr();
     let data = data_addr()..edata_addr();
@@ -324,24 +691,22 @@ fn remap(cons_addr: mem::V4KA) -> &'static mut mmu::PageTable {
     let boot = bootblock_addr()..eaddr();
 
     let cons = range_4k(cons_addr);
+    let fallback = range_4k(mem::V4KA::new(uart::fallback_addr()));
     let iomux = iomux_page_addr()..gpio_page_addr();
     let gpio = range_4k(gpio_page_addr());
 
-    let regions = &[
-        mem::Region::new(xfer, mem::Attrs::new_data()),
-        mem::Region::new(ramdisk, mem::Attrs::new_data()),
-        mem::Region::new(text, mem::Attrs::new_text()),
-        mem::Region::new(rodata, mem::Attrs::new_rodata()),
-        mem::Region::new(data, mem::Attrs::new_data()),
-        mem::Region::new(bss, mem::Attrs::new_bss()),
-        mem::Region::new(boot, mem::Attrs::new_rodata()),
-        mem::Region::new(iomux, mem::Attrs::new_mmio()),
-        mem::Region::new(gpio, mem::Attrs::new_mmio()),
-        mem::Region::new(cons, mem::Attrs::new_mmio()),
-    ];
-    let page_table = mmu::PageTable::new();
-    unsafe {
-        page_table.identity_map(regions);
-        page_table.activate()
-    }
+    [
+        ("xfer", mem::Region::new(xfer, mem::Attrs::new_data())),
+        ("ramdisk", mem::Region::new(ramdisk, mem::Attrs::new_data())),
+        ("scratch", mem::Region::new(scratch, mem::Attrs::new_data())),
+        ("text", mem::Region::new(text, mem::Attrs::new_text())),
+        ("rodata", mem::Region::new(rodata, mem::Attrs::new_rodata())),
+        ("data", mem::Region::new(data, mem::Attrs::new_data())),
+        ("bss", mem::Region::new(bss, mem::Attrs::new_bss())),
+        ("boot", mem::Region::new(boot, mem::Attrs::new_rodata())),
+        ("iomux", mem::Region::new(iomux, mem::Attrs::new_mmio())),
+        ("gpio", mem::Region::new(gpio, mem::Attrs::new_mmio())),
+        ("cons", mem::Region::new(cons, mem::Attrs::new_mmio())),
+        ("fallback", mem::Region::new(fallback, mem::Attrs::new_mmio())),
+    ]
 }
@@ -0,0 +1,167 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Board identification.
+//!
+//! Boards built around the same processor may differ in which
+//! pins carry the console, in ranges of memory that must be left
+//! alone, or in which pin, if any, drives a beacon LED.  A platform profile names the GPIO pins that
+//! strap a board ID, and the boards that those IDs identify.
+//! We read the straps at init, report the board, and apply its
+//! settings, rather than relying on whoever is at the bench to
+//! remember which revision is in front of them.
+
+use crate::gpio;
+use crate::iomux;
+use crate::mem;
+use core::fmt;
+use core::ops::Range;
+
+/// A board, and the settings it needs beyond the platform's.
+#[derive(Debug)]
+pub(crate) struct Board {
+    pub(crate) id: u8,
+    pub(crate) name: &'static str,
+    /// IO mux settings for the console's pins, if they differ
+    /// from the platform's.
+    pub(crate) console_mux: &'static [(u8, iomux::PinFunction)],
+    /// Regions of memory that must not be mapped on this board.
+    pub(crate) reserved: &'static [Range<mem::V4KA>],
+    /// The pin driving the board's beacon LED, if it has one,
+    /// and the IO mux function that selects GPIO on it.
+    pub(crate) beacon: Option<(u8, iomux::PinFunction)>,
+}
+
+/// A platform's board ID straps, and the boards they identify.
+#[derive(Debug)]
+pub(crate) struct Profile {
+    pub(crate) name: &'static str,
+    /// The strap pins, least significant bit first, each with
+    /// the IO mux function that selects GPIO on it.
+    pub(crate) straps: &'static [(u8, iomux::PinFunction)],
+    pub(crate) boards: &'static [Board],
+}
+
+impl Profile {
+    /// Returns the board with the given ID, if known.
+    pub(crate) fn board(&self, id: u8) -> Option<&Board> {
+        self.boards.iter().find(|board| board.id == id)
+    }
+}
+
+/// Returns the profile for the current platform, if any.  None
+/// of the boards of the platforms we run on straps an ID yet, so
+/// there are no profiles.  A platform whose boards do is matched
+/// here by its processor, as reported by `cpuid::cpuinfo`.
+fn profile() -> Option<&'static Profile> {
+    None
+}
+
+/// The identity of the board we are running on, as strapped.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Ident {
+    pub(crate) profile: &'static Profile,
+    pub(crate) id: u8,
+    pub(crate) board: Option<&'static Board>,
+}
+
+impl Ident {
+    fn new(profile: &'static Profile, id: u8) -> Ident {
+        Ident { profile, id, board: profile.board(id) }
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.board.map_or("unknown", |board| board.name);
+        write!(f, "{} {name} (board ID {:#x})", self.profile.name, self.id)
+    }
+}
+
+/// Assembles a board ID from strap levels, least significant
+/// bit first.
+fn strap_id(levels: impl IntoIterator<Item = bool>) -> u8 {
+    levels
+        .into_iter()
+        .enumerate()
+        .fold(0, |id, (k, high)| id | (u8::from(high) << k))
+}
+
+/// Reads the board ID straps for the current platform, and
+/// returns the board's identity, or `None` if the platform has
+/// no profile.
+///
+/// # Safety
+/// The caller must ensure that the IO mux and GPIO MMIO regions
+/// are mapped, and that the strap pins are not otherwise in use.
+pub(crate) unsafe fn identify(
+    iomux: &mut iomux::IoMux,
+    gpios: &mut gpio::Gpios,
+) -> Option<Ident> {
+    let profile = profile()?;
+    let levels = profile.straps.iter().map(|&(pin, function)| {
+        let reg = gpios.get_pin(pin).with_output_enable(false);
+        unsafe {
+            iomux.set_pin(pin, function);
+            gpios.set_pin(pin, reg);
+        }
+        matches!(gpios.get_pin(pin).pin_status(), gpio::PinStatus::High)
+    });
+    Some(Ident::new(profile, strap_id(levels)))
+}
+
+/// Applies the board's console pin settings, if it has any.
+///
+/// # Safety
+/// The caller must ensure that the IO mux MMIO region is mapped.
+pub(crate) unsafe fn apply(board: &Board, iomux: &mut iomux::IoMux) {
+    for &(pin, function) in board.console_mux {
+        unsafe {
+            iomux.set_pin(pin, function);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST: Profile = Profile {
+        name: "test",
+        straps: &[(1, iomux::PinFunction::F1), (2, iomux::PinFunction::F1)],
+        boards: &[
+            Board {
+                id: 0,
+                name: "rev-a",
+                console_mux: &[],
+                reserved: &[],
+                beacon: None,
+            },
+            Board {
+                id: 2,
+                name: "rev-c",
+                console_mux: &[(135, iomux::PinFunction::F1)],
+                reserved: &[mem::V4KA::new(0x1000)..mem::V4KA::new(0x2000)],
+                beacon: Some((19, iomux::PinFunction::F1)),
+            },
+        ],
+    };
+
+    #[test]
+    fn decoding() {
+        assert_eq!(strap_id([]), 0);
+        assert_eq!(strap_id([false, true]), 2);
+        assert_eq!(strap_id([true, true, false, true]), 0b1011);
+        let ident = Ident::new(&TEST, strap_id([false, true]));
+        assert_eq!(ident.board.map(|b| b.name), Some("rev-c"));
+        assert_eq!(
+            ident.board.and_then(|b| b.beacon).map(|(pin, _)| pin),
+            Some(19)
+        );
+        assert_eq!(alloc::format!("{ident}"), "test rev-c (board ID 0x2)");
+        let ident = Ident::new(&TEST, strap_id([true, true]));
+        assert!(ident.board.is_none());
+        assert_eq!(alloc::format!("{ident}"), "test unknown (board ID 0x3)");
+    }
+}
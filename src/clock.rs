@@ -5,11 +5,14 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT.
 
+#[cfg(not(test))]
 use crate::cpuid;
+use core::time::Duration;
 
 pub const NANOS_PER_SEC: u128 = 1_000_000_000;
 
 /// Returns the clock frequency of the current CPU in Hertz.
+#[cfg(not(test))]
 pub fn frequency() -> u128 {
     const DEFAULT_HZ: u128 = 2_000_000_000;
     if let Some(tsc_info) = cpuid::tscinfo()
@@ -23,6 +26,132 @@ pub fn frequency() -> u128 {
     DEFAULT_HZ
 }
 
+#[cfg(test)]
+pub fn frequency() -> u128 {
+    crate::fakes::CLOCK_HZ
+}
+
+#[cfg(not(test))]
 pub fn rdtsc() -> u64 {
     unsafe { core::arch::x86_64::_rdtsc() }
 }
+
+#[cfg(test)]
+pub use crate::fakes::rdtsc;
+
+/// Converts a duration to a number of TSC cycles.
+fn cycles(duration: Duration) -> u128 {
+    duration.as_nanos() * frequency() / NANOS_PER_SEC
+}
+
+/// Converts a number of TSC cycles to a duration.
+fn duration(cycles: u64) -> Duration {
+    let hz = frequency().max(1);
+    let cycles = u128::from(cycles);
+    let secs = cycles / hz;
+    let nanos = (cycles % hz) * NANOS_PER_SEC / hz;
+    Duration::new(secs as u64, nanos as u32)
+}
+
+/// A reading of the monotonic clock, which is the TSC.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(rdtsc())
+    }
+
+    /// Returns the time since this instant, or zero if the clock
+    /// appears to have gone backwards.
+    pub fn elapsed(self) -> Duration {
+        Instant::now().saturating_duration_since(self)
+    }
+
+    /// Returns the time from `earlier` to this instant, if
+    /// `earlier` is not later.
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(duration)
+    }
+
+    /// Like `checked_duration_since`, but returns zero rather
+    /// than failing.
+    pub fn saturating_duration_since(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the instant `duration` after this one, if it can
+    /// be represented.
+    pub fn checked_add(self, duration: Duration) -> Option<Instant> {
+        let cycles = u64::try_from(cycles(duration)).ok()?;
+        self.0.checked_add(cycles).map(Instant)
+    }
+}
+
+/// Returns the time since the TSC was reset, which is roughly
+/// the time since the machine was powered on.
+pub fn uptime() -> Duration {
+    duration(rdtsc())
+}
+
+/// A time by which something should have happened, used to
+/// bound polling loops.  A deadline too far in the future to be
+/// represented never expires.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Returns the deadline `timeout` from now.
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline(Instant::now().checked_add(timeout))
+    }
+
+    /// Returns a deadline that never expires.
+    pub fn never() -> Deadline {
+        Deadline(None)
+    }
+
+    pub fn expired(&self) -> bool {
+        self.0.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Spins for at least the given duration.
+pub fn delay(duration: Duration) {
+    let deadline = Deadline::after(duration);
+    while !deadline.expired() {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let d = Duration::new(3, 141_592_653);
+        assert_eq!(duration(cycles(d) as u64), d);
+        let start = Instant::now();
+        let later = start.checked_add(Duration::from_millis(5)).unwrap();
+        assert_eq!(
+            later.checked_duration_since(start),
+            Some(Duration::from_millis(5))
+        );
+        assert_eq!(start.checked_duration_since(later), None);
+        assert_eq!(start.saturating_duration_since(later), Duration::ZERO);
+        assert!(start.checked_add(Duration::MAX).is_none());
+    }
+
+    #[test]
+    fn deadlines() {
+        let deadline = Deadline::after(Duration::from_millis(1));
+        assert!(!deadline.expired());
+        let start = Instant::now();
+        delay(Duration::from_millis(2));
+        assert!(start.elapsed() >= Duration::from_millis(2));
+        assert!(deadline.expired());
+        assert!(!Deadline::never().expired());
+        assert!(!Deadline::after(Duration::MAX).expired());
+    }
+}
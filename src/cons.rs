@@ -5,8 +5,13 @@
 // license that can be found in the LICENSE file or at
 // https://opensource.org/licenses/MIT.
 
+use crate::clock::Deadline;
+use crate::linedisc::{self, LineDisc};
 use crate::result::{Error, Result};
 use crate::uart::Uart;
+use crate::wdt;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::time::Duration;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -16,129 +21,74 @@ pub enum Prompt {
     Pulser,
 }
 
+const ETX: u8 = 3;
 const BS: u8 = 8;
 const TAB: u8 = 9;
-const NL: u8 = 10;
-const CR: u8 = 13;
-const CTLU: u8 = 21;
-const CTLW: u8 = 23;
 const ESC: u8 = 27;
-const DEL: u8 = 127;
 
-pub fn readline<'a, F>(
+/// Displays the prompt and reads a line from the console into
+/// the given buffer, using the line discipline for editing.  If
+/// nothing is typed before the timeout expires, returns
+/// `Error::Timeout`; once something has been, waits indefinitely.
+pub fn readline_timeout<'a, F>(
     prompt: F,
     uart: &mut Uart,
+    timeout: Duration,
     line: &'a mut [u8],
 ) -> Result<&'a str>
 where
-    F: FnOnce(&mut Uart) -> usize,
+    F: FnMut(&mut Uart) -> usize,
 {
-    readline_timeout(prompt, uart, Duration::ZERO, line)
+    readline_complete(prompt, uart, timeout, line, None)
 }
 
-pub fn readline_timeout<'a, F>(
-    prompt: F,
+/// Returns the candidates for completing the last word of a
+/// line.
+pub type Completer<'a> = &'a mut dyn FnMut(&str) -> Vec<String>;
+
+/// As `readline_timeout`, but completing the last word of the
+/// line when TAB is typed.  `complete` returns the words that
+/// the last word of the line so far might be in full.  If there
+/// are several, and they share nothing more, they are listed,
+/// and the prompt and line drawn again beneath them.
+pub fn readline_complete<'a, F>(
+    mut prompt: F,
     uart: &mut Uart,
     timeout: Duration,
     line: &'a mut [u8],
+    mut complete: Option<Completer<'_>>,
 ) -> Result<&'a str>
 where
-    F: FnOnce(&mut Uart) -> usize,
+    F: FnMut(&mut Uart) -> usize,
 {
-    fn find_prev_col(line: &[u8], start: usize) -> usize {
-        line.iter()
-            .fold(start, |v, &b| v + if b == TAB { 8 - (v & 0b111) } else { 1 })
-    }
-
-    fn backup(
-        uart: &mut Uart,
-        line: &[u8],
-        start: usize,
-        col: usize,
-    ) -> (usize, usize) {
-        if line.is_empty() || col == start {
-            return (start, 0);
-        }
-        let (pcol, overstrike) = match line.last() {
-            Some(&b' ') => (col - 1, false),
-            Some(&b'\t') => {
-                (find_prev_col(&line[..line.len() - 1], start), false)
-            }
-            _ => (col - 1, true),
-        };
-        for _ in pcol..col {
-            backspace(uart, overstrike);
-        }
-        (pcol, line.len() - 1)
-    }
-
-    fn isword(b: u8) -> bool {
-        b.is_ascii_alphanumeric() || b == b'_'
-    }
-
     if line.is_empty() {
         return Ok("");
     }
-
     let start = prompt(uart);
-
-    let mut k = 0;
-    let mut col = start;
-    while k < line.len() {
-        match uart.getb_timeout(timeout) {
-            None => {
-                if k == 0 {
+    let mut ld = LineDisc::new(line, start);
+    while !ld.is_full() {
+        match (uart.getb_timeout(timeout), complete.as_deref_mut()) {
+            (None, _) => {
+                if ld.is_empty() {
                     return Err(Error::Timeout);
                 }
             }
-            Some(CR | NL) => {
-                uart.putb(CR);
-                uart.putb(NL);
-                break;
-            }
-            Some(BS | DEL) => {
-                if k > 0 {
-                    (col, k) = backup(uart, &line[..k], start, col);
-                }
-            }
-            Some(CTLU) => {
-                while k > 0 {
-                    (col, k) = backup(uart, &line[..k], start, col);
-                }
-            }
-            Some(CTLW) => {
-                while k > 0 && line[k - 1].is_ascii_whitespace() {
-                    (col, k) = backup(uart, &line[..k], start, col);
-                }
-                if k > 0 {
-                    let cond = isword(line[k - 1]);
-                    while k > 0
-                        && !line[k - 1].is_ascii_whitespace()
-                        && isword(line[k - 1]) == cond
-                    {
-                        (col, k) = backup(uart, &line[..k], start, col);
-                    }
+            (Some(TAB), Some(complete)) => {
+                let words = complete(ld.line());
+                if ld.complete(&words, uart) {
+                    linedisc::list(&words, uart);
+                    let start = prompt(uart);
+                    ld.redraw(start, uart);
                 }
             }
-            Some(TAB) => {
-                line[k] = TAB;
-                k += 1;
-                let ncol = (8 + col) & !0b111;
-                for _ in col..ncol {
-                    uart.putb(b' ');
+            (Some(b), _) => {
+                if ld.input(b, uart) {
+                    break;
                 }
-                col = ncol;
-            }
-            Some(b) => {
-                line[k] = b;
-                k += 1;
-                uart.putb(b);
-                col += 1;
             }
         }
     }
-
-    core::str::from_utf8(&line[..k]).map_err(|_| Error::Utf8)
+    ld.finish()
 }
 
 pub fn backspace(term: &mut Uart, overstrike: bool) {
@@ -156,6 +106,48 @@ pub fn clear(term: &mut Uart) {
     term.puts("[2J");
 }
 
+/// Parses a cursor position report, `ESC [ rows ; cols R`, as
+/// sent by a terminal in response to a device status report.
+#[cfg(feature = "cmd-files")]
+fn parse_cursor_report(report: &[u8]) -> Option<(usize, usize)> {
+    let report = report.strip_prefix(&[ESC, b'['])?.strip_suffix(b"R")?;
+    let report = core::str::from_utf8(report).ok()?;
+    let (rows, cols) = report.split_once(';')?;
+    Some((rows.parse().ok()?, cols.parse().ok()?))
+}
+
+/// Queries the terminal for its size, returning the number of
+/// rows and columns.  This works by moving the cursor to the
+/// far bottom right corner of the screen, where the terminal
+/// clamps it, and asking where it ended up.  Returns `None` if
+/// the terminal does not respond.
+#[cfg(feature = "cmd-files")]
+pub fn size(term: &mut Uart) -> Option<(usize, usize)> {
+    const WAIT: Duration = Duration::from_millis(250);
+    term.putb(ESC);
+    term.putb(b'7');
+    term.putb(ESC);
+    term.puts("[999;999H");
+    term.putb(ESC);
+    term.puts("[6n");
+    let mut report = [0u8; 16];
+    let mut len = 0;
+    while len < report.len() {
+        let Some(b) = term.getb_timeout(WAIT) else {
+            break;
+        };
+        report[len] = b;
+        len += 1;
+        if b == b'R' {
+            break;
+        }
+    }
+    term.putb(ESC);
+    term.putb(b'8');
+    parse_cursor_report(&report[..len])
+        .filter(|&(rows, cols)| rows > 1 && cols > 1)
+}
+
 pub fn cycle(
     term: &mut Uart,
     prefix: &[u8],
@@ -184,14 +176,119 @@ pub fn cycle(
     erase(term, prefix);
 }
 
-#[cfg(all(feature = "pulse_prompt", feature = "spin_prompt"))]
-compile_error!(
-    "The `pulse_prompt` and `spin_prompt` features are mutually exclusive"
-);
+/// How often long-running operations check the console.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns a function for long-running operations to call
+/// periodically, which fails with `Error::Cancelled` if Ctrl-C
+/// or a BREAK has been received on the console.  It is cheap
+/// enough to call from inner loops, as it only examines the
+/// console, and pets the watchdog, every `POLL_INTERVAL`.  Other
+/// input received while the operation runs is discarded.
+pub fn poller(uart: &mut Uart) -> impl FnMut() -> Result<()> + '_ {
+    let mut next = Deadline::after(POLL_INTERVAL);
+    move || {
+        if !next.expired() {
+            return Ok(());
+        }
+        next = Deadline::after(POLL_INTERVAL);
+        wdt::pet();
+        match uart.data_ready() {
+            Ok(true) if uart.getb() == ETX => Err(Error::Cancelled),
+            Err(e) if e.is(Error::UartBreak) => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
 
-#[cfg(not(any(feature = "pulse_prompt", feature = "spin_prompt")))]
 pub(crate) const DEFAULT_PROMPT: Prompt = Prompt::Tenex;
-#[cfg(feature = "pulse_prompt")]
-pub(crate) const DEFAULT_PROMPT: Prompt = Prompt::Pulser;
-#[cfg(feature = "spin_prompt")]
-pub(crate) const DEFAULT_PROMPT: Prompt = Prompt::Spinner;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "cmd-files")]
+    fn cursor_reports() {
+        assert_eq!(parse_cursor_report(b"\x1b[24;80R"), Some((24, 80)));
+        assert_eq!(parse_cursor_report(b"\x1b[24;80"), None);
+        assert_eq!(parse_cursor_report(b"\x1b[24R"), None);
+        assert_eq!(parse_cursor_report(b""), None);
+    }
+
+    use crate::fakes::{self, Rx};
+
+    const WAIT: Duration = Duration::from_millis(100);
+
+    fn readline(uart: &mut Uart, line: &mut [u8]) -> Result<String> {
+        let prompt = |term: &mut Uart| {
+            term.puts("@ ");
+            2
+        };
+        readline_timeout(prompt, uart, WAIT, line).map(String::from)
+    }
+
+    #[test]
+    fn line_editing() {
+        let mut line = [0u8; 64];
+        let mut uart = fakes::console([Rx::bytes(b"ab\x08c\r")]);
+        assert_eq!(readline(&mut uart, &mut line).unwrap(), "ac");
+        assert_eq!(fakes::transmitted(), b"@ ab\x08 \x08c\r\n");
+        let mut uart = fakes::console([Rx::bytes(b"junk\x15foo bar\x17baz\n")]);
+        assert_eq!(readline(&mut uart, &mut line).unwrap(), "foo baz");
+        let mut uart = fakes::console([Rx::bytes(b"a\tb\x7f\x7f\r")]);
+        assert_eq!(readline(&mut uart, &mut line).unwrap(), "a");
+        let mut uart = fakes::console([Rx::bytes(b"abcd")]);
+        assert_eq!(readline(&mut uart, &mut line[..3]).unwrap(), "abc");
+        assert!(!fakes::exhausted());
+    }
+
+    #[test]
+    fn line_timeouts() {
+        let mut line = [0u8; 64];
+        let mut uart = fakes::console([Rx::Idle(WAIT * 2)]);
+        assert_eq!(readline(&mut uart, &mut line), Err(Error::Timeout));
+        // Once something has been typed, we wait indefinitely.
+        let mut uart = fakes::console([
+            Rx::bytes(b"ab"),
+            Rx::Idle(WAIT * 5),
+            Rx::bytes(b"c\r"),
+        ]);
+        assert_eq!(readline(&mut uart, &mut line).unwrap(), "abc");
+    }
+
+    #[test]
+    fn line_errors() {
+        let mut line = [0u8; 64];
+        let mut uart = fakes::console([
+            Rx::bytes(b"a"),
+            Rx::Error(Error::UartFraming),
+            Rx::Error(Error::UartBreak),
+            Rx::bytes(b"b\r"),
+        ]);
+        assert_eq!(readline(&mut uart, &mut line).unwrap(), "ab");
+        let mut uart = fakes::console([Rx::Error(Error::UartParity)]);
+        assert_eq!(readline(&mut uart, &mut line), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut uart = fakes::console([Rx::bytes(b"x"), Rx::bytes(&[ETX])]);
+        let mut poll = poller(&mut uart);
+        // Input is only examined once per interval.
+        assert_eq!(poll(), Ok(()));
+        assert!(!fakes::exhausted());
+        fakes::advance(POLL_INTERVAL.as_nanos() as u64);
+        assert_eq!(poll(), Ok(()));
+        assert_eq!(poll(), Ok(()));
+        fakes::advance(POLL_INTERVAL.as_nanos() as u64);
+        assert_eq!(poll(), Err(Error::Cancelled));
+        assert!(fakes::exhausted());
+        fakes::advance(POLL_INTERVAL.as_nanos() as u64);
+        assert_eq!(poll(), Ok(()));
+        let mut uart = fakes::console([Rx::Error(Error::UartBreak)]);
+        let mut poll = poller(&mut uart);
+        fakes::advance(POLL_INTERVAL.as_nanos() as u64);
+        assert_eq!(poll(), Err(Error::Cancelled));
+    }
+}
@@ -3,12 +3,256 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! cpio miniroot support.
+//!
+//! Three of the ASCII archive formats are understood: the
+//! portable format written by `cpio -H odc`, whose headers give
+//! fields in octal, and the SVR4 formats written by `cpio -H
+//! newc` and `cpio -H crc`, which give them in hex.  In the
+//! latter, the name that follows each header and the data that
+//! follows each name are padded to a multiple of four bytes,
+//! counting from the start of the header, and in the `crc`
+//! format, each header carries a checksum of the file's data,
+//! which is verified when the file is opened.
+//!
+//! An archive is a flat list of members, and need not have a
+//! member for every directory on the paths of the others, as
+//! when it is made from a list of files.  Directories without
+//! members of their own are implied by the paths beneath them,
+//! and are listed and examined like any other.
 
 use crate::io;
 use crate::ramdisk;
 use crate::result::{Error, Result};
-use crate::{print, println};
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The member that ends an archive.
+const TRAILER: &str = "TRAILER!!!";
+
+/// The most symbolic links followed in resolving one path.
+const MAX_SYMLINKS: usize = 8;
+
+/// An archive format, as given by the magic number that starts
+/// each header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Odc,
+    Newc,
+    Crc,
+}
+
+impl Format {
+    fn detect(bs: &[u8]) -> Option<Format> {
+        match bs.get(..6)? {
+            b"070707" => Some(Format::Odc),
+            b"070701" => Some(Format::Newc),
+            b"070702" => Some(Format::Crc),
+            _ => None,
+        }
+    }
+
+    fn header_len(self) -> usize {
+        match self {
+            Format::Odc => 76,
+            Format::Newc | Format::Crc => 110,
+        }
+    }
+
+    /// The alignment of names and data within the archive.
+    fn align(self) -> usize {
+        match self {
+            Format::Odc => 1,
+            Format::Newc | Format::Crc => 4,
+        }
+    }
+}
+
+/// An archive member.
+pub(crate) struct Entry<'a> {
+    format: Format,
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    check: u32,
+    name: &'a str,
+    data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the member's path within the archive, as given
+    /// by `normalize`.
+    pub(crate) fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub(crate) fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn file_type(&self) -> ramdisk::FileType {
+        use ramdisk::FileType;
+        match (self.mode >> 12) & 0o17 {
+            0o01 => FileType::Fifo,
+            0o02 => FileType::Char,
+            0o04 => FileType::Dir,
+            0o06 => FileType::Block,
+            0o10 => FileType::Regular,
+            0o12 => FileType::SymLink,
+            0o14 => FileType::Sock,
+            _ => FileType::Unused,
+        }
+    }
+
+    /// Checks the member's data against the checksum in its
+    /// header, which is the sum of its bytes.  Only the `crc`
+    /// format has one.
+    fn verify(&self) -> Result<()> {
+        if self.format != Format::Crc {
+            return Ok(());
+        }
+        let sum = self
+            .data
+            .iter()
+            .fold(0u32, |sum, &b| sum.wrapping_add(u32::from(b)));
+        if sum != self.check {
+            return Err(Error::Verify
+                .context("sum,expected", &[sum.into(), self.check.into()]));
+        }
+        Ok(())
+    }
+}
+
+/// Returns a path relative to the root of the archive, without
+/// a leading `./` or `/`, or a trailing `/`.  The root itself is
+/// the empty path.
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) =
+        path.strip_prefix("./").or_else(|| path.strip_prefix('/'))
+    {
+        path = rest;
+    }
+    match path.trim_end_matches('/') {
+        "." => "",
+        path => path,
+    }
+}
+
+/// Returns the part of `path` beneath the directory `dir`, if
+/// it is beneath it at all.  Both are normalized.
+fn beneath<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+    let rest = match dir {
+        "" => path,
+        dir => path.strip_prefix(dir)?.strip_prefix('/')?,
+    };
+    (!rest.is_empty()).then_some(rest)
+}
+
+/// Parses a header field of the given radix.
+fn field(header: &[u8], start: usize, len: usize, radix: u32) -> Option<u64> {
+    let digits = core::str::from_utf8(header.get(start..start + len)?).ok()?;
+    u64::from_str_radix(digits, radix).ok()
+}
+
+/// Parses the member at the start of `bs`, returning it and the
+/// offset of the next, or `None` if `bs` does not start with a
+/// well-formed member.
+fn parse(bs: &[u8]) -> Option<(Entry<'_>, usize)> {
+    let format = Format::detect(bs)?;
+    let header = bs.get(..format.header_len())?;
+    let (ino, mode, uid, gid, nlink, check, namesize, filesize) = match format {
+        Format::Odc => {
+            let oct = |start, len| field(header, start, len, 8);
+            (
+                oct(12, 6)?,
+                oct(18, 6)?,
+                oct(24, 6)?,
+                oct(30, 6)?,
+                oct(36, 6)?,
+                0,
+                oct(59, 6)?,
+                oct(65, 11)?,
+            )
+        }
+        Format::Newc | Format::Crc => {
+            let hex = |k: usize| field(header, 6 + k * 8, 8, 16);
+            (
+                hex(0)?,
+                hex(1)?,
+                hex(2)?,
+                hex(3)?,
+                hex(4)?,
+                hex(12)?,
+                hex(11)?,
+                hex(6)?,
+            )
+        }
+    };
+    let align = format.align();
+    let name_start = header.len();
+    let name_end = name_start.checked_add(usize::try_from(namesize).ok()?)?;
+    let name = bs.get(name_start..name_end)?;
+    // The name's length includes its terminating NUL.
+    let name = core::str::from_utf8(name.strip_suffix(b"\0")?).ok()?;
+    let data_start = name_end.next_multiple_of(align);
+    let data_end = data_start.checked_add(usize::try_from(filesize).ok()?)?;
+    let data = bs.get(data_start..data_end)?;
+    let next = data_end.next_multiple_of(align).min(bs.len());
+    let entry = Entry {
+        format,
+        ino: ino as u32,
+        mode: mode as u32,
+        uid: uid as u32,
+        gid: gid as u32,
+        nlink: nlink as u32,
+        check: check as u32,
+        name: normalize(name),
+        data,
+    };
+    Some((entry, next))
+}
+
+/// Iterates over the members of an archive, up to its trailer,
+/// or the first malformed header.
+pub(crate) struct Entries<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        let (entry, next) = parse(self.rest)?;
+        if entry.name == TRAILER {
+            self.rest = &[];
+            return None;
+        }
+        self.rest = &self.rest[next..];
+        Some(entry)
+    }
+}
+
+/// Returns an iterator over the members of an archive.
+pub(crate) fn entries(bs: &[u8]) -> Entries<'_> {
+    Entries { rest: bs }
+}
+
+/// Returns the data of the member of an archive at the given
+/// path, which is normalized as member names are, after
+/// verifying it against its header's checksum, if it has one.
+pub(crate) fn find<'a>(bs: &'a [u8], path: &str) -> Result<&'a [u8]> {
+    let path = normalize(path);
+    let entry = entries(bs)
+        .find(|entry| entry.name() == path)
+        .ok_or(Error::CpioNoFile)?;
+    entry.verify()?;
+    Ok(entry.data())
+}
 
 pub(crate) struct FileSystem {
     sd: io::Sd,
@@ -16,22 +260,97 @@ pub(crate) struct FileSystem {
 
 impl FileSystem {
     pub(crate) fn try_new(bs: &[u8]) -> Result<FileSystem> {
-        if bs.starts_with(b"070707") {
+        if Format::detect(bs).is_some() {
             let sd = unsafe { io::Sd::from_slice(bs) };
             Ok(FileSystem { sd })
         } else {
             Err(Error::FsInvMagic)
         }
     }
+
+    fn entries(&self) -> Entries<'_> {
+        entries(unsafe { self.sd.as_slice() })
+    }
+
+    /// Returns the member at the given normalized path, if any.
+    fn lookup(&self, key: &str) -> Option<Entry<'_>> {
+        self.entries().find(|file| file.name() == key)
+    }
+
+    /// Resolves the symbolic links along a path, relative to the
+    /// normalized directory `dir`, returning the normalized path
+    /// of the member or implied directory that it names.  As in
+    /// the other file systems, a link's target is taken relative
+    /// to the directory holding the link, unless it is absolute.
+    /// `nlinks` counts the links followed over the whole lookup,
+    /// including those in the targets of other links.
+    fn namex(
+        &self,
+        mut dir: String,
+        path: &str,
+        nlinks: &mut usize,
+    ) -> Result<String> {
+        if path.starts_with('/') {
+            dir.clear();
+        }
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            // Archives have no entries for `.` and `..`, but as
+            // links have already been followed, the path can be
+            // taken apart lexically.
+            match name {
+                "." => continue,
+                ".." => {
+                    dir.truncate(dir.rfind('/').unwrap_or(0));
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(file) = self.lookup(&dir)
+                && file.file_type() != ramdisk::FileType::Dir
+            {
+                return Err(Error::FsInvPath);
+            }
+            let parent = dir.len();
+            if !dir.is_empty() {
+                dir.push('/');
+            }
+            dir.push_str(name);
+            if let Some(link) = self.lookup(&dir)
+                && link.file_type() == ramdisk::FileType::SymLink
+            {
+                if *nlinks == MAX_SYMLINKS {
+                    return Err(Error::FsInvPath);
+                }
+                *nlinks += 1;
+                let target = core::str::from_utf8(link.data())
+                    .map_err(|_| Error::FsInvPath)?;
+                dir.truncate(parent);
+                dir = self.namex(dir, target, nlinks)?;
+            }
+        }
+        Ok(dir)
+    }
+
+    fn namei(&self, path: &str) -> Result<String> {
+        self.namex(String::new(), path, &mut 0)
+    }
+
+    /// Returns true iff the given normalized path is a directory
+    /// implied by the paths of other members.
+    fn implied(&self, key: &str) -> bool {
+        key.is_empty()
+            || self.entries().any(|file| beneath(key, file.name()).is_some())
+    }
 }
 
 pub(crate) struct File {
+    file_type: ramdisk::FileType,
     data: io::Sd,
 }
 
 impl ramdisk::File for File {
     fn file_type(&self) -> ramdisk::FileType {
-        ramdisk::FileType::Regular
+        self.file_type
     }
 }
 
@@ -48,34 +367,56 @@ impl io::Read for File {
 
 impl ramdisk::FileSystem for FileSystem {
     fn open(&self, path: &str) -> Result<Box<dyn ramdisk::File>> {
-        let cpio = unsafe { self.sd.as_slice() };
-        let key = path.strip_prefix("/").unwrap_or(path);
-        for file in cpio_reader::iter_files(cpio) {
-            if file.name() == key {
-                let data = unsafe { io::Sd::from_slice(file.file()) };
-                return Ok(Box::new(File { data }));
+        let key = self.namei(path)?;
+        let (file_type, data) = match self.lookup(&key) {
+            Some(file) => {
+                file.verify()?;
+                (file.file_type(), file.data())
             }
+            None if self.implied(&key) => (ramdisk::FileType::Dir, &[][..]),
+            None => return Err(Error::FsNoFile),
+        };
+        let data = unsafe { io::Sd::from_slice(data) };
+        Ok(Box::new(File { file_type, data }))
+    }
+
+    fn stat(&self, path: &str) -> Result<ramdisk::DirEntry> {
+        let key = self.namei(path)?;
+        match self.lookup(&key) {
+            Some(file) => Ok(dirent(path, &file)),
+            None if self.implied(&key) => Ok(implied_dir(path)),
+            None => Err(Error::FsNoFile),
         }
-        Err(Error::FsNoFile)
     }
 
-    fn list(&self, path: &str) -> Result<()> {
-        let cpio = unsafe { self.sd.as_slice() };
-        let key = path.strip_prefix('/').unwrap_or(path);
-        for file in cpio_reader::iter_files(cpio) {
-            if file.name() == key {
-                lsfile(path, &file);
-                return Ok(());
-            }
+    /// Lists the members directly beneath a directory, along
+    /// with the directories implied by members further down.
+    fn readdir(&self, path: &str) -> Result<Vec<ramdisk::DirEntry>> {
+        let key = self.namei(path)?;
+        let file = self.lookup(&key);
+        if let Some(file) = &file
+            && file.file_type() != ramdisk::FileType::Dir
+        {
+            return Ok(vec![dirent(path, file)]);
         }
-        let mut found = false;
-        for file in cpio_reader::iter_files(cpio) {
-            if file.name().starts_with(key) {
-                lsfile(file.name(), &file);
-                found = true;
+        let mut children = BTreeMap::new();
+        for file in self.entries() {
+            let Some(rest) = beneath(&key, file.name()) else {
+                continue;
+            };
+            match rest.split_once('/') {
+                None => {
+                    children.insert(rest, dirent(rest, &file));
+                }
+                Some((child, _)) => {
+                    children.entry(child).or_insert_with(|| implied_dir(child));
+                }
             }
         }
-        if found { Ok(()) } else { Err(Error::FsNoFile) }
+        if file.is_none() && children.is_empty() && !key.is_empty() {
+            return Err(Error::FsNoFile);
+        }
+        Ok(children.into_values().collect())
     }
 
     fn as_str(&self) -> &str {
@@ -83,58 +424,242 @@ impl ramdisk::FileSystem for FileSystem {
     }
 }
 
-fn lsfile(path: &str, file: &cpio_reader::Entry) {
-    print!("#{ino:<4} ", ino = file.ino());
-    print_mode(file.mode());
-    println!(
-        " {nlink:<2} {uid:<3} {gid:<3} {size:>8} {path}",
-        nlink = file.nlink(),
-        uid = file.uid(),
-        gid = file.gid(),
-        size = file.file().len(),
-    );
+/// Returns the file system independent metadata for an archive
+/// member.
+fn dirent(name: &str, file: &Entry<'_>) -> ramdisk::DirEntry {
+    ramdisk::DirEntry {
+        name: String::from(name),
+        ino: file.ino.into(),
+        file_type: file.file_type(),
+        perms: (file.mode & 0o7777) as u16,
+        nlink: file.nlink,
+        uid: file.uid,
+        gid: file.gid,
+        size: file.data.len(),
+        allocated: file.data.len(),
+    }
 }
 
-fn first_char(mode: cpio_reader::Mode) -> char {
-    use cpio_reader::Mode;
-    match mode {
-        _ if mode.contains(Mode::DIRECTORY) => 'd',
-        _ if mode.contains(Mode::CHARACTER_SPECIAL_DEVICE) => 'c',
-        _ if mode.contains(Mode::BLOCK_SPECIAL_DEVICE) => 'b',
-        _ if mode.contains(Mode::SYMBOLIK_LINK) => 'l',
-        _ if mode.contains(Mode::SOCKET) => 's',
-        _ if mode.contains(Mode::NAMED_PIPE_FIFO) => 'f',
-        _ => '-',
+/// Returns the metadata for a directory that has no member of
+/// its own.
+fn implied_dir(name: &str) -> ramdisk::DirEntry {
+    ramdisk::DirEntry {
+        name: String::from(name),
+        ino: 0,
+        file_type: ramdisk::FileType::Dir,
+        perms: 0o755,
+        nlink: 2,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        allocated: 0,
     }
 }
 
-fn print_mode(mode: cpio_reader::Mode) {
-    use cpio_reader::Mode;
-    print!("{}", first_char(mode));
-    let alt = |bit, t, f| {
-        if mode.contains(bit) { t } else { f }
-    };
-    print!("{}", alt(Mode::USER_READABLE, 'r', '-'));
-    print!("{}", alt(Mode::USER_WRITABLE, 'w', '-'));
-    if !mode.contains(Mode::SUID) {
-        print!("{}", alt(Mode::USER_EXECUTABLE, 'x', '-'));
-    } else {
-        print!("{}", alt(Mode::USER_EXECUTABLE, 's', 'S'));
-    }
-
-    print!("{}", alt(Mode::GROUP_READABLE, 'r', '-'));
-    print!("{}", alt(Mode::GROUP_WRITABLE, 'w', '-'));
-    if !mode.contains(Mode::SGID) {
-        print!("{}", alt(Mode::GROUP_EXECUTABLE, 'x', '-'));
-    } else {
-        print!("{}", alt(Mode::GROUP_EXECUTABLE, 's', 'S'));
-    }
-
-    print!("{}", alt(Mode::WORLD_READABLE, 'r', '-'));
-    print!("{}", alt(Mode::WORLD_WRITABLE, 'w', '-'));
-    if !mode.contains(Mode::STICKY) {
-        print!("{}", alt(Mode::WORLD_EXECUTABLE, 'x', '-'));
-    } else {
-        print!("{}", alt(Mode::WORLD_EXECUTABLE, 't', 'T'));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ramdisk::{FileSystem as _, FileType};
+    use alloc::format;
+
+    /// Appends a member in the given format to an archive.
+    fn member(
+        cpio: &mut Vec<u8>,
+        format: Format,
+        name: &str,
+        mode: u32,
+        data: &[u8],
+    ) {
+        let namesize = name.len() + 1;
+        let header = match format {
+            // dev, ino, mode, uid, gid, nlink, rdev, then the
+            // mtime, name size, and file size.
+            Format::Odc => {
+                let fields = [0, 7, mode, 0, 0, 1, 0];
+                let header =
+                    fields.iter().fold(String::from("070707"), |h, f| {
+                        h + &format!("{f:06o}")
+                    });
+                header + &format!("{:011o}{namesize:06o}{:011o}", 0, data.len())
+            }
+            // ino, mode, uid, gid, nlink, mtime, file size, four
+            // device numbers, the name size, and the checksum.
+            Format::Newc | Format::Crc => {
+                let (magic, check) = match format {
+                    Format::Crc => {
+                        ("070702", data.iter().map(|&b| u32::from(b)).sum())
+                    }
+                    _ => ("070701", 0),
+                };
+                let size = data.len() as u32;
+                let ids = [7, mode, 0, 0, 1, 0, size];
+                let sizes = [namesize as u32, check];
+                ids.iter()
+                    .chain(&[0; 4])
+                    .chain(&sizes)
+                    .fold(String::from(magic), |h, f| h + &format!("{f:08x}"))
+            }
+        };
+        assert_eq!(header.len(), format.header_len());
+        let start = cpio.len();
+        cpio.extend_from_slice(header.as_bytes());
+        cpio.extend_from_slice(name.as_bytes());
+        cpio.push(0);
+        cpio.resize(
+            start + (cpio.len() - start).next_multiple_of(format.align()),
+            0,
+        );
+        cpio.extend_from_slice(data);
+        cpio.resize(
+            start + (cpio.len() - start).next_multiple_of(format.align()),
+            0,
+        );
+    }
+
+    fn archive(format: Format) -> Vec<u8> {
+        let mut cpio = Vec::new();
+        member(&mut cpio, format, ".", 0o40755, &[]);
+        member(&mut cpio, format, "./etc", 0o40755, &[]);
+        member(&mut cpio, format, "./etc/motd", 0o100644, b"hello\n");
+        member(&mut cpio, format, "./unix", 0o100755, b"\x7fELF...");
+        member(&mut cpio, format, TRAILER, 0, &[]);
+        cpio.resize(cpio.len().next_multiple_of(512), 0);
+        cpio
+    }
+
+    #[test]
+    fn formats() {
+        for format in [Format::Odc, Format::Newc, Format::Crc] {
+            let cpio = archive(format);
+            let names = entries(&cpio).map(|e| e.name()).collect::<Vec<_>>();
+            assert_eq!(names, ["", "etc", "etc/motd", "unix"], "{format:?}");
+            let fs = FileSystem::try_new(&cpio).unwrap();
+            let file = fs.open("/etc/motd").unwrap();
+            assert_eq!(file.file_type(), FileType::Regular);
+            let mut buf = [0u8; 16];
+            assert_eq!(file.read(0, &mut buf).unwrap(), 6);
+            assert_eq!(&buf[..6], b"hello\n");
+            let file = fs.open("/unix").unwrap();
+            assert_eq!(file.size(), 7);
+            let stat = fs.stat("/etc").unwrap();
+            assert_eq!(stat.file_type, FileType::Dir);
+            assert_eq!(stat.perms, 0o755);
+            let ls = fs.readdir("/etc").unwrap();
+            assert_eq!(ls.len(), 1);
+            assert_eq!(ls[0].name, "motd");
+            assert_eq!(ls[0].ino, 7);
+        }
+        assert!(FileSystem::try_new(b"070703").is_err());
+    }
+
+    #[test]
+    fn hierarchy() {
+        // No member for /boot or /boot/grub.
+        let mut cpio = Vec::new();
+        member(&mut cpio, Format::Newc, "boot/grub/menu.lst", 0o100644, b"x");
+        member(&mut cpio, Format::Newc, "boot/unix", 0o100755, b"elf");
+        member(&mut cpio, Format::Newc, "/etc/", 0o40700, &[]);
+        member(&mut cpio, Format::Newc, "etc/motd", 0o100644, b"hi\n");
+        member(&mut cpio, Format::Newc, "motd", 0o100644, b"hi\n");
+        member(&mut cpio, Format::Newc, TRAILER, 0, &[]);
+        let fs = FileSystem::try_new(&cpio).unwrap();
+        let names = |path| {
+            fs.readdir(path)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.name, e.file_type))
+                .collect::<Vec<_>>()
+        };
+        let dir = |name: &str| (String::from(name), FileType::Dir);
+        let file = |name: &str| (String::from(name), FileType::Regular);
+        assert_eq!(names("/"), [dir("boot"), dir("etc"), file("motd")]);
+        assert_eq!(names("/boot/"), [dir("grub"), file("unix")]);
+        assert_eq!(names("boot/grub"), [file("menu.lst")]);
+        assert_eq!(names("/etc"), [file("motd")]);
+        assert_eq!(names("/boot/unix"), [file("/boot/unix")]);
+        assert_eq!(fs.stat("/boot/grub").unwrap().file_type, FileType::Dir);
+        assert_eq!(fs.stat("/etc").unwrap().perms, 0o700);
+        assert_eq!(fs.stat("/").unwrap().file_type, FileType::Dir);
+        assert_eq!(fs.open("/boot").unwrap().file_type(), FileType::Dir);
+        assert_eq!(fs.readdir("/bo").unwrap_err(), Error::FsNoFile);
+        assert_eq!(fs.stat("/boot/grub/x").unwrap_err(), Error::FsNoFile);
+        assert_eq!(normalize("././a/b/"), "a/b");
+        assert_eq!(normalize("/."), "");
+    }
+
+    #[test]
+    fn symlinks() {
+        let mut cpio = Vec::new();
+        let link = 0o120777;
+        member(&mut cpio, Format::Newc, "bin", link, b"usr/bin");
+        member(&mut cpio, Format::Newc, "lib", link, b"/usr/lib/");
+        member(&mut cpio, Format::Newc, "usr/bin/sh", 0o100755, b"sh");
+        member(&mut cpio, Format::Newc, "usr/bin/ksh", link, b"./sh");
+        member(&mut cpio, Format::Newc, "usr/lib/libc.so", 0o100755, b"c");
+        member(&mut cpio, Format::Newc, "usr/sbin/sh", link, b"../../bin/sh");
+        member(&mut cpio, Format::Newc, "loop", link, b"a/../loop");
+        member(&mut cpio, Format::Newc, "dangling", link, b"nowhere");
+        member(&mut cpio, Format::Newc, "here", link, b".");
+        member(&mut cpio, Format::Newc, TRAILER, 0, &[]);
+        let fs = FileSystem::try_new(&cpio).unwrap();
+        let read = |path| {
+            let file = fs.open(path).unwrap();
+            let mut buf = [0u8; 8];
+            let len = file.read(0, &mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+        assert_eq!(read("/bin/sh"), "sh");
+        assert_eq!(read("/bin/ksh"), "sh");
+        assert_eq!(read("/usr/sbin/sh"), "sh");
+        assert_eq!(read("lib/../lib/libc.so"), "c");
+        assert_eq!(fs.open("/bin").unwrap().file_type(), FileType::Dir);
+        assert_eq!(fs.stat("/lib").unwrap().file_type, FileType::Dir);
+        let ls = fs.readdir("/bin").unwrap();
+        assert_eq!(ls.len(), 2);
+        assert_eq!(ls[0].name, "ksh");
+        assert_eq!(ls[0].file_type, FileType::SymLink);
+        assert_eq!(fs.open("/loop").map(|_| ()), Err(Error::FsInvPath));
+        assert_eq!(fs.stat("/dangling").unwrap_err(), Error::FsNoFile);
+        assert_eq!(fs.stat("/bin/sh/x").unwrap_err(), Error::FsInvPath);
+        // The limit is on links followed over the whole path, not
+        // on how deeply they nest.
+        let here = |n| "/here".repeat(n) + "/usr/bin/sh";
+        assert_eq!(read(&here(MAX_SYMLINKS)), "sh");
+        let deep = fs.open(&here(MAX_SYMLINKS + 1)).map(|_| ());
+        assert_eq!(deep, Err(Error::FsInvPath));
+    }
+
+    #[test]
+    fn find_members() {
+        let cpio = archive(Format::Newc);
+        for path in ["unix", "/unix", "./unix", "//./unix"] {
+            assert_eq!(find(&cpio, path), Ok(&b"\x7fELF..."[..]), "{path}");
+        }
+        assert_eq!(find(&cpio, "/etc/motd/"), Ok(&b"hello\n"[..]));
+        assert_eq!(find(&cpio, "motd"), Err(Error::CpioNoFile));
+    }
+
+    #[test]
+    fn checksums() {
+        let mut cpio = archive(Format::Crc);
+        let fs = FileSystem::try_new(&cpio).unwrap();
+        assert!(fs.open("/etc/motd").is_ok());
+        let at = cpio.windows(6).position(|w| w == b"hello\n").unwrap();
+        cpio[at] = b'j';
+        let fs = FileSystem::try_new(&cpio).unwrap();
+        assert_eq!(
+            fs.open("/etc/motd").map(|_| ()).map_err(|e| e.as_str()),
+            Err(Error::Verify.as_str())
+        );
+        assert_eq!(
+            find(&cpio, "etc/motd").map_err(|e| e.as_str()),
+            Err(Error::Verify.as_str())
+        );
+        assert_eq!(find(&cpio, "/unix"), Ok(&b"\x7fELF..."[..]));
+        // Damage to a header ends the archive there.
+        let mut cpio = archive(Format::Newc);
+        let at = cpio.windows(4).position(|w| w == b"unix").unwrap();
+        cpio[at - 2 - 110 + 20] = b'g';
+        assert_eq!(entries(&cpio).count(), 3);
     }
 }
@@ -19,6 +19,7 @@ pub(crate) fn cpuinfo() -> Option<(u8, u8, u8, Option<u32>)> {
     Some((family, features.model_id(), features.stepping_id(), pkg_type))
 }
 
+#[cfg_attr(test, allow(dead_code))]
 pub(crate) fn tscinfo() -> Option<cpuid::TscInfo> {
     let cpuid = cpuid::CpuId::new();
     cpuid.get_tsc_info()
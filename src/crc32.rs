@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The CRC-32 used by gzip, zip, and Ethernet, with the
+//! reflected polynomial 0xedb88320.
+
+const POLY: u32 = 0xedb8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut k = 0;
+    while k < 256 {
+        let mut crc = k as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[k] = crc;
+        k += 1;
+    }
+    table
+};
+
+/// Extends a running CRC with more data.  Start with 0 and pass
+/// the previous result to checksum data in pieces.
+pub(crate) fn update(crc: u32, bytes: &[u8]) -> u32 {
+    let crc = bytes
+        .iter()
+        .fold(!crc, |crc, &b| TABLE[usize::from(crc as u8 ^ b)] ^ (crc >> 8));
+    !crc
+}
+
+/// Returns the CRC-32 of the given data.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    update(0, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(update(crc32(b"1234"), b"56789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,264 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A last resort for when the heap is exhausted.
+//!
+//! A failed allocation would otherwise panic, and the only way
+//! out of a panic is a reset, which loses whatever state led to
+//! the failure.  Instead, we drop into a small REPL that uses no
+//! heap at all: it reads lines into a buffer on the stack,
+//! parses them in place, and writes straight to the console
+//! UART.  Only a few read-only commands are offered, so that
+//! the machine may be inspected, but not disturbed further.
+
+use crate::allocator;
+use crate::bldb;
+use crate::post;
+use crate::sink;
+use crate::uart::{self, Uart};
+use core::alloc::Layout;
+use core::arch::asm;
+use core::fmt::Write;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// The loader's configuration, recorded by `arm` so that `peek`
+/// may consult the page table.
+static CONFIG: AtomicPtr<bldb::Config> = AtomicPtr::new(ptr::null_mut());
+
+/// Set once we have entered the emergency REPL.
+static ENTERED: AtomicBool = AtomicBool::new(false);
+
+/// The longest line we will read.
+const LINE_LEN: usize = 128;
+
+/// The most bytes `peek` will show at once.
+const MAX_PEEK: usize = 256;
+
+const HELP: &str = "\
+help                 show this message
+peek <addr> [<len>]  dump up to 256 bytes of mapped memory
+regs                 show the control registers, stack and flags
+meminfo              show the failed request and heap usage
+dmesg [<len>]        show the in-memory log, or its last <len> bytes
+halt                 stop the machine
+Numbers are decimal, or hexadecimal with a 0x prefix.";
+
+/// Records the configuration for use by the emergency REPL.
+pub(crate) fn arm(config: &mut bldb::Config) {
+    CONFIG.store(config, Ordering::Release);
+}
+
+/// The commands available in the emergency REPL.
+#[derive(Debug, Eq, PartialEq)]
+enum Cmd {
+    Help,
+    Peek(usize, usize),
+    Regs,
+    Meminfo,
+    Dmesg(Option<usize>),
+    Halt,
+}
+
+/// Parses a number: hexadecimal with a `0x` prefix, or decimal.
+fn num(tok: &str) -> Option<usize> {
+    match tok.strip_prefix("0x").or_else(|| tok.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => tok.parse().ok(),
+    }
+}
+
+/// Parses a line into a command, or `None` if the line is blank.
+fn parse(line: &str) -> Result<Option<Cmd>, &'static str> {
+    let mut toks = line.split_ascii_whitespace();
+    let Some(name) = toks.next() else {
+        return Ok(None);
+    };
+    let cmd = match name {
+        "help" | "?" => Cmd::Help,
+        "peek" => {
+            let addr =
+                toks.next().and_then(num).ok_or("peek <addr> [<len>]")?;
+            let len = match toks.next() {
+                Some(tok) => num(tok).ok_or("bad length")?,
+                None => 16,
+            };
+            Cmd::Peek(addr, len.min(MAX_PEEK))
+        }
+        "regs" => Cmd::Regs,
+        "meminfo" => Cmd::Meminfo,
+        "dmesg" => match toks.next() {
+            Some(tok) => Cmd::Dmesg(Some(num(tok).ok_or("bad length")?)),
+            None => Cmd::Dmesg(None),
+        },
+        "halt" => Cmd::Halt,
+        _ => return Err("unknown command; try help"),
+    };
+    if toks.next().is_some() {
+        return Err("too many arguments");
+    }
+    Ok(Some(cmd))
+}
+
+/// Reads a line from the console into `buf`, echoing as we go.
+/// Non-ASCII input is ignored.
+fn readline<'a>(cons: &mut Uart, buf: &'a mut [u8; LINE_LEN]) -> &'a str {
+    let mut len = 0;
+    loop {
+        match cons.getb() {
+            b'\r' | b'\n' => break,
+            0x08 | 0x7f if len > 0 => {
+                len -= 1;
+                cons.putbs_crnl(b"\x08 \x08");
+            }
+            0x15 => {
+                while len > 0 {
+                    len -= 1;
+                    cons.putbs_crnl(b"\x08 \x08");
+                }
+            }
+            b @ b' '..=b'~' if len < LINE_LEN => {
+                buf[len] = b;
+                len += 1;
+                cons.putb(b);
+            }
+            _ => {}
+        }
+    }
+    cons.putbs_crnl(b"\n");
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+fn peek(cons: &mut Uart, addr: usize, len: usize) {
+    let config = CONFIG.load(Ordering::Acquire);
+    if config.is_null() {
+        let _ = writeln!(cons, "peek: page table unknown");
+        return;
+    }
+    // Safety: the configuration outlives the loader, and we
+    // only read the page table, which nothing else will touch
+    // again as we never return to the code that holds it.
+    let config = unsafe { &*config };
+    for (k, a) in (addr..addr.saturating_add(len)).enumerate() {
+        if (k == 0 || a % 4096 == 0)
+            && config.page_table.lookup(ptr::without_provenance(a)).is_none()
+        {
+            let _ = writeln!(cons, "\n{a:#x} is not mapped");
+            return;
+        }
+        if k % 16 == 0 {
+            let sep = if k == 0 { "" } else { "\n" };
+            let _ = write!(cons, "{sep}{a:016x}:");
+        }
+        let p = ptr::with_exposed_provenance::<u8>(a);
+        let b = unsafe { ptr::read_volatile(p) };
+        let _ = write!(cons, " {b:02x}");
+    }
+    let _ = writeln!(cons);
+}
+
+fn regs(cons: &mut Uart) {
+    let (cr0, cr2, cr3, cr4): (u64, u64, u64, u64);
+    let (rsp, rflags): (u64, u64);
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0);
+        asm!("mov {}, cr2", out(reg) cr2);
+        asm!("mov {}, cr3", out(reg) cr3);
+        asm!("mov {}, cr4", out(reg) cr4);
+        asm!("mov {}, rsp", out(reg) rsp);
+        asm!("pushfq; pop {}", out(reg) rflags);
+    }
+    let _ = writeln!(cons, "cr0    {cr0:#018x}");
+    let _ = writeln!(cons, "cr2    {cr2:#018x}");
+    let _ = writeln!(cons, "cr3    {cr3:#018x}");
+    let _ = writeln!(cons, "cr4    {cr4:#018x}");
+    let _ = writeln!(cons, "rsp    {rsp:#018x}");
+    let _ = writeln!(cons, "rflags {rflags:#018x}");
+}
+
+fn meminfo(cons: &mut Uart, layout: Layout) {
+    let _ = writeln!(
+        cons,
+        "failed request: {} bytes, aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    match allocator::usage() {
+        Some(usage) => {
+            let _ = writeln!(cons, "heap:    {:#x} bytes", usage.size);
+            let _ = writeln!(cons, "tail:    {:#x} bytes free", usage.tail);
+            let _ = writeln!(cons, "quick:   {:#x} bytes free", usage.quick);
+            let _ = writeln!(cons, "misc:    {:#x} bytes free", usage.misc);
+            let _ = writeln!(cons, "total:   {:#x} bytes free", usage.free());
+        }
+        None => {
+            let _ = writeln!(cons, "heap: allocator busy");
+        }
+    }
+}
+
+fn dmesg(cons: &mut Uart, len: Option<usize>) {
+    let shown = sink::try_log(len, |older, newer| {
+        cons.putbs_crnl(older);
+        cons.putbs_crnl(newer);
+    });
+    if shown.is_none() {
+        let _ = writeln!(cons, "dmesg: log busy");
+    }
+}
+
+/// Enters the emergency REPL after a failure to allocate the
+/// given layout.  Never returns.
+#[cfg_attr(any(test, clippy), allow(dead_code))]
+pub(crate) fn repl(layout: Layout) -> ! {
+    if !uart::cons_inited() || ENTERED.swap(true, Ordering::AcqRel) {
+        panic!("memory allocation of {} bytes failed", layout.size());
+    }
+    post::post(post::Code::Emergency);
+    let mut cons = uart::cons();
+    let _ = writeln!(
+        cons,
+        "\nallocation of {} bytes failed: heap exhausted.\n\
+         Entering the emergency REPL; try help.",
+        layout.size()
+    );
+    let mut buf = [0u8; LINE_LEN];
+    loop {
+        cons.puts("emergency> ");
+        let line = readline(&mut cons, &mut buf);
+        match parse(line) {
+            Ok(None) => {}
+            Ok(Some(Cmd::Help)) => {
+                let _ = writeln!(cons, "{HELP}");
+            }
+            Ok(Some(Cmd::Peek(addr, len))) => peek(&mut cons, addr, len),
+            Ok(Some(Cmd::Regs)) => regs(&mut cons),
+            Ok(Some(Cmd::Meminfo)) => meminfo(&mut cons, layout),
+            Ok(Some(Cmd::Dmesg(len))) => dmesg(&mut cons, len),
+            Ok(Some(Cmd::Halt)) => unsafe { bldb::dnr() },
+            Err(msg) => {
+                let _ = writeln!(cons, "{msg}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse("  "), Ok(None));
+        assert_eq!(parse("help"), Ok(Some(Cmd::Help)));
+        assert_eq!(parse("peek 0x1000"), Ok(Some(Cmd::Peek(0x1000, 16))));
+        assert_eq!(parse("peek 4096 1000"), Ok(Some(Cmd::Peek(4096, 256))));
+        assert_eq!(parse("dmesg 0x20"), Ok(Some(Cmd::Dmesg(Some(32)))));
+        assert_eq!(parse("dmesg"), Ok(Some(Cmd::Dmesg(None))));
+        assert!(parse("peek").is_err());
+        assert!(parse("peek zz").is_err());
+        assert!(parse("regs now").is_err());
+        assert!(parse("poke 0 0").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Random numbers from the processor's hardware generator.
+//!
+//! RDRAND returns the output of a DRBG that is periodically
+//! reseeded from the hardware entropy source; RDSEED returns
+//! conditioned entropy directly, and so may be exhausted more
+//! easily.  Either instruction may fail transiently, which is
+//! indicated by a clear carry flag, and is retried a bounded
+//! number of times.
+//!
+//! Some parts have been known to return all ones, with the carry
+//! flag set, after a bad microcode update or resume, so every
+//! value drawn is also subjected to simple health tests: values
+//! that are all zeros or all ones, or that repeat the previous
+//! value, are rejected as failures of the generator rather than
+//! retried.
+
+use crate::result::{Error, Result};
+use core::arch::asm;
+
+/// How many times RDRAND is attempted before giving up.  This is
+/// the count recommended by Intel, and is ample for AMD parts.
+const RDRAND_RETRIES: usize = 10;
+
+/// RDSEED may fail more often under load, so try it harder.
+const RDSEED_RETRIES: usize = 1000;
+
+/// The instruction used to draw random numbers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Source {
+    Rdrand,
+    Rdseed,
+}
+
+impl Source {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Source::Rdrand => "rdrand",
+            Source::Rdseed => "rdseed",
+        }
+    }
+
+    /// Returns true iff the processor implements the source.
+    pub(crate) fn available(self) -> bool {
+        use x86::cpuid::CpuId;
+        let cpuid = CpuId::new();
+        match self {
+            Source::Rdrand => {
+                cpuid.get_feature_info().is_some_and(|f| f.has_rdrand())
+            }
+            Source::Rdseed => cpuid
+                .get_extended_feature_info()
+                .is_some_and(|f| f.has_rdseed()),
+        }
+    }
+
+    fn retries(self) -> usize {
+        match self {
+            Source::Rdrand => RDRAND_RETRIES,
+            Source::Rdseed => RDSEED_RETRIES,
+        }
+    }
+
+    /// Executes the instruction once, returning its value if the
+    /// carry flag indicates success.
+    fn step(self) -> Option<u64> {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            match self {
+                Source::Rdrand => asm!(
+                    "rdrand {value}",
+                    "setc {ok}",
+                    value = out(reg) value,
+                    ok = out(reg_byte) ok,
+                    options(nomem, nostack),
+                ),
+                Source::Rdseed => asm!(
+                    "rdseed {value}",
+                    "setc {ok}",
+                    value = out(reg) value,
+                    ok = out(reg_byte) ok,
+                    options(nomem, nostack),
+                ),
+            }
+        }
+        (ok != 0).then_some(value)
+    }
+}
+
+/// Counts of what happened while drawing values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Stats {
+    pub(crate) draws: usize,
+    pub(crate) retries: usize,
+    pub(crate) ones: u64,
+}
+
+/// A stream of health-checked values from a source.
+pub(crate) struct Generator<F> {
+    step: F,
+    retries: usize,
+    last: Option<u64>,
+    pub(crate) stats: Stats,
+}
+
+impl Generator<fn() -> Option<u64>> {
+    /// Returns a generator drawing from the given source, or an
+    /// error if the processor does not implement it.
+    pub(crate) fn new(source: Source) -> Result<Self> {
+        if !source.available() {
+            return Err(Error::Entropy("entropy: source not implemented"));
+        }
+        let step: fn() -> Option<u64> = match source {
+            Source::Rdrand => || Source::Rdrand.step(),
+            Source::Rdseed => || Source::Rdseed.step(),
+        };
+        Ok(Generator::with_step(step, source.retries()))
+    }
+}
+
+impl<F: FnMut() -> Option<u64>> Generator<F> {
+    fn with_step(step: F, retries: usize) -> Self {
+        Generator { step, retries, last: None, stats: Stats::default() }
+    }
+
+    /// Draws a single value.
+    pub(crate) fn next(&mut self) -> Result<u64> {
+        for attempt in 0..self.retries {
+            if let Some(value) = (self.step)() {
+                self.stats.retries += attempt;
+                return self.check(value);
+            }
+            core::hint::spin_loop();
+        }
+        self.stats.retries += self.retries;
+        Err(Error::Entropy("entropy: source exhausted"))
+    }
+
+    fn check(&mut self, value: u64) -> Result<u64> {
+        if value == 0 || value == !0 {
+            return Err(Error::Entropy("entropy: stuck value"));
+        }
+        if self.last == Some(value) {
+            return Err(Error::Entropy("entropy: repeated value"));
+        }
+        self.last = Some(value);
+        self.stats.draws += 1;
+        self.stats.ones += u64::from(value.count_ones());
+        Ok(value)
+    }
+
+    /// Fills a buffer with random bytes.
+    pub(crate) fn fill(&mut self, buf: &mut [u8]) -> Result<()> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next()?.to_ne_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+/// Returns a random identifier, used to tell the records of one
+/// transfer from those of another, or zero if RDRAND is not
+/// implemented or fails.
+pub(crate) fn session_id() -> u64 {
+    Generator::new(Source::Rdrand).and_then(|mut rng| rng.next()).unwrap_or(0)
+}
+
+/// Returns true iff the proportion of one bits in the values
+/// drawn so far is plausible for a uniform source: within eight
+/// standard deviations of half.
+pub(crate) fn unbiased(stats: &Stats) -> bool {
+    let bits = stats.draws as u64 * 64;
+    let expected = bits / 2;
+    // The standard deviation is sqrt(bits) / 2.
+    let sigma = bits.isqrt() / 2;
+    stats.ones.abs_diff(expected) <= 8 * sigma.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scripted(values: &[Option<u64>]) -> impl FnMut() -> Option<u64> {
+        let mut values = values.to_vec();
+        values.reverse();
+        move || values.pop().expect("script exhausted")
+    }
+
+    #[test]
+    fn retries() {
+        let mut rng = Generator::with_step(
+            scripted(&[None, None, Some(0x1234), None, None, None]),
+            3,
+        );
+        assert_eq!(rng.next(), Ok(0x1234));
+        assert_eq!(rng.stats.retries, 2);
+        assert_eq!(
+            rng.next(),
+            Err(Error::Entropy("entropy: source exhausted"))
+        );
+    }
+
+    #[test]
+    fn health() {
+        let mut rng = Generator::with_step(
+            scripted(&[Some(5), Some(5), Some(!0), Some(0), Some(6)]),
+            1,
+        );
+        assert_eq!(rng.next(), Ok(5));
+        assert_eq!(rng.next(), Err(Error::Entropy("entropy: repeated value")));
+        assert_eq!(rng.next(), Err(Error::Entropy("entropy: stuck value")));
+        assert_eq!(rng.next(), Err(Error::Entropy("entropy: stuck value")));
+        assert_eq!(rng.next(), Ok(6));
+    }
+
+    #[test]
+    fn fill_and_bias() {
+        let mut x = 0x9e37_79b9_7f4a_7c15u64;
+        let mut rng = Generator::with_step(
+            move || {
+                // xorshift64
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                Some(x)
+            },
+            1,
+        );
+        let mut buf = [0u8; 13];
+        rng.fill(&mut buf).unwrap();
+        assert_eq!(rng.stats.draws, 2);
+        assert!(buf.iter().any(|&b| b != 0));
+        for _ in 0..1000 {
+            rng.next().unwrap();
+        }
+        assert!(unbiased(&rng.stats));
+        let biased = Stats { draws: 1000, retries: 0, ones: 40_000 };
+        assert!(!unbiased(&biased));
+    }
+}
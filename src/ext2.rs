@@ -0,0 +1,936 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ext2 file system support.
+//!
+//! Linux ramdisks are usually ext2 images, or ext3 or ext4
+//! images made with the same tools.  This is a read-only driver
+//! for them.  Both the classic block map and ext4's extent
+//! trees are understood, as are 64-bit group descriptors, so
+//! images made by `mkfs.ext4` with its default options mount,
+//! too.  Images using features that change how data is found,
+//! such as inline data, are refused.
+//!
+//! A journal, if there is one, is not replayed: an image taken
+//! from a system that was not shut down cleanly may be missing
+//! its most recent changes.
+
+use crate::io;
+use crate::ramdisk::{self, FileType};
+use crate::result::{Error, Result};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The superblock is always 1KiB into the volume.
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SUPERBLOCK_LEN: usize = 1024;
+const MAGIC: u16 = 0xef53;
+
+const ROOT_INO: u32 = 2;
+
+const COMPAT_HAS_JOURNAL: u32 = 0x0004;
+
+/// Incompatible features, which must be understood to read the
+/// file system.  Of these, we support the ones below.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const INCOMPAT_RECOVER: u32 = 0x0004;
+const INCOMPAT_EXTENTS: u32 = 0x0040;
+const INCOMPAT_64BIT: u32 = 0x0080;
+const INCOMPAT_MMP: u32 = 0x0100;
+const INCOMPAT_FLEX_BG: u32 = 0x0200;
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const INCOMPAT_LARGEDIR: u32 = 0x4000;
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_MMP
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+
+/// With this read-only compatible feature, inode block counts
+/// are 48 bits wide.
+const RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+
+/// Inode flags.
+const HUGE_FILE_FL: u32 = 0x0004_0000;
+const EXTENTS_FL: u32 = 0x0008_0000;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFIFO: u16 = 0o010000;
+const S_IFCHR: u16 = 0o020000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFBLK: u16 = 0o060000;
+const S_IFREG: u16 = 0o100000;
+const S_IFLNK: u16 = 0o120000;
+const S_IFSOCK: u16 = 0o140000;
+
+/// The size of the block map, or extent tree root, in an inode.
+const IBLOCK_LEN: usize = 60;
+/// The block map holds 12 direct block numbers, followed by
+/// one each of single, double, and triple indirect blocks.
+const NDIRECT: u64 = 12;
+
+const EXTENT_MAGIC: u16 = 0xf30a;
+/// The size of extent tree headers and entries.
+const EXTENT_ENTRY_LEN: usize = 12;
+/// Extent trees are at most this deep.
+const EXTENT_MAX_DEPTH: usize = 5;
+/// Extents longer than this are uninitialized: the blocks are
+/// allocated but read as zeroes.
+const EXTENT_INIT_MAX: u16 = 32768;
+
+/// The most symbolic links followed in resolving one path.
+const MAX_SYMLINKS: usize = 8;
+
+fn le16(bs: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([bs[off], bs[off + 1]])
+}
+
+fn le32(bs: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([bs[off], bs[off + 1], bs[off + 2], bs[off + 3]])
+}
+
+/// The layout of the volume, from the superblock.
+#[derive(Clone, Copy, Debug)]
+struct Geometry {
+    block_size: usize,
+    ninodes: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    /// The block holding the first group descriptor.
+    gdt_block: u64,
+    desc_size: usize,
+    compat: u32,
+    incompat: u32,
+    ro_compat: u32,
+}
+
+impl Geometry {
+    fn parse(bs: &[u8]) -> Result<Geometry> {
+        let sb = bs
+            .get(SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + SUPERBLOCK_LEN)
+            .ok_or(Error::FsInvMagic)?;
+        if le16(sb, 56) != MAGIC {
+            return Err(Error::FsInvMagic);
+        }
+        let log_block_size = le32(sb, 24);
+        if log_block_size > 6 {
+            return Err(Error::FsInvMagic);
+        }
+        let block_size = 1024 << log_block_size;
+        let (inode_size, compat, incompat, ro_compat) = match le32(sb, 76) {
+            0 => (128, 0, 0, 0),
+            _ => (
+                usize::from(le16(sb, 88)),
+                le32(sb, 92),
+                le32(sb, 96),
+                le32(sb, 100),
+            ),
+        };
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            return Err(Error::FsUnsupported);
+        }
+        let desc_size = match incompat & INCOMPAT_64BIT {
+            0 => 32,
+            _ => usize::from(le16(sb, 254)),
+        };
+        let inodes_per_group = le32(sb, 40);
+        let valid = inode_size.is_power_of_two()
+            && (128..=block_size).contains(&inode_size)
+            && desc_size.is_power_of_two()
+            && (32..=block_size).contains(&desc_size)
+            && inodes_per_group != 0;
+        if !valid {
+            return Err(Error::FsInvMagic);
+        }
+        Ok(Geometry {
+            block_size,
+            ninodes: le32(sb, 0),
+            inodes_per_group,
+            inode_size,
+            gdt_block: u64::from(le32(sb, 20)) + 1,
+            desc_size,
+            compat,
+            incompat,
+            ro_compat,
+        })
+    }
+}
+
+/// The parts of an on-disk inode that we use.
+#[derive(Clone, Debug)]
+struct Inode {
+    ino: u32,
+    mode: u16,
+    nlink: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    /// The number of bytes of storage allocated to the file,
+    /// including any extended attribute block.
+    allocated: u64,
+    flags: u32,
+    /// The block holding extended attributes, if any.
+    xattr_block: u32,
+    /// The block map or root of the extent tree, or, for short
+    /// symbolic links, the target.
+    iblock: [u8; IBLOCK_LEN],
+}
+
+impl Inode {
+    fn parse(ino: u32, raw: &[u8], geometry: &Geometry) -> Inode {
+        let blocks = u64::from(le32(raw, 28));
+        let blocks = match geometry.ro_compat & RO_COMPAT_HUGE_FILE {
+            0 => blocks,
+            _ => u64::from(le16(raw, 116)) << 32 | blocks,
+        };
+        let flags = le32(raw, 32);
+        let unit = match flags & HUGE_FILE_FL {
+            0 => 512,
+            _ => geometry.block_size as u64,
+        };
+        let mut iblock = [0; IBLOCK_LEN];
+        iblock.copy_from_slice(&raw[40..40 + IBLOCK_LEN]);
+        Inode {
+            ino,
+            mode: le16(raw, 0),
+            nlink: le16(raw, 26),
+            uid: u32::from(le16(raw, 120)) << 16 | u32::from(le16(raw, 2)),
+            gid: u32::from(le16(raw, 122)) << 16 | u32::from(le16(raw, 24)),
+            size: u64::from(le32(raw, 108)) << 32 | u64::from(le32(raw, 4)),
+            allocated: blocks.saturating_mul(unit),
+            flags,
+            xattr_block: le32(raw, 104),
+            iblock,
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.mode & S_IFMT {
+            S_IFIFO => FileType::Fifo,
+            S_IFCHR => FileType::Char,
+            S_IFDIR => FileType::Dir,
+            S_IFBLK => FileType::Block,
+            S_IFREG => FileType::Regular,
+            S_IFLNK => FileType::SymLink,
+            S_IFSOCK => FileType::Sock,
+            _ => FileType::Unused,
+        }
+    }
+
+    /// Returns true iff this is a symbolic link whose target is
+    /// held in the inode itself, rather than in a data block.
+    fn is_fast_symlink(&self, geometry: &Geometry) -> bool {
+        let xattr = match self.xattr_block {
+            0 => 0,
+            _ => geometry.block_size as u64,
+        };
+        self.file_type() == FileType::SymLink
+            && self.size < IBLOCK_LEN as u64
+            && self.flags & EXTENTS_FL == 0
+            && self.allocated <= xattr
+    }
+
+    fn size(&self) -> usize {
+        self.size as usize
+    }
+}
+
+/// A directory entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    name: String,
+    ino: u32,
+}
+
+/// Parses the entries of a directory.  Entries never span
+/// blocks, so the directory can be parsed as a whole; should a
+/// record be malformed, the rest are ignored.
+fn parse_dir(bytes: &[u8], block_size: usize, filetype: bool) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut off = 0;
+    while off + 8 <= bytes.len() {
+        let ino = le32(bytes, off);
+        // In 64KiB blocks, a record spanning the block has a
+        // length that does not fit in 16 bits.
+        let rec_len = match usize::from(le16(bytes, off + 4)) {
+            0 | 65535 if block_size == 65536 => 65536,
+            len => len,
+        };
+        let name_len = match filetype {
+            true => usize::from(bytes[off + 6]),
+            false => usize::from(le16(bytes, off + 6)),
+        };
+        if rec_len < 8 || off + rec_len > bytes.len() {
+            break;
+        }
+        if ino != 0 && 8 + name_len <= rec_len {
+            let name = &bytes[off + 8..off + 8 + name_len];
+            let name = String::from_utf8_lossy(name).into_owned();
+            entries.push(Entry { name, ino });
+        }
+        off += rec_len;
+    }
+    entries
+}
+
+/// An ext2 volume in memory.
+struct Volume {
+    sd: io::Sd,
+    geometry: Geometry,
+}
+
+impl Volume {
+    fn bytes(&self) -> &[u8] {
+        unsafe { self.sd.as_slice() }
+    }
+
+    /// Returns the contents of a block.
+    fn block(&self, block: u64) -> Result<&[u8]> {
+        let size = self.geometry.block_size;
+        usize::try_from(block)
+            .ok()
+            .and_then(|block| block.checked_mul(size))
+            .and_then(|offset| self.bytes().get(offset..offset + size))
+            .ok_or(Error::FsRead)
+    }
+
+    /// Reads an inode from its group's inode table.
+    fn inode(&self, ino: u32) -> Result<Inode> {
+        let geometry = &self.geometry;
+        if ino == 0 || ino > geometry.ninodes {
+            return Err(Error::FsRead);
+        }
+        let group = ((ino - 1) / geometry.inodes_per_group) as usize;
+        let index = ((ino - 1) % geometry.inodes_per_group) as usize;
+        let bs = self.bytes();
+        let block_size = geometry.block_size;
+        let desc = geometry.gdt_block as usize * block_size
+            + group * geometry.desc_size;
+        let desc =
+            bs.get(desc..desc + geometry.desc_size).ok_or(Error::FsRead)?;
+        let table = match geometry.desc_size {
+            32 => u64::from(le32(desc, 8)),
+            _ => u64::from(le32(desc, 0x28)) << 32 | u64::from(le32(desc, 8)),
+        };
+        let offset = usize::try_from(table)
+            .ok()
+            .and_then(|table| table.checked_mul(block_size))
+            .and_then(|offset| offset.checked_add(index * geometry.inode_size))
+            .ok_or(Error::FsRead)?;
+        let raw = bs.get(offset..offset + 128).ok_or(Error::FsRead)?;
+        Ok(Inode::parse(ino, raw, geometry))
+    }
+
+    /// Maps a block of a file to the block of the volume that
+    /// holds it, or to `None` if it is a hole.
+    fn bmap(&self, inode: &Inode, lblock: u64) -> Result<Option<u64>> {
+        if inode.flags & EXTENTS_FL != 0 {
+            self.extent_map(&inode.iblock, lblock)
+        } else {
+            self.block_map(&inode.iblock, lblock)
+        }
+    }
+
+    fn block_map(&self, iblock: &[u8], mut lblock: u64) -> Result<Option<u64>> {
+        let ptr = |bs: &[u8], k: u64| u64::from(le32(bs, k as usize * 4));
+        let nonzero = |block| (block != 0).then_some(block);
+        if lblock < NDIRECT {
+            return Ok(nonzero(ptr(iblock, lblock)));
+        }
+        lblock -= NDIRECT;
+        let per_block = (self.geometry.block_size / 4) as u64;
+        let mut covered = 1;
+        for level in 0..3 {
+            covered *= per_block;
+            if lblock >= covered {
+                lblock -= covered;
+                continue;
+            }
+            let mut block = ptr(iblock, NDIRECT + level);
+            let mut stride = covered;
+            while stride > 1 && block != 0 {
+                stride /= per_block;
+                block = ptr(self.block(block)?, lblock / stride);
+                lblock %= stride;
+            }
+            return Ok(nonzero(block));
+        }
+        Err(Error::FsOffset)
+    }
+
+    fn extent_map(&self, root: &[u8], lblock: u64) -> Result<Option<u64>> {
+        let mut node = root;
+        for _ in 0..=EXTENT_MAX_DEPTH {
+            if le16(node, 0) != EXTENT_MAGIC {
+                return Err(Error::FsRead);
+            }
+            let nentries = usize::from(le16(node, 2));
+            let depth = le16(node, 6);
+            let end = EXTENT_ENTRY_LEN * (1 + nentries);
+            let entries = node
+                .get(EXTENT_ENTRY_LEN..end)
+                .ok_or(Error::FsRead)?
+                .chunks_exact(EXTENT_ENTRY_LEN);
+            if depth == 0 {
+                for extent in entries {
+                    let start = u64::from(le32(extent, 0));
+                    let (len, init) = match le16(extent, 4) {
+                        len if len > EXTENT_INIT_MAX => {
+                            (len - EXTENT_INIT_MAX, false)
+                        }
+                        len => (len, true),
+                    };
+                    if (start..start + u64::from(len)).contains(&lblock) {
+                        let block = u64::from(le16(extent, 6)) << 32
+                            | u64::from(le32(extent, 8));
+                        return Ok(init.then_some(block + lblock - start));
+                    }
+                }
+                return Ok(None);
+            }
+            // Index entries are sorted by the first block they
+            // cover; the one we want is the last starting at or
+            // before the block sought.
+            let Some(index) = entries
+                .take_while(|index| u64::from(le32(index, 0)) <= lblock)
+                .last()
+            else {
+                return Ok(None);
+            };
+            let child =
+                u64::from(le16(index, 8)) << 32 | u64::from(le32(index, 4));
+            node = self.block(child)?;
+        }
+        Err(Error::FsRead)
+    }
+
+    /// Reads from a file, filling holes with zeroes.
+    fn read(
+        &self,
+        inode: &Inode,
+        offset: u64,
+        dst: &mut [u8],
+    ) -> Result<usize> {
+        let offset = offset as usize;
+        let size = inode.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = usize::min(dst.len(), size - offset);
+        if inode.is_fast_symlink(&self.geometry) {
+            dst[..len].copy_from_slice(&inode.iblock[offset..offset + len]);
+            return Ok(len);
+        }
+        let block_size = self.geometry.block_size;
+        let mut nread = 0;
+        while nread < len {
+            let pos = offset + nread;
+            let start = pos % block_size;
+            let count = usize::min(block_size - start, len - nread);
+            let dst = &mut dst[nread..nread + count];
+            match self.bmap(inode, (pos / block_size) as u64)? {
+                Some(block) => {
+                    let data = self.block(block)?;
+                    dst.copy_from_slice(&data[start..start + count]);
+                }
+                None => dst.fill(0),
+            }
+            nread += count;
+        }
+        Ok(len)
+    }
+
+    /// Reads a whole file, such as a directory or the target of
+    /// a symbolic link.
+    fn read_all(&self, inode: &Inode) -> Result<Vec<u8>> {
+        let mut bytes = vec![0; inode.size()];
+        self.read(inode, 0, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads a directory's entries.
+    fn readdir(&self, dir: &Inode) -> Result<Vec<Entry>> {
+        let bytes = self.read_all(dir)?;
+        let filetype = self.geometry.incompat & INCOMPAT_FILETYPE != 0;
+        Ok(parse_dir(&bytes, self.geometry.block_size, filetype))
+    }
+}
+
+pub(crate) struct FileSystem {
+    vol: Rc<Volume>,
+}
+
+impl FileSystem {
+    pub(crate) fn try_new(bs: &[u8]) -> Result<FileSystem> {
+        let geometry = Geometry::parse(bs)?;
+        let sd = unsafe { io::Sd::from_slice(bs) };
+        let fs = FileSystem { vol: Rc::new(Volume { sd, geometry }) };
+        if fs.vol.inode(ROOT_INO)?.file_type() != FileType::Dir {
+            return Err(Error::FsInvMagic);
+        }
+        Ok(fs)
+    }
+
+    /// Returns true iff the journal holds changes that have not
+    /// been written back to the file system.
+    pub(crate) fn needs_recovery(&self) -> bool {
+        self.vol.geometry.incompat & INCOMPAT_RECOVER != 0
+    }
+
+    /// Looks up the inode for the given path, following symbolic
+    /// links, relative to the given directory.  `nlinks` counts
+    /// the links followed over the whole lookup, including those
+    /// in the targets of other links.
+    fn namex(
+        &self,
+        dir: Inode,
+        path: &str,
+        nlinks: &mut usize,
+    ) -> Result<Inode> {
+        let mut ip = match path.starts_with('/') {
+            true => self.vol.inode(ROOT_INO)?,
+            false => dir,
+        };
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if ip.file_type() != FileType::Dir {
+                return Err(Error::FsInvPath);
+            }
+            let entry = self
+                .vol
+                .readdir(&ip)?
+                .into_iter()
+                .find(|entry| entry.name == name)
+                .ok_or(Error::FsNoFile)?;
+            let mut tip = self.vol.inode(entry.ino)?;
+            if tip.file_type() == FileType::SymLink {
+                if *nlinks == MAX_SYMLINKS {
+                    return Err(Error::FsInvPath);
+                }
+                *nlinks += 1;
+                let target = self.vol.read_all(&tip)?;
+                let target = String::from_utf8_lossy(&target);
+                tip = self.namex(ip, &target, nlinks)?;
+            }
+            ip = tip;
+        }
+        Ok(ip)
+    }
+
+    fn namei(&self, path: &str) -> Result<Inode> {
+        self.namex(self.vol.inode(ROOT_INO)?, path, &mut 0)
+    }
+}
+
+/// Returns the file system independent metadata for an inode.
+fn dirent(inode: &Inode, name: &str) -> ramdisk::DirEntry {
+    ramdisk::DirEntry {
+        name: String::from(name),
+        ino: inode.ino.into(),
+        file_type: inode.file_type(),
+        perms: inode.mode & 0o7777,
+        nlink: inode.nlink.into(),
+        uid: inode.uid,
+        gid: inode.gid,
+        size: inode.size(),
+        allocated: inode.allocated as usize,
+    }
+}
+
+pub(crate) struct File {
+    vol: Rc<Volume>,
+    inode: Inode,
+}
+
+impl ramdisk::File for File {
+    fn file_type(&self) -> FileType {
+        self.inode.file_type()
+    }
+
+    /// Finds the extent by mapping successive blocks until one
+    /// differs from the first in whether it is a hole.
+    fn extent(&self, offset: u64, max: usize) -> Result<ramdisk::Extent> {
+        let off = offset as usize;
+        let end = usize::min(self.inode.size(), off.saturating_add(max));
+        if off >= end {
+            return Ok(ramdisk::Extent::Data(0));
+        }
+        if self.inode.is_fast_symlink(&self.vol.geometry) {
+            return Ok(ramdisk::Extent::Data(end - off));
+        }
+        let block_size = self.vol.geometry.block_size;
+        let is_hole = |off: usize| {
+            let lblock = (off / block_size) as u64;
+            self.vol.bmap(&self.inode, lblock).map(|block| block.is_none())
+        };
+        let hole = is_hole(off)?;
+        let mut next = (off / block_size + 1) * block_size;
+        while next < end && is_hole(next)? == hole {
+            next += block_size;
+        }
+        let len = usize::min(next, end) - off;
+        Ok(if hole {
+            ramdisk::Extent::Hole(len)
+        } else {
+            ramdisk::Extent::Data(len)
+        })
+    }
+}
+
+impl io::Read for File {
+    fn read(&self, offset: u64, dst: &mut [u8]) -> Result<usize> {
+        self.vol.read(&self.inode, offset, dst)
+    }
+
+    fn size(&self) -> usize {
+        self.inode.size()
+    }
+}
+
+impl ramdisk::FileSystem for FileSystem {
+    fn open(&self, path: &str) -> Result<Box<dyn ramdisk::File>> {
+        let inode = self.namei(path)?;
+        Ok(Box::new(File { vol: Rc::clone(&self.vol), inode }))
+    }
+
+    fn stat(&self, path: &str) -> Result<ramdisk::DirEntry> {
+        Ok(dirent(&self.namei(path)?, path))
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<ramdisk::DirEntry>> {
+        let dir = self.namei(path)?;
+        if dir.file_type() != FileType::Dir {
+            return Ok(vec![dirent(&dir, path)]);
+        }
+        let mut entries = Vec::new();
+        for entry in self.vol.readdir(&dir)? {
+            let inode = self.vol.inode(entry.ino)?;
+            entries.push(dirent(&inode, &entry.name));
+        }
+        Ok(entries)
+    }
+
+    /// Names the file system for the features it uses, as Linux
+    /// would mount it.
+    fn as_str(&self) -> &str {
+        let geometry = &self.vol.geometry;
+        if geometry.incompat & (INCOMPAT_EXTENTS | INCOMPAT_64BIT) != 0 {
+            "ext4"
+        } else if geometry.compat & COMPAT_HAS_JOURNAL != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ramdisk::{Extent, FileSystem as _};
+
+    const BLOCK: usize = 1024;
+    const NINODES: u32 = 32;
+    const NBLOCKS: usize = 32;
+
+    const INODE_TABLE: usize = 3;
+    const ROOT_DIR: usize = 7;
+    const ETC_DIR: usize = 8;
+    const MOTD: usize = 9;
+    /// The 12 direct blocks of /big, less a hole, start here, and
+    /// are followed by its indirect block, and the block it maps.
+    const BIG: usize = 10;
+    const BIG_INDIRECT: usize = 21;
+    const EXTENTS: usize = 23;
+    const LEAF: usize = 26;
+    const TREE: usize = 27;
+
+    const ETC_INO: u32 = 11;
+    const MOTD_INO: u32 = 12;
+    const BIG_INO: u32 = 13;
+    const EXTENTS_INO: u32 = 14;
+    const TREE_INO: u32 = 15;
+    const LINK_INO: u32 = 16;
+    const LOOP_INO: u32 = 17;
+
+    fn block(img: &mut [u8], block: usize) -> &mut [u8] {
+        &mut img[block * BLOCK..(block + 1) * BLOCK]
+    }
+
+    fn inode(img: &mut [u8], ino: u32, mode: u16, size: u64) -> &mut [u8] {
+        let offset = INODE_TABLE * BLOCK + (ino as usize - 1) * 128;
+        let raw = &mut img[offset..offset + 128];
+        raw[0..2].copy_from_slice(&mode.to_le_bytes());
+        raw[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        raw[26..28].copy_from_slice(&1u16.to_le_bytes());
+        raw[108..112].copy_from_slice(&((size >> 32) as u32).to_le_bytes());
+        raw
+    }
+
+    fn set_blocks(raw: &mut [u8], blocks: &[u32]) {
+        for (k, block) in blocks.iter().enumerate() {
+            let off = 40 + k * 4;
+            raw[off..off + 4].copy_from_slice(&block.to_le_bytes());
+        }
+        let sectors = blocks.iter().filter(|&&b| b != 0).count() * 2;
+        raw[28..32].copy_from_slice(&(sectors as u32).to_le_bytes());
+    }
+
+    fn dir(img: &mut [u8], blk: usize, entries: &[(u32, &str)]) {
+        let bs = block(img, blk);
+        let mut off = 0;
+        for (k, &(ino, name)) in entries.iter().enumerate() {
+            let rec_len = match k + 1 == entries.len() {
+                true => BLOCK - off,
+                false => (8 + name.len()).next_multiple_of(4),
+            };
+            bs[off..off + 4].copy_from_slice(&ino.to_le_bytes());
+            bs[off + 4..off + 6]
+                .copy_from_slice(&(rec_len as u16).to_le_bytes());
+            bs[off + 6] = name.len() as u8;
+            bs[off + 8..off + 8 + name.len()].copy_from_slice(name.as_bytes());
+            off += rec_len;
+        }
+    }
+
+    fn extent_header(bs: &mut [u8], nentries: u16, depth: u16) {
+        bs[0..2].copy_from_slice(&EXTENT_MAGIC.to_le_bytes());
+        bs[2..4].copy_from_slice(&nentries.to_le_bytes());
+        bs[4..6].copy_from_slice(&4u16.to_le_bytes());
+        bs[6..8].copy_from_slice(&depth.to_le_bytes());
+    }
+
+    fn extent(bs: &mut [u8], k: usize, first: u32, len: u16, start: u32) {
+        let off = EXTENT_ENTRY_LEN * (k + 1);
+        bs[off..off + 4].copy_from_slice(&first.to_le_bytes());
+        bs[off + 4..off + 6].copy_from_slice(&len.to_le_bytes());
+        bs[off + 8..off + 12].copy_from_slice(&start.to_le_bytes());
+    }
+
+    /// Builds a small ext4 image with 1KiB blocks and a single
+    /// group.  /big is block mapped, with holes in its direct
+    /// and indirect blocks; /extents and /tree use extents.
+    fn image() -> Vec<u8> {
+        let mut img = vec![0u8; NBLOCKS * BLOCK];
+        let sb = &mut img[SUPERBLOCK_OFFSET..SUPERBLOCK_OFFSET + 1024];
+        sb[0..4].copy_from_slice(&NINODES.to_le_bytes());
+        sb[4..8].copy_from_slice(&(NBLOCKS as u32).to_le_bytes());
+        sb[20..24].copy_from_slice(&1u32.to_le_bytes());
+        sb[32..36].copy_from_slice(&8192u32.to_le_bytes());
+        sb[40..44].copy_from_slice(&NINODES.to_le_bytes());
+        sb[56..58].copy_from_slice(&MAGIC.to_le_bytes());
+        sb[76..80].copy_from_slice(&1u32.to_le_bytes());
+        sb[88..90].copy_from_slice(&128u16.to_le_bytes());
+        let incompat = INCOMPAT_FILETYPE | INCOMPAT_EXTENTS;
+        sb[96..100].copy_from_slice(&incompat.to_le_bytes());
+        let gd = block(&mut img, 2);
+        gd[8..12].copy_from_slice(&(INODE_TABLE as u32).to_le_bytes());
+
+        let root = inode(&mut img, ROOT_INO, S_IFDIR | 0o755, BLOCK as u64);
+        set_blocks(root, &[ROOT_DIR as u32]);
+        dir(
+            &mut img,
+            ROOT_DIR,
+            &[
+                (ROOT_INO, "."),
+                (ROOT_INO, ".."),
+                (ETC_INO, "etc"),
+                (0, "deleted"),
+                (BIG_INO, "big"),
+                (EXTENTS_INO, "extents"),
+                (TREE_INO, "tree"),
+                (LINK_INO, "motd"),
+                (LOOP_INO, "loop"),
+            ],
+        );
+        let etc = inode(&mut img, ETC_INO, S_IFDIR | 0o755, BLOCK as u64);
+        set_blocks(etc, &[ETC_DIR as u32]);
+        dir(
+            &mut img,
+            ETC_DIR,
+            &[(ETC_INO, "."), (ROOT_INO, ".."), (MOTD_INO, "motd")],
+        );
+        let motd = inode(&mut img, MOTD_INO, S_IFREG | 0o644, 5);
+        set_blocks(motd, &[MOTD as u32]);
+        motd[2..4].copy_from_slice(&0x1234u16.to_le_bytes());
+        motd[120..122].copy_from_slice(&1u16.to_le_bytes());
+        block(&mut img, MOTD)[..5].copy_from_slice(b"hello");
+
+        // Logical block 3 of /big is a hole, as is 13, mapped by
+        // the indirect block.
+        let mut map = [0u32; 13];
+        let mut next = BIG;
+        for (k, entry) in map.iter_mut().take(12).enumerate() {
+            if k != 3 {
+                *entry = next as u32;
+                block(&mut img, next).fill(b'a' + k as u8);
+                next += 1;
+            }
+        }
+        map[12] = BIG_INDIRECT as u32;
+        let big = inode(&mut img, BIG_INO, S_IFREG | 0o644, 14 * BLOCK as u64);
+        set_blocks(big, &map);
+        // Count the block mapped by the indirect block, too.
+        big[28..32].copy_from_slice(&26u32.to_le_bytes());
+        let indirect = block(&mut img, BIG_INDIRECT);
+        indirect[..4].copy_from_slice(&(BIG_INDIRECT as u32 + 1).to_le_bytes());
+        block(&mut img, BIG_INDIRECT + 1).fill(b'm');
+
+        // Blocks 0 and 1 of /extents are mapped, 2 is a hole, and
+        // 3 is allocated but uninitialized.
+        let raw =
+            inode(&mut img, EXTENTS_INO, S_IFREG | 0o644, 4 * BLOCK as u64);
+        raw[32..36].copy_from_slice(&EXTENTS_FL.to_le_bytes());
+        let iblock = &mut raw[40..40 + IBLOCK_LEN];
+        extent_header(iblock, 2, 0);
+        extent(iblock, 0, 0, 2, EXTENTS as u32);
+        extent(iblock, 1, 3, EXTENT_INIT_MAX + 1, EXTENTS as u32 + 2);
+        block(&mut img, EXTENTS).fill(b'x');
+        block(&mut img, EXTENTS + 1).fill(b'y');
+        block(&mut img, EXTENTS + 2).fill(b'z');
+
+        // /tree has a two-level extent tree.
+        let raw = inode(&mut img, TREE_INO, S_IFREG | 0o644, 3);
+        raw[32..36].copy_from_slice(&EXTENTS_FL.to_le_bytes());
+        let iblock = &mut raw[40..40 + IBLOCK_LEN];
+        extent_header(iblock, 1, 1);
+        iblock[16..20].copy_from_slice(&(LEAF as u32).to_le_bytes());
+        let leaf = block(&mut img, LEAF);
+        extent_header(leaf, 1, 0);
+        extent(leaf, 0, 0, 1, TREE as u32);
+        block(&mut img, TREE)[..3].copy_from_slice(b"abc");
+
+        let target = b"etc/motd";
+        let raw =
+            inode(&mut img, LINK_INO, S_IFLNK | 0o777, target.len() as u64);
+        raw[40..40 + target.len()].copy_from_slice(target);
+        let target = b"/loop";
+        let raw =
+            inode(&mut img, LOOP_INO, S_IFLNK | 0o777, target.len() as u64);
+        raw[40..40 + target.len()].copy_from_slice(target);
+        img
+    }
+
+    #[test]
+    fn directories() {
+        let img = image();
+        let fs = FileSystem::try_new(&img).unwrap();
+        assert_eq!(fs.as_str(), "ext4");
+        assert!(!fs.needs_recovery());
+        let root = fs.readdir("/").unwrap();
+        let names = root.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [".", "..", "etc", "big", "extents", "tree", "motd", "loop"]
+        );
+        assert_eq!(root[6].file_type, FileType::SymLink);
+        assert_eq!(root[6].size, 8);
+        assert_eq!(root[2].file_type, FileType::Dir);
+        assert_eq!(root[2].perms, 0o755);
+        let motd = fs.stat("/etc/motd").unwrap();
+        assert_eq!(motd.ino, u64::from(MOTD_INO));
+        assert_eq!(motd.size, 5);
+        assert_eq!(motd.allocated, BLOCK);
+        assert_eq!(motd.uid, 0x1_1234);
+        let big = fs.stat("/big").unwrap();
+        assert_eq!(big.size, 14 * BLOCK);
+        assert_eq!(big.allocated, 13 * BLOCK);
+        // Symbolic links are followed.
+        assert_eq!(fs.stat("/motd").unwrap().ino, u64::from(MOTD_INO));
+        assert_eq!(fs.stat("/etc/../motd").unwrap().ino, u64::from(MOTD_INO));
+        assert_eq!(fs.readdir("/etc/motd").unwrap().len(), 1);
+        assert_eq!(fs.stat("/nope").unwrap_err(), Error::FsNoFile);
+        assert_eq!(fs.stat("/deleted").unwrap_err(), Error::FsNoFile);
+        assert_eq!(fs.stat("/big/x").unwrap_err(), Error::FsInvPath);
+        assert_eq!(fs.stat("/loop").unwrap_err(), Error::FsInvPath);
+    }
+
+    #[test]
+    fn files() {
+        let img = image();
+        let fs = FileSystem::try_new(&img).unwrap();
+        let mut buf = vec![0u8; 16 * BLOCK];
+        let motd = fs.open("/motd").unwrap();
+        assert_eq!(motd.read(0, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+
+        let big = fs.open("/big").unwrap();
+        assert_eq!(big.read(0, &mut buf), Ok(14 * BLOCK));
+        for (k, block) in buf[..14 * BLOCK].chunks(BLOCK).enumerate() {
+            let expected = match k {
+                3 | 13 => 0,
+                12 => b'm',
+                k => b'a' + k as u8,
+            };
+            assert!(block.iter().all(|&b| b == expected), "block {k}");
+        }
+        assert_eq!(big.read((3 * BLOCK - 1) as u64, &mut buf[..2]), Ok(2));
+        assert_eq!(&buf[..2], b"c\0");
+        assert_eq!(big.extent(0, usize::MAX), Ok(Extent::Data(3 * BLOCK)));
+        assert_eq!(
+            big.extent((3 * BLOCK + 1) as u64, 4096),
+            Ok(Extent::Hole(BLOCK - 1))
+        );
+        assert_eq!(
+            big.extent((13 * BLOCK) as u64, 4096),
+            Ok(Extent::Hole(BLOCK))
+        );
+
+        let extents = fs.open("/extents").unwrap();
+        assert_eq!(extents.read(0, &mut buf), Ok(4 * BLOCK));
+        assert!(buf[..BLOCK].iter().all(|&b| b == b'x'));
+        assert!(buf[BLOCK..2 * BLOCK].iter().all(|&b| b == b'y'));
+        assert!(buf[2 * BLOCK..4 * BLOCK].iter().all(|&b| b == 0));
+        assert_eq!(
+            extents.extent(BLOCK as u64, usize::MAX),
+            Ok(Extent::Data(BLOCK))
+        );
+
+        let tree = fs.open("/tree").unwrap();
+        assert_eq!(tree.read(1, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"bc");
+        assert_eq!(tree.read(3, &mut buf), Ok(0));
+    }
+
+    #[test]
+    fn block_maps() {
+        let img = image();
+        let fs = FileSystem::try_new(&img).unwrap();
+        let vol = &fs.vol;
+        // A triple indirect block far beyond the end of the image
+        // is reported, rather than dereferenced.
+        let mut iblock = [0u8; IBLOCK_LEN];
+        iblock[56..60].copy_from_slice(&0x10_0000u32.to_le_bytes());
+        let per = (BLOCK / 4) as u64;
+        let triple = NDIRECT + per + per * per;
+        assert_eq!(vol.block_map(&iblock, triple - 1), Ok(None));
+        assert_eq!(vol.block_map(&iblock, triple), Err(Error::FsRead));
+        let end = triple + per * per * per;
+        assert_eq!(vol.block_map(&iblock, end), Err(Error::FsOffset));
+    }
+
+    #[test]
+    fn detection() {
+        let img = image();
+        assert!(FileSystem::try_new(&img[..2047]).is_err());
+        let mut bad = img.clone();
+        bad[SUPERBLOCK_OFFSET + 56] = 0;
+        assert_eq!(FileSystem::try_new(&bad).err(), Some(Error::FsInvMagic));
+        let mut bad = img.clone();
+        // Inline data.
+        bad[SUPERBLOCK_OFFSET + 97] |= 0x80;
+        assert_eq!(FileSystem::try_new(&bad).err(), Some(Error::FsUnsupported));
+        let mut recover = img;
+        recover[SUPERBLOCK_OFFSET + 96] |= INCOMPAT_RECOVER as u8;
+        recover[SUPERBLOCK_OFFSET + 96] &= !(INCOMPAT_EXTENTS as u8);
+        let fs = FileSystem::try_new(&recover).unwrap();
+        assert!(fs.needs_recovery());
+        assert_eq!(fs.as_str(), "ext2");
+    }
+}
@@ -5,12 +5,17 @@
 //! Stub out things that are not ordinarily available in tests.
 //! For instance, linker-provided symbols.
 
+/// A linker symbol that must be page aligned, as the bounds of
+/// the loader's text are.
+#[repr(C, align(4096))]
+struct Page(usize);
+
 /// Linker symbol.
 #[unsafe(no_mangle)]
-static __sloader: usize = 4096;
+static __sloader: Page = Page(4096);
 /// Linker symbol.
 #[unsafe(no_mangle)]
-static etext: usize = 8192;
+static etext: Page = Page(8192);
 /// Linker symbol.
 #[unsafe(no_mangle)]
 static erodata: usize = 16384;
@@ -46,3 +51,196 @@ static GDT_CODE64: usize = 0x28;
 pub unsafe extern "C" fn dnr() {
     loop {}
 }
+
+// A fake clock and console.
+//
+// Code that talks to the console UART or reads the time stamp
+// counter is exercised in tests against these fakes, which are
+// scripted per test thread.  The clock advances a little every
+// time it is read, so that loops waiting on it make progress,
+// and jumps ahead while the console is idle, so that timeouts
+// expire quickly.
+
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use core::time::Duration;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+/// The frequency of the fake time stamp counter, which thus
+/// counts nanoseconds.
+pub(crate) const CLOCK_HZ: u128 = 1_000_000_000;
+
+/// How far the fake clock advances each time it is read.
+const CLOCK_TICK: u64 = 1_000;
+
+/// How far the fake clock advances each time the console is
+/// polled while idle.
+const IDLE_TICK: u64 = 1_000_000;
+
+/// How many times the console may be polled after its script
+/// is exhausted before we decide the code under test is waiting
+/// for input that will never come.
+const MAX_IDLE_POLLS: usize = 1_000_000;
+
+/// Something that happens on the receive side of the fake
+/// console.
+#[derive(Clone, Debug)]
+pub(crate) enum Rx {
+    /// The given bytes arrive, all at once.
+    Bytes(Vec<u8>),
+    /// Nothing arrives for the given time.
+    Idle(Duration),
+    /// The line status register reports the given error, which
+    /// should be one of the `Uart*` errors.
+    Error(Error),
+}
+
+impl Rx {
+    pub(crate) fn bytes(bs: &[u8]) -> Rx {
+        Rx::Bytes(bs.to_vec())
+    }
+}
+
+#[derive(Default)]
+struct Console {
+    script: VecDeque<Rx>,
+    idle_until: Option<u64>,
+    idle_polls: usize,
+    tx: Vec<u8>,
+}
+
+impl Console {
+    fn status(&mut self) -> Result<bool> {
+        loop {
+            match self.script.front() {
+                None => {
+                    self.idle_polls += 1;
+                    assert!(
+                        self.idle_polls < MAX_IDLE_POLLS,
+                        "fake console script exhausted"
+                    );
+                    advance(IDLE_TICK);
+                    return Ok(false);
+                }
+                Some(Rx::Bytes(bs)) if bs.is_empty() => {}
+                Some(Rx::Bytes(_)) => return Ok(true),
+                Some(&Rx::Error(e)) => {
+                    self.script.pop_front();
+                    return Err(e);
+                }
+                Some(Rx::Idle(d)) => {
+                    let d = d.as_nanos() as u64;
+                    let until = *self.idle_until.get_or_insert(now() + d);
+                    let now = now();
+                    if now < until {
+                        advance(u64::min(IDLE_TICK, until - now));
+                        return Ok(false);
+                    }
+                    self.idle_until = None;
+                }
+            }
+            self.script.pop_front();
+        }
+    }
+
+    fn recv(&mut self) -> u8 {
+        let Some(Rx::Bytes(bs)) = self.script.front_mut() else {
+            panic!("read from the fake console with no data ready");
+        };
+        let b = bs.remove(0);
+        if bs.is_empty() {
+            self.script.pop_front();
+        }
+        b
+    }
+}
+
+thread_local! {
+    static NOW: Cell<u64> = const { Cell::new(0) };
+    static CONSOLE: RefCell<Console> = RefCell::new(Console::default());
+}
+
+/// Returns the fake time stamp counter, advancing it.
+pub fn rdtsc() -> u64 {
+    let now = now();
+    advance(CLOCK_TICK);
+    now
+}
+
+fn now() -> u64 {
+    NOW.with(|now| now.get())
+}
+
+/// Advances the fake clock by the given number of nanoseconds.
+pub(crate) fn advance(ns: u64) {
+    NOW.with(|now| now.set(now.get() + ns));
+}
+
+/// Scripts the fake console for the current thread, discarding
+/// anything transmitted so far, and returns the console UART.
+pub(crate) fn console(script: impl IntoIterator<Item = Rx>) -> Uart {
+    CONSOLE.with(|cons| {
+        *cons.borrow_mut() = Console {
+            script: script.into_iter().collect(),
+            ..Default::default()
+        }
+    });
+    crate::uart::cons()
+}
+
+/// Returns everything transmitted on the fake console since it
+/// was last scripted or examined.
+pub(crate) fn transmitted() -> Vec<u8> {
+    CONSOLE.with(|cons| core::mem::take(&mut cons.borrow_mut().tx))
+}
+
+/// Returns true iff the script has been consumed entirely.
+pub(crate) fn exhausted() -> bool {
+    CONSOLE.with(|cons| cons.borrow().script.is_empty())
+}
+
+/// Polls the fake line status, returning whether a byte is
+/// ready or an error, as the UART's line status register would.
+pub(crate) fn uart_status() -> Result<bool> {
+    CONSOLE.with(|cons| cons.borrow_mut().status())
+}
+
+/// Receives a byte from the fake console.
+pub(crate) fn uart_recv() -> u8 {
+    CONSOLE.with(|cons| cons.borrow_mut().recv())
+}
+
+/// Transmits a byte on the fake console.
+pub(crate) fn uart_send(b: u8) {
+    CONSOLE.with(|cons| cons.borrow_mut().tx.push(b));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let mut uart = console([
+            Rx::bytes(b"ab"),
+            Rx::Error(Error::UartFraming),
+            Rx::Idle(Duration::from_millis(50)),
+            Rx::bytes(b"c"),
+        ]);
+        assert_eq!(uart.try_getb(), Ok(b'a'));
+        assert_eq!(uart.try_getb(), Ok(b'b'));
+        assert_eq!(uart.try_getb(), Err(Error::UartFraming));
+        let t = now();
+        assert_eq!(
+            uart.try_getb_timeout(Duration::from_millis(10)),
+            Err(Error::Timeout)
+        );
+        assert!(now() - t >= 10_000_000);
+        assert_eq!(uart.try_getb(), Ok(b'c'));
+        assert!(now() - t >= 50_000_000);
+        assert!(exhausted());
+        uart.puts("x\n");
+        assert_eq!(transmitted(), b"x\r\n");
+    }
+}
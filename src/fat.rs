@@ -0,0 +1,621 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! FAT32 file system support.
+//!
+//! Some recovery images are built as EFI system partition
+//! images, formatted with FAT32.  This is a read-only driver for
+//! such images, with support for long file names.  As on FAT
+//! itself, names are matched without regard to ASCII case.
+//!
+//! As in other systems, a volume is taken to be FAT32 if its
+//! BIOS parameter block has the FAT32 layout, regardless of its
+//! number of clusters, so that small test images mount, too.
+
+use crate::io;
+use crate::ramdisk::{self, FileType};
+use crate::result::{Error, Result};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+/// The signature at the end of the boot sector.
+const SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// The attributes that mark a long file name entry.
+const ATTR_LFN: u8 = 0x0f;
+
+/// The size of a directory entry.
+const ENTRY_SIZE: usize = 32;
+/// The number of UTF-16 code units of a name in each long file
+/// name entry.
+const LFN_UNITS: usize = 13;
+/// The offsets of those code units within the entry.
+const LFN_OFFSETS: [usize; LFN_UNITS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Marks the last logical, and first physical, long file name
+/// entry of a name.
+const LFN_LAST: u8 = 0x40;
+/// The first byte of a free directory entry.
+const FREE: u8 = 0xe5;
+/// The first byte of the directory entry ending a directory.
+const END: u8 = 0x00;
+
+/// FAT32 entries are 28 bits wide.
+const FAT_MASK: u32 = 0x0fff_ffff;
+/// Entries at or above this end a chain.
+const EOC: u32 = 0x0fff_fff8;
+
+fn le16(bs: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([bs[off], bs[off + 1]])
+}
+
+fn le32(bs: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([bs[off], bs[off + 1], bs[off + 2], bs[off + 3]])
+}
+
+/// The layout of the volume, from the BIOS parameter block.
+#[derive(Clone, Copy, Debug)]
+struct Geometry {
+    cluster_size: usize,
+    /// The byte offset of the first FAT.
+    fat_offset: usize,
+    /// The byte offset of cluster 2, the first data cluster.
+    data_offset: usize,
+    /// The number of data clusters.
+    nclusters: u32,
+    root_cluster: u32,
+}
+
+impl Geometry {
+    fn parse(bs: &[u8]) -> Result<Geometry> {
+        if bs.len() < 512 || bs[510..512] != SIGNATURE {
+            return Err(Error::FsInvMagic);
+        }
+        let sector_size = usize::from(le16(bs, 11));
+        let sectors_per_cluster = usize::from(bs[13]);
+        let reserved = usize::from(le16(bs, 14));
+        let nfats = usize::from(bs[16]);
+        let root_entries = le16(bs, 17);
+        let total16 = usize::from(le16(bs, 19));
+        let fat_size16 = le16(bs, 22);
+        let total32 = le32(bs, 32) as usize;
+        let fat_size = le32(bs, 36) as usize;
+        let root_cluster = le32(bs, 44);
+        let fat32 = matches!(sector_size, 512 | 1024 | 2048 | 4096)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved != 0
+            && nfats != 0
+            && root_entries == 0
+            && fat_size16 == 0
+            && fat_size != 0;
+        if !fat32 {
+            return Err(Error::FsInvMagic);
+        }
+        let total = if total16 != 0 { total16 } else { total32 };
+        let len = total.saturating_mul(sector_size).min(bs.len());
+        let fat_offset = reserved * sector_size;
+        let fat_len = fat_size * sector_size;
+        let data_offset = fat_offset + nfats * fat_len;
+        let cluster_size = sectors_per_cluster * sector_size;
+        if data_offset >= len || fat_offset + fat_len > len {
+            return Err(Error::FsInvMagic);
+        }
+        let nclusters = ((len - data_offset) / cluster_size)
+            .min(fat_len / 4 - 2)
+            .try_into()
+            .map_err(|_| Error::FsInvMagic)?;
+        let geometry = Geometry {
+            cluster_size,
+            fat_offset,
+            data_offset,
+            nclusters,
+            root_cluster,
+        };
+        if !geometry.is_data(root_cluster) {
+            return Err(Error::FsInvMagic);
+        }
+        Ok(geometry)
+    }
+
+    /// Returns true iff the cluster number names a data cluster.
+    fn is_data(&self, cluster: u32) -> bool {
+        (2..self.nclusters + 2).contains(&cluster)
+    }
+}
+
+/// A FAT32 volume in memory.
+struct Volume {
+    sd: io::Sd,
+    geometry: Geometry,
+}
+
+impl Volume {
+    fn bytes(&self) -> &[u8] {
+        unsafe { self.sd.as_slice() }
+    }
+
+    /// Returns the contents of a data cluster.
+    fn cluster(&self, cluster: u32) -> &[u8] {
+        let size = self.geometry.cluster_size;
+        let offset = self.geometry.data_offset + (cluster as usize - 2) * size;
+        &self.bytes()[offset..offset + size]
+    }
+
+    /// Returns the FAT entry for a data cluster.
+    fn fat(&self, cluster: u32) -> u32 {
+        let offset = self.geometry.fat_offset + cluster as usize * 4;
+        le32(self.bytes(), offset) & FAT_MASK
+    }
+
+    /// Returns the chain of clusters starting with `first`.  A
+    /// first cluster of 0 is the empty chain.
+    fn chain(&self, first: u32) -> Chain<'_> {
+        Chain { vol: self, next: (first != 0).then_some(first), count: 0 }
+    }
+
+    /// Reads a directory's entries.
+    fn readdir(&self, first: u32) -> Result<Vec<Entry>> {
+        let first = if first == 0 { self.geometry.root_cluster } else { first };
+        let mut bytes = Vec::new();
+        for cluster in self.chain(first) {
+            bytes.extend_from_slice(self.cluster(cluster?));
+        }
+        Ok(parse_dir(&bytes))
+    }
+}
+
+/// An iterator over a chain of clusters.  Should the chain be
+/// broken, or loop, it ends with an error.
+struct Chain<'a> {
+    vol: &'a Volume,
+    next: Option<u32>,
+    count: u32,
+}
+
+impl Iterator for Chain<'_> {
+    type Item = Result<u32>;
+
+    fn next(&mut self) -> Option<Result<u32>> {
+        let cluster = self.next.take()?;
+        let geometry = &self.vol.geometry;
+        if !geometry.is_data(cluster) || self.count == geometry.nclusters {
+            return Some(Err(Error::FsRead));
+        }
+        self.count += 1;
+        let next = self.vol.fat(cluster);
+        self.next = (next < EOC).then_some(next);
+        Some(Ok(cluster))
+    }
+}
+
+/// A directory entry, with its long name, if it has one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    name: String,
+    attr: u8,
+    cluster: u32,
+    size: u32,
+}
+
+impl Entry {
+    fn root(geometry: &Geometry) -> Entry {
+        Entry {
+            name: String::from("/"),
+            attr: ATTR_DIRECTORY,
+            cluster: geometry.root_cluster,
+            size: 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+}
+
+/// Returns the checksum of a short name that long file name
+/// entries carry, to tie them to it.
+fn checksum(short: &[u8]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Formats a short, 8.3 name.  Windows records whether the base
+/// name and extension are in lower case in otherwise reserved
+/// bits, and we honor them.
+fn short_name(short: &[u8], case: u8) -> String {
+    const LOWER_BASE: u8 = 0x08;
+    const LOWER_EXT: u8 = 0x10;
+    let part = |bs: &[u8], lower: bool| {
+        let bs = bs.trim_ascii_end();
+        bs.iter()
+            .enumerate()
+            .map(|(k, &b)| {
+                // An initial 0x05 stands for 0xe5, which marks
+                // free entries.
+                let b = if k == 0 && b == 0x05 { FREE } else { b };
+                if lower { b.to_ascii_lowercase() } else { b }
+            })
+            .map(char::from)
+            .collect::<String>()
+    };
+    let mut name = part(&short[..8], case & LOWER_BASE != 0);
+    let ext = part(&short[8..11], case & LOWER_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// Parses the entries of a directory, assembling long names from
+/// the entries that precede their short ones.  Long names whose
+/// checksums do not match the short name that follows are
+/// ignored, as they were left behind by systems unaware of them.
+fn parse_dir(bytes: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut long = Vec::<u16>::new();
+    let mut long_sum = None;
+    for rec in bytes.chunks_exact(ENTRY_SIZE) {
+        match rec[0] {
+            END => break,
+            FREE => {
+                long_sum = None;
+                continue;
+            }
+            _ => {}
+        }
+        let attr = rec[11];
+        if attr & 0x3f == ATTR_LFN {
+            let sum = rec[13];
+            if rec[0] & LFN_LAST != 0 {
+                long.clear();
+                long_sum = Some(sum);
+            } else if long_sum != Some(sum) {
+                long_sum = None;
+            }
+            let units = LFN_OFFSETS.map(|off| le16(rec, off));
+            long.splice(0..0, units);
+            continue;
+        }
+        if attr & ATTR_VOLUME_ID != 0 {
+            long_sum = None;
+            continue;
+        }
+        let short = &rec[..11];
+        let name = match long_sum.take() {
+            Some(sum) if sum == checksum(short) => {
+                let units = long.iter().copied().take_while(|&u| u != 0);
+                char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            _ => short_name(short, rec[12]),
+        };
+        let cluster = u32::from(le16(rec, 20)) << 16 | u32::from(le16(rec, 26));
+        entries.push(Entry { name, attr, cluster, size: le32(rec, 28) });
+    }
+    entries
+}
+
+pub(crate) struct FileSystem {
+    vol: Rc<Volume>,
+}
+
+impl FileSystem {
+    pub(crate) fn try_new(bs: &[u8]) -> Result<FileSystem> {
+        let geometry = Geometry::parse(bs)?;
+        let sd = unsafe { io::Sd::from_slice(bs) };
+        Ok(FileSystem { vol: Rc::new(Volume { sd, geometry }) })
+    }
+
+    /// Looks up the entry for the given path.
+    fn lookup(&self, path: &str) -> Result<Entry> {
+        let mut entry = Entry::root(&self.vol.geometry);
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !entry.is_dir() {
+                return Err(Error::FsInvPath);
+            }
+            entry = self
+                .vol
+                .readdir(entry.cluster)?
+                .into_iter()
+                .find(|entry| entry.name.eq_ignore_ascii_case(name))
+                .ok_or(Error::FsNoFile)?;
+        }
+        Ok(entry)
+    }
+
+    /// Returns the file system independent metadata for an
+    /// entry.  FAT has no owners or permissions, so we show
+    /// them as root's, read-only if so marked.
+    fn dirent(&self, entry: &Entry, name: &str) -> ramdisk::DirEntry {
+        let clusters = self.vol.chain(entry.cluster).count();
+        let allocated = clusters * self.vol.geometry.cluster_size;
+        let (file_type, perms, size) = if entry.is_dir() {
+            (FileType::Dir, 0o755, allocated)
+        } else {
+            (FileType::Regular, 0o644, entry.size as usize)
+        };
+        let perms = if entry.attr & ATTR_READ_ONLY != 0 {
+            perms & !0o222
+        } else {
+            perms
+        };
+        ramdisk::DirEntry {
+            name: String::from(name),
+            ino: entry.cluster.into(),
+            file_type,
+            perms,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size,
+            allocated,
+        }
+    }
+}
+
+pub(crate) struct File {
+    vol: Rc<Volume>,
+    entry: Entry,
+    /// The index in the file's chain of the cluster last read,
+    /// and that cluster, so that reading a file in pieces need
+    /// not walk its chain from the start for each.
+    cursor: Cell<Option<(usize, u32)>>,
+}
+
+impl ramdisk::File for File {
+    fn file_type(&self) -> FileType {
+        if self.entry.is_dir() { FileType::Dir } else { FileType::Regular }
+    }
+}
+
+impl io::Read for File {
+    fn read(&self, offset: u64, dst: &mut [u8]) -> Result<usize> {
+        let offset = offset as usize;
+        let size = self.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = usize::min(dst.len(), size - offset);
+        let cluster_size = self.vol.geometry.cluster_size;
+        let mut nread = 0;
+        let index = offset / cluster_size;
+        let (base, first) = match self.cursor.get() {
+            Some((k, cluster)) if k <= index => (k, cluster),
+            _ => (0, self.entry.cluster),
+        };
+        let chain = self.vol.chain(first).enumerate().skip(index - base);
+        for (k, cluster) in chain {
+            if nread == len {
+                break;
+            }
+            let cluster = cluster?;
+            self.cursor.set(Some((base + k, cluster)));
+            let data = self.vol.cluster(cluster);
+            let start = (offset + nread) % cluster_size;
+            let count = usize::min(cluster_size - start, len - nread);
+            dst[nread..nread + count]
+                .copy_from_slice(&data[start..start + count]);
+            nread += count;
+        }
+        if nread < len {
+            return Err(Error::FsRead);
+        }
+        Ok(len)
+    }
+
+    /// Directories record no size, and so read as empty.
+    fn size(&self) -> usize {
+        if self.entry.is_dir() { 0 } else { self.entry.size as usize }
+    }
+}
+
+impl ramdisk::FileSystem for FileSystem {
+    fn open(&self, path: &str) -> Result<Box<dyn ramdisk::File>> {
+        let entry = self.lookup(path)?;
+        Ok(Box::new(File {
+            vol: Rc::clone(&self.vol),
+            entry,
+            cursor: Cell::new(None),
+        }))
+    }
+
+    fn stat(&self, path: &str) -> Result<ramdisk::DirEntry> {
+        Ok(self.dirent(&self.lookup(path)?, path))
+    }
+
+    fn readdir(&self, path: &str) -> Result<Vec<ramdisk::DirEntry>> {
+        let entry = self.lookup(path)?;
+        if !entry.is_dir() {
+            return Ok(vec![self.dirent(&entry, path)]);
+        }
+        let entries = self.vol.readdir(entry.cluster)?;
+        Ok(entries
+            .iter()
+            .map(|entry| self.dirent(entry, &entry.name))
+            .collect())
+    }
+
+    fn as_str(&self) -> &str {
+        "FAT32"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Read as _;
+    use crate::ramdisk::FileSystem as _;
+
+    const SECTOR: usize = 512;
+    const RESERVED: usize = 4;
+    const DATA: usize = RESERVED + 1;
+    const LONG_NAME: &str = "Long File Name.txt";
+
+    fn cluster(img: &mut [u8], cluster: usize) -> &mut [u8] {
+        let offset = (DATA + cluster - 2) * SECTOR;
+        &mut img[offset..offset + SECTOR]
+    }
+
+    fn short(rec: &mut [u8], name: &[u8; 11], attr: u8, first: u32, size: u32) {
+        rec[..11].copy_from_slice(name);
+        rec[11] = attr;
+        rec[20..22].copy_from_slice(&((first >> 16) as u16).to_le_bytes());
+        rec[26..28].copy_from_slice(&(first as u16).to_le_bytes());
+        rec[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    fn long(rec: &mut [u8], ord: u8, sum: u8, units: &[u16]) {
+        rec[0] = ord;
+        rec[11] = ATTR_LFN;
+        rec[13] = sum;
+        let padded = units.iter().copied().chain([0]).chain([0xffff; 13]);
+        for (off, unit) in LFN_OFFSETS.into_iter().zip(padded) {
+            rec[off..off + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    /// Builds a small FAT32 image, with 512-byte sectors and
+    /// clusters, a single FAT, and 12 data clusters.
+    fn image() -> Vec<u8> {
+        let mut img = vec![0u8; (DATA + 12) * SECTOR];
+        let bpb = &mut img[..SECTOR];
+        bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bpb[13] = 1;
+        bpb[14..16].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+        bpb[16] = 1;
+        bpb[32..36].copy_from_slice(&(DATA as u32 + 12).to_le_bytes());
+        bpb[36..40].copy_from_slice(&1u32.to_le_bytes());
+        bpb[44..48].copy_from_slice(&2u32.to_le_bytes());
+        bpb[82..90].copy_from_slice(b"FAT32   ");
+        bpb[510..512].copy_from_slice(&SIGNATURE);
+        // The root directory is cluster 2, /EFI is 3, the long
+        // named file is 4, and /EFI/BOOTX64.EFI is 5 and then 7.
+        let fat = [
+            0x0fff_fff8,
+            FAT_MASK,
+            FAT_MASK,
+            FAT_MASK,
+            FAT_MASK,
+            7,
+            0,
+            FAT_MASK,
+        ];
+        for (k, entry) in fat.into_iter().enumerate() {
+            let offset = RESERVED * SECTOR + k * 4;
+            img[offset..offset + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+        let root = cluster(&mut img, 2);
+        short(&mut root[0..32], b"RECOVERY   ", ATTR_VOLUME_ID, 0, 0);
+        let sum = checksum(b"LONGFI~1TXT");
+        let units = LONG_NAME.encode_utf16().collect::<Vec<_>>();
+        long(&mut root[32..64], LFN_LAST | 2, sum, &units[13..]);
+        long(&mut root[64..96], 1, sum, &units[..13]);
+        short(&mut root[96..128], b"LONGFI~1TXT", 0x20, 4, 5);
+        short(&mut root[128..160], b"GONE    TXT", 0x20, 0, 0);
+        root[128] = FREE;
+        short(&mut root[160..192], b"README  TXT", 0x21, 0, 0);
+        root[160 + 12] = 0x08;
+        short(&mut root[192..224], b"EFI        ", ATTR_DIRECTORY, 3, 0);
+        // A long name left behind, its short name since renamed.
+        long(&mut root[224..256], LFN_LAST | 1, sum ^ 1, &[u16::from(b'x')]);
+        short(&mut root[256..288], b"STALE      ", 0x20, 0, 0);
+        let efi = cluster(&mut img, 3);
+        short(&mut efi[0..32], b".          ", ATTR_DIRECTORY, 3, 0);
+        short(&mut efi[32..64], b"..         ", ATTR_DIRECTORY, 0, 0);
+        short(&mut efi[64..96], b"BOOTX64 EFI", 0x20, 5, 600);
+        cluster(&mut img, 4)[..5].copy_from_slice(b"hello");
+        cluster(&mut img, 5).fill(b'a');
+        cluster(&mut img, 7).fill(b'b');
+        img
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(short_name(b"README  TXT", 0), "README.TXT");
+        assert_eq!(short_name(b"README  TXT", 0x08), "readme.TXT");
+        assert_eq!(short_name(b"MAKEFILE   ", 0x18), "makefile");
+        assert_eq!(short_name(b"\x05BC     TXT", 0), "\u{e5}BC.TXT");
+        assert_eq!(checksum(b"LONGFI~1TXT"), 0xd4);
+    }
+
+    #[test]
+    fn directories() {
+        let img = image();
+        let fs = FileSystem::try_new(&img).unwrap();
+        let names = fs
+            .readdir("/")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, [LONG_NAME, "readme.TXT", "EFI", "STALE"]);
+        let efi = fs.readdir("/efi").unwrap();
+        assert_eq!(efi.len(), 3);
+        assert_eq!(efi[2].name, "BOOTX64.EFI");
+        assert_eq!(efi[2].size, 600);
+        assert_eq!(efi[2].allocated, 1024);
+        let dir = fs.stat("/EFI/..").unwrap();
+        assert_eq!(dir.file_type, FileType::Dir);
+        assert_eq!(fs.stat("/readme.txt").unwrap().perms, 0o444);
+        assert_eq!(fs.stat("/nope").unwrap_err(), Error::FsNoFile);
+        assert_eq!(fs.stat("/readme.txt/x").unwrap_err(), Error::FsInvPath);
+    }
+
+    #[test]
+    fn files() {
+        let img = image();
+        let fs = FileSystem::try_new(&img).unwrap();
+        let file = fs.open("/long file name.TXT").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(file.read(0, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        let file = fs.open("/efi/bootx64.efi").unwrap();
+        let mut buf = vec![0u8; 1024];
+        assert_eq!(file.read(0, &mut buf), Ok(600));
+        assert!(buf[..512].iter().all(|&b| b == b'a'));
+        assert!(buf[512..600].iter().all(|&b| b == b'b'));
+        assert_eq!(file.read(510, &mut buf[..4]), Ok(4));
+        assert_eq!(&buf[..4], b"aabb");
+        assert_eq!(file.read(600, &mut buf), Ok(0));
+        // Reading in pieces picks up the chain where it was left.
+        let entry = fs.lookup("/efi/bootx64.efi").unwrap();
+        let file =
+            File { vol: Rc::clone(&fs.vol), entry, cursor: Cell::new(None) };
+        assert_eq!(file.read(508, &mut buf[..4]), Ok(4));
+        assert_eq!(file.cursor.get(), Some((0, 5)));
+        assert_eq!(file.read(512, &mut buf[..4]), Ok(4));
+        assert_eq!(&buf[..4], b"bbbb");
+        assert_eq!(file.cursor.get(), Some((1, 7)));
+        assert_eq!(file.read(0, &mut buf[..4]), Ok(4));
+        assert_eq!(&buf[..4], b"aaaa");
+        // A chain that runs off the end of the FAT is broken.
+        let mut img = img;
+        let offset = RESERVED * SECTOR + 5 * 4;
+        img[offset..offset + 4].copy_from_slice(&0x4000u32.to_le_bytes());
+        let fs = FileSystem::try_new(&img).unwrap();
+        let file = fs.open("/efi/bootx64.efi").unwrap();
+        assert_eq!(file.read(0, &mut buf), Err(Error::FsRead));
+    }
+
+    #[test]
+    fn detection() {
+        let img = image();
+        assert!(FileSystem::try_new(&img[..511]).is_err());
+        let mut bad = img.clone();
+        bad[22] = 1;
+        assert!(FileSystem::try_new(&bad).is_err());
+        let mut bad = img;
+        bad[510] = 0;
+        assert!(FileSystem::try_new(&bad).is_err());
+    }
+}
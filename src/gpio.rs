@@ -93,6 +93,7 @@ bitstruct! {
 
 impl Reg {
     /// Returns the raw u32 bits for this register.
+    #[cfg(feature = "cmd-hw")]
     pub fn bits(self) -> u32 {
         self.0
     }
@@ -0,0 +1,425 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Streaming decompression of gzip-compressed files.
+//!
+//! A `Reader` wraps a compressed file and presents its
+//! decompressed contents through `io::Read`, decompressing on
+//! demand into a small sliding window rather than expanding the
+//! whole file into memory.  This is efficient for the access
+//! pattern of the ELF loader, which reads headers and then
+//! segments in increasing file order.  Reads that go backwards
+//! are supported, but restart decompression from the beginning
+//! of the stream.
+
+use crate::crc32;
+use crate::io::Read;
+use crate::mem;
+use crate::result::{Error, Result, ResultExt};
+use alloc::boxed::Box;
+use core::cell::RefCell;
+use core::ops::Range;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::DecompressorOxide;
+use miniz_oxide::inflate::core::decompress;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+use static_assertions::const_assert;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The DEFLATE method, the only one defined for gzip.
+const CM_DEFLATE: u8 = 8;
+
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// The length of the fixed portion of the gzip header, and of
+/// the trailer holding the CRC and uncompressed size.
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+
+/// The maximum header length we accept, including optional
+/// fields such as the original file name.
+const MAX_HEADER_LEN: usize = 1024;
+const_assert!(MAX_HEADER_LEN <= mem::MAX_STACK_BUF);
+
+/// The decompression window must be a power of two, and at
+/// least as large as the maximum DEFLATE back-reference
+/// distance.
+const WINDOW_LEN: usize = 32 * 1024;
+const INBUF_LEN: usize = 4096;
+
+/// Formats of file recognized by their leading magic number.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Format {
+    Plain,
+    Gzip,
+    Zstd,
+}
+
+/// Determines the format of the given file by examining its
+/// magic number.
+pub(crate) fn sniff(file: &dyn Read) -> Result<Format> {
+    let mut magic = [0u8; 4];
+    let n = file.read(0, &mut magic)?;
+    let magic = &magic[..n];
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Format::Gzip)
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Ok(Format::Zstd)
+    } else {
+        Ok(Format::Plain)
+    }
+}
+
+/// Parses the gzip header at the start of the file, returning
+/// the offset of the compressed data.
+fn parse_header(file: &dyn Read) -> Result<usize> {
+    let mut buf = [0u8; MAX_HEADER_LEN];
+    let n = file.read(0, &mut buf)?;
+    let buf = &buf[..n];
+    if buf.len() < HEADER_LEN || !buf.starts_with(&GZIP_MAGIC) {
+        return Err(Error::Decompress("gzip: bad header"));
+    }
+    if buf[2] != CM_DEFLATE {
+        return Err(Error::Decompress("gzip: unsupported method"));
+    }
+    let flags = buf[3];
+    let mut off = HEADER_LEN;
+    if flags & FEXTRA != 0 {
+        let xlen = buf
+            .get(off..off + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .ok_or(Error::Decompress("gzip: header too long"))?;
+        off += 2 + usize::from(xlen);
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let nul = buf
+                .get(off..)
+                .and_then(|b| b.iter().position(|&b| b == 0))
+                .ok_or(Error::Decompress("gzip: header too long"))?;
+            off += nul + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        off += 2;
+    }
+    if off > buf.len() {
+        return Err(Error::Decompress("gzip: header too long"));
+    }
+    Ok(off)
+}
+
+/// Returns the CRC-32 and uncompressed size recorded in the
+/// gzip trailer.  Note that the size is modulo 2^32, which is
+/// ample for the images we load.
+fn parse_trailer(file: &dyn Read) -> Result<(u32, usize)> {
+    let size = file.size();
+    if size < HEADER_LEN + TRAILER_LEN {
+        return Err(Error::Decompress("gzip: file truncated"));
+    }
+    let mut trailer = [0u8; TRAILER_LEN];
+    let off = (size - TRAILER_LEN) as u64;
+    if file.read(off, &mut trailer)? != trailer.len() {
+        return Err(Error::Decompress("gzip: file truncated"));
+    }
+    let [c0, c1, c2, c3, s0, s1, s2, s3] = trailer;
+    let crc = u32::from_le_bytes([c0, c1, c2, c3]);
+    let isize = u32::from_le_bytes([s0, s1, s2, s3]);
+    Ok((crc, isize as usize))
+}
+
+/// The state of an in-progress decompression.
+struct Stream {
+    decomp: Box<DecompressorOxide>,
+    window: Box<[u8; WINDOW_LEN]>,
+    inbuf: Box<[u8; INBUF_LEN]>,
+    /// The valid, unconsumed portion of `inbuf`.
+    input: Range<usize>,
+    /// The file offset of the next compressed data to read.
+    next_in: usize,
+    /// Where in the window the decompressor writes next.
+    out_pos: usize,
+    /// Decompressed bytes in the window not yet consumed by a
+    /// read, and the offset in the decompressed file of the
+    /// first of them.
+    avail: Range<usize>,
+    pos: usize,
+    /// The CRC-32 and length of all data decompressed so far,
+    /// checked against the trailer at the end of the stream.
+    crc: u32,
+    total: usize,
+    done: bool,
+}
+
+impl Stream {
+    fn new(data_start: usize) -> Stream {
+        // The decompressor and buffers are too large to build on
+        // the stack and move into the heap, so are zeroed in
+        // place.  All zeros is the decompressor's initial state,
+        // as its `Default` implementation documents.
+        Stream {
+            decomp: unsafe { Box::new_zeroed().assume_init() },
+            window: unsafe { Box::new_zeroed().assume_init() },
+            inbuf: unsafe { Box::new_zeroed().assume_init() },
+            input: 0..0,
+            next_in: data_start,
+            out_pos: 0,
+            avail: 0..0,
+            pos: 0,
+            crc: 0,
+            total: 0,
+            done: false,
+        }
+    }
+
+    fn reset(&mut self, data_start: usize) {
+        self.decomp.init();
+        self.input = 0..0;
+        self.next_in = data_start;
+        self.out_pos = 0;
+        self.avail = 0..0;
+        self.pos = 0;
+        self.crc = 0;
+        self.total = 0;
+        self.done = false;
+    }
+
+    /// Decompresses the next run of data into the window,
+    /// setting `avail` to cover it.  Must only be called when
+    /// all previously available data has been consumed, so that
+    /// it is not overwritten.  At the end of the stream, the
+    /// checksum and length of the output are compared against
+    /// those in the trailer.
+    fn fill(
+        &mut self,
+        file: &dyn Read,
+        data_end: usize,
+        trailer: (u32, usize),
+    ) -> Result<()> {
+        debug_assert!(self.avail.is_empty());
+        if self.input.is_empty() && self.next_in < data_end {
+            let len = usize::min(INBUF_LEN, data_end - self.next_in);
+            let n = file.read(self.next_in as u64, &mut self.inbuf[..len])?;
+            if n == 0 {
+                return Err(Error::Decompress("gzip: file truncated"));
+            }
+            self.input = 0..n;
+            self.next_in += n;
+        }
+        let flags =
+            if self.next_in < data_end { TINFL_FLAG_HAS_MORE_INPUT } else { 0 };
+        let (status, nin, nout) = decompress(
+            &mut self.decomp,
+            &self.inbuf[self.input.clone()],
+            &mut self.window[..],
+            self.out_pos,
+            flags,
+        );
+        self.input.start += nin;
+        self.avail = self.out_pos..self.out_pos + nout;
+        self.out_pos = (self.out_pos + nout) & (WINDOW_LEN - 1);
+        self.crc = crc32::update(self.crc, &self.window[self.avail.clone()]);
+        self.total += nout;
+        match status {
+            TINFLStatus::Done => {
+                // Withhold the final run on a mismatch, so that
+                // later reads fail too rather than serving it.
+                let (crc, size) = trailer;
+                if self.crc != crc || self.total as u32 as usize != size {
+                    self.avail = 0..0;
+                    return Err(Error::Decompress("gzip: checksum mismatch"));
+                }
+                self.done = true;
+            }
+            TINFLStatus::HasMoreOutput | TINFLStatus::NeedsMoreInput
+                if nin != 0 || nout != 0 => {}
+            _ => return Err(Error::Decompress("gzip: corrupt data")),
+        }
+        Ok(())
+    }
+}
+
+/// A reader presenting the decompressed contents of a gzip
+/// file.
+pub(crate) struct Reader<'a> {
+    file: &'a dyn Read,
+    data: Range<usize>,
+    size: usize,
+    crc: u32,
+    stream: RefCell<Stream>,
+}
+
+/// Parses the gzip framing around the compressed data in the
+/// given file, returning the range of the file holding the raw
+/// DEFLATE stream, and the uncompressed size.
+pub(crate) fn frame(file: &dyn Read) -> Result<(Range<usize>, usize)> {
+    let (data, (_, size)) = frame_with_crc(file)?;
+    Ok((data, size))
+}
+
+/// As `frame`, but also returns the CRC-32 of the uncompressed
+/// data recorded in the trailer.
+fn frame_with_crc(file: &dyn Read) -> Result<(Range<usize>, (u32, usize))> {
+    let start = parse_header(file)?;
+    let trailer = parse_trailer(file)?;
+    let end = file.size() - TRAILER_LEN;
+    if start > end {
+        return Err(Error::Decompress("gzip: file truncated"));
+    }
+    Ok((start..end, trailer))
+}
+
+/// The operating system recorded in the headers we write, which
+/// is unknown.
+const OS_UNKNOWN: u8 = 255;
+
+/// Returns a minimal gzip header, with neither optional fields
+/// nor a modification time, to precede a raw DEFLATE stream.
+pub(crate) fn header() -> [u8; HEADER_LEN] {
+    let [m0, m1] = GZIP_MAGIC;
+    [m0, m1, CM_DEFLATE, 0, 0, 0, 0, 0, 0, OS_UNKNOWN]
+}
+
+/// Returns the gzip trailer for the given uncompressed data: its
+/// CRC-32 and its size modulo 2^32.
+pub(crate) fn trailer(data: &[u8]) -> [u8; TRAILER_LEN] {
+    let mut trailer = [0u8; TRAILER_LEN];
+    trailer[..4].copy_from_slice(&crc32::crc32(data).to_le_bytes());
+    trailer[4..].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    trailer
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(file: &'a dyn Read) -> Result<Reader<'a>> {
+        let (data, (crc, size)) = frame_with_crc(file)?;
+        let stream = RefCell::new(Stream::new(data.start));
+        Ok(Reader { file, data, size, crc, stream })
+    }
+}
+
+impl Read for Reader<'_> {
+    fn read(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        let off = off as usize;
+        let mut s = self.stream.borrow_mut();
+        if off < s.pos {
+            s.reset(self.data.start);
+        }
+        let mut nread = 0;
+        while nread < dst.len() {
+            if s.avail.is_empty() {
+                if s.done {
+                    break;
+                }
+                s.fill(self.file, self.data.end, (self.crc, self.size))
+                    .context("decompressing at offset", &[s.pos as u64])?;
+                continue;
+            }
+            let want = off + nread;
+            if s.pos < want {
+                let skip = usize::min(want - s.pos, s.avail.len());
+                s.avail.start += skip;
+                s.pos += skip;
+                continue;
+            }
+            let len = usize::min(s.avail.len(), dst.len() - nread);
+            let start = s.avail.start;
+            dst[nread..nread + len]
+                .copy_from_slice(&s.window[start..start + len]);
+            s.avail.start += len;
+            s.pos += len;
+            nread += len;
+        }
+        Ok(nread)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn gzip(data: &[u8], name: Option<&[u8]>) -> Vec<u8> {
+        let mut gz = vec![0x1f, 0x8b, CM_DEFLATE, 0, 0, 0, 0, 0, 0, 3];
+        if let Some(name) = name {
+            gz[3] |= FNAME;
+            gz.extend_from_slice(name);
+            gz.push(0);
+        }
+        gz.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+        gz.extend_from_slice(&trailer(data));
+        gz
+    }
+
+    fn data() -> Vec<u8> {
+        // Mildly compressible, and larger than the window.
+        (0..100_000u64).map(|k| (k * k % 251) as u8).collect()
+    }
+
+    #[test]
+    fn sniff_formats() {
+        let gz = gzip(b"hello", None);
+        assert_eq!(sniff(&gz.as_slice()).unwrap(), Format::Gzip);
+        let zst: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd, 0];
+        assert_eq!(sniff(&zst).unwrap(), Format::Zstd);
+        let elf: &[u8] = b"\x7fELF";
+        assert_eq!(sniff(&elf).unwrap(), Format::Plain);
+    }
+
+    #[test]
+    fn read_forward_and_back() {
+        let data = data();
+        let gz = gzip(&data, Some(b"unix"));
+        let file = gz.as_slice();
+        let r = Reader::new(&file).unwrap();
+        assert_eq!(r.size(), data.len());
+        let mut buf = [0u8; 4096];
+        for off in [0, 10, 5000, 40_000, 70_001, 300] {
+            let n = r.read(off as u64, &mut buf).unwrap();
+            assert_eq!(n, buf.len());
+            assert_eq!(&buf[..], &data[off..off + n]);
+        }
+        let n = r.read((data.len() - 10) as u64, &mut buf).unwrap();
+        assert_eq!(&buf[..n], &data[data.len() - 10..]);
+        assert_eq!(r.read(data.len() as u64, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let mut gz = gzip(&data(), None);
+        gz[HEADER_LEN] = 0xff;
+        let file = gz.as_slice();
+        let r = Reader::new(&file).unwrap();
+        let mut buf = [0u8; 4096];
+        assert!(r.read(0, &mut buf).is_err());
+    }
+
+    #[test]
+    fn bad_trailer() {
+        let data = data();
+        let mut buf = vec![0u8; data.len()];
+        for k in [0, 4] {
+            let mut gz = gzip(&data, None);
+            let at = gz.len() - TRAILER_LEN + k;
+            gz[at] ^= 1;
+            let file = gz.as_slice();
+            let r = Reader::new(&file).unwrap();
+            let err = r.read(0, &mut buf).unwrap_err();
+            assert!(err.is(Error::Decompress("")));
+            assert!(r.read(0, &mut buf).is_err());
+        }
+        let gz = gzip(&data, None);
+        let file = gz.as_slice();
+        let r = Reader::new(&file).unwrap();
+        assert_eq!(r.read(0, &mut buf).unwrap(), data.len());
+    }
+}
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Access to hardware outside of the address space.
+//!
+//! I/O ports and model-specific registers are reached by special
+//! instructions rather than through memory, so unlike RAM and
+//! MMIO they cannot be simulated by mapping something else in
+//! their place.  REPL commands reach them through the `Hardware`
+//! held in the configuration instead, for which the REPL
+//! simulator substitutes its own.
+
+/// I/O ports and MSRs.  The ports are only reached from the
+/// REPL by the `cmd-hw` commands.
+///
+/// # Safety
+/// Each method has the same requirements as the instruction it
+/// stands for: the caller must ensure that the access cannot
+/// disturb the loader.
+pub(crate) trait Hardware {
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn inb(&self, port: u16) -> u8;
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn inw(&self, port: u16) -> u16;
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn inl(&self, port: u16) -> u32;
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn outb(&self, port: u16, value: u8);
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn outw(&self, port: u16, value: u16);
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    unsafe fn outl(&self, port: u16, value: u32);
+    unsafe fn rdmsr(&self, msr: u32) -> u64;
+    unsafe fn wrmsr(&self, msr: u32, value: u64);
+}
+
+/// The machine the loader is running on.
+pub(crate) struct Machine;
+
+impl Hardware for Machine {
+    unsafe fn inb(&self, port: u16) -> u8 {
+        unsafe { x86::io::inb(port) }
+    }
+
+    unsafe fn inw(&self, port: u16) -> u16 {
+        unsafe { x86::io::inw(port) }
+    }
+
+    unsafe fn inl(&self, port: u16) -> u32 {
+        unsafe { x86::io::inl(port) }
+    }
+
+    unsafe fn outb(&self, port: u16, value: u8) {
+        unsafe { x86::io::outb(port, value) }
+    }
+
+    unsafe fn outw(&self, port: u16, value: u16) {
+        unsafe { x86::io::outw(port, value) }
+    }
+
+    unsafe fn outl(&self, port: u16, value: u32) {
+        unsafe { x86::io::outl(port, value) }
+    }
+
+    unsafe fn rdmsr(&self, msr: u32) -> u64 {
+        unsafe { x86::msr::rdmsr(msr) }
+    }
+
+    unsafe fn wrmsr(&self, msr: u32, value: u64) {
+        unsafe { x86::msr::wrmsr(msr, value) }
+    }
+}
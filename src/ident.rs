@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The loader's identity.
+//!
+//! The loader image carries a record that `cargo xtask dist`
+//! fills in after linking: an ID for the build, and the SHA-256
+//! digest of the loader's text and read-only data.  The record
+//! itself lives in the data segment, so that filling it in does
+//! not change what it describes.  A loader that was built but not
+//! packaged has a record of zeros.
+
+use core::fmt;
+use sha2::{Digest, Sha256};
+
+/// Marks the record in the image, so that `xtask` can find it.
+/// This must agree with `xtask`.
+const MAGIC: [u8; 16] = *b"bldb-ident-v1\0\0\0";
+
+/// An ID for a build: the leading bytes of the SHA-256 digest
+/// of the image as linked.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub(crate) struct BuildId([u8; 16]);
+
+impl fmt::Display for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The identity record.  Its layout must agree with `xtask`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub(crate) struct Ident {
+    magic: [u8; 16],
+    pub(crate) build_id: BuildId,
+    digest: [u8; 32],
+}
+
+#[used]
+#[cfg_attr(not(any(test, clippy)), unsafe(link_section = ".data.ident"))]
+static IDENT: Ident =
+    Ident { magic: MAGIC, build_id: BuildId([0; 16]), digest: [0; 32] };
+
+/// Returns the loader's identity record.  The record is read
+/// from memory, as the compiler only knows its zeroed contents.
+pub(crate) fn ident() -> Ident {
+    unsafe { core::ptr::read_volatile(&IDENT) }
+}
+
+impl Ident {
+    /// Returns true iff the image was packaged, and so the
+    /// record filled in.
+    pub(crate) fn is_packaged(&self) -> bool {
+        self.build_id.0 != [0; 16]
+    }
+
+    /// Returns the digest of the given bytes, and whether it is
+    /// the one recorded in the image.
+    pub(crate) fn check(&self, image: &[u8]) -> ([u8; 32], bool) {
+        let digest: [u8; 32] = Sha256::digest(image).into();
+        (digest, digest == self.digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let ident = ident();
+        assert_eq!(ident.magic, MAGIC);
+        assert!(!ident.is_packaged());
+        assert_eq!(core::mem::size_of::<Ident>(), 64);
+        let image = b"text and rodata";
+        let packaged = Ident {
+            build_id: BuildId([0xa5; 16]),
+            digest: Sha256::digest(image).into(),
+            ..ident
+        };
+        assert!(packaged.is_packaged());
+        assert!(packaged.check(image).1);
+        assert!(!packaged.check(b"text and rodatA").1);
+        assert_eq!(
+            alloc::format!("{}", packaged.build_id),
+            "a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5a5"
+        );
+    }
+}
@@ -5,10 +5,12 @@
 // Derived from the rxv64 operating system.
 
 use crate::println;
+use crate::result::{Error, Result};
 use bit_field::BitField;
 use bitstruct::bitstruct;
 use core::arch::{asm, naked_asm};
 use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 use seq_macro::seq;
 
 /// Returns the selector for the 64-bit code segment in the GDT.
@@ -64,44 +66,107 @@ impl GateDesc {
 }
 
 /// The trap frame captured by software on exceptions
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 #[repr(C)]
-struct TrapFrame {
+pub(crate) struct TrapFrame {
     // Pushed by software.
-    rax: u64,
-    rbx: u64,
-    rcx: u64,
-    rdx: u64,
-    rsi: u64,
-    rdi: u64,
-    rbp: u64,
-    r8: u64,
-    r9: u64,
-    r10: u64,
-    r11: u64,
-    r12: u64,
-    r13: u64,
-    r14: u64,
-    r15: u64,
+    pub(crate) rax: u64,
+    pub(crate) rbx: u64,
+    pub(crate) rcx: u64,
+    pub(crate) rdx: u64,
+    pub(crate) rsi: u64,
+    pub(crate) rdi: u64,
+    pub(crate) rbp: u64,
+    pub(crate) r8: u64,
+    pub(crate) r9: u64,
+    pub(crate) r10: u64,
+    pub(crate) r11: u64,
+    pub(crate) r12: u64,
+    pub(crate) r13: u64,
+    pub(crate) r14: u64,
+    pub(crate) r15: u64,
 
     // %ds and %es are not used in 64-bit mode, but they exist,
     // so we save and restore them.
-    ds: u64, // Really these are u16s, but
-    es: u64, // we waste a few bytes to keep
-    fs: u64, // the stack aligned.  Thank
-    gs: u64, // you, x86 segmentation.
+    pub(crate) ds: u64, // Really these are u16s, but
+    pub(crate) es: u64, // we waste a few bytes to keep
+    pub(crate) fs: u64, // the stack aligned.  Thank
+    pub(crate) gs: u64, // you, x86 segmentation.
 
-    vector: u64,
+    pub(crate) vector: u64,
 
     // Sometimes pushed by hardware.
-    error: u64,
+    pub(crate) error: u64,
 
     // Pushed by hardware.
-    rip: u64,
-    cs: u64,
-    rflags: u64,
-    rsp: u64,
-    ss: u64,
+    pub(crate) rip: u64,
+    pub(crate) cs: u64,
+    pub(crate) rflags: u64,
+    pub(crate) rsp: u64,
+    pub(crate) ss: u64,
+}
+
+impl TrapFrame {
+    /// Returns the general purpose registers, by name, in the
+    /// conventional order.
+    pub(crate) fn gprs(&self) -> [(&'static str, u64); 16] {
+        [
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rbp", self.rbp),
+            ("rsp", self.rsp),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+        ]
+    }
+}
+
+/// Returns the mnemonic of an architectural exception vector.
+pub(crate) fn vector_name(vector: u64) -> &'static str {
+    const NAMES: [&str; 22] = [
+        "#DE", "#DB", "NMI", "#BP", "#OF", "#BR", "#UD", "#NM", "#DF", "-",
+        "#TS", "#NP", "#SS", "#GP", "#PF", "-", "#MF", "#AC", "#MC", "#XM",
+        "#VE", "#CP",
+    ];
+    match NAMES.get(vector as usize) {
+        Some(&"-") | None => "interrupt",
+        Some(name) => name,
+    }
+}
+
+/// An exception taken by the loader: its trap frame, and for
+/// page faults, the faulting address from %cr2.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Trap {
+    pub(crate) frame: TrapFrame,
+    pub(crate) cr2: Option<u64>,
+}
+
+/// The last exception taken, for `regs`.
+static LAST_TRAP: spin::Mutex<Option<Trap>> = spin::Mutex::new(None);
+
+/// Returns the last exception taken, if any.
+pub(crate) fn last_trap() -> Option<Trap> {
+    *LAST_TRAP.lock()
+}
+
+/// Records an exception as the last taken.  The exception may
+/// have interrupted `last_trap`, so this gives up rather than
+/// waiting for the lock.
+pub(crate) fn record(trap: Trap) {
+    if let Some(mut last) = LAST_TRAP.try_lock() {
+        *last = Some(trap);
+    }
 }
 
 macro_rules! gen_stub {
@@ -258,32 +323,175 @@ impl Idt {
     }
 }
 
-// Tries to skip over an instruction.
-//
-// # Safety
-// The caller must ensure `rip` points to an instruction
-// somewhere in the loader text.
-unsafe fn skip_instr(rip: u64) -> u64 {
+/// Returns true if an instruction at `rip` lies within the
+/// loader text.
+fn in_loader_text(rip: u64) -> bool {
+    const MAX_INSTR_LEN: u64 = 15;
+    let loader_text = crate::bldb::loader_text();
+    loader_text.contains(&rip) && loader_text.contains(&(rip + MAX_INSTR_LEN))
+}
+
+// Tries to skip over an instruction, returning the address of
+// the next, or `None` if `rip` is not in the loader text or the
+// instruction there cannot be decoded.
+fn skip_instr(rip: u64) -> Option<u64> {
     use iced_x86::{Code, Decoder, DecoderOptions};
     const MAX_INSTR_LEN: usize = 15;
-    let loader_text = crate::bldb::loader_text();
-    let ripmax = rip + MAX_INSTR_LEN as u64;
-    if !loader_text.contains(&rip) || !loader_text.contains(&ripmax) {
-        panic!("PC does not point into loader text");
+    if !in_loader_text(rip) {
+        return None;
     }
     let ptr = core::ptr::with_exposed_provenance(rip as usize);
     let bytes = unsafe { core::slice::from_raw_parts(ptr, MAX_INSTR_LEN) };
     let mut decoder = Decoder::with_ip(64, bytes, rip, DecoderOptions::NONE);
     let instr = decoder.decode();
-    if instr.code() == Code::INVALID {
-        panic!("Invalid instruction; can't skip: {instr:x?}");
-    } else {
-        rip + instr.len() as u64
+    (instr.code() != Code::INVALID).then(|| rip + instr.len() as u64)
+}
+
+/// Set while a fault-tolerant access is in progress.
+static PROBING: AtomicBool = AtomicBool::new(false);
+
+/// Set by the trap handler if an access faulted while probing.
+static FAULTED: AtomicBool = AtomicBool::new(false);
+
+/// Runs the given closure with fault recovery armed.  If the
+/// closure triggers a general protection or page fault, the
+/// trap handler quietly skips the faulting instruction and
+/// we return `None`; the closure's result is then garbage and
+/// is discarded.
+///
+/// The closure should be a single, simple memory access: any
+/// state the skipped instruction would have updated is left as
+/// it was.
+#[cfg(feature = "cmd-bench")]
+pub(crate) fn with_fault_recovery<T, F>(f: F) -> Option<T>
+where
+    F: FnOnce() -> T,
+{
+    use core::sync::atomic::compiler_fence;
+    FAULTED.store(false, Ordering::Relaxed);
+    PROBING.store(true, Ordering::Release);
+    compiler_fence(Ordering::SeqCst);
+    let r = f();
+    compiler_fence(Ordering::SeqCst);
+    PROBING.store(false, Ordering::Release);
+    if FAULTED.swap(false, Ordering::Acquire) { None } else { Some(r) }
+}
+
+/// A point from which the loader can recover from a fault: the
+/// callee-saved registers and the stack pointer on entry to
+/// `recover_call`, which returns a second time from there.
+#[derive(Default)]
+#[repr(C)]
+struct RecoveryPoint {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+}
+
+/// The innermost armed recovery point, if any.
+static RECOVERY: AtomicPtr<RecoveryPoint> = AtomicPtr::new(ptr::null_mut());
+
+/// Saves a recovery point and calls `f` with `arg`, returning 0
+/// when it returns, or 1 if a fault was recovered from.
+#[unsafe(naked)]
+unsafe extern "C" fn recover_call(
+    _point: *mut RecoveryPoint,
+    _f: extern "C" fn(*mut u8),
+    _arg: *mut u8,
+) -> u64 {
+    naked_asm!(
+        r#"
+        movq %rbx, 0(%rdi);
+        movq %rbp, 8(%rdi);
+        movq %r12, 16(%rdi);
+        movq %r13, 24(%rdi);
+        movq %r14, 32(%rdi);
+        movq %r15, 40(%rdi);
+        movq %rsp, 48(%rdi);
+        // Realign the stack for the call.
+        subq $8, %rsp;
+        movq %rdx, %rdi;
+        callq *%rsi;
+        addq $8, %rsp;
+        xorl %eax, %eax;
+        retq;
+        "#,
+        options(att_syntax)
+    )
+}
+
+/// Where the exception return lands when recovering from a
+/// fault, with the registers of the recovery point restored, so
+/// that `recover_call` returns 1.
+#[unsafe(naked)]
+unsafe extern "C" fn recovered() -> ! {
+    naked_asm!("movl $1, %eax; retq", options(att_syntax))
+}
+
+/// Runs `f`, a REPL command, with a recovery point armed.  A
+/// general protection or page fault taken by the loader while it
+/// runs unwinds back here, and the command fails with
+/// `Error::Trapped` rather than halting the loader.  Unwinding
+/// abandons the frames of `f` without running their destructors,
+/// so whatever they owned is leaked, and any locks they held stay
+/// held.
+pub(crate) fn with_recovery<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    extern "C" fn shim<F: FnOnce()>(arg: *mut u8) {
+        let f = unsafe { &mut *arg.cast::<Option<F>>() };
+        if let Some(f) = f.take() {
+            f();
+        }
+    }
+    fn shim_for<F: FnOnce()>(_: &Option<F>) -> extern "C" fn(*mut u8) {
+        shim::<F>
+    }
+    if cfg!(test) {
+        return f();
+    }
+    let mut point = RecoveryPoint::default();
+    let mut result = None;
+    let mut call = Some(|| result = Some(f()));
+    let shim = shim_for(&call);
+    let prev = RECOVERY.swap(&raw mut point, Ordering::AcqRel);
+    let trapped =
+        unsafe { recover_call(&raw mut point, shim, (&raw mut call).cast()) };
+    RECOVERY.store(prev, Ordering::Release);
+    drop(call);
+    match (trapped, result) {
+        (0, Some(result)) => result,
+        _ => Err(Error::Trapped),
     }
 }
 
 extern "C" fn trap(frame: &mut TrapFrame) {
+    const DB: u64 = 1;
+    const BP: u64 = 3;
     const GPF: u64 = 13;
+    const PF: u64 = 14;
+    let cr2 =
+        (frame.vector == PF).then(|| unsafe { x86::controlregs::cr2() } as u64);
+    record(Trap { frame: *frame, cr2 });
+    // A fault taken by a fault-tolerant access is recovered from
+    // by skipping the instruction that took it; nothing else is,
+    // as the state that instruction would have updated is left
+    // stale.
+    if PROBING.load(Ordering::Acquire)
+        && matches!(frame.vector, GPF | PF)
+        && let Some(rip) = skip_instr(frame.rip)
+    {
+        FAULTED.store(true, Ordering::Release);
+        frame.rip = rip;
+        return;
+    }
+    // Breakpoints and single steps in code called from the REPL
+    // stop in the debugger, and watchpoints are reported there.
+    if matches!(frame.vector, DB | BP) && crate::repl::debug_trap(frame) {
+        return;
+    }
     println!("Exception:");
     println!("{frame:#x?}");
     println!("cr0: {:#x}", unsafe { x86::controlregs::cr0() });
@@ -294,12 +502,28 @@ extern "C" fn trap(frame: &mut TrapFrame) {
     unsafe {
         backtrace(frame.rbp);
     }
-    // If this is a GPF, attempt to recover by skipping to the
-    // next instruction.  Otherwise, arrange for the exception
-    // return to land in a halt loop.
-    if frame.vector == GPF {
-        println!("GPF OK; attempting to resume");
-        frame.rip = unsafe { skip_instr(frame.rip) };
+    // If this is a GPF or page fault in the loader, as when
+    // poking at memory from the REPL, and a recovery point is
+    // armed, unwind to it: the command fails, and the REPL
+    // carries on.  Otherwise, arrange for the exception return to
+    // land in a halt loop.
+    let point = RECOVERY.load(Ordering::Acquire);
+    if matches!(frame.vector, GPF | PF)
+        && in_loader_text(frame.rip)
+        && let Some(point) = unsafe { point.as_ref() }
+    {
+        println!(
+            "{} OK; abandoning the command; see `regs`",
+            vector_name(frame.vector)
+        );
+        frame.rbx = point.rbx;
+        frame.rbp = point.rbp;
+        frame.r12 = point.r12;
+        frame.r13 = point.r13;
+        frame.r14 = point.r14;
+        frame.r15 = point.r15;
+        frame.rsp = point.rsp;
+        frame.rip = recovered as *const () as usize as u64;
     } else {
         // The seemingly superfluous cast to usize and then
         // again to u64 keeps clippy happy.
@@ -345,7 +569,6 @@ unsafe fn backtrace(mut rbp: u64) {
 /// Should be called exactly once, early in boot.
 pub(crate) fn init() {
     use core::cell::SyncUnsafeCell;
-    use core::sync::atomic::{AtomicBool, Ordering};
     static INITED: AtomicBool = AtomicBool::new(false);
     if INITED.swap(true, Ordering::AcqRel) {
         panic!("IDT already initialized");
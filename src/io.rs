@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::result::Result;
+use core::ptr::NonNull;
 
 /// A "Storage Device" that represents the memory allocated to
 /// a ramdisk.
@@ -11,32 +12,48 @@ use crate::result::Result;
 /// to work around some lifetime issues.
 #[derive(Debug)]
 pub(crate) struct Sd {
-    pub(crate) ptr: *const u8,
+    pub(crate) ptr: NonNull<u8>,
     pub(crate) len: usize,
+    /// Set if made from mutable memory, which may be written.
+    pub(crate) writable: bool,
 }
 
 impl Sd {
-    pub(crate) unsafe fn new(ptr: *const u8, len: usize) -> Sd {
-        Sd { ptr, len }
-    }
-
-    /// Creates a new `Sd` from a slice.
+    /// Creates a new `Sd` from a slice.  The data may only be
+    /// read through the result.
     ///
     /// # Safety
     /// It is up to the caller to ensure that the data in `bs`
     /// is not moved or dropped while this `Sd`, or any other
     /// derived from it, is alive.
     pub(crate) unsafe fn from_slice(bs: &[u8]) -> Sd {
-        unsafe { Sd::new(bs.as_ptr(), bs.len()) }
+        Sd { ptr: NonNull::from(bs).cast(), len: bs.len(), writable: false }
+    }
+
+    /// Creates a new `Sd` from a mutable slice, through which
+    /// the data may also be written.
+    ///
+    /// # Safety
+    /// As for `from_slice`.
+    pub(crate) unsafe fn from_mut_slice(bs: &mut [u8]) -> Sd {
+        let len = bs.len();
+        Sd { ptr: NonNull::from(bs).cast(), len, writable: true }
     }
 
     /// Reconstitutes this `Sd`` into a slice
     pub(crate) unsafe fn as_slice(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
     }
 
     pub(crate) fn data(&self) -> *const u8 {
-        self.ptr
+        self.ptr.as_ptr().cast_const()
+    }
+
+    /// Returns a pointer through which the data may be written,
+    /// if this `Sd` was derived from one created by
+    /// `from_mut_slice`.
+    pub(crate) fn data_mut(&self) -> Option<*mut u8> {
+        self.writable.then_some(self.ptr.as_ptr())
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -45,8 +62,8 @@ impl Sd {
 
     pub(crate) fn subset(&self, offset: usize, len: usize) -> Sd {
         assert!(offset + len <= self.len);
-        let ptr = self.ptr.wrapping_add(offset);
-        Sd { ptr, len }
+        let ptr = unsafe { self.ptr.add(offset) };
+        Sd { ptr, len, writable: self.writable }
     }
 }
 
@@ -67,10 +67,7 @@ impl fmt::Debug for IoMux {
 /// The caller must ensure that the IO mux MMIO region is in the
 /// current address space.
 pub unsafe fn init() -> &'static mut IoMux {
-    const IOMUX_BASE_ADDR_OFFSET: usize = 0x0D00;
-    let base_addr = bldb::iomux_page_addr().addr() + IOMUX_BASE_ADDR_OFFSET;
-    let ptr = ptr::with_exposed_provenance_mut::<IoMux>(base_addr);
-    let iomux = unsafe { &mut *ptr };
+    let iomux = unsafe { iomux_mut() };
     if let Some(settings) = mux_settings() {
         for &(pin, function) in settings.iter() {
             unsafe {
@@ -81,6 +78,50 @@ pub unsafe fn init() -> &'static mut IoMux {
     iomux
 }
 
+/// Maps the pins for the secondary UART (UART 1) to UART
+/// functions, so that it can be used as a fallback diagnostic
+/// sink when the console is unavailable.  Returns false if we
+/// do not know the correct settings for this system.
+///
+/// # Safety
+/// The caller must ensure that the IO mux MMIO region is in the
+/// current address space, and that nothing else is using these
+/// pins.
+pub unsafe fn init_fallback_uart() -> bool {
+    const IOMUX140_GPIO: u8 = 140;
+    const IOMUX141_GPIO: u8 = 141;
+    const IOMUX142_GPIO: u8 = 142;
+    const IOMUX143_GPIO: u8 = 143;
+    const SETTINGS: &[(u8, PinFunction)] = &[
+        (IOMUX140_GPIO, PinFunction::F0),
+        (IOMUX141_GPIO, PinFunction::F0),
+        (IOMUX142_GPIO, PinFunction::F0),
+        (IOMUX143_GPIO, PinFunction::F0),
+    ];
+    if mux_settings().is_none() {
+        return false;
+    }
+    let iomux = unsafe { iomux_mut() };
+    for &(pin, function) in SETTINGS.iter() {
+        unsafe {
+            iomux.set_pin(pin, function);
+        }
+    }
+    true
+}
+
+/// Returns a reference to the IO mux MMIO registers.
+///
+/// # Safety
+/// The caller must ensure that the IO mux MMIO region is in the
+/// current address space.
+unsafe fn iomux_mut() -> &'static mut IoMux {
+    const IOMUX_BASE_ADDR_OFFSET: usize = 0x0D00;
+    let base_addr = bldb::iomux_page_addr().addr() + IOMUX_BASE_ADDR_OFFSET;
+    let ptr = ptr::with_exposed_provenance_mut::<IoMux>(base_addr);
+    unsafe { &mut *ptr }
+}
+
 /// Returns the correct IO mux settings for the current system,
 /// if any.
 fn mux_settings() -> Option<&'static [(u8, PinFunction)]> {
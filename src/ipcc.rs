@@ -0,0 +1,464 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The host side of the inter-processor communications channel
+//! (IPCC) with the service processor.
+//!
+//! The SP and host exchange messages over a dedicated UART.
+//! Every exchange is initiated by the host, which sends a
+//! request and waits for the SP's reply.  A message is a header
+//! (a magic number, a protocol version, a sequence number, and
+//! a command), a command-specific payload, and a Fletcher-16
+//! checksum over both, all little-endian.  Messages are framed
+//! on the wire with consistent overhead byte stuffing (COBS),
+//! and each frame is terminated by a zero byte.  Replies carry
+//! the request's sequence number with the high bit set.
+//!
+//! The definitions here follow those used by the illumos IPCC
+//! driver, which is the reference implementation of the host
+//! side of the protocol.
+
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const MAGIC: u32 = 0x1de1_9cc5;
+const VERSION: u32 = 1;
+const SEQ_MASK: u64 = 0x7fff_ffff_ffff_ffff;
+const SEQ_REPLY: u64 = 0x8000_0000_0000_0000;
+
+/// The sizes of the message header and checksum.
+const HEADER_LEN: usize = 4 + 4 + 8 + 1;
+const CHECKSUM_LEN: usize = 2;
+
+/// The largest payload either side may send.
+pub(crate) const MAX_DATA_LEN: usize = 4096;
+
+/// The largest encoded frame, excluding its terminator.
+const MAX_FRAME_LEN: usize = cobs_len(HEADER_LEN + MAX_DATA_LEN + CHECKSUM_LEN);
+
+/// How long to wait for each byte of a reply.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many times a request is sent before giving up.
+const ATTEMPTS: usize = 3;
+
+/// Requests sent by the host.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub(crate) enum Request {
+    Bsu = 0x03,
+    Ident = 0x04,
+    BootFail = 0x06,
+    Status = 0x08,
+    AckStart = 0x09,
+    KeyLookup = 0x0f,
+}
+
+/// Replies sent by the SP.
+pub(crate) mod reply {
+    pub(crate) const ACK: u8 = 0x01;
+    pub(crate) const DECODE_FAIL: u8 = 0x02;
+    pub(crate) const BSU: u8 = 0x03;
+    pub(crate) const IDENT: u8 = 0x04;
+    pub(crate) const STATUS: u8 = 0x06;
+    pub(crate) const KEY_LOOKUP: u8 = 0x0b;
+}
+
+/// Bits in the status word returned by the SP.
+pub(crate) const STATUS_BITS: &[(&str, u64)] =
+    &[("started", 1 << 0), ("alerts", 1 << 1), ("reset", 1 << 2)];
+
+/// Bits in the startup options word returned by the SP, which
+/// tell the host how it should boot.
+pub(crate) const STARTUP_BITS: &[(&str, u64)] = &[
+    ("kbm", 1 << 0),
+    ("bootrd", 1 << 1),
+    ("prom", 1 << 2),
+    ("kmdb", 1 << 3),
+    ("kmdb-boot", 1 << 4),
+    ("boot-ramdisk", 1 << 5),
+    ("boot-net", 1 << 6),
+    ("verbose", 1 << 7),
+];
+
+/// Well-known keys that may be looked up on the SP.
+pub(crate) const KEYS: &[(&str, u8)] = &[
+    ("ping", 0),
+    ("installinator-image-id", 1),
+    ("inventory", 2),
+    ("etc-system", 3),
+    ("dtrace-conf", 4),
+];
+
+/// Returns the length of the COBS encoding of `len` bytes.
+const fn cobs_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Computes the Fletcher-16 checksum of the given bytes.
+fn fletcher16(bs: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for &x in bs {
+        a = (a + u16::from(x)) % 255;
+        b = (b + a) % 255;
+    }
+    b << 8 | a
+}
+
+/// COBS-encodes `bs`, which will then contain no zero bytes.
+fn cobs_encode(bs: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(cobs_len(bs.len()));
+    let mut code = out.len();
+    out.push(0);
+    for &b in bs {
+        if b != 0 {
+            out.push(b);
+        }
+        if b == 0 || out.len() - code == 0xff {
+            out[code] = (out.len() - code) as u8;
+            code = out.len();
+            out.push(0);
+        }
+    }
+    out[code] = (out.len() - code) as u8;
+    out
+}
+
+/// Decodes a COBS-encoded frame, excluding its terminator.
+fn cobs_decode(bs: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bs.len());
+    let mut k = 0;
+    while k < bs.len() {
+        let code = usize::from(bs[k]);
+        if code == 0 || k + code > bs.len() {
+            return Err(Error::Ipcc("IPCC: malformed frame"));
+        }
+        out.extend_from_slice(&bs[k + 1..k + code]);
+        k += code;
+        if code != 0xff && k < bs.len() {
+            out.push(0);
+        }
+    }
+    Ok(out)
+}
+
+/// Encodes a message as a frame, including its terminator.
+fn encode(seq: u64, cmd: u8, data: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + data.len() + CHECKSUM_LEN);
+    msg.extend_from_slice(&MAGIC.to_le_bytes());
+    msg.extend_from_slice(&VERSION.to_le_bytes());
+    msg.extend_from_slice(&seq.to_le_bytes());
+    msg.push(cmd);
+    msg.extend_from_slice(data);
+    let sum = fletcher16(&msg);
+    msg.extend_from_slice(&sum.to_le_bytes());
+    let mut frame = cobs_encode(&msg);
+    frame.push(0);
+    frame
+}
+
+/// A message decoded from a frame.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) seq: u64,
+    pub(crate) cmd: u8,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Decodes and validates a frame, excluding its terminator.
+fn decode(frame: &[u8]) -> Result<Message> {
+    let msg = cobs_decode(frame)?;
+    if msg.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(Error::Ipcc("IPCC: short message"));
+    }
+    let (body, sum) = msg.split_at(msg.len() - CHECKSUM_LEN);
+    if fletcher16(body) != u16::from_le_bytes([sum[0], sum[1]]) {
+        return Err(Error::Ipcc("IPCC: bad checksum"));
+    }
+    if u32::from_le_bytes(body[0..4].try_into().unwrap()) != MAGIC {
+        return Err(Error::Ipcc("IPCC: bad magic"));
+    }
+    if u32::from_le_bytes(body[4..8].try_into().unwrap()) != VERSION {
+        return Err(Error::Ipcc("IPCC: unsupported protocol version"));
+    }
+    let seq = u64::from_le_bytes(body[8..16].try_into().unwrap());
+    let cmd = body[16];
+    let data = body[HEADER_LEN..].to_vec();
+    Ok(Message { seq, cmd, data })
+}
+
+/// Reads a frame, excluding its terminator, skipping any empty
+/// frames.  Oversized frames are discarded in their entirety.
+fn recv_frame(uart: &mut Uart) -> Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    let mut oversized = false;
+    loop {
+        match uart.try_getb_timeout(TIMEOUT)? {
+            0 if oversized => {
+                return Err(Error::Ipcc("IPCC: oversized frame"));
+            }
+            0 if frame.is_empty() => {}
+            0 => return Ok(frame),
+            _ if frame.len() == MAX_FRAME_LEN => oversized = true,
+            b => frame.push(b),
+        }
+    }
+}
+
+/// The channel: the UART connected to the SP, and the sequence
+/// number of the last request sent.
+pub(crate) struct Ipcc {
+    uart: Uart,
+    seq: u64,
+}
+
+impl Ipcc {
+    pub(crate) fn new(uart: Uart) -> Ipcc {
+        Ipcc { uart, seq: 0 }
+    }
+
+    /// Sends a request and returns the SP's reply.  Requests
+    /// that time out or draw a malformed reply are retried,
+    /// each time with a fresh sequence number; replies to
+    /// earlier requests are discarded.
+    pub(crate) fn call(
+        &mut self,
+        req: Request,
+        data: &[u8],
+    ) -> Result<Message> {
+        assert!(data.len() <= MAX_DATA_LEN);
+        let mut err = Error::Ipcc("IPCC: no reply");
+        for _ in 0..ATTEMPTS {
+            self.seq = (self.seq + 1) & SEQ_MASK;
+            self.uart.putbs(&encode(self.seq, req as u8, data))?;
+            match self.recv_reply() {
+                Ok(msg) if msg.cmd == reply::DECODE_FAIL => {
+                    err = Error::Ipcc("IPCC: SP could not decode request");
+                }
+                Ok(msg) => return Ok(msg),
+                Err(e @ (Error::Timeout | Error::Ipcc(_))) => err = e,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(err)
+    }
+
+    fn recv_reply(&mut self) -> Result<Message> {
+        loop {
+            let msg = decode(&recv_frame(&mut self.uart)?)?;
+            if msg.seq == self.seq | SEQ_REPLY {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Calls the SP, checking that the reply is of the expected
+    /// kind and carries at least `len` bytes of payload.
+    fn expect(
+        &mut self,
+        req: Request,
+        data: &[u8],
+        cmd: u8,
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let msg = self.call(req, data)?;
+        if msg.cmd != cmd {
+            return Err(Error::Ipcc("IPCC: unexpected reply"));
+        }
+        if msg.data.len() < len {
+            return Err(Error::Ipcc("IPCC: short reply"));
+        }
+        Ok(msg.data)
+    }
+
+    /// Returns the SP's status word and the startup options it
+    /// has set for this boot.
+    pub(crate) fn status(&mut self) -> Result<(u64, u64)> {
+        let data = self.expect(Request::Status, &[], reply::STATUS, 16)?;
+        let status = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        let startup = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        Ok((status, startup))
+    }
+
+    /// Returns the board's identity: its model, revision, and
+    /// serial number.
+    pub(crate) fn ident(&mut self) -> Result<Ident> {
+        const FIELD_LEN: usize = 51;
+        let data =
+            self.expect(Request::Ident, &[], reply::IDENT, 2 * FIELD_LEN + 4)?;
+        let (model, rest) = data.split_at(FIELD_LEN);
+        let (rev, serial) = rest.split_at(4);
+        Ok(Ident {
+            model: cstr(model),
+            revision: u32::from_le_bytes(rev.try_into().unwrap()),
+            serial: cstr(&serial[..FIELD_LEN]),
+        })
+    }
+
+    /// Returns the boot storage unit the host should boot from.
+    pub(crate) fn bsu(&mut self) -> Result<u8> {
+        let data = self.expect(Request::Bsu, &[], reply::BSU, 1)?;
+        Ok(data[0])
+    }
+
+    /// Looks up the value of the given key on the SP, returning
+    /// at most `max` bytes of it.
+    pub(crate) fn key_lookup(&mut self, key: u8, max: u16) -> Result<Vec<u8>> {
+        let mut req = [0u8; 3];
+        req[0] = key;
+        req[1..].copy_from_slice(&max.to_le_bytes());
+        let data =
+            self.expect(Request::KeyLookup, &req, reply::KEY_LOOKUP, 3)?;
+        let len = usize::from(u16::from_le_bytes([data[1], data[2]]));
+        match data[0] {
+            0 if 3 + len <= data.len() => Ok(data[3..3 + len].to_vec()),
+            0 => Err(Error::Ipcc("IPCC: short reply")),
+            1 => Err(Error::Ipcc("IPCC: unknown key")),
+            2 => Err(Error::Ipcc("IPCC: key has no value")),
+            3 => Err(Error::Ipcc("IPCC: key value too large")),
+            _ => Err(Error::Ipcc("IPCC: key lookup failed")),
+        }
+    }
+
+    /// Tells the SP that the host has seen the startup options
+    /// and is booting.
+    pub(crate) fn ack_start(&mut self) -> Result<()> {
+        self.expect(Request::AckStart, &[], reply::ACK, 0)?;
+        Ok(())
+    }
+
+    /// Reports a boot failure to the SP, with a reason code and
+    /// a message.
+    pub(crate) fn boot_fail(
+        &mut self,
+        reason: u8,
+        message: &[u8],
+    ) -> Result<()> {
+        let mut req = Vec::with_capacity(1 + message.len());
+        req.push(reason);
+        req.extend_from_slice(&message[..message.len().min(MAX_DATA_LEN - 1)]);
+        self.expect(Request::BootFail, &req, reply::ACK, 0)?;
+        Ok(())
+    }
+}
+
+/// The board's identity, as reported by the SP.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Ident {
+    pub(crate) model: alloc::string::String,
+    pub(crate) revision: u32,
+    pub(crate) serial: alloc::string::String,
+}
+
+/// Converts a NUL-padded byte array into a string.
+fn cstr(bs: &[u8]) -> alloc::string::String {
+    let len = bs.iter().position(|&b| b == 0).unwrap_or(bs.len());
+    alloc::string::String::from_utf8_lossy(&bs[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+
+    #[test]
+    fn cobs() {
+        let cases: &[(&[u8], &[u8])] = &[
+            (&[], &[1]),
+            (&[0], &[1, 1]),
+            (&[0, 0], &[1, 1, 1]),
+            (&[0x11, 0x22, 0x00, 0x33], &[3, 0x11, 0x22, 2, 0x33]),
+            (&[0x11, 0x00, 0x00, 0x00], &[2, 0x11, 1, 1, 1]),
+        ];
+        for &(raw, encoded) in cases {
+            assert_eq!(cobs_encode(raw), encoded);
+            assert_eq!(cobs_decode(encoded).unwrap(), raw);
+        }
+        let long = (1..=255).cycle().take(600).collect::<Vec<u8>>();
+        let encoded = cobs_encode(&long);
+        assert!(!encoded.contains(&0));
+        assert!(encoded.len() <= cobs_len(long.len()));
+        assert_eq!(cobs_decode(&encoded).unwrap(), long);
+        assert!(cobs_decode(&[5, 1]).is_err());
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(fletcher16(b"abcde"), 0xc8f0);
+        assert_eq!(fletcher16(b"abcdef"), 0x2057);
+    }
+
+    #[test]
+    fn messages() {
+        let frame = encode(7 | SEQ_REPLY, reply::BSU, &[0, 1]);
+        let (terminator, frame) = frame.split_last().unwrap();
+        assert_eq!(*terminator, 0);
+        assert!(!frame.contains(&0));
+        let msg = decode(frame).unwrap();
+        assert_eq!(
+            msg,
+            Message { seq: 7 | SEQ_REPLY, cmd: reply::BSU, data: vec![0, 1] }
+        );
+        let mut corrupt = cobs_decode(frame).unwrap();
+        corrupt[17] ^= 1;
+        assert_eq!(
+            decode(&cobs_encode(&corrupt)),
+            Err(Error::Ipcc("IPCC: bad checksum"))
+        );
+    }
+
+    fn reply(seq: u64, cmd: u8, data: &[u8]) -> Rx {
+        Rx::Bytes(encode(seq | SEQ_REPLY, cmd, data))
+    }
+
+    #[test]
+    fn requests() {
+        let mut status = [0u8; 16];
+        status[0] = 1;
+        status[8] = 0x22;
+        let mut ipcc = Ipcc::new(fakes::console([
+            reply(1, reply::STATUS, &status),
+            reply(2, reply::KEY_LOOKUP, b"\0\x04\0pong"),
+            reply(3, reply::KEY_LOOKUP, b"\x01\0\0"),
+        ]));
+        assert_eq!(ipcc.status(), Ok((1, 0x22)));
+        let tx = fakes::transmitted();
+        let msg = decode(&tx[..tx.len() - 1]).unwrap();
+        assert_eq!(
+            msg,
+            Message { seq: 1, cmd: Request::Status as u8, data: vec![] }
+        );
+        assert_eq!(ipcc.key_lookup(0, 64), Ok(b"pong".to_vec()));
+        assert_eq!(
+            ipcc.key_lookup(9, 64),
+            Err(Error::Ipcc("IPCC: unknown key"))
+        );
+        assert!(fakes::exhausted());
+    }
+
+    #[test]
+    fn retries() {
+        let mut ipcc = Ipcc::new(fakes::console([
+            // A stale reply, then garbage, then silence.
+            reply(0, reply::BSU, &[1]),
+            Rx::bytes(&[2, 1, 0]),
+            Rx::Idle(TIMEOUT + Duration::from_millis(500)),
+            // The third attempt is answered.
+            reply(3, reply::BSU, &[1]),
+        ]));
+        assert_eq!(ipcc.bsu(), Ok(1));
+        assert!(fakes::exhausted());
+        let mut ipcc = Ipcc::new(fakes::console([
+            reply(1, reply::DECODE_FAIL, &[]),
+            reply(2, reply::DECODE_FAIL, &[]),
+            reply(3, reply::DECODE_FAIL, &[]),
+        ]));
+        assert_eq!(
+            ipcc.ack_start(),
+            Err(Error::Ipcc("IPCC: SP could not decode request"))
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright 2024  The Hypatia Authors
+// All rights reserved
+//
+// Use of this source code is governed by an MIT-style
+// license that can be found in the LICENSE file or at
+// https://opensource.org/licenses/MIT.
+
+//! The console line discipline.
+//!
+//! This is the line editor behind `cons::readline_timeout`,
+//! separated from the UART so that it can be tested: it is fed
+//! input a byte at a time, edits the line accordingly, and
+//! echoes to the terminal through the `Echo` trait.  It keeps
+//! track of the terminal column, so that tabs are expanded to
+//! the next tab stop and can be erased again.
+//!
+//! Lines are UTF-8.  A multi-byte character occupies a single
+//! column, and is erased as a unit.
+//!
+//! The line discipline also does the editing for completion: given
+//! the words that the last word of the line might be in full, it
+//! inserts what they share, or lists them.  What those words are
+//! is up to the caller.
+
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const BS: u8 = 8;
+const TAB: u8 = 9;
+const NL: u8 = 10;
+const CR: u8 = 13;
+const CTLU: u8 = 21;
+const CTLW: u8 = 23;
+const DEL: u8 = 127;
+
+/// Where the line discipline echoes to.
+pub(crate) trait Echo {
+    fn putb(&mut self, b: u8);
+}
+
+impl Echo for Uart {
+    fn putb(&mut self, b: u8) {
+        Uart::putb(self, b);
+    }
+}
+
+#[cfg(test)]
+impl Echo for Vec<u8> {
+    fn putb(&mut self, b: u8) {
+        self.push(b);
+    }
+}
+
+/// Returns true iff the byte continues a multi-byte UTF-8
+/// character, rather than starting one.
+fn is_continuation(b: u8) -> bool {
+    b & 0b1100_0000 == 0b1000_0000
+}
+
+/// Returns true iff the byte is part of a word, for the purposes
+/// of erasing words.  Non-ASCII characters are considered word
+/// characters.
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii()
+}
+
+fn backspace(echo: &mut impl Echo, overstrike: bool) {
+    echo.putb(BS);
+    if overstrike {
+        echo.putb(b' ');
+        echo.putb(BS);
+    }
+}
+
+/// Returns the length of the longest prefix that the two strings
+/// share, in bytes, ending on a character boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|&((_, ac), bc)| ac != bc)
+        .map_or(a.len().min(b.len()), |((k, _), _)| k)
+}
+
+/// Lists the candidates for completion on a line of their own.
+/// Paths are shown by their last component, as in a directory
+/// listing.
+pub(crate) fn list(words: &[String], echo: &mut impl Echo) {
+    echo.putb(CR);
+    echo.putb(NL);
+    for (k, word) in words.iter().enumerate() {
+        let dir = word.strip_suffix('/').unwrap_or(word);
+        let name = &word[dir.rfind('/').map_or(0, |k| k + 1)..];
+        if k > 0 {
+            echo.putb(b' ');
+            echo.putb(b' ');
+        }
+        name.bytes().for_each(|b| echo.putb(b));
+    }
+    echo.putb(CR);
+    echo.putb(NL);
+}
+
+/// A line being edited.
+pub(crate) struct LineDisc<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    /// The column at which the line starts, after the prompt.
+    start: usize,
+    /// The column of the cursor.
+    col: usize,
+}
+
+impl<'a> LineDisc<'a> {
+    /// Begins editing a line into the given buffer, after a
+    /// prompt occupying `start` columns.
+    pub(crate) fn new(buf: &'a mut [u8], start: usize) -> LineDisc<'a> {
+        LineDisc { buf, len: 0, start, col: start }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true iff the buffer is full, and so the line
+    /// must end.
+    pub(crate) fn is_full(&self) -> bool {
+        self.len == self.buf.len()
+    }
+
+    /// Returns the column of the cursor.
+    #[cfg(test)]
+    fn col(&self) -> usize {
+        self.col
+    }
+
+    /// Returns the column at which the character starting at
+    /// `end` would be displayed.
+    fn col_at(&self, end: usize) -> usize {
+        self.buf[..end].iter().fold(self.start, |col, &b| match b {
+            TAB => (col + 8) & !0b111,
+            b if is_continuation(b) => col,
+            _ => col + 1,
+        })
+    }
+
+    /// Returns the line so far.  A character that is not yet
+    /// complete is left off.
+    pub(crate) fn line(&self) -> &str {
+        let line = &self.buf[..self.len];
+        let len = match core::str::from_utf8(line) {
+            Ok(s) => s.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        unsafe { core::str::from_utf8_unchecked(&line[..len]) }
+    }
+
+    /// Completes the last word of the line, given the words it
+    /// might be in full, each of which begins with it.  Inserts
+    /// whatever they all share and, if there is just one, ends
+    /// the word with a space, unless it is a directory.  Returns
+    /// true iff there are several candidates and nothing to
+    /// insert, in which case the caller may list them.
+    pub(crate) fn complete(
+        &mut self,
+        words: &[String],
+        echo: &mut impl Echo,
+    ) -> bool {
+        let Some(first) = words.first() else {
+            return false;
+        };
+        let line = self.line();
+        let word = line.rsplit(|c: char| c.is_ascii_whitespace()).next();
+        let word = word.unwrap_or_default();
+        let common = words
+            .iter()
+            .fold(first.len(), |len, w| len.min(common_prefix(first, w)));
+        let mut insert = Vec::from(first.get(word.len()..common).unwrap_or(""));
+        if words.len() == 1 && !first.ends_with('/') {
+            insert.push(b' ');
+        }
+        let listing = words.len() > 1 && insert.is_empty();
+        for b in insert {
+            if self.is_full() {
+                break;
+            }
+            self.insert(b, echo);
+        }
+        listing
+    }
+
+    /// Draws the line again, as after listing candidates for
+    /// completion, following a prompt occupying `start` columns.
+    pub(crate) fn redraw(&mut self, start: usize, echo: &mut impl Echo) {
+        let len = self.len;
+        self.len = 0;
+        self.start = start;
+        self.col = start;
+        for k in 0..len {
+            self.insert(self.buf[k], echo);
+        }
+    }
+
+    /// Erases the last character, if any.
+    fn erase(&mut self, echo: &mut impl Echo) {
+        let Some(&last) = self.buf[..self.len].last() else {
+            return;
+        };
+        let mut len = self.len - 1;
+        while len > 0 && is_continuation(self.buf[len]) {
+            len -= 1;
+        }
+        let (col, overstrike) = match last {
+            b' ' => (self.col - 1, false),
+            TAB => (self.col_at(len), false),
+            _ => (self.col - 1, true),
+        };
+        for _ in col..self.col {
+            backspace(echo, overstrike);
+        }
+        self.col = col;
+        self.len = len;
+    }
+
+    /// Erases the last word, along with any whitespace after it.
+    /// A word is a run of either word characters or of other
+    /// non-whitespace characters.
+    fn erase_word(&mut self, echo: &mut impl Echo) {
+        let last = |ld: &LineDisc<'_>| ld.buf[..ld.len].last().copied();
+        while last(self).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.erase(echo);
+        }
+        let Some(b) = last(self) else {
+            return;
+        };
+        let word = is_word(b);
+        while last(self)
+            .is_some_and(|b| !b.is_ascii_whitespace() && is_word(b) == word)
+        {
+            self.erase(echo);
+        }
+    }
+
+    fn insert(&mut self, b: u8, echo: &mut impl Echo) {
+        self.buf[self.len] = b;
+        self.len += 1;
+        if b == TAB {
+            let col = (self.col + 8) & !0b111;
+            for _ in self.col..col {
+                echo.putb(b' ');
+            }
+            self.col = col;
+        } else {
+            echo.putb(b);
+            if !is_continuation(b) {
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Handles a byte of input, returning true iff it ends the
+    /// line.  Input is ignored once the buffer is full.
+    pub(crate) fn input(&mut self, b: u8, echo: &mut impl Echo) -> bool {
+        match b {
+            CR | NL => {
+                echo.putb(CR);
+                echo.putb(NL);
+                return true;
+            }
+            BS | DEL => self.erase(echo),
+            CTLU => {
+                while !self.is_empty() {
+                    self.erase(echo);
+                }
+            }
+            CTLW => self.erase_word(echo),
+            b if !self.is_full() => self.insert(b, echo),
+            _ => {}
+        }
+        false
+    }
+
+    /// Returns the line.  A character left incomplete, as when
+    /// the buffer filled in the midst of it, is dropped.
+    pub(crate) fn finish(self) -> Result<&'a str> {
+        let line = &self.buf[..self.len];
+        match core::str::from_utf8(line) {
+            Ok(s) => Ok(s),
+            Err(e) if e.error_len().is_none() => {
+                let line = &self.buf[..e.valid_up_to()];
+                Ok(unsafe { core::str::from_utf8_unchecked(line) })
+            }
+            Err(_) => Err(Error::Utf8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the input to a line discipline with a two column
+    /// prompt, returning the line, what was echoed, and the
+    /// final column.
+    fn edit(input: &[u8], len: usize) -> (Result<String>, Vec<u8>, usize) {
+        let mut buf = alloc::vec![0u8; len];
+        let mut echo = Vec::new();
+        let mut ld = LineDisc::new(&mut buf, 2);
+        for &b in input {
+            if ld.input(b, &mut echo) {
+                break;
+            }
+        }
+        let col = ld.col();
+        (ld.finish().map(String::from), echo, col)
+    }
+
+    #[test]
+    fn editing() {
+        let (line, echo, _) = edit(b"ab\x08c\r", 64);
+        assert_eq!(line.unwrap(), "ac");
+        assert_eq!(echo, b"ab\x08 \x08c\r\n");
+        let (line, ..) = edit(b"junk\x15foo bar\x17baz\n", 64);
+        assert_eq!(line.unwrap(), "foo baz");
+        let (line, ..) = edit(b"foo.bar  \x17x\n", 64);
+        assert_eq!(line.unwrap(), "foo.x");
+        let (line, ..) = edit(b"\x08\x17\x15ok\r", 64);
+        assert_eq!(line.unwrap(), "ok");
+        let (line, _, col) = edit(b"abcd", 3);
+        assert_eq!(line.unwrap(), "abc");
+        assert_eq!(col, 5);
+    }
+
+    #[test]
+    fn tabs() {
+        let (line, echo, col) = edit(b"a\t", 64);
+        assert_eq!(line.unwrap(), "a\t");
+        assert_eq!(echo, b"a     ");
+        assert_eq!(col, 8);
+        // Erasing a tab moves back to where it started, without
+        // overstriking.
+        let (line, echo, col) = edit(b"a\tb\x7f\x7f", 64);
+        assert_eq!(line.unwrap(), "a");
+        assert_eq!(echo, b"a     b\x08 \x08\x08\x08\x08\x08\x08");
+        assert_eq!(col, 3);
+        let (line, _, col) = edit(b"\t\t\x08", 64);
+        assert_eq!(line.unwrap(), "\t");
+        assert_eq!(col, 8);
+    }
+
+    #[test]
+    fn utf8() {
+        let (line, _, col) = edit("héllo\r".as_bytes(), 64);
+        assert_eq!(line.unwrap(), "héllo");
+        assert_eq!(col, 7);
+        // A multi-byte character is erased as a unit, and takes
+        // up a single column.
+        let (line, echo, col) = edit("aé\x08\r".as_bytes(), 64);
+        assert_eq!(line.unwrap(), "a");
+        assert_eq!(echo, "aé\x08 \x08\r\n".as_bytes());
+        assert_eq!(col, 3);
+        let (line, ..) = edit("a\t日本\x08\x08\x7f\r".as_bytes(), 64);
+        assert_eq!(line.unwrap(), "a");
+        let (line, ..) = edit("foo ünï\x17\r".as_bytes(), 64);
+        assert_eq!(line.unwrap(), "foo ");
+        // A character that does not fit is dropped.
+        let (line, ..) = edit("ab€".as_bytes(), 4);
+        assert_eq!(line.unwrap(), "ab");
+        let (line, ..) = edit(b"a\xffb\r", 64);
+        assert_eq!(line, Err(Error::Utf8));
+    }
+
+    /// Completes the line from the given candidates, returning
+    /// the line, what was echoed, and whether to list them.
+    fn complete(line: &str, words: &[&str]) -> (String, Vec<u8>, bool) {
+        let mut buf = alloc::vec![0u8; 64];
+        let mut echo = Vec::new();
+        let mut ld = LineDisc::new(&mut buf, 2);
+        line.bytes().for_each(|b| _ = ld.input(b, &mut echo));
+        echo.clear();
+        let words = words.iter().copied().map(String::from);
+        let listing = ld.complete(&words.collect::<Vec<_>>(), &mut echo);
+        (String::from(ld.line()), echo, listing)
+    }
+
+    #[test]
+    fn completion() {
+        let (line, echo, listing) = complete("inf", &["inflate"]);
+        assert_eq!(line, "inflate ");
+        assert_eq!(echo, b"late ");
+        assert!(!listing);
+        let (line, ..) = complete("cat /et", &["/etc/"]);
+        assert_eq!(line, "cat /etc/");
+        let (line, ..) = complete("sh", &["sha", "sha256"]);
+        assert_eq!(line, "sha");
+        let (line, echo, listing) = complete("s", &["sha", "smn"]);
+        assert_eq!(line, "s");
+        assert!(echo.is_empty());
+        assert!(listing);
+        let (line, _, listing) = complete("x", &[]);
+        assert_eq!(line, "x");
+        assert!(!listing);
+        let (line, ..) = complete("cat /é", &["/été/", "/étés"]);
+        assert_eq!(line, "cat /été");
+        let mut echo = Vec::new();
+        let words = ["/etc/system", "/etc/init.d/", "sha"].map(String::from);
+        list(&words, &mut echo);
+        assert_eq!(echo, b"\r\nsystem  init.d/  sha\r\n");
+    }
+
+    #[test]
+    fn redraw() {
+        let mut buf = alloc::vec![0u8; 64];
+        let mut echo = Vec::new();
+        let mut ld = LineDisc::new(&mut buf, 2);
+        b"a\tb".iter().for_each(|&b| _ = ld.input(b, &mut echo));
+        echo.clear();
+        ld.redraw(1, &mut echo);
+        assert_eq!(echo, b"a      b");
+        assert_eq!(ld.col(), 9);
+    }
+}
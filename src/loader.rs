@@ -7,29 +7,172 @@
 
 extern crate alloc;
 
+use crate::gzip;
 use crate::io::Read;
 use crate::mem;
 use crate::mmu::LoaderPageTable;
 use crate::println;
 use crate::ramdisk::File;
-use crate::result::{Error, Result};
+use crate::result::{Error, Result, ResultExt};
+use crate::symbols::Symbol;
+use crate::zstd;
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
 use core::ptr;
 use goblin::container::{Container, Ctx, Endian};
-use goblin::elf::ProgramHeader;
 use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::section_header::SHT_SYMTAB;
 use goblin::elf::{self, Elf};
+use goblin::elf::{ProgramHeader, SectionHeader, sym};
+use sha2::{Digest, Sha256};
+use static_assertions::const_assert;
 
 const PAGE_SIZE: usize = 4096;
 
+/// A loaded image: its entry point, and the virtual address
+/// ranges of its executable segments.
+pub(crate) struct Image {
+    pub(crate) entry: *const u8,
+    pub(crate) text: Vec<Range<u64>>,
+    /// The SHA-256 digest of each text range, taken from the file
+    /// it was loaded from, so that it can be checked before it is
+    /// run.
+    pub(crate) text_digests: Vec<[u8; 32]>,
+}
+
+/// An image loaded from the REPL, and what it was loaded from.
+pub(crate) struct Loaded {
+    pub(crate) source: String,
+    pub(crate) image: Image,
+}
+
+impl Loaded {
+    /// Returns true iff the address is within the image's text.
+    pub(crate) fn in_text(&self, addr: u64) -> bool {
+        self.image.text.iter().any(|text| text.contains(&addr))
+    }
+}
+
+/// Records a newly loaded image.  Any previously loaded image
+/// whose text the new image overlaps has been overwritten, and
+/// is forgotten.
+pub(crate) fn record(loaded: &mut Vec<Loaded>, source: &str, image: Image) {
+    let overlaps = |old: &Range<u64>| {
+        image.text.iter().any(|new| old.start < new.end && new.start < old.end)
+    };
+    loaded.retain(|old| !old.image.text.iter().any(overlaps));
+    loaded.push(Loaded { source: String::from(source), image });
+}
+
+/// The size of the buffer that parts of a file the loader skips
+/// over are read into to be measured.
+const MEASURE_CHUNK_LEN: usize = 1024;
+const_assert!(MEASURE_CHUNK_LEN <= mem::MAX_STACK_BUF);
+
+/// A file that is hashed as it is read, so that an image can be
+/// measured as it is loaded rather than read a second time.
+/// Reads that skip ahead hash the bytes skipped over first, and
+/// whatever is never read is hashed by `finish`.
+struct Measured<'a> {
+    file: &'a dyn Read,
+    sum: RefCell<Sha256>,
+    /// The length of the prefix of the file hashed so far.
+    hashed: Cell<usize>,
+}
+
+impl<'a> Measured<'a> {
+    fn new(file: &'a dyn Read) -> Measured<'a> {
+        Measured {
+            file,
+            sum: RefCell::new(Sha256::new()),
+            hashed: Cell::new(0),
+        }
+    }
+
+    /// Hashes the file up to `end`, reading whatever has not yet
+    /// been hashed.
+    fn hash_to(&self, end: usize) -> Result<()> {
+        let mut buf = [0u8; MEASURE_CHUNK_LEN];
+        while self.hashed.get() < end {
+            let off = self.hashed.get();
+            let len = usize::min(end - off, buf.len());
+            let n = self.file.read(off as u64, &mut buf[..len])?;
+            if n == 0 {
+                return Err(Error::FsRead);
+            }
+            self.sum.borrow_mut().update(&buf[..n]);
+            self.hashed.set(off + n);
+        }
+        Ok(())
+    }
+
+    /// Hashes the rest of the file, and returns its digest.
+    fn finish(self) -> Result<[u8; 32]> {
+        self.hash_to(self.file.size())?;
+        Ok(self.sum.into_inner().finalize().into())
+    }
+}
+
+impl Read for Measured<'_> {
+    fn read(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        let start = off as usize;
+        self.hash_to(usize::min(start, self.file.size()))?;
+        let n = self.file.read(off, dst)?;
+        let hashed = self.hashed.get();
+        if start <= hashed && hashed < start + n {
+            self.sum.borrow_mut().update(&dst[hashed - start..n]);
+            self.hashed.set(start + n);
+        }
+        Ok(n)
+    }
+
+    fn size(&self) -> usize {
+        self.file.size()
+    }
+}
+
 /// Loads an executable image contained in the given file
 /// creating virtual mappings as required.  Returns the image's
-/// ELF entry point on success.
+/// ELF entry point and text ranges on success, along with the
+/// SHA-256 digest of the file, which is taken as it is read.
+/// Gzip- and zstd-compressed images are decompressed as they
+/// are loaded.
 pub(crate) fn load_file(
     page_table: &mut LoaderPageTable,
     file: &dyn File,
-) -> Result<*const u8> {
-    let mut buf = [0u8; PAGE_SIZE];
+) -> Result<(Image, [u8; 32])> {
+    let file = Measured::new(file);
+    let image = with_image(&file, |image| load_image(page_table, image))?;
+    Ok((image, file.finish()?))
+}
+
+/// Calls `f` with a reader for the possibly compressed image
+/// contained in the given file.
+fn with_image<T>(
+    file: &dyn Read,
+    f: impl FnOnce(&dyn Read) -> Result<T>,
+) -> Result<T> {
+    match gzip::sniff(file)? {
+        gzip::Format::Plain => f(file),
+        gzip::Format::Gzip => {
+            println!("decompressing gzip image");
+            f(&gzip::Reader::new(file)?)
+        }
+        gzip::Format::Zstd => {
+            println!("decompressing zstd image");
+            f(&zstd::Reader::new(file)?)
+        }
+    }
+}
+
+fn load_image(
+    page_table: &mut LoaderPageTable,
+    file: &dyn Read,
+) -> Result<Image> {
+    let mut buf = vec![0u8; PAGE_SIZE];
     file.read(0, &mut buf).map_err(|_| Error::FsRead)?;
     let elf = parse_elf(&buf)?;
     load(page_table, &elf, file)
@@ -37,11 +180,11 @@ pub(crate) fn load_file(
 
 /// Loads an executable image contained in the given byte slice,
 /// creating virtual mappings as required.  Returns the image's
-/// ELF entry point on success.
+/// ELF entry point and text ranges on success.
 pub(crate) fn load_bytes(
     page_table: &mut LoaderPageTable,
     bytes: &[u8],
-) -> Result<*const u8> {
+) -> Result<Image> {
     let elf = parse_elf(bytes)?;
     load(page_table, &elf, &bytes)
 }
@@ -50,26 +193,51 @@ fn load(
     page_table: &mut LoaderPageTable,
     elf: &Elf<'_>,
     file: &dyn Read,
-) -> Result<*const u8> {
+) -> Result<Image> {
     let mut entry = ptr::null();
+    let mut text = Vec::new();
+    let mut text_digests = Vec::new();
     let elfentry = elf.entry.try_into().unwrap();
     for segment in elf.program_headers.iter().filter(|&h| h.p_type == PT_LOAD) {
         let file_range = segment.file_range();
         if file.size() < file_range.end {
-            return Err(Error::ElfTruncatedObj);
+            return Err(Error::ElfTruncatedObj.context(
+                "segment file range ends at",
+                &[file_range.end as u64],
+            ));
         }
-        let (base, len) = load_segment(page_table, segment, file)?;
+        let (base, len) = load_segment(page_table, segment, file).context(
+            "segment vaddr,memsz",
+            &[segment.p_vaddr, segment.p_memsz],
+        )?;
         let addr = base.addr();
         let mem_range = addr..addr + len;
         if mem_range.contains(&elfentry) {
             entry = base.with_addr(elfentry);
         }
+        if segment.is_executable() {
+            let filesz = segment.p_filesz as usize;
+            let digest = file_digest(file, segment.p_offset, filesz, len)?;
+            let loaded = unsafe { core::slice::from_raw_parts(base, len) };
+            if Sha256::digest(loaded)[..] != digest[..] {
+                return Err(Error::Verify.context(
+                    "text segment vaddr,memsz",
+                    &[segment.p_vaddr, segment.p_memsz],
+                ));
+            }
+            text.push(addr as u64..(addr + len) as u64);
+            text_digests.push(digest);
+        }
     }
-    Ok(entry)
+    Ok(Image { entry, text, text_digests })
 }
 
 pub(crate) fn elfinfo(file: &dyn File) -> Result<()> {
-    let mut buf = [0u8; PAGE_SIZE];
+    with_image(file, elfinfo_image)
+}
+
+fn elfinfo_image(file: &dyn Read) -> Result<()> {
+    let mut buf = vec![0u8; PAGE_SIZE];
     file.read(0, &mut buf).map_err(|_| Error::FsRead)?;
     let elf = parse_elf(&buf)?;
     println!("ELF header (version {}):", elf.header.e_version);
@@ -106,6 +274,71 @@ pub(crate) fn elfinfo(file: &dyn File) -> Result<()> {
     Ok(())
 }
 
+/// Reads the function, object, and untyped symbols from the
+/// symbol table of the image contained in the given file.
+pub(crate) fn symbols(file: &dyn File) -> Result<Vec<Symbol>> {
+    with_image(file, symbols_image)
+}
+
+fn symbols_image(file: &dyn Read) -> Result<Vec<Symbol>> {
+    let read = |offset: u64, len: u64| -> Result<Vec<u8>> {
+        let end = offset.checked_add(len).ok_or(Error::ElfTruncatedObj)?;
+        if end > file.size() as u64 {
+            return Err(Error::ElfTruncatedObj);
+        }
+        let mut buf = vec![0u8; len as usize];
+        file.read(offset, &mut buf).map_err(|_| Error::FsRead)?;
+        Ok(buf)
+    };
+    let header = read(0, PAGE_SIZE.min(file.size()) as u64)?;
+    let header = parse_header(&header)?;
+    let container = header.container().map_err(|_| Error::ElfContainer)?;
+    let endian = header.endianness().map_err(|_| Error::ElfEndian)?;
+    let ctx = Ctx::new(container, endian);
+    let nsections = u64::from(header.e_shnum);
+    if nsections == 0 {
+        return Err(Error::ElfNoSymbols);
+    }
+    let len = nsections * u64::from(header.e_shentsize);
+    let sections = read(header.e_shoff, len)?;
+    let sections =
+        SectionHeader::parse_from(&sections, 0, nsections as usize, ctx)
+            .map_err(|_| Error::ElfParseSHeader)?;
+    let symtab = sections
+        .iter()
+        .find(|sh| sh.sh_type == SHT_SYMTAB)
+        .ok_or(Error::ElfNoSymbols)?;
+    let strtab =
+        sections.get(symtab.sh_link as usize).ok_or(Error::ElfParseSHeader)?;
+    let syms = read(symtab.sh_offset, symtab.sh_size)?;
+    let count = syms.len() / sym::Sym::size(container);
+    let syms = sym::Sym::parse(&syms, 0, count, ctx)
+        .map_err(|_| Error::ElfParseObject)?;
+    let strings = read(strtab.sh_offset, strtab.sh_size)?;
+    let name = |offset: usize| {
+        let s = strings.get(offset..)?;
+        let s = &s[..s.iter().position(|&b| b == 0)?];
+        core::str::from_utf8(s).ok().filter(|s| !s.is_empty())
+    };
+    Ok(syms
+        .iter()
+        .filter(|sym| {
+            matches!(
+                sym.st_type(),
+                sym::STT_FUNC | sym::STT_OBJECT | sym::STT_NOTYPE
+            ) && sym.st_shndx != 0
+                && sym.st_value != 0
+        })
+        .filter_map(|sym| {
+            Some(Symbol {
+                name: String::from(name(sym.st_name)?),
+                addr: sym.st_value,
+                size: sym.st_size,
+            })
+        })
+        .collect())
+}
+
 /// Parses the ELF executable contained in the given byte slice.
 fn parse_elf(bytes: &[u8]) -> Result<Elf<'_>> {
     let header = parse_header(bytes)?;
@@ -116,7 +349,10 @@ fn parse_elf(bytes: &[u8]) -> Result<Elf<'_>> {
 
 /// Parses and validates the ELF header from the given byte
 /// slice.  Note that much of the heavy lifting of validating
-/// the ELF header is done by the parsing library.
+/// the ELF header is done by the parsing library.  Only ELF64
+/// images are accepted; ELF32 images, whose headers and tables
+/// are laid out differently, are rejected here rather than
+/// misread later.
 fn parse_header(bytes: &[u8]) -> Result<elf::Header> {
     let binary = Elf::parse_header(bytes).map_err(|_| Error::ElfParseHeader)?;
     if binary.e_machine != elf::header::EM_X86_64 {
@@ -218,3 +454,173 @@ fn load_segment<T: Read + ?Sized>(
     }
     Ok((p, len))
 }
+
+/// Returns the SHA-256 digest of what a segment of `len` bytes
+/// should hold once loaded: the `filesz` bytes at `offset` in
+/// the file, followed by zeros.  This is read from the file
+/// itself, rather than from memory, so that a load that went
+/// wrong is not taken as the reference.
+fn file_digest<T: Read + ?Sized>(
+    file: &T,
+    offset: u64,
+    filesz: usize,
+    len: usize,
+) -> Result<[u8; 32]> {
+    let mut sum = Sha256::new();
+    let mut buf = [0u8; PAGE_SIZE];
+    let ncp = usize::min(filesz, len);
+    let mut done = 0;
+    while done < ncp {
+        let n = usize::min(ncp - done, buf.len());
+        let pos = offset + done as u64;
+        if file.read(pos, &mut buf[..n])? != n {
+            return Err(Error::ElfTruncatedObj);
+        }
+        sum.update(&buf[..n]);
+        done += n;
+    }
+    buf.fill(0);
+    while done < len {
+        let n = usize::min(len - done, buf.len());
+        sum.update(&buf[..n]);
+        done += n;
+    }
+    Ok(sum.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_ranges() {
+        let image = |text: &[Range<u64>]| Image {
+            entry: ptr::null(),
+            text: Vec::from(text),
+            text_digests: vec![[0; 32]; text.len()],
+        };
+        let mut loaded = Vec::new();
+        record(&mut loaded, "a", image(&[0x1000..0x3000, 0x8000..0x9000]));
+        record(&mut loaded, "b", image(&[0xa000..0xb000, 0xc000..0xd000]));
+        assert!(loaded[0].in_text(0x8fff));
+        assert!(!loaded[0].in_text(0x9000));
+        record(&mut loaded, "c", image(&[0x2000..0x4000, 0xe000..0xf000]));
+        let sources = loaded.iter().map(|l| l.source.as_str());
+        assert_eq!(sources.collect::<Vec<_>>(), ["b", "c"]);
+    }
+
+    #[test]
+    fn file_digests() {
+        let file = (0..0x1800u32).map(|k| k as u8).collect::<Vec<_>>();
+        let mut loaded = vec![0u8; 0x3000];
+        loaded[..0x1700].copy_from_slice(&file[0x100..]);
+        let digest = file_digest(&file.as_slice(), 0x100, 0x1700, 0x3000);
+        assert_eq!(digest.unwrap()[..], Sha256::digest(&loaded)[..]);
+        let short = file_digest(&file.as_slice(), 0x100, 0x1800, 0x3000);
+        assert!(matches!(short, Err(Error::ElfTruncatedObj)));
+    }
+
+    #[test]
+    fn measured_reads() {
+        let data = (0..5000u32).map(|k| (k * 7) as u8).collect::<Vec<_>>();
+        let file = data.as_slice();
+        let measured = Measured::new(&file);
+        let mut buf = [0u8; 100];
+        assert_eq!(measured.read(0, &mut buf).unwrap(), 100);
+        assert_eq!(measured.read(3000, &mut buf).unwrap(), 100);
+        assert_eq!(&buf[..], &data[3000..3100]);
+        assert_eq!(measured.read(50, &mut buf).unwrap(), 100);
+        assert_eq!(measured.read(4950, &mut buf).unwrap(), 50);
+        assert_eq!(measured.read(9000, &mut buf).unwrap(), 0);
+        assert_eq!(measured.hashed.get(), data.len());
+        let digest = measured.finish().unwrap();
+        assert_eq!(digest[..], Sha256::digest(&data)[..]);
+        let unread = Measured::new(&file).finish().unwrap();
+        assert_eq!(unread, digest);
+    }
+
+    /// Builds an executable with no segments, and a symbol table
+    /// holding the given symbols, each a name, type, section
+    /// index, value, and size.
+    fn elf_with_symbols(syms: &[(&str, u8, u16, u64, u64)]) -> Vec<u8> {
+        const EHSIZE: usize = 64;
+        const SHENTSIZE: usize = 64;
+        const SYMENTSIZE: usize = 24;
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; SYMENTSIZE];
+        for &(name, typ, shndx, value, size) in syms {
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            symtab.extend_from_slice(&[0x10 | typ, 0]);
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+        let symoff = EHSIZE;
+        let stroff = symoff + symtab.len();
+        let shoff = stroff + strtab.len();
+
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01");
+        elf.resize(16, 0);
+        elf.extend_from_slice(&elf::header::ET_EXEC.to_le_bytes());
+        elf.extend_from_slice(&elf::header::EM_X86_64.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&0x1000u64.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes());
+        elf.extend_from_slice(&(shoff as u64).to_le_bytes());
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for half in [EHSIZE, 56, 0, SHENTSIZE, 3, 0] {
+            elf.extend_from_slice(&(half as u16).to_le_bytes());
+        }
+        elf.extend_from_slice(&symtab);
+        elf.extend_from_slice(&strtab);
+        elf.extend_from_slice(&[0; SHENTSIZE]);
+        let sections = [
+            (SHT_SYMTAB, symoff, symtab.len(), 2, SYMENTSIZE),
+            (elf::section_header::SHT_STRTAB, stroff, strtab.len(), 0, 0),
+        ];
+        for (typ, offset, size, link, entsize) in sections {
+            let mut sh = [0u8; SHENTSIZE];
+            sh[4..8].copy_from_slice(&typ.to_le_bytes());
+            sh[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            sh[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            sh[40..44].copy_from_slice(&(link as u32).to_le_bytes());
+            sh[56..64].copy_from_slice(&(entsize as u64).to_le_bytes());
+            elf.extend_from_slice(&sh);
+        }
+        elf
+    }
+
+    #[test]
+    fn symbol_tables() {
+        let elf = elf_with_symbols(&[
+            ("main", sym::STT_FUNC, 1, 0x1000, 0x40),
+            ("unix.c", sym::STT_FILE, 0xfff1, 0, 0),
+            ("panicstr", sym::STT_OBJECT, 2, 0x2000, 8),
+            ("undefined", sym::STT_NOTYPE, 0, 0, 0),
+            ("_start", sym::STT_NOTYPE, 1, 0x800, 0),
+        ]);
+        let syms = symbols_image(&elf.as_slice()).expect("symbols");
+        let names = syms.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["main", "panicstr", "_start"]);
+        assert_eq!(
+            syms[0],
+            Symbol { name: "main".into(), addr: 0x1000, size: 0x40 }
+        );
+
+        let mut stripped = elf.clone();
+        stripped[60..62].copy_from_slice(&0u16.to_le_bytes());
+        let err = symbols_image(&stripped.as_slice());
+        assert_eq!(err.map(|_| ()), Err(Error::ElfNoSymbols));
+        let err = symbols_image(&&elf[..elf.len() - 8]);
+        assert_eq!(err.map(|_| ()), Err(Error::ElfTruncatedObj));
+
+        let mut elf32 = elf.clone();
+        elf32[elf::header::EI_CLASS] = elf::header::ELFCLASS32;
+        let err = symbols_image(&elf32.as_slice());
+        assert_eq!(err.map(|_| ()), Err(Error::ElfContainer64));
+        assert_eq!(parse_elf(&elf32).err(), Some(Error::ElfContainer64));
+    }
+}
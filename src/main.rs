@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+#![cfg_attr(not(any(test, clippy)), feature(alloc_error_handler))]
 #![feature(allocator_api)]
 #![feature(sync_unsafe_cell)]
 #![cfg_attr(not(any(test, clippy)), no_std)]
@@ -11,31 +12,55 @@
 extern crate alloc;
 
 mod allocator;
+mod banner;
+mod beacon;
 mod bldb;
+mod board;
 mod clock;
 mod cons;
 mod cpio;
 mod cpuid;
+mod crc32;
+mod emergency;
+mod entropy;
+mod ext2;
+mod fat;
 mod gpio;
+mod gzip;
+mod hw;
+mod ident;
 mod idt;
 mod io;
 mod iomux;
+mod ipcc;
+mod linedisc;
 mod loader;
 mod mem;
 mod mmu;
 mod pci;
+mod physmap;
+mod post;
 mod ramdisk;
+mod regdefs;
 mod repl;
 mod result;
+mod sink;
 mod smn;
+#[cfg(feature = "cmd-bench")]
+mod smu;
+mod symbols;
+mod tpm;
 mod uart;
 mod ufs;
+mod wdt;
+mod zstd;
 
 /// The main entry point, called from assembler.
 #[unsafe(no_mangle)]
 pub(crate) extern "C" fn entry(config: &mut bldb::Config) {
-    println!();
-    println!("Oxide Boot Loader/Debugger");
+    emergency::arm(config);
+    banner::print(config);
+    post::post(post::Code::ReplReady);
     println!("{config:#x?}");
     repl::run(config);
     panic!("main returning");
@@ -45,11 +70,48 @@ mod no_std {
     #[cfg(not(any(test, clippy)))]
     #[panic_handler]
     pub fn panic(info: &core::panic::PanicInfo) -> ! {
-        crate::println!("Panic: {:#?}", info);
+        report(info);
         unsafe {
             crate::bldb::dnr();
         }
     }
+
+    /// Rather than panic when the heap is exhausted, enter a
+    /// REPL that does not need it.
+    #[cfg(not(any(test, clippy)))]
+    #[alloc_error_handler]
+    fn oom(layout: core::alloc::Layout) -> ! {
+        crate::emergency::repl(layout)
+    }
+
+    /// Reports a panic to every diagnostic sink we can reach.
+    #[cfg_attr(any(test, clippy), allow(dead_code))]
+    fn report(info: &core::panic::PanicInfo) {
+        use core::fmt::Write;
+        crate::post::post(crate::post::Code::Panic);
+        if crate::uart::cons_inited() {
+            crate::sink::emit(
+                crate::sink::Level::Error,
+                format_args!("Panic: {info:#?}\n"),
+            );
+        }
+        // The console may be broken or not yet initialized, so
+        // also try the secondary UART, unless it is in use.
+        match unsafe { crate::uart::fallback() } {
+            Some(mut fallback) => {
+                let _ = writeln!(fallback, "Panic: {:#?}", info);
+            }
+            // With neither UART, leave a distinct POST code, and
+            // the report in the in-memory log for a debugger.
+            None if !crate::uart::cons_inited() => {
+                crate::post::post(crate::post::Code::PanicUnreported);
+                crate::sink::log(format_args!(
+                    "Panic: {info:#?}\nfallback UART unavailable\n"
+                ));
+            }
+            None => {}
+        }
+    }
 }
 #[cfg(test)]
 mod fakes;
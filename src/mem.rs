@@ -9,6 +9,18 @@ pub(crate) const KIB: usize = 1024;
 pub(crate) const MIB: usize = 1024 * KIB;
 pub(crate) const GIB: usize = 1024 * MIB;
 
+/// The size of the loader's stack, which is reserved in the BSS
+/// by start.S and must agree with `STACK_SIZE` there.  The stack
+/// cannot grow, and overflowing it silently corrupts whatever
+/// lies below it, so large buffers and state machines belong on
+/// the heap or in statically reserved storage.
+pub(crate) const STACK_SIZE: usize = 32 * V4KA::SIZE;
+
+/// The largest buffer that a function should place on the
+/// stack.  Buffers sized by a constant should assert that they
+/// fit.
+pub(crate) const MAX_STACK_BUF: usize = STACK_SIZE / 64;
+
 /// A V4KA represents a 4KiB aligned, canonical virtual memory
 /// address.  The address may or may not be mapped.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd)]
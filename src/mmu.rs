@@ -593,6 +593,11 @@ trait Table: Sized {
     /// reflecting that the region covered by the table starts
     /// at the given base address.
     fn dump(&self, base_addr: usize);
+
+    /// Calls `f` with the address, entry, and size of each page
+    /// mapped through this table, which covers the region that
+    /// starts at the given base address.
+    fn walk(&self, base_addr: usize, f: &mut dyn FnMut(usize, PTE, usize));
 }
 
 /// Interior table types in the radix tree implement this trait
@@ -704,6 +709,17 @@ impl Table for PML4 {
             }
         }
     }
+
+    fn walk(&self, base_addr: usize, f: &mut dyn FnMut(usize, PTE, usize)) {
+        for (k, entry) in self.entries.iter().enumerate() {
+            if entry.p() {
+                let addr = base_addr + k * Self::entry_stride();
+                let ptr = ptr::with_exposed_provenance(addr);
+                let next = self.next(ptr).expect("mapped has next");
+                next.walk(addr, f);
+            }
+        }
+    }
 }
 
 /// The PML3 is the second highest level in the paging radix
@@ -828,6 +844,19 @@ impl Table for PML3 {
             }
         }
     }
+
+    fn walk(&self, base_addr: usize, f: &mut dyn FnMut(usize, PTE, usize)) {
+        for (k, entry) in self.entries.iter().enumerate() {
+            let addr = base_addr + k * Self::entry_stride();
+            if entry.p() && !entry.h() {
+                let ptr = ptr::with_exposed_provenance(addr);
+                let next = self.next(ptr).expect("mapped has next");
+                next.walk(addr, f);
+            } else if entry.p() {
+                f(addr, *entry, Self::entry_stride());
+            }
+        }
+    }
 }
 
 /// The PML2 is the third-highest type of table in the paging
@@ -951,6 +980,19 @@ impl Table for PML2 {
             }
         }
     }
+
+    fn walk(&self, base_addr: usize, f: &mut dyn FnMut(usize, PTE, usize)) {
+        for (k, entry) in self.entries.iter().enumerate() {
+            let addr = base_addr + k * Self::entry_stride();
+            if entry.p() && !entry.h() {
+                let ptr = ptr::with_exposed_provenance(addr);
+                let next = self.next(ptr).expect("mapped has next");
+                next.walk(addr, f);
+            } else if entry.p() {
+                f(addr, *entry, Self::entry_stride());
+            }
+        }
+    }
 }
 
 /// The PML1 represents a terminal leaf note in the paging radix
@@ -1017,6 +1059,15 @@ impl Table for PML1 {
             }
         }
     }
+
+    fn walk(&self, base_addr: usize, f: &mut dyn FnMut(usize, PTE, usize)) {
+        for (k, entry) in self.entries.iter().enumerate() {
+            if entry.p() {
+                let addr = base_addr + k * Self::entry_stride();
+                f(addr, *entry, Self::entry_stride());
+            }
+        }
+    }
 }
 
 /// Represents a complete page table.
@@ -1178,6 +1229,34 @@ impl PageTable {
         }
     }
 
+    /// Returns true iff `unmap_range` would succeed on the given
+    /// range: that is, iff each page it would remove is mapped,
+    /// with the size it expects.
+    fn is_range_unmappable(&self, range: &Range<mem::V4KA>) -> bool {
+        let mut start = range.start.addr();
+        let end = range.end.addr();
+        while start != end {
+            let rest = end.wrapping_sub(start);
+            let Some(len) = [PFN1G::SIZE, PFN2M::SIZE, PFN4K::SIZE]
+                .into_iter()
+                .find(|&len| rest >= len && start.is_multiple_of(len))
+            else {
+                return false;
+            };
+            let mapped = match self.lookup(ptr::without_provenance(start)) {
+                Some(EntryParts::Entry1G(_, _)) => PFN1G::SIZE,
+                Some(EntryParts::Entry2M(_, _)) => PFN2M::SIZE,
+                Some(EntryParts::Entry4K(_, _)) => PFN4K::SIZE,
+                None => return false,
+            };
+            if mapped != len {
+                return false;
+            }
+            start = start.wrapping_add(len);
+        }
+        true
+    }
+
     /// Returns true iff a single region of virtual address space is currently
     /// mapped with the given permissions.  Supports mapping at the end of the
     /// address range.
@@ -1225,7 +1304,7 @@ impl PageTable {
         }
         let ptr = ptr::without_provenance::<()>(va);
         let Some(_entry) = self.pml4.lookup(ptr) else {
-            return Err(Error::Unmapped);
+            return Err(Error::Unmapped.context("address", &[va as u64]));
         };
         let ptr = core::ptr::with_exposed_provenance_mut::<()>(va);
         if !ptr.cast::<T>().is_aligned() {
@@ -1414,14 +1493,71 @@ pub(crate) enum Entry {
     Page4K(PTE),
 }
 
+impl Entry {
+    /// Returns the attributes of the mapping.
+    pub(crate) fn attrs(&self) -> mem::Attrs {
+        match self {
+            Entry::Page1G(pte) | Entry::Page2M(pte) | Entry::Page4K(pte) => {
+                pte.attrs()
+            }
+        }
+    }
+
+    /// Returns the size of the page mapped by this entry.
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Entry::Page1G(_) => PFN1G::SIZE,
+            Entry::Page2M(_) => PFN2M::SIZE,
+            Entry::Page4K(_) => PFN4K::SIZE,
+        }
+    }
+}
+
+/// A handle to a buffer in mapped virtual memory, such as the
+/// result of a transfer or decompression.
+///
+/// Buffers outlive the commands that create them, and the
+/// mappings underneath them may be removed or changed in the
+/// meanwhile.  So that use of such a buffer is reported rather
+/// than silently reading garbage (or faulting), a handle
+/// records the generation of the page table when it was
+/// created, along with the physical addresses backing its first
+/// and last bytes, and is revalidated when resolved.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Buf {
+    ptr: *const u8,
+    len: usize,
+    generation: u64,
+    pas: Option<(u64, u64)>,
+}
+
+impl Buf {
+    pub(crate) fn addr(&self) -> usize {
+        self.ptr.addr()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
 /// A LoaderPageTable is a newtype around a PageTable that
 /// prohibits some types of mappings.  In particular, it
 /// maintains a list of regions that the consumer cannot
 /// creating mappings in.
+///
+/// The table also maintains a generation number, which is
+/// incremented whenever mappings are created, changed or
+/// removed, and is used to detect stale buffer handles.
 pub(crate) struct LoaderPageTable {
     page_table: &'static mut PageTable,
     reserved: Vec<Range<mem::V4KA>>,
     mmio: Vec<Range<mem::V4KA>>,
+    generation: u64,
 }
 
 impl LoaderPageTable {
@@ -1433,7 +1569,22 @@ impl LoaderPageTable {
     ) -> LoaderPageTable {
         let reserved = reserved.into();
         let mmio = mmio.into();
-        LoaderPageTable { page_table, reserved, mmio }
+        LoaderPageTable { page_table, reserved, mmio, generation: 0 }
+    }
+
+    /// Returns the current generation of the page table.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the regions in which mappings may not be created.
+    pub(crate) fn reserved(&self) -> &[Range<mem::V4KA>] {
+        &self.reserved
+    }
+
+    /// Returns the MMIO regions.
+    pub(crate) fn mmio(&self) -> &[Range<mem::V4KA>] {
+        &self.mmio
     }
 
     /// Maps the given virtual region to the given physical
@@ -1444,8 +1595,10 @@ impl LoaderPageTable {
         attrs: mem::Attrs,
         pa: mem::P4KA,
     ) -> Result<()> {
+        let span = [range.start.addr() as u64, range.end.addr() as u64];
         if Self::overlaps(&self.reserved, &range) {
-            return Err(Error::Mmu("range overlaps reserved regions"));
+            return Err(Error::Mmu("range overlaps reserved regions")
+                .context("virtual range", &span));
         }
         let len = range.end.addr().wrapping_sub(range.start.addr());
         let phys_addr = pa.phys_addr() as usize;
@@ -1453,9 +1606,14 @@ impl LoaderPageTable {
         let pend = mem::V4KA::new(phys_addr.wrapping_add(len));
         let prange = pstart..pend;
         if Self::overlaps(&self.reserved, &prange) {
-            return Err(Error::Mmu("physical range overlaps reserved regions"));
+            return Err(Error::Mmu("physical range overlaps reserved regions")
+                .context(
+                    "physical range",
+                    &[pstart.addr() as u64, pend.addr() as u64],
+                ));
         }
         let region = mem::Region::new(range, attrs);
+        self.generation += 1;
         unsafe {
             self.page_table.map_region(&region, pa);
         }
@@ -1472,7 +1630,11 @@ impl LoaderPageTable {
         pa: mem::P4KA,
     ) -> Result<()> {
         if Self::overlaps(&self.mmio, &range) {
-            return Err(Error::Mmu("RAM allocation overlaps MMIO region"));
+            return Err(Error::Mmu("RAM allocation overlaps MMIO region")
+                .context(
+                    "virtual range",
+                    &[range.start.addr() as u64, range.end.addr() as u64],
+                ));
         }
         unsafe { self.map_region(range, attrs, pa) }
     }
@@ -1482,11 +1644,88 @@ impl LoaderPageTable {
         range: Range<mem::V4KA>,
     ) -> Result<()> {
         if Self::overlaps(&self.reserved, &range) {
-            return Err(Error::Mmu("unmap: range overlaps reserved regions"));
+            return Err(Error::Mmu("unmap: range overlaps reserved regions")
+                .context(
+                    "virtual range",
+                    &[range.start.addr() as u64, range.end.addr() as u64],
+                ));
         }
+        self.generation += 1;
         unsafe { self.page_table.unmap_range(&range) }
     }
 
+    /// Replaces the reserved regions in `old` with those in
+    /// `new`, unmapping the former and identity mapping the
+    /// latter with the given attributes.  The new regions may
+    /// overlap the old, but not each other, nor any other
+    /// reserved or MMIO region.  Everything is checked, and the
+    /// new set of reservations built, before anything changes,
+    /// so that on error the table is as it was.
+    pub(crate) unsafe fn replace_reserved(
+        &mut self,
+        old: &[Range<mem::V4KA>],
+        new: &[Range<mem::V4KA>],
+        attrs: mem::Attrs,
+    ) -> Result<()> {
+        let span = |range: &Range<mem::V4KA>| {
+            [range.start.addr() as u64, range.end.addr() as u64]
+        };
+        for (k, range) in old.iter().enumerate() {
+            if !self.reserved.contains(range) {
+                return Err(Error::Mmu("replace: region is not reserved")
+                    .context("virtual range", &span(range)));
+            }
+            if Self::overlaps(&old[..k], range) {
+                return Err(Error::Mmu("replace: regions overlap")
+                    .context("virtual range", &span(range)));
+            }
+            if !self.page_table.is_range_unmappable(range) {
+                return Err(Error::Mmu("replace: region is not mapped")
+                    .context("virtual range", &span(range)));
+            }
+        }
+        let others = self
+            .reserved
+            .iter()
+            .filter(|range| !old.contains(range))
+            .cloned()
+            .collect::<Vec<_>>();
+        for (k, range) in new.iter().enumerate() {
+            if range.start.addr() >= range.end.addr() {
+                return Err(Error::Mmu("replace: empty region")
+                    .context("virtual range", &span(range)));
+            }
+            if Self::overlaps(&others, range)
+                || Self::overlaps(&self.mmio, range)
+            {
+                return Err(Error::Mmu(
+                    "replace: range overlaps reserved regions",
+                )
+                .context("virtual range", &span(range)));
+            }
+            if Self::overlaps(&new[..k], range) {
+                return Err(Error::Mmu("replace: regions overlap")
+                    .context("virtual range", &span(range)));
+            }
+        }
+        let mut reserved = others;
+        reserved.extend_from_slice(new);
+        self.generation += 1;
+        for range in old {
+            unsafe { self.page_table.unmap_range(range) }
+                .expect("checked mapped");
+        }
+        for range in new {
+            let pa = mem::P4KA::new(range.start.addr() as u64);
+            let region = mem::Region::new(range.clone(), attrs);
+            unsafe {
+                self.page_table.map_region(&region, pa);
+            }
+        }
+        self.reserved = reserved;
+        Ok(())
+    }
+
     /// Returns the page table entry for the given virtual address, if it is
     /// mapped in this address space.
     pub(crate) fn lookup(&self, va: *const ()) -> Option<Entry> {
@@ -1503,6 +1742,68 @@ impl LoaderPageTable {
         })
     }
 
+    /// Returns the physical address that the given virtual
+    /// address is mapped to, if any.
+    pub(crate) fn translate(&self, va: usize) -> Option<u64> {
+        let va_ptr = ptr::without_provenance(va);
+        let (base, size) = match self.page_table.lookup(va_ptr)? {
+            EntryParts::Entry4K(pfn, _) => (pfn.phys_addr(), PFN4K::SIZE),
+            EntryParts::Entry2M(pfn, _) => (pfn.phys_addr(), PFN2M::SIZE),
+            EntryParts::Entry1G(pfn, _) => (pfn.phys_addr(), PFN1G::SIZE),
+        };
+        Some(base + (va & (size - 1)) as u64)
+    }
+
+    /// Returns the physical addresses backing the first and last
+    /// bytes of the given buffer, if it is non-empty and mapped.
+    fn buf_pas(&self, ptr: *const u8, len: usize) -> Option<(u64, u64)> {
+        let last = ptr.addr().checked_add(len.checked_sub(1)?)?;
+        Some((self.translate(ptr.addr())?, self.translate(last)?))
+    }
+
+    /// Creates a handle for the given buffer, which must be
+    /// mapped in this table.
+    pub(crate) fn buf(&self, bs: &[u8]) -> Buf {
+        let ptr = bs.as_ptr();
+        let len = bs.len();
+        let pas = self.buf_pas(ptr, len);
+        Buf { ptr, len, generation: self.generation, pas }
+    }
+
+    /// Returns the slice described by a buffer handle.  If the
+    /// table has changed since the handle was created, the
+    /// buffer is revalidated: it must still be readable, and
+    /// still backed by the same physical memory, or it is
+    /// reported as stale.
+    pub(crate) fn resolve(&self, buf: &Buf) -> Result<&'static [u8]> {
+        if buf.generation != self.generation {
+            let range = mem::page_range_raw(buf.ptr.cast(), buf.len);
+            if !self.is_region_readable(range)
+                || self.buf_pas(buf.ptr, buf.len) != buf.pas
+            {
+                return Err(Error::StaleBuf.context(
+                    "buffer addr,len",
+                    &[buf.addr() as u64, buf.len as u64],
+                ));
+            }
+        }
+        Ok(unsafe { core::slice::from_raw_parts(buf.ptr, buf.len) })
+    }
+
+    /// Returns the slice described by a buffer handle, which
+    /// must also be writable, mutably.  Like any other writable
+    /// memory named in the REPL, the slice is made from the
+    /// buffer's address.
+    pub(crate) fn resolve_mut(&self, buf: &Buf) -> Result<&'static mut [u8]> {
+        self.resolve(buf)?;
+        let range = mem::page_range_raw(buf.ptr.cast(), buf.len);
+        if !self.is_region_writeable(range) {
+            return Err(Error::Unmapped);
+        }
+        let ptr = ptr::with_exposed_provenance_mut(buf.addr());
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr, buf.len) })
+    }
+
     /// Returns true iff the entire region `a` is currently
     /// mapped with the given privileges.
     pub(crate) fn is_region_mapped(
@@ -1555,6 +1856,12 @@ impl LoaderPageTable {
         println!("Root (PML4): {root:#x}", root = self.phys_addr());
         self.page_table.pml4.dump(0);
     }
+
+    /// Calls `f` with the virtual address, entry, and size of
+    /// each page mapped by the table.
+    pub(crate) fn walk(&self, mut f: impl FnMut(usize, PTE, usize)) {
+        self.page_table.pml4.walk(0, &mut f);
+    }
 }
 
 #[cfg(test)]
@@ -1617,6 +1924,139 @@ mod loader_page_table_tests {
         let range = mem::page_range_raw(ptr, 20);
         assert!(loader_page_table.is_region_readable(range));
     }
+
+    #[test]
+    fn walk_pages() {
+        let page_table = PageTable::new();
+        let mut loader_page_table = LoaderPageTable::new(page_table, &[], &[]);
+        let region = mem::V4KA::new(0x8000)..mem::V4KA::new(0xa000);
+        unsafe {
+            loader_page_table
+                .map_region(
+                    region,
+                    mem::Attrs::new_data(),
+                    mem::P4KA::new(0x8000),
+                )
+                .unwrap();
+        }
+        let mut pages = Vec::new();
+        loader_page_table
+            .walk(|va, pte, size| pages.push((va, pte.phys_addr(), size)));
+        assert_eq!(pages, [(0x8000, 0x8000, 4096), (0x9000, 0x9000, 4096)]);
+    }
+
+    #[test]
+    fn replace_reserved_regions() {
+        let page_table = PageTable::new();
+        let xfer = mem::V4KA::new(0x4000)..mem::V4KA::new(0x6000);
+        let ramdisk = mem::V4KA::new(0x6000)..mem::V4KA::new(0x8000);
+        let loader = mem::V4KA::new(0x8000)..mem::V4KA::new(0x9000);
+        let mut loader_page_table = LoaderPageTable::new(
+            page_table,
+            &[xfer.clone(), ramdisk.clone(), loader.clone()],
+            &[],
+        );
+        let attrs = mem::Attrs::new_data();
+        unsafe {
+            let regions = [xfer.clone(), ramdisk.clone()];
+            for range in regions {
+                let pa = mem::P4KA::new(range.start.addr() as u64);
+                loader_page_table
+                    .page_table
+                    .map_region(&mem::Region::new(range, attrs), pa);
+            }
+        }
+        let nxfer = mem::V4KA::new(0x1000)..mem::V4KA::new(0x3000);
+        let nramdisk = mem::V4KA::new(0x3000)..mem::V4KA::new(0x8000);
+        let bad = mem::V4KA::new(0x3000)..mem::V4KA::new(0x9000);
+        let overlapping = mem::V4KA::new(0x2000)..mem::V4KA::new(0x4000);
+        for new in [[nxfer.clone(), bad], [nxfer.clone(), overlapping]] {
+            assert!(unsafe {
+                loader_page_table
+                    .replace_reserved(
+                        &[xfer.clone(), ramdisk.clone()],
+                        &new,
+                        attrs,
+                    )
+                    .is_err()
+            });
+        }
+        // Nothing mapped the loader region, so it cannot be
+        // replaced, and a failure leaves the others in place.
+        assert!(unsafe {
+            loader_page_table
+                .replace_reserved(
+                    &[xfer.clone(), loader.clone()],
+                    core::slice::from_ref(&nxfer),
+                    attrs,
+                )
+                .is_err()
+        });
+        assert!(loader_page_table.is_region_readable(xfer.clone()));
+        assert_eq!(loader_page_table.reserved.len(), 3);
+        assert!(unsafe {
+            loader_page_table
+                .replace_reserved(
+                    &[xfer.clone(), ramdisk.clone()],
+                    &[nxfer.clone(), nramdisk.clone()],
+                    attrs,
+                )
+                .is_ok()
+        });
+        assert!(loader_page_table.is_region_readable(nxfer.clone()));
+        assert!(loader_page_table.is_region_readable(nramdisk));
+        assert!(!loader_page_table.is_region_writeable(nxfer));
+        let ptr = ptr::without_provenance(0x3000);
+        assert!(loader_page_table.lookup(ptr).is_some());
+        assert!(unsafe {
+            loader_page_table
+                .replace_reserved(&[xfer], &[ramdisk], attrs)
+                .is_err()
+        });
+    }
+
+    #[test]
+    fn stale_buffers() {
+        let page_table = PageTable::new();
+        let mut loader_page_table = LoaderPageTable::new(page_table, &[], &[]);
+        let attrs = mem::Attrs::new_data();
+        let region = mem::V4KA::new(0x8000)..mem::V4KA::new(0xa000);
+        let other = mem::V4KA::new(0x10000)..mem::V4KA::new(0x11000);
+        unsafe {
+            loader_page_table
+                .map_region(region.clone(), attrs, mem::P4KA::new(0x8000))
+                .unwrap();
+        }
+        let bs = unsafe {
+            core::slice::from_raw_parts(
+                ptr::with_exposed_provenance::<u8>(0x8100),
+                0x1000,
+            )
+        };
+        let buf = loader_page_table.buf(bs);
+        assert!(loader_page_table.resolve(&buf).is_ok());
+        // Unrelated changes leave the buffer valid.
+        unsafe {
+            loader_page_table
+                .map_region(other, attrs, mem::P4KA::new(0x10000))
+                .unwrap();
+        }
+        assert!(loader_page_table.resolve(&buf).is_ok());
+        // Remapping it elsewhere makes it stale, as does
+        // unmapping it.
+        unsafe {
+            loader_page_table
+                .map_region(region.clone(), attrs, mem::P4KA::new(0x20000))
+                .unwrap();
+        }
+        assert!(loader_page_table.resolve(&buf).is_err());
+        let buf = loader_page_table.buf(bs);
+        assert!(loader_page_table.resolve(&buf).is_ok());
+        unsafe {
+            loader_page_table.unmap_range(region).unwrap();
+        }
+        assert!(loader_page_table.resolve(&buf).is_err());
+    }
 }
 
 mod arena {
@@ -1628,7 +2068,10 @@ mod arena {
     use static_assertions::const_assert;
 
     const PAGE_SIZE: usize = 4096;
-    const PAGE_ARENA_SIZE: usize = 128 * PAGE_SIZE;
+    // Tests build many page tables, as each simulated machine has
+    // its own, and the arena is never reclaimed.
+    const PAGE_ARENA_SIZE: usize =
+        if cfg!(test) { 1024 } else { 128 } * PAGE_SIZE;
     // This is trivially true, but keep the assert as
     // documentation of the minimum arena size invariant.
     // See RFD215 for details.
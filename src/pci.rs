@@ -214,6 +214,7 @@ pub(crate) mod ecam {
             self.0.get_bits(8..12)
         }
 
+        #[cfg(feature = "cmd-hw")]
         pub fn addr(self) -> u32 {
             self.0
         }
@@ -243,6 +244,7 @@ pub(crate) mod ecam {
         legacy::Address(addr)
     }
 
+    #[cfg(feature = "cmd-hw")]
     pub(crate) unsafe fn write<T: Into<u32>>(
         bus: Bus,
         dev: Device,
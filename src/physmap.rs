@@ -0,0 +1,225 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The physical memory map, as programmed into the data fabric.
+//!
+//! The data fabric routes each system physical address to the
+//! component that serves it.  Its DRAM address map is a set of
+//! base and limit register pairs in the configuration space of
+//! function 0 of the fabric's PCI device, 0/0x18/0, each giving
+//! a range of addresses backed by DRAM, and the fabric
+//! destination and channels across which it is interleaved.
+//! The legacy MMIO hole below 4GiB is carved out of the range
+//! that spans it, so the DRAM that actually exists is a list of
+//! disjoint segments, which are the safe targets for `map`.
+//!
+//! The register layout is that of the fabric in Milan.
+
+use crate::pci;
+use crate::result::Result;
+use alloc::vec::Vec;
+use bit_field::BitField;
+use core::ops::Range;
+
+/// The number of DRAM address map rules.
+const NRULES: u32 = 16;
+
+/// DF::DramHoleControl.
+const DRAM_HOLE_CONTROL: u32 = 0x104;
+
+/// DF::DramBaseAddress and DF::DramLimitAddress of the first
+/// rule; the registers of each successive rule follow at a
+/// stride of 8 bytes.
+const DRAM_BASE_ADDRESS: u32 = 0x110;
+const DRAM_LIMIT_ADDRESS: u32 = 0x114;
+const RULE_STRIDE: u32 = 8;
+
+/// The base and limit fields hold bits 47:28 of the address.
+const ADDR_SHIFT: u32 = 28;
+
+/// The end of the 32-bit address space, where the MMIO hole
+/// ends.
+const FOUR_GIB: u64 = 1 << 32;
+
+/// A range of DRAM addresses, as routed by one rule of the map.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Rule {
+    pub(crate) index: u32,
+    pub(crate) range: Range<u64>,
+    /// The fabric ID of the destination, or of the first of the
+    /// destinations across which the range is interleaved.
+    pub(crate) dst: u16,
+    /// The encoded number of channels interleaved across.
+    pub(crate) channels: u8,
+    /// The encoded interleave address bit.
+    pub(crate) addr_sel: u8,
+    /// Set if the MMIO hole is carved out of this range.
+    pub(crate) hole: bool,
+}
+
+impl Rule {
+    /// Decodes a base and limit register pair, returning `None`
+    /// if the rule is not valid.
+    fn decode(index: u32, base: u32, limit: u32) -> Option<Rule> {
+        if !base.get_bit(0) {
+            return None;
+        }
+        let start = u64::from(base.get_bits(12..32)) << ADDR_SHIFT;
+        let end = (u64::from(limit.get_bits(12..32)) + 1) << ADDR_SHIFT;
+        Some(Rule {
+            index,
+            range: start..end,
+            dst: limit.get_bits(0..10) as u16,
+            channels: base.get_bits(4..8) as u8,
+            addr_sel: base.get_bits(8..11) as u8,
+            hole: base.get_bit(1),
+        })
+    }
+}
+
+/// The DRAM address map.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Map {
+    pub(crate) rules: Vec<Rule>,
+    /// The MMIO hole below 4GiB, if enabled.
+    pub(crate) hole: Option<Range<u64>>,
+}
+
+impl Map {
+    /// Decodes the map from the value of DF::DramHoleControl and
+    /// the base and limit registers of each rule.
+    fn decode(hole: u32, pairs: &[(u32, u32)]) -> Map {
+        let hole = hole
+            .get_bit(0)
+            .then(|| u64::from(hole.get_bits(24..32)) << 24..FOUR_GIB);
+        let rules = (0..)
+            .zip(pairs)
+            .filter_map(|(k, &(base, limit))| Rule::decode(k, base, limit))
+            .collect();
+        Map { rules, hole }
+    }
+
+    /// Returns the segments of physical memory backed by DRAM,
+    /// in address order, with the MMIO hole removed and adjacent
+    /// ranges coalesced.
+    pub(crate) fn segments(&self) -> Vec<Range<u64>> {
+        let mut pieces = Vec::new();
+        for rule in self.rules.iter() {
+            match &self.hole {
+                Some(hole) if rule.hole => {
+                    let Range { start, end } = rule.range;
+                    let below = start..end.min(hole.start);
+                    let above = start.max(hole.end)..end;
+                    pieces.extend(
+                        [below, above].into_iter().filter(|r| !r.is_empty()),
+                    );
+                }
+                _ => pieces.push(rule.range.clone()),
+            }
+        }
+        pieces.sort_by_key(|r| r.start);
+        let mut segments: Vec<Range<u64>> = Vec::new();
+        for piece in pieces {
+            match segments.last_mut() {
+                Some(last) if last.end == piece.start => last.end = piece.end,
+                _ => segments.push(piece),
+            }
+        }
+        segments
+    }
+}
+
+/// Reads a register of the fabric's function 0.
+fn read_df(offset: u32) -> Result<u32> {
+    let offset = pci::ecam::Offset::try_from(offset)?;
+    unsafe {
+        pci::ecam::read(
+            pci::Bus(0),
+            pci::Device::D24,
+            pci::Function::F0,
+            offset,
+        )
+    }
+}
+
+/// Reads the DRAM address map from the data fabric.
+pub(crate) fn read() -> Result<Map> {
+    let hole = read_df(DRAM_HOLE_CONTROL)?;
+    let mut pairs = Vec::new();
+    for k in 0..NRULES {
+        let base = read_df(DRAM_BASE_ADDRESS + k * RULE_STRIDE)?;
+        let limit = read_df(DRAM_LIMIT_ADDRESS + k * RULE_STRIDE)?;
+        pairs.push((base, limit));
+    }
+    Ok(Map::decode(hole, &pairs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    /// A base register for a valid rule starting at the given
+    /// address, interleaved across 2 channels.
+    fn base(addr: u64, hole: bool) -> u32 {
+        ((addr >> ADDR_SHIFT) as u32) << 12 | 1 << 4 | u32::from(hole) << 1 | 1
+    }
+
+    /// A limit register for a rule ending before the given
+    /// address, and routed to the given destination.
+    fn limit(end: u64, dst: u32) -> u32 {
+        (((end >> ADDR_SHIFT) - 1) as u32) << 12 | dst
+    }
+
+    #[test]
+    fn rules() {
+        let rule = Rule::decode(3, base(0, true), limit(2 * GIB, 0x20));
+        assert_eq!(
+            rule,
+            Some(Rule {
+                index: 3,
+                range: 0..2 * GIB,
+                dst: 0x20,
+                channels: 1,
+                addr_sel: 0,
+                hole: true,
+            })
+        );
+        assert_eq!(Rule::decode(0, base(0, false) & !1, limit(GIB, 0)), None);
+    }
+
+    /// Returns the range between the given numbers of GiB.
+    fn gib(start: u64, end: u64) -> Range<u64> {
+        start * GIB..end * GIB
+    }
+
+    #[test]
+    fn segments() {
+        // 64GiB, interleaved over two sockets with rules split
+        // at the hole, which starts at 2GiB.
+        let hole = 0x80 << 24 | 1;
+        let pairs = [
+            (base(0, true), limit(36 * GIB, 0x20)),
+            (0, 0),
+            (base(36 * GIB, false), limit(66 * GIB, 0x60)),
+        ];
+        let map = Map::decode(hole, &pairs);
+        assert_eq!(map.hole, Some(2 * GIB..4 * GIB));
+        assert_eq!(map.rules.len(), 2);
+        assert_eq!(map.rules[1].index, 2);
+        assert_eq!(map.segments(), [gib(0, 2), gib(4, 66)]);
+
+        // Without the hole enabled, nothing is carved out.
+        let map = Map::decode(0x80 << 24, &pairs);
+        assert_eq!(map.hole, None);
+        assert_eq!(map.segments(), [gib(0, 66)]);
+
+        // A rule wholly below the hole leaves nothing above it.
+        let pairs = [(base(0, true), limit(GIB, 0)), (0, 0)];
+        let map = Map::decode(hole, &pairs);
+        assert_eq!(map.segments(), [gib(0, 1)]);
+        assert!(Map::decode(0, &[]).segments().is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! POST codes.
+//!
+//! Writes to I/O port 0x80 are traditionally latched by
+//! platform hardware and displayed, or captured by a service
+//! processor or bus analyzer.  We emit a code as we pass each
+//! phase of early boot, and another on panic, so that a machine
+//! that appears to be bricked still leaves a breadcrumb even if
+//! the console never comes up.
+
+/// The legacy POST code port.
+const POST_PORT: u16 = 0x80;
+
+/// Progress and error codes.
+#[derive(Clone, Copy, Debug)]
+#[repr(u8)]
+pub(crate) enum Code {
+    EnterRust = 0xb0,
+    IoMuxInit = 0xb1,
+    UartInit = 0xb2,
+    IdtInit = 0xb3,
+    Remapped = 0xb4,
+    ConfigInit = 0xb5,
+    ReplReady = 0xb6,
+    Emergency = 0xbc,
+    Panic = 0xbd,
+    /// A panic with neither the console nor the fallback UART
+    /// to report it on.
+    PanicUnreported = 0xbe,
+}
+
+/// Emits the given code to the POST port.
+pub(crate) fn post(code: Code) {
+    #[cfg(not(any(test, clippy)))]
+    unsafe {
+        x86::io::outb(POST_PORT, code as u8);
+    }
+    #[cfg(any(test, clippy))]
+    let _ = (POST_PORT, code);
+}
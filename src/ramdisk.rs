@@ -5,12 +5,19 @@
 //! Code for dealing with the UFS ramdisk.
 
 use crate::cpio;
+use crate::ext2;
+use crate::fat;
 use crate::io;
+use crate::mem;
+use crate::mmu;
 use crate::println;
 use crate::result::{Error, Result};
 use crate::uart::Uart;
 use crate::ufs;
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::convert::TryInto;
 
 /// The type of file, taken from the inode.
@@ -33,28 +40,283 @@ pub enum FileType {
     AttrDir,
 }
 
+impl FileType {
+    /// Returns a single character that represents the file
+    /// type, such as 'd' for directories, or '-' for regular
+    /// files.  These are mostly the characters one would see in
+    /// the output of `ls -l`.
+    pub fn as_char(self) -> char {
+        match self {
+            FileType::Unused => 'X',
+            FileType::Fifo => 'p',
+            FileType::Char => 'c',
+            FileType::Dir => 'd',
+            FileType::Block => 'b',
+            FileType::Regular => '-',
+            FileType::SymLink => 'l',
+            FileType::ShadowInode => 'I',
+            FileType::Sock => 's',
+            FileType::AttrDir => 'A',
+        }
+    }
+}
+
 pub trait File: io::Read {
     fn file_type(&self) -> FileType;
+
+    /// Returns the extent of the file starting at the given
+    /// offset, up to `max` bytes long.  File systems that do not
+    /// support holes have a single extent of data.
+    fn extent(&self, offset: u64, max: usize) -> Result<Extent> {
+        let rest = self.size().saturating_sub(offset as usize);
+        Ok(Extent::Data(usize::min(rest, max)))
+    }
+
+    /// Overwrites part of the file in place, returning the number
+    /// of bytes written.  File systems are read-only unless they
+    /// say otherwise.
+    fn write(&self, _offset: u64, _src: &[u8]) -> Result<usize> {
+        Err(Error::FsReadOnly)
+    }
+}
+
+/// A run of bytes in a file, either backed by storage or a hole,
+/// which reads as zeroes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Extent {
+    Data(usize),
+    Hole(usize),
+}
+
+/// Metadata about a file, independent of the type of file
+/// system that holds it.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub ino: u64,
+    pub file_type: FileType,
+    /// The permission bits from the file's mode, including the
+    /// setuid, setgid, and sticky bits.
+    pub perms: u16,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: usize,
+    /// The number of bytes of storage actually allocated to the
+    /// file, which may be less than its size if it has holes.
+    pub allocated: usize,
 }
 
 pub trait FileSystem {
     fn open(&self, path: &str) -> Result<Box<dyn File>>;
-    fn list(&self, path: &str) -> Result<()>;
+    /// Returns metadata for the named file itself.
+    fn stat(&self, path: &str) -> Result<DirEntry>;
+    /// Returns metadata for each entry in the named directory,
+    /// or for the file itself if it is not a directory.
+    fn readdir(&self, path: &str) -> Result<Vec<DirEntry>>;
     fn as_str(&self) -> &str;
 }
 
-pub fn mount(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
-    mount_cpio(ramdisk).or_else(|_| {
-        let fs = ufs::FileSystem::new(ramdisk)?;
-        if let Ok(ufs::State::Clean) = fs.state() {
-            let flags = fs.flags();
-            println!("ramdisk mounted successfully (Clean, {flags:?})");
-            Ok(Box::new(fs))
-        } else {
-            println!("ramdisk mount failed: invalid state {:?}", fs.state());
-            Err(Error::FsInvState)
+/// A mounted ramdisk, along with a handle to the memory that
+/// holds it.
+pub struct Mounted {
+    fs: Box<dyn FileSystem>,
+    buf: mmu::Buf,
+}
+
+impl Mounted {
+    pub fn new(fs: Box<dyn FileSystem>, buf: mmu::Buf) -> Mounted {
+        Mounted { fs, buf }
+    }
+
+    /// Returns the file system, provided that the memory
+    /// holding the ramdisk has not been unmapped or remapped
+    /// since it was mounted.
+    pub fn fs(
+        &self,
+        page_table: &mmu::LoaderPageTable,
+    ) -> Result<&dyn FileSystem> {
+        page_table.resolve(&self.buf)?;
+        Ok(self.fs.as_ref())
+    }
+
+    /// Returns the file system for writing, provided that the
+    /// memory holding the ramdisk is still mapped, and writeable.
+    pub fn fs_mut(
+        &self,
+        page_table: &mmu::LoaderPageTable,
+    ) -> Result<&dyn FileSystem> {
+        let bs = page_table.resolve(&self.buf)?;
+        let range = mem::page_range_raw(bs.as_ptr().cast(), bs.len());
+        if !page_table.is_region_writeable(range) {
+            return Err(Error::FsReadOnly);
         }
-    })
+        Ok(self.fs.as_ref())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.fs.as_str()
+    }
+
+    /// Returns the handle to the memory holding the ramdisk.
+    pub fn buf(&self) -> &mmu::Buf {
+        &self.buf
+    }
+}
+
+/// The mount table: the ramdisks that are mounted, and the
+/// directories at which they are mounted.  A path names a file
+/// on the ramdisk mounted at the longest directory that leads
+/// to it, and the mount points themselves need not exist on the
+/// ramdisks above them.
+#[derive(Default)]
+pub struct Mounts {
+    table: Vec<(String, Mounted)>,
+}
+
+impl Mounts {
+    /// Mounts a file system at the given directory, replacing
+    /// anything already mounted there.
+    pub fn mount(&mut self, dir: &str, mounted: Mounted) -> Result<()> {
+        let dir = mount_point(dir)?;
+        self.table.retain(|(point, _)| point != dir);
+        self.table.push((String::from(dir), mounted));
+        self.table.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at the given directory.
+    pub fn umount(&mut self, dir: &str) -> Result<Mounted> {
+        let dir = mount_point(dir)?;
+        let k = self
+            .table
+            .iter()
+            .position(|(point, _)| point == dir)
+            .ok_or(Error::FsNoRoot)?;
+        Ok(self.table.remove(k).1)
+    }
+
+    /// Returns the file system mounted at `/`, if any.
+    pub fn root(&self) -> Option<&Mounted> {
+        self.iter().find(|&(dir, _)| dir == "/").map(|(_, mounted)| mounted)
+    }
+
+    /// Iterates over the mount points and their file systems,
+    /// in order of their directories.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Mounted)> {
+        self.table.iter().map(|(dir, mounted)| (dir.as_str(), mounted))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Returns the file system holding the named file, and the
+    /// file's path within it.  The path is normalized first, so
+    /// that `.`, `..` and repeated `/`s cannot lead it to the
+    /// wrong mount.
+    pub fn find(&self, path: &str) -> Option<(&Mounted, String)> {
+        let path = normalize(path);
+        self.table
+            .iter()
+            .filter_map(|(dir, mounted)| {
+                Some((dir.len(), mounted, beneath(dir, &path)?))
+            })
+            .max_by_key(|&(len, _, _)| len)
+            .map(|(_, mounted, inner)| (mounted, String::from(inner)))
+    }
+}
+
+/// Checks that a directory may be used as a mount point, and
+/// returns it without any trailing `/`.  Mount points are
+/// absolute paths, without `.` or `..` components, and the
+/// built-in image's directory is reserved.
+fn mount_point(dir: &str) -> Result<&str> {
+    let dir = match dir.trim_end_matches('/') {
+        "" if dir.starts_with('/') => return Ok("/"),
+        dir => dir,
+    };
+    let valid = dir.starts_with('/')
+        && dir[1..].split('/').all(|name| !matches!(name, "" | "." | ".."))
+        && builtin_path(dir).is_none();
+    if !valid {
+        println!("mount point {dir} is not an absolute directory path");
+        return Err(Error::FsInvPath);
+    }
+    Ok(dir)
+}
+
+/// Returns an absolute path with empty and `.` components
+/// removed, and `..` components resolved lexically; `..` at the
+/// root stays there.  There is no trailing `/`, except on the
+/// root itself.  Relative paths are returned unchanged, as there
+/// is no current directory against which to resolve them.
+pub fn normalize(path: &str) -> String {
+    if !path.starts_with('/') {
+        return String::from(path);
+    }
+    let mut names = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    let mut normal = String::with_capacity(path.len());
+    for name in names.iter() {
+        normal.push('/');
+        normal.push_str(name);
+    }
+    if normal.is_empty() {
+        normal.push('/');
+    }
+    normal
+}
+
+/// If `path` is the directory `dir` or beneath it, returns the
+/// rest of the path, relative to `dir`.  Everything is beneath
+/// the root, and paths there are unchanged.
+fn beneath<'p>(dir: &str, path: &'p str) -> Option<&'p str> {
+    if dir == "/" {
+        return Some(path);
+    }
+    match path.strip_prefix(dir)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// Mounts the ramdisk held in the given memory, read-only.
+pub fn mount(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
+    mount_cpio(ramdisk)
+        .or_else(|_| mount_fat(ramdisk))
+        .or_else(|_| mount_ext2(ramdisk))
+        .or_else(|_| mount_ufs(ufs::FileSystem::new(ramdisk)?))
+}
+
+/// Mounts the ramdisk held in the given writable memory.  A UFS
+/// ramdisk is mounted so that its files may be written in place;
+/// any other is mounted read-only, as by `mount`.
+pub fn mount_mut(ramdisk: &'static mut [u8]) -> Result<Box<dyn FileSystem>> {
+    if ufs::SuperBlock::read(ramdisk).is_err() {
+        return mount(ramdisk);
+    }
+    mount_ufs(ufs::FileSystem::new_mut(ramdisk)?)
+}
+
+fn mount_ufs(fs: ufs::FileSystem) -> Result<Box<dyn FileSystem>> {
+    if let Ok(ufs::State::Clean) = fs.state() {
+        let flags = fs.flags();
+        println!("ramdisk mounted successfully (Clean, {flags:?})");
+        Ok(Box::new(fs))
+    } else {
+        println!("ramdisk mount failed: invalid state {:?}", fs.state());
+        Err(Error::FsInvState)
+    }
 }
 
 pub fn mount_cpio(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
@@ -63,20 +325,233 @@ pub fn mount_cpio(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
     Ok(fs)
 }
 
-pub fn list(fs: &dyn FileSystem, path: &str) -> Result<()> {
-    fs.list(path)
+pub fn mount_ext2(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
+    let fs = ext2::FileSystem::try_new(ramdisk).inspect_err(|&e| {
+        if e.is(Error::FsUnsupported) {
+            println!("ext2 ramdisk mount failed: {}", e.as_str());
+        }
+    })?;
+    if fs.needs_recovery() {
+        println!(
+            "warning: journal not replayed; recent changes may be missing"
+        );
+    }
+    println!("{} ramdisk mounted successfully", fs.as_str());
+    Ok(Box::new(fs))
+}
+
+pub fn mount_fat(ramdisk: &'static [u8]) -> Result<Box<dyn FileSystem>> {
+    let fs = Box::new(fat::FileSystem::try_new(ramdisk)?);
+    println!("FAT32 ramdisk mounted successfully");
+    Ok(fs)
+}
+
+/// The directory under which the files of the built-in image
+/// appear, alongside those of the mounted ramdisk.
+pub const BUILTIN_DIR: &str = "/builtin";
+
+/// Returns the file system holding the named file, and the
+/// file's path within it.  Paths under `/builtin` name files in
+/// the built-in image, if there is one; all others, files on the
+/// mounted ramdisks.  The path is normalized, as by `normalize`.
+pub fn lookup<'a>(
+    mounts: &'a Mounts,
+    builtin: Option<&'a dyn FileSystem>,
+    page_table: &mmu::LoaderPageTable,
+    path: &str,
+) -> Result<(&'a dyn FileSystem, String)> {
+    let path = normalize(path);
+    if let Some(builtin) = builtin
+        && let Some(inner) = builtin_path(&path)
+    {
+        return Ok((builtin, String::from(inner)));
+    }
+    let (mounted, inner) = mounts.find(&path).ok_or(Error::FsNoRoot)?;
+    Ok((mounted.fs(page_table)?, inner))
+}
+
+/// The cpio archive of diagnostic payloads embedded in the
+/// loader image, named at build time by `BLDB_BUILTIN_CPIO`.
+#[cfg(feature = "builtin-ramdisk")]
+static BUILTIN_IMAGE: &[u8] = include_bytes!(env!("BLDB_BUILTIN_CPIO"));
+
+/// Mounts the built-in image, if the loader was built with one.
+/// The image is part of the loader's own read-only data, and so
+/// is always mapped.
+pub fn mount_builtin() -> Option<Box<dyn FileSystem>> {
+    #[cfg(feature = "builtin-ramdisk")]
+    match cpio::FileSystem::try_new(BUILTIN_IMAGE) {
+        Ok(fs) => {
+            println!("builtin image mounted at {BUILTIN_DIR}");
+            return Some(Box::new(fs));
+        }
+        Err(e) => println!("builtin image mount failed: {e:?}"),
+    }
+    None
+}
+
+/// If the path names a file in the built-in image, returns its
+/// path within that image.
+pub fn builtin_path(path: &str) -> Option<&str> {
+    beneath(BUILTIN_DIR, path)
+}
+
+/// The order in which `list` displays entries.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SortBy {
+    /// The order in which the file system returns them.
+    #[default]
+    None,
+    Name,
+    /// Largest first, as with `ls -S`.
+    Size,
+    Inode,
+}
+
+/// Options controlling the output of `list`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ListOptions {
+    pub sort: SortBy,
+    /// Display sizes with a binary unit suffix.
+    pub human: bool,
+    /// List directories themselves, rather than their contents.
+    pub dir_itself: bool,
+}
+
+/// Formats the type and permission bits of a file as in the
+/// first field of the output of `ls -l`.
+fn mode_string(file_type: FileType, perms: u16) -> String {
+    const SUID: u16 = 0o4000;
+    const SGID: u16 = 0o2000;
+    const STICKY: u16 = 0o1000;
+    let bit = |shift: u32, c: char| {
+        if perms & (1 << shift) != 0 { c } else { '-' }
+    };
+    let exec = |shift: u32, special: u16, set: char, unset: char| match (
+        perms & (1 << shift) != 0,
+        perms & special != 0,
+    ) {
+        (x, false) => {
+            if x {
+                'x'
+            } else {
+                '-'
+            }
+        }
+        (x, true) => {
+            if x {
+                set
+            } else {
+                unset
+            }
+        }
+    };
+    let mut mode = String::with_capacity(10);
+    mode.push(file_type.as_char());
+    mode.push(bit(8, 'r'));
+    mode.push(bit(7, 'w'));
+    mode.push(exec(6, SUID, 's', 'S'));
+    mode.push(bit(5, 'r'));
+    mode.push(bit(4, 'w'));
+    mode.push(exec(3, SGID, 's', 'S'));
+    mode.push(bit(2, 'r'));
+    mode.push(bit(1, 'w'));
+    mode.push(exec(0, STICKY, 't', 'T'));
+    mode
+}
+
+/// Formats a size using the largest binary unit in which it is
+/// at least one, with a single decimal place below ten units.
+pub(crate) fn human_size(size: usize) -> String {
+    const UNITS: [char; 6] = ['K', 'M', 'G', 'T', 'P', 'E'];
+    if size < 1024 {
+        return alloc::format!("{size}");
+    }
+    let mut unit = 0;
+    let mut scale = 1024;
+    while unit + 1 < UNITS.len() && size / scale >= 1024 {
+        unit += 1;
+        scale *= 1024;
+    }
+    let u = UNITS[unit];
+    let tenths = (size as u128 * 10).div_ceil(scale as u128);
+    if tenths < 100 {
+        alloc::format!("{}.{}{u}", tenths / 10, tenths % 10)
+    } else {
+        alloc::format!("{}{u}", size.div_ceil(scale))
+    }
+}
+
+fn sort(entries: &mut [DirEntry], by: SortBy) {
+    match by {
+        SortBy::None => {}
+        SortBy::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortBy::Size => entries.sort_by_key(|e| core::cmp::Reverse(e.size)),
+        SortBy::Inode => entries.sort_by_key(|e| e.ino),
+    }
+}
+
+/// Lists a file or the contents of a directory, in a manner
+/// similar to `ls -l`.
+pub fn list(fs: &dyn FileSystem, path: &str, opts: ListOptions) -> Result<()> {
+    let mut entries =
+        if opts.dir_itself { vec![fs.stat(path)?] } else { fs.readdir(path)? };
+    sort(&mut entries, opts.sort);
+    for entry in entries.iter() {
+        let size = if opts.human {
+            human_size(entry.size)
+        } else {
+            alloc::format!("{}", entry.size)
+        };
+        println!(
+            "#{ino:<4} {mode} {nlink:<2} {uid:<3} {gid:<3} {size:>8} {name}",
+            ino = entry.ino,
+            mode = mode_string(entry.file_type, entry.perms),
+            nlink = entry.nlink,
+            uid = entry.uid,
+            gid = entry.gid,
+            name = entry.name,
+        );
+    }
+    Ok(())
+}
+
+/// Displays the metadata of a file, including how much storage
+/// it occupies, which is less than its size if it has holes.
+pub fn stat(fs: &dyn FileSystem, path: &str) -> Result<()> {
+    let entry = fs.stat(path)?;
+    println!(
+        "{path}: inode {}, {} {} links, uid {} gid {}",
+        entry.ino,
+        mode_string(entry.file_type, entry.perms),
+        entry.nlink,
+        entry.uid,
+        entry.gid,
+    );
+    let sparse = if entry.allocated < entry.size { " (sparse)" } else { "" };
+    println!(
+        "size {} ({}), allocated {} ({}){sparse}",
+        entry.size,
+        human_size(entry.size),
+        entry.allocated,
+        human_size(entry.allocated),
+    );
+    Ok(())
 }
 
+/// How much of a file is read at a time by `cat` and `sha256`.
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
 pub fn cat(uart: &mut Uart, fs: &dyn FileSystem, path: &str) -> Result<()> {
     let file = fs.open(path)?;
     if file.file_type() != FileType::Regular {
         println!("cat: not a regular file");
         return Err(Error::BadArgs);
     }
+    let mut buf = vec![0u8; READ_CHUNK_LEN];
     let mut offset = 0;
     let size = file.size();
     while offset != size {
-        let mut buf = [0u8; 1024];
         let nb = file.read(offset.try_into().unwrap(), &mut buf)?;
         uart.putbs_crnl(&buf[..nb]);
         offset += nb;
@@ -84,18 +559,102 @@ pub fn cat(uart: &mut Uart, fs: &dyn FileSystem, path: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn copy(fs: &dyn FileSystem, path: &str, dst: &mut [u8]) -> Result<usize> {
+/// How much of a file to process between polls for
+/// cancellation.
+const POLL_CHUNK: usize = 1024 * 1024;
+
+/// Copies a file into memory, calling `poll` periodically;
+/// should it fail, the copy is abandoned.
+pub fn copy(
+    fs: &dyn FileSystem,
+    path: &str,
+    dst: &mut [u8],
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<usize> {
+    copy_from(fs, path, 0, dst, poll)
+}
+
+/// Copies the part of a file starting at `start` into memory,
+/// calling `poll` periodically; should it fail, the copy is
+/// abandoned.  Returns the number of bytes copied, which is
+/// short if the file ends before `dst` is filled.
+pub fn copy_from(
+    fs: &dyn FileSystem,
+    path: &str,
+    start: usize,
+    dst: &mut [u8],
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<usize> {
     let file = fs.open(path)?;
     if file.file_type() != FileType::Regular {
         println!("copy: not a regular file");
         return Err(Error::BadArgs);
     }
-    let len = core::cmp::min(file.size(), dst.len());
-    let nb = file.read(0, &mut dst[..len])?;
-    Ok(nb)
+    let len = core::cmp::min(file.size().saturating_sub(start), dst.len());
+    let mut offset = 0;
+    while offset < len {
+        poll()?;
+        let end = usize::min(offset + POLL_CHUNK, len);
+        match file.extent((start + offset) as u64, end - offset)? {
+            Extent::Data(0) | Extent::Hole(0) => break,
+            // Holes are only zeroed where the destination is not
+            // already zero, as it often is.
+            Extent::Hole(n) => {
+                let dst = &mut dst[offset..offset + n];
+                if dst.iter().any(|&b| b != 0) {
+                    dst.fill(0);
+                }
+                offset += n;
+            }
+            Extent::Data(n) => {
+                let dst = &mut dst[offset..offset + n];
+                let nb = file.read((start + offset) as u64, dst)?;
+                if nb == 0 {
+                    break;
+                }
+                offset += nb;
+            }
+        }
+    }
+    Ok(offset)
+}
+
+/// Overwrites part of a file, starting at `offset`, with the
+/// given bytes.  The file must already extend past the end of
+/// the write.
+pub fn write(
+    fs: &dyn FileSystem,
+    path: &str,
+    offset: usize,
+    src: &[u8],
+) -> Result<usize> {
+    let file = fs.open(path)?;
+    if file.file_type() != FileType::Regular {
+        println!("write: not a regular file");
+        return Err(Error::BadArgs);
+    }
+    if offset.saturating_add(src.len()) > file.size() {
+        println!(
+            "write: {:#x} bytes at offset {offset:#x} would extend the \
+             file past its size of {:#x}",
+            src.len(),
+            file.size()
+        );
+        return Err(Error::FsOffset);
+    }
+    file.write(offset as u64, src)
 }
 
-pub fn sha256(fs: &dyn FileSystem, path: &str) -> Result<[u8; 32]> {
+/// Zeroes, for summing holes without reading them.
+static ZEROES: [u8; 4096] = [0; 4096];
+
+/// Computes the SHA-256 digest of a file, calling `poll`
+/// periodically; should it fail, so does the digest.
+pub fn sha256(
+    fs: &dyn FileSystem,
+    path: &str,
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<[u8; 32]> {
     use sha2::{Digest, Sha256};
 
     let file = fs.open(path)?;
@@ -104,14 +663,576 @@ pub fn sha256(fs: &dyn FileSystem, path: &str) -> Result<[u8; 32]> {
         return Err(Error::BadArgs);
     }
     let mut sum = Sha256::new();
+    let mut buf = vec![0u8; READ_CHUNK_LEN];
     let mut offset = 0;
     let size = file.size();
     while offset != size {
-        let mut buf = [0u8; 1024];
-        let nb = file.read(offset.try_into().unwrap(), &mut buf)?;
-        sum.update(&buf[..nb]);
-        offset += nb;
+        poll()?;
+        match file.extent(offset as u64, READ_CHUNK_LEN)? {
+            Extent::Data(0) | Extent::Hole(0) => return Err(Error::FsRead),
+            // Holes are summed without reading them.
+            Extent::Hole(n) => {
+                for k in (0..n).step_by(ZEROES.len()) {
+                    sum.update(&ZEROES[..usize::min(ZEROES.len(), n - k)]);
+                }
+                offset += n;
+            }
+            Extent::Data(n) => {
+                let nb = file.read(offset as u64, &mut buf[..n])?;
+                if nb == 0 {
+                    return Err(Error::FsRead);
+                }
+                sum.update(&buf[..nb]);
+                offset += nb;
+            }
+        }
     }
     let hash = sum.finalize();
     Ok(hash.into())
 }
+
+/// Matches a name against a shell-style pattern, in which `*`
+/// matches any run of characters other than `/`, and `?` any
+/// single such character.
+#[cfg(feature = "cmd-files")]
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            glob_match(rest, name)
+                || name.first().is_some_and(|&c| c != b'/')
+                    && glob_match(pattern, &name[1..])
+        }
+        (Some((b'?', p)), Some((&c, n))) => c != b'/' && glob_match(p, n),
+        (Some((pc, p)), Some((nc, n))) => pc == nc && glob_match(p, n),
+        _ => false,
+    }
+}
+
+/// Expands a path whose final component may contain wildcards
+/// into the sorted list of matching regular files.  A path
+/// without wildcards is returned unchanged.
+#[cfg(feature = "cmd-files")]
+pub fn glob(fs: &dyn FileSystem, path: &str) -> Result<Vec<String>> {
+    let (dir, pattern) = path.rsplit_once('/').unwrap_or(("", path));
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![String::from(path)]);
+    }
+    let dir = if dir.is_empty() { "/" } else { dir };
+    let mut paths = fs
+        .readdir(dir)?
+        .into_iter()
+        .filter(|entry| entry.file_type == FileType::Regular)
+        .filter(|entry| glob_match(pattern.as_bytes(), entry.name.as_bytes()))
+        .map(|entry| join(dir, &entry.name))
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Joins a directory path and the name of an entry within it.
+#[cfg(feature = "cmd-files")]
+fn join(dir: &str, name: &str) -> String {
+    let mut path = String::from(dir.trim_end_matches('/'));
+    path.push('/');
+    path.push_str(name);
+    path
+}
+
+/// The deepest directory nesting `find` descends into, as a
+/// guard against cycles in a corrupt file system.
+#[cfg(feature = "cmd-files")]
+const FIND_MAX_DEPTH: usize = 32;
+
+/// Returns the sorted list of regular files named by the path.
+/// A path whose final component contains wildcards is expanded
+/// as by `glob`, a directory is searched recursively, and any
+/// other path is returned unchanged.
+#[cfg(feature = "cmd-files")]
+pub fn find(fs: &dyn FileSystem, path: &str) -> Result<Vec<String>> {
+    fn walk(
+        fs: &dyn FileSystem,
+        dir: &str,
+        depth: usize,
+        paths: &mut Vec<String>,
+    ) -> Result<()> {
+        for entry in fs.readdir(dir)? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let path = join(dir, &entry.name);
+            match entry.file_type {
+                FileType::Regular => paths.push(path),
+                FileType::Dir if depth < FIND_MAX_DEPTH => {
+                    walk(fs, &path, depth + 1, paths)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    let last = path.rsplit_once('/').map_or(path, |(_, last)| last);
+    if last.contains(['*', '?']) {
+        return glob(fs, path);
+    }
+    if fs.stat(path)?.file_type != FileType::Dir {
+        return Ok(vec![String::from(path)]);
+    }
+    let mut paths = Vec::new();
+    walk(fs, path, 0, &mut paths)?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Returns the offsets of all occurrences of the pattern in the
+/// haystack, optionally ignoring ASCII case.
+#[cfg(feature = "cmd-files")]
+fn find_all<'a>(
+    haystack: &'a [u8],
+    pattern: &'a [u8],
+    ignore_case: bool,
+) -> impl Iterator<Item = usize> + 'a {
+    haystack.windows(pattern.len()).enumerate().filter_map(move |(k, w)| {
+        let hit = if ignore_case {
+            w.eq_ignore_ascii_case(pattern)
+        } else {
+            w == pattern
+        };
+        hit.then_some(k)
+    })
+}
+
+/// How much of the file to search at a time, the longest
+/// pattern we search for, and how many bytes of context to show
+/// on either side of a match.
+#[cfg(feature = "cmd-files")]
+const GREP_CHUNK_LEN: usize = 64 * 1024;
+#[cfg(feature = "cmd-files")]
+const GREP_MAX_PATTERN_LEN: usize = 256;
+#[cfg(feature = "cmd-files")]
+const GREP_CONTEXT_LEN: usize = 24;
+
+/// Searches the named file for a literal string, printing the
+/// path, offset, and surrounding context of each match.
+/// Returns the number of matches.
+#[cfg(feature = "cmd-files")]
+pub fn fgrep(
+    fs: &dyn FileSystem,
+    path: &str,
+    pattern: &[u8],
+    ignore_case: bool,
+) -> Result<usize> {
+    if pattern.is_empty() || pattern.len() > GREP_MAX_PATTERN_LEN {
+        return Err(Error::BadArgs);
+    }
+    let file = fs.open(path)?;
+    if file.file_type() != FileType::Regular {
+        println!("fgrep: {path}: not a regular file");
+        return Err(Error::BadArgs);
+    }
+    let size = file.size();
+    let mut buf = vec![0u8; GREP_CHUNK_LEN];
+    let mut offset = 0;
+    let mut count = 0;
+    while offset < size {
+        let nb = file.read(offset as u64, &mut buf)?;
+        if nb == 0 {
+            return Err(Error::FsRead);
+        }
+        for k in find_all(&buf[..nb], pattern, ignore_case) {
+            let start = (offset + k).saturating_sub(GREP_CONTEXT_LEN);
+            let end =
+                usize::min(offset + k + pattern.len() + GREP_CONTEXT_LEN, size);
+            let mut context =
+                [0u8; 2 * GREP_CONTEXT_LEN + GREP_MAX_PATTERN_LEN];
+            let len = file.read(start as u64, &mut context[..end - start])?;
+            let snippet = context[..len]
+                .iter()
+                .map(|&b| {
+                    if b == b' ' || b.is_ascii_graphic() {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            println!("{path}:{:#x}: {snippet}", offset + k);
+            count += 1;
+        }
+        if offset + nb >= size {
+            break;
+        }
+        // Overlap successive chunks so that matches spanning a
+        // boundary are found, but not reported twice.
+        offset += nb.saturating_sub(pattern.len() - 1).max(1);
+    }
+    Ok(count)
+}
+
+/// The largest manifest we are willing to read.
+const MAX_MANIFEST_LEN: usize = 1024 * 1024;
+
+/// Parses a line from an integrity manifest.  Manifests use the
+/// format emitted by `sha256sum`: a hex SHA256 digest, followed
+/// by whitespace and a path, optionally prefixed by `*`.  Paths
+/// are relative to the root of the ramdisk, and blank lines and
+/// lines starting with `#` are ignored.
+fn parse_manifest_line(line: &str) -> Result<Option<([u8; 32], String)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (hex, path) =
+        line.split_once(char::is_whitespace).ok_or(Error::BadArgs)?;
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::NumParse);
+    }
+    let mut digest = [0u8; 32];
+    for (k, byte) in digest.iter_mut().enumerate() {
+        let s = hex.get(2 * k..2 * k + 2).ok_or(Error::NumParse)?;
+        *byte = u8::from_str_radix(s, 16).map_err(|_| Error::NumParse)?;
+    }
+    let path = path.trim_start();
+    let path = path.strip_prefix('*').unwrap_or(path);
+    let path = path.strip_prefix("./").unwrap_or(path);
+    if path.is_empty() {
+        return Err(Error::FsInvPath);
+    }
+    let mut abs = String::from("/");
+    abs.push_str(path.trim_start_matches('/'));
+    Ok(Some((digest, abs)))
+}
+
+/// The outcome of verifying a ramdisk against a manifest.
+#[derive(Debug, Default)]
+pub struct Verified {
+    pub good: usize,
+    pub bad: usize,
+    pub missing: usize,
+    /// Files that exist but could not be read.
+    pub failed: usize,
+}
+
+/// Verifies the files listed in the given manifest, itself a
+/// file on the ramdisk, against their recorded SHA256 digests.
+/// Mismatched, missing, and unreadable files are reported as
+/// they are found.
+pub fn verify(
+    fs: &dyn FileSystem,
+    manifest: &str,
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<Verified> {
+    let file = fs.open(manifest)?;
+    if file.file_type() != FileType::Regular {
+        println!("verifyfs: manifest is not a regular file");
+        return Err(Error::BadArgs);
+    }
+    let size = file.size();
+    if size > MAX_MANIFEST_LEN {
+        println!("verifyfs: manifest too large");
+        return Err(Error::BadArgs);
+    }
+    let mut buf = vec![0u8; size];
+    if file.read(0, &mut buf)? != size {
+        return Err(Error::FsRead);
+    }
+    let text = core::str::from_utf8(&buf).map_err(|_| Error::Utf8)?;
+    let mut verified = Verified::default();
+    for (lineno, line) in text.lines().enumerate() {
+        let Some((expected, path)) =
+            parse_manifest_line(line).inspect_err(|_| {
+                println!("verifyfs: {manifest}:{}: malformed", lineno + 1)
+            })?
+        else {
+            continue;
+        };
+        match sha256(fs, &path, poll) {
+            Ok(digest) if digest == expected => verified.good += 1,
+            Err(e) if e.is(Error::Cancelled) => return Err(e),
+            Ok(_) => {
+                println!("MISMATCH {path}");
+                verified.bad += 1;
+            }
+            Err(e) if e.is(Error::FsNoFile) => {
+                println!("MISSING  {path}");
+                verified.missing += 1;
+            }
+            Err(e) => {
+                println!("FAILED   {path}: {e:?}");
+                verified.failed += 1;
+            }
+        }
+    }
+    Ok(verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::Read;
+
+    #[test]
+    #[cfg(feature = "cmd-files")]
+    fn glob_patterns() {
+        assert!(glob_match(b"*.conf", b"system.conf"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"k?rnel*", b"kernel.bak"));
+        assert!(!glob_match(b"*.conf", b"etc/system.conf"));
+        assert!(!glob_match(b"?", b""));
+        assert!(!glob_match(b"unix", b"unix2"));
+        assert_eq!(join("/", "etc"), "/etc");
+        assert_eq!(join("/etc/", "system"), "/etc/system");
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-files")]
+    fn find_matches() {
+        let hits =
+            |h: &[u8], p: &[u8], i| find_all(h, p, i).collect::<Vec<_>>();
+        assert_eq!(hits(b"aXbxAx", b"x", false), [3, 5]);
+        assert_eq!(hits(b"aXbxAx", b"x", true), [1, 3, 5]);
+        assert_eq!(hits(b"aaaa", b"aa", false), [0, 1, 2]);
+        assert_eq!(hits(b"a", b"aa", false), []);
+    }
+
+    /// A file of 0x3000 bytes whose middle page is a hole.
+    #[derive(Clone)]
+    struct Sparse;
+
+    impl Sparse {
+        const HOLE: core::ops::Range<usize> = 0x1000..0x2000;
+    }
+
+    impl io::Read for Sparse {
+        fn read(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+            let off = off as usize;
+            let len = usize::min(dst.len(), self.size().saturating_sub(off));
+            for (k, b) in dst[..len].iter_mut().enumerate() {
+                let at = off + k;
+                *b = if Self::HOLE.contains(&at) { 0 } else { at as u8 | 1 };
+            }
+            Ok(len)
+        }
+
+        fn size(&self) -> usize {
+            0x3000
+        }
+    }
+
+    impl File for Sparse {
+        fn file_type(&self) -> FileType {
+            FileType::Regular
+        }
+
+        fn extent(&self, offset: u64, max: usize) -> Result<Extent> {
+            let off = offset as usize;
+            let (hole, end) = if off < Self::HOLE.start {
+                (false, Self::HOLE.start)
+            } else if off < Self::HOLE.end {
+                (true, Self::HOLE.end)
+            } else {
+                (false, self.size())
+            };
+            let len = usize::min(end.saturating_sub(off), max);
+            Ok(if hole { Extent::Hole(len) } else { Extent::Data(len) })
+        }
+    }
+
+    impl FileSystem for Sparse {
+        fn open(&self, _path: &str) -> Result<Box<dyn File>> {
+            Ok(Box::new(Sparse))
+        }
+
+        fn stat(&self, _path: &str) -> Result<DirEntry> {
+            Err(Error::FsNoFile)
+        }
+
+        fn readdir(&self, _path: &str) -> Result<Vec<DirEntry>> {
+            Err(Error::FsNoFile)
+        }
+
+        fn as_str(&self) -> &str {
+            "sparse"
+        }
+    }
+
+    #[test]
+    fn holes() {
+        use sha2::{Digest, Sha256};
+        let mut expected = vec![0u8; Sparse.size()];
+        Sparse.read(0, &mut expected).unwrap();
+        let mut dst = vec![0xffu8; 0x3800];
+        dst[Sparse::HOLE.start + 0x800..].fill(0);
+        let len = copy(&Sparse, "f", &mut dst, &mut || Ok(())).unwrap();
+        assert_eq!(len, 0x3000);
+        assert_eq!(&dst[..len], &expected[..]);
+        assert_eq!(&dst[len..], &[0; 0x800]);
+        let sum = sha256(&Sparse, "f", &mut || Ok(())).unwrap();
+        assert_eq!(sum, <[u8; 32]>::from(Sha256::digest(&expected)));
+    }
+
+    #[test]
+    fn writes() {
+        assert_eq!(
+            write(&Sparse, "f", 0x2ff0, &[0; 0x20]),
+            Err(Error::FsOffset)
+        );
+        assert_eq!(write(&Sparse, "f", usize::MAX, b"x"), Err(Error::FsOffset));
+        assert_eq!(write(&Sparse, "f", 0, b"x"), Err(Error::FsReadOnly));
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("//"), "/");
+        assert_eq!(normalize("/.."), "/");
+        assert_eq!(normalize("/a/./b//c/"), "/a/b/c");
+        assert_eq!(normalize("/a/b/../../c"), "/c");
+        assert_eq!(normalize("a/../b"), "a/../b");
+    }
+
+    #[test]
+    fn builtin_paths() {
+        assert_eq!(builtin_path("/builtin"), Some("/"));
+        assert_eq!(builtin_path("/builtin/"), Some("/"));
+        assert_eq!(builtin_path("/builtin/memtest"), Some("/memtest"));
+        assert_eq!(builtin_path("/builtins/memtest"), None);
+        assert_eq!(builtin_path("/platform/builtin"), None);
+        assert_eq!(builtin_path("builtin/memtest"), None);
+    }
+
+    #[test]
+    fn mount_table() {
+        let page_table =
+            mmu::LoaderPageTable::new(mmu::PageTable::new(), &[], &[]);
+        let mounted = |bs: &'static [u8]| {
+            let fs = Box::new(cpio::FileSystem::try_new(bs).unwrap());
+            Mounted::new(fs, page_table.buf(bs))
+        };
+        let (root, a, b) = (b"070701 root", b"070701 a", b"070701 b");
+        let mut mounts = Mounts::default();
+        assert!(mounts.find("/etc/motd").is_none());
+        mounts.mount("/mnt/a/", mounted(a)).unwrap();
+        mounts.mount("/", mounted(root)).unwrap();
+        mounts.mount("/mnt/a/b", mounted(b)).unwrap();
+        fn find(mounts: &Mounts, path: &str) -> (usize, String) {
+            let (m, inner) = mounts.find(path).unwrap();
+            (m.buf().addr(), inner)
+        }
+        assert_eq!(
+            find(&mounts, "/etc/motd"),
+            (root.as_ptr().addr(), "/etc/motd".into())
+        );
+        assert_eq!(find(&mounts, "/mnt/a"), (a.as_ptr().addr(), "/".into()));
+        assert_eq!(
+            find(&mounts, "/mnt/a/unix"),
+            (a.as_ptr().addr(), "/unix".into())
+        );
+        assert_eq!(
+            find(&mounts, "/mnt/a/b/c"),
+            (b.as_ptr().addr(), "/c".into())
+        );
+        assert_eq!(
+            find(&mounts, "/mnt/ab"),
+            (root.as_ptr().addr(), "/mnt/ab".into())
+        );
+        let dirs = mounts.iter().map(|(dir, _)| dir).collect::<Vec<_>>();
+        assert_eq!(dirs, ["/", "/mnt/a", "/mnt/a/b"]);
+        assert_eq!(mounts.root().unwrap().buf().addr(), root.as_ptr().addr());
+
+        mounts.mount("/mnt/a", mounted(b)).unwrap();
+        assert_eq!(
+            find(&mounts, "/mnt/a/unix"),
+            (b.as_ptr().addr(), "/unix".into())
+        );
+        mounts.umount("/mnt/a").unwrap();
+        assert_eq!(
+            find(&mounts, "/mnt/a/unix"),
+            (root.as_ptr().addr(), "/mnt/a/unix".into())
+        );
+        assert_eq!(mounts.umount("/mnt/a").err(), Some(Error::FsNoRoot));
+        mounts.umount("//").unwrap();
+        assert!(mounts.root().is_none());
+        for dir in ["mnt", "/mnt//a", "/mnt/../a", "/builtin/x", "/builtin"] {
+            assert_eq!(mount_point(dir), Err(Error::FsInvPath), "{dir}");
+        }
+
+        mounts.mount("/", mounted(root)).unwrap();
+        mounts.mount("/mnt/a", mounted(a)).unwrap();
+        let (root, a) = (root.as_ptr().addr(), a.as_ptr().addr());
+        let cases = [
+            ("/mnt/a/../b", root, "/mnt/b"),
+            ("//mnt/a/x", a, "/x"),
+            ("/mnt//a/x", a, "/x"),
+            ("/mnt/./a/x", a, "/x"),
+            ("/mnt/a/./x/", a, "/x"),
+            ("/mnt/a/..", root, "/mnt"),
+            ("/mnt/a/x/../../a/y", a, "/y"),
+            ("/../../mnt/a", a, "/"),
+        ];
+        for (path, fs, inner) in cases {
+            assert_eq!(find(&mounts, path), (fs, inner.into()), "{path}");
+        }
+    }
+
+    #[test]
+    fn list_formatting() {
+        assert_eq!(mode_string(FileType::Dir, 0o755), "drwxr-xr-x");
+        assert_eq!(mode_string(FileType::Regular, 0o4644), "-rwSr--r--");
+        assert_eq!(mode_string(FileType::Dir, 0o1777), "drwxrwxrwt");
+        assert_eq!(mode_string(FileType::SymLink, 0o2777), "lrwxrwsrwx");
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(1023), "1023");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(10 * 1024), "10K");
+        assert_eq!(human_size(3 * 1024 * 1024 + 1), "3.1M");
+        assert_eq!(human_size(1023 * 1024 * 1024), "1023M");
+    }
+
+    #[test]
+    fn list_sorting() {
+        let entry = |name: &str, ino, size| DirEntry {
+            name: name.into(),
+            ino,
+            file_type: FileType::Regular,
+            perms: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size,
+            allocated: size,
+        };
+        let mut entries =
+            [entry("b", 3, 10), entry("c", 1, 30), entry("a", 2, 20)];
+        let names = |es: &[DirEntry]| {
+            es.iter().map(|e| e.name.clone()).collect::<Vec<_>>().join("")
+        };
+        sort(&mut entries, SortBy::None);
+        assert_eq!(names(&entries), "bca");
+        sort(&mut entries, SortBy::Name);
+        assert_eq!(names(&entries), "abc");
+        sort(&mut entries, SortBy::Size);
+        assert_eq!(names(&entries), "cab");
+        sort(&mut entries, SortBy::Inode);
+        assert_eq!(names(&entries), "cab");
+    }
+
+    #[test]
+    fn manifest_lines() {
+        let hex = "00112233445566778899aabbccddeeff\
+                   00112233445566778899AABBCCDDEEFF";
+        let line = alloc::format!("{hex}  ./platform/oxide/unix");
+        let (digest, path) = parse_manifest_line(&line).unwrap().unwrap();
+        assert_eq!(digest[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(digest[31], 0xff);
+        assert_eq!(path, "/platform/oxide/unix");
+        let line = alloc::format!("{hex} *etc/system");
+        let (_, path) = parse_manifest_line(&line).unwrap().unwrap();
+        assert_eq!(path, "/etc/system");
+        assert!(parse_manifest_line("  # comment").unwrap().is_none());
+        assert!(parse_manifest_line("").unwrap().is_none());
+        assert!(parse_manifest_line("abcd  /etc/system").is_err());
+        assert!(parse_manifest_line(hex).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Definitions of the fields of commonly examined registers.
+//!
+//! These are used to annotate values read from hardware with
+//! the names of their fields.  Only registers that are stable
+//! across the processors we support are described here;
+//! undefined bits are simply left unnamed.
+//!
+//! Registers that are reached by address may also say how they
+//! must be accessed: the widths the hardware accepts, and what
+//! reading or writing them does besides move data.  The commands
+//! that peek and poke registers consult these, so that a 32-bit
+//! only register is not byte-poked by mistake.
+
+use core::ops::Range;
+
+/// A named range of bits within a register.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(feature = "cmd-debugger"), allow(dead_code))]
+pub(crate) struct Field {
+    pub(crate) name: &'static str,
+    pub(crate) start: u8,
+    pub(crate) end: u8,
+}
+
+#[cfg_attr(not(feature = "cmd-debugger"), allow(dead_code))]
+impl Field {
+    const fn bit(name: &'static str, bit: u8) -> Field {
+        Field { name, start: bit, end: bit + 1 }
+    }
+
+    const fn bits(name: &'static str, start: u8, end: u8) -> Field {
+        Field { name, start, end }
+    }
+
+    pub(crate) fn range(&self) -> Range<usize> {
+        usize::from(self.start)..usize::from(self.end)
+    }
+
+    /// Returns true iff the field contains the given bit.
+    pub(crate) fn contains(&self, bit: usize) -> bool {
+        self.range().contains(&bit)
+    }
+
+    /// Extracts the value of the field from a register value.
+    pub(crate) fn get(&self, value: u128) -> u128 {
+        let width = self.end - self.start;
+        (value >> self.start) & (!0u128 >> (128 - u32::from(width)))
+    }
+}
+
+/// The address spaces in which registers are found.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Space {
+    Mmio,
+    Smn,
+}
+
+/// What accessing a register does besides transfer data.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Effect {
+    None,
+    /// Reading the register clears some of its bits.
+    ReadClears,
+}
+
+/// Where a register is found, and how it may be accessed.
+#[derive(Debug)]
+pub(crate) struct Access {
+    pub(crate) space: Space,
+    pub(crate) addr: u64,
+    /// The access widths, in bytes, that the hardware accepts.
+    pub(crate) sizes: &'static [usize],
+    pub(crate) effect: Effect,
+}
+
+/// A register and its fields, ordered from least to most
+/// significant.
+#[derive(Debug)]
+pub(crate) struct RegDef {
+    pub(crate) name: &'static str,
+    pub(crate) width: u8,
+    #[cfg_attr(not(feature = "cmd-debugger"), allow(dead_code))]
+    pub(crate) fields: &'static [Field],
+    pub(crate) access: Option<Access>,
+}
+
+impl RegDef {
+    /// Returns the field containing the given bit, if any.
+    #[cfg_attr(not(feature = "cmd-debugger"), allow(dead_code))]
+    pub(crate) fn field_at(&self, bit: usize) -> Option<&'static Field> {
+        self.fields.iter().find(|field| field.contains(bit))
+    }
+
+    /// Returns the range of addresses the register occupies, if
+    /// it is reached by address in the given space.
+    fn span(&self, space: Space) -> Option<Range<u64>> {
+        let access = self.access.as_ref().filter(|a| a.space == space)?;
+        Some(access.addr..access.addr + u64::from(self.width / 8))
+    }
+
+    /// Returns true iff the register accepts an access of `len`
+    /// bytes at `addr`: one that starts at the register, and is
+    /// of a width the hardware accepts.
+    pub(crate) fn accepts(&self, addr: u64, len: usize) -> bool {
+        self.access
+            .as_ref()
+            .is_some_and(|a| a.addr == addr && a.sizes.contains(&len))
+    }
+
+    /// Returns a warning describing the side effect of reading,
+    /// or writing, the register, if it has one.
+    pub(crate) fn side_effect(&self, write: bool) -> Option<&'static str> {
+        match (self.access.as_ref()?.effect, write) {
+            (Effect::ReadClears, false) => Some("reading clears bits"),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) const REGDEFS: &[RegDef] = &[
+    RegDef {
+        name: "cr0",
+        width: 64,
+        fields: &[
+            Field::bit("PE", 0),
+            Field::bit("MP", 1),
+            Field::bit("EM", 2),
+            Field::bit("TS", 3),
+            Field::bit("ET", 4),
+            Field::bit("NE", 5),
+            Field::bit("WP", 16),
+            Field::bit("AM", 18),
+            Field::bit("NW", 29),
+            Field::bit("CD", 30),
+            Field::bit("PG", 31),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "cr4",
+        width: 64,
+        fields: &[
+            Field::bit("VME", 0),
+            Field::bit("PVI", 1),
+            Field::bit("TSD", 2),
+            Field::bit("DE", 3),
+            Field::bit("PSE", 4),
+            Field::bit("PAE", 5),
+            Field::bit("MCE", 6),
+            Field::bit("PGE", 7),
+            Field::bit("PCE", 8),
+            Field::bit("OSFXSR", 9),
+            Field::bit("OSXMMEXCPT", 10),
+            Field::bit("UMIP", 11),
+            Field::bit("LA57", 12),
+            Field::bit("FSGSBASE", 16),
+            Field::bit("PCIDE", 17),
+            Field::bit("OSXSAVE", 18),
+            Field::bit("SMEP", 20),
+            Field::bit("SMAP", 21),
+            Field::bit("PKE", 22),
+            Field::bit("CET", 23),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "efer",
+        width: 64,
+        fields: &[
+            Field::bit("SCE", 0),
+            Field::bit("LME", 8),
+            Field::bit("LMA", 10),
+            Field::bit("NXE", 11),
+            Field::bit("SVME", 12),
+            Field::bit("LMSLE", 13),
+            Field::bit("FFXSR", 14),
+            Field::bit("TCE", 15),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "pcicmd",
+        width: 16,
+        fields: &[
+            Field::bit("IOSE", 0),
+            Field::bit("MSE", 1),
+            Field::bit("BME", 2),
+            Field::bit("SCE", 3),
+            Field::bit("MWIE", 4),
+            Field::bit("VGASE", 5),
+            Field::bit("PERE", 6),
+            Field::bit("SERRE", 8),
+            Field::bit("FBE", 9),
+            Field::bit("INTXD", 10),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "pcists",
+        width: 16,
+        fields: &[
+            Field::bit("INTS", 3),
+            Field::bit("CAPL", 4),
+            Field::bit("C66", 5),
+            Field::bit("FBC", 7),
+            Field::bit("MDPE", 8),
+            Field::bits("DEVT", 9, 11),
+            Field::bit("STA", 11),
+            Field::bit("RTA", 12),
+            Field::bit("RMA", 13),
+            Field::bit("SSE", 14),
+            Field::bit("DPE", 15),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "rflags",
+        width: 64,
+        fields: &[
+            Field::bit("CF", 0),
+            Field::bit("PF", 2),
+            Field::bit("AF", 4),
+            Field::bit("ZF", 6),
+            Field::bit("SF", 7),
+            Field::bit("TF", 8),
+            Field::bit("IF", 9),
+            Field::bit("DF", 10),
+            Field::bit("OF", 11),
+            Field::bits("IOPL", 12, 14),
+            Field::bit("NT", 14),
+            Field::bit("RF", 16),
+            Field::bit("VM", 17),
+            Field::bit("AC", 18),
+            Field::bit("VIF", 19),
+            Field::bit("VIP", 20),
+            Field::bit("ID", 21),
+        ],
+        access: None,
+    },
+    RegDef {
+        name: "tcon_cur_tmp",
+        width: 32,
+        fields: &[
+            Field::bits("TJ_SEL", 16, 18),
+            Field::bit("RANGE_SEL", 19),
+            Field::bits("CUR_TEMP", 21, 32),
+        ],
+        access: Some(Access {
+            space: Space::Smn,
+            addr: 0x5_9800,
+            sizes: &[4],
+            effect: Effect::None,
+        }),
+    },
+    // The console UART's line and status registers.  The
+    // DesignWare UART accepts only 32-bit accesses.
+    RegDef {
+        name: "uart0_lsr",
+        width: 32,
+        fields: &[
+            Field::bit("DR", 0),
+            Field::bit("OE", 1),
+            Field::bit("PE", 2),
+            Field::bit("FE", 3),
+            Field::bit("BI", 4),
+            Field::bit("THRE", 5),
+            Field::bit("TEMT", 6),
+            Field::bit("RFE", 7),
+        ],
+        access: Some(Access {
+            space: Space::Mmio,
+            addr: 0xfedc_9014,
+            sizes: &[4],
+            effect: Effect::ReadClears,
+        }),
+    },
+    RegDef {
+        name: "uart0_usr",
+        width: 32,
+        fields: &[
+            Field::bit("BUSY", 0),
+            Field::bit("TFNF", 1),
+            Field::bit("TFE", 2),
+            Field::bit("RFNE", 3),
+            Field::bit("RFF", 4),
+        ],
+        access: Some(Access {
+            space: Space::Mmio,
+            addr: 0xfedc_907c,
+            sizes: &[4],
+            effect: Effect::None,
+        }),
+    },
+];
+
+/// Looks up a register definition by name, ignoring case.
+#[cfg_attr(not(feature = "cmd-debugger"), allow(dead_code))]
+pub(crate) fn lookup(name: &str) -> Option<&'static RegDef> {
+    REGDEFS.iter().find(|def| def.name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the register, if any, reached by address in the
+/// given space that overlaps an access of `len` bytes at `addr`.
+pub(crate) fn find(
+    space: Space,
+    addr: u64,
+    len: usize,
+) -> Option<&'static RegDef> {
+    let end = addr.saturating_add(len as u64);
+    REGDEFS.iter().find(|def| {
+        def.span(space).is_some_and(|span| span.start < end && addr < span.end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions_are_consistent() {
+        for def in REGDEFS {
+            for (k, field) in def.fields.iter().enumerate() {
+                assert!(field.start < field.end, "{}.{}", def.name, field.name);
+                assert!(field.end <= def.width, "{}.{}", def.name, field.name);
+                for other in &def.fields[k + 1..] {
+                    assert!(
+                        other.start >= field.end || other.end <= field.start,
+                        "{}.{} overlaps {}",
+                        def.name,
+                        field.name,
+                        other.name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let tmp = lookup("TCON_CUR_TMP").unwrap();
+        let raw = 400 << 21 | 1 << 19;
+        assert_eq!(tmp.field_at(22).unwrap().name, "CUR_TEMP");
+        assert_eq!(tmp.field_at(22).unwrap().get(raw), 400);
+        assert_eq!(tmp.field_at(19).unwrap().get(raw), 1);
+        assert!(tmp.field_at(0).is_none());
+        assert!(lookup("nonesuch").is_none());
+    }
+
+    #[test]
+    fn access() {
+        let lsr = find(Space::Mmio, 0xfedc_9014, 4).unwrap();
+        assert_eq!(lsr.name, "uart0_lsr");
+        assert!(lsr.accepts(0xfedc_9014, 4));
+        assert!(!lsr.accepts(0xfedc_9014, 1));
+        assert!(!lsr.accepts(0xfedc_9016, 2));
+        assert_eq!(lsr.side_effect(false), Some("reading clears bits"));
+        assert_eq!(lsr.side_effect(true), None);
+        let usr = find(Space::Mmio, 0xfedc_9078, 8).unwrap();
+        assert_eq!(usr.name, "uart0_usr");
+        assert!(!usr.accepts(0xfedc_9078, 8));
+        assert!(find(Space::Mmio, 0xfedc_9010, 4).is_none());
+        assert!(find(Space::Smn, 0xfedc_9014, 1).is_none());
+        let tmp = find(Space::Smn, 0x5_9800, 4).unwrap();
+        assert_eq!(tmp.name, "tcon_cur_tmp");
+        assert!(tmp.accepts(0x5_9800, 4));
+        assert_eq!(tmp.side_effect(false), None);
+    }
+}
@@ -0,0 +1,212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Address arithmetic.
+//!
+//! `addr` evaluates an expression over numbers and the names of
+//! the loader's regions, such as `ramdisk_base + 0x4000`, so that
+//! addresses need not be worked out by hand from the
+//! configuration dump.
+
+use super::reader;
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// A token of an expression.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Tok<'a> {
+    Num(u128),
+    Name(&'a str),
+    Op(char),
+}
+
+const OPS: &str = "+-*/()";
+
+fn lex(s: &str) -> Result<Vec<Tok<'_>>> {
+    let mut toks = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if OPS.contains(c) {
+            toks.push(Tok::Op(c));
+            rest = rest[1..].trim_start();
+            continue;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || OPS.contains(c))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            toks.push(Tok::Num(reader::parse_len(word)?));
+        } else {
+            toks.push(Tok::Name(word));
+        }
+        rest = rest[end..].trim_start();
+    }
+    Ok(toks)
+}
+
+/// A recursive descent evaluator for the usual grammar, in
+/// which `*` and `/` bind more tightly than `+` and `-`.
+struct Eval<'a, 'n> {
+    toks: &'a [Tok<'a>],
+    names: &'n [(&'n str, Range<usize>)],
+}
+
+impl<'a> Eval<'a, '_> {
+    fn next(&mut self) -> Option<Tok<'a>> {
+        let (&tok, rest) = self.toks.split_first()?;
+        self.toks = rest;
+        Some(tok)
+    }
+
+    fn peek_op(&self, ops: &str) -> Option<char> {
+        match self.toks.first() {
+            Some(&Tok::Op(op)) if ops.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self) -> Result<u128> {
+        let mut value = self.term()?;
+        while let Some(op) = self.peek_op("+-") {
+            self.next();
+            let rhs = self.term()?;
+            value = match op {
+                '+' => value.checked_add(rhs),
+                _ => value.checked_sub(rhs),
+            }
+            .ok_or(Error::NumRange)?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<u128> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.peek_op("*/") {
+            self.next();
+            let rhs = self.factor()?;
+            value = match op {
+                '*' => value.checked_mul(rhs),
+                _ => value.checked_div(rhs),
+            }
+            .ok_or(Error::NumRange)?;
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<u128> {
+        match self.next() {
+            Some(Tok::Num(n)) => Ok(n),
+            Some(Tok::Name(name)) => {
+                lookup(self.names, name).ok_or_else(|| {
+                    Value::Str(name.into()).bad_arg("number or region name")
+                })
+            }
+            Some(Tok::Op('(')) => {
+                let value = self.expr()?;
+                match self.next() {
+                    Some(Tok::Op(')')) => Ok(value),
+                    _ => Err(Error::BadArgs),
+                }
+            }
+            _ => Err(Error::BadArgs),
+        }
+    }
+}
+
+/// Returns the value of a name derived from a region: the
+/// region's name or `<name>_base` for its start, `<name>_end`
+/// for its end, and `<name>_len` for its length.
+fn lookup(names: &[(&str, Range<usize>)], name: &str) -> Option<u128> {
+    names.iter().find_map(|(region, range)| {
+        let suffix = name.strip_prefix(region)?;
+        let value = match suffix {
+            "" | "_base" => range.start,
+            "_end" => range.end,
+            "_len" => range.end - range.start,
+            _ => return None,
+        };
+        Some(value as u128)
+    })
+}
+
+/// Evaluates an expression, given the region names.
+fn eval(names: &[(&str, Range<usize>)], expr: &str) -> Result<u128> {
+    let toks = lex(expr)?;
+    let mut eval = Eval { toks: &toks, names };
+    let value = eval.expr()?;
+    if !eval.toks.is_empty() {
+        return Err(Error::BadArgs);
+    }
+    Ok(value)
+}
+
+/// Returns the names of the loader's regions and their ranges.
+fn names(config: &bldb::Config) -> Vec<(&'static str, Range<usize>)> {
+    let loader = &config.loader_region;
+    let mut names =
+        Vec::from([("loader", loader.start.addr()..loader.end.addr())]);
+    for (name, region) in config.regions() {
+        names.push((name, region.start().addr()..region.end().addr()));
+    }
+    names
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: addr <expression>");
+        error
+    };
+    let mut expr = String::new();
+    loop {
+        let arg = repl::popenv(env);
+        let word = match arg {
+            Value::Nil => break,
+            Value::Str(s) => s,
+            Value::Unsigned(n) => format!("{n:#x}"),
+            _ => return Err(usage(arg.bad_arg("number, name, or operator"))),
+        };
+        expr.push_str(&word);
+        expr.push(' ');
+    }
+    if expr.is_empty() {
+        return Err(usage(Error::BadArgs));
+    }
+    let value = eval(&names(config), &expr).map_err(usage)?;
+    Ok(Value::Unsigned(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let names = [
+            ("xfer", 0x1000_0000..0x1400_0000),
+            ("ramdisk", 0x1400_0000..0x2000_0000),
+        ];
+        assert_eq!(eval(&names, "ramdisk_base + 0x4000"), Ok(0x1400_4000));
+        assert_eq!(eval(&names, "xfer_end - 1M"), Ok(0x13f0_0000));
+        assert_eq!(eval(&names, "xfer_len/4k"), Ok(0x4000));
+        assert_eq!(eval(&names, "ramdisk+2*(1+1)"), Ok(0x1400_0004));
+        assert_eq!(eval(&names, "2+3*4"), Ok(14));
+        assert_eq!(eval(&names, "xfer - 1"), Ok(0x0fff_ffff));
+        assert_eq!(eval(&names, "0 - 1"), Err(Error::NumRange));
+        assert_eq!(eval(&names, "1 / 0"), Err(Error::NumRange));
+        assert_eq!(eval(&names, "(1 + 2"), Err(Error::BadArgs));
+        assert_eq!(eval(&names, "1 2"), Err(Error::BadArgs));
+        assert!(eval(&names, "xfer_start").is_err());
+        assert!(eval(&names, "bogus + 1").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Aliases, which are shorthands for command lines.
+//!
+//! When the first word of a line typed at the prompt names an
+//! alias, it is replaced by the line the alias stands for before
+//! the line is parsed, and the rest of the line follows it, so
+//! that arguments may be added to the last command in the
+//! expansion.  Expansion is not recursive.
+//!
+//! Aliases are defined and removed by reader commands, which see
+//! the line as typed: an expansion is usually a pipeline, and
+//! would otherwise be split at its `.` or `|` separators.
+
+use super::{commands, reader};
+use crate::bldb;
+use crate::println;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+
+/// Characters with meaning to the reader, which may not appear
+/// in the name of an alias.
+const SPECIAL: &[char] = &['@', '#', '$', '.', '|', '>', '=', '/'];
+
+/// Returns the line with an alias in its first word expanded.
+pub(super) fn expand<'a>(
+    aliases: &BTreeMap<String, String>,
+    line: &'a str,
+) -> Cow<'a, str> {
+    let line = line.trim_start();
+    let name = line.split_ascii_whitespace().next().unwrap_or_default();
+    match aliases.get(name) {
+        Some(expansion) => {
+            Cow::Owned(format!("{expansion}{}", &line[name.len()..]))
+        }
+        None => Cow::Borrowed(line),
+    }
+}
+
+fn list(aliases: &BTreeMap<String, String>) {
+    if aliases.is_empty() {
+        println!("no aliases");
+    }
+    for (name, expansion) in aliases.iter() {
+        println!("{name} = {expansion}");
+    }
+}
+
+/// Defines an alias, given `<name> = <expansion>`, or shows one
+/// or all of them.
+pub(super) fn alias(config: &mut bldb::Config, args: &str) {
+    let args = args.trim();
+    if args.is_empty() {
+        list(&config.aliases);
+        return;
+    }
+    let Some((name, expansion)) = args.split_once('=') else {
+        match config.aliases.get(args) {
+            Some(expansion) => println!("{args} = {expansion}"),
+            None => println!("alias: no alias named '{args}'"),
+        }
+        return;
+    };
+    let (name, expansion) = (name.trim(), expansion.trim());
+    if name.is_empty()
+        || name.contains(|c: char| c.is_ascii_whitespace())
+        || name.contains(SPECIAL)
+    {
+        println!("alias: '{name}' is not a valid name");
+        return;
+    }
+    if reader::READER_COMMANDS.contains(&name) || name == "unalias" {
+        println!("alias: '{name}' is a reader command");
+        return;
+    }
+    if expansion.is_empty() {
+        println!("usage: alias [<name> [= <expansion>]]");
+        return;
+    }
+    if commands::names().any(|command| command == name) {
+        println!("alias: '{name}' hides the command of that name");
+    }
+    config.aliases.insert(name.into(), expansion.into());
+}
+
+/// Removes an alias.
+pub(super) fn unalias(config: &mut bldb::Config, name: &str) {
+    let name = name.trim();
+    if config.aliases.remove(name).is_none() {
+        println!("unalias: no alias named '{name}'");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+
+    #[test]
+    fn expansion() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert(String::from("b"), String::from("call . load /unix"));
+        assert_eq!(expand(&aliases, "b"), "call . load /unix");
+        assert_eq!(expand(&aliases, "  b 1 2"), "call . load /unix 1 2");
+        assert_eq!(expand(&aliases, "bb 1"), "bb 1");
+        assert_eq!(expand(&aliases, "push b"), "push b");
+        assert_eq!(expand(&aliases, ""), "");
+    }
+
+    #[test]
+    fn sessions() {
+        let mut sim = Sim::new();
+        let out = sim.session("alias two = push 2\ntwo 3\nenv\npush\n");
+        assert!(out.contains("[0]: 0x2\n[1]: 0x3\n"), "{out}");
+        let out = sim.session("alias\nalias two\npush\n");
+        assert!(out.contains("two = push 2\n"), "{out}");
+        let out = sim.session("alias a.b = x\nalias help = x\nalias\npush\n");
+        assert!(out.contains("'a.b' is not a valid name"), "{out}");
+        assert!(out.contains("'help' is a reader command"), "{out}");
+        let out = sim.session("alias depth = push 9 . pop\ndepth\n");
+        assert!(out.contains("hides the command"), "{out}");
+        assert!(out.ends_with("res: nil\n"), "{out}");
+        let out = sim.session("unalias two\nunalias two\ntwo\n");
+        assert!(out.contains("no alias named 'two'"), "{out}");
+        assert!(out.contains("Unknown command"), "{out}");
+    }
+}
@@ -0,0 +1,306 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An audit log of SMN, PCIe configuration space, and MMIO
+//! accesses made from the REPL.
+//!
+//! When enabled, the register access commands record each read
+//! and write.  The log can be exported as a bldb script that
+//! replays the accesses, or as Rust arrays of the writes, ready
+//! to be pasted into initialization code once interactive
+//! exploration has found a working sequence.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// The number of accesses retained; older entries are dropped.
+const MAX_ENTRIES: usize = 4096;
+
+/// A recorded register access.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Access {
+    Smn {
+        index: u8,
+        addr: u32,
+        value: u32,
+        write: bool,
+    },
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    Ecam {
+        bdf: (u8, u8, u8),
+        offset: u32,
+        value: u32,
+        write: bool,
+    },
+    /// An access to memory with `peek`, `poke`, or `pokev`,
+    /// typically of a device register; `value` is as the user
+    /// sees it.
+    Mmio {
+        addr: u64,
+        len: u8,
+        value: u128,
+        write: bool,
+    },
+}
+
+impl Access {
+    /// Writes the access as a bldb command line.  The values
+    /// read are not shown, as the REPL has no comment syntax.
+    fn script(&self, w: &mut impl Write) -> fmt::Result {
+        match *self {
+            Access::Smn { index: 0, addr, write: false, .. } => {
+                write!(w, "rdsmn {addr:#x}")
+            }
+            Access::Smn { index: 0, addr, value, write: true } => {
+                write!(w, "wrsmn {addr:#x} {value:#x}")
+            }
+            Access::Smn { index, addr, write: false, .. } => {
+                write!(w, "rdsmni {index} {addr:#x}")
+            }
+            Access::Smn { index, addr, value, write: true } => {
+                write!(w, "wrsmni {index} {addr:#x} {value:#x}")
+            }
+            Access::Ecam { bdf: (b, d, f), offset, write: false, .. } => {
+                write!(w, "ecamrd {b}/{d}/{f} {offset:#x}")
+            }
+            Access::Ecam { bdf: (b, d, f), offset, value, write: true } => {
+                write!(w, "ecamwr {b}/{d}/{f} {offset:#x} {value:#x}")
+            }
+            Access::Mmio { addr, len, write: false, .. } => {
+                write!(w, "peek {addr:#x},{len}")
+            }
+            Access::Mmio { addr, len, value, write: true } => {
+                write!(w, "poke {addr:#x},{len} {value:#x}")
+            }
+        }
+    }
+
+    /// Returns the name of the Rust array holding writes to
+    /// the same target as this access.
+    fn group(&self) -> String {
+        match self {
+            Access::Smn { index, .. } => alloc::format!("SMN{index}_WRITES"),
+            Access::Ecam { .. } => String::from("ECAM_WRITES"),
+            Access::Mmio { .. } => String::from("MMIO_WRITES"),
+        }
+    }
+
+    /// Returns the type of the elements of this access's array.
+    fn element_type(&self) -> &'static str {
+        match self {
+            Access::Smn { .. } => "(u32, u32)",
+            Access::Ecam { .. } => "((u8, u8, u8), u16, u32)",
+            Access::Mmio { .. } => "(u64, u8, u128)",
+        }
+    }
+
+    /// Writes the access as an element of a Rust array, with
+    /// reads rendered as comments.
+    fn rust(&self, w: &mut impl Write) -> fmt::Result {
+        match *self {
+            Access::Smn { addr, value, write: true, .. } => {
+                write!(w, "({addr:#010x}, {value:#010x}),")
+            }
+            Access::Smn { addr, value, write: false, .. } => {
+                write!(w, "// read {addr:#010x} == {value:#010x}")
+            }
+            Access::Ecam { bdf: (b, d, f), offset, value, write: true } => {
+                write!(w, "(({b}, {d}, {f}), {offset:#05x}, {value:#010x}),")
+            }
+            Access::Ecam { bdf: (b, d, f), offset, value, write: false } => {
+                write!(
+                    w,
+                    "// read ({b}, {d}, {f}) {offset:#05x} == {value:#010x}"
+                )
+            }
+            Access::Mmio { addr, len, value, write: true } => {
+                write!(w, "({addr:#x}, {len}, {value:#x}),")
+            }
+            Access::Mmio { addr, len, value, write: false } => {
+                write!(w, "// read {addr:#x},{len} == {value:#x}")
+            }
+        }
+    }
+}
+
+/// The access log itself.
+#[derive(Debug, Default)]
+pub(crate) struct AccessLog {
+    enabled: bool,
+    entries: VecDeque<Access>,
+    dropped: usize,
+}
+
+impl AccessLog {
+    /// Records an access, if logging is enabled.
+    pub(crate) fn record(&mut self, access: Access) {
+        if !self.enabled {
+            return;
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(access);
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Renders the log as a bldb script, one access per line.
+fn export_script(log: &AccessLog, w: &mut impl Write) -> fmt::Result {
+    for access in log.entries.iter() {
+        access.script(w)?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// Renders the log as Rust arrays of writes, one per target.
+/// Accesses are in order within each array, but the relative
+/// order of accesses to different targets is not preserved.
+fn export_rust(log: &AccessLog, w: &mut impl Write) -> fmt::Result {
+    let mut groups = Vec::<(String, &str)>::new();
+    for access in log.entries.iter() {
+        let group = access.group();
+        if !groups.iter().any(|(g, _)| *g == group) {
+            groups.push((group, access.element_type()));
+        }
+    }
+    for (group, typ) in groups {
+        writeln!(w, "const {group}: &[{typ}] = &[")?;
+        for access in log.entries.iter().filter(|a| a.group() == group) {
+            w.write_str("    ")?;
+            access.rust(w)?;
+            writeln!(w)?;
+        }
+        writeln!(w, "];")?;
+    }
+    Ok(())
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: audit [on | off | clear | show | export rust|script]");
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = match &arg {
+        Value::Nil => String::from("show"),
+        v => v.as_string().map_err(usage)?,
+    };
+    let log = &mut config.access_log;
+    let mut out = String::new();
+    match cmd.as_str() {
+        "on" => log.enabled = true,
+        "off" => log.enabled = false,
+        "clear" => {
+            log.entries.clear();
+            log.dropped = 0;
+        }
+        "show" | "export" => {
+            let format = match cmd.as_str() {
+                "show" => Value::Str(String::from("script")),
+                _ => repl::popenv(env),
+            };
+            let res = match format.as_string().map_err(usage)?.as_str() {
+                "script" => export_script(log, &mut out),
+                "rust" => export_rust(log, &mut out),
+                _ => return Err(usage(format.bad_arg("rust or script"))),
+            };
+            res.expect("formatting to a string");
+        }
+        _ => {
+            return Err(usage(arg.bad_arg("on, off, clear, show, or export")));
+        }
+    }
+    config.cons.puts(&out);
+    let state = if log.enabled { "on" } else { "off" };
+    println!("audit: {state}, {} accesses logged", log.entries.len());
+    if log.dropped != 0 {
+        println!("audit: {} earliest accesses dropped", log.dropped);
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports() {
+        let mut log = AccessLog::default();
+        let smn = |index, addr, value, write| Access::Smn {
+            index,
+            addr,
+            value,
+            write,
+        };
+        log.record(smn(0, 0x10, 1, true));
+        assert_eq!(log.len(), 0);
+        log.enabled = true;
+        log.record(smn(0, 0x5a000, 0xff, false));
+        log.record(smn(0, 0x5a000, 0x1, true));
+        let bdf = (0, 24, 0);
+        log.record(Access::Ecam { bdf, offset: 0x44, value: 7, write: true });
+        log.record(smn(2, 0x100, 0x2, true));
+        let addr = 0xfed8_0b00;
+        log.record(Access::Mmio { addr, len: 4, value: 0x81, write: true });
+        log.record(Access::Mmio { addr, len: 4, value: 0x1, write: false });
+
+        let mut script = String::new();
+        export_script(&log, &mut script).unwrap();
+        assert_eq!(
+            script,
+            "rdsmn 0x5a000\n\
+             wrsmn 0x5a000 0x1\n\
+             ecamwr 0/24/0 0x44 0x7\n\
+             wrsmni 2 0x100 0x2\n\
+             poke 0xfed80b00,4 0x81\n\
+             peek 0xfed80b00,4\n"
+        );
+
+        let mut rust = String::new();
+        export_rust(&log, &mut rust).unwrap();
+        assert_eq!(
+            rust,
+            "const SMN0_WRITES: &[(u32, u32)] = &[\n    \
+             // read 0x0005a000 == 0x000000ff\n    \
+             (0x0005a000, 0x00000001),\n\
+             ];\n\
+             const ECAM_WRITES: &[((u8, u8, u8), u16, u32)] = &[\n    \
+             ((0, 24, 0), 0x044, 0x00000007),\n\
+             ];\n\
+             const SMN2_WRITES: &[(u32, u32)] = &[\n    \
+             (0x00000100, 0x00000002),\n\
+             ];\n\
+             const MMIO_WRITES: &[(u64, u8, u128)] = &[\n    \
+             (0xfed80b00, 4, 0x81),\n    \
+             // read 0xfed80b00,4 == 0x1\n\
+             ];\n"
+        );
+    }
+
+    #[test]
+    fn bad_export_format() {
+        let mut sim = crate::repl::sim::Sim::new();
+        let out = sim.session("audit export bogus\n");
+        assert!(out.contains("arg 2: expected rust or script, got 'bogus'"));
+    }
+}
@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Changing the console's rate and framing.
+//!
+//! Once the console has switched, the terminal on the far end
+//! must be switched to match, and until it is, nothing can be
+//! typed at the REPL.  So a change must be confirmed, by
+//! pressing return at the new settings; if that does not happen
+//! in time, the old settings are restored.
+
+use crate::bldb;
+use crate::clock::Deadline;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::{self, Line, Uart};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How long the far end has to confirm a change.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times to try restoring the old settings, each of
+/// which waits for the transmitter to drain.
+const RESTORE_TRIES: usize = 3;
+
+/// Waits for a carriage return or newline.  Until the far end
+/// follows us, whatever it sends will be garbled, and may
+/// provoke framing or parity errors, which are ignored.
+fn confirmed(uart: &mut Uart) -> bool {
+    let deadline = Deadline::after(CONFIRM_TIMEOUT);
+    while !deadline.expired() {
+        let b = uart.try_getb_timeout(Duration::from_millis(100));
+        if let Ok(b'\r' | b'\n') = b {
+            return true;
+        }
+    }
+    false
+}
+
+/// Switches the UART to `new`, and back to `old` unless the
+/// change is confirmed.  Returns whether it was.  If the old
+/// settings cannot be restored, the UART is left at the new
+/// ones, and says so there, as that is the only place anyone
+/// might see it.
+fn switch(uart: &mut Uart, old: Line, new: Line) -> Result<bool> {
+    uart.set_line(new)?;
+    if confirmed(uart) {
+        return Ok(true);
+    }
+    let mut restored = uart.set_line(old);
+    for _ in 1..RESTORE_TRIES {
+        if restored.is_ok() {
+            break;
+        }
+        restored = uart.set_line(old);
+    }
+    restored.inspect_err(|_| {
+        println!("baud: cannot restore {old}; the console is still at {new}");
+    })?;
+    Ok(false)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: baud [<rate> [<framing>]]");
+        error
+    };
+    let old = uart::cons_line();
+    let baud = match repl::popenv(env) {
+        Value::Nil => {
+            println!("{old}");
+            return Ok(Value::Unsigned(old.baud().into()));
+        }
+        arg => arg.as_num::<u32>().map_err(usage)?,
+    };
+    let mut new = old.with_baud(baud).inspect_err(|_| {
+        println!("baud: cannot run the console at {baud} baud");
+    })?;
+    match repl::popenv(env) {
+        Value::Nil => {}
+        arg => {
+            let framing = arg.as_string().map_err(usage)?;
+            new = new.with_framing(&framing).map_err(usage)?;
+        }
+    }
+    println!(
+        "switching the console to {new}; press return within {} \
+         seconds to keep it",
+        CONFIRM_TIMEOUT.as_secs()
+    );
+    if !switch(&mut config.cons, old, new)? {
+        println!("no response; the console is back at {old}");
+        return Err(Error::Timeout);
+    }
+    println!("console at {new}");
+    Ok(Value::Unsigned(new.baud().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+
+    #[test]
+    fn switches() {
+        let old = uart::cons_line();
+        let new = old.with_baud(115_200).unwrap();
+        let mut uart = fakes::console([
+            Rx::Idle(Duration::from_secs(5)),
+            Rx::Error(Error::UartFraming),
+            Rx::bytes(b"\xfe\r"),
+        ]);
+        assert_eq!(switch(&mut uart, old, new), Ok(true));
+        assert!(fakes::exhausted());
+        assert_eq!(uart::cons_line(), new);
+
+        let mut uart = fakes::console([Rx::bytes(b"x")]);
+        let slow = old.with_baud(9600).unwrap();
+        assert_eq!(switch(&mut uart, new, slow), Ok(false));
+        assert_eq!(uart::cons_line(), new);
+        assert_eq!(uart.set_line(old), Ok(()));
+    }
+}
@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::beacon;
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+
+fn parse_phase(value: Value) -> Result<beacon::Phase> {
+    let name = value.as_string()?;
+    beacon::Phase::from_name(&name).ok_or_else(|| value.bad_arg("boot phase"))
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: beacon [pin <pin> <function> | off | \
+             pattern <phase> <pulses> | signal <phase>]"
+        );
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = match &arg {
+        Value::Nil => {
+            println!("{:?}", config.beacon);
+            return Ok(Value::Nil);
+        }
+        v => v.as_string().map_err(usage)?,
+    };
+    match cmd.as_str() {
+        "pin" => {
+            let pin = repl::popenv(env).as_num::<u8>().map_err(usage)?;
+            let function =
+                super::iomux::parse_func(repl::popenv(env)).map_err(usage)?;
+            if let Some(beacon) = config.beacon.take() {
+                beacon.off(config.gpios);
+            }
+            let beacon = beacon::Beacon::new(pin, function);
+            unsafe {
+                beacon.init(config.iomux, config.gpios);
+            }
+            config.beacon = Some(beacon);
+        }
+        "off" => {
+            if let Some(beacon) = config.beacon.take() {
+                beacon.off(config.gpios);
+            }
+        }
+        "pattern" => {
+            let phase = parse_phase(repl::popenv(env)).map_err(usage)?;
+            let arg = repl::popenv(env);
+            let pulses = arg.as_num::<u8>().map_err(usage)?;
+            if pulses > beacon::MAX_PULSES {
+                let expected = "at most 8 pulses";
+                return Err(usage(arg.bad_arg(expected)));
+            }
+            let beacon = config.beacon.as_mut().ok_or(Error::BadArgs)?;
+            beacon.set_pulses(phase, pulses);
+        }
+        "signal" => {
+            let phase = parse_phase(repl::popenv(env)).map_err(usage)?;
+            config.signal(phase);
+        }
+        _ => {
+            let expected = "pin, off, pattern, or signal";
+            return Err(usage(arg.bad_arg(expected)));
+        }
+    }
+    Ok(Value::Nil)
+}
@@ -0,0 +1,249 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Measurement of memory and MMIO bandwidth.
+//!
+//! Accesses are volatile and of a fixed width, so that the
+//! compiler neither elides nor widens them, and the timings
+//! reflect what the memory system delivers for that access
+//! pattern.  Whether the region is cached is determined by its
+//! mapping; to compare, map an alias of the same physical memory
+//! with `-c` and benchmark both.
+
+use crate::bldb;
+use crate::clock::Instant;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+use core::ptr;
+use core::time::Duration;
+
+/// Regions smaller than this are benchmarked over multiple
+/// passes, so that the timing is not dominated by overhead.
+const MIN_BYTES: usize = 64 * 1024 * 1024;
+const MAX_PASSES: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Read,
+    Write,
+    Copy,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Read => "read",
+            Op::Write => "write",
+            Op::Copy => "copy",
+        }
+    }
+}
+
+/// Reads every `T`-sized word of the region, returning their
+/// wrapping sum so that the reads have a consumer.
+fn read_pass<T: Copy + Into<u64>>(src: &[u8]) -> u64 {
+    let words = src.len() / size_of::<T>();
+    let p = src.as_ptr().cast::<T>();
+    (0..words).fold(0, |sum: u64, k| {
+        let w = unsafe { ptr::read_volatile(p.add(k)) };
+        sum.wrapping_add(w.into())
+    })
+}
+
+/// Writes every `T`-sized word of the region.
+fn write_pass<T: Copy>(dst: &mut [u8], value: T) {
+    let words = dst.len() / size_of::<T>();
+    let p = dst.as_mut_ptr().cast::<T>();
+    for k in 0..words {
+        unsafe { ptr::write_volatile(p.add(k), value) };
+    }
+}
+
+/// Copies `src` to `dst`, a `T`-sized word at a time.
+fn copy_pass<T: Copy>(src: &[u8], dst: &mut [u8]) {
+    let words = usize::min(src.len(), dst.len()) / size_of::<T>();
+    let s = src.as_ptr().cast::<T>();
+    let d = dst.as_mut_ptr().cast::<T>();
+    for k in 0..words {
+        unsafe { ptr::write_volatile(d.add(k), ptr::read_volatile(s.add(k))) };
+    }
+}
+
+/// Reads the region with accesses of the given width.
+fn read(width: usize, src: &[u8]) -> u64 {
+    match width {
+        1 => read_pass::<u8>(src),
+        2 => read_pass::<u16>(src),
+        4 => read_pass::<u32>(src),
+        _ => read_pass::<u64>(src),
+    }
+}
+
+/// Fills the region with a pattern using accesses of the given
+/// width.
+fn write(width: usize, dst: &mut [u8]) {
+    match width {
+        1 => write_pass::<u8>(dst, 0xa5),
+        2 => write_pass::<u16>(dst, 0xa5a5),
+        4 => write_pass::<u32>(dst, 0xa5a5_a5a5),
+        _ => write_pass::<u64>(dst, 0xa5a5_a5a5_a5a5_a5a5),
+    }
+}
+
+/// Copies the first half of the region to the second using
+/// accesses of the given width.
+fn copy(width: usize, region: &mut [u8]) {
+    let (src, dst) = region.split_at_mut(region.len() / 2);
+    match width {
+        1 => copy_pass::<u8>(src, dst),
+        2 => copy_pass::<u16>(src, dst),
+        4 => copy_pass::<u32>(src, dst),
+        _ => copy_pass::<u64>(src, dst),
+    }
+}
+
+/// The number of bytes moved by one pass of the operation over
+/// a region of the given length.  A copy moves half the region
+/// from one half to the other.
+fn pass_bytes(op: Op, width: usize, len: usize) -> usize {
+    match op {
+        Op::Read | Op::Write => len / width * width,
+        Op::Copy => len / 2 / width * width,
+    }
+}
+
+/// Converts bytes moved in the given number of TSC cycles to
+/// MB/s, using decimal megabytes as is conventional for
+/// bandwidth.
+fn mbps(bytes: u128, elapsed: Duration) -> u128 {
+    (bytes * 1000).checked_div(elapsed.as_nanos()).unwrap_or(0)
+}
+
+fn parse_op(value: &Value) -> Result<Vec<Op>> {
+    match value.as_string()?.as_str() {
+        "read" => Ok(alloc::vec![Op::Read]),
+        "write" => Ok(alloc::vec![Op::Write]),
+        "copy" => Ok(alloc::vec![Op::Copy]),
+        "all" => Ok(alloc::vec![Op::Read, Op::Write, Op::Copy]),
+        _ => Err(value.bad_arg("read, write, copy, or all")),
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: copybench <addr>,<len> [read|write|copy|all] \
+             [1|2|4|8] [<passes>]"
+        );
+        error
+    };
+    let region = repl::popenv(env);
+    let ops = match repl::popenv(env) {
+        Value::Nil => alloc::vec![Op::Read, Op::Write, Op::Copy],
+        v => parse_op(&v).map_err(usage)?,
+    };
+    let width = match repl::popenv(env) {
+        Value::Nil => 8,
+        v => match v.as_num::<usize>().map_err(usage)? {
+            w @ (1 | 2 | 4 | 8) => w,
+            _ => return Err(usage(v.bad_arg("width of 1, 2, 4, or 8"))),
+        },
+    };
+    let passes = match repl::popenv(env) {
+        Value::Nil => None,
+        v => Some(v.as_num::<usize>().map_err(usage)?.clamp(1, MAX_PASSES)),
+    };
+    // Only zero (and thus write) the region if we are going to
+    // write to it anyway, so that read-only benchmarks of MMIO
+    // regions are not destructive.
+    let (mut ro, mut rw): (&[u8], Option<&mut [u8]>) = (&[], None);
+    if ops == [Op::Read] {
+        ro = region
+            .as_slice(&config.page_table, 0)
+            .and_then(|o| o.ok_or(Error::BadArgs))
+            .map_err(usage)?;
+    } else {
+        rw = region
+            .as_slice_mut(&config.page_table, 0)
+            .and_then(|o| o.ok_or(Error::BadArgs))
+            .map(Some)
+            .map_err(usage)?;
+    }
+    let (addr, len) = match rw.as_deref() {
+        Some(region) => (region.as_ptr(), region.len()),
+        None => (ro.as_ptr(), ro.len()),
+    };
+    if len < 2 * width {
+        return Err(usage(Error::NumRange));
+    }
+    if addr.addr() % width != 0 {
+        return Err(usage(Error::PtrAlign));
+    }
+    let cached = config
+        .page_table
+        .lookup(addr.cast())
+        .is_some_and(|pte| !pte.attrs().nc());
+    let passes =
+        passes.unwrap_or_else(|| MIN_BYTES.div_ceil(len).clamp(1, MAX_PASSES));
+    println!(
+        "copybench: {:#x} bytes at {:p}, {}, \
+         {width}-byte accesses, {passes} passes",
+        len,
+        addr,
+        if cached { "cached" } else { "uncached" },
+    );
+    for op in ops {
+        let start = Instant::now();
+        let mut sum = 0u64;
+        for _ in 0..passes {
+            match (op, rw.as_deref_mut()) {
+                (Op::Read, None) => sum = sum.wrapping_add(read(width, ro)),
+                (Op::Read, Some(region)) => {
+                    sum = sum.wrapping_add(read(width, region))
+                }
+                (Op::Write, Some(region)) => write(width, region),
+                (Op::Copy, Some(region)) => copy(width, region),
+                (_, None) => unreachable!("region is writable"),
+            }
+        }
+        let elapsed = start.elapsed();
+        core::hint::black_box(sum);
+        let bytes = (pass_bytes(op, width, len) * passes) as u128;
+        println!(
+            "{:>5}: {bytes:#x} bytes in {}.{:06}s: {} MB/s",
+            op.as_str(),
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            mbps(bytes, elapsed),
+        );
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes() {
+        let mut region = alloc::vec![1u8; 64];
+        assert_eq!(read(1, &region), 64);
+        assert_eq!(read(8, &region), 8 * 0x0101_0101_0101_0101);
+        region[..32].fill(7);
+        region[32..].fill(0);
+        copy(4, &mut region);
+        assert!(region.iter().all(|&b| b == 7));
+        write(2, &mut region);
+        assert!(region.iter().all(|&b| b == 0xa5));
+        assert_eq!(pass_bytes(Op::Copy, 8, 100), 48);
+        assert_eq!(pass_bytes(Op::Read, 8, 100), 96);
+        assert_eq!(mbps(2_000_000, Duration::from_secs(1)), 2);
+        assert_eq!(mbps(1, Duration::ZERO), 0);
+    }
+}
@@ -2,7 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-//! Simple hex dump routine.
+//! Bit field, byte order, and bit order manipulation of values.
 
 use crate::bldb;
 use crate::println;
@@ -39,6 +39,78 @@ pub fn set(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     Ok(Value::Unsigned(value))
 }
 
+/// Reverses the order of the bytes of a value of the given
+/// width in bits.
+fn bswap(bits: usize, value: u128) -> Result<u128> {
+    if !value_fits(0..bits, value) {
+        return Err(Error::NumRange);
+    }
+    Ok(value.swap_bytes() >> (128 - bits))
+}
+
+/// Reverses the order of the low `bits` bits of a value.
+fn bitrev(bits: usize, value: u128) -> Result<u128> {
+    if bits == 0 || bits > 128 || !value_fits(0..bits, value) {
+        return Err(Error::NumRange);
+    }
+    Ok(value.reverse_bits() >> (128 - bits))
+}
+
+fn swap(env: &mut Vec<Value>, bits: usize) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bswap{bits} <value>");
+        error
+    };
+    let value = repl::popenv(env).as_num::<u128>().map_err(usage)?;
+    bswap(bits, value).map(Value::Unsigned)
+}
+
+pub fn swap16(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    swap(env, 16)
+}
+
+pub fn swap32(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    swap(env, 32)
+}
+
+pub fn swap64(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    swap(env, 64)
+}
+
+pub fn reverse(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bitrev <width> <value>");
+        error
+    };
+    let bits = repl::popenv(env).as_num::<usize>().map_err(usage)?;
+    let value = repl::popenv(env).as_num::<u128>().map_err(usage)?;
+    bitrev(bits, value).map(Value::Unsigned)
+}
+
+pub fn popcount(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: popcount <value>");
+        error
+    };
+    let value = repl::popenv(env).as_num::<u128>().map_err(usage)?;
+    Ok(Value::Unsigned(value.count_ones().into()))
+}
+
 fn check_bits_pair(pair: (u64, usize)) -> Result<(usize, usize)> {
     let start = pair.0 as usize;
     let end = pair.1;
@@ -64,6 +136,22 @@ mod tests {
         assert!(value_fits(0..4, 0xf));
     }
 
+    #[test]
+    fn swaps_and_reversals() {
+        assert_eq!(bswap(16, 0x1234).unwrap(), 0x3412);
+        assert_eq!(bswap(32, 0x1234).unwrap(), 0x3412_0000);
+        assert_eq!(
+            bswap(64, 0x0102_0304_0506_0708).unwrap(),
+            0x0807_0605_0403_0201
+        );
+        assert!(bswap(16, 0x10000).is_err());
+        assert_eq!(bitrev(8, 0b0000_0110).unwrap(), 0b0110_0000);
+        assert_eq!(bitrev(32, 1).unwrap(), 0x8000_0000);
+        assert_eq!(bitrev(128, 1).unwrap(), 1 << 127);
+        assert!(bitrev(4, 0x10).is_err());
+        assert!(bitrev(0, 0).is_err());
+    }
+
     #[test]
     fn value_doesnt_fit() {
         assert!(!value_fits(1..2, 2));
@@ -0,0 +1,180 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Booting illumos in one step.
+//!
+//! `boot illumos` runs the documented recipe: receive a
+//! compressed ramdisk, inflate it, mount it, load `unix` from
+//! it, and call `unix` with the ramdisk's address and length.
+//! Each stage is run by the same handler as the corresponding
+//! command, so the two cannot disagree; this just sequences
+//! them, reports the duration of each, and offers to retry a
+//! stage that fails rather than abandoning the whole pipeline.
+//! The kernel's text is verified against what was loaded just
+//! before it is called, as by `call -v`.
+
+use super::{call, inflate, load, mount, rx, rz};
+use crate::bldb;
+use crate::clock::Instant;
+use crate::cons;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The kernel loaded when no path is given.
+const DEFAULT_KERNEL: &str = "/platform/oxide/kernel/amd64/unix";
+
+/// How long to wait for an answer to the retry question.
+const RETRY_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Eq, PartialEq)]
+struct Options {
+    /// Receive via XMODEM rather than ZMODEM.
+    xmodem: bool,
+    /// The ramdisk is sent uncompressed.
+    raw: bool,
+    kernel: String,
+}
+
+fn parse_options(env: &mut Vec<Value>) -> Result<Options> {
+    let mut options =
+        Options { xmodem: false, raw: false, kernel: DEFAULT_KERNEL.into() };
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Str(s) if s == "-x" => options.xmodem = true,
+            Value::Str(s) if s == "-n" => options.raw = true,
+            Value::Str(s) if s.starts_with('/') => options.kernel = s,
+            arg => return Err(arg.bad_arg("-x, -n, or a kernel path")),
+        }
+    }
+    Ok(options)
+}
+
+/// Asks whether to retry a failed stage.
+fn retry(config: &mut bldb::Config) -> Result<bool> {
+    let prompt = |term: &mut Uart| {
+        const PROMPT: &str = "retry? [y/N] ";
+        term.puts(PROMPT);
+        PROMPT.len()
+    };
+    let mut buf = [0u8; 16];
+    match cons::readline_timeout(
+        prompt,
+        &mut config.cons,
+        RETRY_TIMEOUT,
+        &mut buf,
+    ) {
+        Ok(answer) => Ok(matches!(answer.trim(), "y" | "yes")),
+        Err(e) if e.is(Error::Timeout) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs one stage of the pipeline, reporting how long it took,
+/// and offering to retry it if it fails.
+fn stage<F>(
+    config: &mut bldb::Config,
+    step: usize,
+    name: &str,
+    mut f: F,
+) -> Result<Value>
+where
+    F: FnMut(&mut bldb::Config) -> Result<Value>,
+{
+    loop {
+        println!("boot: [{step}/5] {name}");
+        let start = Instant::now();
+        match f(config) {
+            Ok(value) => {
+                println!("boot: {name} done in {:.3?}", start.elapsed());
+                return Ok(value);
+            }
+            Err(e) => {
+                println!("boot: {name} failed: {e:?}");
+                if !retry(config)? {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn illumos(config: &mut bldb::Config, options: Options) -> Result<Value> {
+    let received = stage(config, 1, "receive", |config| {
+        if options.xmodem {
+            rx::run(config, &mut Vec::new())
+        } else {
+            rz::run(config, &mut Vec::new())
+        }
+    })?;
+    let ramdisk = if options.raw {
+        println!("boot: [2/5] inflate skipped");
+        received
+    } else {
+        stage(config, 2, "inflate", |config| {
+            let mut args = vec![received.clone(), Value::Str("-r".into())];
+            inflate::run(config, &mut args)
+        })?
+    };
+    stage(config, 3, "mount", |config| {
+        mount::run(config, &mut vec![ramdisk.clone()])
+    })?;
+    let entry = stage(config, 4, "load", |config| {
+        load::run(config, &mut vec![Value::Str(options.kernel.clone())])
+    })?;
+    println!("boot: [5/5] calling {entry:?} with ramdisk {ramdisk:?}");
+    call::run(config, &mut vec![ramdisk, entry, Value::Str("-v".into())])
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: boot illumos [-x] [-n] [<kernel path>]");
+        error
+    };
+    let arg = repl::popenv(env);
+    match arg.as_string().map_err(usage)?.as_str() {
+        "illumos" => {
+            let options = parse_options(env).map_err(usage)?;
+            illumos(config, options)
+        }
+        _ => Err(usage(arg.bad_arg("illumos"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options() {
+        let mut env = Vec::new();
+        assert_eq!(
+            parse_options(&mut env).unwrap(),
+            Options {
+                xmodem: false,
+                raw: false,
+                kernel: DEFAULT_KERNEL.into()
+            }
+        );
+        let mut env =
+            vec![Value::Str("/boot/unix".into()), Value::Str("-x".into())];
+        assert_eq!(
+            parse_options(&mut env).unwrap(),
+            Options { xmodem: true, raw: false, kernel: "/boot/unix".into() }
+        );
+        let mut env = vec![Value::Str("-n".into())];
+        assert!(parse_options(&mut env).unwrap().raw);
+        let mut env = vec![Value::Str("unix".into())];
+        assert!(parse_options(&mut env).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Editing of the boot environment string handed to the loaded
+//! image.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The longest boot environment we accept, including the
+/// terminating NUL.
+const MAX_LEN: usize = 4096;
+
+/// Joins the remaining words on the stack into a NUL-terminated
+/// string, separated by single spaces.
+fn collect(env: &mut Vec<Value>) -> Result<String> {
+    let mut words = String::new();
+    loop {
+        let word = match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Str(s) => s,
+            v => return Err(v.bad_arg("string")),
+        };
+        if !words.is_empty() {
+            words.push(' ');
+        }
+        words.push_str(&word);
+    }
+    if words.is_empty() || words.contains('\0') {
+        return Err(Error::BadArgs);
+    }
+    words.push('\0');
+    if words.len() > MAX_LEN {
+        return Err(Error::NumRange);
+    }
+    Ok(words)
+}
+
+/// Returns the address and length, excluding the terminating
+/// NUL, of the current boot environment, if any.
+pub(super) fn args(config: &bldb::Config) -> Option<(u64, u64)> {
+    let env = config.bootenv.as_ref()?;
+    Some((env.as_ptr().addr() as u64, (env.len() - 1) as u64))
+}
+
+fn show(config: &bldb::Config) {
+    match &config.bootenv {
+        Some(env) => {
+            let env = &env[..env.len() - 1];
+            let env = core::str::from_utf8(env).unwrap_or("<invalid>");
+            println!("bootenv: {env}");
+        }
+        None => println!("bootenv: (unset)"),
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bootenv [show | set <words...> | clear]");
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = match &arg {
+        Value::Nil => String::from("show"),
+        v => v.as_string().map_err(usage)?,
+    };
+    match cmd.as_str() {
+        "show" => {}
+        "set" => {
+            let words = collect(env).map_err(usage)?;
+            config.bootenv = Some(words.into_bytes().into_boxed_slice());
+        }
+        "clear" => config.bootenv = None,
+        _ => return Err(usage(arg.bad_arg("show, set, or clear"))),
+    }
+    show(config);
+    Ok(Value::Nil)
+}
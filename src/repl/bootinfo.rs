@@ -0,0 +1,492 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Multiboot2 boot information.
+//!
+//! Kernels written for a boot loader such as GRUB expect more
+//! than a few arguments in registers: they find their command
+//! line, modules, and the memory map in a Multiboot2 boot
+//! information structure, whose physical address is passed to
+//! them along with a magic number.  `bootinfo` builds one from
+//! the machine's state: the boot environment set with `bootenv`
+//! is the command line, the mounted ramdisk is a module, and
+//! the DRAM in the data fabric's address map, less the regions
+//! the loader reserves for itself, is the memory map.
+//!
+//! The structure is a header giving its total size, followed by
+//! a sequence of tags, each 8-byte aligned, and ended by a tag
+//! of type 0, all little-endian:
+//!
+//! ```text
+//! header:  total_size u32 | reserved u32
+//! tag:     type u32 | size u32 | payload
+//! ```
+//!
+//! Only the tags below are built or decoded; the addresses they
+//! hold are physical.  Multiboot2 is a 32-bit protocol, so
+//! modules must lie below 4GiB.
+
+use crate::bldb;
+use crate::mem;
+use crate::mmu;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Passed to the kernel along with the address of the structure.
+pub(super) const MAGIC: u32 = 0x36d7_6289;
+
+const HEADER_LEN: usize = 8;
+const TAG_HEADER_LEN: usize = 8;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MMAP: u32 = 6;
+
+const MMAP_ENTRY_LEN: usize = 24;
+const MMAP_ENTRY_VERSION: u32 = 0;
+
+/// Memory map entry types.
+const AVAILABLE: u32 = 1;
+const RESERVED: u32 = 2;
+
+const LOADER_NAME: &str = "bldb";
+
+/// A module: a range of physical memory holding, e.g., a
+/// ramdisk, and a string describing it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Module {
+    range: Range<u64>,
+    name: String,
+}
+
+/// The boot information, as built or decoded.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Info {
+    cmdline: Option<String>,
+    loader: Option<String>,
+    modules: Vec<Module>,
+    /// Ranges of physical memory and their types.
+    mmap: Vec<(Range<u64>, u32)>,
+}
+
+fn tag(blob: &mut Vec<u8>, typ: u32, payload: &[u8]) -> Result<()> {
+    let size = u32::try_from(TAG_HEADER_LEN + payload.len())
+        .map_err(|_| Error::NumRange)?;
+    blob.extend_from_slice(&typ.to_le_bytes());
+    blob.extend_from_slice(&size.to_le_bytes());
+    blob.extend_from_slice(payload);
+    blob.resize(blob.len().next_multiple_of(8), 0);
+    Ok(())
+}
+
+fn cstr(s: &str) -> Vec<u8> {
+    let mut bs = Vec::from(s.as_bytes());
+    bs.push(0);
+    bs
+}
+
+/// Serializes the boot information.
+fn encode(info: &Info) -> Result<Vec<u8>> {
+    let mut blob = alloc::vec![0; HEADER_LEN];
+    if let Some(cmdline) = &info.cmdline {
+        tag(&mut blob, TAG_CMDLINE, &cstr(cmdline))?;
+    }
+    if let Some(loader) = &info.loader {
+        tag(&mut blob, TAG_LOADER_NAME, &cstr(loader))?;
+    }
+    for module in info.modules.iter() {
+        let start = u32::try_from(module.range.start);
+        let end = u32::try_from(module.range.end);
+        let (Ok(start), Ok(end)) = (start, end) else {
+            println!(
+                "bootinfo: module {:#x}..{:#x} is not below 4GiB",
+                module.range.start, module.range.end
+            );
+            return Err(Error::NumRange);
+        };
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&start.to_le_bytes());
+        payload.extend_from_slice(&end.to_le_bytes());
+        payload.extend_from_slice(&cstr(&module.name));
+        tag(&mut blob, TAG_MODULE, &payload)?;
+    }
+    if !info.mmap.is_empty() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(MMAP_ENTRY_LEN as u32).to_le_bytes());
+        payload.extend_from_slice(&MMAP_ENTRY_VERSION.to_le_bytes());
+        for (range, typ) in info.mmap.iter() {
+            payload.extend_from_slice(&range.start.to_le_bytes());
+            payload.extend_from_slice(&(range.end - range.start).to_le_bytes());
+            payload.extend_from_slice(&typ.to_le_bytes());
+            payload.extend_from_slice(&0u32.to_le_bytes());
+        }
+        tag(&mut blob, TAG_MMAP, &payload)?;
+    }
+    tag(&mut blob, TAG_END, &[])?;
+    let len = u32::try_from(blob.len()).map_err(|_| Error::NumRange)?;
+    blob[..4].copy_from_slice(&len.to_le_bytes());
+    Ok(blob)
+}
+
+fn u32_at(bs: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bs.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn u64_at(bs: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bs.get(off..off + 8)?.try_into().ok()?))
+}
+
+fn parse_cstr(bs: &[u8]) -> Option<String> {
+    let nul = bs.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bs[..nul]).ok().map(String::from)
+}
+
+/// Decodes the boot information at the start of `bs`, returning
+/// it and the length of the structure.  Tags of other types are
+/// skipped.
+fn decode(bs: &[u8]) -> Result<(Info, usize)> {
+    let len = u32_at(bs, 0).ok_or(Error::BadArgs)? as usize;
+    if len < HEADER_LEN + TAG_HEADER_LEN || len > bs.len() {
+        return Err(Error::BadArgs);
+    }
+    let bs = &bs[..len];
+    let mut info = Info::default();
+    let mut off = HEADER_LEN;
+    loop {
+        let typ = u32_at(bs, off).ok_or(Error::BadArgs)?;
+        let size = u32_at(bs, off + 4).ok_or(Error::BadArgs)? as usize;
+        let payload = size
+            .checked_sub(TAG_HEADER_LEN)
+            .and_then(|n| bs.get(off + TAG_HEADER_LEN..)?.get(..n))
+            .ok_or(Error::BadArgs)?;
+        match typ {
+            TAG_END => break,
+            TAG_CMDLINE => info.cmdline = parse_cstr(payload),
+            TAG_LOADER_NAME => info.loader = parse_cstr(payload),
+            TAG_MODULE => {
+                let start = u32_at(payload, 0).ok_or(Error::BadArgs)?;
+                let end = u32_at(payload, 4).ok_or(Error::BadArgs)?;
+                let name = payload.get(8..).and_then(parse_cstr);
+                info.modules.push(Module {
+                    range: start.into()..end.into(),
+                    name: name.unwrap_or_default(),
+                });
+            }
+            TAG_MMAP => {
+                let entry_len = u32_at(payload, 0).ok_or(Error::BadArgs)?;
+                let entry_len = entry_len as usize;
+                if entry_len < MMAP_ENTRY_LEN {
+                    return Err(Error::BadArgs);
+                }
+                let entries = payload.get(8..).ok_or(Error::BadArgs)?;
+                for entry in entries.chunks_exact(entry_len) {
+                    let base = u64_at(entry, 0).ok_or(Error::BadArgs)?;
+                    let len = u64_at(entry, 8).ok_or(Error::BadArgs)?;
+                    let typ = u32_at(entry, 16).ok_or(Error::BadArgs)?;
+                    info.mmap.push((base..base.saturating_add(len), typ));
+                }
+            }
+            _ => {}
+        }
+        off += size.next_multiple_of(8);
+    }
+    Ok((info, len))
+}
+
+fn show(info: &Info, len: usize) {
+    println!("boot information, {len:#x} bytes:");
+    if let Some(loader) = &info.loader {
+        println!("loader:  {loader}");
+    }
+    if let Some(cmdline) = &info.cmdline {
+        println!("cmdline: {cmdline}");
+    }
+    for module in info.modules.iter() {
+        let Range { start, end } = module.range;
+        println!("module:  {start:#x}..{end:#x} {}", module.name);
+    }
+    for (range, typ) in info.mmap.iter() {
+        let what = match *typ {
+            AVAILABLE => "available",
+            RESERVED => "reserved",
+            _ => "other",
+        };
+        println!("mmap:    {:#014x}..{:#014x} {what}", range.start, range.end);
+    }
+}
+
+/// Returns the available ranges, with the reserved ranges carved
+/// out of them, followed by the reserved ranges, as a memory
+/// map in address order.
+fn carve(
+    available: &[Range<u64>],
+    reserved: &[Range<u64>],
+) -> Vec<(Range<u64>, u32)> {
+    let mut pieces = available.to_vec();
+    for hole in reserved {
+        pieces = pieces
+            .into_iter()
+            .flat_map(|r| {
+                [r.start..r.end.min(hole.start), r.start.max(hole.end)..r.end]
+            })
+            .filter(|r| !r.is_empty())
+            .collect();
+    }
+    let mut mmap =
+        pieces.into_iter().map(|r| (r, AVAILABLE)).collect::<Vec<_>>();
+    mmap.extend(reserved.iter().map(|r| (r.clone(), RESERVED)));
+    mmap.sort_by_key(|(r, _)| r.start);
+    mmap
+}
+
+/// Returns the segments of physical memory backed by DRAM, if
+/// they can be read.
+fn dram() -> Result<Vec<Range<u64>>> {
+    if cfg!(test) {
+        return Ok(Vec::new());
+    }
+    Ok(crate::physmap::read()?.segments())
+}
+
+/// Returns the regions reserved by the loader: its own image,
+/// the transfer, ramdisk, and scratch regions, and the devices
+/// it drives.  These are identity mapped, so their virtual
+/// addresses are also physical.
+fn reserved(page_table: &mmu::LoaderPageTable) -> Vec<Range<u64>> {
+    let mut reserved = page_table
+        .reserved()
+        .iter()
+        .map(|r| r.start.addr() as u64..r.end.addr() as u64)
+        .collect::<Vec<_>>();
+    reserved.sort_by_key(|r| r.start);
+    reserved
+}
+
+/// Returns the physical range backing a virtual range, assuming
+/// it is contiguous, as the loader's regions are.
+fn phys_range(
+    config: &bldb::Config,
+    start: usize,
+    len: usize,
+) -> Option<Range<u64>> {
+    let pa = config.page_table.translate(start)?;
+    Some(pa..pa + len as u64)
+}
+
+/// Gathers the boot information from the machine's state.
+fn gather(config: &bldb::Config) -> Result<Info> {
+    let cmdline = config.bootenv.as_ref().map(|env| {
+        let env = env.strip_suffix(&[0]).unwrap_or(env);
+        String::from_utf8_lossy(env).into_owned()
+    });
+    let mut modules = Vec::new();
+    if let Some(ramdisk) = config.mounts.root() {
+        let buf = ramdisk.buf();
+        let range =
+            phys_range(config, buf.addr(), buf.len()).ok_or(Error::Unmapped)?;
+        modules.push(Module { range, name: String::from("ramdisk") });
+    }
+    let dram = dram()?;
+    let mmap = match dram.is_empty() {
+        true => Vec::new(),
+        false => carve(&dram, &reserved(&config.page_table)),
+    };
+    Ok(Info { cmdline, loader: Some(String::from(LOADER_NAME)), modules, mmap })
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bootinfo [<addr>,<len>] | bootinfo show <addr>");
+        error
+    };
+    let (addr, len) = match repl::popenv(env) {
+        // Files are received at the start of the transfer region,
+        // where a ramdisk may still be, so the scratch region is
+        // used instead.
+        Value::Nil => {
+            config.regions_claimed = true;
+            let scratch = &config.scratch_region;
+            (scratch.start.addr(), scratch.end.addr() - scratch.start.addr())
+        }
+        Value::Str(cmd) if cmd == "show" => {
+            let src = repl::popenv(env);
+            let header = src
+                .as_slice(&config.page_table, HEADER_LEN)
+                .and_then(|o| o.ok_or(Error::BadArgs))
+                .map_err(usage)?;
+            // The structure is 8-byte aligned, as are its tags.
+            if header.as_ptr().addr() % 8 != 0 {
+                return Err(Error::PtrAlign);
+            }
+            let len = u32_at(header, 0).map_or(0, |len| len as usize);
+            let bs = src
+                .as_slice(&config.page_table, len.max(HEADER_LEN))?
+                .ok_or(Error::BadArgs)?;
+            let (info, len) = decode(bs)?;
+            show(&info, len);
+            return Ok(Value::Nil);
+        }
+        v => {
+            let (addr, len) = v.as_pair().map_err(usage)?;
+            (addr as usize, len)
+        }
+    };
+    let info = gather(config)?;
+    if info.mmap.is_empty() {
+        println!("bootinfo: the DRAM map is unknown; no memory map included");
+    }
+    let blob = encode(&info)?;
+    if blob.len() > len {
+        println!(
+            "bootinfo: structure is {:#x} bytes, region only {len:#x}",
+            blob.len()
+        );
+        return Err(Error::NumRange);
+    }
+    if !mem::is_canonical(addr) {
+        return Err(Error::PtrNonCanon);
+    }
+    let pa = config.page_table.translate(addr).ok_or(Error::Unmapped)?;
+    let range = pa..pa + blob.len() as u64;
+    if let Some(module) = info.modules.iter().find(|module| {
+        range.start < module.range.end && module.range.start < range.end
+    }) {
+        println!(
+            "bootinfo: {:#x}..{:#x} would overwrite the {} module at \
+             {:#x}..{:#x}",
+            range.start,
+            range.end,
+            module.name,
+            module.range.start,
+            module.range.end
+        );
+        return Err(Error::BadArgs);
+    }
+    let dst = Value::Pair(addr, blob.len())
+        .as_slice_mut(&config.page_table, 0)?
+        .ok_or(Error::BadArgs)?;
+    dst.copy_from_slice(&blob);
+    show(&info, blob.len());
+    println!("bootinfo: enter with `call -m <entry> {pa:#x}`");
+    Ok(Value::Unsigned(pa.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use alloc::format;
+
+    #[test]
+    fn round_trip() {
+        let info = Info {
+            cmdline: Some(String::from("-kd")),
+            loader: Some(String::from(LOADER_NAME)),
+            modules: alloc::vec![Module {
+                range: 0x10_0000..0x20_0000,
+                name: String::from("ramdisk"),
+            }],
+            mmap: alloc::vec![
+                (0..0x8000_0000, AVAILABLE),
+                (0x1_0000_0000..0x2_0000_0000, RESERVED)
+            ],
+        };
+        let mut blob = encode(&info).unwrap();
+        assert_eq!(blob.len() % 8, 0);
+        assert_eq!(u32_at(&blob, 0), Some(blob.len() as u32));
+        blob.extend_from_slice(&[0xff; 16]);
+        let (decoded, len) = decode(&blob).unwrap();
+        assert_eq!(decoded, info);
+        assert_eq!(len, blob.len() - 16);
+        assert!(decode(&blob[..len - 1]).is_err());
+
+        // An mmap tag too short to hold its entry size and
+        // version, or giving a zero entry size.
+        for payload in [&[24, 0, 0, 0][..], &[0; 8]] {
+            let mut blob = alloc::vec![0; HEADER_LEN];
+            tag(&mut blob, TAG_MMAP, payload).unwrap();
+            tag(&mut blob, TAG_END, &[]).unwrap();
+            let len = blob.len() as u32;
+            blob[..4].copy_from_slice(&len.to_le_bytes());
+            assert_eq!(decode(&blob).unwrap_err(), Error::BadArgs);
+        }
+
+        let high = Info {
+            modules: alloc::vec![Module {
+                range: 0x1_0000_0000..0x1_0000_1000,
+                name: String::new(),
+            }],
+            ..Info::default()
+        };
+        assert!(encode(&high).is_err());
+    }
+
+    #[test]
+    fn carving() {
+        let gib = 1u64 << 30;
+        let loader = gib..gib + 0x1000;
+        let dram = [0..2 * gib, 4 * gib..8 * gib];
+        let mmap = carve(&dram, core::slice::from_ref(&loader));
+        assert_eq!(
+            mmap,
+            [
+                (0..gib, AVAILABLE),
+                (gib..gib + 0x1000, RESERVED),
+                (gib + 0x1000..2 * gib, AVAILABLE),
+                (4 * gib..8 * gib, AVAILABLE),
+            ]
+        );
+    }
+
+    #[test]
+    fn reservations() {
+        let region = |start: usize, end: usize| {
+            mem::V4KA::new(start)..mem::V4KA::new(end)
+        };
+        let page_table = mmu::LoaderPageTable::new(
+            mmu::PageTable::new(),
+            &[region(0x7f00_0000, 0x8000_0000), region(0x1000, 0x2000)],
+            &[],
+        );
+        let reserved = reserved(&page_table);
+        assert_eq!(reserved, [0x1000..0x2000, 0x7f00_0000..0x8000_0000]);
+        let dram = 0..0x8000_0000;
+        let mmap = carve(core::slice::from_ref(&dram), &reserved);
+        assert_eq!(
+            mmap,
+            [
+                (0..0x1000, AVAILABLE),
+                (0x1000..0x2000, RESERVED),
+                (0x2000..0x7f00_0000, AVAILABLE),
+                (0x7f00_0000..0x8000_0000, RESERVED),
+            ]
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        let mut sim = Sim::new();
+        let addr = sim.ram().as_ptr().addr();
+        let lines = format!("bootenv set -kd -v\nbootinfo {addr:#x},0x100\n");
+        let out = sim.session(&lines);
+        assert!(out.contains("cmdline: -kd -v"), "{out}");
+        assert!(out.contains("call -m <entry> 0x100000"), "{out}");
+        assert!(out.ends_with("res: 0x100000\n"), "{out}");
+        let out = sim.session(&format!("bootinfo show {addr:#x}\n"));
+        assert!(out.contains("loader:  bldb"), "{out}");
+        let out = sim.session(&format!("bootinfo show {:#x}\n", addr + 4));
+        assert!(out.contains(Error::PtrAlign.as_str()), "{out}");
+        let out = sim.session(&format!("bootinfo {addr:#x},0x10\n"));
+        assert!(out.contains("region only 0x10"), "{out}");
+    }
+}
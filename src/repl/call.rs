@@ -2,14 +2,19 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::beacon;
 use crate::bldb;
 use crate::mem;
 use crate::println;
-use crate::repl::{self, Value};
+use crate::repl::{self, Value, bootinfo, confirm, debug, snapshot};
 use crate::result::{Error, Result};
-use alloc::vec;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::arch::naked_asm;
 use core::convert::TryFrom;
+use core::ops::Range;
+use sha2::{Digest, Sha256};
 
 pub type Thunk = unsafe extern "C" fn(
     rdi: u64,
@@ -43,44 +48,363 @@ fn parse_rip(config: &bldb::Config, value: Value) -> Result<u64> {
     Ok(rip)
 }
 
-fn callargs(config: &bldb::Config, env: &mut Vec<Value>) -> Result<Vec<u64>> {
-    let rip = parse_rip(config, repl::popenv(env))?;
-    let mut args = vec![rip];
-    for _ in 0..6 {
-        match repl::popenv(env) {
+/// The System V ABI's argument registers, in order.
+const ARG_REGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+/// The registers in which a Multiboot2 kernel expects the magic
+/// number and the address of its boot information.
+const MULTIBOOT_REGS: [&str; 2] = ["eax", "ebx"];
+
+/// Calls a Multiboot2 kernel at `entry`, with `magic` in EAX and
+/// the address of its boot information in EBX.  RBX is callee
+/// saved, so it is preserved around the call.
+#[unsafe(naked)]
+unsafe extern "C" fn multiboot_thunk(entry: u64, magic: u64, info: u64) -> u64 {
+    naked_asm!(
+        r#"
+        pushq %rbx;
+        movl %esi, %eax;
+        movl %edx, %ebx;
+        callq *%rdi;
+        popq %rbx;
+        retq;
+        "#,
+        options(att_syntax)
+    )
+}
+
+/// A value bound for an argument register, with a description
+/// of where it came from and, if it is the address of a region
+/// of memory, whether that region is mapped.
+struct Arg {
+    value: u64,
+    from: String,
+    mapped: Option<bool>,
+}
+
+impl Arg {
+    fn new(value: u64, from: String) -> Arg {
+        Arg { value, from, mapped: None }
+    }
+
+    fn region(config: &bldb::Config, addr: u64, len: u64, from: String) -> Arg {
+        let mapped = is_readable(config, addr, len);
+        Arg { value: addr, from, mapped: Some(mapped) }
+    }
+}
+
+/// Returns true iff the region at the given address is mapped
+/// and readable.
+fn is_readable(config: &bldb::Config, addr: u64, len: u64) -> bool {
+    let Ok(addr) = usize::try_from(addr) else {
+        return false;
+    };
+    let Ok(len) = usize::try_from(len.max(1)) else {
+        return false;
+    };
+    mem::is_canonical(addr)
+        && addr.checked_add(len).is_some()
+        && config.page_table.is_region_readable(mem::page_range_raw(
+            core::ptr::without_provenance(addr),
+            len,
+        ))
+}
+
+/// Pops the arguments to a call, at most one for each argument
+/// register, leaving the rest of the environment alone.
+fn callargs(config: &bldb::Config, env: &mut Vec<Value>) -> Result<Vec<Arg>> {
+    let mut args = Vec::new();
+    for _ in 0..ARG_REGS.len() {
+        let value = repl::popenv(env);
+        match &value {
             Value::Nil => break,
-            Value::Slice(slice) => {
-                args.push(slice.as_ptr().addr() as u64);
-                args.push(slice.len() as u64);
+            Value::Slice(buf) => {
+                let slice = config.page_table.resolve(buf)?;
+                let (addr, len) = (slice.as_ptr().addr(), slice.len());
+                args.push(Arg::new(
+                    addr as u64,
+                    format!("address of {value:?}"),
+                ));
+                args.push(Arg::new(len as u64, format!("length of {value:?}")));
             }
-            Value::Pair(a, b) => {
-                args.push(a as u64);
-                args.push(b as u64);
+            &Value::Pair(a, b) => {
+                let from = format!("address of {value:?}");
+                args.push(Arg::region(config, a as u64, b as u64, from));
+                args.push(Arg::new(b as u64, format!("length of {value:?}")));
             }
-            Value::Unsigned(a) => {
-                args.push(u64::try_from(a).map_err(|_| Error::NumRange)?);
+            &Value::Unsigned(a) => {
+                let a = u64::try_from(a).map_err(|_| Error::NumRange)?;
+                args.push(Arg::new(a, format!("{value:?}")));
             }
-            _ => return Err(Error::BadArgs),
+            v => return Err(v.bad_arg("number, <addr>,<len>, or slice")),
         }
     }
+    // If a boot environment has been set, its address and
+    // length are passed after the explicit arguments.
+    if let Some((addr, len)) = super::bootenv::args(config) {
+        let from = "address of boot environment".into();
+        args.push(Arg::region(config, addr, len, from));
+        args.push(Arg::new(len, "length of boot environment".into()));
+    }
     Ok(args)
 }
 
+/// Pops the address of the boot information for a Multiboot2
+/// call, which must lie below 4GiB, and returns it along with
+/// the magic number.  The boot environment is not passed, as
+/// the boot information holds it as the command line.
+fn multiboot_args(env: &mut Vec<Value>) -> Result<Vec<Arg>> {
+    let value = repl::popenv(env);
+    let info = value.as_num::<u32>()?;
+    Ok(Vec::from([
+        Arg::new(bootinfo::MAGIC.into(), String::from("Multiboot2 magic")),
+        Arg::new(info.into(), format!("boot information at {value:?}")),
+    ]))
+}
+
+/// Assigns the arguments to registers, in order, failing if
+/// there are more than there are registers to hold them.
+fn registers(args: &[Arg]) -> Result<[u64; ARG_REGS.len()]> {
+    if args.len() > ARG_REGS.len() {
+        println!(
+            "call: {} argument words will not fit in {} registers",
+            args.len(),
+            ARG_REGS.len()
+        );
+        return Err(Error::BadArgs);
+    }
+    let mut regs = [0; ARG_REGS.len()];
+    for (reg, arg) in regs.iter_mut().zip(args) {
+        *reg = arg.value;
+    }
+    Ok(regs)
+}
+
+/// Describes the call without making it.
+fn dry_run(config: &bldb::Config, rip: u64, regs: &[&str], args: &[Arg]) {
+    let target = if bldb::loader_text().contains(&rip) {
+        String::from("in the loader's text")
+    } else {
+        match config.images.iter().find(|l| l.in_text(rip)) {
+            Some(loaded) => format!("in the text of {}", loaded.source),
+            None => String::from("not in any known text; `-f` is needed"),
+        }
+    };
+    println!("call: would call {rip:#x}, {target}");
+    for (k, reg) in regs.iter().enumerate() {
+        let Some(arg) = args.get(k) else {
+            println!("{reg:>5} = 0");
+            continue;
+        };
+        let mapped = match arg.mapped {
+            None => "",
+            Some(true) => " (mapped)",
+            Some(false) => " (NOT MAPPED)",
+        };
+        println!("{reg:>5} = {:#018x}  {}{mapped}", arg.value, arg.from);
+    }
+}
+
+/// Returns true iff the given address lies within the text of
+/// the loader itself or of an image loaded since boot.
+fn is_known_text(config: &bldb::Config, rip: u64) -> bool {
+    bldb::loader_text().contains(&rip)
+        || config.images.iter().any(|loaded| loaded.in_text(rip))
+}
+
+/// Returns a copy of a text range of a loaded image, if it is
+/// still mapped and readable, with the bytes displaced by any
+/// breakpoints planted in it put back, so that it reads as it
+/// was loaded.
+fn text_bytes(config: &bldb::Config, text: &Range<u64>) -> Option<Vec<u8>> {
+    let ptr = core::ptr::with_exposed_provenance::<u8>(text.start as usize);
+    let len = (text.end - text.start) as usize;
+    let range = mem::page_range_raw(ptr.cast(), len);
+    if !config.page_table.is_region_readable(range) {
+        return None;
+    }
+    let mut bytes = unsafe { core::slice::from_raw_parts(ptr, len) }.to_vec();
+    debug::unplant(text.start, &mut bytes);
+    Some(bytes)
+}
+
+/// Measures the text of the loaded image containing the call
+/// target, as it is at the time of the call.  Calls into the
+/// loader itself, or to unknown addresses, are not measured.
+fn measure(config: &mut bldb::Config, rip: u64) {
+    let Some(loaded) = config.images.iter().find(|l| l.in_text(rip)) else {
+        return;
+    };
+    let mut sum = Sha256::new();
+    for text in loaded.image.text.iter() {
+        let Some(bytes) = text_bytes(config, text) else {
+            println!("call: cannot measure text at {:#x}", text.start);
+            return;
+        };
+        sum.update(&bytes);
+    }
+    let what = format!("call {rip:#x} in {}", loaded.source);
+    config.measurements.measure(what, sum.finalize().into());
+}
+
+/// Reads back the text of the loaded image containing the call
+/// target through its current mappings, and checks it against
+/// the digests taken as it was loaded.  This catches partial
+/// loads, text overwritten since, and mappings that alias other
+/// memory or have the wrong attributes, before they can show up
+/// as faults early in the kernel.  As a side effect, the text
+/// is pulled into the cache.
+fn verify_text(config: &bldb::Config, rip: u64) -> Result<()> {
+    let Some(loaded) = config.images.iter().find(|l| l.in_text(rip)) else {
+        println!("call: {rip:#x} is not in a loaded image; not verified");
+        return Ok(());
+    };
+    let texts = loaded.image.text.iter().zip(&loaded.image.text_digests);
+    for (text, digest) in texts {
+        let Some(bytes) = text_bytes(config, text) else {
+            println!("call: text at {text:#x?} is not readable");
+            return Err(Error::Unmapped);
+        };
+        if Sha256::digest(&bytes)[..] != digest[..] {
+            println!(
+                "call: text at {text:#x?} differs from that loaded from {}",
+                loaded.source
+            );
+            return Err(Error::Verify);
+        }
+    }
+    println!("call: text of {} verified", loaded.source);
+    Ok(())
+}
+
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: call <rip> [up to six args]");
+        println!("usage: call [-f] [-n] [-q] [-v] <rip> [up to six args]");
+        println!("       call -m [-f] [-n] [-q] [-v] <rip> <bootinfo addr>");
         error
     };
-    let args = callargs(config, env).map_err(usage)?;
-    let rip = args[0];
+    let (mut force, mut dry, mut verify) = (false, false, false);
+    let mut multiboot = false;
+    let mut audit = true;
+    loop {
+        if confirm::take_force(env) {
+            force = true;
+            continue;
+        }
+        match env.last() {
+            Some(Value::Str(s)) if s == "-n" || s == "--dry-run" => dry = true,
+            Some(Value::Str(s)) if s == "-q" => audit = false,
+            Some(Value::Str(s)) if s == "-v" => verify = true,
+            Some(Value::Str(s)) if s == "-m" => multiboot = true,
+            _ => break,
+        }
+        env.pop();
+    }
+    let rip = parse_rip(config, repl::popenv(env)).map_err(usage)?;
+    let args = match multiboot {
+        true => multiboot_args(env),
+        false => callargs(config, env),
+    }
+    .map_err(usage)?;
+    let regs = registers(&args).map_err(usage)?;
+    if verify {
+        verify_text(config, rip)?;
+    }
+    if dry {
+        let names = if multiboot { &MULTIBOOT_REGS[..] } else { &ARG_REGS };
+        dry_run(config, rip, names, &args);
+        return Ok(Value::Nil);
+    }
+    if !force && !is_known_text(config, rip) {
+        println!(
+            "call: {rip:#x} is not in the text of the loader or of any \
+             loaded image; use `call -f` to call it anyway"
+        );
+        return Err(Error::CallTarget);
+    }
     let thunk = unsafe { core::mem::transmute::<u64, Thunk>(rip) };
-    let rdi = if args.len() > 1 { args[1] } else { 0 };
-    let rsi = if args.len() > 2 { args[2] } else { 0 };
-    let rdx = if args.len() > 3 { args[3] } else { 0 };
-    let rcx = if args.len() > 4 { args[4] } else { 0 };
-    let r8 = if args.len() > 5 { args[5] } else { 0 };
-    let r9 = if args.len() > 6 { args[6] } else { 0 };
-    let rax = unsafe { thunk(rdi, rsi, rdx, rcx, r8, r9) };
+    let [rdi, rsi, rdx, rcx, r8, r9] = regs;
+    measure(config, rip);
+    config.signal(beacon::Phase::Handoff);
+    let before = audit.then(|| snapshot::Snapshot::take(config));
+    let rax = debug::attached(config, || unsafe {
+        match multiboot {
+            true => multiboot_thunk(rip, rdi, rsi),
+            false => thunk(rdi, rsi, rdx, rcx, r8, r9),
+        }
+    });
     println!("call returned {rax:x}");
+    if let Some(before) = before {
+        before.report(&snapshot::Snapshot::take(config));
+    }
     Ok(Value::Unsigned(rax.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marshalling() {
+        let args =
+            (1..=4).map(|k| Arg::new(k, format!("{k:#x}"))).collect::<Vec<_>>();
+        assert_eq!(registers(&args), Ok([1, 2, 3, 4, 0, 0]));
+        let args =
+            (0..7).map(|k| Arg::new(k, String::new())).collect::<Vec<_>>();
+        assert_eq!(registers(&args), Err(Error::BadArgs));
+        assert_eq!(registers(&[]), Ok([0; 6]));
+    }
+
+    #[test]
+    fn arity() {
+        let mut sim = crate::repl::sim::Sim::new();
+        let mut env = (1..=8).rev().map(Value::Unsigned).collect::<Vec<_>>();
+        let (args, _) = sim.console("", |config| callargs(config, &mut env));
+        let args = args.expect("arguments");
+        assert_eq!(
+            args.iter().map(|a| a.value).collect::<Vec<_>>(),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert!(matches!(env[..], [Value::Unsigned(8), Value::Unsigned(7)]));
+    }
+
+    #[test]
+    fn breakpoints_in_text() {
+        use crate::loader::{Image, Loaded};
+        let mut sim = crate::repl::sim::Sim::new();
+        let code = [0x55, 0x48, 0x89, 0xe5, 0x90, 0x5d, 0xc3, 0x90];
+        sim.ram()[0x100..0x108].copy_from_slice(&code);
+        let start = sim.ram()[0x100..].as_ptr().addr() as u64;
+        let text = start..start + code.len() as u64;
+        let digest: [u8; 32] = Sha256::digest(code).into();
+        sim.session(&format!("bp {:#x}\n", start + 4));
+        assert_eq!(sim.ram()[0x104], 0xcc);
+        let (res, out) = sim.console("", |config| {
+            config.images.push(Loaded {
+                source: String::from("test"),
+                image: Image {
+                    entry: core::ptr::null(),
+                    text: alloc::vec![text.clone()],
+                    text_digests: alloc::vec![digest],
+                },
+            });
+            measure(config, start);
+            let measured = config.measurements.log().last().map(|e| e.digest);
+            (verify_text(config, start), measured)
+        });
+        assert_eq!(res, (Ok(()), Some(digest)), "{out}");
+        assert!(out.contains("call: text of test verified"), "{out}");
+        sim.session("bpclear\n");
+        assert_eq!(sim.ram()[0x100..0x108], code);
+    }
+
+    #[test]
+    fn multiboot() {
+        let mut env = alloc::vec![Value::Unsigned(0x10_0000)];
+        let args = multiboot_args(&mut env).expect("arguments");
+        assert_eq!(registers(&args), Ok([0x36d7_6289, 0x10_0000, 0, 0, 0, 0]));
+        let mut env = alloc::vec![Value::Unsigned(0x1_0000_0000)];
+        assert!(multiboot_args(&mut env).is_err());
+        assert!(multiboot_args(&mut Vec::new()).is_err());
+    }
+}
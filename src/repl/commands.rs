@@ -0,0 +1,2331 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The command registry.
+//!
+//! Every REPL command is described by an entry in `COMMANDS`,
+//! which gives its name and any aliases, a category, synopsis
+//! lines, descriptive text, and the handler that implements
+//! it.  The registry is used both to dispatch commands and to
+//! generate online help, so the two cannot drift apart.
+//!
+//! Related commands are also gathered into namespaces in
+//! `NAMESPACES`, so that, e.g., `mem xd` runs `hexdump`.
+
+use super::{
+    Value, addr, audit, baud, beacon, boot, bootenv, bootinfo, call, cat,
+    confirm, copy, cpuid, dd, debug, deflate, dis, dump, elfinfo, handoff,
+    idle, inflate, iomux, layout, list, load, log, memory, mount, msr, numfmt,
+    pcr, pop2, prompt, random, region, replay, rraw, rx, ry, rz, search, sha,
+    sinks, smn, sp, stack, state, syms, sysregs, sz, transcript, vm, watch,
+    watchdog, write, xferport,
+};
+#[cfg(feature = "cmd-bench")]
+use super::{bench, probe, telemetry};
+#[cfg(feature = "cmd-debugger")]
+use super::{bits, dtables, jfmt, lowmem};
+#[cfg(feature = "cmd-files")]
+use super::{each, fgrep, more};
+#[cfg(feature = "cmd-hw")]
+use super::{ecam, gpio, physmap, pio, uartline};
+use crate::bldb;
+use crate::println;
+use crate::result::Result;
+use alloc::vec::Vec;
+
+/// The type of a function implementing a command.
+pub(super) type Handler =
+    fn(&mut bldb::Config, &mut Vec<Value>) -> Result<Value>;
+
+/// Command categories, used to group commands in help output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Category {
+    Stack,
+    Transfer,
+    Ramdisk,
+    Exec,
+    Memory,
+    Vm,
+    Io,
+    Cpu,
+    Misc,
+}
+
+impl Category {
+    pub(super) const ALL: [Category; 9] = [
+        Category::Stack,
+        Category::Transfer,
+        Category::Ramdisk,
+        Category::Exec,
+        Category::Memory,
+        Category::Vm,
+        Category::Io,
+        Category::Cpu,
+        Category::Misc,
+    ];
+
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Category::Stack => "stack",
+            Category::Transfer => "transfer",
+            Category::Ramdisk => "ramdisk",
+            Category::Exec => "exec",
+            Category::Memory => "memory",
+            Category::Vm => "vm",
+            Category::Io => "io",
+            Category::Cpu => "cpu",
+            Category::Misc => "misc",
+        }
+    }
+
+    pub(super) fn description(self) -> &'static str {
+        match self {
+            Category::Stack => "environment stack manipulation",
+            Category::Transfer => "receiving and decompressing images",
+            Category::Ramdisk => "mounting and examining the ramdisk",
+            Category::Exec => "loading and calling into ELF objects",
+            Category::Memory => "examining and modifying memory",
+            Category::Vm => "virtual memory mappings",
+            Category::Io => "port IO, the IO mux, GPIOs, SMN, and PCIe",
+            Category::Cpu => "MSRs and CPUID",
+            Category::Misc => "everything else",
+        }
+    }
+
+    pub(super) fn from_name(name: &str) -> Option<Category> {
+        Self::ALL.into_iter().find(|category| category.name() == name)
+    }
+}
+
+/// A registered command.
+pub(super) struct Command {
+    pub(super) name: &'static str,
+    pub(super) aliases: &'static [&'static str],
+    pub(super) category: Category,
+    pub(super) synopsis: &'static [&'static str],
+    pub(super) help: &'static str,
+    pub(super) handler: Handler,
+}
+
+impl Command {
+    /// Returns true iff this command is known by the given name.
+    fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+
+    /// Prints the one-line summary of this command used when
+    /// listing commands.
+    fn summarize(&self) {
+        for synopsis in self.synopsis {
+            println!("    {synopsis}");
+        }
+    }
+
+    /// Prints the full help text for this command.
+    fn describe(&self) {
+        println!("{} ({})", self.name, self.category.name());
+        println!();
+        println!("usage:");
+        self.summarize();
+        if !self.aliases.is_empty() {
+            println!();
+            println!("aliases: {}", self.aliases.join(", "));
+        }
+        for namespace in NAMESPACES {
+            for &(sub, name) in namespace.members {
+                if name == self.name {
+                    println!();
+                    println!("also: {} {sub}", namespace.name);
+                }
+            }
+        }
+        println!();
+        println!("{}", self.help.trim());
+    }
+}
+
+/// A group of related commands, each of which may also be run
+/// as `<namespace> <subcommand>`: `mem xd` is `hexdump`, and
+/// `vm ls` is `mappings`.  The flat names remain the canonical
+/// ones; namespaces only add a second, more discoverable, way
+/// to reach them.
+pub(super) struct Namespace {
+    pub(super) name: &'static str,
+    pub(super) description: &'static str,
+    /// Pairs of subcommand and command names.
+    pub(super) members: &'static [(&'static str, &'static str)],
+}
+
+impl Namespace {
+    /// Returns the command with the given subcommand name.
+    pub(super) fn lookup(&self, sub: &str) -> Option<&'static Command> {
+        self.members
+            .iter()
+            .find(|&&(name, _)| name == sub)
+            .and_then(|&(_, command)| lookup(command))
+    }
+
+    /// Prints the members of the namespace.
+    pub(super) fn list(&self) {
+        println!("{} - {}:", self.name, self.description);
+        for &(sub, command) in self.members {
+            println!("    {} {sub:<8} {command}", self.name);
+        }
+        println!();
+    }
+}
+
+/// Returns the command with the given name or alias, if any.
+pub(super) fn lookup(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.is_named(name))
+}
+
+/// Returns the namespace with the given name, if any.
+pub(super) fn namespace(name: &str) -> Option<&'static Namespace> {
+    NAMESPACES.iter().find(|namespace| namespace.name == name)
+}
+
+/// Returns the names by which commands may be run: those of
+/// commands, their aliases, and namespaces.
+pub(super) fn names() -> impl Iterator<Item = &'static str> {
+    let commands = COMMANDS.iter().flat_map(|command| {
+        core::iter::once(command.name).chain(command.aliases.iter().copied())
+    });
+    commands.chain(NAMESPACES.iter().map(|namespace| namespace.name))
+}
+
+/// Prints a listing of commands by category.
+pub(super) fn list() {
+    for category in Category::ALL {
+        list_category(category);
+    }
+    println!("namespaces, as in `mem xd` for `hexdump`:");
+    for namespace in NAMESPACES {
+        let subs = namespace.members.iter().map(|&(sub, _)| sub);
+        let subs = subs.collect::<Vec<_>>().join(" ");
+        println!("    {:<6} {subs}", namespace.name);
+    }
+    println!();
+}
+
+/// Prints the commands in the given category.
+pub(super) fn list_category(category: Category) {
+    println!("{} - {}:", category.name(), category.description());
+    for command in COMMANDS.iter().filter(|c| c.category == category) {
+        command.summarize();
+    }
+    println!();
+}
+
+/// Prints help on the given topic, which may be a command, a
+/// namespaced command, a namespace, or a category.  Returns
+/// false if the topic is unknown.
+pub(super) fn help_topic(topic: &str) -> bool {
+    let namespaced = topic.split_once(' ').and_then(|(name, sub)| {
+        namespace(name).and_then(|namespace| namespace.lookup(sub.trim()))
+    });
+    if let Some(command) = lookup(topic).or(namespaced) {
+        command.describe();
+    } else if let Some(namespace) = namespace(topic) {
+        namespace.list();
+    } else if let Some(category) = Category::from_name(topic) {
+        list_category(category);
+    } else {
+        return false;
+    }
+    true
+}
+
+/// The registry itself.  Commands are kept in alphabetical
+/// order.
+pub(super) const COMMANDS: &[Command] = &[
+    Command {
+        name: "addr",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["addr <expression>"],
+        help: r#"
+Evaluates an arithmetic expression and returns its value.  The
+expression may use `+`, `-`, `*`, `/`, and parentheses, with the
+usual precedence, over numbers, which may have a `k`, `m`, or
+`g` suffix, and the names of the loader's regions, as shown by
+`region` and in the startup banner.  A region's name, or its
+name with `_base`, is its start; `_end` gives its end and `_len`
+its length.  For example, `addr ramdisk_base + 0x4000` or `addr
+xfer_end - 1M`.  The result may not be negative.
+"#,
+        handler: addr::run,
+    },
+    Command {
+        name: "addr2sym",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["addr2sym <addr>"],
+        help: r#"
+Resolves an address to the nearest symbol at or below it in the
+table read with `syms`, and returns it as `name+offset`.  Fails
+if the address is not within the symbol.
+"#,
+        handler: syms::addr2sym,
+    },
+    Command {
+        name: "audit",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &[
+            "audit [on | off | clear | show]",
+            "audit export rust|script",
+        ],
+        help: r#"
+Controls the audit log of SMN, PCIe configuration space, and
+MMIO accesses made with `rdsmn`, `wrsmn`, `rdsmni`, `wrsmni`,
+`ecamrd`, `ecamwr`, `peek`, `poke`, and `pokev`.  Logging is off
+by default; the most recent 4096 accesses are kept.  `show`
+prints the log.
+
+`export script` prints the log as bldb commands that replay the
+accesses in order.  `export rust` prints Rust arrays of the
+writes, one per SMN index, one for PCIe configuration space, and
+one for MMIO, with reads and the values they returned as
+comments; note that the relative order of accesses to different
+arrays is lost.
+"#,
+        handler: audit::run,
+    },
+    Command {
+        name: "baud",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["baud [<rate> [<framing>]]"],
+        help: r#"
+Displays or changes the console's rate and framing.  With no
+arguments, prints the current settings, e.g., `3000000 8N1`.
+Otherwise, switches the console to the given rate and, if
+given, framing, written as data bits, parity, and stop bits,
+as in `8N1` or `7E2`.  The rate must be within 3% of one that
+the UART's 48MHz clock can produce.
+
+Once the console has switched, change the terminal to match
+and press return within 30 seconds to keep the new settings;
+otherwise, the old ones are restored.  Drop to a slower rate
+for a flaky cable, or try a faster one where the link allows.
+"#,
+        handler: baud::run,
+    },
+    Command {
+        name: "beacon",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &[
+            "beacon",
+            "beacon pin <pin> <function>",
+            "beacon off",
+            "beacon pattern <phase> <pulses>",
+            "beacon signal <phase>",
+        ],
+        help: r#"
+Displays or configures the boot progress beacon.  If a beacon
+GPIO is configured, the loader emits a distinct number of
+pulses on it as it enters each phase of boot: `rust`,
+`console`, `repl`, `receiving`, `loading`, and `handoff`.  The
+pin defaults to the one named by the board's profile, if any.
+A pattern takes at most a few milliseconds, so as not to delay
+boot; watch the pin with a logic analyzer or oscilloscope.
+
+`beacon pin` uses the given GPIO pin for the beacon, where
+`<function>` is the IO mux function that selects GPIO on that
+pin.  `beacon off` disables the beacon.  `beacon pattern` sets
+the number of pulses for the given phase, at most 8; 0 silences
+it.
+`beacon signal` plays the pattern for a phase.
+"#,
+        handler: beacon::run,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "bitrev",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["bitrev <width> <value>"],
+        help: r#"
+Returns `<value>` with the order of its low `<width>` bits
+reversed, for registers whose bit numbering runs the other way.
+The value must fit in the given width, which may be at most 128.
+"#,
+        handler: bits::reverse,
+    },
+    Command {
+        name: "boot",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["boot illumos [-x] [-n] [<kernel path>]"],
+        help: r#"
+Runs the usual illumos boot recipe as a single command: receives
+a compressed ramdisk via ZMODEM (or XMODEM, with `-x`), inflates
+it (unless `-n` says it was sent uncompressed), mounts it, loads
+the kernel (by default, /platform/oxide/kernel/amd64/unix), and
+calls the kernel's entry point with the ramdisk's address and
+length as its first two arguments.  The duration of each stage
+is reported, and if a stage fails, offers to retry it.
+"#,
+        handler: boot::run,
+    },
+    Command {
+        name: "bootenv",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &[
+            "bootenv [show]",
+            "bootenv set <words...>",
+            "bootenv clear",
+        ],
+        help: r#"
+Displays, sets, or clears the boot environment string handed
+to the image entered by `call`.  `bootenv set` joins its
+arguments with single spaces, so, for example,
+`bootenv set -kd -B console=ttya` toggles kernel debugging for
+the next boot without rebuilding the ramdisk.  The string is
+NUL terminated, and its address and length (excluding the NUL)
+are appended to the arguments given to `call`.
+"#,
+        handler: bootenv::run,
+    },
+    Command {
+        name: "bootinfo",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["bootinfo [<addr>,<len>]", "bootinfo show <addr>"],
+        help: r#"
+Builds Multiboot2 boot information, for kernels that expect
+it, in the given region of memory or, by default, at the start
+of the scratch region, and returns its physical address.  The
+command line is the boot environment set with `bootenv`, the
+mounted ramdisk is passed as a module, and the memory map is
+the DRAM in the data fabric's address map, with the regions the
+loader reserves for itself, its image, the transfer, ramdisk,
+and scratch regions, and the devices it drives, marked reserved.
+A region overlapping a module is refused.  Such a kernel is
+entered with `call -m <entry> <addr>`, which passes the
+Multiboot2 magic number in EAX and the address in EBX.  `show`
+decodes the boot information at the given 8-byte aligned
+address.
+"#,
+        handler: bootinfo::run,
+    },
+    Command {
+        name: "bp",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["bp [<addr>]"],
+        help: r#"
+Sets a breakpoint at `<addr>` by replacing the byte there with
+an INT3 instruction, or, with no address, lists the breakpoints
+that are set.  When code entered with `call` reaches a
+breakpoint, its registers are shown and a nested REPL session
+starts, in which memory may be examined and changed; `cont`
+resumes the code and `step` single steps it.  Breakpoints may
+not be set in the loader's own text.
+"#,
+        handler: debug::bp,
+    },
+    Command {
+        name: "bpclear",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["bpclear [<addr>]"],
+        help: r#"
+Clears the breakpoint at `<addr>`, restoring the byte it
+replaced, or, with no address, clears all breakpoints.
+"#,
+        handler: debug::bpclear,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "bswap16",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["bswap16 <value>"],
+        help: "Returns the 16-bit `<value>` with its bytes swapped.",
+        handler: bits::swap16,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "bswap32",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["bswap32 <value>"],
+        help: "Returns the 32-bit `<value>` with its bytes reversed.",
+        handler: bits::swap32,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "bswap64",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["bswap64 <value>"],
+        help: "Returns the 64-bit `<value>` with its bytes reversed.",
+        handler: bits::swap64,
+    },
+    Command {
+        name: "call",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &[
+            "call [-f] [-n] [-q] [-v] <location> [<up to 6 args>]",
+            "call -m [-f] [-n] [-q] [-v] <location> <bootinfo addr>",
+        ],
+        help: r#"
+Calls the System V ABI compliant function at `<location>`,
+passing up to six arguments taken from the environment stack
+argument list terminated by nil.  If a boot environment has
+been set with `bootenv set`, its address and length are passed
+as two further arguments.  Unless `-f` is given, the location
+must lie within the text of the loader or of an image loaded
+with `load`, `loadmem`, or `loadcpio`, to catch calls to stale
+or mistyped addresses.  Slices and `<addr>,<len>` pairs each
+take two registers, the address and then the length.
+
+With `-m`, the location is entered as a Multiboot2 kernel, with
+the magic number 0x36d76289 in EAX and the physical address of
+the boot information built by `bootinfo`, which must lie below
+4GiB, in EBX; the boot environment is not passed, as the boot
+information holds it.  The kernel is entered in 64-bit mode,
+with the loader's page tables, as with Multiboot2's EFI amd64
+entry point.
+
+With `-n` (or `--dry-run`), shows which value would be passed in
+each register and where it came from, and whether each region
+passed by address is mapped, without making the call.
+
+With `-v`, first reads back the text of the image containing
+`<location>` and checks it against digests taken as it was
+loaded, refusing to call it if any has changed or is no longer
+readable.
+
+Unless `-q` is given, CR0, CR3, CR4, the MSRs controlling paging,
+memory typing, and the address map, the direction of each GPIO,
+and a summary of the loader's page table are recorded before the
+call and compared when it returns, and anything the callee
+changed is displayed.
+"#,
+        handler: call::run,
+    },
+    Command {
+        name: "cat",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["cat <file>"],
+        help: "Displays the contents of a file on the ramdisk.",
+        handler: cat::run,
+    },
+    Command {
+        name: "confirm",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["confirm [on | off]"],
+        help: r#"
+Enables or disables confirmation of destructive operations, and
+displays the current setting.  When enabled, as it is at boot,
+commands that can easily wedge the machine or corrupt memory,
+such as writing MSRs that control paging and memory typing or
+unmapping the loader, describe what they are about to do and
+ask for "yes" to be typed before proceeding.  Each such command
+also accepts `-f` to skip the question, for use in scripts.
+"#,
+        handler: confirm::run,
+    },
+    Command {
+        name: "cont",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["cont"],
+        help: r#"
+Ends the session started at a breakpoint or step, and resumes
+the code that was stopped.  The breakpoint it stopped at, if
+any, is stepped over and stays set.
+"#,
+        handler: debug::cont,
+    },
+    Command {
+        name: "copy",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["copy <file> [<dst addr>,<dst len>]"],
+        help: r#"
+Copies the contents of a file to a region of memory, by default
+the scratch region (see `region`).
+"#,
+        handler: copy::run,
+    },
+    #[cfg(feature = "cmd-bench")]
+    Command {
+        name: "copybench",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &[
+            "copybench <addr>,<len> [read|write|copy|all] [1|2|4|8] [<passes>]",
+        ],
+        help: r#"
+Measures read, write, and copy bandwidth over the given region
+of memory, using volatile accesses of the given width in bytes
+(8 by default), and reports the results in MB/s.  Copies move
+the first half of the region to the second.  Regions smaller
+than 64MiB are benchmarked over multiple passes unless a count
+is given.  The mapping's cacheability is reported with the
+results; to compare cached and uncached performance, map an
+alias of the same memory with `-c` and benchmark both.
+
+Write and copy benchmarks overwrite the region; a read-only
+benchmark leaves it untouched, and so may be used on MMIO.
+"#,
+        handler: bench::run,
+    },
+    Command {
+        name: "cpuid",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["cpuid <leaf> [<subleaf>]"],
+        help: r#"
+Returns the results of the `CPUID` instruction for the given
+leaf and subleaf.  The subleaf defaults to 0.
+"#,
+        handler: |config, env| cpuid::run(config, env),
+    },
+    Command {
+        name: "dd",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &[
+            "dd if=<file | addr,len> of=<addr,len> [bs=<n>] [skip=<n>] [seek=<n>] [count=<n>]",
+        ],
+        help: r#"
+Copies from a file or region of memory into a region of memory.
+`skip` blocks of the input are skipped, and the copy starts
+`seek` blocks into the output; at most `count` blocks are copied,
+or as many as fit in the output if no count is given.  Blocks
+are `bs` bytes (default 1), so `bs=4k skip=3 count=2` copies
+8KiB starting 12KiB into the input.  A copy that would overrun
+the output is refused; one that runs off the end of the input
+stops short.  Memory outside of what is copied is left alone.
+Returns the region written.
+"#,
+        handler: dd::run,
+    },
+    Command {
+        name: "deflate",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &[
+            "deflate <src addr>,<src len> [<dst addr>,<dst len>] [gzip | zlib | raw]",
+        ],
+        help: r#"
+Compresses the source region into the destination, which is the
+scratch region if none is given, and returns the compressed data.
+A destination that overlaps the source is refused.  The output is
+gzip unless `zlib` or `raw` DEFLATE is asked for; `inflate` accepts
+all three.  Compressing a memory dump before
+sending it with `sz` makes the most of a slow console.
+"#,
+        handler: deflate::run,
+    },
+    Command {
+        name: "depth",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["depth"],
+        help: "Returns the number of items on the environment stack.",
+        handler: |_config, env| Ok(stack::depth(env)),
+    },
+    Command {
+        name: "dis",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["dis [<addr>[,<len>]]"],
+        help: r#"
+Disassembles the `len` bytes (default 64) of code at `addr`, and
+shows each instruction with its address and bytes, in AT&T
+syntax.  Without an address, disassembles the code stopped at a
+breakpoint or step, or else that at which the last exception was
+taken.  Breakpoints are shown as the instructions they displaced.
+Returns the address following the last instruction shown, so
+that `dis` may be continued from there.
+"#,
+        handler: dis::run,
+    },
+    Command {
+        name: "dump",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["dump", "dump <addr>,<len> <name>", "dump -d <name>"],
+        help: r#"
+Saves a copy of the region of `len` bytes at `addr` under the
+given name, replacing any earlier copy of that name, so that it
+can be put back later with `restore`.  Copies are kept on the
+heap for the rest of the session.  With no arguments, lists the
+saved regions; with `-d`, discards one.
+"#,
+        handler: dump::dump,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "ecamrd",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["ecamrd <b/d/f> <offset>"],
+        help: r#"
+Reads a 32-bit word from PCIe extended configuration space for
+the given bus/device/function.
+"#,
+        handler: ecam::read,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "ecamwr",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["ecamwr <b/d/f> <offset> <value>"],
+        help: r#"
+Writes a 32-bit word to PCIe extended configuration space for
+the given bus/device/function.
+"#,
+        handler: ecam::write,
+    },
+    Command {
+        name: "elfinfo",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["elfinfo <file>"],
+        help: r#"
+Reads the contents of the ELF header and segment headers of an
+ELF file on the ramdisk.
+"#,
+        handler: elfinfo::run,
+    },
+    #[cfg(feature = "cmd-files")]
+    Command {
+        name: "each",
+        aliases: &["xargs"],
+        category: Category::Misc,
+        synopsis: &["each <command> [<args>...]"],
+        help: r#"
+Runs a command once for each item of the list on the stack, such
+as that returned by `find`.  Each invocation sees the item as if it had
+been piped into the command, beneath any further arguments, so
+`find /kernel | each sha256` hashes every file under `/kernel`.
+Results other than nil are displayed alongside their items and
+returned as a list.  A failure is reported and the remaining
+items are still processed, but the error of the first failure
+is returned.
+"#,
+        handler: each::run,
+    },
+    #[cfg(feature = "cmd-files")]
+    Command {
+        name: "fgrep",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["fgrep [-i] <pattern> <path|glob>"],
+        help: r#"
+Searches ramdisk files for a literal string, optionally ignoring
+ASCII case with `-i`, printing the path, byte offset, and some
+surrounding context for each match; unprintable bytes in the
+context are shown as `.`.  The last component of the path may
+contain `*` and `?` wildcards, in which case every matching
+regular file in that directory is searched.  Returns the number
+of matches.
+"#,
+        handler: fgrep::run,
+    },
+    #[cfg(feature = "cmd-files")]
+    Command {
+        name: "find",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["find <path|glob>"],
+        help: r#"
+Lists the regular files named by a path on the ramdisk, and
+returns them as a list for use with `each`.  Directories are
+searched recursively, and the last component of the path may
+contain `*` and `?` wildcards, which match files in that
+directory.
+"#,
+        handler: each::find,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "gdt",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["gdt"],
+        help: r#"
+Decodes the live global descriptor table, located with SGDT,
+showing the kind, base, limit, and privilege level of each
+descriptor.  Returns the table as a pair.
+"#,
+        handler: dtables::gdt,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "getbits",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["getbits <start>,<end> <value>"],
+        help: "Returns the given bit range from `<value>`.",
+        handler: bits::get,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "gpioget",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["gpioget <pin>"],
+        help: "Gets the state of the given GPIO pin.",
+        handler: gpio::get,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "gpioset",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["gpioset <pin> <state>"],
+        help: r#"
+Sets the given GPIO pin to the given state, which is a
+comma-separated list of:
+
+* `pu` to enable the internal pullup (`-pu` to disable)
+* `pd` to enable the internal pulldown (`-pd` to disable)
+* `ah` to configure active high
+* `al` to configure active low
+* `oh` to configure output high
+* `ol` to configure output low
+* `out` to configure as output (output enable is true)
+* `in` to configure as input (output enable is false)
+"#,
+        handler: gpio::set,
+    },
+    Command {
+        name: "handoff",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &[
+            "handoff kv <addr>,<len> [<key> <value>]...",
+            "handoff tlv <addr>,<len> [<tag> <value>]...",
+            "handoff show <addr>[,<len>]",
+        ],
+        help: r#"
+Builds a parameter blob for an experimental kernel in the given
+region of memory, and returns a slice around it; the blob can
+then be passed to `call`.  `kv` writes named entries and `tlv`
+writes entries identified by a non-zero numeric tag.  Values
+that parse as numbers are stored as 64-bit integers, and others
+as NUL-terminated strings.  `show` validates and decodes a blob;
+given just an address, the length is taken from its header.
+
+The blob is little-endian: a header of the magic `BLHO`, a u16
+version (1), a u16 entry count, a u32 total length, and the u32
+CRC-32 of the blob computed with that field zeroed.  Each entry
+is a u32 tag (0 for key-value entries), a u16 type (1 for u64,
+2 for string), u16 key length, and u32 value length, followed
+by the key and value and padded to a multiple of 8 bytes.
+Lengths include the terminating NULs.
+"#,
+        handler: handoff::run,
+    },
+    Command {
+        name: "hexdump",
+        aliases: &["xd"],
+        category: Category::Memory,
+        synopsis: &["hexdump <addr>,<len>", "hexdump <file>"],
+        help: r#"
+Produces a hexdump of `len` bytes of memory starting at `addr`,
+or of the contents of a file on the ramdisk.
+"#,
+        handler: memory::xd,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "idt",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["idt"],
+        help: r#"
+Decodes the live interrupt descriptor table, located with SIDT,
+showing the selector, handler address, IST index, and privilege
+level of each present gate.  Returns the table as a pair.
+"#,
+        handler: dtables::idt,
+    },
+    Command {
+        name: "idle",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &[
+            "idle",
+            "idle off",
+            "idle <secs> status",
+            "idle <secs> beacon",
+            "idle <secs> run <command...>",
+        ],
+        help: r#"
+Displays or configures the REPL input idle timeout, for
+unattended operation.  If no input arrives at the prompt for
+`<secs>` seconds, the loader takes the given action: `status`
+prints a status line, `beacon` signals the REPL phase on the
+boot beacon, and `run` evaluates the given command as if it
+had been typed.  As the command may not contain `.` or `|`,
+pipelines should be run via an alias, such as `zoxboot`.  The
+timer restarts after each action.  `idle off` disables the
+timeout.
+"#,
+        handler: idle::run,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "inb",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["inb <port>"],
+        help: "Reads a byte from an x86 IO port.",
+        handler: pio::inb,
+    },
+    Command {
+        name: "inflate",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["inflate [-r] <src addr>,<src len> [<dst addr>,<dst len>]"],
+        help: r#"
+Decompresses a zlib, gzip, or raw DEFLATE compressed slice
+from the given source to the given destination.  The format is
+detected from the header; data that is neither zlib nor gzip is
+treated as raw DEFLATE.  If no destination is given, the
+scratch region is used, or with `-r`, the ramdisk region, as
+when inflating a ramdisk to be mounted.  A destination that
+overlaps the source is refused.  Progress is reported periodically
+while expanding.  If the destination is too small, the error
+gives the space required, when the format records it (gzip
+does), and the space available.
+"#,
+        handler: inflate::run,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "inl",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["inl <port>"],
+        help: "Reads a 32-bit word from an x86 IO port.",
+        handler: pio::inl,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "inw",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["inw <port>"],
+        help: "Reads a 16-bit word from an x86 IO port.",
+        handler: pio::inw,
+    },
+    Command {
+        name: "iomuxget",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["iomuxget <pin>"],
+        help: r#"
+Gets the function currently active in the IO mux for the given
+pin.
+"#,
+        handler: iomux::get,
+    },
+    Command {
+        name: "iomuxset",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["iomuxset <pin> <function>"],
+        help: r#"
+Configures the IO mux for the given pin to the given function,
+where `<function>` is one of `F0`, `F1`, `F2`, or `F3`.
+"#,
+        handler: iomux::set,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "jfmt",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &[
+            "jfmt [-s] [-r <register>] <num>",
+            "jfmt [-s] [-r <register>] -d <old> <new>",
+        ],
+        help: r#"
+Formats a number using the "jazzy" format from the illumos `mdb`
+debugger.  With `-s`, the number is also shown as a two's
+complement signed integer of the register's width, or else of
+the narrowest integer type that holds it.  With `-r`, set bits
+are labeled with the names of the fields of the given register,
+and the value of each field is shown; the known registers are
+`cr0`, `cr4`, `efer`, `pcicmd`, `pcists`, `rflags`, and
+`tcon_cur_tmp`.  With `-d`, the bits that differ between the
+two values are shown instead, along with any changed fields.
+"#,
+        handler: jfmt::run,
+    },
+    Command {
+        name: "layout",
+        aliases: &[],
+        category: Category::Vm,
+        synopsis: &["layout"],
+        help: r#"
+Reports the layout of the loader's own address space: the
+segments given by linker symbols, the transfer and ramdisk
+regions, and the MMIO pages the loader uses, along with the
+regions reserved in the page table.  Each region is checked
+against the live page table, and inconsistencies are flagged:
+segments outside the loader region, overlapping regions,
+regions that are not reserved, pages that are unmapped or
+mapped with unexpected permissions, and reserved regions that
+correspond to nothing known.  Returns the number of
+inconsistencies found.
+"#,
+        handler: layout::run,
+    },
+    Command {
+        name: "load",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["load <file>"],
+        help: r#"
+Loads the given ELF file from the ramdisk and returns its entry
+point.  Gzip- and zstd-compressed files are detected by their
+magic number and decompressed as they are loaded.
+"#,
+        handler: load::run,
+    },
+    Command {
+        name: "loadcpio",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["loadcpio <src addr>,<src len> <path>"],
+        help: r#"
+Loads the ELF object at `<path>` in the cpio archive at the
+given region of memory, and returns its entry point.
+"#,
+        handler: load::loadcpio,
+    },
+    Command {
+        name: "loadmem",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["loadmem <addr>,<len>"],
+        help: r#"
+Loads an ELF object from the given region of memory, and returns
+its entry point.
+"#,
+        handler: load::loadmem,
+    },
+    Command {
+        name: "log",
+        aliases: &["dmesg"],
+        category: Category::Misc,
+        synopsis: &["log [<len>]"],
+        help: r#"
+Replays the in-memory log of console output, or its last `len`
+bytes.  The log holds the most recent 256KiB of output at every
+level, including debugging messages the console does not show,
+so that the early output of a long boot can be recovered after
+it has scrolled out of the terminal.  See `sinks` to change what
+is logged.
+"#,
+        handler: log::run,
+    },
+    Command {
+        name: "logclear",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["logclear"],
+        help: r#"
+Discards the contents of the in-memory log.
+"#,
+        handler: log::clear,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "lowmem",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &[
+            "lowmem map | unmap",
+            "lowmem ivt | bda | ebda | tables | roms",
+        ],
+        help: r#"
+Examines legacy firmware structures in the first MiB of physical
+memory.  `map` maps that MiB read-only at a fixed virtual
+address, returning it as a pair; `unmap` removes the mapping.
+With the mapping in place, `ivt` decodes the real-mode interrupt
+vector table, collapsing runs of identical vectors; `bda`
+decodes interesting fields of the BIOS data area; `ebda` shows
+the extended BIOS data area, returning it as a pair; `tables`
+finds ACPI, MP, and SMBIOS entry points and checks their
+checksums; and `roms` lists legacy option ROMs, along with the
+PCI IDs and class of each.
+"#,
+        handler: lowmem::run,
+    },
+    Command {
+        name: "ls",
+        aliases: &["list"],
+        category: Category::Ramdisk,
+        synopsis: &["ls [-d] [-h] [-s name|size|inode] <file>"],
+        help: r#"
+Lists a file or directory on the ramdisk.  Entries are shown
+in directory order unless `-s` is given, which sorts them by
+name, by size (largest first), or by inode number.  `-h`
+displays sizes with binary unit suffixes, and `-d` lists a
+directory itself rather than its contents.
+"#,
+        handler: list::run,
+    },
+    Command {
+        name: "map",
+        aliases: &[],
+        category: Category::Vm,
+        synopsis: &["map <phys addr>,<len> <virt addr> <attrs>"],
+        help: r#"
+Maps `len` bytes at physical address `phys addr` to virtual
+address `virt addr` with the given attributes, which is a
+comma-separated list of:
+
+* `r` to enable page read permission (the default)
+* `-r` to remove page read permission
+* `w` to enable page write permission
+* `-w` to remove page write permission (the default)
+* `x` to enable page executable permission (the default)
+* `-x` to remove page execute permission
+* `c` to enable page cachability (the default)
+* `-c` to disable page caching
+* `g` to set this page as a "global" page
+* `-g` to remove the global page attribute (the default)
+
+`<phys addr>`, `<len>`, `<phys addr>` must all be multiples of
+4KiB.  If these values are also multiples of 2MiB or 1GiB, those
+size mappings will be used.  To map such a region using smaller
+page sizes, issue multiple `map` commands covering smaller
+regions to make up a contiguous whole.
+"#,
+        handler: vm::map,
+    },
+    Command {
+        name: "mapping",
+        aliases: &[],
+        category: Category::Vm,
+        synopsis: &["mapping <addr>"],
+        help: r#"
+Displays the page table mapping for the given address, if any.
+"#,
+        handler: vm::mapping,
+    },
+    Command {
+        name: "mappings",
+        aliases: &[],
+        category: Category::Vm,
+        synopsis: &["mappings"],
+        help: "Displays all virtual memory mappings.",
+        handler: |config, env| vm::mappings(config, env),
+    },
+    Command {
+        name: "memcmp",
+        aliases: &["memdiff"],
+        category: Category::Memory,
+        synopsis: &["memcmp [-n <count>] <addr>,<len> <addr>,<len>"],
+        help: r#"
+Compares two regions of memory byte by byte, and shows the
+offset and the two values of each of the first `count` (default
+16) bytes that differ, for checking that an image loaded or
+copied into memory matches its source.  If the regions differ
+in length, only the length of the shorter is compared.  Returns
+the number of bytes that differ, which is 0 if the regions
+match.
+"#,
+        handler: memory::compare_regions,
+    },
+    Command {
+        name: "megapulser",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["megapulser"],
+        help: "Exists just for fun.",
+        handler: |config, env| prompt::mega_pulser(config, env),
+    },
+    #[cfg(feature = "cmd-files")]
+    Command {
+        name: "more",
+        aliases: &["less"],
+        category: Category::Ramdisk,
+        synopsis: &["more <file>"],
+        help: r#"
+Displays a file on the ramdisk a screenful at a time, sized to
+the terminal if it answers a cursor position query, and 24x80
+otherwise.  At the `--More--` prompt, space or `f` moves forward
+a page, `b` back a page, return or `j` and `k` a line, `d` and
+`u` half a page, and `g` and `G` to the start and end.
+`/pattern` searches forward for a line containing `pattern`,
+`n` repeats the search, and `q` quits.
+"#,
+        handler: more::run,
+    },
+    Command {
+        name: "mount",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["mount [<addr>,<len> [<dir>]]"],
+        help: r#"
+Mounts a UFS, FAT32, or ext2/3/4 ramdisk or cpio miniroot at
+the given directory, or at `/`, replacing whatever was mounted
+there.  Several ramdisks may be mounted at once: a path names a
+file on the ramdisk mounted at the longest directory leading to
+it, and a mount point need not exist on the ramdisk above it.
+Files on a UFS ramdisk in writable memory may be overwritten in
+place; a ramdisk in read-only memory, or of another type, is
+mounted read-only.  With no arguments, lists what is mounted,
+and where.
+"#,
+        handler: mount::run,
+    },
+    Command {
+        name: "numfmt",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["numfmt [hex | dec | oct] [group | nogroup] [le | be]"],
+        help: r#"
+Sets how numbers are displayed for the rest of the session, and
+displays the current settings.  Results, the values read and
+written by `peek`, `peekmany`, `poke`, and `pokev`, and SMN
+reads are shown in hexadecimal (the default), decimal, or octal,
+each with a prefix the reader accepts, so that what is shown may
+be typed back in.  With `group`, digits are separated into
+groups with underscores.  With `be`, `peek` and `poke` and their
+kin treat memory as big-endian, swapping the bytes of values
+read and written; `le`, the default, is the machine's own order.
+"#,
+        handler: numfmt::run,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "outb",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["outb <port> <u8>"],
+        help: "Writes a byte to an x86 IO port.",
+        handler: pio::outb,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "outl",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["outl <port> <u32>"],
+        help: "Writes a 32-bit word to an x86 IO port.",
+        handler: pio::outl,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "outw",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["outw <port> <u16>"],
+        help: "Writes a 16-bit word to an x86 IO port.",
+        handler: pio::outw,
+    },
+    Command {
+        name: "over",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["over"],
+        help: r#"
+Copies the item below the top of the environment stack to the
+top, as `a b -- a b a`.
+"#,
+        handler: |_config, env| stack::over(env),
+    },
+    Command {
+        name: "pcrread",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["pcrread [<pcr>]"],
+        help: r#"
+Reads the SHA-256 bank of TPM PCRs, or only the given PCR, and
+shows the log of measurements the loader has made.  Every image
+loaded with `load`, `loadmem`, or `loadcpio` is measured as it
+is loaded, and the text of the image containing the target of
+each `call` is measured just before the call, by extending PCR
+9 with its SHA-256 digest.  Measurements are logged whether or
+not a TPM is present.  Also shown is the value PCR 9 would have
+if nothing else had extended it, for comparison with the value
+read.  Returns the digest of the last PCR read.
+"#,
+        handler: pcr::run,
+    },
+    Command {
+        name: "peek",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["peek [-f] <addr>,<len>"],
+        help: r#"
+Reads `len` bytes starting at `addr`.  `len` must be 1, 2, 4, 8,
+or 16.  If the register at `addr` has a definition that gives
+the widths it accepts, an access of any other width is refused
+unless forced with `-f`.  Reading a register whose bits clear on
+read draws a warning.
+"#,
+        handler: memory::read,
+    },
+    Command {
+        name: "peekmany",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &[
+            "peekmany [-n <count>] [-i <interval ms>] <addr>,<len>...",
+            "peekmany [-n <count>] [-i <interval ms>] -t <addr>,<len>",
+        ],
+        help: r#"
+Reads a set of registers together and displays them as a table.
+Each register is given as an `<addr>,<len>` pair, where `len`
+is 1, 2, 4, 8, or 16 and the address is aligned to it, and is
+read with a single access of that width.  The pairs may also be
+given as a list, or with `-t`, as a table in memory of 16-byte
+entries, each a little-endian 64-bit address followed by a
+64-bit width.  As with `peek`, registers with definitions must be
+read at a width they accept.  All registers are read before any
+are displayed.
+With `-n`, the set is sampled `count` times, `interval`
+milliseconds (default 1000) apart, until a key is pressed.
+Returns the last values read as a list.
+"#,
+        handler: memory::read_many,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "physmap",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["physmap"],
+        help: r#"
+Displays the physical memory map: the DRAM address map rules
+programmed into the data fabric, each with its range, size,
+destination fabric ID, and interleave; the MMIO hole below 4GiB,
+if enabled; and the TOP_MEM and TOM2 MSRs, for comparison.
+Returns the segments of physical memory actually backed by
+DRAM, with the hole removed, as a list of `<addr>,<len>` pairs,
+which are the safe targets for `map`.
+"#,
+        handler: physmap::run,
+    },
+    Command {
+        name: "poke",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["poke [-f] <addr>,<len> <value>"],
+        help: r#"
+Pokes a value into the `len` bytes starting at `addr`.  `len`
+must be 1, 2, 4, 8, or 16.  The value is written in the byte
+order set by `numfmt`, which is little-endian by default.  If
+the register at `addr` has a definition that gives the widths
+it accepts, an access of any other width is refused unless
+forced with `-f`.
+"#,
+        handler: memory::write,
+    },
+    Command {
+        name: "pokepat",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["pokepat <addr>,<len> <pattern bytes>..."],
+        help: r#"
+Fills the `len` bytes starting at `addr` with repeated copies of
+a pattern, for stamping recognizable sentinels over buffers.
+Each argument contributes bytes to the pattern, in order: strings
+of hex digits are written byte by byte as typed, so `deadbeef`
+writes de ad be ef, and numbers are written as their big-endian
+bytes without leading zeros.  The region must be mapped writable.
+Returns the region.
+"#,
+        handler: memory::write_pattern,
+    },
+    Command {
+        name: "pokev",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["pokev [-f] <addr>,<len> <value> [<retries>]"],
+        help: r#"
+Like `poke`, but reads the value back after writing it, to catch
+device registers that silently ignore writes.  The address must
+be aligned to `len`, and the write and read are each a single
+access of that width.  On a mismatch, the write is retried up to
+`retries` times (default 0) before failing.  Register widths are
+checked as with `poke`.  Returns the value read back.
+"#,
+        handler: memory::write_verify,
+    },
+    Command {
+        name: "pick",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["pick <n>"],
+        help: r#"
+Copies the `n`th item on the environment stack to the top,
+counting the top item as 0, so that `pick 0` duplicates the
+top and `pick 1` is `over`.  Fails if the stack has too few
+items.
+"#,
+        handler: |_config, env| stack::pick(env),
+    },
+    Command {
+        name: "pop",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["pop"],
+        help: r#"
+Pops and returns the item currently at the top of the
+environment stack.  Returns nil if the stack is empty.
+"#,
+        handler: |_config, env| Ok(pop2(env)),
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "popcount",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["popcount <value>"],
+        help: "Returns the number of bits set in `<value>`.",
+        handler: bits::popcount,
+    },
+    #[cfg(feature = "cmd-bench")]
+    Command {
+        name: "probe",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["probe <addr>,<len> [<width> [<stride>]]"],
+        help: r#"
+Cautiously scans physical address space for MMIO devices.  Reads
+`width` bytes (1, 2, 4, or 8; default 4) every `stride` bytes
+(default 4KiB), temporarily mapping unmapped pages uncached.
+Addresses that return something other than all ones are
+displayed; faulting and all-ones addresses are counted.
+"#,
+        handler: probe::run,
+    },
+    Command {
+        name: "prompt",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &[
+            "prompt [tenex | spinner | pulser]",
+            "prompt segments [status | fs | safe | uptime]...",
+        ],
+        help: r#"
+Changes the prompt type.  `tenex` is the "@" prompt, and is the
+default.  The other two are animated; see the `spinner` and
+`pulser` commands.
+
+`prompt segments` selects the items of session state shown in
+brackets before the prompt, in the order given: `status` is `ok`
+or `err` for the last command line, `fs` is the type of the
+mounted ramdisk, `safe` shows whether destructive operations
+must be confirmed (see `confirm`), and `uptime` is the time
+since reset.  With no segments, nothing is shown.  With no
+arguments, displays the current settings.
+"#,
+        handler: prompt::prompt,
+    },
+    Command {
+        name: "pulser",
+        aliases: &["throbber"],
+        category: Category::Misc,
+        synopsis: &["pulser"],
+        help: r#"
+Like `spinner`, but with a different character pattern.
+"#,
+        handler: |config, env| prompt::pulser(config, env),
+    },
+    Command {
+        name: "push",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["push <item(s)>"],
+        help: "Pushes one or more items onto the environment stack.",
+        handler: |_config, _env| Ok(Value::Nil),
+    },
+    Command {
+        name: "random",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &[
+            "random [-s] <len>",
+            "random [-s] <addr>,<len>",
+            "random check",
+        ],
+        help: r#"
+Draws random numbers from the processor's hardware generator,
+using RDRAND, or RDSEED with `-s`.  Given a length of at most
+16 bytes, returns a random number of that many bytes; given a
+region of memory, fills it with random bytes.  Values that are
+all zeros or all ones, or that repeat the previous value, are
+treated as failures of the generator.  `random check` draws
+many values from each instruction and reports retries, health
+test failures, and the proportion of one bits, returning the
+number of instructions that failed.
+"#,
+        handler: random::run,
+    },
+    Command {
+        name: "rdmsr",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["rdmsr <u32>"],
+        help: r#"
+Reads the numbered MSR.  Some MSRs can be specified by name,
+such as `IA32_APIC_BASE`.
+"#,
+        handler: msr::read,
+    },
+    Command {
+        name: "rdsmn",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["rdsmn <addr>"],
+        help: r#"
+Reads a 32-bit word from the given SMN address.  If the address
+falls in the aperture of a known IP block, the block, its
+instance, and the offset of the register within it are shown
+with the value, as in `UMC3+0x104`, along with the register's
+name if it is defined.
+"#,
+        handler: smn::read,
+    },
+    Command {
+        name: "rdsmni",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["rdsmni <index> <addr>"],
+        help: r#"
+Like `rdsmn`, but using a specific address/data register pair.
+"#,
+        handler: smn::rdsmni,
+    },
+    Command {
+        name: "record",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["record [start | stop]"],
+        help: r#"
+Records the command lines evaluated at the prompt as a script
+that `replay` can evaluate again, noting whether each succeeded.
+Lines that only inspect the REPL, such as `env` or `help`, and
+the `record` and `replay` commands themselves, are not recorded.
+
+`record start` starts a new recording, discarding any earlier
+one.  `record stop` stops recording and returns the script as a
+slice, ready to be sent to the host; it remains valid until the
+next recording starts.  With no arguments, shows the state of
+the recording.  A script is text, with one step per line: `ok`
+or `fail`, then the command line as typed.
+"#,
+        handler: replay::record,
+    },
+    Command {
+        name: "region",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &[
+            "region",
+            "region resize <xfer len> <ramdisk len> [<scratch len>]",
+            "region move <xfer | ramdisk | scratch> <addr>,<len>",
+        ],
+        help: r#"
+Displays the transfer region used as the default destination
+for `rz` and `rx`, the ramdisk region used by `inflate -r`, and
+the scratch region, 16MiB by default, set aside for experiments
+and used as the default destination for `inflate`, `deflate`,
+and `copy`.
+
+`region resize` resizes those regions, which are placed
+contiguously immediately below the loader; the scratch region
+keeps its size unless one is given.  `region move` places a
+single region at the given address instead, leaving the others
+where they are; regions need not be contiguous, but must lie
+between 0x7000_0000 and the loader, and must not overlap.
+Addresses and lengths must be multiples of 4KiB, and regions
+may only be resized or moved before any has been used.
+"#,
+        handler: region::run,
+    },
+    Command {
+        name: "regs",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["regs"],
+        help: r#"
+Displays the registers of the last exception taken: its vector,
+error code, RIP, RFLAGS, the general purpose registers including
+RSP, and for page faults, the faulting address from CR2.  When
+stopped at a breakpoint or step, displays the registers of the
+code stopped instead.  A general protection or page fault taken
+by the loader itself, as when poking unmapped memory, fails the
+command that took it and returns to the REPL.
+"#,
+        handler: debug::regs,
+    },
+    Command {
+        name: "replay",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["replay [<script>] [from <step>]"],
+        help: r#"
+Evaluates the steps of a script made by `record` again, in order,
+on the current environment stack.  The script is the current
+recording, unless one is given, as when a script saved before a
+reset is sent back with `rz`.  Steps are numbered from 1, and
+`from` starts at the given step.
+
+Before a step that failed when it was recorded, asks whether to
+run it, skip it, or quit.  Should a step fail, the replay stops,
+and may be resumed from that step once the trouble is fixed.
+Returns the result of the last step.
+"#,
+        handler: replay::replay,
+    },
+    Command {
+        name: "restore",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["restore <name> [<addr>]"],
+        help: r#"
+Writes a region saved by `dump` back to memory, at the address
+it was saved from unless another is given, and reports how many
+bytes had changed since it was saved.  Returns the region.
+"#,
+        handler: dump::restore,
+    },
+    Command {
+        name: "rot",
+        aliases: &[],
+        category: Category::Stack,
+        synopsis: &["rot"],
+        help: r#"
+Moves the third item on the environment stack to the top, as
+`a b c -- b c a`.
+"#,
+        handler: |_config, env| stack::rot(env),
+    },
+    Command {
+        name: "rraw",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["rraw <dst addr>,<len>"],
+        help: r#"
+Receives exactly `len` bytes of raw data, with no framing,
+followed by their CRC-32 as four bytes, least significant
+first.  With no protocol overhead, this is the fastest way to
+send an image at high baud rates; the transfer fails if the
+CRC-32 does not match, or if the sender pauses for more than a
+second once it has started.  For example, from the host:
+
+  python3 -c 'import sys, zlib; d = open(sys.argv[1], "rb").read();
+    sys.stdout.buffer.write(d + zlib.crc32(d).to_bytes(4, "little"))' \
+    unix.z > /dev/ttyUSB0
+
+Returns the received slice.
+"#,
+        handler: rraw::run,
+    },
+    Command {
+        name: "rx",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["rx [<dst addr>,<dst len>]"],
+        help: r#"
+Receives a file via XMODEM.  If no destination is given, the
+transfer region is used.  A summary with the time taken is
+printed at the end, and if transfers have been moved off the
+console with `xferport`, the amount received and the rate are
+reported every ten seconds.
+"#,
+        handler: rx::run,
+    },
+    Command {
+        name: "ry",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["ry [<dst addr>,<dst len>]"],
+        help: r#"
+Receives a batch of files via YMODEM, such as a kernel and its
+ramdisk sent together with `sb` or a terminal program's YMODEM
+upload.  If no destination is given, the transfer region is
+used.  Each file is placed at the next page boundary after the
+one before, trimmed to the size the sender gives for it, and
+`ry` returns a list of the name, address, and length of each.
+"#,
+        handler: ry::run,
+    },
+    Command {
+        name: "rz",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["rz [-c] [<dst addr>,<dst len>]"],
+        help: r#"
+Receives a file via ZMODEM.  If no destination is given, the
+transfer region is used.  With `-c`, the SHA-256 digest of the
+data is computed as it arrives, rather than by reading it back
+afterwards, and printed when the transfer completes; `rz` then
+returns a list of the received slice and its digest.  A summary
+with the time taken is printed at the end.  If transfers have
+been moved off the console with `xferport`, the amount
+received, the rate, and the time left are also reported every
+ten seconds, at info level; lower the console's level to
+silence them.  On the console, they would be taken for part of
+the transfer.
+"#,
+        handler: rz::run,
+    },
+    Command {
+        name: "rzstats",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["rzstats"],
+        help: r#"
+Shows what happened during the last ZMODEM receive: its session
+identifier, drawn from RDRAND and logged as it starts, the
+number of blocks and bytes received, CRC and other errors, and
+the CRC-32 of the data as it arrived, alongside that of the
+same memory now.  If the former matches the CRC-32 of the file
+sent but the latter does not, the image was corrupted in memory
+after it arrived, rather than on the line.  Each block
+received, and each error, is also logged to the in-memory log.
+Returns the CRC-32 of the data as it arrived.
+"#,
+        handler: rz::stats,
+    },
+    Command {
+        name: "search",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &[
+            "search [-n <count>] <addr>,<len> <pattern bytes>...",
+            "search [-n <count>] <addr>,<len> -s <text>...",
+        ],
+        help: r#"
+Searches a region of memory for a pattern of bytes, and shows
+the address of each of the first `count` (default 32) matches.
+The pattern is given as for `pokepat`, or with `-s`, as text,
+the words of which are joined by single spaces.  Only the pages
+of the region that are mapped readable are searched, so a wide
+region may be given to find, e.g., a structure in a loaded
+kernel; a match cannot span an unmapped page.  Returns the
+addresses of the matches shown as a list.
+"#,
+        handler: search::run,
+    },
+    Command {
+        name: "selfsum",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["selfsum"],
+        help: r#"
+Computes the SHA256 checksum of the loader's own text and
+read-only data, and checks it against the digest recorded in
+the image by `cargo xtask dist`.  Also prints the build ID
+recorded there.  Fails if the two do not match; an image that
+was not packaged has nothing to check against, and the checksum
+is simply returned.
+"#,
+        handler: sha::selfsum,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "setbits",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["setbits <start>,<end> <new bits> <value>"],
+        help: r#"
+Sets the given bit range in `<value>` to `<new bits>`.
+"#,
+        handler: bits::set,
+    },
+    Command {
+        name: "sha256",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["sha256 <file>"],
+        help: r#"
+Computes the SHA256 checksum of a file in the ramdisk.
+"#,
+        handler: sha::run,
+    },
+    Command {
+        name: "sha256mem",
+        aliases: &[],
+        category: Category::Memory,
+        synopsis: &["sha256mem <addr>,<len>"],
+        help: r#"
+Computes the SHA256 checksum over a region of memory.
+"#,
+        handler: sha::mem,
+    },
+    Command {
+        name: "sinks",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["sinks [<sink> off|error|warn|info|debug]"],
+        help: r#"
+Shows or changes where console output goes.  Output is written to
+every enabled sink whose level admits it: `uart`, the console
+UART, which by default shows ordinary output but not debugging
+messages; and `log`, an in-memory record of recent output, which
+keeps everything.  With a sink and a level, sets the most verbose
+level the sink accepts, or with `off`, disables it.  Interactive
+input and its echo always go to the console.
+"#,
+        handler: sinks::run,
+    },
+    Command {
+        name: "sp",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &[
+            "sp status | ident | bsu | ackstart",
+            "sp key <key> [<max len>]",
+            "sp bootfail <reason> [<message>]",
+        ],
+        help: r#"
+Talks to the service processor over the IPCC channel, which is
+opened on first use on UART 1.  `status` shows the SP's status
+and the startup options it has set for this boot, returning the
+latter; `ident` shows the board's model, revision, and serial
+number; and `bsu` returns the boot storage unit from which the
+host should boot.  `key` looks up a boot parameter by number or
+by name, one of `ping`, `installinator-image-id`, `inventory`,
+`etc-system`, or `dtrace-conf`, returning its value as a string
+if it is one and otherwise dumping it in hex.  `ackstart` tells
+the SP that the host has seen its startup options and is
+booting, and `bootfail` reports a boot failure with a numeric
+reason and an optional message.
+"#,
+        handler: sp::run,
+    },
+    Command {
+        name: "spinner",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["spinner"],
+        help: r#"
+Displays a moving "spinner" on the terminal until a byte is
+received on the UART.
+"#,
+        handler: |config, env| prompt::spinner(config, env),
+    },
+    Command {
+        name: "stat",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["stat <file>"],
+        help: r#"
+Displays the metadata of a file on the ramdisk: its inode, mode,
+links, and owners, and both its size and the storage allocated to
+it, which is less than the size of a sparse file with holes.
+"#,
+        handler: list::stat,
+    },
+    Command {
+        name: "state",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["state"],
+        help: r#"
+Summarizes the state created by the session: the mounted
+ramdisk and whether its mapping is still current, the use of the
+transfer and ramdisk regions, the boot environment, the images
+loaded and their entry points and text, the audit log, and the
+last value written to each MSR and SMN register from the REPL.
+"#,
+        handler: state::run,
+    },
+    Command {
+        name: "step",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["step"],
+        help: r#"
+Ends the session started at a breakpoint or step, and resumes
+the code that was stopped for a single instruction, after which
+it stops again.
+"#,
+        handler: debug::step,
+    },
+    Command {
+        name: "sym",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["sym <name>"],
+        help: r#"
+Looks up a symbol by name in the table read with `syms`, and
+returns its address.
+"#,
+        handler: syms::sym,
+    },
+    Command {
+        name: "syms",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["syms [<file>]"],
+        help: r#"
+Reads the ELF symbol table of an image on the ramdisk, usually
+the image loaded with `load`, replacing any read before, and
+returns the number of symbols read.  Without a file, shows what
+the table was read from.  Once read, the registers shown at a
+breakpoint or by `regs`, and the code shown by `dis`, are
+labeled with the names of the symbols they are within.
+"#,
+        handler: syms::syms,
+    },
+    Command {
+        name: "sysregs",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["sysregs [<reg>]", "sysregs set|clear [-f] <reg> <flag>"],
+        help: r#"
+Reads CR0, CR2, CR3, CR4, CR8, EFER, and XCR0, or just the named
+register, and shows each flag by name with its meaning.  Returns
+the value of a single register.
+
+`set` and `clear` change a single flag, after confirmation.  Only
+flags that cannot affect paging, caching, or the processor mode
+may be changed: CR0 MP, NE, WP, and AM; CR4 TSD, DE, PCE, UMIP,
+and FSGSBASE; and EFER SCE.
+"#,
+        handler: sysregs::run,
+    },
+    Command {
+        name: "sz",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["sz <addr>,<len>", "sz <file>"],
+        help: r#"
+Sends a region of memory, or a file on the ramdisk or under
+`/builtin`, via ZMODEM, to be received with `rz` at the other end
+of the console.  Memory is sent under a name giving its address
+and length, as `mem-<addr>-<len>.bin`; files under their own.
+Slices, such as those returned by `transcript stop` and `record
+stop`, may be sent directly.
+"#,
+        handler: sz::run,
+    },
+    #[cfg(feature = "cmd-bench")]
+    Command {
+        name: "telemetry",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["telemetry [<count> [<interval ms>]]"],
+        help: r#"
+Prints the SMU firmware version, and then `count` samples (one
+by default) of package power, control temperature (Tctl), and
+the effective clock and C0 residency of the current core, each
+averaged over the preceding interval (1000ms by default).  Any
+input stops sampling early.
+
+Power comes from the RAPL package energy counter, Tctl from the
+SMU thermal block, and clocks from the APERF and MPERF counters.
+The SMU is also asked for its PM table, and if the table is
+mapped, each sample is followed by the SMU's own package power
+(PPT), current (TDC), and thermal readings and limits.
+"#,
+        handler: telemetry::run,
+    },
+    Command {
+        name: "transcript",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["transcript [start [<addr>,<len>] | stop]"],
+        help: r#"
+Records a transcript of the session: every line typed at the
+prompt, and all output that reaches the console, each line
+stamped with the time since power on, in seconds.  Output
+captured by redirection, and the data of file transfers, are
+not recorded.
+
+`transcript start` starts recording into the given memory, or
+into a 1MiB buffer on the heap if none is given, replacing any
+earlier transcript.  Once the buffer is full, the rest of the
+session is dropped.  `transcript stop` stops recording and
+returns the transcript as a slice, ready to be sent to the host.
+With no arguments, shows the state of the transcript.
+"#,
+        handler: transcript::run,
+    },
+    #[cfg(feature = "cmd-debugger")]
+    Command {
+        name: "tss",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["tss"],
+        help: r#"
+Decodes the task state segment named by the task register, read
+with STR, showing its stack pointers and interrupt stacks.
+Returns the segment as a pair, or nil if no task register is
+loaded.
+"#,
+        handler: dtables::tss,
+    },
+    #[cfg(feature = "cmd-hw")]
+    Command {
+        name: "uartline",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["uartline [afc|dtr|rts|xonxoff on|off]..."],
+        help: r#"
+Displays the console UART's modem control and status lines,
+whether hardware (RTS/CTS) auto flow control and software
+(XON/XOFF) flow control are enabled, and its transmit and
+receive FIFO levels.  `afc`, `dtr`, and `rts` turn auto flow
+control, DTR, and RTS on or off.  Note that with auto flow
+control on, RTS must also be on for the UART to drive RTS
+itself; otherwise only CTS is honored.  A terminal server that
+does not assert CTS will stall output while auto flow control
+is on.
+
+`xonxoff` turns software flow control on or off, for terminal
+servers that only support it.  While it is on, an XOFF (Ctrl-S)
+from the far end pauses output, including the pager's, until an
+XON (Ctrl-Q) arrives, and neither is taken as input.  A BREAK
+also resumes output.  ZMODEM transfers honor it, as ZMODEM
+escapes XON and XOFF in its data; XMODEM does not, so it is
+suspended during `rx`.
+"#,
+        handler: uartline::run,
+    },
+    Command {
+        name: "umount",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["umount [<dir>]"],
+        help: "Unmounts the ramdisk mounted at the given directory, or `/`.",
+        handler: mount::umount,
+    },
+    Command {
+        name: "unmap",
+        aliases: &[],
+        category: Category::Vm,
+        synopsis: &["unmap [-f] <virt addr>,<len>"],
+        help: r#"
+Removes the virtual memory mapping for the range of virtual
+address space covering `<len>` bytes starting at `<virt addr>`.
+As with mapping, `<len>` and `<virt addr>` must both be
+multiples of 4KiB.  If these values are also multiples of 2MiB
+or 1GiB, those size mappings will be used.  To unmap such a
+region mapped with smaller page sizes, issue multiple `unmap`
+calls.  Unmapping any part of the loader must be confirmed,
+unless `-f` is given.
+"#,
+        handler: vm::unmap,
+    },
+    Command {
+        name: "verifyfs",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["verifyfs <manifest>"],
+        help: r#"
+Verifies the mounted ramdisk against a manifest file stored in
+it.  The manifest lists a SHA256 digest and path per line, in
+the format produced by `sha256sum`; paths are relative to the
+root of the ramdisk.  Every listed file is checksummed, and any
+whose digests do not match are reported as `MISMATCH`, any that
+do not exist as `MISSING`, and any that cannot be read as
+`FAILED`, with the error.  Fails unless every file verifies.
+"#,
+        handler: sha::verifyfs,
+    },
+    Command {
+        name: "watch",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["watch [<addr>,<len> [r | w | rw]]"],
+        help: r#"
+Sets a hardware watchpoint in one of the four debug address
+registers, or, with no arguments, lists those set.  `<len>` must
+be 1, 2, 4, or 8, and `<addr>` aligned to it.  A watchpoint
+matches writes (`w`, the default) or any access (`rw`; as x86
+cannot watch for reads alone, `r` is the same).  When an access
+matches, the watchpoint and the address of the instruction after
+the access are reported; if it was made by code entered with
+`call`, its registers are shown and a nested REPL session
+starts, as at a breakpoint.  Useful for finding what clobbers a
+loaded image before it is called.
+"#,
+        handler: watch::watch,
+    },
+    Command {
+        name: "watchclear",
+        aliases: &[],
+        category: Category::Exec,
+        synopsis: &["watchclear [<addr>]"],
+        help: r#"
+Clears the watchpoint at `<addr>`, or, with no address, clears
+all watchpoints.
+"#,
+        handler: watch::watchclear,
+    },
+    Command {
+        name: "watchdog",
+        aliases: &[],
+        category: Category::Misc,
+        synopsis: &["watchdog [disable [-f]]"],
+        help: r#"
+Reports whether the FCH watchdog is running.  If firmware left
+it running when the loader was entered, long-running operations
+pet it as they poll the console.  `watchdog disable` stops it,
+so that a hung machine is no longer reset; this must be
+confirmed unless `-f` is given.
+"#,
+        handler: watchdog::run,
+    },
+    Command {
+        name: "wrmsr",
+        aliases: &[],
+        category: Category::Cpu,
+        synopsis: &["wrmsr [-f] <u32> <u64>"],
+        help: r#"
+Writes the given value to the given MSR.  Writes to MSRs that
+control paging, memory typing, and the layout of the address
+space must be confirmed, unless `-f` is given.
+"#,
+        handler: msr::write,
+    },
+    Command {
+        name: "write",
+        aliases: &[],
+        category: Category::Ramdisk,
+        synopsis: &["write <file> <addr>,<len> [<offset>]"],
+        help: r#"
+Overwrites part of a file on the mounted ramdisk with the given
+region of memory, starting at the given offset in the file, or
+at its start.  Files are patched in place: only UFS ramdisks can
+be written, the file must already extend past the end of the
+write, and its holes cannot be written, as that would mean
+allocating storage.
+"#,
+        handler: write::run,
+    },
+    Command {
+        name: "wrsmn",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["wrsmn <addr> <value>"],
+        help: "Writes a 32-bit word to the given SMN address.",
+        handler: smn::write,
+    },
+    Command {
+        name: "wrsmni",
+        aliases: &[],
+        category: Category::Io,
+        synopsis: &["wrsmni <index> <addr> <value>"],
+        help: r#"
+Like `wrsmn`, but using a specific address/data register pair.
+"#,
+        handler: smn::wrsmni,
+    },
+    Command {
+        name: "xferport",
+        aliases: &[],
+        category: Category::Transfer,
+        synopsis: &["xferport [console|uart0|uart1]"],
+        help: r#"
+Selects the UART over which `rx`, `ry`, `rz`, `rraw`, and `sz`
+transfer files, or, with no argument, shows which is in use.
+By default, transfers use the console.  With `uart1`, UART 1 is
+set up at 3Mbaud 8N1 with hardware flow control, and transfers
+use it instead, so that the console can report their progress.
+The REPL still waits for each transfer to finish.  `console` or
+`uart0` moves transfers back.
+
+UART 1 is also used for the SP's IPCC channel, so the two
+cannot be used in the same session.  `uart2` and `uart3` are
+refused: those UARTs share their pins with the flow control
+lines of UARTs 0 and 1.
+"#,
+        handler: xferport::run,
+    },
+];
+
+/// The namespaces.  Each member must name a command in
+/// `COMMANDS`, and namespace names must not collide with
+/// command names.
+static NAMESPACES: &[Namespace] = &[
+    Namespace {
+        name: "cpu",
+        description: "processor state",
+        members: &[
+            ("id", "cpuid"),
+            ("regs", "sysregs"),
+            #[cfg(feature = "cmd-debugger")]
+            ("gdt", "gdt"),
+            #[cfg(feature = "cmd-debugger")]
+            ("idt", "idt"),
+            #[cfg(feature = "cmd-debugger")]
+            ("tss", "tss"),
+        ],
+    },
+    Namespace {
+        name: "fs",
+        description: "the ramdisk filesystem",
+        members: &[
+            ("mount", "mount"),
+            ("umount", "umount"),
+            ("ls", "ls"),
+            ("stat", "stat"),
+            ("cat", "cat"),
+            ("copy", "copy"),
+            ("dd", "dd"),
+            #[cfg(feature = "cmd-files")]
+            ("find", "find"),
+            #[cfg(feature = "cmd-files")]
+            ("grep", "fgrep"),
+            #[cfg(feature = "cmd-files")]
+            ("more", "more"),
+            ("verify", "verifyfs"),
+            ("write", "write"),
+        ],
+    },
+    #[cfg(feature = "cmd-hw")]
+    Namespace {
+        name: "gpio",
+        description: "GPIO pins",
+        members: &[("get", "gpioget"), ("set", "gpioset")],
+    },
+    #[cfg(feature = "cmd-hw")]
+    Namespace {
+        name: "io",
+        description: "port IO",
+        members: &[
+            ("inb", "inb"),
+            ("inw", "inw"),
+            ("inl", "inl"),
+            ("outb", "outb"),
+            ("outw", "outw"),
+            ("outl", "outl"),
+        ],
+    },
+    Namespace {
+        name: "iomux",
+        description: "the pin IO mux",
+        members: &[("get", "iomuxget"), ("set", "iomuxset")],
+    },
+    Namespace {
+        name: "mem",
+        description: "examining and modifying memory",
+        members: &[
+            ("xd", "hexdump"),
+            ("peek", "peek"),
+            ("many", "peekmany"),
+            ("poke", "poke"),
+            ("pokev", "pokev"),
+            ("pat", "pokepat"),
+            ("dump", "dump"),
+            ("restore", "restore"),
+            ("sha", "sha256mem"),
+            ("cmp", "memcmp"),
+            ("search", "search"),
+            #[cfg(feature = "cmd-debugger")]
+            ("low", "lowmem"),
+            #[cfg(feature = "cmd-hw")]
+            ("phys", "physmap"),
+        ],
+    },
+    Namespace {
+        name: "msr",
+        description: "model-specific registers",
+        members: &[("rd", "rdmsr"), ("wr", "wrmsr")],
+    },
+    #[cfg(feature = "cmd-hw")]
+    Namespace {
+        name: "pci",
+        description: "PCIe extended configuration space",
+        members: &[("rd", "ecamrd"), ("wr", "ecamwr")],
+    },
+    Namespace {
+        name: "smn",
+        description: "the system management network",
+        members: &[
+            ("rd", "rdsmn"),
+            ("rdi", "rdsmni"),
+            ("wr", "wrsmn"),
+            ("wri", "wrsmni"),
+        ],
+    },
+    #[cfg(feature = "cmd-hw")]
+    Namespace {
+        name: "uart",
+        description: "the console UART",
+        members: &[("baud", "baud"), ("line", "uartline")],
+    },
+    Namespace {
+        name: "vm",
+        description: "virtual memory mappings",
+        members: &[
+            ("map", "map"),
+            ("unmap", "unmap"),
+            ("show", "mapping"),
+            ("ls", "mappings"),
+            ("layout", "layout"),
+        ],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let mut names = Vec::new();
+        for command in COMMANDS {
+            names.push(command.name);
+            names.extend_from_slice(command.aliases);
+        }
+        let len = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), len);
+    }
+
+    #[test]
+    fn every_command_is_categorized_and_documented() {
+        for command in COMMANDS {
+            assert!(Category::ALL.contains(&command.category));
+            assert!(!command.synopsis.is_empty());
+            assert!(command.synopsis[0].starts_with(command.name));
+            assert!(!command.help.trim().is_empty());
+        }
+    }
+
+    #[test]
+    fn namespaces_resolve() {
+        for namespace in NAMESPACES {
+            assert!(lookup(namespace.name).is_none(), "{}", namespace.name);
+            assert!(!namespace.members.is_empty());
+            for &(sub, command) in namespace.members {
+                let found = namespace.lookup(sub).map(|c| c.name);
+                assert_eq!(found, Some(command), "{} {sub}", namespace.name);
+            }
+        }
+        let mem = namespace("mem").unwrap();
+        assert_eq!(mem.lookup("xd").map(|c| c.name), Some("hexdump"));
+        assert!(mem.lookup("hexdump").is_none());
+        assert!(namespace("nonesuch").is_none());
+    }
+
+    #[test]
+    fn lookup_by_alias() {
+        assert_eq!(lookup("xd").map(|c| c.name), Some("hexdump"));
+        assert_eq!(lookup("list").map(|c| c.name), Some("ls"));
+        assert!(lookup("nonesuch").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Completion at the prompt.
+//!
+//! When TAB is typed, the line discipline asks for the words that
+//! the last word of the line might be in full.  Where a command
+//! is expected, at the start of the line or after a `|` or `.`,
+//! those are the names of commands, their aliases, namespaces,
+//! the commands of the reader, and aliases defined with `alias`;
+//! after a namespace, its subcommands.  Elsewhere, a word
+//! beginning with `/` is a path, and is completed from the
+//! entries of the directory it names, on the mounted ramdisks or
+//! under `/builtin`, and from the mount points directly beneath
+//! it.
+
+use super::{commands, reader};
+use crate::mmu;
+use crate::ramdisk::{self, FileType};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The prefixes that manipulate the stack before a command.
+const PREFIXES: [char; 3] = ['@', '#', '$'];
+
+/// Returns the words that the last word of the line might be.
+pub(super) fn candidates(
+    line: &str,
+    aliases: &BTreeMap<String, String>,
+    mounts: &ramdisk::Mounts,
+    builtin: Option<&dyn ramdisk::FileSystem>,
+    page_table: &mmu::LoaderPageTable,
+) -> Vec<String> {
+    let word = line.rsplit(|c: char| c.is_ascii_whitespace()).next();
+    let word = word.unwrap_or_default();
+    let before = &line[..line.len() - word.len()];
+    // The words of the command in progress before this one, last
+    // first.
+    let args = before
+        .split_ascii_whitespace()
+        .rev()
+        .take_while(|&arg| arg != "|" && arg != ".")
+        .collect::<Vec<_>>();
+    let mut words = match args.as_slice() {
+        [] => commands(word, aliases),
+        _ if word.contains('|') => commands(word, aliases),
+        [name] => {
+            match commands::namespace(name.trim_start_matches(PREFIXES)) {
+                Some(namespace) => namespace
+                    .members
+                    .iter()
+                    .filter(|&&(sub, _)| sub.starts_with(word))
+                    .map(|&(sub, _)| String::from(sub))
+                    .collect(),
+                None => paths(word, mounts, builtin, page_table),
+            }
+        }
+        _ => paths(word, mounts, builtin, page_table),
+    };
+    words.sort();
+    words.dedup();
+    words
+}
+
+/// Returns the commands that the word might name.  Anything
+/// before the name itself, such as a `|` or `@`, is kept.
+fn commands(word: &str, aliases: &BTreeMap<String, String>) -> Vec<String> {
+    let start = word.rfind(['|', '@', '#', '$']).map_or(0, |k| k + 1);
+    let (prefix, name) = word.split_at(start);
+    let aliases = aliases.keys().map(String::as_str);
+    commands::names()
+        .chain(reader::READER_COMMANDS.iter().copied())
+        .map(|command| -> &str { command })
+        .chain(aliases)
+        .filter(|command| command.starts_with(name))
+        .map(|command| format!("{prefix}{command}"))
+        .collect()
+}
+
+/// Returns the paths that the word might name.  Directories end
+/// with a `/`.
+fn paths(
+    word: &str,
+    mounts: &ramdisk::Mounts,
+    builtin: Option<&dyn ramdisk::FileSystem>,
+    page_table: &mmu::LoaderPageTable,
+) -> Vec<String> {
+    if !word.starts_with('/') {
+        return Vec::new();
+    }
+    let Some((dir, partial)) = word.rsplit_once('/') else {
+        return Vec::new();
+    };
+    let path = if dir.is_empty() { "/" } else { dir };
+    let builtin_dir = builtin.map(|_| ramdisk::BUILTIN_DIR);
+    let points = mounts.iter().map(|(point, _)| point).chain(builtin_dir);
+    let mut words = points
+        .filter_map(|point| point.strip_prefix(dir)?.strip_prefix('/'))
+        .filter_map(|rest| rest.split('/').next())
+        .filter(|name| !name.is_empty() && name.starts_with(partial))
+        .map(|name| format!("{dir}/{name}/"))
+        .collect::<Vec<_>>();
+    let Ok((fs, path)) = ramdisk::lookup(mounts, builtin, page_table, path)
+    else {
+        return words;
+    };
+    let Ok(entries) = fs.readdir(&path) else {
+        return words;
+    };
+    let entries = entries.into_iter().filter(|entry| {
+        let name = entry.name.as_str();
+        name != "." && name != ".." && !name.contains('/')
+    });
+    for entry in entries.filter(|entry| entry.name.starts_with(partial)) {
+        let slash = if entry.file_type == FileType::Dir { "/" } else { "" };
+        words.push(format!("{dir}/{}{slash}", entry.name));
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use crate::result::{Error, Result};
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    /// A file system holding `/etc`, with `system` and `init.d`
+    /// beneath it, and `/unix`.
+    struct Tree;
+
+    fn entry(name: &str, file_type: FileType) -> ramdisk::DirEntry {
+        ramdisk::DirEntry {
+            name: String::from(name),
+            ino: 0,
+            file_type,
+            perms: 0o755,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            allocated: 0,
+        }
+    }
+
+    impl ramdisk::FileSystem for Tree {
+        fn open(&self, _path: &str) -> Result<Box<dyn ramdisk::File>> {
+            Err(Error::FsNoFile)
+        }
+
+        fn stat(&self, _path: &str) -> Result<ramdisk::DirEntry> {
+            Err(Error::FsNoFile)
+        }
+
+        fn readdir(&self, path: &str) -> Result<Vec<ramdisk::DirEntry>> {
+            match path {
+                "/" => Ok(vec![
+                    entry(".", FileType::Dir),
+                    entry("etc", FileType::Dir),
+                    entry("unix", FileType::Regular),
+                ]),
+                "/etc" => Ok(vec![
+                    entry("..", FileType::Dir),
+                    entry("system", FileType::Regular),
+                    entry("init.d", FileType::Dir),
+                ]),
+                _ => Err(Error::FsNoFile),
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            "tree"
+        }
+    }
+
+    #[test]
+    fn words() {
+        let page_table =
+            mmu::LoaderPageTable::new(mmu::PageTable::new(), &[], &[]);
+        let mut aliases = BTreeMap::new();
+        aliases.insert(String::from("zoxboot"), String::from("rz"));
+        let mounts = ramdisk::Mounts::default();
+        let complete = |line| {
+            candidates(line, &aliases, &mounts, Some(&Tree), &page_table)
+        };
+        assert_eq!(complete("infl"), ["inflate"]);
+        assert_eq!(complete("rz | @infl"), ["@inflate"]);
+        assert_eq!(complete("rz|infl"), ["rz|inflate"]);
+        assert_eq!(complete("cle"), ["clear"]);
+        assert_eq!(complete("zox"), ["zoxboot"]);
+        assert_eq!(complete("unal"), ["unalias"]);
+        assert!(complete("mem ").contains(&String::from("xd")));
+        assert_eq!(complete("cat /"), ["/builtin/"]);
+        assert_eq!(
+            complete("cat /builtin/"),
+            ["/builtin/etc/", "/builtin/unix"]
+        );
+        assert_eq!(
+            complete("cat /builtin/etc/"),
+            ["/builtin/etc/init.d/", "/builtin/etc/system"]
+        );
+        assert_eq!(complete("cat /builtin/etc/s"), ["/builtin/etc/system"]);
+        assert!(complete("cat /nope/").is_empty());
+        assert!(complete("push inf").is_empty());
+        assert!(complete("cat /etc/").is_empty());
+
+        // Mount points, and the directories leading to them, are
+        // completed whether or not they exist above.
+        let mut mounts = ramdisk::Mounts::default();
+        let mounted =
+            ramdisk::Mounted::new(Box::new(Tree), page_table.buf(&[]));
+        mounts.mount("/mnt/a", mounted).unwrap();
+        let complete =
+            |line| candidates(line, &aliases, &mounts, None, &page_table);
+        assert_eq!(complete("cat /"), ["/mnt/"]);
+        assert_eq!(complete("cat /mnt/"), ["/mnt/a/"]);
+        assert_eq!(complete("cat /mnt/a/u"), ["/mnt/a/unix"]);
+    }
+
+    #[test]
+    fn tab() {
+        let mut sim = Sim::new();
+        let out = sim.session("pus\t5 6\ndep\t\n");
+        assert!(out.starts_with("@push 5 6\n"), "{out}");
+        assert!(out.contains("@depth \n"), "{out}");
+        // Ambiguous names are listed, and the line drawn again.
+        let out = sim.session("pu\tsh\n");
+        assert!(out.starts_with("@pu\npulser  push\n@push\n"), "{out}");
+    }
+}
@@ -0,0 +1,80 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Confirmation of destructive operations.
+//!
+//! Commands that can wedge or corrupt the machine in ways that
+//! are easy to trigger with a typo ask the user to type "yes"
+//! before proceeding.  Scripts can skip the question either per
+//! command, with `-f`, or globally, with `confirm off`.
+
+use crate::bldb;
+use crate::cons;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How long to wait for an answer before giving up.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Pops a leading `-f` from the stack, returning true iff it
+/// was present.
+pub(super) fn take_force(env: &mut Vec<Value>) -> bool {
+    let force = matches!(env.last(), Some(Value::Str(s)) if s == "-f");
+    if force {
+        env.pop();
+    }
+    force
+}
+
+/// Describes the operation and asks the user to confirm it,
+/// unless it was forced or confirmations are disabled.
+pub(super) fn confirm(
+    config: &mut bldb::Config,
+    force: bool,
+    what: &str,
+) -> Result<()> {
+    if force || !config.confirm {
+        return Ok(());
+    }
+    println!("{what}");
+    let prompt = |term: &mut Uart| {
+        const PROMPT: &str = "type yes to continue: ";
+        term.puts(PROMPT);
+        PROMPT.len()
+    };
+    let mut buf = [0u8; 16];
+    match cons::readline_timeout(prompt, &mut config.cons, TIMEOUT, &mut buf) {
+        Ok(answer) if answer.trim() == "yes" => Ok(()),
+        Err(e) if !e.is(Error::Timeout) => Err(e),
+        _ => {
+            println!("not confirmed");
+            Err(Error::NotConfirmed)
+        }
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: confirm [on | off]");
+        error
+    };
+    match repl::popenv(env) {
+        Value::Nil => {}
+        arg => match arg.as_string().map_err(usage)?.as_str() {
+            "on" => config.confirm = true,
+            "off" => config.confirm = false,
+            _ => return Err(usage(arg.bad_arg("on or off"))),
+        },
+    }
+    let state = if config.confirm { "on" } else { "off" };
+    println!("confirmation of destructive operations is {state}");
+    Ok(Value::Nil)
+}
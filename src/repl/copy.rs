@@ -3,23 +3,30 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::bldb;
+use crate::cons;
 use crate::println;
 use crate::ramdisk;
 use crate::repl::{self, Value};
-use crate::result::{Error, Result};
+use crate::result::Result;
 use alloc::vec::Vec;
 
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: copy <file> <dst addr>,<dst len>");
+        println!("usage: copy <file> [<dst addr>,<dst len>]");
         error
     };
     let path = repl::popenv(env).as_string().map_err(usage)?;
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
     let dst = repl::popenv(env)
         .as_slice_mut(&config.page_table, 0)
-        .and_then(|o| o.ok_or(Error::BadArgs))
-        .map_err(usage)?;
-    let len = ramdisk::copy(fs.as_ref(), &path, dst)?;
-    Ok(Value::Slice(&dst[..len]))
+        .map_err(usage)?
+        .unwrap_or_else(|| config.scratch_region_init_mut());
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let mut poll = cons::poller(&mut config.cons);
+    let len = ramdisk::copy(fs, &path, dst, &mut poll)?;
+    Ok(Value::Slice(config.page_table.buf(&dst[..len])))
 }
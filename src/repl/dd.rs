@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Copies between files and memory at arbitrary offsets.
+//!
+//! `dd` takes its operands in the style of its namesake: an input
+//! and an output, each a file or an `<addr>,<len>` pair, and a
+//! block size in which to count the bytes skipped in the input,
+//! sought past in the output, and copied.  It subsumes `copy` and
+//! memory-to-memory copies, and extracts part of a larger object,
+//! such as one segment of a blob, without working out addresses
+//! by hand.
+
+use super::reader;
+use crate::bldb;
+use crate::cons;
+use crate::mem;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+use core::slice;
+
+/// An input or output of a copy.
+#[derive(Debug, Eq, PartialEq)]
+enum Endpoint {
+    File(String),
+    Mem(usize, usize),
+}
+
+/// The operands of `dd`, with counts converted to bytes.
+#[derive(Debug, Eq, PartialEq)]
+struct Operands {
+    input: Endpoint,
+    output: Endpoint,
+    skip: usize,
+    seek: usize,
+    count: Option<usize>,
+}
+
+fn parse_endpoint(s: &str) -> Result<Endpoint> {
+    match reader::parse_value(s)? {
+        Value::Pair(addr, len) => Ok(Endpoint::Mem(addr, len)),
+        Value::Str(path) => Ok(Endpoint::File(path)),
+        v => Err(v.bad_arg("file or <addr>,<len>")),
+    }
+}
+
+/// Parses `key=value` operands.  Block counts are multiplied by
+/// the block size, wherever it appears among the operands.
+fn parse(args: &[String]) -> Result<Operands> {
+    let (mut input, mut output) = (None, None);
+    let (mut bs, mut skip, mut seek, mut count) = (1, 0, 0, None);
+    for arg in args {
+        let Some((key, value)) = arg.split_once('=') else {
+            return Err(Value::Str(arg.clone()).bad_arg("<key>=<value>"));
+        };
+        match key {
+            "if" => input = Some(parse_endpoint(value)?),
+            "of" => output = Some(parse_endpoint(value)?),
+            "bs" => bs = reader::parse_len(value)?,
+            "skip" => skip = reader::parse_len(value)?,
+            "seek" => seek = reader::parse_len(value)?,
+            "count" => count = Some(reader::parse_len::<usize>(value)?),
+            _ => {
+                let arg = Value::Str(arg.clone());
+                return Err(arg.bad_arg("if, of, bs, skip, seek, or count"));
+            }
+        }
+    }
+    if bs == 0 {
+        return Err(Error::NumRange);
+    }
+    let blocks = |n: usize| n.checked_mul(bs).ok_or(Error::NumRange);
+    Ok(Operands {
+        input: input.ok_or(Error::BadArgs)?,
+        output: output.ok_or(Error::BadArgs)?,
+        skip: blocks(skip)?,
+        seek: blocks(seek)?,
+        count: count.map(blocks).transpose()?,
+    })
+}
+
+/// Returns the writable memory at `seek` bytes into the output
+/// region, up to `count` bytes long.  Unlike `as_slice_mut`, the
+/// memory is not zeroed, as bytes outside of what is copied must
+/// be left alone.
+fn output(
+    config: &bldb::Config,
+    (addr, len): (usize, usize),
+    seek: usize,
+    count: Option<usize>,
+) -> Result<&'static mut [u8]> {
+    let avail = len.checked_sub(seek).ok_or(Error::Offset)?;
+    let len = match count {
+        Some(count) if count > avail => return Err(Error::Offset),
+        Some(count) => count,
+        None => avail,
+    };
+    let addr = addr.checked_add(seek).ok_or(Error::NumRange)?;
+    let ptr = repl::unsigned_to_ptr_mut::<_, u8>(addr)?;
+    if !mem::is_canonical_range(addr, addr.wrapping_add(len))
+        || !config
+            .page_table
+            .is_region_writeable(mem::page_range_raw(ptr.cast(), len))
+    {
+        return Err(Error::Unmapped);
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: dd if=<file | addr,len> of=<addr,len> [bs=<n>] \
+             [skip=<n>] [seek=<n>] [count=<n>]"
+        );
+        error
+    };
+    let mut args = Vec::new();
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            arg => args.push(arg.as_string().map_err(usage)?),
+        }
+    }
+    let ops = parse(&args).map_err(usage)?;
+    let Endpoint::Mem(addr, len) = ops.output else {
+        println!("dd: files may not be written");
+        return Err(usage(Error::BadArgs));
+    };
+    let dst = output(config, (addr, len), ops.seek, ops.count)?;
+    let copied = match &ops.input {
+        Endpoint::File(path) => {
+            let (fs, path) = ramdisk::lookup(
+                &config.mounts,
+                config.builtin.as_deref(),
+                &config.page_table,
+                path,
+            )?;
+            let mut poll = cons::poller(&mut config.cons);
+            ramdisk::copy_from(fs, &path, ops.skip, dst, &mut poll)?
+        }
+        &Endpoint::Mem(addr, len) => {
+            let avail = len.checked_sub(ops.skip).ok_or(Error::Offset)?;
+            let addr = addr.checked_add(ops.skip).ok_or(Error::NumRange)?;
+            let src = Value::Pair(addr, avail)
+                .as_slice(&config.page_table, 0)?
+                .ok_or(Error::BadArgs)?;
+            let n = usize::min(src.len(), dst.len());
+            unsafe {
+                ptr::copy(src.as_ptr(), dst.as_mut_ptr(), n);
+            }
+            n
+        }
+    };
+    if ops.count.is_some_and(|count| copied < count) {
+        println!("dd: input ended after {copied:#x} bytes");
+    }
+    println!("copied {copied:#x} bytes to {:#x}", dst.as_ptr().addr());
+    Ok(Value::Slice(config.page_table.buf(&dst[..copied])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn operands() {
+        let ops = parse(&args("if=/boot/blob of=0x1000,64k skip=2 bs=4k"));
+        assert_eq!(
+            ops,
+            Ok(Operands {
+                input: Endpoint::File("/boot/blob".into()),
+                output: Endpoint::Mem(0x1000, 0x10000),
+                skip: 0x2000,
+                seek: 0,
+                count: None,
+            })
+        );
+        let ops = parse(&args("of=0x2000,0x100 if=0x1000,0x100 count=16"));
+        assert_eq!(
+            ops,
+            Ok(Operands {
+                input: Endpoint::Mem(0x1000, 0x100),
+                output: Endpoint::Mem(0x2000, 0x100),
+                skip: 0,
+                seek: 0,
+                count: Some(16),
+            })
+        );
+        assert!(parse(&args("if=/a")).is_err());
+        assert!(parse(&args("if=/a of=0x1000,4k bs=0")).is_err());
+        assert!(parse(&args("if=/a of=0x1000,4k conv=sync")).is_err());
+        assert!(parse(&args("if=/a of=0x1000,4k skip")).is_err());
+        assert!(parse(&args("if=/a of=0x1000 ")).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Breakpoints and single stepping.
+//!
+//! `bp` plants an INT3 instruction at an address, saving the
+//! byte it replaces, and `bpclear` puts the byte back.  When code
+//! entered with `call` reaches a breakpoint, or completes a
+//! single step, the trap handler stops it: the trap frame is
+//! shown, and a REPL session is started on the trap's stack, in
+//! which memory may be examined and changed as usual.  `cont`
+//! ends the session and resumes the code; `step` resumes it for
+//! a single instruction, after which it stops again.
+//!
+//! Breakpoints stay planted while stopped, so the text reads
+//! back with an INT3 byte where each is set.  To resume from a
+//! breakpoint, its byte is restored and the instruction there
+//! single stepped, after which the INT3 is planted again.
+//!
+//! Traps are only caught while the debugger is attached, during
+//! a `call`; elsewhere, they are fatal as any other exception.
+//! Hardware watchpoints, set with `watch`, also stop here when
+//! attached.
+//!
+//! Attaching hands the REPL's exclusive reference to the
+//! configuration over to the debugger for the duration of the
+//! call, and a stop takes it back out while its session runs, so
+//! that there is never more than one live at a time.  A trap may
+//! interrupt code holding the debugger's lock, so the trap path
+//! only ever tries to take it, leaving the trap to the fatal path
+//! if it cannot.
+
+use crate::bldb;
+use crate::idt::{self, TrapFrame};
+use crate::mem;
+use crate::println;
+use crate::repl::{self, Value, watch};
+use crate::result::{Error, Result};
+use crate::symbols::Symbols;
+use alloc::vec::Vec;
+use core::ptr::{self, NonNull};
+
+/// The INT3 instruction.
+const INT3: u8 = 0xcc;
+
+/// The trap flag in RFLAGS, which raises #DB after the next
+/// instruction.
+const TF: u64 = 1 << 8;
+
+const DB: u64 = 1;
+
+/// A planted breakpoint, and the byte it displaced.
+#[derive(Clone, Copy, Debug)]
+struct Breakpoint {
+    addr: u64,
+    saved: u8,
+}
+
+/// How to resume from a stop.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Resume {
+    Continue,
+    Step,
+}
+
+/// The configuration that the code being debugged was called
+/// with, for the REPL sessions at its stops.  It is derived from
+/// the exclusive reference given to `attached`, which is not
+/// otherwise used until the call returns.
+#[derive(Clone, Copy)]
+struct Attached(NonNull<bldb::Config>);
+
+/// The trap frame of a stop.
+#[derive(Clone, Copy)]
+struct Stopped(*mut TrapFrame);
+
+// The debugger only ever runs on the bootstrap processor.
+unsafe impl Send for Attached {}
+unsafe impl Send for Stopped {}
+
+struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    attached: Option<Attached>,
+    stopped: Option<Stopped>,
+    resume: Option<Resume>,
+    /// The breakpoint being stepped over, to be planted again
+    /// once its instruction has been executed.
+    replant: Option<u64>,
+    /// Set when the user asked for a single step, rather than
+    /// one taken to step over a breakpoint.
+    stepping: bool,
+}
+
+impl Debugger {
+    const fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            attached: None,
+            stopped: None,
+            resume: None,
+            replant: None,
+            stepping: false,
+        }
+    }
+
+    fn breakpoint(&self, addr: u64) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|bp| bp.addr == addr)
+    }
+}
+
+#[cfg(not(test))]
+static DEBUGGER: spin::Mutex<Debugger> = spin::Mutex::new(Debugger::new());
+
+#[cfg(test)]
+std::thread_local! {
+    static DEBUGGER: core::cell::RefCell<Debugger> =
+        const { core::cell::RefCell::new(Debugger::new()) };
+}
+
+fn with<R>(f: impl FnOnce(&mut Debugger) -> R) -> R {
+    #[cfg(not(test))]
+    return f(&mut DEBUGGER.lock());
+    #[cfg(test)]
+    return DEBUGGER.with_borrow_mut(f);
+}
+
+/// As `with`, but gives up rather than waiting if the debugger
+/// is in use, as it may be by the code a trap interrupted.
+fn try_with<R>(f: impl FnOnce(&mut Debugger) -> R) -> Option<R> {
+    #[cfg(not(test))]
+    return DEBUGGER.try_lock().map(|mut d| f(&mut d));
+    #[cfg(test)]
+    return DEBUGGER.with(|d| d.try_borrow_mut().ok().map(|mut d| f(&mut d)));
+}
+
+/// Writes a byte of text.  Text is usually mapped read-only, so
+/// write protection is lifted while doing so.
+fn patch(addr: u64, b: u8) {
+    let ptr = ptr::with_exposed_provenance_mut::<u8>(addr as usize);
+    #[cfg(not(test))]
+    unsafe {
+        use x86::controlregs::{Cr0, cr0, cr0_write};
+        let cr0 = cr0();
+        cr0_write(cr0 - Cr0::CR0_WRITE_PROTECT);
+        ptr.write_volatile(b);
+        cr0_write(cr0);
+    }
+    #[cfg(test)]
+    unsafe {
+        ptr.write_volatile(b);
+    }
+}
+
+/// Runs `f`, a call into code being debugged, with the debugger
+/// attached, so that its breakpoints and steps stop in a REPL
+/// session with the given configuration.
+pub(super) fn attached<R>(
+    config: &mut bldb::Config,
+    f: impl FnOnce() -> R,
+) -> R {
+    let attached = Attached(NonNull::from(config));
+    let prev = with(|d| d.attached.replace(attached));
+    let r = f();
+    with(|d| d.attached = prev);
+    r
+}
+
+/// Handles #BP and #DB traps, returning false if the trap is not
+/// ours to handle: a breakpoint or step while the debugger is
+/// not attached.
+pub(crate) fn trap(frame: &mut TrapFrame) -> bool {
+    let status = if frame.vector == DB { watch::status() } else { 0 };
+    dispatch(frame, status)
+}
+
+/// Takes the configuration from the debugger, if attached, for
+/// the duration of a trap, and handles the trap with it.
+fn dispatch(frame: &mut TrapFrame, status: u64) -> bool {
+    // Once the lock has been taken here, the interrupted code
+    // cannot be holding it, and it may be waited for as usual.
+    let Some(attached) = try_with(|d| d.attached.take()) else {
+        return false;
+    };
+    let handled = match attached {
+        Some(Attached(mut config)) => {
+            handle(Some(unsafe { config.as_mut() }), frame, status)
+        }
+        None => handle(None, frame, status),
+    };
+    with(|d| d.attached = attached);
+    handled
+}
+
+/// Handles a trap, given the configuration if the debugger is
+/// attached, and the watchpoint status bits of DR6 for #DB.
+/// Watchpoints are reported whether or not the debugger is
+/// attached, but only stop when it is.
+fn handle(
+    config: Option<&mut bldb::Config>,
+    frame: &mut TrapFrame,
+    status: u64,
+) -> bool {
+    let watched = status != 0 && watch::hit(status, frame);
+    let Some(config) = config else {
+        return watched;
+    };
+    let (replant, stepping) =
+        with(|d| (d.replant.take(), core::mem::take(&mut d.stepping)));
+    if frame.vector == DB {
+        frame.rflags &= !TF;
+        if let Some(addr) = replant {
+            patch(addr, INT3);
+            if !stepping && !watched {
+                return true;
+            }
+        }
+        if stepping || !watched {
+            println!("debug: stepped to {:#x}", frame.rip);
+        }
+    } else {
+        let addr = frame.rip.wrapping_sub(1);
+        match with(|d| d.breakpoint(addr).is_some()) {
+            true => {
+                frame.rip = addr;
+                println!("debug: breakpoint at {addr:#x}");
+            }
+            false => println!("debug: INT3 at {addr:#x}"),
+        }
+    }
+    show(frame, &config.symbols);
+    stop(config, frame);
+    true
+}
+
+/// Prints the registers of a trap frame, labeling the
+/// instruction pointer with its symbol, if known.
+fn show(frame: &TrapFrame, symbols: &Symbols) {
+    println!(
+        "rip {:#018x}  rflags {:#010x}  error {:#x}",
+        frame.rip, frame.rflags, frame.error
+    );
+    if let Some(name) = symbols.describe(frame.rip) {
+        println!("    <{name}>");
+    }
+    for row in frame.gprs().chunks(4) {
+        for (k, (name, value)) in row.iter().enumerate() {
+            let sep = if k + 1 == row.len() { "\n" } else { "  " };
+            crate::print!("{name:>3} {value:#018x}{sep}");
+        }
+    }
+}
+
+/// Runs a REPL session at a stop, until it is resumed, then
+/// arranges to resume as asked.
+fn stop(config: &mut bldb::Config, frame: &mut TrapFrame) {
+    let prev = with(|d| {
+        d.resume = None;
+        d.stopped.replace(Stopped(ptr::from_mut(frame)))
+    });
+    let mut env = Vec::new();
+    let mut val = Value::Nil;
+    let resume = loop {
+        if let Some(resume) = with(|d| d.resume.take()) {
+            break resume;
+        }
+        repl::rep(config, &mut env, &mut val);
+    };
+    with(|d| d.stopped = prev);
+    let rip = frame.rip;
+    if let Some(bp) = with(|d| d.breakpoint(rip).copied()) {
+        patch(rip, bp.saved);
+        with(|d| d.replant = Some(rip));
+        frame.rflags |= TF;
+    }
+    if resume == Resume::Step {
+        with(|d| d.stepping = true);
+        frame.rflags |= TF;
+    }
+}
+
+/// Lists the breakpoints, marking the one stopped at, if any.
+fn list() {
+    with(|d| {
+        if d.breakpoints.is_empty() {
+            println!("no breakpoints");
+        }
+        let rip = d.stopped.map(|Stopped(frame)| unsafe { (*frame).rip });
+        for (k, bp) in d.breakpoints.iter().enumerate() {
+            let mark = if rip == Some(bp.addr) { " <- stopped" } else { "" };
+            println!(
+                "[{k}]: {:#x} (displaced {:#04x}){mark}",
+                bp.addr, bp.saved
+            );
+        }
+    });
+}
+
+pub(super) fn bp(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bp [<addr>]");
+        error
+    };
+    let addr = match repl::popenv(env) {
+        Value::Nil => {
+            list();
+            return Ok(Value::Nil);
+        }
+        v => v.as_num::<u64>().map_err(usage)?,
+    };
+    if bldb::loader_text().contains(&addr) {
+        println!("bp: {addr:#x} is in the loader's own text");
+        return Err(Error::BadArgs);
+    }
+    let ptr = ptr::with_exposed_provenance::<u8>(addr as usize);
+    let range = mem::page_range_raw(ptr.cast(), 1);
+    if !mem::is_canonical(addr as usize)
+        || !config.page_table.is_region_readable(range)
+    {
+        return Err(Error::Unmapped);
+    }
+    if with(|d| d.breakpoint(addr).is_some()) {
+        println!("bp: already set at {addr:#x}");
+        return Ok(Value::Nil);
+    }
+    let saved = unsafe { ptr.read_volatile() };
+    patch(addr, INT3);
+    with(|d| d.breakpoints.push(Breakpoint { addr, saved }));
+    Ok(Value::Nil)
+}
+
+pub(super) fn bpclear(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: bpclear [<addr>]");
+        error
+    };
+    let addr = match repl::popenv(env) {
+        Value::Nil => None,
+        v => Some(v.as_num::<u64>().map_err(usage)?),
+    };
+    let cleared = with(|d| {
+        let (cleared, kept) = d
+            .breakpoints
+            .iter()
+            .partition(|bp| addr.is_none_or(|addr| bp.addr == addr));
+        d.breakpoints = kept;
+        cleared
+    });
+    if addr.is_some() && cleared.is_empty() {
+        println!("bpclear: no breakpoint at {:#x}", addr.unwrap_or_default());
+        return Err(Error::BadArgs);
+    }
+    for bp in cleared {
+        patch(bp.addr, bp.saved);
+    }
+    Ok(Value::Nil)
+}
+
+/// Returns the address at which code is stopped, if it is.
+pub(super) fn stopped_rip() -> Option<u64> {
+    with(|d| d.stopped.map(|Stopped(frame)| unsafe { (*frame).rip }))
+}
+
+/// Puts back the bytes displaced by breakpoints into `text`, a
+/// copy of the text at `addr`.
+pub(super) fn unplant(addr: u64, text: &mut [u8]) {
+    with(|d| {
+        for bp in d.breakpoints.iter() {
+            if let Some(offset) = bp.addr.checked_sub(addr)
+                && let Some(b) = text.get_mut(offset as usize)
+            {
+                *b = bp.saved;
+            }
+        }
+    });
+}
+
+/// Shows the registers of the code stopped at a breakpoint or
+/// step, or else those of the last exception taken.
+pub(super) fn regs(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let stopped = with(|d| d.stopped.map(|Stopped(frame)| unsafe { *frame }));
+    if let Some(frame) = stopped {
+        println!("stopped at {:#x}", frame.rip);
+        show(&frame, &config.symbols);
+        return Ok(Value::Nil);
+    }
+    let Some(trap) = idt::last_trap() else {
+        println!("regs: no exception has been taken");
+        return Ok(Value::Nil);
+    };
+    let vector = trap.frame.vector;
+    println!("last exception: {} (vector {vector})", idt::vector_name(vector));
+    if let Some(cr2) = trap.cr2 {
+        println!("cr2 {cr2:#018x}");
+    }
+    show(&trap.frame, &config.symbols);
+    Ok(Value::Nil)
+}
+
+fn resume(how: Resume) -> Result<Value> {
+    with(|d| {
+        if d.stopped.is_none() {
+            return Err(Error::NotStopped);
+        }
+        d.resume = Some(how);
+        Ok(Value::Nil)
+    })
+}
+
+pub(super) fn cont(
+    _config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    resume(Resume::Continue)
+}
+
+pub(super) fn step(
+    _config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    resume(Resume::Step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use alloc::format;
+
+    const BP: u64 = 3;
+
+    #[test]
+    fn breakpoints() {
+        let mut sim = Sim::new();
+        sim.ram()[0x100..0x104].copy_from_slice(&[0x90, 0x55, 0x90, 0xc3]);
+        let text = sim.ram()[0x100..].as_ptr().addr() as u64;
+        let lines = format!("bp {text:#x}\nbp {:#x}\nbp\npush\n", text + 1);
+        let out = sim.session(&lines);
+        assert!(out.contains("(displaced 0x90)"), "{out}");
+        assert!(out.contains("(displaced 0x55)"), "{out}");
+        assert_eq!(sim.ram()[0x100..0x102], [INT3, INT3]);
+        let out = sim.session("cont\npush\n");
+        assert!(out.contains("Not stopped"), "{out}");
+
+        // Reaching the first breakpoint stops, and `cont` steps
+        // over it, planting it again once past.
+        let mut frame =
+            TrapFrame { vector: BP, rip: text + 1, ..Default::default() };
+        let lines = format!("peek {text:#x}\nbp\ncont\n");
+        let (handled, out) =
+            sim.console(&lines, |config| attached(config, || trap(&mut frame)));
+        assert!(handled);
+        assert!(out.contains(&format!("debug: breakpoint at {text:#x}")));
+        assert!(out.contains("rip "), "{out}");
+        assert!(out.contains("(displaced 0x90) <- stopped"), "{out}");
+        assert_eq!(frame.rip, text);
+        assert_eq!(frame.rflags & TF, TF);
+        assert_eq!(sim.ram()[0x100], 0x90);
+        frame.vector = DB;
+        frame.rip = text + 1;
+        let (handled, out) =
+            sim.console("", |config| attached(config, || trap(&mut frame)));
+        assert!(handled);
+        assert!(out.is_empty(), "{out}");
+        assert_eq!(frame.rflags & TF, 0);
+        assert_eq!(sim.ram()[0x100], INT3);
+
+        // Stepping from the second stops again after one
+        // instruction.
+        frame.vector = BP;
+        frame.rip = text + 2;
+        let (_, out) = sim.console("regs\nstep\n", |config| {
+            attached(config, || trap(&mut frame))
+        });
+        assert!(out.contains("breakpoint at"), "{out}");
+        assert!(out.contains(&format!("stopped at {:#x}", text + 1)), "{out}");
+        assert_eq!(sim.ram()[0x101], 0x55);
+        frame.vector = DB;
+        frame.rip = text + 2;
+        let (_, out) = sim
+            .console("cont\n", |config| attached(config, || trap(&mut frame)));
+        assert!(out.contains(&format!("stepped to {:#x}", text + 2)));
+        assert_eq!(sim.ram()[0x101], INT3);
+        assert_eq!(frame.rflags & TF, 0);
+
+        // Unattached, traps are not handled, nor are they if the
+        // interrupted code holds the debugger.
+        assert!(!trap(&mut frame));
+        let (handled, _) = sim.console("", |config| {
+            attached(config, || with(|_| dispatch(&mut frame, 0)))
+        });
+        assert!(!handled);
+        sim.session(&format!("bpclear {text:#x}\nbpclear\npush\n"));
+        assert_eq!(sim.ram()[0x100..0x102], [0x90, 0x55]);
+        let out = sim.session("bp\npush\n");
+        assert!(out.contains("no breakpoints"), "{out}");
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut sim = Sim::new();
+        sim.session("watch 0x1000,4\n");
+        let mut frame =
+            TrapFrame { vector: DB, rip: 0x2000, ..Default::default() };
+        // Unattached, a watchpoint is reported, and execution
+        // carries on.
+        let (handled, out) = sim.console("", |_| dispatch(&mut frame, 0b1));
+        assert!(handled);
+        assert!(out.contains("watch: [0] 0x1000,4 (w) hit"), "{out}");
+        assert!(!out.contains("rflags"), "{out}");
+        assert!(!dispatch(&mut frame, 0));
+        // Attached, it stops.
+        let (handled, out) = sim.console("cont\n", |config| {
+            attached(config, || dispatch(&mut frame, 0b1))
+        });
+        assert!(handled);
+        assert!(out.contains("watch: [0]"), "{out}");
+        assert!(!out.contains("stepped to"), "{out}");
+        assert!(out.contains("rip 0x0000000000002000"), "{out}");
+        assert_eq!(frame.rip, 0x2000);
+        sim.session("watchclear\n");
+    }
+
+    #[test]
+    fn last_exception() {
+        const PF: u64 = 14;
+        let mut sim = Sim::new();
+        let frame = TrapFrame {
+            vector: PF,
+            error: 2,
+            rip: 0x1234,
+            rsp: 0x8000,
+            r15: 0xfeed,
+            ..Default::default()
+        };
+        idt::record(idt::Trap { frame, cr2: Some(0xdead_0000) });
+        let out = sim.session("regs\n");
+        assert!(out.contains("last exception: #PF (vector 14)"), "{out}");
+        assert!(out.contains("cr2 0x00000000dead0000"), "{out}");
+        assert!(out.contains("rip 0x0000000000001234"), "{out}");
+        assert!(out.contains("error 0x2"), "{out}");
+        assert!(out.contains("rsp 0x0000000000008000"), "{out}");
+        assert!(out.contains("r15 0x000000000000feed"), "{out}");
+        assert_eq!(idt::vector_name(13), "#GP");
+        assert_eq!(idt::vector_name(15), "interrupt");
+        assert_eq!(idt::vector_name(32), "interrupt");
+    }
+}
@@ -0,0 +1,297 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compressing regions of memory.
+//!
+//! `deflate` is the counterpart of `inflate`: it compresses a
+//! region, such as a memory dump about to be sent over the
+//! console, where the line is far slower than the compressor.
+//! The output is gzip by default, so that it can be expanded on
+//! the workstation with `gunzip`; zlib and raw DEFLATE streams
+//! may also be had, and `inflate` takes all three.
+
+use crate::bldb;
+use crate::cons;
+use crate::gzip;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use miniz_oxide::deflate::core::{
+    CompressorOxide, TDEFLFlush, TDEFLStatus, compress,
+    create_comp_flags_from_zip_params,
+};
+
+/// How often to report progress, in bytes of input.
+const PROGRESS_INTERVAL: usize = 16 * 1024 * 1024;
+
+/// How much input to give the compressor at a time, so that
+/// progress can be reported, and the console polled, while
+/// compressing.
+const CHUNK_LEN: usize = 1024 * 1024;
+
+/// The compression level, which is miniz's default, trading
+/// speed for size.
+const LEVEL: i32 = 6;
+
+/// The base-2 logarithm of the window size, which is the largest
+/// that DEFLATE allows.
+const WINDOW_BITS: i32 = 15;
+
+/// The stream formats that we can produce.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Gzip,
+    Zlib,
+    Raw,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "gzip" => Some(Format::Gzip),
+            "zlib" => Some(Format::Zlib),
+            "raw" => Some(Format::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// Ways in which compression can fail: the destination is too
+/// small, the compressor failed, or the poll function failed.
+#[derive(Debug)]
+enum Failure {
+    Overflow,
+    Poll(Error),
+    Status(TDEFLStatus),
+}
+
+/// Compresses `src` into `dst`, as a zlib stream if `zlib` is
+/// set and a raw DEFLATE stream otherwise, returning the number
+/// of bytes written.  The source is fed to the compressor in
+/// chunks, and `progress` is called with the number of bytes
+/// consumed and produced after each.  `poll` is called before
+/// each chunk, and should it fail, compression is abandoned.
+fn shrink(
+    src: &[u8],
+    dst: &mut [u8],
+    zlib: bool,
+    mut progress: impl FnMut(usize, usize),
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> core::result::Result<usize, Failure> {
+    // Negative window sizes ask for no zlib framing.
+    let window_bits = if zlib { WINDOW_BITS } else { -WINDOW_BITS };
+    let flags = create_comp_flags_from_zip_params(LEVEL, window_bits, 0);
+    // The compressor's state is large, so is kept off the stack.
+    let mut d = Box::new(CompressorOxide::new(flags));
+    let (mut nin, mut nout) = (0, 0);
+    loop {
+        poll().map_err(Failure::Poll)?;
+        let end = usize::min(nin + CHUNK_LEN, src.len());
+        let flush = if end == src.len() {
+            TDEFLFlush::Finish
+        } else {
+            TDEFLFlush::None
+        };
+        let (s, i, o) =
+            compress(&mut d, &src[nin..end], &mut dst[nout..], flush);
+        nin += i;
+        nout += o;
+        progress(nin, nout);
+        match s {
+            TDEFLStatus::Done => return Ok(nout),
+            TDEFLStatus::Okay if nout == dst.len() => {
+                return Err(Failure::Overflow);
+            }
+            TDEFLStatus::Okay => {}
+            s => return Err(Failure::Status(s)),
+        }
+    }
+}
+
+/// Compresses the source into the destination in the given
+/// format, returning the compressed data.
+fn deflate<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    format: Format,
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<&'a [u8]> {
+    let header = gzip::header();
+    let trailer_len = gzip::trailer(&[]).len();
+    let (start, reserved) = match format {
+        Format::Gzip => (header.len(), header.len() + trailer_len),
+        Format::Zlib | Format::Raw => (0, 0),
+    };
+    let available = dst.len();
+    let overflow = || {
+        println!("deflate: destination too small: {available:#x} bytes");
+        Error::Compress("destination too small")
+    };
+    if dst.len() < reserved {
+        return Err(overflow());
+    }
+    let mut next_report = PROGRESS_INTERVAL;
+    let progress = |nin: usize, nout: usize| {
+        if nin >= next_report {
+            println!("deflate: {} MiB in, {} MiB out", nin >> 20, nout >> 20);
+            next_report = nin + PROGRESS_INTERVAL;
+        }
+    };
+    let end = dst.len() - (reserved - start);
+    let zlib = format == Format::Zlib;
+    let nout = match shrink(src, &mut dst[start..end], zlib, progress, poll) {
+        Ok(nout) => start + nout,
+        Err(Failure::Overflow) => return Err(overflow()),
+        Err(Failure::Poll(e)) => return Err(e),
+        Err(Failure::Status(s)) => {
+            println!("deflate failed: state is {s:?}");
+            return Err(Error::Compress("compression failed"));
+        }
+    };
+    let len = if format == Format::Gzip {
+        dst[..start].copy_from_slice(&header);
+        let trailer = gzip::trailer(src);
+        dst[nout..nout + trailer.len()].copy_from_slice(&trailer);
+        nout + trailer.len()
+    } else {
+        nout
+    };
+    println!(
+        "deflate: {format:?}, {:#x} bytes compressed to {len:#x}",
+        src.len()
+    );
+    Ok(&dst[..len])
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: deflate <src addr>,<src len> [<dst addr>,<dst len>] \
+             [gzip | zlib | raw]"
+        );
+        error
+    };
+    let src = repl::popenv(env)
+        .as_slice(&config.page_table, 0)
+        .and_then(|o| o.ok_or(Error::BadArgs))
+        .map_err(usage)?;
+    let mut format = Format::Gzip;
+    let mut dst = None;
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            arg @ Value::Str(_) => {
+                let name = arg.as_string()?;
+                format = Format::from_name(&name)
+                    .ok_or_else(|| usage(arg.bad_arg("gzip, zlib, or raw")))?;
+            }
+            // The compressor writes every byte it reports, so the
+            // destination need not be cleared first, and must not
+            // be before it is known not to hold the source.
+            v => {
+                dst = v
+                    .as_slice_mut_unzeroed(&config.page_table, 0)
+                    .map_err(usage)?
+            }
+        }
+    }
+    let dst_range = match &dst {
+        Some(dst) => dst.as_ptr_range(),
+        None => {
+            let scratch = &config.scratch_region;
+            scratch.start.addr() as *const u8..scratch.end.addr() as *const u8
+        }
+    };
+    let src_range = src.as_ptr_range();
+    if src_range.start < dst_range.end && dst_range.start < src_range.end {
+        println!(
+            "deflate: the destination {:p}..{:p} overlaps the source",
+            dst_range.start, dst_range.end
+        );
+        return Err(Error::BadArgs);
+    }
+    let dst = dst.unwrap_or_else(|| config.scratch_region_init_mut());
+    let deflated =
+        deflate(src, dst, format, &mut cons::poller(&mut config.cons))?;
+    Ok(Value::Slice(config.page_table.buf(deflated)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Read;
+    use crate::repl::sim::Sim;
+    use miniz_oxide::inflate::{decompress_to_vec, decompress_to_vec_zlib};
+
+    fn data() -> Vec<u8> {
+        (0..200_000u64).map(|k| (k * k % 253) as u8).collect()
+    }
+
+    #[test]
+    fn formats() {
+        let data = data();
+        let mut dst = vec![0u8; data.len()];
+        let mut poll = || Ok(());
+        let raw = deflate(&data, &mut dst, Format::Raw, &mut poll).unwrap();
+        assert!(raw.len() < data.len());
+        assert_eq!(decompress_to_vec(raw).unwrap(), data);
+        let zlib = deflate(&data, &mut dst, Format::Zlib, &mut poll).unwrap();
+        assert_eq!(decompress_to_vec_zlib(zlib).unwrap(), data);
+        let gz = deflate(&data, &mut dst, Format::Gzip, &mut poll).unwrap();
+        let reader = gzip::Reader::new(&gz).unwrap();
+        assert_eq!(reader.size(), data.len());
+        let mut out = vec![0u8; data.len()];
+        assert_eq!(reader.read(0, &mut out).unwrap(), data.len());
+        assert_eq!(out, data);
+        assert_eq!(gz[gz.len() - 8..gz.len() - 4], gzip::trailer(&data)[..4]);
+        let empty = deflate(&[], &mut dst, Format::Gzip, &mut poll).unwrap();
+        assert_eq!(gzip::frame(&empty).unwrap().1, 0);
+    }
+
+    #[test]
+    fn destination_too_small() {
+        let data = data();
+        let mut dst = vec![0u8; 64];
+        let mut poll = || Ok(());
+        let res = shrink(&data, &mut dst, false, |_, _| {}, &mut poll);
+        assert!(matches!(res, Err(Failure::Overflow)));
+        let mut dst = [0u8; 17];
+        assert!(deflate(b"x", &mut dst, Format::Gzip, &mut poll).is_err());
+        let mut dst = [0u8; 32];
+        assert!(deflate(b"x", &mut dst, Format::Gzip, &mut poll).is_ok());
+    }
+
+    #[test]
+    fn cancelled() {
+        let data: Vec<u8> = data().repeat(10);
+        assert!(data.len() > CHUNK_LEN);
+        let mut dst = vec![0u8; data.len()];
+        let mut polls = 0;
+        let mut poll = || {
+            polls += 1;
+            if polls > 1 { Err(Error::Cancelled) } else { Ok(()) }
+        };
+        let res = shrink(&data, &mut dst, true, |_, _| {}, &mut poll);
+        assert!(matches!(res, Err(Failure::Poll(Error::Cancelled))));
+        assert_eq!(polls, 2);
+    }
+
+    #[test]
+    fn overlapping_destination() {
+        let mut sim = Sim::new();
+        sim.ram()[..0x100].fill(0xa5);
+        let addr = sim.ram().as_ptr().addr();
+        let out = sim.session(&format!(
+            "deflate {addr:#x},0x100 {:#x},0x100\n",
+            addr + 0x80
+        ));
+        assert!(out.contains("overlaps the source"), "{out}");
+        assert!(sim.ram()[..0x100].iter().all(|&b| b == 0xa5));
+    }
+}
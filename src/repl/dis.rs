@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Disassembling code in memory.
+//!
+//! Instructions are decoded as 64-bit code and shown in AT&T
+//! syntax, one to a line with their addresses and bytes, in the
+//! manner of `objdump -d`.  Breakpoints planted in the region are
+//! shown as the instructions they displaced.  With a symbol table
+//! read, the start of each symbol is labeled, as are the targets
+//! of branches.
+
+use crate::bldb;
+use crate::idt;
+use crate::println;
+use crate::repl::{self, Value, debug};
+use crate::result::{Error, Result};
+use crate::symbols::Symbols;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use iced_x86::{DecoderError, DecoderOptions, Formatter, GasFormatter, OpKind};
+
+/// How many bytes are disassembled when no length is given.
+const DEFAULT_LEN: usize = 64;
+
+/// How many bytes of an instruction are shown on each line.
+const BYTES_PER_LINE: usize = 7;
+
+/// Formats the instructions in `text`, which is at `ip`, calling
+/// `line` with each line of output.  Returns the address
+/// following the last whole instruction.
+fn disassemble(
+    text: &[u8],
+    ip: u64,
+    symbols: &Symbols,
+    mut line: impl FnMut(&str),
+) -> u64 {
+    let mut decoder =
+        iced_x86::Decoder::with_ip(64, text, ip, DecoderOptions::NONE);
+    let mut formatter = GasFormatter::new();
+    formatter.options_mut().set_uppercase_hex(false);
+    formatter.options_mut().set_first_operand_char_index(7);
+    formatter.options_mut().set_branch_leading_zeros(false);
+    let mut instr = iced_x86::Instruction::default();
+    let mut next = ip;
+    let mut out = String::new();
+    while decoder.can_decode() {
+        let pos = decoder.position();
+        let addr = ip + pos as u64;
+        match symbols.resolve(addr) {
+            Some((sym, 0)) => {
+                if pos != 0 {
+                    line("");
+                }
+                line(&format!("{addr:016x} <{}>:", sym.name));
+            }
+            Some((sym, offset)) if pos == 0 => {
+                line(&format!("{addr:016x} <{}+{offset:#x}>:", sym.name));
+            }
+            _ => {}
+        }
+        decoder.decode_out(&mut instr);
+        let truncated = decoder.last_error() == DecoderError::NoMoreBytes;
+        let len = if truncated { text.len() - pos } else { instr.len() };
+        let bytes = &text[pos..pos + len];
+        out.clear();
+        if truncated {
+            out.push_str("(truncated)");
+        } else if instr.is_invalid() {
+            out.push_str("(bad)");
+        } else {
+            formatter.format(&instr, &mut out);
+            if instr.op0_kind() == OpKind::NearBranch64
+                && let Some(name) = symbols.describe(instr.near_branch64())
+            {
+                let _ = write!(out, " <{name}>");
+            }
+            next = instr.next_ip();
+        }
+        for (k, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let addr = ip + (pos + k * BYTES_PER_LINE) as u64;
+            let mut s = String::new();
+            let _ = write!(s, "{addr:16x}:\t");
+            for b in chunk {
+                let _ = write!(s, "{b:02x} ");
+            }
+            if k == 0 {
+                let pad = 3 * (BYTES_PER_LINE - chunk.len());
+                let _ = write!(s, "{:pad$}\t{out}", "");
+            }
+            line(s.trim_end());
+        }
+    }
+    next
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: dis [<addr>[,<len>]]");
+        error
+    };
+    let arg = match repl::popenv(env) {
+        Value::Nil => {
+            let rip = debug::stopped_rip()
+                .or_else(|| idt::last_trap().map(|trap| trap.frame.rip))
+                .ok_or_else(|| {
+                    println!("dis: no address, and nothing has trapped");
+                    usage(Error::BadArgs)
+                })?;
+            Value::Unsigned(rip.into())
+        }
+        arg => arg,
+    };
+    let text = arg
+        .as_slice(&config.page_table, DEFAULT_LEN)
+        .and_then(|o| o.ok_or(Error::BadArgs))
+        .map_err(usage)?;
+    let ip = text.as_ptr().addr() as u64;
+    let mut text = text.to_vec();
+    debug::unplant(ip, &mut text);
+    let next =
+        disassemble(&text, ip, &config.symbols, |line| println!("{line}"));
+    Ok(Value::Unsigned(next.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use crate::symbols::Symbol;
+
+    fn disassembly(text: &[u8], ip: u64) -> (Vec<String>, u64) {
+        labeled(text, ip, &Symbols::default())
+    }
+
+    fn labeled(text: &[u8], ip: u64, symbols: &Symbols) -> (Vec<String>, u64) {
+        let mut lines = Vec::new();
+        let next =
+            disassemble(text, ip, symbols, |line| lines.push(line.into()));
+        (lines, next)
+    }
+
+    #[test]
+    fn formatting() {
+        let text = [0x55, 0x48, 0x89, 0xe5, 0xe8, 0xf7, 0xff, 0xff, 0xff, 0xc3];
+        let (lines, next) = disassembly(&text, 0x1000);
+        assert_eq!(
+            lines,
+            [
+                "            1000:\t55                   \tpush   %rbp",
+                "            1001:\t48 89 e5             \tmov    %rsp,%rbp",
+                "            1004:\te8 f7 ff ff ff       \tcall   0x1000",
+                "            1009:\tc3                   \tret",
+            ]
+        );
+        assert_eq!(next, 0x100a);
+
+        // Long instructions continue on the following line, and
+        // what cannot be decoded is shown as such.
+        let text = [0x48, 0xb8, 1, 2, 3, 4, 5, 6, 7, 8, 0x06, 0x06, 0x48, 0x89];
+        let (lines, next) = disassembly(&text, 0);
+        assert_eq!(lines.len(), 4, "{lines:?}");
+        assert!(lines[0].ends_with("movabs $0x807060504030201,%rax"));
+        assert_eq!(lines[1], "               7:\t06 07 08");
+        assert!(lines[2].ends_with("(bad)"), "{lines:?}");
+        assert!(lines.last().is_some_and(|l| l.ends_with("(truncated)")));
+        assert_eq!(next, 10);
+    }
+
+    #[test]
+    fn labels() {
+        let sym = |name: &str, addr, size| Symbol {
+            name: String::from(name),
+            addr,
+            size,
+        };
+        let symbols = Symbols::new(
+            "unix",
+            alloc::vec![sym("f", 0x1000, 4), sym("g", 0x1004, 6)],
+        );
+        let text = [0x55, 0x48, 0x89, 0xe5, 0xe8, 0xf7, 0xff, 0xff, 0xff, 0xc3];
+        let (lines, _) = labeled(&text[1..], 0x1001, &symbols);
+        assert_eq!(lines[0], "0000000000001001 <f+0x1>:");
+        assert_eq!(lines[2], "");
+        assert_eq!(lines[3], "0000000000001004 <g>:");
+        assert!(lines[4].ends_with("call   0x1000 <f>"), "{lines:?}");
+    }
+
+    #[test]
+    fn sessions() {
+        let mut sim = Sim::new();
+        sim.ram()[0x100..0x104].copy_from_slice(&[0x90, 0x55, 0x90, 0xc3]);
+        let text = sim.ram()[0x100..].as_ptr().addr() as u64;
+        let lines = format!("bp {:#x}\ndis {text:#x},4\n", text + 1);
+        let out = sim.session(&lines);
+        assert!(out.contains("push   %rbp"), "{out}");
+        assert!(!out.contains("int3"), "{out}");
+        assert!(out.ends_with(&format!("res: {:#x}\n", text + 4)), "{out}");
+        let out = sim.session("dis 0x10,4\n");
+        assert!(out.contains("usage: dis"), "{out}");
+    }
+}
@@ -0,0 +1,389 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Decoding the descriptor tables the CPU is using.
+//!
+//! The tables are located with SGDT, SIDT, and STR, so these
+//! commands show whatever is live: the loader's own tables, or
+//! those left behind by a payload that returned to us.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::Value;
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+use core::arch::asm;
+
+/// The base and limit of a descriptor table, as stored by SGDT
+/// and SIDT.
+#[derive(Clone, Copy, Debug)]
+struct TablePtr {
+    base: usize,
+    limit: u16,
+}
+
+impl TablePtr {
+    fn len(self) -> usize {
+        usize::from(self.limit) + 1
+    }
+}
+
+fn sgdt() -> TablePtr {
+    let mut desc = [0u8; 10];
+    unsafe {
+        asm!("sgdt [{}]", in(reg) desc.as_mut_ptr(), options(nostack));
+    }
+    decode_table_ptr(&desc)
+}
+
+fn sidt() -> TablePtr {
+    let mut desc = [0u8; 10];
+    unsafe {
+        asm!("sidt [{}]", in(reg) desc.as_mut_ptr(), options(nostack));
+    }
+    decode_table_ptr(&desc)
+}
+
+/// Returns the selector in the task register.
+fn task_register() -> u16 {
+    let selector: u16;
+    unsafe {
+        asm!("str {:x}", out(reg) selector, options(nomem, nostack));
+    }
+    selector
+}
+
+fn decode_table_ptr(desc: &[u8; 10]) -> TablePtr {
+    let limit = u16::from_le_bytes([desc[0], desc[1]]);
+    let base = u64::from_le_bytes(desc[2..].try_into().unwrap());
+    TablePtr { base: base as usize, limit }
+}
+
+/// Returns the table in memory, if it is mapped.
+fn table(config: &bldb::Config, ptr: TablePtr) -> Result<&'static [u8]> {
+    Value::Pair(ptr.base, ptr.len())
+        .as_slice(&config.page_table, 0)?
+        .ok_or(Error::Unmapped)
+}
+
+fn quad(bs: &[u8], offset: usize) -> u64 {
+    bs.get(offset..offset + 8)
+        .map_or(0, |q| u64::from_le_bytes(q.try_into().unwrap()))
+}
+
+/// A decoded code, data, or system segment descriptor.
+#[derive(Debug, Eq, PartialEq)]
+struct Segment {
+    base: u64,
+    limit: u32,
+    typ: u8,
+    system: bool,
+    dpl: u8,
+    present: bool,
+    long: bool,
+    default32: bool,
+    granular: bool,
+}
+
+impl Segment {
+    /// Decodes a descriptor.  In long mode, system descriptors
+    /// for the LDT and TSS are 16 bytes long; `hi` holds the
+    /// second quadword, which is ignored for other descriptors.
+    fn decode(lo: u64, hi: u64) -> Segment {
+        let bits = |start: u32, len: u32| (lo >> start) & ((1 << len) - 1);
+        let system = bits(44, 1) == 0;
+        let typ = bits(40, 4) as u8;
+        let mut base = bits(16, 24) | bits(56, 8) << 24;
+        if system && Self::is_wide(typ) {
+            base |= (hi & 0xffff_ffff) << 32;
+        }
+        Segment {
+            base,
+            limit: (bits(0, 16) | bits(48, 4) << 16) as u32,
+            typ,
+            system,
+            dpl: bits(45, 2) as u8,
+            present: bits(47, 1) != 0,
+            long: bits(53, 1) != 0,
+            default32: bits(54, 1) != 0,
+            granular: bits(55, 1) != 0,
+        }
+    }
+
+    /// Returns true iff a system descriptor of the given type
+    /// occupies two slots in the GDT.
+    fn is_wide(typ: u8) -> bool {
+        matches!(typ, 0x2 | 0x9 | 0xb)
+    }
+
+    fn wide(&self) -> bool {
+        self.system && Self::is_wide(self.typ)
+    }
+
+    /// Returns the limit in bytes, accounting for granularity.
+    fn byte_limit(&self) -> u64 {
+        let limit = u64::from(self.limit);
+        if self.granular { limit << 12 | 0xfff } else { limit }
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.system {
+            return match self.typ {
+                0x2 => "ldt",
+                0x9 => "tss",
+                0xb => "tss busy",
+                0xc => "call gate",
+                0xe => "intr gate",
+                0xf => "trap gate",
+                _ => "reserved",
+            };
+        }
+        match (self.typ & 0b1000 != 0, self.long, self.default32) {
+            (true, true, _) => "code64",
+            (true, false, true) => "code32",
+            (true, false, false) => "code16",
+            (false, _, true) => "data32",
+            (false, _, false) => "data16",
+        }
+    }
+
+    fn flags(&self) -> &'static str {
+        if self.system {
+            return "";
+        }
+        let code = self.typ & 0b1000 != 0;
+        match (code, self.typ & 0b0110) {
+            (true, 0b000) => "x",
+            (true, 0b010) => "rx",
+            (true, 0b100) => "x conforming",
+            (true, _) => "rx conforming",
+            (false, 0b000) => "r",
+            (false, 0b010) => "rw",
+            (false, 0b100) => "r expand-down",
+            (false, _) => "rw expand-down",
+        }
+    }
+}
+
+/// A decoded long mode interrupt or trap gate.
+#[derive(Debug, Eq, PartialEq)]
+struct Gate {
+    offset: u64,
+    selector: u16,
+    ist: u8,
+    typ: u8,
+    dpl: u8,
+    present: bool,
+}
+
+impl Gate {
+    fn decode(desc: u128) -> Gate {
+        let bits =
+            |start: u32, len: u32| ((desc >> start) & ((1 << len) - 1)) as u64;
+        Gate {
+            offset: bits(0, 16) | bits(48, 16) << 16 | bits(64, 32) << 32,
+            selector: bits(16, 16) as u16,
+            ist: bits(32, 3) as u8,
+            typ: bits(40, 4) as u8,
+            dpl: bits(45, 2) as u8,
+            present: bits(47, 1) != 0,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.typ {
+            0xe => "intr",
+            0xf => "trap",
+            _ => "invalid",
+        }
+    }
+}
+
+/// The mnemonics of the architecturally defined exceptions.
+const EXCEPTIONS: [&str; 32] = [
+    "#DE", "#DB", "NMI", "#BP", "#OF", "#BR", "#UD", "#NM", "#DF", "", "#TS",
+    "#NP", "#SS", "#GP", "#PF", "", "#MF", "#AC", "#MC", "#XM", "#VE", "#CP",
+    "", "", "", "", "", "", "#HV", "#VC", "#SX", "",
+];
+
+/// The 64-bit task state segment.
+#[derive(Debug, Eq, PartialEq)]
+struct Tss {
+    rsp: [u64; 3],
+    ist: [u64; 7],
+    iomap: u16,
+}
+
+/// The size of the 64-bit TSS.
+const TSS_LEN: usize = 104;
+
+impl Tss {
+    fn decode(bs: &[u8]) -> Result<Tss> {
+        if bs.len() < TSS_LEN {
+            return Err(Error::BadArgs);
+        }
+        let rsp = core::array::from_fn(|k| quad(bs, 4 + k * 8));
+        let ist = core::array::from_fn(|k| quad(bs, 36 + k * 8));
+        let iomap = u16::from_le_bytes([bs[102], bs[103]]);
+        Ok(Tss { rsp, ist, iomap })
+    }
+}
+
+pub(super) fn gdt(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let ptr = sgdt();
+    println!("GDT at {:#x}, limit {:#x}", ptr.base, ptr.limit);
+    let bs = table(config, ptr)?;
+    let mut offset = 0;
+    while offset < bs.len() {
+        let lo = quad(bs, offset);
+        let seg = Segment::decode(lo, quad(bs, offset + 8));
+        if lo == 0 {
+            println!("{offset:#06x} null");
+        } else {
+            println!(
+                "{offset:#06x} {:<9} base {:#x} limit {:#x} dpl {}{}{}{}",
+                seg.kind(),
+                seg.base,
+                seg.byte_limit(),
+                seg.dpl,
+                if seg.flags().is_empty() { "" } else { " " },
+                seg.flags(),
+                if seg.present { "" } else { " not present" },
+            );
+        }
+        offset += if seg.wide() { 16 } else { 8 };
+    }
+    Ok(Value::Pair(ptr.base, ptr.len()))
+}
+
+pub(super) fn idt(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let ptr = sidt();
+    println!("IDT at {:#x}, limit {:#x}", ptr.base, ptr.limit);
+    let bs = table(config, ptr)?;
+    let mut absent = 0;
+    for (vector, desc) in bs.chunks_exact(16).enumerate() {
+        let gate = Gate::decode(u128::from_le_bytes(desc.try_into().unwrap()));
+        if !gate.present {
+            absent += 1;
+            continue;
+        }
+        println!(
+            "{vector:>3} {:<4} {:#06x}:{:#018x} ist {} dpl {} {}",
+            EXCEPTIONS.get(vector).copied().unwrap_or(""),
+            gate.selector,
+            gate.offset,
+            gate.ist,
+            gate.dpl,
+            gate.kind(),
+        );
+    }
+    if absent != 0 {
+        println!("{absent} vectors not present");
+    }
+    Ok(Value::Pair(ptr.base, ptr.len()))
+}
+
+pub(super) fn tss(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let selector = task_register();
+    if selector & !0b111 == 0 {
+        println!("task register not loaded");
+        return Ok(Value::Nil);
+    }
+    let gdt = table(config, sgdt())?;
+    let offset = usize::from(selector & !0b111);
+    if offset + 16 > gdt.len() {
+        println!("task register selector {selector:#x} is beyond the GDT");
+        return Err(Error::BadArgs);
+    }
+    let seg = Segment::decode(quad(gdt, offset), quad(gdt, offset + 8));
+    println!(
+        "TR {selector:#x}: {} base {:#x} limit {:#x}",
+        seg.kind(),
+        seg.base,
+        seg.byte_limit()
+    );
+    let base = seg.base as usize;
+    let len = (seg.byte_limit() as usize + 1).max(TSS_LEN);
+    let bs = Value::Pair(base, TSS_LEN)
+        .as_slice(&config.page_table, 0)?
+        .ok_or(Error::Unmapped)?;
+    let tss = Tss::decode(bs)?;
+    for (k, rsp) in tss.rsp.iter().enumerate() {
+        println!("rsp{k}  {rsp:#018x}");
+    }
+    for (k, ist) in tss.ist.iter().enumerate() {
+        println!("ist{}  {ist:#018x}", k + 1);
+    }
+    println!("iomap {:#x}", tss.iomap);
+    Ok(Value::Pair(base, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments() {
+        // The loader's own 64-bit code and 32-bit data segments.
+        let code64 = 1 << 47 | 1 << 41 | 1 << 43 | 1 << 53 | 1 << 44;
+        let seg = Segment::decode(code64, 0);
+        assert!(seg.present && seg.long && !seg.system);
+        assert_eq!((seg.kind(), seg.flags(), seg.dpl), ("code64", "rx", 0));
+        let data32 = 0x00cf_9300_0000_ffffu64;
+        let seg = Segment::decode(data32, 0);
+        assert_eq!((seg.kind(), seg.flags()), ("data32", "rw"));
+        assert_eq!(seg.byte_limit(), 0xffff_ffff);
+
+        // A 64-bit TSS at 0xffff_8000_1234_5678, limit 0x67.
+        let lo = 0x1200_8934_5678_0067u64;
+        let seg = Segment::decode(lo, 0xffff_8000);
+        assert!(seg.wide());
+        assert_eq!(seg.kind(), "tss");
+        assert_eq!(seg.base, 0xffff_8000_1234_5678);
+        assert_eq!(seg.byte_limit(), 0x67);
+    }
+
+    #[test]
+    fn gates() {
+        let desc = 0x0000_0000_ffff_ffff_8001_8e01_0008_5678u128;
+        let gate = Gate::decode(desc);
+        assert_eq!(
+            gate,
+            Gate {
+                offset: 0xffff_ffff_8001_5678,
+                selector: 8,
+                ist: 1,
+                typ: 0xe,
+                dpl: 0,
+                present: true,
+            }
+        );
+        assert_eq!(gate.kind(), "intr");
+    }
+
+    #[test]
+    fn task_state() {
+        let mut bs = [0u8; TSS_LEN];
+        bs[4..12].copy_from_slice(&0x1000u64.to_le_bytes());
+        bs[36..44].copy_from_slice(&0x2000u64.to_le_bytes());
+        bs[84..92].copy_from_slice(&0x8000u64.to_le_bytes());
+        bs[102..104].copy_from_slice(&0x68u16.to_le_bytes());
+        let tss = Tss::decode(&bs).unwrap();
+        assert_eq!(tss.rsp, [0x1000, 0, 0]);
+        assert_eq!(tss.ist, [0x2000, 0, 0, 0, 0, 0, 0x8000]);
+        assert_eq!(tss.iomap, 0x68);
+        assert!(Tss::decode(&bs[..100]).is_err());
+        let ptr = decode_table_ptr(&[0x1f, 0, 0x00, 0x10, 0, 0, 0, 0, 0, 0]);
+        assert_eq!((ptr.base, ptr.len()), (0x1000, 0x20));
+    }
+}
@@ -0,0 +1,160 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Saving memory regions and restoring them later.
+//!
+//! The ramdisk filesystem is read-only, so saved copies live on
+//! the heap, named by the user, for the rest of the session.
+//! This is handy for putting a window of device state or a
+//! framebuffer back the way it was between experiments.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A saved copy of a region of memory.
+#[derive(Debug)]
+struct Saved {
+    addr: usize,
+    data: Vec<u8>,
+}
+
+/// The regions saved by `dump`, by name.
+#[derive(Debug, Default)]
+pub(crate) struct Dumps {
+    saved: BTreeMap<String, Saved>,
+}
+
+impl Dumps {
+    fn save(&mut self, name: String, addr: usize, data: &[u8]) {
+        self.saved.insert(name, Saved { addr, data: data.to_vec() });
+    }
+
+    fn get(&self, name: &str) -> Result<&Saved> {
+        self.saved.get(name).ok_or(Error::BadArgs)
+    }
+
+    /// Returns the number of saved regions and their total size.
+    pub(crate) fn usage(&self) -> (usize, usize) {
+        let bytes = self.saved.values().map(|s| s.data.len()).sum();
+        (self.saved.len(), bytes)
+    }
+}
+
+/// Returns the number of bytes that differ between two slices of
+/// equal length.
+fn differing(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).filter(|(a, b)| a != b).count()
+}
+
+fn list(dumps: &Dumps) {
+    if dumps.saved.is_empty() {
+        println!("no saved regions");
+    }
+    for (name, saved) in dumps.saved.iter() {
+        println!("{name:<16} {:#x},{:#x}", saved.addr, saved.data.len());
+    }
+}
+
+pub(super) fn dump(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: dump [<addr>,<len> <name> | -d <name>]");
+        error
+    };
+    let region = repl::popenv(env);
+    match &region {
+        Value::Nil => {
+            list(&config.dumps);
+            return Ok(Value::Nil);
+        }
+        Value::Str(s) if s == "-d" => {
+            let name = repl::popenv(env).as_string().map_err(usage)?;
+            return match config.dumps.saved.remove(&name) {
+                Some(_) => Ok(Value::Nil),
+                None => Err(usage(Error::BadArgs)),
+            };
+        }
+        _ => {}
+    }
+    let Value::Pair(addr, len) = region else {
+        return Err(usage(region.bad_arg("<addr>,<len>")));
+    };
+    let src = region
+        .as_slice(&config.page_table, 0)
+        .map_err(usage)?
+        .ok_or(Error::BadArgs)?;
+    let name = repl::popenv(env).as_string().map_err(usage)?;
+    config.dumps.save(name, addr, src);
+    Ok(Value::Pair(addr, len))
+}
+
+pub(super) fn restore(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: restore <name> [<addr>]");
+        error
+    };
+    let name = repl::popenv(env).as_string().map_err(usage)?;
+    let saved = config.dumps.get(&name).map_err(usage)?;
+    let addr = match repl::popenv(env) {
+        Value::Nil => saved.addr,
+        arg => arg.as_num::<usize>().map_err(usage)?,
+    };
+    let len = saved.data.len();
+    // The memory is compared before it is written, and is not
+    // zeroed first, as it may be a device's.
+    let dst = Value::Pair(addr, len)
+        .as_slice_mut_unzeroed(&config.page_table, 0)?
+        .ok_or(Error::BadArgs)?;
+    let changed = differing(dst, &saved.data);
+    dst.copy_from_slice(&saved.data);
+    println!("restored {len:#x} bytes at {addr:#x}, {changed:#x} had changed");
+    Ok(Value::Pair(addr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_compare() {
+        let mut dumps = Dumps::default();
+        let mut buf = [1u8, 2, 3, 4];
+        dumps.save("fb".into(), buf.as_ptr().addr(), &buf);
+        dumps.save("regs".into(), 0x1000, &[0; 8]);
+        assert_eq!(dumps.usage(), (2, 12));
+        buf[1] = 0;
+        buf[3] = 0;
+        let saved = dumps.get("fb").unwrap();
+        assert_eq!(saved.addr, buf.as_ptr().addr());
+        assert_eq!(differing(&buf, &saved.data), 2);
+        dumps.save("fb".into(), 0, &buf);
+        assert_eq!(dumps.usage(), (2, 12));
+        assert!(dumps.get("nope").is_err());
+    }
+
+    #[test]
+    fn restores() {
+        use crate::repl::sim::Sim;
+        use alloc::format;
+        let mut sim = Sim::new();
+        sim.ram()[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let addr = sim.ram().as_ptr().addr();
+        sim.session(&format!("dump {addr:#x},4 fb\n"));
+        sim.ram()[1] = 0;
+        let out = sim.session(&format!("restore fb {addr:#x}\n"));
+        assert!(out.contains("restored 0x4 bytes"), "{out}");
+        assert!(out.contains("0x1 had changed"), "{out}");
+        assert_eq!(&sim.ram()[..4], &[1, 2, 3, 4]);
+    }
+}
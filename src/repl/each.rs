@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Applying a command to each of a list of items.
+//!
+//! `find` produces a list of ramdisk paths, and `each` runs a
+//! command once per element of a list, as in `find /kernel |
+//! each sha256`.  Each invocation gets a fresh stack holding
+//! the element with any extra arguments given to `each` above
+//! it, just as if the element had been piped into the command.
+
+use crate::bldb;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::format;
+use alloc::vec::Vec;
+
+pub(super) fn find(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: find <path|glob>");
+        error
+    };
+    let path = repl::popenv(env).as_string().map_err(usage)?;
+    let (fs, inner) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let paths = ramdisk::find(fs, &inner)?;
+    let paths = if inner == path {
+        paths
+    } else {
+        paths.iter().map(|p| format!("{}{p}", ramdisk::BUILTIN_DIR)).collect()
+    };
+    for path in paths.iter() {
+        println!("{path}");
+    }
+    Ok(Value::List(paths.into_iter().map(Value::Str).collect()))
+}
+
+/// Pops the arguments to pass to each invocation, up to and
+/// including the list of items.  The arguments are returned in
+/// the order they were given.
+fn split(env: &mut Vec<Value>) -> Result<(Vec<Value>, Vec<Value>)> {
+    let mut args = Vec::new();
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => return Err(Error::BadArgs),
+            Value::List(items) => return Ok((args, items)),
+            v => args.push(v),
+        }
+    }
+}
+
+/// Builds the stack for one invocation, with the first argument
+/// on top and the item at the bottom.
+fn frame(item: Value, args: &[Value]) -> Vec<Value> {
+    let mut env = Vec::with_capacity(args.len() + 1);
+    env.push(item);
+    env.extend(args.iter().rev().cloned());
+    env
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: <list> | each <command> [<args>...]");
+        error
+    };
+    let cmd = repl::popenv(env).as_string().map_err(usage)?;
+    let (args, items) = split(env).map_err(usage)?;
+    let mut results = Vec::with_capacity(items.len());
+    let mut failed = None;
+    let total = items.len();
+    for item in items {
+        let label = alloc::format!("{item:?}");
+        let mut sub = frame(item, &args);
+        match repl::evalcmd(config, &cmd, &mut sub) {
+            Ok(Value::Nil) => {}
+            Ok(v) => {
+                println!("{label}: {v:?}");
+                results.push(v);
+            }
+            Err(e) => {
+                println!("each: {cmd} {label}: {e:?}");
+                failed.get_or_insert((0, e)).0 += 1;
+            }
+        }
+    }
+    if let Some((count, e)) = failed {
+        println!("each: {count} of {total} failed");
+        return Err(e);
+    }
+    Ok(Value::List(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let s = |s: &str| Value::Str(s.into());
+        let mut env = alloc::vec![
+            Value::Nil,
+            Value::List(alloc::vec![s("/a"), s("/b")]),
+            Value::Unsigned(2),
+            s("dst"),
+        ];
+        let (args, items) = split(&mut env).unwrap();
+        assert_eq!(alloc::format!("{args:?}"), "[dst, 0x2]");
+        assert_eq!(items.len(), 2);
+        assert_eq!(env.len(), 1);
+        let sub = frame(s("/a"), &args);
+        assert_eq!(alloc::format!("{sub:?}"), "[/a, 0x2, dst]");
+        assert!(split(&mut env).is_err());
+        let list = Value::List(items);
+        assert_eq!(alloc::format!("{list:?}"), "{/a /b}");
+    }
+}
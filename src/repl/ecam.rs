@@ -5,7 +5,7 @@
 use crate::bldb;
 use crate::pci;
 use crate::println;
-use crate::repl;
+use crate::repl::{self, Access};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 
@@ -25,7 +25,7 @@ fn parse_bdf(s: &str) -> Result<(pci::Bus, pci::Device, pci::Function)> {
 }
 
 pub(super) fn read(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -42,6 +42,12 @@ pub(super) fn read(
         .map_err(usage)?;
     let data = unsafe { pci::ecam::read::<u32>(bus, dev, func, offset) }
         .map_err(usage)?;
+    config.access_log.record(Access::Ecam {
+        bdf: (bus.0, dev as u8, func as u8),
+        offset: offset.addr(),
+        value: data,
+        write: false,
+    });
     println!(
         "{b}/{d}/{f} {offset:#x} {data:#x}",
         b = bus.0,
@@ -53,7 +59,7 @@ pub(super) fn read(
 }
 
 pub(super) fn write(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -72,5 +78,11 @@ pub(super) fn write(
     unsafe {
         pci::ecam::write(bus, dev, func, offset, value)?;
     }
+    config.access_log.record(Access::Ecam {
+        bdf: (bus.0, dev as u8, func as u8),
+        offset: offset.addr(),
+        value,
+        write: true,
+    });
     Ok(repl::Value::Nil)
 }
@@ -5,8 +5,9 @@
 use crate::bldb;
 use crate::loader;
 use crate::println;
+use crate::ramdisk;
 use crate::repl::{self, Value};
-use crate::result::{Error, Result};
+use crate::result::Result;
 use alloc::vec::Vec;
 
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
@@ -15,7 +16,12 @@ pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
         error
     };
     let path = repl::popenv(env).as_string().map_err(usage)?;
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
     let kernel = fs.open(&path)?;
     loader::elfinfo(kernel.as_ref())?;
     Ok(Value::Nil)
@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: fgrep [-i] <pattern> <path|glob>");
+        error
+    };
+    let mut pattern = repl::popenv(env).as_string().map_err(usage)?;
+    let ignore_case = pattern == "-i";
+    if ignore_case {
+        pattern = repl::popenv(env).as_string().map_err(usage)?;
+    }
+    let path = repl::popenv(env).as_string().map_err(usage)?;
+    let (fs, inner) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let paths = ramdisk::glob(fs, &inner)?;
+    if paths.is_empty() {
+        println!("fgrep: no files match {path}");
+        return Err(Error::FsNoFile);
+    }
+    let mut count = 0;
+    for path in paths.iter() {
+        count += ramdisk::fgrep(fs, path, pattern.as_bytes(), ignore_case)?;
+    }
+    Ok(Value::Unsigned(count as u128))
+}
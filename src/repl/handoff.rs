@@ -0,0 +1,282 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Construction and decoding of ad-hoc parameter blobs handed
+//! to experimental kernels.
+//!
+//! A blob is a 16 byte header followed by a sequence of
+//! entries, all little-endian:
+//!
+//! ```text
+//! header:  magic "BLHO" | version u16 | count u16 | len u32 | crc u32
+//! entry:   tag u32 | type u16 | klen u16 | vlen u32 | key | value
+//! ```
+//!
+//! `len` is the length of the whole blob, and `crc` is the
+//! CRC-32 of the whole blob, computed with the `crc` field
+//! zeroed.  Key-value entries have tag 0 and a NUL-terminated
+//! key; TLV entries have a non-zero tag and no key.  Values are
+//! either 64-bit unsigned integers (type 1) or NUL-terminated
+//! strings (type 2), and `klen` and `vlen` include the NULs.
+//! Each entry is padded with zeroes to a multiple of 8 bytes.
+
+use crate::bldb;
+use crate::crc32;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const MAGIC: [u8; 4] = *b"BLHO";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 16;
+const ENTRY_HEADER_LEN: usize = 12;
+const CRC_OFFSET: usize = 12;
+
+const TYPE_U64: u16 = 1;
+const TYPE_STR: u16 = 2;
+
+/// The value of an entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Datum {
+    U64(u64),
+    Str(String),
+}
+
+/// An entry: either a named key-value pair, or a tagged value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Key {
+    Name(String),
+    Tag(u32),
+}
+
+fn cstr(s: &str) -> Vec<u8> {
+    let mut bs = Vec::from(s.as_bytes());
+    bs.push(0);
+    bs
+}
+
+/// Serializes entries into a blob.
+fn encode(entries: &[(Key, Datum)]) -> Result<Vec<u8>> {
+    let count = u16::try_from(entries.len()).map_err(|_| Error::NumRange)?;
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&MAGIC);
+    blob.extend_from_slice(&VERSION.to_le_bytes());
+    blob.extend_from_slice(&count.to_le_bytes());
+    blob.extend_from_slice(&[0; 8]);
+    for (key, datum) in entries {
+        let (tag, key) = match key {
+            Key::Name(name) => (0, cstr(name)),
+            Key::Tag(tag) => (*tag, Vec::new()),
+        };
+        let (typ, value) = match datum {
+            Datum::U64(v) => (TYPE_U64, Vec::from(v.to_le_bytes())),
+            Datum::Str(s) => (TYPE_STR, cstr(s)),
+        };
+        let klen = u16::try_from(key.len()).map_err(|_| Error::NumRange)?;
+        let vlen = u32::try_from(value.len()).map_err(|_| Error::NumRange)?;
+        blob.extend_from_slice(&tag.to_le_bytes());
+        blob.extend_from_slice(&typ.to_le_bytes());
+        blob.extend_from_slice(&klen.to_le_bytes());
+        blob.extend_from_slice(&vlen.to_le_bytes());
+        blob.extend_from_slice(&key);
+        blob.extend_from_slice(&value);
+        blob.resize(blob.len().next_multiple_of(8), 0);
+    }
+    let len = u32::try_from(blob.len()).map_err(|_| Error::NumRange)?;
+    blob[8..12].copy_from_slice(&len.to_le_bytes());
+    let crc = crc32::crc32(&blob);
+    blob[CRC_OFFSET..CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+    Ok(blob)
+}
+
+fn u16_at(bs: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bs.get(off..off + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bs: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bs.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn parse_cstr(bs: &[u8]) -> Option<String> {
+    let (&nul, s) = bs.split_last()?;
+    if nul != 0 || s.contains(&0) {
+        return None;
+    }
+    core::str::from_utf8(s).ok().map(String::from)
+}
+
+/// Validates and decodes a blob at the start of the given
+/// memory, returning its entries and its length.
+fn decode(bs: &[u8]) -> Result<(Vec<(Key, Datum)>, usize)> {
+    let malformed = Error::Handoff("malformed blob");
+    if !bs.starts_with(&MAGIC) {
+        return Err(Error::Handoff("bad magic"));
+    }
+    if u16_at(bs, 4) != Some(VERSION) {
+        return Err(Error::Handoff("unsupported version"));
+    }
+    let count = u16_at(bs, 6).ok_or(malformed)?;
+    let len = u32_at(bs, 8).ok_or(malformed)? as usize;
+    let crc = u32_at(bs, CRC_OFFSET).ok_or(malformed)?;
+    let blob = bs.get(..len).filter(|b| b.len() >= HEADER_LEN);
+    let blob = blob.ok_or(Error::Handoff("truncated"))?;
+    let computed = crc32::update(crc32::crc32(&blob[..CRC_OFFSET]), &[0; 4]);
+    let computed = crc32::update(computed, &blob[CRC_OFFSET + 4..]);
+    if computed != crc {
+        return Err(Error::Verify);
+    }
+    let mut entries = Vec::new();
+    let mut off = HEADER_LEN;
+    for _ in 0..count {
+        let tag = u32_at(blob, off).ok_or(malformed)?;
+        let typ = u16_at(blob, off + 4).ok_or(malformed)?;
+        let klen = usize::from(u16_at(blob, off + 6).ok_or(malformed)?);
+        let vlen = u32_at(blob, off + 8).ok_or(malformed)? as usize;
+        let kstart = off + ENTRY_HEADER_LEN;
+        let vstart = kstart + klen;
+        let key = blob.get(kstart..vstart).ok_or(malformed)?;
+        let value = blob.get(vstart..vstart + vlen).ok_or(malformed)?;
+        let key = match tag {
+            0 => Key::Name(parse_cstr(key).ok_or(malformed)?),
+            tag if klen == 0 => Key::Tag(tag),
+            _ => return Err(malformed),
+        };
+        let datum = match typ {
+            TYPE_U64 => Datum::U64(u64::from_le_bytes(
+                value.try_into().map_err(|_| malformed)?,
+            )),
+            TYPE_STR => Datum::Str(parse_cstr(value).ok_or(malformed)?),
+            _ => return Err(malformed),
+        };
+        entries.push((key, datum));
+        off = (vstart + vlen).next_multiple_of(8);
+    }
+    Ok((entries, len))
+}
+
+fn show(bs: &[u8]) -> Result<()> {
+    let (entries, len) = decode(bs)?;
+    println!("handoff: {} entries, {len:#x} bytes, checksum ok", entries.len());
+    for (key, datum) in entries {
+        match key {
+            Key::Name(name) => print_entry(&name, &datum),
+            Key::Tag(tag) => print_entry(&alloc::format!("#{tag}"), &datum),
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(key: &str, datum: &Datum) {
+    match datum {
+        Datum::U64(v) => println!("{key:<16} = {v:#x}"),
+        Datum::Str(s) => println!("{key:<16} = \"{s}\""),
+    }
+}
+
+/// Collects the remaining key and value pairs on the stack.
+fn collect(env: &mut Vec<Value>, tlv: bool) -> Result<Vec<(Key, Datum)>> {
+    let mut entries = Vec::new();
+    loop {
+        let key = match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Str(name) if !tlv && !name.is_empty() => Key::Name(name),
+            v @ Value::Unsigned(_) if tlv => {
+                let tag = v.as_num::<u32>()?;
+                if tag == 0 {
+                    return Err(v.bad_arg("non-zero tag"));
+                }
+                Key::Tag(tag)
+            }
+            v => return Err(v.bad_arg(if tlv { "tag" } else { "key" })),
+        };
+        let datum = match repl::popenv(env) {
+            Value::Str(s) => Datum::Str(s),
+            v @ Value::Unsigned(_) => Datum::U64(v.as_num()?),
+            v => return Err(v.bad_arg("number or string")),
+        };
+        entries.push((key, datum));
+    }
+    Ok(entries)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: handoff kv <addr>,<len> [<key> <value>]... | \
+             handoff tlv <addr>,<len> [<tag> <value>]... | \
+             handoff show <addr>[,<len>]"
+        );
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = arg.as_string().map_err(usage)?;
+    match cmd.as_str() {
+        "kv" | "tlv" => {
+            let dst = repl::popenv(env)
+                .as_slice_mut(&config.page_table, 0)
+                .and_then(|o| o.ok_or(Error::BadArgs))
+                .map_err(usage)?;
+            let entries = collect(env, cmd == "tlv").map_err(usage)?;
+            let blob = encode(&entries)?;
+            if blob.len() > dst.len() {
+                println!(
+                    "handoff: blob is {:#x} bytes, region only {:#x}",
+                    blob.len(),
+                    dst.len()
+                );
+                return Err(Error::NumRange);
+            }
+            let dst = &mut dst[..blob.len()];
+            dst.copy_from_slice(&blob);
+            show(dst)?;
+            Ok(Value::Slice(config.page_table.buf(dst)))
+        }
+        "show" => {
+            // Given only an address, take the length from the
+            // header.
+            let src = repl::popenv(env);
+            let header = src
+                .as_slice(&config.page_table, HEADER_LEN)
+                .and_then(|o| o.ok_or(Error::BadArgs))
+                .map_err(usage)?;
+            let len = u32_at(header, 8).map_or(HEADER_LEN, |len| len as usize);
+            let bs = src
+                .as_slice(&config.page_table, len.max(HEADER_LEN))?
+                .ok_or(Error::BadArgs)?;
+            show(bs)?;
+            Ok(Value::Nil)
+        }
+        _ => Err(usage(arg.bad_arg("kv, tlv, or show"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let entries = [
+            (Key::Name("console".into()), Datum::Str("ttya".into())),
+            (Key::Name("baud".into()), Datum::U64(115200)),
+            (Key::Tag(7), Datum::Str(String::new())),
+            (Key::Tag(9), Datum::U64(u64::MAX)),
+        ];
+        let mut blob = encode(&entries).unwrap();
+        assert_eq!(blob.len() % 8, 0);
+        blob.extend_from_slice(&[0xff; 32]);
+        let (decoded, len) = decode(&blob).unwrap();
+        assert_eq!(decoded, entries);
+        assert_eq!(len, blob.len() - 32);
+        blob[HEADER_LEN + 13] ^= 1;
+        assert!(matches!(decode(&blob), Err(Error::Verify)));
+        assert!(decode(&blob[..len - 1]).is_err());
+        assert!(decode(b"nope").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Actions taken when the REPL sits idle at the prompt.
+//!
+//! For unattended and remotely driven operation, the REPL may
+//! be configured with an input idle timeout.  If no input
+//! arrives at the prompt before the timeout expires, the
+//! configured action is taken: re-running a command line (such
+//! as an autoboot pipeline), signaling the boot beacon, or
+//! printing a status line so that a remote observer can tell
+//! that the loader is alive.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+/// What to do when the idle timeout expires.
+#[derive(Clone, Debug)]
+pub(crate) enum Action {
+    /// Prints a status line.
+    Status,
+    /// Signals the REPL phase on the boot beacon.
+    Beacon,
+    /// Runs the given command line as if it had been typed.
+    Run(String),
+}
+
+/// The REPL idle timeout and the action to take when it
+/// expires.
+#[derive(Clone, Debug)]
+pub(crate) struct Idle {
+    pub(crate) timeout: Duration,
+    pub(crate) action: Action,
+}
+
+impl fmt::Display for Idle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.timeout.as_secs();
+        match &self.action {
+            Action::Status => write!(f, "after {secs}s: status"),
+            Action::Beacon => write!(f, "after {secs}s: beacon"),
+            Action::Run(cmd) => write!(f, "after {secs}s: run '{cmd}'"),
+        }
+    }
+}
+
+/// Takes the idle action, returning a command line for the
+/// reader to evaluate, if the action calls for one.
+pub(super) fn expired(
+    config: &mut bldb::Config,
+    env: &[Value],
+) -> Option<String> {
+    let action = config.idle.as_ref()?.action.clone();
+    println!();
+    match action {
+        Action::Status => {
+            let ramdisk = config.mounts.root().map(|fs| fs.as_str());
+            println!(
+                "idle: waiting for input; {} on stack; ramdisk {}",
+                env.len(),
+                ramdisk.unwrap_or("not mounted"),
+            );
+            None
+        }
+        Action::Beacon => {
+            config.signal(crate::beacon::Phase::ReplReady);
+            None
+        }
+        Action::Run(cmd) => {
+            println!("idle: running '{cmd}'");
+            Some(cmd)
+        }
+    }
+}
+
+/// Joins the remaining words on the stack into a command line.
+fn cmdline(env: &mut Vec<Value>) -> Result<String> {
+    let mut line = String::new();
+    loop {
+        let word = match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Str(s) => s,
+            Value::Unsigned(n) => alloc::format!("{n:#x}"),
+            v => return Err(v.bad_arg("command word")),
+        };
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if line.is_empty() {
+        return Err(Error::BadArgs);
+    }
+    Ok(line)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: idle [off | <secs> status | <secs> beacon | \
+             <secs> run <command...>]"
+        );
+        error
+    };
+    let arg = repl::popenv(env);
+    match &arg {
+        Value::Nil => {}
+        Value::Str(s) if s == "off" => config.idle = None,
+        v => {
+            let secs = v.as_num::<u64>().map_err(usage)?;
+            if secs == 0 {
+                return Err(usage(v.bad_arg("non-zero seconds")));
+            }
+            let which = repl::popenv(env);
+            let action = match which.as_string().map_err(usage)?.as_str() {
+                "status" => Action::Status,
+                "beacon" => Action::Beacon,
+                "run" => Action::Run(cmdline(env).map_err(usage)?),
+                _ => {
+                    return Err(usage(which.bad_arg("status, beacon, or run")));
+                }
+            };
+            let timeout = Duration::from_secs(secs);
+            config.idle = Some(Idle { timeout, action });
+        }
+    }
+    match &config.idle {
+        Some(idle) => println!("idle: {idle}"),
+        None => println!("idle: off"),
+    }
+    Ok(Value::Nil)
+}
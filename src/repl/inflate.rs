@@ -3,42 +3,301 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::bldb;
+use crate::cons;
+use crate::gzip;
 use crate::println;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 
-/// Expands the compressed ramdisk into a dedicated RAM region and returns
-/// a slice around the its contents.
-fn inflate<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8]> {
+/// How often to report progress, in bytes of output.
+const PROGRESS_INTERVAL: usize = 16 * 1024 * 1024;
+
+/// How much input to give the decompressor at a time, so that
+/// progress can be reported while expanding.
+const CHUNK_LEN: usize = 1024 * 1024;
+
+/// The stream formats that we can decompress.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Zlib,
+    Gzip,
+    Raw,
+}
+
+/// Returns true if the source begins with a valid zlib header,
+/// specifying DEFLATE with a window of at most 32KiB.
+fn is_zlib(src: &[u8]) -> bool {
+    match src {
+        &[cmf, flg, ..] => {
+            cmf & 0x0f == 8
+                && cmf >> 4 <= 7
+                && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+/// Determines the format of the compressed source, returning
+/// it along with the portion of the source given to the
+/// decompressor, and the uncompressed size if the format
+/// records it.  Sources that are neither zlib nor gzip are
+/// assumed to be raw DEFLATE streams.
+fn sniff(src: &[u8]) -> Result<(Format, &[u8], Option<usize>)> {
+    const FDICT: u8 = 1 << 5;
+    if src.starts_with(&[0x1f, 0x8b]) {
+        let (data, size) = gzip::frame(&src)?;
+        Ok((Format::Gzip, &src[data], Some(size)))
+    } else if is_zlib(src) {
+        if src[1] & FDICT != 0 {
+            return Err(Error::Decompress("zlib: preset dictionary required"));
+        }
+        Ok((Format::Zlib, src, None))
+    } else {
+        Ok((Format::Raw, src, None))
+    }
+}
+
+/// Reports that the destination is too small to hold the
+/// expanded data.
+fn overflow(required: Option<usize>, available: usize) -> Error {
+    match required {
+        Some(required) => println!(
+            "inflate: destination too small: \
+             {required:#x} bytes required, {available:#x} available"
+        ),
+        None => println!(
+            "inflate: destination too small: \
+             more than {available:#x} bytes required"
+        ),
+    }
+    Error::Decompress("destination too small").context(
+        "required,available",
+        &[required.unwrap_or(0) as u64, available as u64],
+    )
+}
+
+/// Ways in which expansion can fail: the destination is too
+/// small, the decompressor failed at the given input offset, or
+/// the poll function failed.
+#[derive(Debug)]
+enum Failure {
+    Overflow,
+    Poll(Error),
+    Status(miniz_oxide::inflate::TINFLStatus, usize),
+}
+
+/// Expands the DEFLATE stream in `src`, of the given format, into
+/// `dst`, returning the number of bytes written.  The source is
+/// fed to the decompressor in chunks, and `progress` is called
+/// with the number of bytes consumed and produced after each.
+/// `poll` is called before each chunk, and should it fail,
+/// expansion is abandoned.
+fn expand(
+    src: &[u8],
+    dst: &mut [u8],
+    format: Format,
+    mut progress: impl FnMut(usize, usize),
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> core::result::Result<usize, Failure> {
     use miniz_oxide::inflate::TINFLStatus;
     use miniz_oxide::inflate::core::DecompressorOxide;
     use miniz_oxide::inflate::core::decompress;
-    use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER;
+    use miniz_oxide::inflate::core::inflate_flags::{
+        TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_PARSE_ZLIB_HEADER,
+        TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+    };
 
     let mut r = DecompressorOxide::new();
-    let flags = TINFL_FLAG_PARSE_ZLIB_HEADER;
-    let (s, _, o) = decompress(&mut r, src, dst, 0, flags);
-    if s != TINFLStatus::Done {
-        println!("inflate failed: state is {s:?}");
+    let mut flags = TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    if format == Format::Zlib {
+        flags |= TINFL_FLAG_PARSE_ZLIB_HEADER;
+    }
+    let (mut nin, mut nout) = (0, 0);
+    loop {
+        poll().map_err(Failure::Poll)?;
+        let end = usize::min(nin + CHUNK_LEN, src.len());
+        let more = if end < src.len() { TINFL_FLAG_HAS_MORE_INPUT } else { 0 };
+        let (s, i, o) =
+            decompress(&mut r, &src[nin..end], dst, nout, flags | more);
+        nin += i;
+        nout += o;
+        progress(nin, nout);
+        match s {
+            TINFLStatus::Done => return Ok(nout),
+            TINFLStatus::NeedsMoreInput if more != 0 => {}
+            TINFLStatus::HasMoreOutput => return Err(Failure::Overflow),
+            s => return Err(Failure::Status(s, nin)),
+        }
+    }
+}
+
+/// Expands the compressed ramdisk into a dedicated RAM region and returns
+/// a slice around the its contents.
+fn inflate<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    poll: &mut dyn FnMut() -> Result<()>,
+) -> Result<&'a [u8]> {
+    let (format, src, expected) = sniff(src)?;
+    match expected {
+        Some(size) => println!("inflate: {format:?}, {size:#x} bytes expected"),
+        None => println!("inflate: {format:?}, size unknown"),
+    }
+    if let Some(size) = expected
+        && size > dst.len()
+    {
+        return Err(overflow(expected, dst.len()));
+    }
+    let mut next_report = PROGRESS_INTERVAL;
+    let progress = |nin: usize, nout: usize| {
+        if nout >= next_report {
+            println!("inflate: {} MiB in, {} MiB out", nin >> 20, nout >> 20);
+            next_report = nout + PROGRESS_INTERVAL;
+        }
+    };
+    let nout = match expand(src, dst, format, progress, poll) {
+        Ok(nout) => nout,
+        Err(Failure::Overflow) => return Err(overflow(expected, dst.len())),
+        Err(Failure::Poll(e)) => return Err(e),
+        Err(Failure::Status(s, nin)) => {
+            println!("inflate failed: state is {s:?} at input offset {nin:#x}");
+            return Err(Error::SadBalloon);
+        }
+    };
+    if let Some(size) = expected
+        && size != nout
+    {
+        println!("inflate: expanded to {nout:#x} bytes, expected {size:#x}");
         return Err(Error::SadBalloon);
     }
-    Ok(&dst[..o])
+    println!("inflate: {:#x} bytes expanded to {nout:#x}", src.len());
+    Ok(&dst[..nout])
 }
 
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: inflate <src addr>,<src len> [<dst addr>,<dst len>]");
+        println!(
+            "usage: inflate [-r] <src addr>,<src len> [<dst addr>,<dst len>]"
+        );
         error
     };
+    let ramdisk = matches!(env.last(), Some(Value::Str(s)) if s == "-r");
+    if ramdisk {
+        env.pop();
+    }
     let src = repl::popenv(env)
         .as_slice(&config.page_table, 0)
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
     let dst = repl::popenv(env)
-        .as_slice_mut(&config.page_table, 0)
-        .map_err(usage)?
-        .unwrap_or_else(|| bldb::ramdisk_region_init_mut());
-    let inflated = inflate(src, dst)?;
-    Ok(Value::Slice(inflated))
+        .as_slice_mut_unzeroed(&config.page_table, 0)
+        .map_err(usage)?;
+    if ramdisk && dst.is_some() {
+        return Err(usage(Error::BadArgs));
+    }
+    let dst_range = match &dst {
+        Some(dst) => dst.as_ptr_range(),
+        None => {
+            let region = match ramdisk {
+                true => &config.ramdisk_region,
+                false => &config.scratch_region,
+            };
+            region.start.addr() as *const u8..region.end.addr() as *const u8
+        }
+    };
+    let src_range = src.as_ptr_range();
+    if src_range.start < dst_range.end && dst_range.start < src_range.end {
+        println!(
+            "inflate: the destination {:p}..{:p} overlaps the source",
+            dst_range.start, dst_range.end
+        );
+        return Err(Error::BadArgs);
+    }
+    let dst = match dst {
+        Some(dst) => dst,
+        None if ramdisk => config.ramdisk_region_init_mut(),
+        None => config.scratch_region_init_mut(),
+    };
+    let inflated = inflate(src, dst, &mut cons::poller(&mut config.cons))?;
+    Ok(Value::Slice(config.page_table.buf(inflated)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+
+    fn data() -> Vec<u8> {
+        (0..200_000u64).map(|k| (k * k % 253) as u8).collect()
+    }
+
+    #[test]
+    fn formats() {
+        let data = data();
+        let mut dst = vec![0u8; data.len()];
+        let zlib = compress_to_vec_zlib(&data, 6);
+        let (format, src, _) = sniff(&zlib).unwrap();
+        assert_eq!(format, Format::Zlib);
+        let n =
+            expand(src, &mut dst, format, |_, _| {}, &mut || Ok(())).unwrap();
+        assert_eq!(&dst[..n], &data[..]);
+        let raw = compress_to_vec(&data, 6);
+        let (format, src, _) = sniff(&raw).unwrap();
+        assert_eq!(format, Format::Raw);
+        let n =
+            expand(src, &mut dst, format, |_, _| {}, &mut || Ok(())).unwrap();
+        assert_eq!(&dst[..n], &data[..]);
+    }
+
+    #[test]
+    fn destination_too_small() {
+        let data = data();
+        let zlib = compress_to_vec_zlib(&data, 6);
+        let mut dst = vec![0u8; data.len() - 1];
+        let mut calls = 0;
+        let progress = |_, _| calls += 1;
+        let res =
+            expand(&zlib, &mut dst, Format::Zlib, progress, &mut || Ok(()));
+        assert!(matches!(res, Err(Failure::Overflow)));
+        assert!(calls > 0);
+    }
+
+    #[test]
+    fn cancelled() {
+        let data: Vec<u8> = data().repeat(20);
+        let zlib = compress_to_vec_zlib(&data, 0);
+        assert!(zlib.len() > CHUNK_LEN);
+        let mut dst = vec![0u8; data.len()];
+        let mut polls = 0;
+        let mut poll = || {
+            polls += 1;
+            if polls > 1 { Err(Error::Cancelled) } else { Ok(()) }
+        };
+        let res = expand(&zlib, &mut dst, Format::Zlib, |_, _| {}, &mut poll);
+        assert!(matches!(res, Err(Failure::Poll(Error::Cancelled))));
+        assert_eq!(polls, 2);
+    }
+
+    #[test]
+    fn overlapping_destination() {
+        let mut sim = Sim::new();
+        let zlib = compress_to_vec_zlib(&[0xa5; 0x100], 6);
+        sim.ram()[..zlib.len()].copy_from_slice(&zlib);
+        let addr = sim.ram().as_ptr().addr();
+        let out = sim.session(&format!(
+            "inflate {addr:#x},{:#x} {:#x},0x100\n",
+            zlib.len(),
+            addr + 1
+        ));
+        assert!(out.contains("overlaps the source"), "{out}");
+        assert_eq!(sim.ram()[..zlib.len()], zlib[..]);
+        let out = sim.session(&format!(
+            "inflate -r {addr:#x},{:#x} {:#x},0x100\n",
+            zlib.len(),
+            addr + 0x1000
+        ));
+        assert!(out.contains("usage: inflate"), "{out}");
+    }
 }
@@ -6,7 +6,7 @@ use crate::bldb;
 use crate::iomux;
 use crate::println;
 use crate::repl::{self, Value};
-use crate::result::{Error, Result};
+use crate::result::Result;
 use alloc::vec::Vec;
 
 pub(super) fn get(
@@ -26,14 +26,14 @@ pub(super) fn get(
     }
 }
 
-fn parse_func(value: Value) -> Result<iomux::PinFunction> {
+pub(super) fn parse_func(value: Value) -> Result<iomux::PinFunction> {
     let s = value.as_string()?;
     match s.as_str() {
         "F0" | "f0" => Ok(iomux::PinFunction::F0),
         "F1" | "f1" => Ok(iomux::PinFunction::F1),
         "F2" | "f2" => Ok(iomux::PinFunction::F2),
         "F3" | "f3" => Ok(iomux::PinFunction::F3),
-        _ => Err(Error::BadArgs),
+        _ => Err(value.bad_arg("F0, F1, F2, or F3")),
     }
 }
 
@@ -2,10 +2,15 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! The "jazzy" number formatter.
+
 use crate::bldb;
+use crate::regdefs::{self, RegDef};
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
 use crate::{print, println};
+use alloc::format;
+use alloc::string::String;
 use alloc::{vec, vec::Vec};
 
 const PREFIX: &str = "                ";
@@ -22,32 +27,58 @@ fn putsln(s: &[char]) {
     puts(s, "\n");
 }
 
-pub fn jfmt(num: u128) {
-    let n = 128 - num.leading_zeros() as usize;
+/// Options controlling how a value is rendered.
+#[derive(Clone, Copy, Default)]
+struct Options {
+    signed: bool,
+    regdef: Option<&'static RegDef>,
+}
 
-    let mut v = Vec::new();
-    let mut ones = Vec::new();
-    for k in 0..n {
-        let bit = (num >> k) & 0b1 == 0b1;
-        v.push(bit);
-        if bit {
-            ones.push(k);
-        }
+impl Options {
+    /// Returns the width of the value as a signed quantity: that
+    /// of the register, if given, or otherwise the narrowest
+    /// integer type that holds it.
+    fn width(&self, num: u128) -> u32 {
+        self.regdef.map_or_else(
+            || {
+                [8, 16, 32, 64]
+                    .into_iter()
+                    .find(|&w| num >> w == 0)
+                    .unwrap_or(128)
+            },
+            |def| u32::from(def.width),
+        )
     }
-    v.reverse();
 
-    let mut cs = vec![' '; n];
+    /// Returns the name of the field containing the given bit, if
+    /// a register was given, with a leading space.
+    fn field_name(&self, bit: usize) -> String {
+        let field = self.regdef.and_then(|def| def.field_at(bit));
+        field.map_or_else(String::new, |field| format!(" {}", field.name))
+    }
+}
 
-    println!("{PREFIX}{num:b}");
-    for k in 0..v.len() {
-        cs[k] = if v[k] { '▴' } else { ' ' };
+/// Interprets the low `bits` bits of a value as a two's
+/// complement signed integer.
+fn signed(num: u128, bits: u32) -> i128 {
+    let shift = 128 - bits;
+    ((num << shift) as i128) >> shift
+}
+
+/// Draws a line from each of the given bits, which are marked in
+/// `v` (most significant first), to its label.
+fn annotate(v: &[bool], bits: &[usize], label: impl Fn(usize) -> String) {
+    let n = v.len();
+    let mut cs = vec![' '; n];
+    for (k, &b) in v.iter().enumerate() {
+        cs[k] = if b { '▴' } else { ' ' };
     }
     putsln(&cs);
 
-    let max1 = ones.iter().last().map_or(0, |&l| l);
+    let max1 = bits.iter().last().map_or(0, |&l| l);
     let bit_width = max1.checked_ilog10().unwrap_or(0) as usize + 1;
     let mask_width = (max1 + 4) / 4;
-    for this1 in ones.into_iter() {
+    for &this1 in bits {
         let off = n - 1 - this1;
         for (k, &b) in v.iter().enumerate() {
             cs[k] = match (k, b) {
@@ -59,25 +90,158 @@ pub fn jfmt(num: u128) {
         }
         puts(&cs, "── ");
         println!(
-            "bit {this1:bit_width$} mask 0x{mask:0>mask_width$x}",
-            mask = 1u128 << this1
+            "bit {this1:bit_width$} mask 0x{mask:0>mask_width$x}{label}",
+            mask = 1u128 << this1,
+            label = label(this1),
+        );
+    }
+}
+
+/// Returns the bits of `num` below `n` (most significant first),
+/// and the positions of those that are set, in ascending order.
+fn bits(num: u128, n: usize) -> (Vec<bool>, Vec<usize>) {
+    let mut v = Vec::new();
+    let mut ones = Vec::new();
+    for k in 0..n {
+        let bit = (num >> k) & 0b1 == 0b1;
+        v.push(bit);
+        if bit {
+            ones.push(k);
+        }
+    }
+    v.reverse();
+    (v, ones)
+}
+
+fn print_fields(def: &RegDef, num: u128) {
+    let name_width = def.fields.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    println!();
+    for field in def.fields.iter().rev() {
+        let range = if field.end - field.start == 1 {
+            format!("{}", field.start)
+        } else {
+            format!("{}:{}", field.end - 1, field.start)
+        };
+        println!(
+            "{PREFIX}{name:<name_width$} [{range:>5}] {value:#x}",
+            name = field.name,
+            value = field.get(num),
         );
     }
+}
+
+fn jfmt(num: u128, opts: Options) {
+    let n = 128 - num.leading_zeros() as usize;
+    let (v, ones) = bits(num, n);
+
+    println!("{PREFIX}{num:b}");
+    annotate(&v, &ones, |bit| opts.field_name(bit));
 
     println!();
     println!("{PREFIX}hex: {num:#x}");
     println!("{PREFIX}dec: {num}");
+    if opts.signed {
+        let w = opts.width(num);
+        println!("{PREFIX}i{w}: {}", signed(num, w));
+    }
     println!("{PREFIX}oct: {num:#o}");
+    if let Some(def) = opts.regdef {
+        print_fields(def, num);
+    }
+}
+
+/// Renders the bits that differ between two values.
+fn diff(old: u128, new: u128, opts: Options) {
+    let n = (128 - (old | new).leading_zeros() as usize).max(1);
+    let (v, changed) = bits(old ^ new, n);
+    let from = |bit| (old >> bit) & 1;
+
+    println!("{PREFIX}{old:0n$b} old");
+    println!("{PREFIX}{new:0n$b} new");
+    annotate(&v, &changed, |bit| {
+        format!(" {}->{}{}", from(bit), from(bit) ^ 1, opts.field_name(bit))
+    });
+
+    println!();
+    println!("{PREFIX}hex: {old:#x} -> {new:#x}, xor {:#x}", old ^ new);
+    println!("{PREFIX}dec: {old} -> {new}");
+    if opts.signed {
+        let w = opts.width(old).max(opts.width(new));
+        println!("{PREFIX}i{w}: {} -> {}", signed(old, w), signed(new, w));
+    }
+    if let Some(def) = opts.regdef {
+        println!();
+        for field in def.fields.iter().rev() {
+            let (a, b) = (field.get(old), field.get(new));
+            if a != b {
+                println!("{PREFIX}{}: {a:#x} -> {b:#x}", field.name);
+            }
+        }
+    }
 }
 
 pub fn run(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    let value = match repl::popenv(env) {
-        Value::Unsigned(value) => value,
-        _ => {
-            println!("usage: jfmt <number>");
-            return Err(Error::BadArgs);
+    let usage = |error| {
+        println!(
+            "usage: jfmt [-s] [-r <register>] <number> | \
+             jfmt [-s] [-r <register>] -d <old> <new>"
+        );
+        error
+    };
+    let mut opts = Options::default();
+    let mut is_diff = false;
+    let value = loop {
+        match repl::popenv(env) {
+            Value::Nil => return Err(usage(Error::BadArgs)),
+            Value::Unsigned(value) => break value,
+            arg => match arg.as_string().map_err(usage)?.as_str() {
+                "-s" => opts.signed = true,
+                "-d" => is_diff = true,
+                "-r" => {
+                    let name = repl::popenv(env).as_string().map_err(usage)?;
+                    let Some(def) = regdefs::lookup(&name) else {
+                        println!("jfmt: unknown register {name}");
+                        return Err(usage(Error::BadArgs));
+                    };
+                    opts.regdef = Some(def);
+                }
+                _ => return Err(usage(arg.bad_arg("-s, -r, -d, or number"))),
+            },
         }
     };
-    jfmt(value);
+    if is_diff {
+        let new = repl::popenv(env).as_num::<u128>().map_err(usage)?;
+        diff(value, new, opts);
+    } else {
+        jfmt(value, opts);
+    }
     Ok(Value::Nil)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signedness() {
+        let opts = Options::default();
+        assert_eq!(opts.width(0xff), 8);
+        assert_eq!(opts.width(0x100), 16);
+        assert_eq!(opts.width(1 << 64), 128);
+        assert_eq!(signed(0xff, 8), -1);
+        assert_eq!(signed(0x7f, 8), 127);
+        assert_eq!(signed(0xffff_fffe, 32), -2);
+        assert_eq!(signed(u128::MAX, 128), -1);
+        let opts = Options { regdef: regdefs::lookup("efer"), ..opts };
+        assert_eq!(opts.width(0xff), 64);
+        assert_eq!(opts.field_name(11), " NXE");
+        assert_eq!(opts.field_name(1), "");
+    }
+
+    #[test]
+    fn bit_vectors() {
+        let (v, ones) = bits(0b1010, 4);
+        assert_eq!(v, [true, false, true, false]);
+        assert_eq!(ones, [1, 3]);
+    }
+}
@@ -0,0 +1,202 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A report of the loader's own address space layout.
+//!
+//! The regions the loader maps for itself are derived from the
+//! linker-provided segment bounds and a handful of fixed MMIO
+//! pages.  `layout` cross-references those against the regions
+//! reserved in the loader page table and the live mappings, and
+//! flags any disagreement, which usually means that the linker
+//! script or `start.S` has changed in a way that Rust code has
+//! not caught up with.
+
+use crate::bldb;
+use crate::mem;
+use crate::println;
+use crate::repl::Value;
+use crate::result::Result;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The regions that make up the loader's memory image, and so
+/// must lie within the loader region.
+const LOADER_SEGMENTS: &[&str] = &["text", "rodata", "data", "bss", "boot"];
+
+fn range(region: &mem::Region) -> Range<usize> {
+    region.start().addr()..region.end().addr()
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    !a.is_empty() && !b.is_empty() && a.start < b.end && b.start < a.end
+}
+
+fn contains(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// Formats the permissions in a set of attributes, as in `rw-`,
+/// with a `u` suffix for uncached mappings.
+fn perms(attrs: mem::Attrs) -> String {
+    let flag = |b, c| if b { c } else { '-' };
+    format!(
+        "{}{}{}{}",
+        flag(attrs.r(), 'r'),
+        flag(attrs.w(), 'w'),
+        flag(attrs.x(), 'x'),
+        if attrs.c() { "" } else { "u" }
+    )
+}
+
+/// Returns the names of pairs of distinct regions that overlap.
+fn collisions<'a>(
+    regions: &[(&'a str, Range<usize>)],
+) -> Vec<(&'a str, &'a str)> {
+    let mut pairs = Vec::new();
+    for (k, (a, ra)) in regions.iter().enumerate() {
+        for (b, rb) in regions[k + 1..].iter() {
+            if overlaps(ra, rb) {
+                pairs.push((*a, *b));
+            }
+        }
+    }
+    pairs
+}
+
+/// Walks the mappings over a region, returning a description of
+/// the first page that is unmapped or mapped with permissions
+/// other than those expected.
+fn check_mapping(
+    config: &bldb::Config,
+    region: &mem::Region,
+) -> Option<String> {
+    let want = perms(region.attrs());
+    let range = range(region);
+    let mut va = range.start;
+    while va < range.end {
+        let Some(entry) =
+            config.page_table.lookup(core::ptr::without_provenance(va))
+        else {
+            return Some(format!("unmapped at {va:#x}"));
+        };
+        let have = perms(entry.attrs());
+        if have != want {
+            return Some(format!("mapped {have} at {va:#x}"));
+        }
+        let size = entry.size();
+        va = (va & !(size - 1)).saturating_add(size);
+    }
+    None
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let regions = config.regions();
+    let ranges = regions
+        .iter()
+        .map(|(name, region)| (*name, range(region)))
+        .collect::<Vec<_>>();
+    let reserved = config
+        .page_table
+        .reserved()
+        .iter()
+        .map(|r| r.start.addr()..r.end.addr())
+        .collect::<Vec<_>>();
+    let loader =
+        config.loader_region.start.addr()..config.loader_region.end.addr();
+    let collisions = collisions(&ranges);
+    let mut problems = 0;
+    println!(
+        "{:<9} {:>18} {:>18} {:>10} {:<5} notes",
+        "region", "start", "end", "size", "attrs"
+    );
+    for (name, region) in regions.iter() {
+        let range = range(region);
+        let mut notes = Vec::new();
+        if range.is_empty() {
+            notes.push(String::from("empty"));
+        }
+        if LOADER_SEGMENTS.contains(name) && !contains(&loader, &range) {
+            notes.push(String::from("outside the loader region"));
+        }
+        for (a, b) in collisions.iter() {
+            if a == name {
+                notes.push(format!("overlaps {b}"));
+            } else if b == name {
+                notes.push(format!("overlaps {a}"));
+            }
+        }
+        if !reserved.iter().any(|r| contains(r, &range)) {
+            notes.push(String::from("not reserved"));
+        }
+        if let Some(note) = check_mapping(config, region) {
+            notes.push(note);
+        }
+        problems += notes.len();
+        println!(
+            "{name:<9} {:#18x} {:#18x} {:#10x} {:<5} {}",
+            range.start,
+            range.end,
+            range.len(),
+            perms(region.attrs()),
+            if notes.is_empty() {
+                String::from("ok")
+            } else {
+                notes.join(", ")
+            }
+        );
+    }
+    println!();
+    println!("reserved:");
+    for r in reserved.iter() {
+        let covers = ranges
+            .iter()
+            .filter(|(_, range)| !range.is_empty() && contains(r, range))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        let note = if covers.is_empty() {
+            problems += 1;
+            String::from("no known region")
+        } else {
+            covers.join(" ")
+        };
+        println!("    {:#18x} {:#18x} {note}", r.start, r.end);
+    }
+    println!("mmio:");
+    for r in config.page_table.mmio().iter() {
+        println!("    {:#18x} {:#18x}", r.start.addr(), r.end.addr());
+    }
+    println!();
+    println!("{problems} inconsistencies");
+    Ok(Value::Unsigned(problems as u128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_regions() {
+        let regions = [
+            ("text", 0x1000..0x3000),
+            ("rodata", 0x3000..0x4000),
+            ("data", 0x3800..0x5000),
+            ("empty", 0x4000..0x4000),
+        ];
+        assert_eq!(collisions(&regions), [("rodata", "data")]);
+        assert!(contains(&(0x1000..0x5000), &regions[2].1));
+        assert!(!contains(&(0x1000..0x4000), &regions[2].1));
+    }
+
+    #[test]
+    fn permissions() {
+        assert_eq!(perms(mem::Attrs::new_text()), "r-x");
+        assert_eq!(perms(mem::Attrs::new_data()), "rw-");
+        assert_eq!(perms(mem::Attrs::new_mmio()), "rw-u");
+    }
+}
@@ -4,17 +4,67 @@
 
 use crate::bldb;
 use crate::println;
-use crate::ramdisk;
+use crate::ramdisk::{self, ListOptions, SortBy};
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 
+fn parse_sort(value: Value) -> Result<SortBy> {
+    match value.as_string()?.as_str() {
+        "name" => Ok(SortBy::Name),
+        "size" => Ok(SortBy::Size),
+        "inode" => Ok(SortBy::Inode),
+        _ => Err(value.bad_arg("name, size, or inode")),
+    }
+}
+
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    let Ok(path) = repl::popenv(env).as_string() else {
-        println!("usage: ls file");
-        return Err(Error::BadArgs);
+    let usage = |error| {
+        println!("usage: ls [-d] [-h] [-s name|size|inode] file");
+        error
+    };
+    let mut opts = ListOptions::default();
+    let path = loop {
+        let arg = repl::popenv(env);
+        let s = match &arg {
+            Value::Nil => return Err(usage(Error::BadArgs)),
+            v => v.as_string().map_err(usage)?,
+        };
+        match s.as_str() {
+            "-d" => opts.dir_itself = true,
+            "-h" => opts.human = true,
+            "-s" => opts.sort = parse_sort(repl::popenv(env)).map_err(usage)?,
+            _ if s.starts_with('-') => {
+                return Err(usage(arg.bad_arg("-d, -h, or -s")));
+            }
+            _ => break s,
+        }
+    };
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    ramdisk::list(fs, &path, opts)?;
+    Ok(Value::Nil)
+}
+
+pub(super) fn stat(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: stat <file>");
+        error
     };
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
-    ramdisk::list(fs.as_ref(), &path)?;
+    let path = repl::popenv(env).as_string().map_err(usage)?;
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    ramdisk::stat(fs, &path)?;
     Ok(Value::Nil)
 }
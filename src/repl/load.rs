@@ -2,13 +2,32 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::beacon;
 use crate::bldb;
+use crate::cons;
+use crate::cpio;
 use crate::loader;
 use crate::println;
+use crate::ramdisk;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
+use alloc::format;
 use alloc::vec::Vec;
 
+/// Computes the SHA-256 digest of an image in memory, polling
+/// the console for cancellation.
+fn digest(config: &mut bldb::Config, src: &[u8]) -> Result<[u8; 32]> {
+    use sha2::{Digest, Sha256};
+    const CHUNK_LEN: usize = 1024 * 1024;
+    let mut poll = cons::poller(&mut config.cons);
+    let mut sum = Sha256::new();
+    for chunk in src.chunks(CHUNK_LEN) {
+        poll()?;
+        sum.update(chunk);
+    }
+    Ok(sum.finalize().into())
+}
+
 pub fn loadcpio(
     config: &mut bldb::Config,
     env: &mut Vec<Value>,
@@ -22,11 +41,13 @@ pub fn loadcpio(
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
     let path = repl::popenv(env).as_string().map_err(usage)?;
-    let src = cpio_reader::iter_files(cpio)
-        .find(|entry| entry.name() == path)
-        .ok_or(Error::CpioNoFile)?
-        .file();
-    let entry = loader::load_bytes(&mut config.page_table, src)?;
+    let src = cpio::find(cpio, &path)?;
+    let digest = digest(config, src)?;
+    config.signal(beacon::Phase::Loading);
+    let image = loader::load_bytes(&mut config.page_table, src)?;
+    let entry = image.entry;
+    config.measurements.measure(format!("loadcpio {path}"), digest);
+    loader::record(&mut config.images, &path, image);
     Ok(Value::Pointer(entry.cast_mut()))
 }
 
@@ -42,7 +63,13 @@ pub fn loadmem(
         .as_slice(&config.page_table, 0)
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
-    let entry = loader::load_bytes(&mut config.page_table, src)?;
+    let digest = digest(config, src)?;
+    config.signal(beacon::Phase::Loading);
+    let image = loader::load_bytes(&mut config.page_table, src)?;
+    let entry = image.entry;
+    let source = format!("{:p},{:#x}", src.as_ptr(), src.len());
+    config.measurements.measure(format!("loadmem {source}"), digest);
+    loader::record(&mut config.images, &source, image);
     crate::println!("Loaded ELF object from memory: entry point {entry:p}");
     Ok(Value::Pointer(entry.cast_mut()))
 }
@@ -53,9 +80,19 @@ pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
         error
     };
     let path = repl::popenv(env).as_string().map_err(usage)?;
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
-    let kernel = fs.open(&path)?;
-    let entry = loader::load_file(&mut config.page_table, kernel.as_ref())?;
+    config.signal(beacon::Phase::Loading);
+    let (fs, inner) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let kernel = fs.open(&inner)?;
+    let (image, digest) =
+        loader::load_file(&mut config.page_table, kernel.as_ref())?;
+    let entry = image.entry;
+    config.measurements.measure(format!("load {path}"), digest);
+    loader::record(&mut config.images, &path, image);
     crate::println!("Loaded ELF file: entry point {entry:p}");
     Ok(Value::Pointer(entry.cast_mut()))
 }
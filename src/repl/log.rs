@@ -0,0 +1,41 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Replaying the in-memory log of console output.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use crate::sink;
+use alloc::vec::Vec;
+
+/// Writes the log, or its last `len` bytes, to the console.  The
+/// log is copied out first, and written straight to the UART,
+/// so that the replay is not itself logged, and so that nothing
+/// printed meanwhile has to wait for it.
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: log [<len>]");
+        error
+    };
+    let len = match repl::popenv(env) {
+        Value::Nil => None,
+        arg => Some(arg.as_num::<usize>().map_err(usage)?),
+    };
+    let text = sink::with_log(len, |older, newer| [older, newer].concat());
+    config.cons.putbs_crnl(&text);
+    Ok(Value::Nil)
+}
+
+pub(super) fn clear(
+    _config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    sink::clear_log();
+    Ok(Value::Nil)
+}
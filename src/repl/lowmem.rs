@@ -0,0 +1,431 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Legacy firmware structures in the first MiB of memory.
+//!
+//! Nothing on our platforms should depend on the real-mode
+//! interrupt vector table, the BIOS data area, or option ROMs,
+//! but remnants left by earlier firmware stages sometimes
+//! explain odd platform behavior during bring-up.
+//!
+//! The first MiB of physical memory is mapped read-only at
+//! `LOWMEM_BASE`, rather than identity mapped, so that physical
+//! address 0 does not coincide with the null pointer.  Once
+//! mapped, it may also be examined with the ordinary memory
+//! commands, at `LOWMEM_BASE` plus the physical address.
+
+use crate::bldb;
+use crate::mem;
+use crate::print;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The virtual address at which low memory is mapped.
+const LOWMEM_BASE: usize = 0x7f00_0000_0000;
+
+/// The length of low memory.
+const LOWMEM_LEN: usize = mem::MIB;
+
+/// The physical address of the BIOS data area.
+const BDA: usize = 0x400;
+
+/// Where the extended BIOS data area may legitimately live.
+const EBDA_RANGE: Range<usize> = 0x8_0000..0xa_0000;
+
+/// Where option ROMs are found, and their alignment.
+const ROM_RANGE: Range<usize> = 0xc_0000..0xf_0000;
+const ROM_ALIGN: usize = 2 * 1024;
+
+/// The BIOS area searched for firmware tables.
+const BIOS_RANGE: Range<usize> = 0xe_0000..0x10_0000;
+
+fn range() -> Range<mem::V4KA> {
+    mem::V4KA::new(LOWMEM_BASE)..mem::V4KA::new(LOWMEM_BASE + LOWMEM_LEN)
+}
+
+/// Returns low memory, indexed by physical address, if it is
+/// mapped.
+fn lowmem(config: &bldb::Config) -> Result<&'static [u8]> {
+    if !config.page_table.is_region_readable(range()) {
+        println!("lowmem: not mapped; use `lowmem map`");
+        return Err(Error::Unmapped);
+    }
+    let ptr = core::ptr::with_exposed_provenance::<u8>(LOWMEM_BASE);
+    Ok(unsafe { core::slice::from_raw_parts(ptr, LOWMEM_LEN) })
+}
+
+fn u16_at(mem: &[u8], pa: usize) -> u16 {
+    u16::from_le_bytes([mem[pa], mem[pa + 1]])
+}
+
+fn u32_at(mem: &[u8], pa: usize) -> u32 {
+    u32::from_le_bytes(mem[pa..pa + 4].try_into().unwrap())
+}
+
+/// Converts a real-mode `segment:offset` pair to a linear
+/// address.
+fn linear(seg: u16, off: u16) -> usize {
+    (usize::from(seg) << 4) + usize::from(off)
+}
+
+/// Returns true iff the bytes sum to zero, modulo 256.
+fn checksum_ok(bs: &[u8]) -> bool {
+    bs.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Returns the interrupt vectors, each as `segment << 16 | offset`.
+fn ivt(mem: &[u8]) -> Vec<u32> {
+    (0..256).map(|k| u32_at(mem, k * 4)).collect()
+}
+
+/// Collapses runs of identical values, returning the first and
+/// last index of each run along with its value.
+fn runs(values: &[u32]) -> Vec<(usize, usize, u32)> {
+    let mut runs: Vec<(usize, usize, u32)> = Vec::new();
+    for (k, &v) in values.iter().enumerate() {
+        match runs.last_mut() {
+            Some((_, last, value)) if *value == v => *last = k,
+            _ => runs.push((k, k, v)),
+        }
+    }
+    runs
+}
+
+/// The BIOS data area fields we decode: name, offset from the
+/// start of the BDA, and width in bytes.
+const BDA_FIELDS: &[(&str, usize, usize)] = &[
+    ("COM1 port", 0x00, 2),
+    ("COM2 port", 0x02, 2),
+    ("COM3 port", 0x04, 2),
+    ("COM4 port", 0x06, 2),
+    ("LPT1 port", 0x08, 2),
+    ("LPT2 port", 0x0a, 2),
+    ("LPT3 port", 0x0c, 2),
+    ("EBDA segment", 0x0e, 2),
+    ("equipment", 0x10, 2),
+    ("base memory KiB", 0x13, 2),
+    ("keyboard flags", 0x17, 2),
+    ("video mode", 0x49, 1),
+    ("video columns", 0x4a, 2),
+    ("video page size", 0x4c, 2),
+    ("CRTC port", 0x63, 2),
+    ("timer ticks", 0x6c, 4),
+    ("reset flag", 0x72, 2),
+    ("hard disks", 0x75, 1),
+];
+
+fn bda_field(mem: &[u8], offset: usize, width: usize) -> u32 {
+    let pa = BDA + offset;
+    match width {
+        1 => mem[pa].into(),
+        2 => u16_at(mem, pa).into(),
+        _ => u32_at(mem, pa),
+    }
+}
+
+/// Returns the address of the EBDA, if the BDA points to a
+/// plausible one.
+fn ebda(mem: &[u8]) -> Option<usize> {
+    let addr = usize::from(u16_at(mem, BDA + 0x0e)) << 4;
+    EBDA_RANGE.contains(&addr).then_some(addr)
+}
+
+/// A firmware table signature, the length of the portion of the
+/// table covered by its checksum, and where that length is
+/// found if it is not fixed.
+struct Table {
+    name: &'static str,
+    sig: &'static [u8],
+    len: fn(&[u8]) -> usize,
+}
+
+const TABLES: &[Table] = &[
+    Table { name: "ACPI RSDP", sig: b"RSD PTR ", len: |_| 20 },
+    Table {
+        name: "MP floating pointer",
+        sig: b"_MP_",
+        len: |t| 16 * usize::from(t[8]),
+    },
+    Table {
+        name: "SMBIOS 2 entry point",
+        sig: b"_SM_",
+        len: |t| usize::from(t[5]),
+    },
+    Table {
+        name: "SMBIOS 3 entry point",
+        sig: b"_SM3_",
+        len: |t| usize::from(t[6]),
+    },
+];
+
+/// Scans the given range of low memory for firmware table
+/// signatures on 16 byte boundaries, returning the address of
+/// each, the table, and whether its checksum is valid.
+fn tables(
+    mem: &[u8],
+    range: Range<usize>,
+) -> Vec<(usize, &'static Table, bool)> {
+    let mut found = Vec::new();
+    for pa in range.step_by(16) {
+        for table in TABLES {
+            if mem[pa..].starts_with(table.sig) {
+                let len = (table.len)(&mem[pa..]);
+                let ok = len != 0
+                    && pa + len <= mem.len()
+                    && checksum_ok(&mem[pa..pa + len]);
+                found.push((pa, table, ok));
+            }
+        }
+    }
+    found
+}
+
+/// An option ROM found in low memory.
+#[derive(Debug, Eq, PartialEq)]
+struct Rom {
+    addr: usize,
+    len: usize,
+    checksum_ok: bool,
+    pci: Option<(u16, u16, u32)>,
+}
+
+/// Scans for option ROMs, returning the address, length, and
+/// checksum status of each, along with the vendor, device and
+/// class code from its PCI data structure, if present.
+fn roms(mem: &[u8]) -> Vec<Rom> {
+    let mut roms = Vec::new();
+    let mut pa = ROM_RANGE.start;
+    while pa < ROM_RANGE.end {
+        if mem[pa..].starts_with(&[0x55, 0xaa]) {
+            let len = usize::from(mem[pa + 2]) * 512;
+            let end = usize::min(pa + len, mem.len());
+            let pcir = pa + usize::from(u16_at(mem, pa + 0x18));
+            let pci = (pcir + 16 <= end && mem[pcir..].starts_with(b"PCIR"))
+                .then(|| {
+                    let vendor = u16_at(mem, pcir + 4);
+                    let device = u16_at(mem, pcir + 6);
+                    let class = u32_at(mem, pcir + 0x0c) >> 8;
+                    (vendor, device, class)
+                });
+            roms.push(Rom {
+                addr: pa,
+                len,
+                checksum_ok: len != 0 && checksum_ok(&mem[pa..end]),
+                pci,
+            });
+            pa += len.next_multiple_of(ROM_ALIGN).max(ROM_ALIGN);
+        } else {
+            pa += ROM_ALIGN;
+        }
+    }
+    roms
+}
+
+fn map(config: &mut bldb::Config) -> Result<Value> {
+    unsafe {
+        config.page_table.map_region(
+            range(),
+            mem::Attrs::new_rodata(),
+            mem::P4KA::new(0),
+        )?;
+    }
+    println!("low memory mapped read-only at {LOWMEM_BASE:#x}");
+    Ok(Value::Pair(LOWMEM_BASE, LOWMEM_LEN))
+}
+
+fn unmap(config: &mut bldb::Config) -> Result<Value> {
+    unsafe {
+        config.page_table.unmap_range(range())?;
+    }
+    Ok(Value::Nil)
+}
+
+fn show_ivt(mem: &[u8]) -> Result<Value> {
+    for (first, last, v) in runs(&ivt(mem)) {
+        let (seg, off) = ((v >> 16) as u16, v as u16);
+        let vecs = if first == last {
+            alloc::format!("{first:02x}")
+        } else {
+            alloc::format!("{first:02x}-{last:02x}")
+        };
+        println!("{vecs:<5} {seg:04x}:{off:04x} ({:#07x})", linear(seg, off));
+    }
+    Ok(Value::Nil)
+}
+
+fn show_bda(mem: &[u8]) -> Result<Value> {
+    for &(name, offset, width) in BDA_FIELDS {
+        let value = bda_field(mem, offset, width);
+        println!(
+            "{:#05x} {name:<16} {value:#0w$x}",
+            BDA + offset,
+            w = 2 * width + 2
+        );
+    }
+    Ok(Value::Nil)
+}
+
+fn show_ebda(mem: &[u8]) -> Result<Value> {
+    let Some(addr) = ebda(mem) else {
+        let seg = u16_at(mem, BDA + 0x0e);
+        println!("no EBDA (segment {seg:#06x})");
+        return Ok(Value::Nil);
+    };
+    let len = usize::from(mem[addr]) * 1024;
+    println!("EBDA at {addr:#07x}, {len:#x} bytes");
+    Ok(Value::Pair(LOWMEM_BASE + addr, len))
+}
+
+fn show_tables(mem: &[u8]) -> Result<Value> {
+    let mut found = Vec::new();
+    if let Some(addr) = ebda(mem) {
+        found.extend(tables(mem, addr..addr + 1024));
+    }
+    found.extend(tables(mem, BIOS_RANGE));
+    if found.is_empty() {
+        println!("no firmware tables found");
+    }
+    for (addr, table, ok) in found {
+        let checksum = if ok { "ok" } else { "bad checksum" };
+        println!("{addr:#07x} {} ({checksum})", table.name);
+    }
+    Ok(Value::Nil)
+}
+
+fn show_roms(mem: &[u8]) -> Result<Value> {
+    let roms = roms(mem);
+    if roms.is_empty() {
+        println!("no option ROMs found");
+    }
+    for rom in roms {
+        let checksum = if rom.checksum_ok { "ok" } else { "bad checksum" };
+        print!("{:#07x} {:#7x} bytes ({checksum})", rom.addr, rom.len);
+        match rom.pci {
+            Some((vendor, device, class)) => {
+                println!(" PCI {vendor:04x}:{device:04x} class {class:06x}")
+            }
+            None => println!(),
+        }
+    }
+    Ok(Value::Nil)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: lowmem map | unmap | ivt | bda | ebda | tables | roms"
+        );
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = arg.as_string().map_err(usage)?;
+    match cmd.as_str() {
+        "map" => map(config),
+        "unmap" => unmap(config),
+        "ivt" => show_ivt(lowmem(config)?),
+        "bda" => show_bda(lowmem(config)?),
+        "ebda" => show_ebda(lowmem(config)?),
+        "tables" => show_tables(lowmem(config)?),
+        "roms" => show_roms(lowmem(config)?),
+        _ => Err(usage(arg.bad_arg("lowmem subcommand"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Vec<u8> {
+        vec![0u8; LOWMEM_LEN]
+    }
+
+    #[test]
+    fn vectors() {
+        let mut mem = image();
+        for k in 0..256 {
+            mem[k * 4..k * 4 + 4].copy_from_slice(&[0x53, 0xff, 0x00, 0xf0]);
+        }
+        mem[0x20..0x24].copy_from_slice(&[0xa5, 0xfe, 0x00, 0xf0]);
+        let runs = runs(&ivt(&mem));
+        assert_eq!(
+            runs,
+            [(0, 7, 0xf000_ff53), (8, 8, 0xf000_fea5), (9, 255, 0xf000_ff53)]
+        );
+        assert_eq!(linear(0xf000, 0xfea5), 0xffea5);
+    }
+
+    #[test]
+    fn bios_data_area() {
+        let mut mem = image();
+        mem[0x400..0x402].copy_from_slice(&0x3f8u16.to_le_bytes());
+        mem[0x40e..0x410].copy_from_slice(&0x9fc0u16.to_le_bytes());
+        mem[0x46c..0x470].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        assert_eq!(bda_field(&mem, 0x00, 2), 0x3f8);
+        assert_eq!(bda_field(&mem, 0x6c, 4), 0x1234_5678);
+        assert_eq!(ebda(&mem), Some(0x9_fc00));
+        mem[0x40e..0x410].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(ebda(&mem), None);
+    }
+
+    #[test]
+    fn firmware_tables() {
+        let mut mem = image();
+        let mut rsdp = *b"RSD PTR \0OEMID \0\0\0\0\0";
+        rsdp[8] =
+            0u8.wrapping_sub(rsdp.iter().fold(0u8, |s, &b| s.wrapping_add(b)));
+        mem[0xf_6a40..0xf_6a40 + 20].copy_from_slice(&rsdp);
+        mem[0xf_0010..0xf_0014].copy_from_slice(b"_SM_");
+        mem[0xf_0015] = 0x1f;
+        let found = tables(&mem, BIOS_RANGE);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            (found[0].0, found[0].1.name, found[0].2),
+            (0xf_0010, "SMBIOS 2 entry point", false)
+        );
+        assert_eq!(
+            (found[1].0, found[1].1.name, found[1].2),
+            (0xf_6a40, "ACPI RSDP", true)
+        );
+    }
+
+    #[test]
+    fn option_roms() {
+        let mut mem = image();
+        let rom = 0xc_0000;
+        mem[rom..rom + 3].copy_from_slice(&[0x55, 0xaa, 8]);
+        mem[rom + 0x18..rom + 0x1a].copy_from_slice(&0x40u16.to_le_bytes());
+        let pcir = rom + 0x40;
+        mem[pcir..pcir + 4].copy_from_slice(b"PCIR");
+        mem[pcir + 4..pcir + 8].copy_from_slice(&[0x02, 0x10, 0x3f, 0x74]);
+        mem[pcir + 0x0d..pcir + 0x10].copy_from_slice(&[0x00, 0x00, 0x03]);
+        let sum =
+            mem[rom..rom + 4096].iter().fold(0u8, |s, &b| s.wrapping_add(b));
+        mem[rom + 4095] = 0u8.wrapping_sub(sum);
+        mem[0xc_8000..0xc_8003].copy_from_slice(&[0x55, 0xaa, 2]);
+        let roms = roms(&mem);
+        assert_eq!(
+            roms,
+            [
+                Rom {
+                    addr: rom,
+                    len: 4096,
+                    checksum_ok: true,
+                    pci: Some((0x1002, 0x743f, 0x03_0000))
+                },
+                Rom {
+                    addr: 0xc_8000,
+                    len: 1024,
+                    checksum_ok: false,
+                    pci: None
+                },
+            ]
+        );
+    }
+}
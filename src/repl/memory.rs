@@ -5,14 +5,19 @@
 //! Simple hex dump routine.
 
 use crate::bldb;
+use crate::clock;
+use crate::cons;
 use crate::io::Read;
 use crate::mem;
-use crate::repl::{self, Value};
+use crate::ramdisk;
+use crate::regdefs::{self, Space};
+use crate::repl::{self, Access, Value, confirm, numfmt};
 use crate::result::{Error, Result};
 use crate::{print, println};
 use alloc::vec::Vec;
 use core::ptr;
 use core::slice;
+use core::time::Duration;
 
 fn hexdump<T: Read + ?Sized>(mut addr: usize, src: &T) -> Result<()> {
     println!(
@@ -57,8 +62,13 @@ fn hexdump<T: Read + ?Sized>(mut addr: usize, src: &T) -> Result<()> {
 }
 
 fn xdfile(config: &bldb::Config, path: &str) -> Result<()> {
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
-    let file = fs.open(path)?;
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        path,
+    )?;
+    let file = fs.open(&path)?;
     hexdump(0, file.as_ref())
 }
 
@@ -87,8 +97,9 @@ impl Read for PtrLenPair {
 }
 
 unsafe fn xdmem(config: &bldb::Config, arg: Value) -> Result<()> {
-    let (ptr, len) =
-        arg.as_ptr_len().and_then(|(ptr, len)| check_pair(config, ptr, len))?;
+    let (ptr, len) = arg
+        .as_ptr_len(&config.page_table)
+        .and_then(|(ptr, len)| check_pair(config, ptr, len))?;
     let pair = PtrLenPair(ptr, len);
     let addr = ptr.addr();
     hexdump(addr, &pair)
@@ -158,7 +169,7 @@ fn parse_peek_poke_pair(
     value: Value,
 ) -> Result<(*const u8, usize)> {
     value
-        .as_ptr_len()
+        .as_ptr_len(&config.page_table)
         .and_then(|(ptr, len)| check_pair(config, ptr, len))
         .and_then(|(ptr, len)| {
             if check_size(len) { Ok((ptr, len)) } else { Err(Error::BadArgs) }
@@ -177,13 +188,46 @@ fn parse_peek_poke_pair_mut(
         })
 }
 
+/// Checks an access against the definition of the register at
+/// its address in the given space, if there is one.  An access
+/// of a width the register does not accept is refused unless
+/// forced; one with a side effect draws a warning.
+pub(super) fn guard(
+    space: Space,
+    addr: u64,
+    len: usize,
+    write: bool,
+    force: bool,
+) -> Result<()> {
+    let Some(def) = regdefs::find(space, addr, len) else {
+        return Ok(());
+    };
+    if !def.accepts(addr, len) {
+        let sizes = def.access.as_ref().map_or(&[][..], |a| a.sizes);
+        println!(
+            "{}: {len}-byte access at {addr:#x}; the register \
+             accepts only {sizes:?}-byte accesses at its base",
+            def.name
+        );
+        if !force {
+            return Err(Error::AccessWidth);
+        }
+    }
+    if let Some(effect) = def.side_effect(write) {
+        println!("warning: {}: {effect}", def.name);
+    }
+    Ok(())
+}
+
 pub fn read(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: peek <addr>,<len>");
+        println!("usage: peek [-f] <addr>,<len>");
         error
     };
+    let force = confirm::take_force(env);
     let (ptr, len) =
         parse_peek_poke_pair(config, repl::popenv(env)).map_err(usage)?;
+    guard(Space::Mmio, ptr.addr() as u64, len, false, force)?;
     let value = match len {
         1 => unsafe { ptr::read::<u8>(ptr).into() },
         2 => unsafe { ptr::read_unaligned::<u16>(ptr.cast()).into() },
@@ -192,35 +236,526 @@ pub fn read(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
         16 => unsafe { ptr::read_unaligned::<u128>(ptr.cast()) },
         _ => panic!("impossible length value"),
     };
-    println!("{ptr:p} {value:#0pad$x}", pad = 2 * len);
+    let value = numfmt::order(value, len);
+    config.access_log.record(Access::Mmio {
+        addr: ptr.addr() as u64,
+        len: len as u8,
+        value,
+        write: false,
+    });
+    println!("{ptr:p} {}", numfmt::sized(value, len));
     Ok(Value::Unsigned(value))
 }
 
 pub fn write(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: poke <addr>,<len> <value>");
+        println!("usage: poke [-f] <addr>,<len> <value>");
         error
     };
+    let force = confirm::take_force(env);
     let (ptr, len) =
         parse_peek_poke_pair_mut(config, repl::popenv(env)).map_err(usage)?;
-    let val = repl::popenv(env);
+    guard(Space::Mmio, ptr.addr() as u64, len, true, force)?;
+    let value = repl::popenv(env).as_num::<u128>()?;
+    if !fits(len, value) {
+        return Err(Error::NumRange);
+    }
+    config.access_log.record(Access::Mmio {
+        addr: ptr.addr() as u64,
+        len: len as u8,
+        value,
+        write: true,
+    });
+    let value = numfmt::order(value, len);
     match len {
         1 => unsafe {
-            ptr::write(ptr, val.as_num::<u8>()?);
+            ptr::write(ptr, value as u8);
         },
         2 => unsafe {
-            ptr::write_unaligned(ptr.cast(), val.as_num::<u16>()?);
+            ptr::write_unaligned(ptr.cast(), value as u16);
         },
         4 => unsafe {
-            ptr::write_unaligned(ptr.cast(), val.as_num::<u32>()?);
+            ptr::write_unaligned(ptr.cast(), value as u32);
         },
         8 => unsafe {
-            ptr::write_unaligned(ptr.cast(), val.as_num::<u64>()?);
+            ptr::write_unaligned(ptr.cast(), value as u64);
         },
         16 => unsafe {
-            ptr::write_unaligned(ptr.cast(), val.as_num::<u128>()?);
+            ptr::write_unaligned(ptr.cast(), value);
         },
         _ => panic!("impossible length value"),
     }
     Ok(Value::Nil)
 }
+
+/// How long to wait before retrying a write that did not stick.
+const RETRY_DELAY: Duration = Duration::from_millis(1);
+
+/// Returns true iff the value fits in `len` bytes.
+fn fits(len: usize, value: u128) -> bool {
+    len >= 16 || value >> (8 * len) == 0
+}
+
+/// Reads a `len`-byte value with a single volatile access.
+///
+/// # Safety
+/// The pointer must be valid for reads and aligned to `len`.
+unsafe fn read_volatile(ptr: *const u8, len: usize) -> u128 {
+    unsafe {
+        match len {
+            1 => ptr::read_volatile(ptr).into(),
+            2 => ptr::read_volatile::<u16>(ptr.cast()).into(),
+            4 => ptr::read_volatile::<u32>(ptr.cast()).into(),
+            8 => ptr::read_volatile::<u64>(ptr.cast()).into(),
+            16 => ptr::read_volatile::<u128>(ptr.cast()),
+            _ => panic!("impossible length value"),
+        }
+    }
+}
+
+/// Writes a `len`-byte value with a single volatile access.
+///
+/// # Safety
+/// The pointer must be valid for writes and aligned to `len`,
+/// and the value must fit in `len` bytes.
+unsafe fn write_volatile(ptr: *mut u8, len: usize, value: u128) {
+    unsafe {
+        match len {
+            1 => ptr::write_volatile(ptr, value as u8),
+            2 => ptr::write_volatile(ptr.cast(), value as u16),
+            4 => ptr::write_volatile(ptr.cast(), value as u32),
+            8 => ptr::write_volatile(ptr.cast(), value as u64),
+            16 => ptr::write_volatile(ptr.cast(), value),
+            _ => panic!("impossible length value"),
+        }
+    }
+}
+
+pub fn write_verify(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: pokev [-f] <addr>,<len> <value> [<retries>]");
+        error
+    };
+    let force = confirm::take_force(env);
+    let (ptr, len) =
+        parse_peek_poke_pair_mut(config, repl::popenv(env)).map_err(usage)?;
+    if ptr.addr() % len != 0 {
+        return Err(usage(Error::PtrAlign));
+    }
+    guard(Space::Mmio, ptr.addr() as u64, len, true, force)?;
+    let value = repl::popenv(env).as_num::<u128>().map_err(usage)?;
+    if !fits(len, value) {
+        return Err(usage(Error::NumRange));
+    }
+    let retries = match repl::popenv(env) {
+        Value::Nil => 0,
+        v => v.as_num::<u32>().map_err(usage)?,
+    };
+    for attempt in 0..=retries {
+        if attempt != 0 {
+            clock::delay(RETRY_DELAY);
+        }
+        let readback = unsafe {
+            write_volatile(ptr, len, numfmt::order(value, len));
+            numfmt::order(read_volatile(ptr, len), len)
+        };
+        let addr = ptr.addr() as u64;
+        for (value, write) in [(value, true), (readback, false)] {
+            let len = len as u8;
+            config.access_log.record(Access::Mmio { addr, len, value, write });
+        }
+        let (shown, read) =
+            (numfmt::sized(value, len), numfmt::sized(readback, len));
+        if readback == value {
+            println!("{ptr:p} {shown} ok after {} writes", attempt + 1);
+            return Ok(Value::Unsigned(readback));
+        }
+        println!(
+            "{ptr:p} wrote {shown} read {read} (differs in {})",
+            numfmt::sized(value ^ readback, len)
+        );
+    }
+    Err(Error::Verify)
+}
+
+/// Decodes the bytes of a pattern from the arguments to `pokepat`.
+/// Strings are taken as hex digits, written in the order given;
+/// numbers are written as their big-endian bytes, without leading
+/// zeros.  The bytes from each argument are concatenated.
+pub(super) fn parse_pattern(args: &[Value]) -> Result<Vec<u8>> {
+    let mut pattern = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Str(s) => {
+                let digits = s.strip_prefix("0x").unwrap_or(s);
+                let digits = digits
+                    .chars()
+                    .filter(|&c| c != '_')
+                    .map(|c| c.to_digit(16).map(|d| d as u8))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| arg.bad_arg("hex bytes"))?;
+                if digits.is_empty() || digits.len() % 2 != 0 {
+                    return Err(arg.bad_arg("an even number of hex digits"));
+                }
+                pattern.extend(digits.chunks(2).map(|d| d[0] << 4 | d[1]));
+            }
+            Value::Unsigned(n) => {
+                let bytes = n.to_be_bytes();
+                let skip = (n.leading_zeros() / 8).min(15) as usize;
+                pattern.extend_from_slice(&bytes[skip..]);
+            }
+            _ => return Err(arg.bad_arg("hex bytes or a number")),
+        }
+    }
+    if pattern.is_empty() {
+        return Err(Error::BadArgs);
+    }
+    Ok(pattern)
+}
+
+/// Fills `dst` with repeated copies of `pattern`, truncating the
+/// last copy if need be.
+fn fill_pattern(dst: &mut [u8], pattern: &[u8]) {
+    for chunk in dst.chunks_mut(pattern.len()) {
+        chunk.copy_from_slice(&pattern[..chunk.len()]);
+    }
+}
+
+pub fn write_pattern(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: pokepat <addr>,<len> <pattern bytes>...");
+        error
+    };
+    let region = repl::popenv(env);
+    let Value::Pair(addr, len) = region else {
+        return Err(usage(region.bad_arg("<addr>,<len>")));
+    };
+    let mut args = Vec::new();
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            arg => args.push(arg),
+        }
+    }
+    let pattern = parse_pattern(&args).map_err(usage)?;
+    let dst = region
+        .as_slice_mut(&config.page_table, 0)
+        .map_err(usage)?
+        .ok_or(Error::BadArgs)?;
+    fill_pattern(dst, &pattern);
+    println!(
+        "wrote {len:#x} bytes at {addr:#x} ({} byte pattern)",
+        pattern.len()
+    );
+    Ok(Value::Pair(addr, len))
+}
+
+/// The size of an entry in an in-memory table of registers for
+/// `peekmany`: a 64-bit address followed by a 64-bit width.
+const TABLE_ENTRY_LEN: usize = 16;
+
+/// Decodes an in-memory table of address and width pairs.
+fn parse_table(bs: &[u8]) -> Result<Vec<(usize, usize)>> {
+    if !bs.len().is_multiple_of(TABLE_ENTRY_LEN) {
+        return Err(Error::BadArgs);
+    }
+    bs.chunks_exact(TABLE_ENTRY_LEN)
+        .map(|entry| {
+            let (addr, width) = entry.split_at(8);
+            let addr = u64::from_le_bytes(addr.try_into().unwrap());
+            let width = u64::from_le_bytes(width.try_into().unwrap());
+            let addr = usize::try_from(addr).map_err(|_| Error::NumRange)?;
+            let width = usize::try_from(width).map_err(|_| Error::NumRange)?;
+            Ok((addr, width))
+        })
+        .collect()
+}
+
+/// Validates a register to be read by `peekmany`.
+fn check_target(
+    config: &bldb::Config,
+    addr: usize,
+    len: usize,
+) -> Result<(*const u8, usize)> {
+    let value = Value::Pair(addr, len);
+    let (ptr, len) = parse_peek_poke_pair(config, value)?;
+    if ptr.addr() % len != 0 {
+        return Err(Error::PtrAlign);
+    }
+    guard(Space::Mmio, addr as u64, len, false, false)?;
+    Ok((ptr, len))
+}
+
+pub fn read_many(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: peekmany [-n <count>] [-i <interval ms>] \
+             <addr>,<len>... | -t <addr>,<len>"
+        );
+        error
+    };
+    let mut count = 1;
+    let mut interval = Duration::from_millis(1000);
+    let mut pairs = Vec::new();
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Pair(addr, len) => pairs.push((addr, len)),
+            Value::List(items) => {
+                for item in items {
+                    let (addr, len) = item.as_pair().map_err(usage)?;
+                    pairs.push((addr as usize, len));
+                }
+            }
+            arg => match arg.as_string().map_err(usage)?.as_str() {
+                "-n" => {
+                    count = repl::popenv(env).as_num::<u64>().map_err(usage)?
+                }
+                "-i" => {
+                    let ms =
+                        repl::popenv(env).as_num::<u64>().map_err(usage)?;
+                    interval = Duration::from_millis(ms);
+                }
+                "-t" => {
+                    let table = repl::popenv(env)
+                        .as_slice(&config.page_table, 0)
+                        .and_then(|o| o.ok_or(Error::BadArgs))
+                        .and_then(parse_table)
+                        .map_err(usage)?;
+                    pairs.extend(table);
+                }
+                _ => return Err(usage(arg.bad_arg("-n, -i, -t, or pair"))),
+            },
+        }
+    }
+    if pairs.is_empty() {
+        return Err(usage(Error::BadArgs));
+    }
+    let targets = pairs
+        .into_iter()
+        .map(|(addr, len)| check_target(config, addr, len))
+        .collect::<Result<Vec<_>>>()
+        .map_err(usage)?;
+    let mut values = alloc::vec![0u128; targets.len()];
+    for sample in 0..count {
+        if sample != 0 {
+            match config.cons.wait_data_ready(interval) {
+                Ok(false) => {}
+                Ok(true) => {
+                    let _ = config.cons.try_getb();
+                    break;
+                }
+                Err(e) if e.is(Error::UartBreak) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        // Read everything before printing anything, so that the
+        // snapshot is as close to coherent as we can make it.
+        for (value, &(ptr, len)) in values.iter_mut().zip(targets.iter()) {
+            *value = numfmt::order(unsafe { read_volatile(ptr, len) }, len);
+        }
+        if count > 1 {
+            println!("sample {sample}:");
+        }
+        for (&value, &(ptr, len)) in values.iter().zip(targets.iter()) {
+            let value = numfmt::sized(value, len);
+            println!("{ptr:>18p} {len:>2} {value:>34}");
+        }
+    }
+    Ok(Value::List(values.into_iter().map(Value::Unsigned).collect()))
+}
+
+/// How many differing bytes `memcmp` shows by default.
+const DEFAULT_SHOWN: usize = 16;
+
+/// How much memory to compare between polls for cancellation.
+const COMPARE_CHUNK_LEN: usize = 1024 * 1024;
+
+/// Calls `f` with the offset and values of each byte that
+/// differs between the two slices, over the length of the
+/// shorter, polling for cancellation as it goes.  Returns the
+/// number of bytes that differ.
+fn compare(
+    a: &[u8],
+    b: &[u8],
+    poll: &mut dyn FnMut() -> Result<()>,
+    mut f: impl FnMut(usize, u8, u8),
+) -> Result<usize> {
+    let mut ndiffs = 0;
+    let chunks = a.chunks(COMPARE_CHUNK_LEN).zip(b.chunks(COMPARE_CHUNK_LEN));
+    for (k, (ca, cb)) in chunks.enumerate() {
+        poll()?;
+        if ca == cb {
+            continue;
+        }
+        let base = k * COMPARE_CHUNK_LEN;
+        for (offset, (&x, &y)) in ca.iter().zip(cb).enumerate() {
+            if x != y {
+                f(base + offset, x, y);
+                ndiffs += 1;
+            }
+        }
+    }
+    Ok(ndiffs)
+}
+
+pub fn compare_regions(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: memcmp [-n <count>] <addr>,<len> <addr>,<len>");
+        error
+    };
+    let mut count = DEFAULT_SHOWN;
+    let mut arg = repl::popenv(env);
+    if arg.as_string().is_ok_and(|flag| flag == "-n") {
+        count = repl::popenv(env).as_num::<usize>().map_err(usage)?;
+        arg = repl::popenv(env);
+    }
+    let region = |arg: Value| {
+        arg.as_slice(&config.page_table, 0)
+            .and_then(|o| o.ok_or(Error::BadArgs))
+            .map_err(usage)
+    };
+    let a = region(arg)?;
+    let b = region(repl::popenv(env))?;
+    if a.len() != b.len() {
+        println!(
+            "memcmp: lengths differ; comparing the first {:#x} bytes",
+            a.len().min(b.len())
+        );
+    }
+    let mut poll = cons::poller(&mut config.cons);
+    let mut shown = 0;
+    let ndiffs = compare(a, b, &mut poll, |offset, x, y| {
+        if shown < count {
+            println!("+{offset:#x}: {x:02x} {y:02x}");
+            shown += 1;
+        }
+    })?;
+    match ndiffs {
+        0 => println!("memcmp: regions match"),
+        n if n > shown => {
+            println!("memcmp: {n} bytes differ ({} not shown)", n - shown)
+        }
+        n => println!("memcmp: {n} bytes differ"),
+    }
+    Ok(Value::Unsigned(ndiffs as u128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use alloc::format;
+
+    #[test]
+    fn sizes() {
+        assert!(fits(1, 0xff));
+        assert!(!fits(1, 0x100));
+        assert!(fits(4, 0xffff_ffff));
+        assert!(!fits(8, 1 << 64));
+        assert!(fits(16, u128::MAX));
+        let mut word = 0u64;
+        let p = (&raw mut word).cast::<u8>();
+        let readback = unsafe {
+            write_volatile(p, 8, 0x1122_3344_5566_7788);
+            read_volatile(p, 4)
+        };
+        assert_eq!(readback, 0x5566_7788);
+    }
+
+    #[test]
+    fn tables() {
+        let mut table = Vec::new();
+        for (addr, width) in [(0xfed8_0300u64, 4u64), (0x1000, 8)] {
+            table.extend_from_slice(&addr.to_le_bytes());
+            table.extend_from_slice(&width.to_le_bytes());
+        }
+        assert_eq!(
+            parse_table(&table).unwrap(),
+            [(0xfed8_0300, 4), (0x1000, 8)]
+        );
+        assert!(parse_table(&table[..20]).is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        let args = [Value::Str("deadbeef".into())];
+        assert_eq!(parse_pattern(&args).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        let args = [Value::Unsigned(0xdead_beef)];
+        assert_eq!(parse_pattern(&args).unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        let args = [
+            Value::Unsigned(0),
+            Value::Str("ca_fe".into()),
+            Value::Unsigned(0x12),
+        ];
+        assert_eq!(parse_pattern(&args).unwrap(), [0, 0xca, 0xfe, 0x12]);
+        assert!(parse_pattern(&[Value::Str("abc".into())]).is_err());
+        assert!(parse_pattern(&[Value::Str("xy".into())]).is_err());
+        assert!(parse_pattern(&[]).is_err());
+
+        let mut buf = [0u8; 7];
+        fill_pattern(&mut buf, &[1, 2, 3]);
+        assert_eq!(buf, [1, 2, 3, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let a = alloc::vec![0u8; COMPARE_CHUNK_LEN + 8];
+        let mut b = a.clone();
+        b[3] = 0x33;
+        b[COMPARE_CHUNK_LEN + 1] = 0x44;
+        let mut diffs = Vec::new();
+        let mut polls = 0;
+        let mut poll = || {
+            polls += 1;
+            Ok(())
+        };
+        let n = compare(&a, &b, &mut poll, |offset, x, y| {
+            diffs.push((offset, x, y))
+        });
+        assert_eq!(n, Ok(2));
+        assert_eq!(diffs, [(3, 0, 0x33), (COMPARE_CHUNK_LEN + 1, 0, 0x44)]);
+        assert_eq!(polls, 2);
+
+        let n = compare(&a[..4], &b, &mut || Ok(()), |_, _, _| {});
+        assert_eq!(n, Ok(1));
+        let n = compare(&a, &b, &mut || Err(Error::Cancelled), |_, _, _| {});
+        assert_eq!(n, Err(Error::Cancelled));
+    }
+
+    #[test]
+    fn memcmp() {
+        let mut sim = Sim::new();
+        let addr = sim.ram().as_ptr().addr();
+        sim.ram()[0x100..0x120].fill(0x5a);
+        sim.ram()[0x200..0x220].fill(0x5a);
+        let (a, b) = (addr + 0x100, addr + 0x200);
+        let out = sim.session(&format!("memcmp {a:#x},0x20 {b:#x},0x20\n"));
+        assert!(out.contains("regions match"), "{out}");
+        assert!(out.ends_with("res: 0x0\n"), "{out}");
+        sim.ram()[0x204] = 0x11;
+        sim.ram()[0x210..0x213].fill(0);
+        let out =
+            sim.session(&format!("memcmp -n 2 {a:#x},0x20 {b:#x},0x20\n"));
+        assert!(out.contains("+0x4: 5a 11\n+0x10: 5a 00\n"), "{out}");
+        assert!(out.contains("4 bytes differ (2 not shown)"), "{out}");
+        assert!(out.ends_with("res: 0x4\n"), "{out}");
+        let out = sim.session(&format!("memcmp {a:#x},0x8 {b:#x},0x20\n"));
+        assert!(out.contains("comparing the first 0x8 bytes"), "{out}");
+        assert!(out.ends_with("res: 0x1\n"), "{out}");
+        let out = sim.session(&format!("memcmp {a:#x},0x8\n"));
+        assert!(out.contains("usage: memcmp"), "{out}");
+    }
+}
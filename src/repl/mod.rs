@@ -3,10 +3,12 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::bldb;
+use crate::idt;
 use crate::mem;
 use crate::mmu;
 use crate::println;
-use crate::result::{Error, Result};
+use crate::result::{BadArg, Error, Result};
+use crate::sink;
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::convert::TryFrom;
@@ -14,41 +16,120 @@ use core::fmt;
 use core::ptr;
 use core::slice;
 
+mod addr;
+mod alias;
+mod audit;
+mod baud;
+mod beacon;
+#[cfg(feature = "cmd-bench")]
+mod bench;
+#[cfg(feature = "cmd-debugger")]
 mod bits;
+mod boot;
+mod bootenv;
+mod bootinfo;
 mod call;
 mod cat;
+mod commands;
+mod complete;
+mod confirm;
 mod copy;
 mod cpuid;
+mod dd;
+mod debug;
+mod deflate;
+mod dis;
+#[cfg(feature = "cmd-debugger")]
+mod dtables;
+mod dump;
+#[cfg(feature = "cmd-files")]
+mod each;
+#[cfg(feature = "cmd-hw")]
 mod ecam;
 mod elfinfo;
+#[cfg(feature = "cmd-files")]
+mod fgrep;
+#[cfg(feature = "cmd-hw")]
 mod gpio;
+mod handoff;
+mod idle;
 mod inflate;
 mod iomux;
+#[cfg(feature = "cmd-debugger")]
 mod jfmt;
+mod layout;
 mod list;
 mod load;
+mod log;
+#[cfg(feature = "cmd-debugger")]
+mod lowmem;
 mod memory;
+#[cfg(feature = "cmd-files")]
+mod more;
 mod mount;
 mod msr;
+mod numfmt;
+mod pcr;
+#[cfg(feature = "cmd-hw")]
+mod physmap;
+#[cfg(feature = "cmd-hw")]
 mod pio;
+#[cfg(feature = "cmd-bench")]
+mod probe;
+mod progress;
 mod prompt;
+mod random;
 mod reader;
+mod region;
+mod replay;
+mod results;
+mod rraw;
 mod rx;
+mod ry;
 mod rz;
+mod search;
 mod sha;
+#[cfg(test)]
+mod sim;
+mod sinks;
 mod smn;
+mod snapshot;
+mod sp;
+mod stack;
+mod state;
+mod syms;
+mod sysregs;
+mod sz;
+#[cfg(feature = "cmd-bench")]
+mod telemetry;
+mod transcript;
+#[cfg(feature = "cmd-hw")]
+mod uartline;
 mod vm;
+mod watch;
+mod watchdog;
+mod write;
+mod xferport;
+
+pub(crate) use audit::{Access, AccessLog};
+pub(crate) use debug::trap as debug_trap;
+pub(crate) use dump::Dumps;
+pub(crate) use idle::Idle;
+pub(crate) use prompt::Segment;
+pub(crate) use results::Results;
+pub(crate) use rz::RzStats;
+pub(crate) use state::Modified;
 
 pub const DEF_ALIASES: &[(&str, &str)] = &[(
     "zoxboot",
-    "call . load /platform/oxide/kernel/amd64/unix . mount . @inflate . rz",
+    "call . load /platform/oxide/kernel/amd64/unix . mount . @inflate -r . rz",
 )];
 
 #[derive(Clone)]
 #[allow(dead_code)]
 enum Value {
     Nil,
-    Slice(&'static [u8]),
+    Slice(mmu::Buf),
     Pair(usize, usize),
     Unsigned(u128),
     Pointer(*mut u8),
@@ -56,6 +137,7 @@ enum Value {
     Cmd(String),
     Sha256([u8; 32]),
     CpuIdResult(x86::cpuid::CpuIdResult),
+    List(Vec<Value>),
 }
 
 fn unsigned_to_ptr<F, T>(addr: F) -> Result<*const T>
@@ -81,6 +163,12 @@ where
 }
 
 impl Value {
+    /// Returns an error describing this value as a bad argument
+    /// where the given kind of value was expected.
+    fn bad_arg(&self, expected: &'static str) -> Error {
+        Error::BadArg(BadArg::new(expected, self))
+    }
+
     pub fn as_slice(
         &self,
         page_table: &mmu::LoaderPageTable,
@@ -88,11 +176,11 @@ impl Value {
     ) -> Result<Option<&'static [u8]>> {
         let (ptr, len) = match self {
             Value::Nil => return Ok(None),
-            Value::Slice(slice) => return Ok(Some(*slice)),
+            Value::Slice(buf) => return page_table.resolve(buf).map(Some),
             Value::Pair(addr, len) => Ok((unsigned_to_ptr(*addr)?, *len)),
             Value::Unsigned(addr) => Ok((unsigned_to_ptr(*addr)?, deflen)),
             Value::Pointer(ptr) => Ok((ptr.cast_const(), deflen)),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("<addr>,<len> or slice")),
         }?;
         if page_table.is_region_readable(mem::page_range_raw(ptr.cast(), len)) {
             Ok(Some(unsafe { slice::from_raw_parts(ptr, len) }))
@@ -105,19 +193,32 @@ impl Value {
         &self,
         page_table: &mmu::LoaderPageTable,
         deflen: usize,
+    ) -> Result<Option<&'static mut [u8]>> {
+        let mut dst = self.as_slice_mut_unzeroed(page_table, deflen)?;
+        if let Some(dst) = dst.as_deref_mut() {
+            dst.fill(0);
+        }
+        Ok(dst)
+    }
+
+    /// Returns the writable memory, as `as_slice_mut` does, but
+    /// leaves its contents alone, for commands that must look at
+    /// them first, or that write to device registers.
+    pub fn as_slice_mut_unzeroed(
+        &self,
+        page_table: &mmu::LoaderPageTable,
+        deflen: usize,
     ) -> Result<Option<&'static mut [u8]>> {
         let (ptr, len) = match self {
             Value::Nil => return Ok(None),
+            Value::Slice(buf) => return page_table.resolve_mut(buf).map(Some),
             Value::Pair(addr, len) => Ok((unsigned_to_ptr_mut(*addr)?, *len)),
             Value::Unsigned(addr) => Ok((unsigned_to_ptr_mut(*addr)?, deflen)),
             Value::Pointer(ptr) => Ok((*ptr, deflen)),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("<addr>,<len> or slice")),
         }?;
         if page_table.is_region_writeable(mem::page_range_raw(ptr.cast(), len))
         {
-            unsafe {
-                ptr::write_bytes(ptr, 0, len);
-            }
             Ok(Some(unsafe { slice::from_raw_parts_mut(ptr, len) }))
         } else {
             Err(Error::Unmapped)
@@ -127,7 +228,7 @@ impl Value {
     pub fn as_string(&self) -> Result<String> {
         match self {
             Value::Str(s) => Ok(s.clone()),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("string")),
         }
     }
 
@@ -140,40 +241,46 @@ impl Value {
                 let addr = p.addr() as u128;
                 T::try_from(addr).map_err(|_| Error::NumRange)
             }
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("number")),
         }
     }
 
-    fn as_ptr<T>(&self) -> Result<*const T> {
+    fn as_ptr<T>(&self, page_table: &mmu::LoaderPageTable) -> Result<*const T> {
         match self {
             Value::Nil => Ok(ptr::null()),
-            Value::Slice(slice) => Ok(slice.as_ptr().cast()),
+            Value::Slice(buf) => Ok(page_table.resolve(buf)?.as_ptr().cast()),
             Value::Pair(addr, _len) => Ok(unsigned_to_ptr(*addr)?),
             Value::Unsigned(addr) => Ok(unsigned_to_ptr(*addr)?),
             Value::Pointer(ptr) => Ok(ptr.cast()),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("address")),
         }
     }
 
     fn as_pair(&self) -> Result<(u64, usize)> {
         match self {
             &Value::Pair(addr, len) => Ok((addr as u64, len)),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("<addr>,<len> pair")),
         }
     }
 
-    fn as_ptr_len(&self) -> Result<(*const u8, usize)> {
+    fn as_ptr_len(
+        &self,
+        page_table: &mmu::LoaderPageTable,
+    ) -> Result<(*const u8, usize)> {
         match self {
-            Value::Slice(slice) => Ok((slice.as_ptr(), slice.len())),
+            Value::Slice(buf) => {
+                let buf = page_table.resolve(buf)?;
+                Ok((buf.as_ptr(), buf.len()))
+            }
             &Value::Pair(addr, len) => Ok((unsigned_to_ptr(addr)?, len)),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("<addr>,<len> or slice")),
         }
     }
 
     fn as_ptr_len_mut(&self) -> Result<(*mut u8, usize)> {
         match self {
             &Value::Pair(addr, len) => Ok((unsigned_to_ptr_mut(addr)?, len)),
-            _ => Err(Error::BadArgs),
+            _ => Err(self.bad_arg("<addr>,<len> pair")),
         }
     }
 }
@@ -197,9 +304,9 @@ impl fmt::Debug for Value {
     ) -> core::result::Result<(), fmt::Error> {
         match self {
             Self::Nil => write!(f, "nil"),
-            Self::Slice(s) => write!(f, "{:#x?},{}", s.as_ptr(), s.len()),
+            Self::Slice(b) => write!(f, "{:#x?},{}", b.addr(), b.len()),
             Self::Pair(a, b) => write!(f, "{:#x},{}", *a, *b),
-            Self::Unsigned(u) => write!(f, "{:#x}", *u),
+            Self::Unsigned(u) => write!(f, "{}", numfmt::num(*u)),
             Self::Pointer(p) => write!(f, "{:#x?}", *p),
             Self::Str(s) => write!(f, "{s}"),
             Self::Cmd(s) => write!(f, "[{s}]"),
@@ -216,6 +323,14 @@ impl fmt::Debug for Value {
                     cpuid.eax, cpuid.ebx, cpuid.ecx, cpuid.edx
                 )
             }
+            Self::List(values) => {
+                write!(f, "{{")?;
+                for (k, v) in values.iter().enumerate() {
+                    let sep = if k == 0 { "" } else { " " };
+                    write!(f, "{sep}{v:?}")?;
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -225,59 +340,46 @@ fn evalcmd(
     cmd: &str,
     env: &mut Vec<Value>,
 ) -> Result<Value> {
-    match cmd {
-        "call" => call::run(config, env),
-        "cat" => cat::run(config, env),
-        "copy" => copy::run(config, env),
-        "cpuid" => cpuid::run(config, env),
-        "ecamrd" => ecam::read(config, env),
-        "ecamwr" => ecam::write(config, env),
-        "elfinfo" => elfinfo::run(config, env),
-        "getbits" => bits::get(config, env),
-        "gpioget" => gpio::get(config, env),
-        "gpioset" => gpio::set(config, env),
-        "hexdump" | "xd" => memory::xd(config, env),
-        "iomuxget" => iomux::get(config, env),
-        "iomuxset" => iomux::set(config, env),
-        "inb" => pio::inb(config, env),
-        "inl" => pio::inl(config, env),
-        "inflate" => inflate::run(config, env),
-        "inw" => pio::inw(config, env),
-        "jfmt" => jfmt::run(config, env),
-        "load" => load::run(config, env),
-        "loadcpio" => load::loadcpio(config, env),
-        "loadmem" => load::loadmem(config, env),
-        "ls" | "list" => list::run(config, env),
-        "map" => vm::map(config, env),
-        "mapping" => vm::mapping(config, env),
-        "mappings" => vm::mappings(config, env),
-        "megapulser" => prompt::mega_pulser(config, env),
-        "mount" => mount::run(config, env),
-        "outb" => pio::outb(config, env),
-        "outl" => pio::outl(config, env),
-        "outw" => pio::outw(config, env),
-        "peek" => memory::read(config, env),
-        "poke" => memory::write(config, env),
-        "pop" => Ok(pop2(env)),
-        "prompt" => prompt::prompt(config, env),
-        "pulser" | "throbber" => prompt::pulser(config, env),
-        "push" => Ok(Value::Nil),
-        "rdmsr" => msr::read(config, env),
-        "rdsmn" => smn::read(config, env),
-        "rdsmni" => smn::rdsmni(config, env),
-        "rx" => rx::run(config, env),
-        "rz" => rz::run(config, env),
-        "setbits" => bits::set(config, env),
-        "sha256" => sha::run(config, env),
-        "sha256mem" => sha::mem(config, env),
-        "spinner" => prompt::spinner(config, env),
-        "unmap" => vm::unmap(config, env),
-        "umount" => mount::umount(config, env),
-        "wrmsr" => msr::write(config, env),
-        "wrsmn" => smn::write(config, env),
-        "wrsmni" => smn::wrsmni(config, env),
-        _ => Err(Error::NoCommand),
+    let command = match commands::lookup(cmd) {
+        Some(command) => command,
+        None => {
+            let namespace = commands::namespace(cmd).ok_or(Error::NoCommand)?;
+            match popenv(env) {
+                Value::Nil => {
+                    namespace.list();
+                    return Ok(Value::Nil);
+                }
+                Value::Str(sub) => {
+                    namespace.lookup(&sub).ok_or(Error::NoCommand)?
+                }
+                _ => return Err(Error::NoCommand),
+            }
+        }
+    };
+    // Arguments are popped from the environment stack as they
+    // are parsed, so the number consumed when parsing fails is
+    // the position of the offending argument.
+    let depth = env.len();
+    idt::with_recovery(|| (command.handler)(config, env))
+        .map_err(|e| e.at_arg(depth.saturating_sub(env.len())))
+}
+
+/// Evaluates a command with its console output captured into
+/// the memory buffer `target`, and returns the number of bytes
+/// written there.
+fn evalredirect(
+    config: &mut bldb::Config,
+    cmd: &str,
+    env: &mut Vec<Value>,
+    target: &Value,
+) -> Result<Value> {
+    let buf = target.as_slice_mut(&config.page_table, 0)?.unwrap_or(&mut []);
+    let (res, captured) = sink::capture(buf, || evalcmd(config, cmd, env));
+    res?;
+    if captured.overflowed {
+        return Err(Error::Redirect);
     }
+    Ok(Value::Unsigned(captured.len as u128))
 }
 
 fn dup(env: &mut Vec<Value>) -> Value {
@@ -317,7 +419,7 @@ fn eval(
     match cmd {
         reader::Command::Push => Ok(dup(env)),
         reader::Command::Swap => Ok(swaptop(env)),
-        reader::Command::Cmd(_, tokens) => {
+        reader::Command::Cmd(_, tokens, redirect) => {
             let mut tokens = tokens.clone();
             while let Some(token) = tokens.pop() {
                 match token {
@@ -334,7 +436,11 @@ fn eval(
             let Some(Value::Cmd(cmd)) = env.pop() else {
                 return Ok(Value::Nil);
             };
-            match evalcmd(config, &cmd, env)? {
+            let val = match redirect {
+                Some(target) => evalredirect(config, &cmd, env, target)?,
+                None => evalcmd(config, &cmd, env)?,
+            };
+            match val {
                 Value::Nil => Ok(Value::Nil),
                 v => {
                     env.push(v.clone());
@@ -346,27 +452,39 @@ fn eval(
 }
 
 pub(crate) fn run(config: &mut bldb::Config) {
+    config.signal(crate::beacon::Phase::ReplReady);
     let mut env = Vec::<Value>::new();
     let mut val = Value::default();
     loop {
-        match reader::read(config, &mut env, &val) {
-            Err(e) => {
-                println!("reader: {:?}", e);
-                continue;
-            }
-            Ok(mut cmdstack) => {
-                while let Some(cmd) = cmdstack.pop() {
-                    match eval(config, &cmd, &mut env) {
-                        Err(e) => {
-                            println!("eval: '{cmd:?}': {e:?}");
-                            env.clear();
-                            val = Value::Nil;
-                        }
-                        Ok(v) => val = v,
+        rep(config, &mut env, &mut val);
+    }
+}
+
+/// Reads a line, evaluates the commands on it, and prints the
+/// result.  `val` is the result of the previous line.
+fn rep(config: &mut bldb::Config, env: &mut Vec<Value>, val: &mut Value) {
+    match reader::read(config, env) {
+        Err(e) => {
+            println!("reader: {:?}", e);
+            config.last_failed = true;
+            replay::outcome(false);
+        }
+        Ok(mut cmdstack) => {
+            config.last_failed = false;
+            while let Some(cmd) = cmdstack.pop() {
+                match eval(config, &cmd, env) {
+                    Err(e) => {
+                        println!("eval: '{cmd:?}': {e:?}");
+                        env.clear();
+                        *val = Value::Nil;
+                        config.last_failed = true;
                     }
+                    Ok(v) => *val = v,
                 }
-                println!("res: {val:?}");
             }
+            println!("res: {val:?}");
+            config.results.record(val);
+            replay::outcome(!config.last_failed);
         }
     }
 }
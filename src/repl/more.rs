@@ -0,0 +1,218 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A simple pager for reading files on the ramdisk over the
+//! console, a screenful at a time.
+
+use crate::bldb;
+use crate::cons;
+use crate::println;
+use crate::ramdisk::{self, FileType};
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The terminal size assumed if the terminal does not report
+/// its own.
+const DEFAULT_ROWS: usize = 24;
+const DEFAULT_COLS: usize = 80;
+
+/// The largest file we are willing to page.
+const MAX_LEN: usize = 16 * 1024 * 1024;
+
+/// Splits text into lines no wider than the screen, expanding
+/// tabs and replacing unprintable characters with '.'.
+fn layout(text: &[u8], cols: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.split(|&b| b == b'\n') {
+        let mut out = String::new();
+        let mut col = 0;
+        for &b in line.strip_suffix(b"\r").unwrap_or(line) {
+            let (c, width) = match b {
+                b'\t' => (' ', 8 - col % 8),
+                b' '..=b'~' => (char::from(b), 1),
+                _ => ('.', 1),
+            };
+            for _ in 0..width {
+                if col == cols {
+                    lines.push(core::mem::take(&mut out));
+                    col = 0;
+                }
+                out.push(c);
+                col += 1;
+            }
+        }
+        lines.push(out);
+    }
+    if text.ends_with(b"\n") {
+        lines.pop();
+    }
+    lines
+}
+
+/// Returns the index of the first line after `from` that
+/// contains the pattern.
+fn search(lines: &[String], from: usize, pattern: &str) -> Option<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .skip(from + 1)
+        .find(|(_, line)| line.contains(pattern))
+        .map(|(k, _)| k)
+}
+
+/// The state of the pager: the laid out file, the index of the
+/// line at the top of the screen, and the number of lines that
+/// fit on a page, beneath which is the prompt.
+struct Pager {
+    lines: Vec<String>,
+    top: usize,
+    page: usize,
+    pattern: Option<String>,
+}
+
+impl Pager {
+    fn last_top(&self) -> usize {
+        self.lines.len().saturating_sub(self.page)
+    }
+
+    fn scroll_to(&mut self, top: usize) {
+        self.top = usize::min(top, self.last_top());
+    }
+
+    fn draw(&self, term: &mut Uart, status: &str) {
+        cons::clear(term);
+        let end = usize::min(self.top + self.page, self.lines.len());
+        for line in &self.lines[self.top..end] {
+            term.puts(line);
+            term.puts("\n");
+        }
+        let percent = if self.lines.is_empty() {
+            100
+        } else {
+            end * 100 / self.lines.len()
+        };
+        if status.is_empty() {
+            term.puts(&alloc::format!("--More-- ({percent}%)"));
+        } else {
+            term.puts(status);
+        }
+    }
+
+    /// Searches forward for the current pattern, scrolling so
+    /// that the matching line is at the top of the screen.
+    /// Returns a status message if there is no match.
+    fn find_next(&mut self) -> &'static str {
+        let Some(pattern) = self.pattern.as_deref() else {
+            return "No previous pattern";
+        };
+        match search(&self.lines, self.top, pattern) {
+            Some(k) => {
+                self.top = k;
+                ""
+            }
+            None => "Pattern not found",
+        }
+    }
+
+    /// Pages interactively until the user quits.
+    fn run(&mut self, term: &mut Uart) -> Result<()> {
+        let mut status = "";
+        loop {
+            self.draw(term, status);
+            status = "";
+            match term.try_getb()? {
+                b'q' | b'Q' => break,
+                b' ' | b'f' => self.scroll_to(self.top + self.page),
+                b'b' => self.top = self.top.saturating_sub(self.page),
+                b'\r' | b'\n' | b'j' => self.scroll_to(self.top + 1),
+                b'k' => self.top = self.top.saturating_sub(1),
+                b'd' => self.scroll_to(self.top + self.page / 2),
+                b'u' => self.top = self.top.saturating_sub(self.page / 2),
+                b'g' | b'<' => self.top = 0,
+                b'G' | b'>' => self.top = self.last_top(),
+                b'/' => {
+                    term.puts("\r\x1b[K");
+                    let mut buf = [0u8; 128];
+                    let pattern = cons::readline_timeout(
+                        |term| {
+                            term.putb(b'/');
+                            1
+                        },
+                        term,
+                        Duration::ZERO,
+                        &mut buf,
+                    )?;
+                    if !pattern.is_empty() {
+                        self.pattern = Some(String::from(pattern));
+                    }
+                    status = self.find_next();
+                }
+                b'n' => status = self.find_next(),
+                _ => {
+                    status = "q quit, f/b page, j/k line, d/u half, \
+                               g/G ends, /pattern, n next"
+                }
+            }
+        }
+        term.puts("\r\x1b[K");
+        Ok(())
+    }
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: more file");
+        error
+    };
+    let path = repl::popenv(env).as_string().map_err(usage)?;
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let file = fs.open(&path)?;
+    if file.file_type() != FileType::Regular {
+        println!("more: not a regular file");
+        return Err(Error::BadArgs);
+    }
+    let size = file.size();
+    if size > MAX_LEN {
+        println!("more: file too large");
+        return Err(Error::BadArgs);
+    }
+    let mut text = vec![0u8; size];
+    if file.read(0, &mut text)? != size {
+        return Err(Error::FsRead);
+    }
+    let (rows, cols) =
+        cons::size(&mut config.cons).unwrap_or((DEFAULT_ROWS, DEFAULT_COLS));
+    let lines = layout(&text, cols);
+    let mut pager = Pager { lines, top: 0, page: rows - 1, pattern: None };
+    pager.run(&mut config.cons)?;
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_lines() {
+        let lines = layout(b"a\tb\r\n\x01xyz\n\n0123456789ab\n", 10);
+        assert_eq!(
+            lines,
+            ["a       b", ".xyz", "", "0123456789", "ab"].map(String::from)
+        );
+        assert_eq!(layout(b"no newline", 80), [String::from("no newline")]);
+        assert_eq!(search(&lines, 0, "xyz"), Some(1));
+        assert_eq!(search(&lines, 1, "xyz"), None);
+        assert_eq!(search(&lines, 0, "ab"), Some(4));
+    }
+}
@@ -6,23 +6,60 @@ use crate::bldb;
 use crate::println;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
+use alloc::string::String;
 use alloc::vec::Vec;
 
-pub fn umount(config: &mut bldb::Config, _env: &mut [Value]) -> Result<Value> {
-    config.ramdisk = None;
+/// Returns the directory given as an argument, or the root.
+fn dir(arg: Value) -> Result<String> {
+    match arg {
+        Value::Nil => Ok(String::from("/")),
+        arg => arg.as_string(),
+    }
+}
+
+pub fn umount(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: umount [<dir>]");
+        error
+    };
+    let dir = dir(repl::popenv(env)).map_err(usage)?;
+    config.mounts.umount(&dir)?;
     Ok(Value::Nil)
 }
 
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: mount <ramdisk addr>,<ramdisk len>");
+        println!("usage: mount [<ramdisk addr>,<ramdisk len> [<dir>]]");
         error
     };
     let val = repl::popenv(env);
+    if let Value::Nil = val {
+        for (dir, mounted) in config.mounts.iter() {
+            let buf = mounted.buf();
+            println!(
+                "{dir}: {} at {:#x},{:#x}",
+                mounted.as_str(),
+                buf.addr(),
+                buf.len()
+            );
+        }
+        return Ok(Value::Nil);
+    }
+    let dir = dir(repl::popenv(env)).map_err(usage)?;
+    // Ramdisks in writable memory are mounted so that a UFS file
+    // system may be written; any other memory is only read.
+    if let Ok(Some(ramdisk)) = val.as_slice_mut_unzeroed(&config.page_table, 0)
+    {
+        config.mount_mut(ramdisk, &dir)?;
+        return Ok(Value::Nil);
+    }
     let ramdisk = val
         .as_slice(&config.page_table, 0)
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
-    config.mount(ramdisk)?;
+    config.mount(ramdisk, &dir)?;
     Ok(Value::Nil)
 }
@@ -4,7 +4,7 @@
 
 use crate::bldb;
 use crate::println;
-use crate::repl::{self, Value};
+use crate::repl::{self, Value, confirm};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 use core::convert::TryFrom;
@@ -30,34 +30,75 @@ fn msr_from_str(name: &str) -> Option<u32> {
 }
 
 fn value_to_msr(val: Value) -> Result<u32> {
-    match val {
-        Value::Str(name) => msr_from_str(&name).ok_or(Error::BadArgs),
-        Value::Unsigned(num) => u32::try_from(num).map_err(|_| Error::NumRange),
-        _ => Err(Error::BadArgs),
+    match &val {
+        Value::Str(name) => {
+            msr_from_str(name).ok_or_else(|| val.bad_arg("MSR name"))
+        }
+        &Value::Unsigned(num) => {
+            u32::try_from(num).map_err(|_| Error::NumRange)
+        }
+        _ => Err(val.bad_arg("MSR number or name")),
+    }
+}
+
+/// Returns a description of the given MSR if writing it can
+/// easily wedge the machine or corrupt memory: those controlling
+/// paging, memory typing, and the layout of the address space.
+fn dangerous(msr: u32) -> Option<&'static str> {
+    match msr {
+        x86::msr::IA32_EFER => Some("EFER"),
+        x86::msr::IA32_APIC_BASE => Some("APIC_BASE"),
+        x86::msr::IA32_PAT => Some("PAT"),
+        x86::msr::IA32_MTRR_DEF_TYPE => Some("MTRR_DEF_TYPE"),
+        0x200..=0x20f => Some("a variable range MTRR"),
+        0x250 | 0x258 | 0x259 | 0x268..=0x26f => Some("a fixed range MTRR"),
+        0xc001_0010 => Some("SYSCFG"),
+        0xc001_0015 => Some("HWCR"),
+        0xc001_001a => Some("TOP_MEM"),
+        0xc001_001d => Some("TOP_MEM2"),
+        0xc001_0058 => Some("MMIO_CFG_BASE_ADDR"),
+        _ => None,
     }
 }
-pub fn write(
-    _config: &mut bldb::Config,
-    env: &mut Vec<Value>,
-) -> Result<Value> {
+
+pub fn write(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: wrmsr <msr>, <value>");
+        println!("usage: wrmsr [-f] <msr>, <value>");
         error
     };
+    let force = confirm::take_force(env);
     let msr = value_to_msr(repl::popenv(env)).map_err(usage)?;
     let value = repl::popenv(env).as_num().map_err(usage)?;
+    if let Some(name) = dangerous(msr) {
+        let what = alloc::format!("MSR {msr:#x} is {name}");
+        confirm::confirm(config, force, &what)?;
+    }
     unsafe {
-        x86::msr::wrmsr(msr, value);
+        config.hw.wrmsr(msr, value);
     }
+    config.modified.msr(msr, value);
     Ok(Value::Nil)
 }
 
-pub fn read(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+pub fn read(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
         println!("usage: rdmsr <msr>");
         error
     };
     let msr = value_to_msr(repl::popenv(env)).map_err(usage)?;
-    let val = unsafe { x86::msr::rdmsr(msr) };
+    let val = unsafe { config.hw.rdmsr(msr) };
     Ok(Value::Unsigned(val.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dangerous_msrs() {
+        assert_eq!(dangerous(0xc000_0080), Some("EFER"));
+        assert_eq!(dangerous(0x205), Some("a variable range MTRR"));
+        assert_eq!(dangerous(0xc001_001d), Some("TOP_MEM2"));
+        assert_eq!(dangerous(x86::msr::IA32_FS_BASE), None);
+    }
+}
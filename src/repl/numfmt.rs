@@ -0,0 +1,237 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Session preferences for the display of numbers.
+//!
+//! Values are displayed in hexadecimal by default, and memory is
+//! peeked and poked in the machine's native, little-endian byte
+//! order.  `numfmt` changes either for the rest of the session:
+//! results, `peek` and `poke`, and register reads all format
+//! their values through `Num`, so that they agree.  Whatever the
+//! radix, numbers are shown with a prefix that the reader parses
+//! back to the same value.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Radix {
+    Hex,
+    Dec,
+    Oct,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) enum Endian {
+    Little,
+    Big,
+}
+
+/// How numbers are displayed, and the byte order of memory
+/// operands.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) struct Prefs {
+    radix: Radix,
+    /// Separate groups of digits with underscores.
+    group: bool,
+    endian: Endian,
+}
+
+impl Prefs {
+    const fn new() -> Prefs {
+        Prefs { radix: Radix::Hex, group: false, endian: Endian::Little }
+    }
+}
+
+impl fmt::Display for Prefs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let radix = match self.radix {
+            Radix::Hex => "hex",
+            Radix::Dec => "dec",
+            Radix::Oct => "oct",
+        };
+        let group = if self.group { "group" } else { "nogroup" };
+        let endian = match self.endian {
+            Endian::Little => "le",
+            Endian::Big => "be",
+        };
+        write!(f, "{radix} {group} {endian}")
+    }
+}
+
+#[cfg(not(test))]
+static PREFS: spin::Mutex<Prefs> = spin::Mutex::new(Prefs::new());
+
+#[cfg(test)]
+std::thread_local! {
+    static PREFS: core::cell::Cell<Prefs> =
+        const { core::cell::Cell::new(Prefs::new()) };
+}
+
+/// Returns the current preferences.  Should they be in use, as
+/// when we panic while formatting, the defaults are returned.
+fn prefs() -> Prefs {
+    #[cfg(not(test))]
+    return PREFS.try_lock().map_or(Prefs::new(), |prefs| *prefs);
+    #[cfg(test)]
+    return PREFS.get();
+}
+
+fn set_prefs(prefs: Prefs) {
+    #[cfg(not(test))]
+    {
+        *PREFS.lock() = prefs;
+    }
+    #[cfg(test)]
+    PREFS.set(prefs);
+}
+
+/// A number, displayed according to the session's preferences.
+pub(super) struct Num {
+    value: u128,
+    /// The width of the value in bytes, to which hexadecimal
+    /// values are padded, or 0 if unknown.
+    width: usize,
+}
+
+/// Returns a number for display.
+pub(super) fn num(value: impl Into<u128>) -> Num {
+    Num { value: value.into(), width: 0 }
+}
+
+/// Returns a number for display that was read from, or is to be
+/// written to, `width` bytes of memory or a register.
+pub(super) fn sized(value: impl Into<u128>, width: usize) -> Num {
+    Num { value: value.into(), width }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&render(self.value, self.width, prefs()))
+    }
+}
+
+/// Renders a value in the given radix, with a prefix that the
+/// reader recognizes, optionally grouping digits.
+fn render(value: u128, width: usize, prefs: Prefs) -> String {
+    use core::fmt::Write;
+    let mut digits = String::new();
+    let (prefix, group) = match prefs.radix {
+        Radix::Hex => {
+            let _ = write!(digits, "{value:0pad$x}", pad = 2 * width);
+            ("0x", 4)
+        }
+        Radix::Dec => {
+            let _ = write!(digits, "{value}");
+            ("", 3)
+        }
+        Radix::Oct => {
+            let _ = write!(digits, "{value:o}");
+            (if value == 0 { "" } else { "0" }, 3)
+        }
+    };
+    let mut s = String::from(prefix);
+    for (k, c) in digits.chars().enumerate() {
+        if prefs.group && k != 0 && (digits.len() - k).is_multiple_of(group) {
+            s.push('_');
+        }
+        s.push(c);
+    }
+    s
+}
+
+/// Converts a `len`-byte value between the machine's byte order
+/// and the session's, in either direction.
+pub(super) fn order(value: u128, len: usize) -> u128 {
+    match prefs().endian {
+        Endian::Little => value,
+        Endian::Big => swap(value, len),
+    }
+}
+
+/// Reverses the order of the low `len` bytes of a value.
+fn swap(value: u128, len: usize) -> u128 {
+    match len {
+        0 => 0,
+        len => value.swap_bytes() >> (8 * (16 - len.min(16))),
+    }
+}
+
+pub(super) fn run(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: numfmt [hex | dec | oct] [group | nogroup] [le | be]");
+        error
+    };
+    let mut prefs = prefs();
+    loop {
+        let arg = repl::popenv(env);
+        if let Value::Nil = arg {
+            break;
+        }
+        match arg.as_string().map_err(usage)?.as_str() {
+            "hex" => prefs.radix = Radix::Hex,
+            "dec" => prefs.radix = Radix::Dec,
+            "oct" => prefs.radix = Radix::Oct,
+            "group" => prefs.group = true,
+            "nogroup" => prefs.group = false,
+            "le" => prefs.endian = Endian::Little,
+            "be" => prefs.endian = Endian::Big,
+            _ => {
+                let expected = "hex, dec, oct, group, nogroup, le, or be";
+                return Err(usage(arg.bad_arg(expected)));
+            }
+        }
+    }
+    set_prefs(prefs);
+    println!("numfmt: {prefs}");
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let hex = Prefs::new();
+        let dec = Prefs { radix: Radix::Dec, ..hex };
+        let oct = Prefs { radix: Radix::Oct, ..hex };
+        assert_eq!(render(0x1234, 0, hex), "0x1234");
+        assert_eq!(render(0x1234, 4, hex), "0x00001234");
+        assert_eq!(render(0, 0, hex), "0x0");
+        assert_eq!(render(1234567, 0, dec), "1234567");
+        assert_eq!(render(8, 0, oct), "010");
+        assert_eq!(render(0, 0, oct), "0");
+        let group = |prefs: Prefs| Prefs { group: true, ..prefs };
+        assert_eq!(render(0x1234_5678, 0, group(hex)), "0x1234_5678");
+        assert_eq!(render(0x12345, 4, group(hex)), "0x0001_2345");
+        assert_eq!(render(0x12345, 0, group(hex)), "0x1_2345");
+        assert_eq!(render(1234567, 0, group(dec)), "1_234_567");
+        assert_eq!(render(123, 0, group(dec)), "123");
+        assert_eq!(render(0o7654321, 0, group(oct)), "07_654_321");
+    }
+
+    #[test]
+    fn byte_order() {
+        assert_eq!(swap(0x1234, 2), 0x3412);
+        assert_eq!(swap(0x12, 1), 0x12);
+        assert_eq!(swap(0x1122_3344, 4), 0x4433_2211);
+        assert_eq!(swap(0x1122_3344, 8), 0x4433_2211_0000_0000);
+        assert_eq!(order(0x1234, 2), 0x1234);
+        set_prefs(Prefs { endian: Endian::Big, ..Prefs::new() });
+        assert_eq!(order(0x1234, 2), 0x3412);
+        assert_eq!(alloc::format!("{}", sized(0xab_u8, 2)), "0x00ab");
+        set_prefs(Prefs { radix: Radix::Dec, ..Prefs::new() });
+        assert_eq!(alloc::format!("{:>6}", num(42_u8)), "    42");
+        set_prefs(Prefs::new());
+    }
+}
@@ -0,0 +1,81 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading TPM PCRs and the log of measurements made by the
+//! loader.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use crate::tpm;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+fn hex(digest: &[u8; 32]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: pcrread [<pcr>]");
+        error
+    };
+    let pcrs = match repl::popenv(env) {
+        Value::Nil => !0 >> (32 - tpm::NPCRS),
+        arg => {
+            let pcr = arg.as_num::<u32>().map_err(usage)?;
+            if pcr >= tpm::NPCRS {
+                return Err(usage(arg.bad_arg("PCR number")));
+            }
+            1 << pcr
+        }
+    };
+    let measurements = &mut config.measurements;
+    let mut last = Value::Nil;
+    match measurements.tpm() {
+        None => println!("no TPM found"),
+        Some(tpm) => {
+            match tpm.ids() {
+                Some((vid, did)) => println!(
+                    "TPM ({:?}) vendor {vid:#06x} device {did:#06x}",
+                    tpm.interface()
+                ),
+                None => println!("TPM ({:?})", tpm.interface()),
+            }
+            for (pcr, value) in tpm.read(pcrs)? {
+                println!("pcr{pcr:<2} {}", hex(&value));
+                last = Value::Sha256(value);
+            }
+        }
+    }
+    let log = measurements.log();
+    if log.is_empty() {
+        println!("no measurements");
+        return Ok(last);
+    }
+    println!("measurements:");
+    for event in log.iter().filter(|e| pcrs & (1 << e.pcr) != 0) {
+        println!(
+            "    pcr{} {} {}{}",
+            event.pcr,
+            hex(&event.digest),
+            event.what,
+            if event.extended { "" } else { " (not extended)" }
+        );
+    }
+    if pcrs & (1 << tpm::IMAGE_PCR) != 0 {
+        let replayed = measurements.replay(tpm::IMAGE_PCR);
+        println!(
+            "pcr{} from an initial zero value would be {}",
+            tpm::IMAGE_PCR,
+            hex(&replayed)
+        );
+    }
+    Ok(last)
+}
@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Displaying the physical memory map.
+
+use crate::bldb;
+use crate::physmap::{self, Map};
+use crate::println;
+use crate::ramdisk::human_size;
+use crate::repl::Value;
+use crate::result::Result;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The top of memory below 4GiB, and above it, as the cores see
+/// them.
+const TOP_MEM: u32 = 0xc001_001a;
+const TOM2: u32 = 0xc001_001d;
+
+/// Formats the length of a range of memory.
+fn size(range: &Range<u64>) -> alloc::string::String {
+    human_size((range.end - range.start) as usize)
+}
+
+fn show(map: &Map) {
+    println!("DRAM address map:");
+    for rule in map.rules.iter() {
+        println!(
+            "[{:2}] {:#014x}..{:#014x} {:>6} dst {:#05x} ilv chan {:#x} sel \
+             {}{}",
+            rule.index,
+            rule.range.start,
+            rule.range.end,
+            size(&rule.range),
+            rule.dst,
+            rule.channels,
+            rule.addr_sel,
+            if rule.hole { " hole" } else { "" },
+        );
+    }
+    if let Some(hole) = &map.hole {
+        println!(
+            "MMIO hole: {:#x}..{:#x} ({})",
+            hole.start,
+            hole.end,
+            size(hole)
+        );
+    }
+    println!("DRAM segments:");
+    for segment in map.segments() {
+        println!(
+            "  {:#014x}..{:#014x} {:>6}",
+            segment.start,
+            segment.end,
+            size(&segment)
+        );
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let map = physmap::read()?;
+    show(&map);
+    let (tom, tom2) =
+        unsafe { (config.hw.rdmsr(TOP_MEM), config.hw.rdmsr(TOM2)) };
+    println!("TOP_MEM {tom:#x}, TOM2 {tom2:#x}");
+    let segments = map
+        .segments()
+        .into_iter()
+        .map(|r| Value::Pair(r.start as usize, (r.end - r.start) as usize))
+        .collect();
+    Ok(Value::List(segments))
+}
@@ -25,33 +25,41 @@ impl PortSize {
     }
 }
 
-fn pio_in(port_size: PortSize, env: &mut Vec<Value>) -> Result<Value> {
+fn pio_in(
+    config: &bldb::Config,
+    port_size: PortSize,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
     let usage = |error| {
         println!("usage: in{} <port>", port_size.as_char());
         error
     };
     let port = repl::popenv(env).as_num::<u16>().map_err(usage)?;
     let value = match port_size {
-        PortSize::P8 => unsafe { x86::io::inb(port).into() },
-        PortSize::P16 => unsafe { x86::io::inw(port).into() },
-        PortSize::P32 => unsafe { x86::io::inl(port).into() },
+        PortSize::P8 => unsafe { config.hw.inb(port).into() },
+        PortSize::P16 => unsafe { config.hw.inw(port).into() },
+        PortSize::P32 => unsafe { config.hw.inl(port).into() },
     };
     Ok(Value::Unsigned(value))
 }
 
-pub fn inb(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_in(PortSize::P8, env)
+pub fn inb(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_in(config, PortSize::P8, env)
 }
 
-pub fn inw(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_in(PortSize::P16, env)
+pub fn inw(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_in(config, PortSize::P16, env)
 }
 
-pub fn inl(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_in(PortSize::P32, env)
+pub fn inl(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_in(config, PortSize::P32, env)
 }
 
-fn pio_out(port_size: PortSize, env: &mut Vec<Value>) -> Result<Value> {
+fn pio_out(
+    config: &bldb::Config,
+    port_size: PortSize,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
     let usage = |error| {
         println!("usage: out{} <port> <value>", port_size.as_char());
         error
@@ -59,27 +67,27 @@ fn pio_out(port_size: PortSize, env: &mut Vec<Value>) -> Result<Value> {
     let port = repl::popenv(env).as_num::<u16>().map_err(usage)?;
     match port_size {
         PortSize::P8 => repl::popenv(env).as_num().map(|value| unsafe {
-            x86::io::outb(port, value);
+            config.hw.outb(port, value);
         }),
         PortSize::P16 => repl::popenv(env).as_num().map(|value| unsafe {
-            x86::io::outw(port, value);
+            config.hw.outw(port, value);
         }),
         PortSize::P32 => repl::popenv(env).as_num().map(|value| unsafe {
-            x86::io::outl(port, value);
+            config.hw.outl(port, value);
         }),
     }
     .map_err(usage)?;
     Ok(Value::Nil)
 }
 
-pub fn outb(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_out(PortSize::P8, env)
+pub fn outb(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_out(config, PortSize::P8, env)
 }
 
-pub fn outw(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_out(PortSize::P16, env)
+pub fn outw(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_out(config, PortSize::P16, env)
 }
 
-pub fn outl(_config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
-    pio_out(PortSize::P32, env)
+pub fn outl(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    pio_out(config, PortSize::P32, env)
 }
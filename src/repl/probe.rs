@@ -0,0 +1,136 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Cautious scanning of physical address space for MMIO.
+
+use crate::bldb;
+use crate::idt;
+use crate::mem;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+use core::ptr;
+
+/// The outcome of probing a single address.
+enum Probe {
+    Value(u64),
+    AllOnes,
+    Fault,
+}
+
+/// Reads `width` bytes at `addr`, with fault recovery armed.
+///
+/// # Safety
+/// The caller must ensure that `addr` is mapped uncached and
+/// suitably aligned for `width`.
+unsafe fn probe1(addr: usize, width: usize) -> Probe {
+    let p = ptr::with_exposed_provenance::<u8>(addr);
+    let read = || unsafe {
+        match width {
+            1 => ptr::read_volatile(p) as u64,
+            2 => ptr::read_volatile(p.cast::<u16>()) as u64,
+            4 => ptr::read_volatile(p.cast::<u32>()) as u64,
+            8 => ptr::read_volatile(p.cast::<u64>()),
+            _ => panic!("impossible probe width"),
+        }
+    };
+    let ones = u64::MAX >> (64 - 8 * width);
+    match idt::with_fault_recovery(read) {
+        None => Probe::Fault,
+        Some(v) if v == ones => Probe::AllOnes,
+        Some(v) => Probe::Value(v),
+    }
+}
+
+/// Returns true if we mapped the page containing `addr` for
+/// probing, and false if it was already mapped.
+fn map_probe_page(config: &mut bldb::Config, addr: usize) -> Result<bool> {
+    let page = mem::V4KA::new(addr & !(mem::V4KA::SIZE - 1));
+    if config.page_table.lookup(ptr::without_provenance(page.addr())).is_some()
+    {
+        return Ok(false);
+    }
+    let end = mem::V4KA::new(page.addr() + mem::V4KA::SIZE);
+    unsafe {
+        config.page_table.map_region(
+            page..end,
+            mem::Attrs::new_mmio(),
+            mem::P4KA::new(page.addr() as u64),
+        )?;
+    }
+    Ok(true)
+}
+
+fn unmap_probe_page(config: &mut bldb::Config, addr: usize) -> Result<()> {
+    let page = addr & !(mem::V4KA::SIZE - 1);
+    let range = mem::V4KA::new(page)..mem::V4KA::new(page + mem::V4KA::SIZE);
+    unsafe { config.page_table.unmap_range(range) }
+}
+
+fn check_range(addr: usize, len: usize, width: usize) -> Result<()> {
+    if !matches!(width, 1 | 2 | 4 | 8) || !addr.is_multiple_of(width) {
+        return Err(Error::PtrAlign);
+    }
+    let end = addr.checked_add(len).ok_or(Error::NumRange)?;
+    if len == 0 || !mem::is_physical(end as u64 - 1) {
+        return Err(Error::NumRange);
+    }
+    if !mem::is_canonical_range(addr, end) {
+        return Err(Error::PtrNonCanon);
+    }
+    Ok(())
+}
+
+/// Probes the given range of physical address space, reading
+/// `width` bytes every `stride` bytes.  Pages that are not
+/// already mapped are temporarily identity mapped uncached.
+/// Faulting accesses are recovered from and counted, as are
+/// reads returning all ones, which is what unclaimed MMIO space
+/// typically returns.  Addresses that return anything else are
+/// reported individually.
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: probe <addr>,<len> [<width> [<stride>]]");
+        error
+    };
+    let (addr, len) = repl::popenv(env).as_pair().map_err(usage)?;
+    let addr = usize::try_from(addr).map_err(|_| usage(Error::NumRange))?;
+    let width = match repl::popenv(env) {
+        Value::Nil => 4,
+        v => v.as_num::<usize>().map_err(usage)?,
+    };
+    let stride = match repl::popenv(env) {
+        Value::Nil => mem::V4KA::SIZE,
+        v => v.as_num::<usize>().map_err(usage)?,
+    };
+    if stride < width || !stride.is_multiple_of(width) {
+        return Err(usage(Error::BadArgs));
+    }
+    check_range(addr, len, width).map_err(usage)?;
+
+    let (mut responding, mut ones, mut faults) = (0, 0, 0);
+    let end = addr + len;
+    let mut va = addr;
+    while va < end {
+        let mapped = map_probe_page(config, va)?;
+        match unsafe { probe1(va, width) } {
+            Probe::Value(v) => {
+                println!("{va:#x}: {v:#0pad$x}", pad = 2 + 2 * width);
+                responding += 1;
+            }
+            Probe::AllOnes => ones += 1,
+            Probe::Fault => faults += 1,
+        }
+        if mapped {
+            unmap_probe_page(config, va)?;
+        }
+        va = match va.checked_add(stride) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    println!("{responding} responding, {ones} all-ones, {faults} faulted");
+    Ok(Value::Unsigned(responding))
+}
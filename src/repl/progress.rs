@@ -0,0 +1,154 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Progress reports for file transfers.
+//!
+//! Receiving a large image over the console can take many
+//! minutes, so the receive loops report how much has arrived,
+//! how quickly, and, if the size of the file is known, how long
+//! the rest should take.  A report written to the UART carrying
+//! the transfer would be read by the sender as protocol data,
+//! and never seen, so periodic reports are only made when the
+//! transfer has been moved to another port with `xferport`;
+//! otherwise, only the summary at the end is printed.  Reports
+//! are written at info level, so lowering the console's level
+//! silences them.
+
+use crate::clock::Instant;
+use crate::println;
+use crate::ramdisk::human_size;
+use crate::sink::{self, Level};
+use crate::uart::Uart;
+use alloc::format;
+use alloc::string::String;
+use core::time::Duration;
+
+/// The least time between reports.
+const INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tracks a transfer, and reports on it periodically.
+pub(super) struct Progress {
+    what: &'static str,
+    start: Instant,
+    last: Instant,
+    total: Option<usize>,
+    /// Whether the transfer is on a port other than the
+    /// console, so that reports can be made while it runs.
+    live: bool,
+}
+
+impl Progress {
+    /// Starts tracking a transfer of `total` bytes, if known,
+    /// over the given UART.
+    pub(super) fn new(
+        what: &'static str,
+        uart: &Uart,
+        total: Option<usize>,
+    ) -> Progress {
+        let now = Instant::now();
+        let live = !uart.is_cons();
+        Progress { what, start: now, last: now, total, live }
+    }
+
+    /// Records the size of the file, once it is known.
+    pub(super) fn set_total(&mut self, total: usize) {
+        self.total = Some(total);
+    }
+
+    /// Reports that `bytes` have been transferred, if it has been
+    /// long enough since the last report, and the console is
+    /// free to show it.
+    pub(super) fn update(&mut self, bytes: usize) {
+        let now = Instant::now();
+        if !self.live || now.saturating_duration_since(self.last) < INTERVAL {
+            return;
+        }
+        self.last = now;
+        let elapsed = now.saturating_duration_since(self.start);
+        let line = report(self.what, bytes, self.total, elapsed);
+        sink::emit(Level::Info, format_args!("{line}\n"));
+    }
+
+    /// Prints a summary of the completed transfer.
+    pub(super) fn finish(&self, verb: &str, bytes: usize) {
+        let elapsed = self.start.elapsed();
+        println!(
+            "\n\n{verb} {bytes} bytes in {elapsed:.3?} ({}/s)",
+            human_size(rate(bytes, elapsed))
+        );
+    }
+}
+
+/// Returns the rate of transfer in bytes per second.
+fn rate(bytes: usize, elapsed: Duration) -> usize {
+    match elapsed.as_millis() {
+        0 => 0,
+        ms => (bytes as u128 * 1000 / ms) as usize,
+    }
+}
+
+/// Formats a duration as hours, minutes and seconds.
+fn hms(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match h {
+        0 => format!("{m}:{s:02}"),
+        _ => format!("{h}:{m:02}:{s:02}"),
+    }
+}
+
+/// Describes a transfer in progress, as, e.g.,
+/// `rz: 12M of 96M (12%), 11.3K/s, 2:07:03 left`.
+fn report(
+    what: &str,
+    bytes: usize,
+    total: Option<usize>,
+    elapsed: Duration,
+) -> String {
+    let rate = rate(bytes, elapsed);
+    let mut line = format!("{what}: {}", human_size(bytes));
+    if let Some(total) = total.filter(|&total| total != 0) {
+        let percent = bytes.min(total) as u128 * 100 / total as u128;
+        line.push_str(&format!(" of {} ({percent}%)", human_size(total)));
+    }
+    line.push_str(&format!(", {}/s", human_size(rate)));
+    if let Some(total) = total
+        && rate != 0
+    {
+        let left = total.saturating_sub(bytes).div_ceil(rate);
+        line.push_str(&format!(
+            ", {} left",
+            hms(Duration::from_secs(left as u64))
+        ));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        let secs = Duration::from_secs;
+        assert_eq!(rate(4096, secs(2)), 2048);
+        assert_eq!(rate(4096, Duration::ZERO), 0);
+        assert_eq!(hms(secs(59)), "0:59");
+        assert_eq!(hms(secs(3600 + 62)), "1:01:02");
+        let mib = 1024 * 1024;
+        assert_eq!(
+            report("rz", 12 * mib, Some(96 * mib), secs(1024)),
+            "rz: 12M of 96M (12%), 12K/s, 1:59:28 left"
+        );
+        assert_eq!(report("rx", 1536, None, secs(1)), "rx: 1.5K, 1.5K/s");
+        assert_eq!(
+            report("rz", 0, Some(mib), Duration::ZERO),
+            "rz: 0 of 1.0M (0%), 0/s"
+        );
+        // Nothing is reported on the console while it carries the
+        // transfer.
+        let progress = Progress::new("rz", &crate::fakes::console([]), None);
+        assert!(!progress.live);
+    }
+}
@@ -2,15 +2,99 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! Prompt animations, and the status segments shown before the
+//! prompt.
+
 use crate::bldb;
+use crate::clock;
 use crate::cons;
 use crate::println;
 use crate::repl::{self, Value};
-use crate::result::{Error, Result};
+use crate::result::Result;
 use crate::uart;
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt::Write;
 use core::time::Duration;
 
+/// An item of session state that may be shown before the
+/// prompt.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Segment {
+    /// Whether the last command line succeeded.
+    Status,
+    /// The type of the mounted file system, if any.
+    Fs,
+    /// Whether destructive operations must be confirmed.
+    Safe,
+    /// The time since reset.
+    Uptime,
+}
+
+impl Segment {
+    const ALL: [Segment; 4] =
+        [Segment::Status, Segment::Fs, Segment::Safe, Segment::Uptime];
+
+    fn name(self) -> &'static str {
+        match self {
+            Segment::Status => "status",
+            Segment::Fs => "fs",
+            Segment::Safe => "safe",
+            Segment::Uptime => "uptime",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Segment> {
+        Self::ALL.into_iter().find(|segment| segment.name() == name)
+    }
+}
+
+/// The session state rendered by the segments.
+struct Facts<'a> {
+    failed: bool,
+    fs: Option<&'a str>,
+    confirm: bool,
+    uptime: u64,
+}
+
+/// Renders the given segments, returning an empty string if
+/// there are none.
+fn render(segments: &[Segment], facts: &Facts<'_>) -> String {
+    let mut s = String::new();
+    for &segment in segments {
+        s.push(if s.is_empty() { '[' } else { ' ' });
+        let _ = match segment {
+            Segment::Status => {
+                s.write_str(if facts.failed { "err" } else { "ok" })
+            }
+            Segment::Fs => s.write_str(facts.fs.unwrap_or("-")),
+            Segment::Safe => {
+                s.write_str(if facts.confirm { "safe" } else { "unsafe" })
+            }
+            Segment::Uptime => {
+                let t = facts.uptime;
+                write!(s, "{}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60)
+            }
+        };
+    }
+    if !s.is_empty() {
+        s.push_str("] ");
+    }
+    s
+}
+
+/// Returns the text to be shown before the prompt.
+pub(super) fn status(config: &bldb::Config) -> String {
+    let uptime = clock::uptime().as_secs();
+    let facts = Facts {
+        failed: config.last_failed,
+        fs: config.mounts.root().map(|mounted| mounted.as_str()),
+        confirm: config.confirm,
+        uptime,
+    };
+    render(&config.prompt_segments, &facts)
+}
+
 fn cycle(term: &mut uart::Uart, bs: &[u8], timeout: Duration) {
     cons::cycle(term, b"", bs, b"", timeout);
     term.getb();
@@ -51,15 +135,68 @@ pub(super) fn prompt(
     env: &mut Vec<Value>,
 ) -> Result<Value> {
     let usage = |error| {
-        println!("usage: prompt <tenex | spinner | pulser>");
+        println!(
+            "usage: prompt [tenex | spinner | pulser] | \
+             prompt segments [status | fs | safe | uptime]..."
+        );
         error
     };
-    let p = repl::popenv(env).as_string().map_err(usage)?;
-    match p.as_str() {
-        "tenex" => config.prompt = cons::Prompt::Tenex,
-        "spinner" => config.prompt = cons::Prompt::Spinner,
-        "pulser" => config.prompt = cons::Prompt::Pulser,
-        _ => return Err(usage(Error::BadArgs)),
+    let arg = repl::popenv(env);
+    match arg {
+        Value::Nil => {}
+        _ => match arg.as_string().map_err(usage)?.as_str() {
+            "tenex" => config.prompt = cons::Prompt::Tenex,
+            "spinner" => config.prompt = cons::Prompt::Spinner,
+            "pulser" => config.prompt = cons::Prompt::Pulser,
+            "segments" => {
+                let mut segments = Vec::new();
+                loop {
+                    let arg = repl::popenv(env);
+                    if let Value::Nil = arg {
+                        break;
+                    }
+                    let name = arg.as_string().map_err(usage)?;
+                    let segment =
+                        Segment::from_name(&name).ok_or_else(|| {
+                            usage(arg.bad_arg("status, fs, safe, or uptime"))
+                        })?;
+                    segments.push(segment);
+                }
+                config.prompt_segments = segments;
+            }
+            _ => {
+                return Err(usage(
+                    arg.bad_arg("tenex, spinner, pulser, or segments"),
+                ));
+            }
+        },
     }
+    let names = config
+        .prompt_segments
+        .iter()
+        .map(|segment| segment.name())
+        .collect::<Vec<_>>();
+    println!("prompt: {:?}, segments: [{}]", config.prompt, names.join(" "));
     Ok(Value::Nil)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let facts = Facts {
+            failed: false,
+            fs: Some("cpio"),
+            confirm: true,
+            uptime: 3723,
+        };
+        assert_eq!(render(&[], &facts), "");
+        let all = Segment::ALL;
+        assert_eq!(render(&all, &facts), "[ok cpio safe 1:02:03] ");
+        let facts = Facts { failed: true, fs: None, confirm: false, ..facts };
+        assert_eq!(render(&all[..3], &facts), "[err - unsafe] ");
+        assert_eq!(Segment::from_name("uptime"), Some(Segment::Uptime));
+    }
+}
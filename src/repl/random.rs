@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::entropy::{self, Generator, Source};
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use alloc::vec::Vec;
+
+/// How many values `random check` draws from each source.
+const CHECK_DRAWS: usize = 4096;
+
+/// Draws values from each source, reporting retries, health test
+/// failures, and the proportion of one bits.  Returns the number
+/// of sources that failed.
+fn check() -> Result<Value> {
+    let mut failed = 0;
+    for source in [Source::Rdrand, Source::Rdseed] {
+        let name = source.name();
+        let mut rng = match Generator::new(source) {
+            Ok(rng) => rng,
+            Err(_) => {
+                println!("{name}: not implemented");
+                continue;
+            }
+        };
+        let mut errors = 0;
+        for _ in 0..CHECK_DRAWS {
+            if let Err(e) = rng.next() {
+                errors += 1;
+                if errors == 1 {
+                    println!("{name}: {e:?}");
+                }
+            }
+        }
+        let stats = rng.stats;
+        let bits = stats.draws as u64 * 64;
+        let unbiased = entropy::unbiased(&stats);
+        println!(
+            "{name}: {} values, {} retries, {errors} failures, \
+             {}/{bits} one bits{}",
+            stats.draws,
+            stats.retries,
+            stats.ones,
+            if unbiased { "" } else { " (biased)" }
+        );
+        if errors != 0 || !unbiased {
+            failed += 1;
+        }
+    }
+    Ok(Value::Unsigned(failed))
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: random [-s] <len> | [-s] <addr>,<len> | check");
+        error
+    };
+    let mut source = Source::Rdrand;
+    let mut arg = repl::popenv(env);
+    match &arg {
+        Value::Str(s) if s == "check" => return check(),
+        Value::Str(s) if s == "-s" => {
+            source = Source::Rdseed;
+            arg = repl::popenv(env);
+        }
+        _ => {}
+    }
+    let mut rng = Generator::new(source)?;
+    match arg {
+        Value::Unsigned(len) if len <= 16 => {
+            let mut bytes = [0u8; 16];
+            rng.fill(&mut bytes[..len as usize])?;
+            Ok(Value::Unsigned(u128::from_le_bytes(bytes)))
+        }
+        Value::Pair(addr, len) => {
+            let buf = arg
+                .as_slice_mut(&config.page_table, 0)
+                .map_err(usage)?
+                .unwrap();
+            rng.fill(buf)?;
+            Ok(Value::Pair(addr, len))
+        }
+        _ => Err(usage(arg.bad_arg("length of at most 16 or <addr>,<len>"))),
+    }
+}
@@ -6,11 +6,19 @@ use crate::bldb;
 use crate::cons;
 use crate::println;
 use crate::repl::Value;
+use crate::repl::alias;
+use crate::repl::complete;
+use crate::repl::idle;
+use crate::repl::replay;
 use crate::result::{Error, Result};
+use crate::sink;
+use crate::uart;
 use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt;
+use core::time::Duration;
 
 #[derive(Clone, Debug)]
 pub enum Token {
@@ -24,7 +32,7 @@ pub enum Token {
 pub enum Command {
     Push,
     Swap,
-    Cmd(String, Vec<Token>),
+    Cmd(String, Vec<Token>, Option<Value>),
 }
 
 impl fmt::Debug for Command {
@@ -35,7 +43,7 @@ impl fmt::Debug for Command {
         match self {
             Self::Push => write!(f, "Push"),
             Self::Swap => write!(f, "Swap"),
-            Self::Cmd(cmd, _) => write!(f, "{cmd}"),
+            Self::Cmd(cmd, _, _) => write!(f, "{cmd}"),
         }
     }
 }
@@ -59,7 +67,9 @@ pub(super) fn parse_num<T: Default + TryFrom<u128>>(num: &str) -> Result<T> {
     T::try_from(num).map_err(|_| Error::NumRange)
 }
 
-fn parse_len<T: Default + TryFrom<u128>>(mut tok: &str) -> Result<T> {
+pub(super) fn parse_len<T: Default + TryFrom<u128>>(
+    mut tok: &str,
+) -> Result<T> {
     let mut multiplier: u128 = 1;
     while !tok.is_empty() {
         if let Some(rest) = tok.strip_suffix(['k', 'K']) {
@@ -92,24 +102,102 @@ fn split_pair(s: &str, pat: char) -> Result<(&str, Option<&str>)> {
     Ok((a, b))
 }
 
+/// Splits a trailing output redirection, `> <addr>,<len>`, from
+/// a command, returning the command and the redirection target.
+/// Only a `>` that begins a word, outside of double quotes, is a
+/// redirection, so that arguments such as `->` are left alone.
+fn split_redirect(cmd: &str) -> Result<(&str, Option<Value>)> {
+    let mut quoted = false;
+    let mut prev = ' ';
+    let mut redirect = None;
+    for (k, c) in cmd.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '>' if !quoted && prev.is_whitespace() => {
+                redirect = Some(k);
+                break;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    let Some(k) = redirect else {
+        return Ok((cmd, None));
+    };
+    let (cmd, target) = (&cmd[..k], &cmd[k + 1..]);
+    let target = target.trim();
+    if target.contains(char::is_whitespace) {
+        return Err(Error::BadArgs);
+    }
+    match parse_value(target)? {
+        pair @ Value::Pair(..) => Ok((cmd.trim_end(), Some(pair))),
+        _ => Err(Error::BadArgs),
+    }
+}
+
+/// The commands handled by the reader itself, for completion.
+pub(super) const READER_COMMANDS: &[&str] = &[
+    "clear", "config", "result", "res", "env", "stack", "clrenv", "help",
+    "man", "alias", "unalias",
+];
+
 fn eval_reader_command(
     config: &mut bldb::Config,
     cmd: &str,
     env: &mut Vec<Value>,
-    lastval: &Value,
 ) -> bool {
     match cmd {
         "clear" => cons::clear(&mut config.cons),
         "config" => println!("{config:#x?}"),
-        "result" | "res" => println!("{lastval:?}"),
+        "result" | "res" => config.results.list(),
         "env" | "stack" => dumpenv(env),
         "clrenv" => env.clear(),
         "help" | "man" => help(),
-        _ => return false,
+        "alias" => alias::alias(config, ""),
+        _ => {
+            if let Some(args) = cmd.strip_prefix("alias ") {
+                alias::alias(config, args);
+                return true;
+            }
+            if let Some(name) = cmd.strip_prefix("unalias ") {
+                alias::unalias(config, name);
+                return true;
+            }
+            if let Some(n) =
+                cmd.strip_prefix("res ").or_else(|| cmd.strip_prefix("result "))
+            {
+                recall(config, n.trim(), env);
+                return true;
+            }
+            let Some(topic) =
+                cmd.strip_prefix("help ").or_else(|| cmd.strip_prefix("man "))
+            else {
+                return false;
+            };
+            let topic = topic.trim();
+            if !super::commands::help_topic(topic) {
+                println!("help: no command or category named '{topic}'");
+            }
+        }
     }
     true
 }
 
+/// Pushes the `n`th most recent result back onto the stack.
+fn recall(config: &bldb::Config, n: &str, env: &mut Vec<Value>) {
+    let Ok(k) = parse_num::<usize>(n) else {
+        println!("res: expected a number, got '{n}'");
+        return;
+    };
+    match config.results.get(k) {
+        Some(val) => {
+            println!("[{k}]: {val:?}");
+            env.push(val.clone());
+        }
+        None => println!("res: no result {k}"),
+    }
+}
+
 fn dumpenv(env: &[Value]) {
     println!("environment:");
     if !env.is_empty() {
@@ -121,14 +209,14 @@ fn dumpenv(env: &[Value]) {
     }
 }
 
-fn parse_value(s: &str) -> Result<Value> {
+pub(super) fn parse_value(s: &str) -> Result<Value> {
     let v = match s.chars().next() {
         Some(c) if c.is_ascii_digit() && !s.contains('/') => {
             let (a, b) = split_pair(s, ',')?;
             if let Some(b) = b {
                 Value::Pair(parse_num(a)?, parse_len(b)?)
             } else {
-                Value::Unsigned(parse_num(a)?)
+                Value::Unsigned(parse_len(a)?)
             }
         }
         Some(_) => Value::Str(String::from(s)),
@@ -137,30 +225,59 @@ fn parse_value(s: &str) -> Result<Value> {
     Ok(v)
 }
 
+/// The longest line that can be read at the prompt.
+const LINE_LEN: usize = 1024;
+
+/// Reads a line from the console.  Animated prompts are redrawn
+/// periodically while waiting for input.  If an idle timeout is
+/// configured and expires before any input arrives, returns
+/// `Error::Timeout`.
 fn readline(config: &mut bldb::Config) -> Result<String> {
     let prompt = match config.prompt {
         cons::Prompt::Tenex => prompt::tenex,
         cons::Prompt::Spinner => prompt::spin,
         cons::Prompt::Pulser => prompt::pulse,
     };
-    if config.prompt == cons::Prompt::Tenex {
-        let mut buf = [0u8; 1024];
-        cons::readline(prompt, &mut config.cons, &mut buf).map(String::from)
-    } else {
-        loop {
-            let mut buf = [0u8; 1024];
-            match cons::readline_timeout(
-                prompt,
-                &mut config.cons,
-                core::time::Duration::from_secs(10),
-                &mut buf,
-            ) {
-                Err(Error::Timeout) => {
-                    cons::backspace(&mut config.cons, false);
-                    continue;
+    let redraw = (config.prompt != cons::Prompt::Tenex)
+        .then_some(Duration::from_secs(10));
+    let idle = config.idle.as_ref().map(|idle| idle.timeout);
+    let mut waited = Duration::ZERO;
+    let mut buf = vec![0u8; LINE_LEN];
+    loop {
+        // A zero timeout waits forever.
+        let timeout = match (redraw, idle) {
+            (None, None) => Duration::ZERO,
+            (Some(redraw), None) => redraw,
+            (None, Some(idle)) => idle - waited,
+            (Some(redraw), Some(idle)) => redraw.min(idle - waited),
+        };
+        let status = super::prompt::status(config);
+        let prompt = |term: &mut uart::Uart| {
+            term.puts(&status);
+            status.len() + prompt(term)
+        };
+        let aliases = &config.aliases;
+        let mounts = &config.mounts;
+        let builtin = config.builtin.as_deref();
+        let page_table = &config.page_table;
+        let mut complete = |line: &str| {
+            complete::candidates(line, aliases, mounts, builtin, page_table)
+        };
+        match cons::readline_complete(
+            prompt,
+            &mut config.cons,
+            timeout,
+            &mut buf,
+            Some(&mut complete),
+        ) {
+            Err(e) if e.is(Error::Timeout) => {
+                cons::backspace(&mut config.cons, false);
+                waited += timeout;
+                if idle.is_some_and(|idle| waited >= idle) {
+                    return Err(Error::Timeout);
                 }
-                res => return res.map(String::from),
             }
+            res => return res.map(String::from),
         }
     }
 }
@@ -188,25 +305,40 @@ mod prompt {
 pub fn read(
     config: &mut bldb::Config,
     env: &mut Vec<Value>,
-    lastval: &Value,
 ) -> Result<Vec<Command>> {
     let line = loop {
-        let Ok(s) = readline(config) else {
-            return Err(Error::Reader);
+        let s = match readline(config) {
+            Ok(s) => {
+                sink::transcribe_input(&s);
+                s
+            }
+            Err(e) if e.is(Error::Timeout) => {
+                match idle::expired(config, env) {
+                    Some(s) => s,
+                    None => continue,
+                }
+            }
+            Err(_) => return Err(Error::Reader),
         };
         let line = s.as_str();
         let line = line.trim();
         if line.is_empty() {
             continue;
         }
-        if eval_reader_command(config, line, env, lastval) {
+        if eval_reader_command(config, line, env) {
             continue;
         }
-        if let Some(expansion) = config.aliases.get(line) {
-            break expansion.clone();
-        }
         break s;
     };
+    replay::input(&line);
+    parse(config, &line)
+}
+
+/// Parses a command line, expanding it if it is an alias, into
+/// the commands to evaluate, last first.
+pub(super) fn parse(config: &bldb::Config, line: &str) -> Result<Vec<Command>> {
+    let line = alias::expand(&config.aliases, line);
+    let line = line.as_ref();
     let mut cmds = Vec::<Command>::new();
     let cs: Box<dyn Iterator<Item = &str>> = if line.contains('|') {
         Box::new(line.split('|').rev())
@@ -214,8 +346,8 @@ pub fn read(
         Box::new(line.split('.'))
     };
     for cmd in cs {
-        let mut cmd = cmd.trim();
-        let cmdline = String::from(cmd);
+        let cmdline = String::from(cmd.trim());
+        let (mut cmd, redirect) = split_redirect(cmd.trim())?;
         while !cmd.is_empty() {
             if let Some(rest) = cmd.strip_prefix("@") {
                 cmds.push(Command::Push);
@@ -256,7 +388,7 @@ pub fn read(
         {
             tokens[0] = Token::Value(Value::Cmd(cmd));
         }
-        cmds.push(Command::Cmd(cmdline, tokens));
+        cmds.push(Command::Cmd(cmdline, tokens, redirect));
     }
     Ok(cmds)
 }
@@ -278,6 +410,33 @@ mod tests {
             parse_value("0x1000,4k").unwrap(),
             Value::Pair(0x1000, 4096)
         ));
+        assert!(matches!(
+            parse_value("1M").unwrap(),
+            Value::Unsigned(0x100000)
+        ));
+        assert!(matches!(parse_value("0x1b").unwrap(), Value::Unsigned(0x1b)));
+    }
+
+    #[test]
+    fn redirect() {
+        assert!(matches!(split_redirect("mappings"), Ok(("mappings", None))));
+        assert!(matches!(
+            split_redirect("mappings > 0x1000,4k"),
+            Ok(("mappings", Some(Value::Pair(0x1000, 4096))))
+        ));
+        assert!(matches!(
+            split_redirect("@sha256 >0x1000,16"),
+            Ok(("@sha256", Some(Value::Pair(0x1000, 16))))
+        ));
+        assert!(split_redirect("mappings > 0x1000").is_err());
+        assert!(split_redirect("mappings > 0x1000,16 extra").is_err());
+        assert!(split_redirect("mappings >").is_err());
+        assert!(matches!(split_redirect("echo ->"), Ok(("echo ->", None))));
+        assert!(matches!(split_redirect("a>b"), Ok(("a>b", None))));
+        assert!(matches!(
+            split_redirect("fgrep \"x > y\" /f"),
+            Ok(("fgrep \"x > y\" /f", None))
+        ));
     }
 }
 
@@ -316,6 +475,16 @@ Pushes the strings "a", "b", and "c" onto the stack, while,
 
 will pop the top element.
 
+A command's console output may be redirected into memory by
+ending it with `> <addr>,<len>`.  The buffer is zeroed first,
+and the command returns the number of bytes written rather than
+its usual result; it fails if its output did not fit, though
+what did fit is kept.  For example,
+
+```
+mappings > 0x1000000,64k
+```
+
 ## Booting a machine
 
 In the simplest case, run `zoxboot` and send your ramdisk via
@@ -327,7 +496,7 @@ kernel from it, and call into that kernel, passing the ramdisk
 base address and length as arguments, run:
 
 ```
-call . load /platform/oxide/kernel/amd64/unix . mount . @inflate . rz
+call . load /platform/oxide/kernel/amd64/unix . mount . @inflate -r . rz
 ```
 
 And then send your compressed ramdisk image using ZMODEM.  For
@@ -338,7 +507,7 @@ characters, you may use that instead.  The above example is
 equivalent to:
 
 ```
-rz | @inflate | mount | load /platform/oxide/kernel/amd64/unix | call
+rz | @inflate -r | mount | load /platform/oxide/kernel/amd64/unix | call
 ```
 
 ## Commands
@@ -349,127 +518,21 @@ The reader supports a handful of "reader commands":
 * `config` displays the current system configuration
 * `env` or `stack` displays the current environment stack
 * `clrenv` clears the environment stack
-* `res` or `result` displays the last returned value
-* `help` or `man` displays this text
-
-Supported commands include:
-
-* `push item(s)` to push one or more items onto the environment
-  stack.
-* `pop` to pop and return the item currently at the top of the
-  environment stack.  Returns nil if the stack is empty.
-* `rz <addr,len>` to receive a file via ZMODEM
-* `rx <addr,len>` to receive a file via XMODEM
-* `inflate <src addr>,<src len> [<dst addr>,<dst len>]`
-  decompresses the a ZLIB compressed slice from the given
-  source to the given destination.
-* `mount <addr,len>` to mount a UFS ramdisk or cpio miniroot.
-* `umount` to unmount the ramdisk.
-* `ls <file>` to list a file or directory on the ramdisk
-* `cat <file>` to display the contents of a file
-* `copy <file> <dst addr>,<dst len>` to copy the contents of a
-  file to a region of memory.
-* `elfinfo <file>` to read the contents of the ELF header and
-  segment headers of an ELF file
-* `load <file>` to load the given ELF file and retrieve its
-  entry point
-* `loadmem <addr>,<len>` to load an ELF object from the given
-  region of memory.
-* `call <location> [<up to 6 args>]` calls the System V ABI
-  compliant function at `<location>`, passing up to six
-  arguments taken from the environment stack argument list
-  terminated by nil.
-* `rdmsr <u32>` to read the numbered MSR (note some MSRs can be
-  specified by name, such as `IA32_APIC_BASE`)
-* `wrmsr <u32> <u64>` to write the given value to the given MSR
-* `jfmt <num>` to format a number using the "jazzy" format from
-  the illumos `mdb` debugger
-* `sha256 <file>` to compute the SHA256 checksum of a file in
-  the ramdisk
-* `sha256mem <addr,len>` to compute the SHA256 checksum over a
-  region of memory
-* `inb <port>`, `inw <port>`, `inl <port>` to read data from an
-  x86 IO port
-* `outb <port> <u8>`, `outw <port> <u16>`, `outl <port> <u32>`
-  to write data to an x86 IO port
-* `iomuxget <pin>` to get the function currently active in the
-  IO mux for the given pin
-* `iomuxset <pin> <function>` to configure the IO mux for the
-  given pin to the given function, where `<function>` is one of,
-  `F0`, `F1`, `F2`, or `F3`
-* `gpioget pin` to get the state of the given GPIO pin
-* `gpioset pin <state>` to set the given GPIO pin to the given
-  state, which includes:
-  * `pu` to enable the internal pullup (`-pu` to disable)
-  * `pd` to enable the internal pulldown (`-pd` to disable)
-  * `ah` to configure active high
-  * `al` to configure active low
-  * `oh` to configure output high
-  * `ol` to configure output low
-  * `out` to configure as output (output enable is true)
-  * `in` to configure as input (output enable is false)
-* `hexdump <addr>,<len>` to produce a hexdump of `len` bytes of
-  memory starting at `base`.
-* `peek <addr>,<len>` to read `len` bytes starting at `addr`.
-  `len` must be 1, 2, 4, 8, or 16.
-* `poke <addr>,<len> <value>` to poke a value into the `len`
-  bytes starting at `addr`.  `len` must be 1, 2, 4, 8, or 16.
-  The value is written in native byte order.
-* `mapping address` to display the page table mapping for the
-  given address, if any
-* `mappings` to display all virtual memory mappings
-* `map <phys addr>,<len> <virt addr> <attrs>` maps `len` bytes
-  at physical address `phys addr` to virtual address `virt addr`
-  with the given attributesk, which is a comma-separated list
-  of:
-  * `r` to enable page read permission (the default)
-  * `-r` to remove page read permission
-  * `w` to enable page write permission
-  * `-w` to remove page write permission (the default)
-  * `x` to enable page executable permission (the default)
-  * `-x` to remove page execute permission
-  * `c` to enable page cachability (the default)
-  * `-c` to disable page caching
-  * `g` to set this page as a "global" page
-  * `-g` to remove the global page attribute (the default)
-  `<phys addr>`, `<len>`, `<phys addr>` must all be multiples
-  of 4KiB.  If these values are also multiples of 2MiB or 1GiB,
-  those size mappings will be used.  To map such a region using
-  smaller page sizes, issue multiple `map` commands covering
-  smaller regions to make up a contiguous whole.
-* `unmap <virt addr>,<len>` to remove a virtual memory mapping
-  for the range of given virtual address space covering `<len>`
-  bytes starting at `<virt addr>`.  As with mapping, `<len>` and
-  `<virt addr>` must both be multiples of 4KiB.  If these values
-  are also multiples of 2MiB or 1GiB, those size mappings will
-  be used.  To unmap such a region mapped with smaller page
-  sizes, issue mulitple `unmap` calls.
-* `rdsmn <addr>` to read a 32-bit word from the given SMN
-  address.
-* `rdsmni <index> <addr>` like `rdsmn`, but using a specific
-  address/data register pair.
-* `wrsmn <addr> <value>` to write a 32-bit word to the given SMN
-  address.
-* `wrsmni <index> <addr>` like `wrsmn`, but using a spcecific
-  address/data register pair.
-* `cpuid <leaf> <subleaf>` to return the results of the `CPUID`
-  instruction for the given leaf and subleaf.
-* `ecamrd <b/d/f> <offset>` read a 32-bit word from PCIe
-  extended configuration space for the given bus/device/function
-* `ecamwr <b/d/f> <offset> <value>` writes a 32-bit word to PCIe
-  extended configuration space for the given bus/device/function
-* `getbits <start>,<end> <value>` returns  the given bit range
-  from `<value>`
-* `setbits <start>,<end> <new bits> <value>` sets the given bit
-  range in `<value>` to `<new bits>`
-* `spinner` displays a moving "spinner" on the terminal until a
-  byte is received on the UART.  The `pulser` and `throbber`
-  commands do essentially the same thing, with a different
-  character pattern.  The `megapulser` command exists just for
-  fun.
-* `prompt <tenex | spinner | pulser>` to change the default
-  prompt type.  `tenex` is the "@" prompt.  The other two are
-  animated; see the `spinner` and `pulser` commands above.
+* `res` or `result` lists the last 16 values returned by
+  command lines, most recent first, numbered from 0; `res <n>`
+  pushes the `n`th back onto the environment stack
+* `help` or `man` displays this text; `help <topic>` displays
+  help on a command, namespace, or category
+* `alias` lists aliases; `alias <name> = <line>` defines one,
+  which is expanded when it starts a line, and `unalias <name>`
+  removes one
+
+Use `help <command>` for details on a specific command, or
+`help <category>` to list the commands in a category.  Related
+commands are also grouped into namespaces, so that, e.g., `mem
+xd` is another name for `hexdump`; `help <namespace>` lists the
+members of one.  Commands by category are:
 "#
     );
+    super::commands::list();
 }
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use alloc::vec::Vec;
+
+fn show(config: &bldb::Config) {
+    let regions = [
+        ("xfer", &config.xfer_region),
+        ("ramdisk", &config.ramdisk_region),
+        ("scratch", &config.scratch_region),
+        ("loader", &config.loader_region),
+    ];
+    for (name, range) in regions {
+        let start = range.start.addr();
+        let end = range.end.addr();
+        let len = end.wrapping_sub(start);
+        println!("{name:<8} {start:#x}..{end:#x} ({len:#x} bytes)");
+    }
+    if config.regions_claimed {
+        println!("regions are in use and can no longer be resized");
+    }
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: region [resize <xfer len> <ramdisk len> [<scratch len>] | \
+             move <xfer | ramdisk | scratch> <addr>,<len>]"
+        );
+        error
+    };
+    match repl::popenv(env) {
+        Value::Nil => {}
+        Value::Str(cmd) if cmd == "resize" => {
+            let xfer_len =
+                repl::popenv(env).as_num::<usize>().map_err(usage)?;
+            let ramdisk_len =
+                repl::popenv(env).as_num::<usize>().map_err(usage)?;
+            let scratch_len = match repl::popenv(env) {
+                Value::Nil => {
+                    config.scratch_region.end.addr()
+                        - config.scratch_region.start.addr()
+                }
+                v => v.as_num::<usize>().map_err(usage)?,
+            };
+            let layout =
+                bldb::Layout::packed(xfer_len, ramdisk_len, scratch_len)?;
+            config.set_layout(layout)?;
+        }
+        Value::Str(cmd) if cmd == "move" => {
+            let mut layout = config.layout();
+            let region = match repl::popenv(env) {
+                Value::Str(name) if name == "xfer" => &mut layout.xfer,
+                Value::Str(name) if name == "ramdisk" => &mut layout.ramdisk,
+                Value::Str(name) if name == "scratch" => &mut layout.scratch,
+                v => return Err(usage(v.bad_arg("xfer, ramdisk, or scratch"))),
+            };
+            *region = match repl::popenv(env) {
+                Value::Pair(addr, len) => bldb::Layout::region(addr, len)?,
+                v => return Err(usage(v.bad_arg("<addr>,<len>"))),
+            };
+            config.set_layout(layout)?;
+        }
+        v => return Err(usage(v.bad_arg("resize or move"))),
+    }
+    show(config);
+    Ok(Value::Nil)
+}
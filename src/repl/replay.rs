@@ -0,0 +1,290 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recording and replaying sessions.
+//!
+//! Bringing up a machine is often a long sequence of commands,
+//! and a reset part way through wipes out the state they built.
+//! `record` keeps the command lines evaluated at the prompt as a
+//! script, noting whether each succeeded, and `replay` evaluates
+//! them again, from the start or from a given step.  Unlike a
+//! transcript, a script holds only what was typed, so it can be
+//! sent to the host, and sent back after a reset, to pick up
+//! where the session left off.
+//!
+//! A script is text, with one step per line: `ok` or `fail`,
+//! then a space and the command line as typed.
+
+use crate::bldb;
+use crate::cons;
+use crate::println;
+use crate::repl::{self, Value, reader};
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How long to wait for an answer at a step that failed.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The commands that manage recordings, which are not
+/// themselves recorded.
+const UNRECORDED: &[&str] = &["record", "replay"];
+
+/// The recording in progress, or most recently stopped.
+struct Recording {
+    script: Vec<u8>,
+    recording: bool,
+    /// The line being evaluated, whose outcome is not yet known.
+    pending: Option<String>,
+}
+
+impl Recording {
+    const fn new() -> Recording {
+        Recording { script: Vec::new(), recording: false, pending: None }
+    }
+}
+
+#[cfg(not(test))]
+static RECORDING: spin::Mutex<Recording> = spin::Mutex::new(Recording::new());
+
+#[cfg(test)]
+std::thread_local! {
+    static RECORDING: core::cell::RefCell<Recording> =
+        const { core::cell::RefCell::new(Recording::new()) };
+}
+
+fn with<R>(f: impl FnOnce(&mut Recording) -> R) -> R {
+    #[cfg(not(test))]
+    return f(&mut RECORDING.lock());
+    #[cfg(test)]
+    return RECORDING.with_borrow_mut(f);
+}
+
+/// Notes a line read at the prompt, which is about to be
+/// evaluated, if recording.
+pub(super) fn input(line: &str) {
+    let line = line.trim();
+    let cmd = line.split_ascii_whitespace().next().unwrap_or_default();
+    if UNRECORDED.contains(&cmd) {
+        return;
+    }
+    with(|rec| {
+        if rec.recording {
+            rec.pending = Some(String::from(line));
+        }
+    });
+}
+
+/// Records the outcome of evaluating the line last noted.
+pub(super) fn outcome(ok: bool) {
+    with(|rec| {
+        if let Some(line) = rec.pending.take() {
+            let verdict = if ok { "ok" } else { "fail" };
+            rec.script.extend_from_slice(verdict.as_bytes());
+            rec.script.push(b' ');
+            rec.script.extend_from_slice(line.as_bytes());
+            rec.script.push(b'\n');
+        }
+    });
+}
+
+/// A step of a script.
+#[derive(Debug, Eq, PartialEq)]
+struct Step<'a> {
+    ok: bool,
+    line: &'a str,
+}
+
+/// Parses a script into its steps.  Blank lines are ignored.
+fn steps(script: &str) -> Result<Vec<Step<'_>>> {
+    script
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once(' ') {
+            Some(("ok", line)) => Ok(Step { ok: true, line }),
+            Some(("fail", line)) => Ok(Step { ok: false, line }),
+            _ => Err(Error::Replay),
+        })
+        .collect()
+}
+
+pub(super) fn record(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: record [start | stop]");
+        error
+    };
+    let arg = repl::popenv(env);
+    let verb = match &arg {
+        Value::Nil => None,
+        arg => Some(arg.as_string().map_err(usage)?),
+    };
+    with(|rec| match verb.as_deref() {
+        None => {
+            let state = if rec.recording { "recording" } else { "stopped" };
+            let steps = rec.script.iter().filter(|&&b| b == b'\n').count();
+            println!("record: {state}, {steps} steps");
+            Ok(Value::Nil)
+        }
+        Some("start") => {
+            if rec.recording {
+                println!("record: discarding the recording in progress");
+            }
+            *rec = Recording { recording: true, ..Recording::new() };
+            Ok(Value::Nil)
+        }
+        Some("stop") => {
+            if !rec.recording {
+                return Err(Error::BadArgs);
+            }
+            rec.recording = false;
+            let steps = rec.script.iter().filter(|&&b| b == b'\n').count();
+            println!("record: stopped, {steps} steps");
+            Ok(Value::Slice(config.page_table.buf(&rec.script)))
+        }
+        Some(_) => Err(usage(arg.bad_arg("start or stop"))),
+    })
+}
+
+/// What to do at a step that failed when it was recorded.
+enum Answer {
+    Run,
+    Skip,
+    Quit,
+}
+
+fn ask(config: &mut bldb::Config, step: usize) -> Result<Answer> {
+    println!("replay: step {step} failed when it was recorded");
+    let prompt = |term: &mut Uart| {
+        const PROMPT: &str = "run, skip, or quit? [r/s/q]: ";
+        term.puts(PROMPT);
+        PROMPT.len()
+    };
+    let mut buf = [0u8; 16];
+    loop {
+        let answer =
+            cons::readline_timeout(prompt, &mut config.cons, TIMEOUT, &mut buf);
+        match answer.map(str::trim) {
+            Ok("r" | "run") => return Ok(Answer::Run),
+            Ok("s" | "skip") => return Ok(Answer::Skip),
+            Ok("q" | "quit") => return Ok(Answer::Quit),
+            Ok(_) => continue,
+            Err(e) if e.is(Error::Timeout) => return Ok(Answer::Quit),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Evaluates a command line, as `rep` does, returning the
+/// value of its last command.
+fn evalline(
+    config: &mut bldb::Config,
+    line: &str,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let mut cmds = reader::parse(config, line)?;
+    let mut val = Value::Nil;
+    while let Some(cmd) = cmds.pop() {
+        val = repl::eval(config, &cmd, env)?;
+    }
+    Ok(val)
+}
+
+pub(super) fn replay(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: replay [<script>] [from <step>]");
+        error
+    };
+    let mut from = 1;
+    let mut script = None;
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            Value::Str(s) if s == "from" => {
+                from = repl::popenv(env).as_num::<usize>().map_err(usage)?;
+            }
+            v => script = v.as_slice(&config.page_table, 0).map_err(usage)?,
+        }
+    }
+    let script = match script {
+        Some(bs) => {
+            let s = core::str::from_utf8(bs).map_err(|_| Error::Replay)?;
+            String::from(s)
+        }
+        None => with(|rec| String::from_utf8_lossy(&rec.script).into_owned()),
+    };
+    let steps = steps(&script)?;
+    let mut val = Value::Nil;
+    for (k, step) in steps.iter().enumerate().skip(from.saturating_sub(1)) {
+        let k = k + 1;
+        if !step.ok {
+            match ask(config, k)? {
+                Answer::Run => {}
+                Answer::Skip => continue,
+                Answer::Quit => {
+                    println!("replay: resume with 'replay from {k}'");
+                    return Err(Error::Cancelled);
+                }
+            }
+        }
+        println!("replay: [{k}/{}] {}", steps.len(), step.line);
+        val = evalline(config, step.line, env).inspect_err(|_| {
+            println!("replay: step {k} failed; resume with 'replay from {k}'");
+        })?;
+        println!("res: {val:?}");
+    }
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+
+    #[test]
+    fn parsing() {
+        let script = "ok push 1\n\nfail nosuch 2\nok pop\n";
+        assert_eq!(
+            steps(script).unwrap(),
+            [
+                Step { ok: true, line: "push 1" },
+                Step { ok: false, line: "nosuch 2" },
+                Step { ok: true, line: "pop" },
+            ]
+        );
+        assert!(steps("maybe push 1\n").is_err());
+        assert!(steps("ok\n").is_err());
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut sim = Sim::new();
+        let out = sim.session(
+            "record start\npush 1\nnosuch\npush 2\n\
+             clrenv\nrecord\nrecord stop\n",
+        );
+        assert!(out.contains("record: recording, 3 steps"), "{out}");
+        assert!(out.contains("record: stopped, 3 steps"), "{out}");
+        let script = with(|rec| rec.script.clone());
+        assert_eq!(script, b"ok push 1\nfail nosuch\nok push 2\n");
+        let out = sim.session("clrenv\nreplay\ns\n");
+        assert!(out.contains("replay: [1/3] push 1"), "{out}");
+        assert!(out.contains("step 2 failed when it was recorded"), "{out}");
+        assert!(!out.contains("[2/3]"), "{out}");
+        let end = "replay: [3/3] push 2\nres: nil\nres: nil\n";
+        assert!(out.ends_with(end), "{out}");
+        let out = sim.session("clrenv\nreplay from 2\nr\n");
+        assert!(!out.contains("[1/3]"), "{out}");
+        assert!(out.contains("step 2 failed; resume with 'replay from 2'"));
+        let out = sim.session("replay from 2\nq\n");
+        assert!(out.contains("resume with 'replay from 2'"), "{out}");
+    }
+}
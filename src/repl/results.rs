@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The history of command results.
+//!
+//! The REPL prints the value of each command line as it is
+//! evaluated.  The most recent values that were not nil are
+//! kept, so that an address produced several commands ago can
+//! be recovered without scrolling back or typing it again: `res`
+//! lists them, and `res <n>` pushes one back onto the stack.
+//! Results are numbered from 0, the most recent.
+
+use crate::println;
+use crate::repl::Value;
+use alloc::collections::VecDeque;
+
+/// The number of results kept.
+const DEPTH: usize = 16;
+
+/// The most recent results, newest first.
+#[derive(Default)]
+pub(crate) struct Results {
+    ring: VecDeque<Value>,
+}
+
+impl Results {
+    /// Records the result of a command line.  Nil results are
+    /// not kept.
+    pub(super) fn record(&mut self, val: &Value) {
+        if let Value::Nil = val {
+            return;
+        }
+        if self.ring.len() == DEPTH {
+            self.ring.pop_back();
+        }
+        self.ring.push_front(val.clone());
+    }
+
+    /// Returns the `n`th most recent result, if kept.
+    pub(super) fn get(&self, n: usize) -> Option<&Value> {
+        self.ring.get(n)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub(super) fn list(&self) {
+        if self.ring.is_empty() {
+            println!("no results");
+        }
+        for (k, val) in self.ring.iter().enumerate() {
+            println!("[{k}]: {val:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use alloc::format;
+
+    #[test]
+    fn ring() {
+        let mut results = Results::default();
+        results.record(&Value::Nil);
+        assert_eq!(results.len(), 0);
+        for k in 0..DEPTH as u128 + 2 {
+            results.record(&Value::Unsigned(k));
+        }
+        assert_eq!(results.len(), DEPTH);
+        assert_eq!(format!("{:?}", results.get(0)), "Some(0x11)");
+        assert_eq!(format!("{:?}", results.get(DEPTH - 1)), "Some(0x2)");
+        assert!(results.get(DEPTH).is_none());
+    }
+
+    #[test]
+    fn recall() {
+        // Reader commands are not command lines, so each session
+        // ends with one to finish reading.
+        let mut sim = Sim::new();
+        let out = sim.session("res\npush 5 6\ndepth\nres 0\npush\n");
+        assert!(out.starts_with("@res\nno results\n"), "{out}");
+        assert!(out.contains("@res 0\n[0]: 0x2\n"), "{out}");
+        let out = sim.session("clrenv\ndepth\nres\npush\n");
+        assert!(out.contains("[0]: 0x0\n[1]: 0x2\n"), "{out}");
+        let out = sim.session("clrenv\nres 1\nenv\nres 16\nres x\npush\n");
+        assert!(out.contains("environment:\n[0]: 0x2\n"), "{out}");
+        assert!(out.contains("res: no result 16"), "{out}");
+        assert!(out.contains("res: expected a number, got 'x'"), "{out}");
+    }
+}
@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Raw binary receive.
+//!
+//! The fastest way to move an image over the console is to send
+//! it with no framing at all.  The length is given up front, and
+//! the sender follows the data with its CRC-32, least significant
+//! byte first, so that a corrupted or truncated transfer is still
+//! detected.  There is no way to ask for data to be resent: a
+//! failed transfer is simply run again.
+
+use crate::beacon;
+use crate::bldb;
+use crate::crc32;
+use crate::println;
+use crate::repl::progress::Progress;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result, ResultExt};
+use crate::uart::{self, Uart};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// How long to wait for the sender to start.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long to wait for each byte once it has.
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Receives exactly `dst.len()` bytes, followed by their CRC-32.
+fn rraw(uart: &mut Uart, dst: &mut [u8]) -> Result<()> {
+    println!(
+        "receiving {:#x} bytes to {:#x?}; send the data and its CRC-32",
+        dst.len(),
+        dst.as_ptr()
+    );
+    // The data is not escaped, so XON and XOFF are data, too.
+    let soft_flow = uart::set_soft_flow(false);
+    let result = receive(uart, dst);
+    uart::set_soft_flow(soft_flow);
+    result
+}
+
+fn receive(uart: &mut Uart, dst: &mut [u8]) -> Result<()> {
+    let mut progress = Progress::new("rraw", uart, Some(dst.len()));
+    let mut timeout = START_TIMEOUT;
+    let len = dst.len();
+    for (k, b) in dst.iter_mut().enumerate() {
+        *b = uart.try_getb_timeout(timeout).context("received", &[k as u64])?;
+        timeout = BYTE_TIMEOUT;
+        progress.update(k + 1);
+    }
+    let mut sum = [0; 4];
+    for b in sum.iter_mut() {
+        *b = uart
+            .try_getb_timeout(timeout)
+            .context("received", &[len as u64])?;
+        timeout = BYTE_TIMEOUT;
+    }
+    progress.finish("Received", len);
+    let expected = u32::from_le_bytes(sum);
+    let crc = crc32::crc32(dst);
+    if crc != expected {
+        println!("rraw: CRC-32 is {crc:#010x}, but {expected:#010x} was sent");
+        return Err(
+            Error::Verify.context("crc,sent", &[crc.into(), expected.into()])
+        );
+    }
+    Ok(())
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: rraw <dst addr>,<len>");
+        error
+    };
+    let dst = repl::popenv(env)
+        .as_slice_mut(&config.page_table, 0)
+        .and_then(|o| o.ok_or(Error::BadArgs))
+        .map_err(usage)?;
+    config.signal(beacon::Phase::Receiving);
+    rraw(config.xfer.as_mut().unwrap_or(&mut config.cons), dst)?;
+    Ok(Value::Slice(config.page_table.buf(dst)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+    use alloc::vec;
+
+    fn framed(data: &[u8]) -> Vec<u8> {
+        let mut bs = data.to_vec();
+        bs.extend_from_slice(&crc32::crc32(data).to_le_bytes());
+        bs
+    }
+
+    #[test]
+    fn receives() {
+        // XON and XOFF must arrive as data.
+        let data = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+        let mut uart = fakes::console([
+            Rx::Idle(Duration::from_secs(10)),
+            Rx::Bytes(framed(&data)),
+        ]);
+        let mut dst = vec![0u8; data.len()];
+        rraw(&mut uart, &mut dst).unwrap();
+        assert!(fakes::exhausted());
+        assert_eq!(dst, data);
+
+        let mut uart = fakes::console([Rx::Bytes(framed(&[]))]);
+        rraw(&mut uart, &mut []).unwrap();
+        assert!(fakes::exhausted());
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut bs = framed(b"hello, world");
+        bs[3] ^= 0x20;
+        let mut uart = fakes::console([Rx::Bytes(bs)]);
+        let mut dst = [0u8; 12];
+        let error = rraw(&mut uart, &mut dst).unwrap_err();
+        assert_eq!(error.as_str(), Error::Verify.as_str());
+        assert_eq!(&dst, b"helLo, world");
+    }
+
+    #[test]
+    fn truncated() {
+        let bs = framed(b"hello, world");
+        let mut uart = fakes::console([
+            Rx::bytes(&bs[..5]),
+            Rx::Idle(Duration::from_secs(2)),
+            Rx::bytes(&bs[5..]),
+        ]);
+        let mut dst = [0u8; 12];
+        let error = rraw(&mut uart, &mut dst).unwrap_err();
+        assert_eq!(error.as_str(), Error::Timeout.as_str());
+    }
+}
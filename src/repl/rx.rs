@@ -2,11 +2,13 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::beacon;
 use crate::bldb;
 use crate::println;
+use crate::repl::progress::Progress;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
-use crate::uart::Uart;
+use crate::uart::{self, Uart};
 use alloc::vec::Vec;
 use xmodem::Xmodem;
 use xmodem::io::Error as XError;
@@ -43,17 +45,57 @@ impl xmodem::io::Write for Uart {
     }
 }
 
-fn rx(uart: &mut Uart, mut dst: &mut [u8]) -> Result<usize> {
+/// The destination of an XMODEM receive, which reports progress
+/// as blocks arrive.
+struct Dst<'a> {
+    buf: &'a mut [u8],
+    off: usize,
+    progress: Progress,
+}
+
+impl xmodem::io::Write for Dst<'_> {
+    fn write(&mut self, bs: &[u8]) -> XResult<usize> {
+        let dst = &mut self.buf[self.off..];
+        let n = usize::min(bs.len(), dst.len());
+        dst[..n].copy_from_slice(&bs[..n]);
+        self.off += n;
+        self.progress.update(self.off);
+        Ok(n)
+    }
+
+    fn write_all(&mut self, bs: &[u8]) -> XResult<()> {
+        if self.write(bs)? != bs.len() {
+            return Err(XError::new(XErrorKind::Other, "buffer full"));
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> XResult<()> {
+        Ok(())
+    }
+}
+
+fn rx(uart: &mut Uart, dst: &mut [u8]) -> Result<usize> {
     println!("receiving to {:#x?}", dst.as_ptr());
     let b = uart.getb();
     if b != b'g' {
         println!("Aborted!");
         return Err(Error::Recv);
     }
+    // XMODEM does not escape its data, so XON and XOFF in it
+    // are not flow control.
+    let soft_flow = uart::set_soft_flow(false);
     let mut xfer = Xmodem::new();
-    let nrecv = xfer
-        .recv(uart, &mut dst, xmodem::Checksum::CRC16)
-        .map_err(|_| Error::Recv)?;
+    let addr = dst.as_ptr().addr();
+    let len = dst.len();
+    let progress = Progress::new("rx", uart, None);
+    let mut dst = Dst { buf: dst, off: 0, progress };
+    let nrecv = xfer.recv(uart, &mut dst, xmodem::Checksum::CRC16);
+    uart::set_soft_flow(soft_flow);
+    let nrecv = nrecv.map_err(|_| {
+        Error::Recv.context("buffer addr,len", &[addr as u64, len as u64])
+    })?;
+    dst.progress.finish("Received", nrecv);
     Ok(nrecv)
 }
 
@@ -65,8 +107,102 @@ pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let dst = repl::popenv(env)
         .as_slice_mut(&config.page_table, 0)
         .map_err(usage)?
-        .unwrap_or_else(|| bldb::xfer_region_init_mut());
-    let nrecv = rx(&mut config.cons, dst)?;
-    println!("\n\nReceived {nrecv} bytes");
-    Ok(Value::Slice(&dst[..nrecv]))
+        .unwrap_or_else(|| config.xfer_region_init_mut());
+    config.signal(beacon::Phase::Receiving);
+    let nrecv = rx(config.xfer.as_mut().unwrap_or(&mut config.cons), dst)?;
+    Ok(Value::Slice(config.page_table.buf(&dst[..nrecv])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+    use crate::repl::ry::crc16;
+    use xmodem::io::{Read, Write};
+
+    const SOH: u8 = 0x01;
+    const EOT: u8 = 0x04;
+    const ACK: u8 = 0x06;
+    const NAK: u8 = 0x15;
+
+    fn block(seq: u8, data: &[u8; 128]) -> Vec<u8> {
+        let mut bs = alloc::vec![SOH, seq, !seq];
+        bs.extend_from_slice(data);
+        bs.extend_from_slice(&crc16(data).to_be_bytes());
+        bs
+    }
+
+    #[test]
+    fn transfer() {
+        let data = [[0x5a; 128], [0xc3; 128]];
+        let mut corrupt = block(1, &data[0]);
+        corrupt[20] ^= 0x10;
+        let mut uart = fakes::console([
+            Rx::bytes(b"g"),
+            Rx::Bytes(corrupt),
+            Rx::Bytes(block(1, &data[0])),
+            Rx::Bytes(block(2, &data[1])),
+            Rx::bytes(&[EOT]),
+        ]);
+        let mut dst = [0u8; 512];
+        assert_eq!(rx(&mut uart, &mut dst), Ok(256));
+        assert!(fakes::exhausted());
+        assert_eq!(&dst[..256], data.as_flattened());
+        assert!(dst[256..].iter().all(|&b| b == 0));
+        let tx = fakes::transmitted();
+        let count = |b| tx.iter().filter(|&&t| t == b).count();
+        assert_eq!((count(NAK), count(ACK)), (1, 3));
+    }
+
+    #[test]
+    fn line_error() {
+        let mut uart = fakes::console([
+            Rx::bytes(b"g"),
+            Rx::Bytes(block(1, &[0x5a; 128])[..64].to_vec()),
+            Rx::Error(Error::UartFraming),
+        ]);
+        let mut dst = [0u8; 512];
+        assert_eq!(
+            rx(&mut uart, &mut dst).map_err(|e| e.as_str()),
+            Err(Error::Recv.as_str())
+        );
+        assert!(fakes::exhausted());
+    }
+
+    #[test]
+    fn aborted() {
+        let mut uart = fakes::console([Rx::bytes(b"q")]);
+        let mut dst = [0u8; 128];
+        assert_eq!(rx(&mut uart, &mut dst), Err(Error::Recv));
+        assert!(fakes::transmitted().ends_with(b"Aborted!\r\n"));
+        assert!(fakes::exhausted());
+    }
+
+    #[test]
+    fn port_errors() {
+        let mut uart = fakes::console([
+            Rx::bytes(b"ab"),
+            Rx::Error(Error::UartFraming),
+            Rx::bytes(b"cd"),
+        ]);
+        let mut buf = [0u8; 4];
+        assert!(uart.read_exact(&mut buf).is_err());
+        assert_eq!(&buf[..2], b"ab");
+        assert_eq!(uart.read(&mut buf[..2]).ok(), Some(2));
+        assert_eq!(&buf[..2], b"cd");
+        assert_eq!(uart.write(b"\x06").ok(), Some(1));
+        assert_eq!(fakes::transmitted(), b"\x06");
+    }
+
+    #[test]
+    fn destination() {
+        let mut buf = [0u8; 4];
+        let progress = Progress::new("rx", &fakes::console([]), None);
+        let mut dst = Dst { buf: &mut buf, off: 0, progress };
+        dst.write_all(b"abc").unwrap();
+        assert!(dst.write_all(b"de").is_err());
+        assert_eq!(dst.off, 4);
+        assert_eq!(dst.write(b"f").ok(), Some(0));
+        assert_eq!(&buf, b"abcd");
+    }
 }
@@ -0,0 +1,394 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! YMODEM batch receive.
+//!
+//! YMODEM is XMODEM with 1KiB blocks and CRC-16, preceded, for
+//! each file, by a block 0 giving its name and size, so that
+//! several files, such as a kernel and its ramdisk, can be sent
+//! in one session.  A block 0 with an empty name ends the batch.
+//!
+//! The exchange for each file is:
+//!
+//! ```text
+//! receiver: C             sender: block 0 (name, size)
+//! receiver: ACK C         sender: blocks 1, 2, ...
+//! receiver: ACK ...       sender: EOT
+//! receiver: NAK           sender: EOT
+//! receiver: ACK
+//! ```
+//!
+//! Each file is landed at the next page boundary after the last,
+//! and trimmed to the size given in its block 0, as the last
+//! block is padded.
+
+use crate::beacon;
+use crate::bldb;
+use crate::println;
+use crate::repl::progress::Progress;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::{self, Uart};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Sent in place of NAK to ask for CRC-16 rather than checksums.
+const CRC: u8 = b'C';
+
+/// How long to wait for each byte of a block.
+const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long to wait for a block to begin.
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the first block 0, in each of
+/// `START_TRIES`, to give time to start the sender.
+const START_TIMEOUT: Duration = Duration::from_secs(3);
+const START_TRIES: usize = 60;
+/// How many bad blocks in a row are tolerated before the
+/// transfer is abandoned.
+const MAX_ERRORS: usize = 16;
+
+/// Files are landed at addresses aligned to this.
+const FILE_ALIGN: usize = 4096;
+
+/// Returns the CRC-16 used by XMODEM and YMODEM: polynomial
+/// 0x1021, initially zero, not reflected.
+pub(super) fn crc16(bs: &[u8]) -> u16 {
+    bs.iter().fold(0, |crc, &b| {
+        (0..8).fold(crc ^ (u16::from(b) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+/// What the sender sent.
+#[derive(Debug, Eq, PartialEq)]
+enum Packet<'a> {
+    Block(u8, &'a [u8]),
+    Eot,
+    Cancel,
+}
+
+/// Reads a packet.  A block whose sequence number and its
+/// complement disagree, or whose CRC is wrong, is an error, as
+/// is a timeout or a line error.
+fn packet<'a>(
+    uart: &mut Uart,
+    timeout: Duration,
+    buf: &'a mut [u8; 1028],
+) -> Result<Packet<'a>> {
+    let len = match uart.try_getb_timeout(timeout)? {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Ok(Packet::Eot),
+        CAN if uart.try_getb_timeout(BYTE_TIMEOUT) == Ok(CAN) => {
+            return Ok(Packet::Cancel);
+        }
+        _ => return Err(Error::Recv),
+    };
+    let buf = &mut buf[..len + 4];
+    for b in buf.iter_mut() {
+        *b = uart.try_getb_timeout(BYTE_TIMEOUT)?;
+    }
+    let (seq, data) = (buf[0], &buf[2..len + 2]);
+    let crc = u16::from_be_bytes([buf[len + 2], buf[len + 3]]);
+    if seq != !buf[1] || crc != crc16(data) {
+        return Err(Error::Recv);
+    }
+    Ok(Packet::Block(seq, data))
+}
+
+/// Discards whatever the sender is still sending, after a bad
+/// block, so that it can be asked to start again.
+fn purge(uart: &mut Uart) {
+    while uart.try_getb_timeout(BYTE_TIMEOUT).is_ok() {}
+}
+
+fn cancel(uart: &mut Uart) {
+    let _ = uart.putbs(&[CAN; 5]);
+}
+
+/// Parses a block 0: the file name, NUL terminated, then the
+/// size in decimal, possibly followed by a space and other
+/// attributes.  The size is optional.
+fn header(data: &[u8]) -> (String, Option<usize>) {
+    let nul = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let name = String::from_utf8_lossy(&data[..nul]).into_owned();
+    let rest = data.get(nul + 1..).unwrap_or_default();
+    let end = rest.iter().position(|&b| b == b' ' || b == 0);
+    let size = core::str::from_utf8(&rest[..end.unwrap_or(rest.len())])
+        .ok()
+        .and_then(|size| size.parse().ok());
+    (name, size)
+}
+
+/// A file received, and where it was put in the destination.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Received {
+    name: String,
+    offset: usize,
+    len: usize,
+}
+
+/// Receives a batch of files into consecutive pages of `dst`.
+fn ry(uart: &mut Uart, dst: &mut [u8]) -> Result<Vec<Received>> {
+    println!("receiving to {:#x?}; start the YMODEM sender", dst.as_ptr());
+    // Like XMODEM, YMODEM does not escape its data.
+    let soft_flow = uart::set_soft_flow(false);
+    let result = batch(uart, dst);
+    uart::set_soft_flow(soft_flow);
+    if result.is_err() {
+        cancel(uart);
+    }
+    result
+}
+
+fn batch(uart: &mut Uart, dst: &mut [u8]) -> Result<Vec<Received>> {
+    let mut buf = [0; 1028];
+    let mut files = Vec::new();
+    let mut offset = 0;
+    let mut progress = Progress::new("ry", uart, None);
+    let mut received = 0;
+    loop {
+        let tries = if files.is_empty() { START_TRIES } else { MAX_ERRORS };
+        let timeout =
+            if files.is_empty() { START_TIMEOUT } else { BLOCK_TIMEOUT };
+        let mut attempts = 0;
+        let (name, size) = loop {
+            attempts += 1;
+            if attempts > tries {
+                return Err(Error::Timeout);
+            }
+            uart.putb(CRC);
+            match packet(uart, timeout, &mut buf) {
+                Ok(Packet::Block(0, data)) => break header(data),
+                Ok(Packet::Cancel) => return Err(Error::Recv),
+                // The sender may still be finishing the last file.
+                Ok(Packet::Eot) => uart.putb(ACK),
+                Ok(Packet::Block(..)) => {}
+                Err(e) if e.is(Error::Timeout) => {}
+                Err(_) => purge(uart),
+            }
+        };
+        uart.putb(ACK);
+        if name.is_empty() {
+            break;
+        }
+        uart.putb(CRC);
+        let start = offset.min(dst.len());
+        let file = &mut dst[start..];
+        let len = file_data(uart, file, size, &mut buf, |len| {
+            progress.update(received + len);
+        })
+        .map_err(|e| {
+            e.context("file,received", &[files.len() as u64, received as u64])
+        })?;
+        received += len;
+        files.push(Received { name, offset, len });
+        offset = (offset + len).next_multiple_of(FILE_ALIGN);
+    }
+    progress.finish("Received", received);
+    Ok(files)
+}
+
+/// Receives the data blocks of one file, up to the EOT that
+/// ends it, returning the length of the file.
+fn file_data(
+    uart: &mut Uart,
+    dst: &mut [u8],
+    size: Option<usize>,
+    buf: &mut [u8; 1028],
+    mut progress: impl FnMut(usize),
+) -> Result<usize> {
+    let mut len = 0;
+    let mut next = 1u8;
+    let mut errors = 0;
+    let mut eot = false;
+    loop {
+        match packet(uart, BLOCK_TIMEOUT, buf) {
+            Ok(Packet::Block(seq, data)) if seq == next => {
+                let data = match size {
+                    Some(size) => &data[..data.len().min(size - len.min(size))],
+                    None => data,
+                };
+                let end = len + data.len();
+                if end > dst.len() {
+                    println!("ry: file does not fit in {:#x} bytes", dst.len());
+                    return Err(Error::Recv);
+                }
+                dst[len..end].copy_from_slice(data);
+                len = end;
+                next = next.wrapping_add(1);
+                errors = 0;
+                uart.putb(ACK);
+                progress(len);
+            }
+            // Our ACK was lost, so the sender has sent the same
+            // block again; if it was block 0, it also wants
+            // another 'C' before it starts.
+            Ok(Packet::Block(seq, _)) if seq == next.wrapping_sub(1) => {
+                uart.putb(ACK);
+                if seq == 0 {
+                    uart.putb(CRC);
+                }
+            }
+            Ok(Packet::Block(..)) => return Err(Error::Recv),
+            Ok(Packet::Cancel) => return Err(Error::Recv),
+            // The first EOT is refused, in case it was noise.
+            Ok(Packet::Eot) if !eot => {
+                eot = true;
+                uart.putb(NAK);
+            }
+            Ok(Packet::Eot) => {
+                uart.putb(ACK);
+                return Ok(len);
+            }
+            Err(e) => {
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    return Err(e);
+                }
+                purge(uart);
+                uart.putb(NAK);
+            }
+        }
+    }
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: ry [<dst addr>,<dst len>]");
+        error
+    };
+    let dst = repl::popenv(env)
+        .as_slice_mut(&config.page_table, 0)
+        .map_err(usage)?
+        .unwrap_or_else(|| config.xfer_region_init_mut());
+    config.signal(beacon::Phase::Receiving);
+    let files = ry(config.xfer.as_mut().unwrap_or(&mut config.cons), dst)?;
+    let base = dst.as_ptr().addr();
+    let mut triples = Vec::new();
+    for file in files {
+        let addr = base + file.offset;
+        println!("{}: {:#x} bytes at {addr:#x}", file.name, file.len);
+        triples.push(Value::List(alloc::vec![
+            Value::Str(file.name),
+            Value::Unsigned(addr as u128),
+            Value::Unsigned(file.len as u128),
+        ]));
+    }
+    Ok(Value::List(triples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+    use alloc::vec;
+
+    fn block(seq: u8, data: &[u8]) -> Vec<u8> {
+        let len = if data.len() <= 128 { 128 } else { 1024 };
+        // Data blocks are padded with SUB, and block 0 with NULs.
+        let pad = if seq == 0 { 0 } else { 0x1a };
+        let mut padded = data.to_vec();
+        padded.resize(len, pad);
+        let mut bs = vec![if len == 128 { SOH } else { STX }, seq, !seq];
+        bs.extend_from_slice(&padded);
+        bs.extend_from_slice(&crc16(&padded).to_be_bytes());
+        bs
+    }
+
+    fn header_block(name: &str, size: usize) -> Vec<u8> {
+        let mut data = Vec::from(name.as_bytes());
+        data.push(0);
+        data.extend_from_slice(alloc::format!("{size} 0 644").as_bytes());
+        block(0, &data)
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16(&[]), 0);
+        assert_eq!(
+            header(b"unix\x00300 14621 100644\x00"),
+            ("unix".into(), Some(300))
+        );
+        assert_eq!(header(b"a\x00\x00"), ("a".into(), None));
+        assert_eq!(header(&[0; 128]), (String::new(), None));
+    }
+
+    #[test]
+    fn batches() {
+        let kernel = (0..300).map(|k| k as u8).collect::<Vec<_>>();
+        let ramdisk = [0xa5; 1024];
+        let mut corrupt = block(2, &kernel[128..256]);
+        corrupt[10] ^= 1;
+        let mut uart = fakes::console([
+            Rx::Idle(Duration::from_secs(5)),
+            Rx::Bytes(header_block("unix", kernel.len())),
+            Rx::Bytes(block(1, &kernel[..128])),
+            Rx::Bytes(corrupt),
+            Rx::Idle(Duration::from_secs(2)),
+            Rx::Bytes(block(2, &kernel[128..256])),
+            Rx::Bytes(block(2, &kernel[128..256])),
+            Rx::Bytes(block(3, &kernel[256..])),
+            Rx::bytes(&[EOT, EOT]),
+            Rx::Bytes(header_block("ramdisk", ramdisk.len())),
+            Rx::Bytes(block(1, &ramdisk)),
+            Rx::bytes(&[EOT, EOT]),
+            Rx::Bytes(block(0, &[])),
+        ]);
+        let mut dst = vec![0u8; 2 * FILE_ALIGN];
+        let files = ry(&mut uart, &mut dst).unwrap();
+        assert!(fakes::exhausted());
+        assert_eq!(
+            files,
+            [
+                Received { name: "unix".into(), offset: 0, len: 300 },
+                Received { name: "ramdisk".into(), offset: 4096, len: 1024 },
+            ]
+        );
+        assert_eq!(&dst[..300], &kernel[..]);
+        assert!(dst[300..4096].iter().all(|&b| b == 0));
+        assert_eq!(&dst[4096..5120], &ramdisk[..]);
+        // Pick the protocol out from the messages around it.
+        let tx = fakes::transmitted();
+        let start = tx.iter().position(|&b| b == b'\n').unwrap();
+        let protocol = tx[start..]
+            .iter()
+            .copied()
+            .filter(|b| [CRC, ACK, NAK].contains(b))
+            .collect::<Vec<_>>();
+        let expected = [
+            // The sender is started after the first 'C'.
+            &[CRC, CRC][..],
+            &[ACK, CRC, ACK, NAK, ACK, ACK, ACK, NAK, ACK],
+            &[CRC, ACK, CRC, ACK, NAK, ACK],
+            &[CRC, ACK],
+        ]
+        .concat();
+        assert_eq!(protocol, expected);
+    }
+
+    #[test]
+    fn too_big() {
+        let mut uart = fakes::console([
+            Rx::Bytes(header_block("unix", 256)),
+            Rx::Bytes(block(1, &[1; 128])),
+            Rx::Bytes(block(2, &[2; 128])),
+        ]);
+        let mut dst = vec![0u8; 200];
+        assert_eq!(
+            ry(&mut uart, &mut dst).map_err(|e| e.as_str()),
+            Err("Receive failed")
+        );
+        assert!(fakes::transmitted().ends_with(&[CAN; 5]));
+    }
+}
@@ -2,12 +2,19 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::beacon;
 use crate::bldb;
+use crate::crc32;
+use crate::entropy;
 use crate::println;
+use crate::repl::progress::Progress;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
+use crate::sink::{self, Level};
 use crate::uart::Uart;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
 use zmodem2::{Read, Write};
 
 use core::result::Result as ZResult;
@@ -33,9 +40,62 @@ impl Write for Uart {
     }
 }
 
+/// How many times a CRC failure is tolerated, resuming the
+/// receive to let the sender retransmit, before the transfer is
+/// abandoned.
+const MAX_CRC_RETRIES: usize = 16;
+
+/// What happened during the last ZMODEM receive, kept so that a
+/// failed transfer or a corrupt image can be diagnosed after the
+/// fact.  Each block received, and each error, is also logged at
+/// debug level, and so ends up in the in-memory log but not on
+/// the console, where it would disrupt the transfer.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RzStats {
+    /// A random identifier for the receive, logged as it starts
+    /// and with each error, to pick its records out of the log.
+    session: u64,
+    addr: usize,
+    blocks: usize,
+    bytes: usize,
+    /// The CRC-32 of the data as it arrived, before anything
+    /// else could disturb it in memory.
+    crc: u32,
+    /// The SHA-256 digest of the data as it arrived, if asked
+    /// for.
+    sha256: Option<[u8; 32]>,
+    crc_errors: usize,
+    other_errors: usize,
+    /// The last error, and the offset at which it happened.
+    last_error: Option<(usize, zmodem2::Error)>,
+    done: bool,
+}
+
 struct SliceVec<'a> {
     buf: &'a mut [u8],
     off: usize,
+    blocks: usize,
+    crc: u32,
+    /// Hashes the data as it is written, if a digest is wanted,
+    /// so that it need not be read back afterwards.
+    sha: Option<Sha256>,
+}
+
+impl<'a> SliceVec<'a> {
+    fn new(buf: &'a mut [u8]) -> SliceVec<'a> {
+        SliceVec { buf, off: 0, blocks: 0, crc: 0, sha: None }
+    }
+
+    fn with_sha256(buf: &'a mut [u8]) -> SliceVec<'a> {
+        SliceVec { sha: Some(Sha256::new()), ..SliceVec::new(buf) }
+    }
+
+    fn update(&mut self, src: &[u8]) {
+        self.crc = crc32::update(self.crc, src);
+        if let Some(sha) = self.sha.as_mut() {
+            sha.update(src);
+        }
+    }
 }
 
 impl<'a> Write for SliceVec<'a> {
@@ -46,6 +106,7 @@ impl<'a> Write for SliceVec<'a> {
         }
         dst[0] = b;
         self.off += 1;
+        self.update(&[b]);
         Ok(())
     }
 
@@ -56,34 +117,237 @@ impl<'a> Write for SliceVec<'a> {
         }
         let dst = &mut dst[..src.len()];
         dst.copy_from_slice(src);
+        sink::emit(
+            Level::Debug,
+            format_args!(
+                "rz: block {} at {:#x}, {:#x} bytes, crc32 {:08x}\n",
+                self.blocks,
+                self.off,
+                src.len(),
+                crc32::crc32(src)
+            ),
+        );
         self.off += src.len();
+        self.blocks += 1;
+        self.update(src);
         Ok(())
     }
 }
 
-fn rz(uart: &mut Uart, dst: &mut [u8]) -> Result<usize> {
+/// Receives a file into `dst`, returning its size and, if asked
+/// for, the SHA-256 digest of the data received.
+fn rz(
+    uart: &mut Uart,
+    dst: &mut [u8],
+    digest: bool,
+    stats: &mut RzStats,
+) -> Result<(usize, Option<[u8; 32]>)> {
     println!("receiving to {:#x?}", dst.as_ptr());
-    let mut state = zmodem2::State::new();
-    let mut v = SliceVec { buf: dst, off: 0 };
-    while state.stage() != zmodem2::Stage::Done {
-        if let Err(e) = zmodem2::receive(uart, &mut v, &mut state) {
-            println!("zmodem error: {e:?}");
-            return Err(Error::Recv);
+    let session = entropy::session_id();
+    let addr = dst.as_ptr().addr();
+    *stats = RzStats { session, addr, ..RzStats::default() };
+    sink::emit(
+        Level::Debug,
+        format_args!("rz: session {session:016x} at {addr:#x}\n"),
+    );
+    // The protocol state includes its frame buffers, so is
+    // written straight into an uninitialized allocation rather
+    // than built on the stack and moved.
+    let mut state = Box::write(Box::new_uninit(), zmodem2::State::new());
+    let mut progress = Progress::new("rz", uart, None);
+    let mut v =
+        if digest { SliceVec::with_sha256(dst) } else { SliceVec::new(dst) };
+    let result = loop {
+        if state.stage() == zmodem2::Stage::Done {
+            break Ok(());
+        }
+        if state.file_size() != 0 {
+            progress.set_total(state.file_size() as usize);
+        }
+        progress.update(v.off);
+        let Err(e) = zmodem2::receive(uart, &mut v, &mut state) else {
+            continue;
+        };
+        stats.last_error = Some((v.off, e));
+        let crc = matches!(
+            e,
+            zmodem2::Error::UnexpectedCrc16 | zmodem2::Error::UnexpectedCrc32
+        );
+        if crc {
+            stats.crc_errors += 1;
+        } else {
+            stats.other_errors += 1;
+        }
+        sink::emit(
+            Level::Debug,
+            format_args!(
+                "rz: session {:016x}: {e:?} at {:#x}\n",
+                stats.session, v.off
+            ),
+        );
+        if !crc || stats.crc_errors > MAX_CRC_RETRIES {
+            break Err(e);
         }
+    };
+    stats.blocks = v.blocks;
+    stats.bytes = v.off;
+    stats.crc = v.crc;
+    if let Err(e) = result {
+        println!("zmodem error: {e:?}");
+        return Err(Error::Recv.context(
+            "received,buffer len",
+            &[v.off as u64, v.buf.len() as u64],
+        ));
     }
-    Ok(state.file_size().try_into().unwrap())
+    stats.done = true;
+    stats.sha256 = v.sha.take().map(|sha| sha.finalize().into());
+    let nrecv = state.file_size().try_into().unwrap();
+    progress.finish("Received", nrecv);
+    Ok((nrecv, stats.sha256))
 }
 
 pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: rz <dst addr>,<dst len>");
+        println!("usage: rz [-c] <dst addr>,<dst len>");
         error
     };
-    let dst = repl::popenv(env)
+    let mut arg = repl::popenv(env);
+    let digest = arg.as_string().is_ok_and(|flag| flag == "-c");
+    if digest {
+        arg = repl::popenv(env);
+    }
+    let dst = arg
         .as_slice_mut(&config.page_table, 0)
         .map_err(usage)?
-        .unwrap_or_else(|| bldb::xfer_region_init_mut());
-    let nrecv = rz(&mut config.cons, dst)?;
-    println!("\n\nReceived {nrecv} bytes");
-    Ok(Value::Slice(&dst[..nrecv]))
+        .unwrap_or_else(|| config.xfer_region_init_mut());
+    config.signal(beacon::Phase::Receiving);
+    let (nrecv, sha256) = rz(
+        config.xfer.as_mut().unwrap_or(&mut config.cons),
+        dst,
+        digest,
+        &mut config.rz_stats,
+    )?;
+    let received = Value::Slice(config.page_table.buf(&dst[..nrecv]));
+    match sha256 {
+        Some(hash) => {
+            let hash = Value::Sha256(hash);
+            println!("sha256: {hash:?}");
+            Ok(Value::List(alloc::vec![received, hash]))
+        }
+        None => Ok(received),
+    }
+}
+
+/// Shows what happened during the last ZMODEM receive, and
+/// checks whether the received data has changed in memory since
+/// it arrived: if the CRC-32 of the data as it arrived matches
+/// that of the file sent, but the data in memory no longer
+/// does, the fault is in memory rather than on the line.
+pub(super) fn stats(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let stats = &config.rz_stats;
+    if stats.addr == 0 {
+        println!("rzstats: nothing received");
+        return Ok(Value::Nil);
+    }
+    println!(
+        "session {:016x} {} at {:#x}: {} blocks, {:#x} bytes",
+        stats.session,
+        if stats.done { "received" } else { "failed" },
+        stats.addr,
+        stats.blocks,
+        stats.bytes
+    );
+    println!("errors: {} CRC, {} other", stats.crc_errors, stats.other_errors);
+    if let Some((off, e)) = stats.last_error {
+        println!("last error: {e:?} at {off:#x}");
+    }
+    println!("crc32 as received: {:08x}", stats.crc);
+    if let Some(hash) = stats.sha256 {
+        println!("sha256 as received: {:?}", Value::Sha256(hash));
+    }
+    let region = Value::Pair(stats.addr, stats.bytes);
+    match region.as_slice(&config.page_table, 0) {
+        Ok(Some(data)) => {
+            let now = crc32::crc32(data);
+            let verdict = if now == stats.crc { "same" } else { "CHANGED" };
+            println!("crc32 in memory:   {now:08x} ({verdict})");
+        }
+        _ => println!("crc32 in memory:   unavailable (unmapped)"),
+    }
+    Ok(Value::Unsigned(stats.crc.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+
+    #[test]
+    fn port_errors() {
+        let mut uart = fakes::console([
+            Rx::bytes(b"ab"),
+            Rx::Error(Error::UartFifoOverrun),
+            Rx::bytes(b"cd"),
+        ]);
+        let mut buf = [0u8; 4];
+        assert!(matches!(
+            Read::read(&mut uart, &mut buf),
+            Err(zmodem2::Error::Read)
+        ));
+        assert_eq!(&buf[..2], b"ab");
+        assert_eq!(uart.read_byte().ok(), Some(b'c'));
+        assert_eq!(uart.read_byte().ok(), Some(b'd'));
+        assert!(fakes::exhausted());
+        Write::write_all(&mut uart, b"xy").unwrap();
+        uart.write_byte(b'z').unwrap();
+        assert_eq!(fakes::transmitted(), b"xyz");
+    }
+
+    #[test]
+    fn line_error() {
+        // Line noise, and then a framing error while waiting for
+        // the sender's first header.
+        let mut uart =
+            fakes::console([Rx::bytes(b"x"), Rx::Error(Error::UartFraming)]);
+        let mut buf = [0u8; 128];
+        let mut stats = RzStats::default();
+        assert_eq!(
+            rz(&mut uart, &mut buf, false, &mut stats).map_err(|e| e.as_str()),
+            Err(Error::Recv.as_str())
+        );
+        assert!(fakes::exhausted());
+        assert_eq!(stats.addr, buf.as_ptr().addr());
+        assert_eq!((stats.crc_errors, stats.other_errors), (0, 1));
+        assert!(stats.last_error.is_some());
+        assert!(!stats.done);
+    }
+
+    #[test]
+    fn destination_bounds() {
+        let mut buf = [0u8; 4];
+        let mut v = SliceVec::new(&mut buf);
+        v.write_all(b"abc").unwrap();
+        assert!(v.write_all(b"de").is_err());
+        v.write_byte(b'd').unwrap();
+        assert!(v.write_byte(b'e').is_err());
+        assert_eq!(v.off, 4);
+        assert_eq!((v.blocks, v.crc), (1, crc32::crc32(b"abcd")));
+        assert!(v.sha.is_none());
+        assert_eq!(&buf, b"abcd");
+    }
+
+    #[test]
+    fn streamed_digest() {
+        let mut buf = [0u8; 8];
+        let mut v = SliceVec::with_sha256(&mut buf);
+        v.write_all(b"abc").unwrap();
+        v.write_byte(b'd').unwrap();
+        assert!(v.write_all(b"efghi").is_err());
+        v.write_all(b"efgh").unwrap();
+        let digest: [u8; 32] = v.sha.take().unwrap().finalize().into();
+        assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(b"abcdefgh")));
+    }
 }
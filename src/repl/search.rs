@@ -0,0 +1,225 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Searching memory for a pattern of bytes.
+//!
+//! Only pages mapped readable are searched: the range is split
+//! into runs of contiguous mapped pages, each searched on its
+//! own, so that a search over a wide range, such as all of the
+//! address space a kernel was loaded into, skips the holes in
+//! it rather than faulting.  A match cannot span a hole.
+
+use crate::bldb;
+use crate::cons;
+use crate::mem;
+use crate::println;
+use crate::repl::{self, Value, memory};
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// How many matches `search` shows by default.
+const DEFAULT_COUNT: usize = 32;
+
+/// How many positions to try between polls for cancellation.
+const CHUNK_LEN: usize = 1024 * 1024;
+
+/// Returns the runs of pages within `range` for which `readable`
+/// is true, clipped to the range.
+fn runs(
+    range: Range<usize>,
+    readable: impl Fn(usize) -> bool,
+) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    let mut page = mem::round_down_4k(range.start);
+    while page < range.end {
+        let next = page.saturating_add(4096);
+        if readable(page) {
+            let run = page.max(range.start)..next.min(range.end);
+            match runs.last_mut() {
+                Some(last) if last.end == run.start => last.end = run.end,
+                _ => runs.push(run),
+            }
+        }
+        page = next;
+    }
+    runs
+}
+
+/// Calls `found` with the offset of each occurrence of `pattern`
+/// in `hay`, polling for cancellation as it goes, until `found`
+/// returns false.  Returns false if the search was stopped.
+fn find(
+    hay: &[u8],
+    pattern: &[u8],
+    poll: &mut dyn FnMut() -> Result<()>,
+    mut found: impl FnMut(usize) -> bool,
+) -> Result<bool> {
+    for (offset, window) in hay.windows(pattern.len()).enumerate() {
+        if offset % CHUNK_LEN == 0 {
+            poll()?;
+        }
+        if window == pattern && !found(offset) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Takes the pattern from the remaining arguments: with `-s`, the
+/// words of a string, joined by single spaces; otherwise, bytes
+/// as for `pokepat`.
+fn parse_pattern(env: &mut Vec<Value>) -> Result<Vec<u8>> {
+    let mut args = Vec::new();
+    loop {
+        match repl::popenv(env) {
+            Value::Nil => break,
+            arg => args.push(arg),
+        }
+    }
+    match args.split_first() {
+        Some((flag, words)) if flag.as_string().is_ok_and(|f| f == "-s") => {
+            let words = words
+                .iter()
+                .map(Value::as_string)
+                .collect::<Result<Vec<String>>>()?;
+            if words.is_empty() {
+                return Err(Error::BadArgs);
+            }
+            Ok(words.join(" ").into_bytes())
+        }
+        _ => memory::parse_pattern(&args),
+    }
+}
+
+pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!(
+            "usage: search [-n <count>] <addr>,<len> <pattern bytes>...\n       \
+             search [-n <count>] <addr>,<len> -s <text>..."
+        );
+        error
+    };
+    let mut count = DEFAULT_COUNT;
+    let mut arg = repl::popenv(env);
+    if arg.as_string().is_ok_and(|flag| flag == "-n") {
+        count = repl::popenv(env).as_num::<usize>().map_err(usage)?;
+        arg = repl::popenv(env);
+    }
+    let (ptr, len) = arg.as_ptr_len(&config.page_table).map_err(usage)?;
+    let pattern = parse_pattern(env).map_err(usage)?;
+    let start = ptr.addr();
+    let end = start.checked_add(len).ok_or(Error::NumRange)?;
+    if !mem::is_canonical_range(start, end) {
+        return Err(Error::PtrNonCanon);
+    }
+    let runs = runs(start..end, |page| {
+        let page = page as *const ();
+        config.page_table.is_region_readable(mem::page_range_raw(page, 1))
+    });
+    let searched = runs.iter().map(|run| run.len()).sum::<usize>();
+    if searched < len {
+        println!("search: skipping {:#x} unmapped bytes", len - searched);
+    }
+    let mut poll = cons::poller(&mut config.cons);
+    let mut matches = Vec::new();
+    let mut more = false;
+    for run in runs {
+        let hay = unsafe {
+            core::slice::from_raw_parts(run.start as *const u8, run.len())
+        };
+        let done = find(hay, &pattern, &mut poll, |offset| {
+            if matches.len() == count {
+                more = true;
+                return false;
+            }
+            let addr = run.start + offset;
+            println!("{addr:#x}");
+            matches.push(Value::Unsigned(addr as u128));
+            true
+        })?;
+        if !done {
+            break;
+        }
+    }
+    match matches.len() {
+        0 => println!("search: no matches"),
+        n if more => println!("search: stopped after {n} matches"),
+        _ => {}
+    }
+    Ok(Value::List(matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use alloc::format;
+
+    #[test]
+    fn page_runs() {
+        let mapped = |page: usize| page != 0x3000 && page < 0x6000;
+        assert_eq!(
+            runs(0x1800..0x5100, mapped),
+            [0x1800..0x3000, 0x4000..0x5100]
+        );
+        assert!(runs(0x3000..0x3800, mapped).is_empty());
+        let run = runs(0x5f00..0x7000, mapped);
+        assert_eq!(run.first(), Some(&(0x5f00..0x6000)));
+        assert_eq!(run.len(), 1);
+        assert!(runs(0x1000..0x1000, mapped).is_empty());
+    }
+
+    #[test]
+    fn finding() {
+        let hay = b"abcabcab";
+        let mut offsets = Vec::new();
+        let mut poll = || Ok(());
+        let done = find(hay, b"ab", &mut poll, |offset| {
+            offsets.push(offset);
+            offsets.len() < 2
+        });
+        assert_eq!(done, Ok(false));
+        assert_eq!(offsets, [0, 3]);
+        assert_eq!(find(hay, b"abcd", &mut poll, |_| true), Ok(true));
+        let done = find(b"", b"a", &mut poll, |_| false);
+        assert_eq!(done, Ok(true));
+    }
+
+    #[test]
+    fn searches() {
+        // The pattern takes every argument left on the stack, so
+        // each search is made in a fresh session.
+        let fresh = || {
+            let mut sim = Sim::new();
+            let ram = sim.ram();
+            ram[0x1ffe..0x2002].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+            ram[0x3000..0x3005].copy_from_slice(b"hi bo");
+            ram[0x3100..0x3105].copy_from_slice(b"hi bo");
+            let addr = ram.as_ptr().addr();
+            (sim, addr)
+        };
+
+        let (mut sim, addr) = fresh();
+        let out = sim.session(&format!("search {addr:#x},0x4000 deadbeef\n"));
+        assert!(out.contains(&format!("{:#x}\n", addr + 0x1ffe)), "{out}");
+
+        let (mut sim, addr) = fresh();
+        let start = addr - 0x1000;
+        let script = format!("search -n 1 {start:#x},0x5000 -s hi bo\n");
+        let out = sim.session(&script);
+        assert!(out.contains("skipping 0x1000 unmapped bytes"), "{out}");
+        assert!(out.contains(&format!("{:#x}\n", addr + 0x3000)), "{out}");
+        assert!(!out.contains(&format!("{:#x}\n", addr + 0x3100)), "{out}");
+        assert!(out.contains("stopped after 1 matches"), "{out}");
+
+        let (mut sim, addr) = fresh();
+        let out = sim.session(&format!("search {addr:#x},0x100 0xfeed\n"));
+        assert!(out.contains("no matches"), "{out}");
+        let (mut sim, addr) = fresh();
+        let out = sim.session(&format!("search {addr:#x},0x100 -s\n"));
+        assert!(out.contains("usage: search"), "{out}");
+    }
+}
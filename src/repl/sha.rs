@@ -3,12 +3,17 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::bldb;
+use crate::cons;
+use crate::ident;
 use crate::println;
 use crate::ramdisk;
 use crate::repl::{self, Value};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 
+/// How much memory to hash between polls for cancellation.
+const CHUNK_LEN: usize = 1024 * 1024;
+
 pub fn mem(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     use sha2::{Digest, Sha256};
     let usage = |error| {
@@ -19,8 +24,12 @@ pub fn mem(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
         .as_slice(&config.page_table, 0)
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
+    let mut poll = cons::poller(&mut config.cons);
     let mut sum = Sha256::new();
-    sum.update(bs);
+    for chunk in bs.chunks(CHUNK_LEN) {
+        poll()?;
+        sum.update(chunk);
+    }
     let hash = sum.finalize();
     Ok(Value::Sha256(hash.into()))
 }
@@ -33,7 +42,61 @@ pub fn run(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
             return Err(Error::BadArgs);
         }
     };
-    let fs = config.ramdisk.as_ref().ok_or(Error::FsNoRoot)?;
-    let hash = ramdisk::sha256(fs.as_ref(), &path)?;
+    let (fs, path) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let mut poll = cons::poller(&mut config.cons);
+    let hash = ramdisk::sha256(fs, &path, &mut poll)?;
     Ok(Value::Sha256(hash))
 }
+
+/// Checks the loader's text and read-only data against the
+/// digest recorded in the image when it was packaged.
+pub fn selfsum(
+    _config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let ident = ident::ident();
+    let (digest, ok) = ident.check(bldb::loader_readonly());
+    if !ident.is_packaged() {
+        println!("selfsum: image was not packaged; no digest to check");
+        return Ok(Value::Sha256(digest));
+    }
+    println!("build {}", ident.build_id);
+    if !ok {
+        println!("selfsum: text and read-only data do not match the image");
+        return Err(Error::Verify);
+    }
+    println!("selfsum: ok");
+    Ok(Value::Sha256(digest))
+}
+
+pub fn verifyfs(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: verifyfs <manifest>");
+        error
+    };
+    let manifest = repl::popenv(env).as_string().map_err(usage)?;
+    let (fs, manifest) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &manifest,
+    )?;
+    let mut poll = cons::poller(&mut config.cons);
+    let v = ramdisk::verify(fs, &manifest, &mut poll)?;
+    println!(
+        "{} ok, {} mismatched, {} missing, {} unreadable",
+        v.good, v.bad, v.missing, v.failed
+    );
+    if v.bad != 0 || v.missing != 0 || v.failed != 0 {
+        return Err(Error::Verify);
+    }
+    Ok(Value::Unsigned(v.good as u128))
+}
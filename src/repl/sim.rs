@@ -0,0 +1,284 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A loopback simulator for the REPL.
+//!
+//! The reader, evaluator, and command registry run on the host,
+//! talking to the scripted fake console and clock in place of
+//! the UART and time stamp counter.  A buffer of host memory,
+//! mapped by the loader page table, stands in for RAM, so that
+//! commands that read and write memory work as they do
+//! on a machine, and I/O ports and MSRs are simulated behind
+//! `hw::Hardware`.  Commands that touch other hardware, such as
+//! control registers or SMN, are not simulated and must not be
+//! run.
+//!
+//! Sessions are exercised in tests below.  To drive the REPL by
+//! hand, run `cargo xtask sim`, which reads lines from standard
+//! input and writes what the console transmits to standard
+//! output.
+
+use super::Value;
+use crate::bldb;
+use crate::cons;
+use crate::fakes::{self, Rx};
+use crate::hw;
+use crate::mem;
+use crate::mmu;
+use crate::ramdisk;
+use crate::repl;
+use crate::symbols;
+use crate::tpm;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// The size of the simulated RAM.
+const RAM_LEN: usize = 64 * 1024;
+
+/// The physical address at which the simulated RAM is mapped,
+/// as host addresses are not valid physical addresses.
+const RAM_PA: u64 = 0x10_0000;
+
+/// Simulated I/O ports and MSRs, each of which reads back what
+/// was last written to it.  Ports never written read as all
+/// ones, as from an empty bus, and MSRs as zero.
+#[derive(Default)]
+struct Hardware {
+    #[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+    ports: RefCell<BTreeMap<u16, u32>>,
+    msrs: RefCell<BTreeMap<u32, u64>>,
+}
+
+#[cfg_attr(not(feature = "cmd-hw"), allow(dead_code))]
+impl Hardware {
+    fn port(&self, port: u16) -> u32 {
+        self.ports.borrow().get(&port).copied().unwrap_or(!0)
+    }
+
+    fn set_port(&self, port: u16, value: u32) {
+        self.ports.borrow_mut().insert(port, value);
+    }
+}
+
+impl hw::Hardware for Hardware {
+    unsafe fn inb(&self, port: u16) -> u8 {
+        self.port(port) as u8
+    }
+
+    unsafe fn inw(&self, port: u16) -> u16 {
+        self.port(port) as u16
+    }
+
+    unsafe fn inl(&self, port: u16) -> u32 {
+        self.port(port)
+    }
+
+    unsafe fn outb(&self, port: u16, value: u8) {
+        self.set_port(port, value.into());
+    }
+
+    unsafe fn outw(&self, port: u16, value: u16) {
+        self.set_port(port, value.into());
+    }
+
+    unsafe fn outl(&self, port: u16, value: u32) {
+        self.set_port(port, value);
+    }
+
+    unsafe fn rdmsr(&self, msr: u32) -> u64 {
+        self.msrs.borrow().get(&msr).copied().unwrap_or(0)
+    }
+
+    unsafe fn wrmsr(&self, msr: u32, value: u64) {
+        self.msrs.borrow_mut().insert(msr, value);
+    }
+}
+
+/// A simulated machine, and the state of a REPL session on it.
+pub(super) struct Sim {
+    config: Box<bldb::Config>,
+    env: Vec<Value>,
+    val: Value,
+    ram: &'static mut [u8],
+}
+
+impl Sim {
+    pub(super) fn new() -> Sim {
+        let layout = std::alloc::Layout::from_size_align(RAM_LEN, 4096)
+            .expect("valid layout");
+        let ram = unsafe {
+            let ptr = std::alloc::alloc_zeroed(layout);
+            assert!(!ptr.is_null(), "allocated simulated RAM");
+            core::slice::from_raw_parts_mut(ptr, RAM_LEN)
+        };
+        let start = ram.as_ptr().addr();
+        let empty = mem::V4KA::new(0)..mem::V4KA::new(0);
+        let mut page_table =
+            mmu::LoaderPageTable::new(mmu::PageTable::new(), &[], &[]);
+        unsafe {
+            page_table
+                .map_region(
+                    mem::V4KA::new(start)..mem::V4KA::new(start + RAM_LEN),
+                    mem::Attrs::new_data(),
+                    mem::P4KA::new(RAM_PA),
+                )
+                .expect("mapped simulated RAM");
+        }
+        let config = Box::new(bldb::Config {
+            cons: fakes::console([]),
+            iomux: Box::leak(unsafe { Box::new_zeroed().assume_init() }),
+            gpios: Box::leak(unsafe { Box::new_zeroed().assume_init() }),
+            hw: Box::leak(Box::new(Hardware::default())),
+            board: None,
+            loader_region: empty.clone(),
+            xfer_region: empty.clone(),
+            ramdisk_region: empty.clone(),
+            scratch_region: empty,
+            regions_claimed: false,
+            page_table,
+            mounts: ramdisk::Mounts::default(),
+            builtin: None,
+            prompt: cons::DEFAULT_PROMPT,
+            prompt_segments: Vec::new(),
+            last_failed: false,
+            aliases: BTreeMap::new(),
+            beacon: None,
+            bootenv: None,
+            idle: None,
+            access_log: repl::AccessLog::default(),
+            images: Vec::new(),
+            modified: repl::Modified::default(),
+            confirm: true,
+            ipcc: None,
+            xfer: None,
+            measurements: tpm::Measurements::default(),
+            dumps: repl::Dumps::default(),
+            rz_stats: repl::RzStats::default(),
+            results: repl::Results::default(),
+            symbols: symbols::Symbols::default(),
+        });
+        Sim { config, env: Vec::new(), val: Value::Nil, ram }
+    }
+
+    /// Returns the simulated RAM.
+    pub(super) fn ram(&mut self) -> &mut [u8] {
+        self.ram
+    }
+
+    /// Types the given lines at the console, and returns what
+    /// the console transmitted in response, with line endings
+    /// converted to newlines.
+    pub(super) fn session(&mut self, lines: &str) -> String {
+        self.config.cons = fakes::console([Rx::bytes(lines.as_bytes())]);
+        while !fakes::exhausted() {
+            super::rep(&mut self.config, &mut self.env, &mut self.val);
+        }
+        String::from_utf8_lossy(&fakes::transmitted()).replace("\r\n", "\n")
+    }
+
+    /// Types the given lines at the console while `f` runs with
+    /// the configuration, as for a nested session started by
+    /// code that the REPL called, and returns its result with
+    /// what the console transmitted.
+    pub(super) fn console<R>(
+        &mut self,
+        lines: &str,
+        f: impl FnOnce(&mut bldb::Config) -> R,
+    ) -> (R, String) {
+        self.config.cons = fakes::console([Rx::bytes(lines.as_bytes())]);
+        let r = f(&mut self.config);
+        let out = fakes::transmitted();
+        (r, String::from_utf8_lossy(&out).replace("\r\n", "\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[cfg(feature = "cmd-debugger")]
+    #[test]
+    fn arithmetic_and_stack() {
+        let mut sim = Sim::new();
+        let out = sim.session("getbits 4,8 0xa5\n");
+        assert_eq!(out, "@getbits 4,8 0xa5\nres: 0xa\n");
+        let out = sim.session("bswap16 0x1234 . @popcount\n");
+        assert!(out.ends_with("res: 0x3412\n"), "{out}");
+        let out = sim.session("nosuchcommand\n");
+        assert!(out.contains("Unknown command"), "{out}");
+        assert!(sim.config.last_failed);
+    }
+
+    #[test]
+    fn memory() {
+        let mut sim = Sim::new();
+        let addr = sim.ram().as_ptr().addr();
+        let script = format!(
+            "poke {addr:#x},4 0xfeedface\n\
+             dd if={addr:#x},4 of={:#x},4\n\
+             poke {:#x},2 0x2211\n\
+             peek {addr:#x},1\n",
+            addr + 0x100,
+            addr + 0x10,
+        );
+        let out = sim.session(&script);
+        assert!(out.contains("copied 0x4 bytes"), "{out}");
+        assert!(out.ends_with("res: 0xce\n"), "{out}");
+        assert_eq!(&sim.ram()[..4], &[0xce, 0xfa, 0xed, 0xfe]);
+        assert_eq!(&sim.ram()[0x10..0x12], &[0x11, 0x22]);
+        assert_eq!(&sim.ram()[0x100..0x104], &[0xce, 0xfa, 0xed, 0xfe]);
+    }
+
+    #[test]
+    fn msrs() {
+        let mut sim = Sim::new();
+        let out = sim.session("wrmsr IA32_FS_BASE 0x1234\nrdmsr 0xc0000100\n");
+        assert!(out.ends_with("res: 0x1234\n"), "{out}");
+        let out = sim.session("rdmsr IA32_GS_BASE\n");
+        assert!(out.ends_with("res: 0x0\n"), "{out}");
+    }
+
+    #[cfg(feature = "cmd-hw")]
+    #[test]
+    fn ports() {
+        let mut sim = Sim::new();
+        let out = sim.session("inb 0x80\n");
+        assert!(out.ends_with("res: 0xff\n"), "{out}");
+        let out = sim.session("outw 0x80 0x1234\ninb 0x80\n");
+        assert!(out.ends_with("res: 0x34\n"), "{out}");
+    }
+
+    #[test]
+    fn unmapped() {
+        let mut sim = Sim::new();
+        let out = sim.session("peek 0x10\n");
+        assert!(out.contains("eval:"), "{out}");
+        assert!(sim.config.last_failed);
+    }
+
+    /// Runs a session typed at standard input, a line at a time.
+    /// This is not run with the other tests; see `cargo xtask sim`.
+    #[test]
+    #[ignore]
+    fn interactive() {
+        use std::io::{BufRead, Write};
+        let mut sim = Sim::new();
+        let ram = sim.ram().as_ptr_range();
+        println!(
+            "simulated RAM at {:#x}..{:#x}",
+            ram.start.addr(),
+            ram.end.addr()
+        );
+        let mut stdout = std::io::stdout();
+        for line in std::io::stdin().lock().lines() {
+            let line = line.expect("read standard input");
+            let _ = stdout.write_all(sim.session(&(line + "\n")).as_bytes());
+            let _ = stdout.flush();
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use crate::sink::{self, Level, Sink};
+use alloc::vec::Vec;
+
+fn show() {
+    for s in Sink::ALL {
+        let level = sink::level(s).map_or("off", Level::name);
+        match s {
+            Sink::Log => {
+                println!(
+                    "{:<6} {level:<6} {} bytes held",
+                    s.name(),
+                    sink::logged()
+                )
+            }
+            _ => println!("{:<6} {level}", s.name()),
+        }
+    }
+}
+
+pub(super) fn run(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: sinks [<sink> off|error|warn|info|debug]");
+        error
+    };
+    let arg = repl::popenv(env);
+    if let Value::Nil = arg {
+        show();
+        return Ok(Value::Nil);
+    }
+    let name = arg.as_string().map_err(usage)?;
+    let s = Sink::from_name(&name)
+        .ok_or_else(|| usage(arg.bad_arg("uart or log")))?;
+    let arg = repl::popenv(env);
+    let level = match arg.as_string().map_err(usage)?.as_str() {
+        "off" => None,
+        name => Some(
+            Level::from_name(name)
+                .ok_or_else(|| usage(arg.bad_arg("off or a level")))?,
+        ),
+    };
+    if s == Sink::Uart && level.is_none_or(|l| l < Level::Info) {
+        println!("sinks: ordinary output will no longer reach the console");
+    }
+    sink::set_level(s, level);
+    Ok(Value::Nil)
+}
@@ -4,13 +4,28 @@
 
 use crate::bldb;
 use crate::println;
-use crate::repl;
+use crate::regdefs::{self, Space};
+use crate::repl::{self, Access, memory, numfmt};
 use crate::result::Result;
 use crate::smn;
 use alloc::vec::Vec;
 
+/// Displays a value read from an SMN address, with the IP block
+/// and register the address decodes to, if known.
+fn show(addr: u32, data: u32) {
+    let value = numfmt::sized(data, 4);
+    let name = regdefs::find(Space::Smn, addr.into(), 4).map(|def| def.name);
+    match (smn::decode(addr), name) {
+        (Some(decoded), Some(name)) => {
+            println!("{addr:#x} {value} ({decoded} {name})")
+        }
+        (Some(decoded), None) => println!("{addr:#x} {value} ({decoded})"),
+        (None, _) => println!("{addr:#x} {value}"),
+    }
+}
+
 pub(super) fn read(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -18,13 +33,20 @@ pub(super) fn read(
         error
     };
     let addr = repl::popenv(env).as_num::<u32>().map_err(usage)?;
+    memory::guard(Space::Smn, addr.into(), 4, false, false)?;
     let data = smn::read(smn::Index::Smn0, addr).map_err(usage)?;
-    println!("{addr:#x} {data:#x}");
+    config.access_log.record(Access::Smn {
+        index: 0,
+        addr,
+        value: data,
+        write: false,
+    });
+    show(addr, data);
     Ok(repl::Value::Unsigned(data.into()))
 }
 
 pub(super) fn write(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -33,14 +55,22 @@ pub(super) fn write(
     };
     let addr = repl::popenv(env).as_num::<u32>().map_err(usage)?;
     let value = repl::popenv(env).as_num::<u32>().map_err(usage)?;
+    memory::guard(Space::Smn, addr.into(), 4, true, false)?;
     unsafe {
         smn::write(smn::Index::Smn0, addr, value)?;
     }
+    config.modified.smn(0, addr, value);
+    config.access_log.record(Access::Smn {
+        index: 0,
+        addr,
+        value,
+        write: true,
+    });
     Ok(repl::Value::Nil)
 }
 
 pub(super) fn rdsmni(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -52,13 +82,20 @@ pub(super) fn rdsmni(
         .and_then(smn::Index::try_from)
         .map_err(usage)?;
     let addr = repl::popenv(env).as_num::<u32>().map_err(usage)?;
+    memory::guard(Space::Smn, addr.into(), 4, false, false)?;
     let data = smn::read(index, addr).map_err(usage)?;
-    println!("{addr:#x} {data:#x}");
+    config.access_log.record(Access::Smn {
+        index: index as u8,
+        addr,
+        value: data,
+        write: false,
+    });
+    show(addr, data);
     Ok(repl::Value::Unsigned(data.into()))
 }
 
 pub(super) fn wrsmni(
-    _config: &mut bldb::Config,
+    config: &mut bldb::Config,
     env: &mut Vec<repl::Value>,
 ) -> Result<repl::Value> {
     let usage = |error| {
@@ -71,8 +108,16 @@ pub(super) fn wrsmni(
         .map_err(usage)?;
     let addr = repl::popenv(env).as_num::<u32>().map_err(usage)?;
     let value = repl::popenv(env).as_num::<u32>().map_err(usage)?;
+    memory::guard(Space::Smn, addr.into(), 4, true, false)?;
     unsafe {
         smn::write(index, addr, value)?;
     }
+    config.modified.smn(index as u8, addr, value);
+    config.access_log.record(Access::Smn {
+        index: index as u8,
+        addr,
+        value,
+        write: true,
+    });
     Ok(repl::Value::Nil)
 }
@@ -0,0 +1,194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Snapshots of machine state, for auditing calls.
+//!
+//! Firmware blobs under test often change state they do not
+//! document: a control register bit, a memory typing MSR, the
+//! direction of a GPIO, or the loader's own page tables.  `call`
+//! takes a snapshot of such state before calling, and another
+//! when the callee returns, and reports what differs.
+
+use crate::bldb;
+use crate::println;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::fmt;
+
+/// The MSRs we snapshot: those controlling paging, memory typing,
+/// and the layout of the address space, and the segment bases
+/// and system call entry points that firmware is apt to set.
+const MSRS: &[(&str, u32)] = &[
+    ("EFER", x86::msr::IA32_EFER),
+    ("APIC_BASE", x86::msr::IA32_APIC_BASE),
+    ("PAT", x86::msr::IA32_PAT),
+    ("MTRR_DEF_TYPE", x86::msr::IA32_MTRR_DEF_TYPE),
+    ("SYSCFG", 0xc001_0010),
+    ("HWCR", 0xc001_0015),
+    ("TOP_MEM", 0xc001_001a),
+    ("TOP_MEM2", 0xc001_001d),
+    ("FS_BASE", x86::msr::IA32_FS_BASE),
+    ("GS_BASE", x86::msr::IA32_GS_BASE),
+    ("KERNEL_GSBASE", x86::msr::IA32_KERNEL_GSBASE),
+    ("LSTAR", x86::msr::IA32_LSTAR),
+];
+
+/// The number of GPIO pins whose direction we record.
+const GPIO_PINS: usize = 256;
+
+/// An item of state in a snapshot.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Item {
+    Cr(&'static str),
+    Msr(&'static str, u32),
+    /// Whether the pin's output is enabled.
+    GpioOutput(u8),
+    /// The number of pages of the named size mapped by the
+    /// loader's page table.
+    Pages(&'static str),
+    /// A digest of the loader's page table mappings.
+    Mappings,
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Cr(name) => write!(f, "{name}"),
+            Item::Msr(name, msr) => write!(f, "{name} (MSR {msr:#x})"),
+            Item::GpioOutput(pin) => write!(f, "gpio {pin} output enable"),
+            Item::Pages(size) => write!(f, "{size} pages mapped"),
+            Item::Mappings => write!(f, "page table digest"),
+        }
+    }
+}
+
+/// A snapshot of machine state.
+pub(super) struct Snapshot(Vec<(Item, u64)>);
+
+impl Snapshot {
+    /// Snapshots the current state of the machine.
+    pub(super) fn take(config: &bldb::Config) -> Snapshot {
+        let mut items = Vec::new();
+        let (cr0, cr3, cr4): (u64, u64, u64);
+        unsafe {
+            asm!("mov {}, cr0", out(reg) cr0);
+            asm!("mov {}, cr3", out(reg) cr3);
+            asm!("mov {}, cr4", out(reg) cr4);
+        }
+        items.push((Item::Cr("cr0"), cr0));
+        items.push((Item::Cr("cr3"), cr3));
+        items.push((Item::Cr("cr4"), cr4));
+        for &(name, msr) in MSRS {
+            let value = unsafe { config.hw.rdmsr(msr) };
+            items.push((Item::Msr(name, msr), value));
+        }
+        for pin in 0..GPIO_PINS {
+            let pin = pin as u8;
+            let output = config.gpios.get_pin(pin).output_enable();
+            items.push((Item::GpioOutput(pin), output.into()));
+        }
+        items.extend(mappings(config));
+        Snapshot(items)
+    }
+
+    /// Returns each item that differs between this snapshot and
+    /// a later one, with its value before and after.
+    fn diff<'a>(
+        &'a self,
+        after: &'a Snapshot,
+    ) -> impl Iterator<Item = (Item, u64, u64)> + 'a {
+        self.0.iter().zip(after.0.iter()).filter_map(
+            |(&(item, before), &(_, after))| {
+                (before != after).then_some((item, before, after))
+            },
+        )
+    }
+
+    /// Displays what changed between this snapshot and a later
+    /// one.
+    pub(super) fn report(&self, after: &Snapshot) {
+        let mut changed = false;
+        for (item, before, after) in self.diff(after) {
+            if !changed {
+                println!("call: the callee changed:");
+                changed = true;
+            }
+            println!("    {item}: {before:#x} -> {after:#x}");
+        }
+        if !changed {
+            println!(
+                "call: no change to control registers, MSRs, GPIOs, or mappings"
+            );
+        }
+    }
+}
+
+/// Summarizes the loader's page table: the number of pages of
+/// each size, and an FNV-1a digest of every mapping, so that any
+/// change to one is noticed.  The accessed and dirty bits, which
+/// the processor sets as the callee runs, are ignored.
+fn mappings(config: &bldb::Config) -> [(Item, u64); 4] {
+    const SIZES: [(&str, usize); 3] =
+        [("4KiB", 4 << 10), ("2MiB", 2 << 20), ("1GiB", 1 << 30)];
+    const ACCESSED_DIRTY: u64 = 1 << 5 | 1 << 6;
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    let mut counts = [0u64; SIZES.len()];
+    let mut digest = FNV_OFFSET;
+    config.page_table.walk(|va, pte, size| {
+        if let Some(k) = SIZES.iter().position(|&(_, s)| s == size) {
+            counts[k] += 1;
+        }
+        let words = [va as u64, pte.bits() & !ACCESSED_DIRTY];
+        for b in words.iter().flat_map(|w| w.to_le_bytes()) {
+            digest = (digest ^ u64::from(b)).wrapping_mul(FNV_PRIME);
+        }
+    });
+    [
+        (Item::Pages(SIZES[0].0), counts[0]),
+        (Item::Pages(SIZES[1].0), counts[1]),
+        (Item::Pages(SIZES[2].0), counts[2]),
+        (Item::Mappings, digest),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differences() {
+        let before = Snapshot(Vec::from([
+            (Item::Cr("cr0"), 0x8000_0011),
+            (Item::Msr("PAT", 0x277), 0x0007_0406_0007_0406),
+            (Item::GpioOutput(5), 0),
+        ]));
+        let after = Snapshot(Vec::from([
+            (Item::Cr("cr0"), 0x8000_0011),
+            (Item::Msr("PAT", 0x277), 0x0007_0106_0007_0406),
+            (Item::GpioOutput(5), 1),
+        ]));
+        let diff = before.diff(&after).collect::<Vec<_>>();
+        assert_eq!(
+            diff,
+            [
+                (
+                    Item::Msr("PAT", 0x277),
+                    0x0007_0406_0007_0406,
+                    0x0007_0106_0007_0406
+                ),
+                (Item::GpioOutput(5), 0, 1),
+            ]
+        );
+        assert_eq!(before.diff(&before).count(), 0);
+        assert_eq!(
+            alloc::format!("{}", Item::Msr("PAT", 0x277)),
+            "PAT (MSR 0x277)"
+        );
+        assert_eq!(
+            alloc::format!("{}", Item::Pages("2MiB")),
+            "2MiB pages mapped"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Commands to talk to the service processor over IPCC.
+//!
+//! The channel is opened on first use, claiming UART 1 from the
+//! fallback diagnostic path, which leaves it alone thereafter.
+
+use crate::bldb;
+use crate::ipcc::{self, Ipcc};
+use crate::print;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// The default limit on the length of a key's value.
+const DEFAULT_KEY_MAX: u16 = 1024;
+
+/// Returns the IPCC channel, opening it if need be.
+fn channel(config: &mut bldb::Config) -> Result<&mut Ipcc> {
+    if config.ipcc.is_none() {
+        if config.xfer.is_some() {
+            return Err(Error::Ipcc("IPCC: UART 1 is in use for transfers"));
+        }
+        // UART 1 and the IO mux are mapped by `bldb::init`, and
+        // claiming it keeps the fallback path off it.
+        let Some(uart) = (unsafe { uart::sp() }) else {
+            return Err(Error::Ipcc("IPCC: no SP UART on this system"));
+        };
+        config.ipcc = Some(Ipcc::new(uart));
+    }
+    Ok(config.ipcc.as_mut().unwrap())
+}
+
+/// Prints the names of the bits set in a word, followed by any
+/// unnamed bits.
+fn print_bits(what: &str, word: u64, names: &[(&str, u64)]) {
+    print!("{what}: {word:#x}");
+    let mut rest = word;
+    for &(name, bit) in names {
+        if word & bit != 0 {
+            print!(" {name}");
+            rest &= !bit;
+        }
+    }
+    if rest != 0 {
+        print!(" {rest:#x}");
+    }
+    println!();
+}
+
+fn parse_key(arg: &Value) -> Result<u8> {
+    match arg {
+        Value::Str(name) => ipcc::KEYS
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|&(_, k)| k)
+            .ok_or_else(|| arg.bad_arg("key name or number")),
+        _ => arg.as_num::<u8>(),
+    }
+}
+
+fn status(ipcc: &mut Ipcc) -> Result<Value> {
+    let (status, startup) = ipcc.status()?;
+    print_bits("status", status, ipcc::STATUS_BITS);
+    print_bits("startup", startup, ipcc::STARTUP_BITS);
+    Ok(Value::Unsigned(startup.into()))
+}
+
+fn ident(ipcc: &mut Ipcc) -> Result<Value> {
+    let ident = ipcc.ident()?;
+    println!("model:    {}", ident.model);
+    println!("revision: {}", ident.revision);
+    println!("serial:   {}", ident.serial);
+    Ok(Value::Str(ident.serial))
+}
+
+fn key(ipcc: &mut Ipcc, env: &mut Vec<Value>) -> Result<Value> {
+    let key = parse_key(&repl::popenv(env))?;
+    let max = match repl::popenv(env) {
+        Value::Nil => DEFAULT_KEY_MAX,
+        arg => arg.as_num::<u16>()?,
+    };
+    let value = ipcc.key_lookup(key, max)?;
+    println!("{:#x} bytes", value.len());
+    match String::from_utf8(value) {
+        Ok(s) => Ok(Value::Str(s)),
+        Err(e) => {
+            for line in e.as_bytes().chunks(16) {
+                for b in line {
+                    print!("{b:02x} ");
+                }
+                println!();
+            }
+            Ok(Value::Nil)
+        }
+    }
+}
+
+fn bootfail(ipcc: &mut Ipcc, env: &mut Vec<Value>) -> Result<Value> {
+    let reason = repl::popenv(env).as_num::<u8>()?;
+    let message = match repl::popenv(env) {
+        Value::Nil => String::new(),
+        arg => arg.as_string()?,
+    };
+    ipcc.boot_fail(reason, message.as_bytes())?;
+    Ok(Value::Nil)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: sp status | ident | bsu | ackstart");
+        println!("       sp key <key> [<max len>]");
+        println!("       sp bootfail <reason> [<message>]");
+        error
+    };
+    let arg = repl::popenv(env);
+    let cmd = arg.as_string().map_err(usage)?;
+    match cmd.as_str() {
+        "status" => status(channel(config)?),
+        "ident" => ident(channel(config)?),
+        "bsu" => {
+            let bsu = channel(config)?.bsu()?;
+            println!("boot storage unit {bsu}");
+            Ok(Value::Unsigned(bsu.into()))
+        }
+        "ackstart" => channel(config)?.ack_start().map(|()| Value::Nil),
+        "key" => key(channel(config)?, env).map_err(usage),
+        "bootfail" => bootfail(channel(config)?, env).map_err(usage),
+        _ => Err(usage(arg.bad_arg("sp subcommand"))),
+    }
+}
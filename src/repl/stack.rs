@@ -0,0 +1,80 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Combinators for the environment stack.
+//!
+//! These are the usual ones from stack languages.  Items are
+//! counted from the top of the stack, which is item 0.  As with
+//! `pop`, the item each returns is pushed onto the stack as the
+//! result of the command.
+
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+
+/// Returns the index in `env` of the `n`th item from the top.
+fn index(env: &[Value], n: usize) -> Result<usize> {
+    env.len().checked_sub(n + 1).ok_or(Error::StackDepth)
+}
+
+/// Returns the number of items on the stack.
+pub(super) fn depth(env: &[Value]) -> Value {
+    Value::Unsigned(env.len() as u128)
+}
+
+/// Copies the item below the top of the stack to the top.
+pub(super) fn over(env: &[Value]) -> Result<Value> {
+    Ok(env[index(env, 1)?].clone())
+}
+
+/// Moves the third item on the stack to the top.
+pub(super) fn rot(env: &mut Vec<Value>) -> Result<Value> {
+    let k = index(env, 2)?;
+    Ok(env.remove(k))
+}
+
+/// Copies the `n`th item on the stack to the top.
+pub(super) fn pick(env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: pick <n>");
+        error
+    };
+    let n = repl::popenv(env).as_num::<usize>().map_err(usage)?;
+    Ok(env[index(env, n)?].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+
+    #[test]
+    fn indexing() {
+        let env = [1u128, 2, 3].map(Value::Unsigned);
+        assert_eq!(index(&env, 0), Ok(2));
+        assert_eq!(index(&env, 2), Ok(0));
+        assert_eq!(index(&env, 3), Err(Error::StackDepth));
+        assert_eq!(index(&[], 0), Err(Error::StackDepth));
+    }
+
+    #[test]
+    fn combinators() {
+        let mut sim = Sim::new();
+        let results = |out: String| {
+            out.lines()
+                .filter_map(|line| line.strip_prefix("res: "))
+                .map(alloc::string::ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        // `push 1 2 3` leaves 1 on top.
+        let out = sim.session("push 1 2 3\nrot\nover\ndepth\npick 4\n");
+        assert_eq!(results(out), ["nil", "0x3", "0x1", "0x4", "0x2"]);
+        let out = sim.session("pick 9\n");
+        assert!(out.contains("Too few items"), "{out}");
+        // The stack is cleared after an error.
+        let out = sim.session("depth\n");
+        assert_eq!(results(out), ["0x0"]);
+    }
+}
@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A summary of the state the session has created.
+//!
+//! Over a long bench session it is easy to lose track of what
+//! has been mounted, loaded, staged, and poked.  The `state`
+//! command gathers all of that in one place.
+
+use crate::bldb;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::Value;
+use crate::result::Result;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The registers written from the REPL, and the last value
+/// written to each.
+#[derive(Debug, Default)]
+pub(crate) struct Modified {
+    msrs: BTreeMap<u32, u64>,
+    smn: BTreeMap<(u8, u32), u32>,
+}
+
+impl Modified {
+    pub(crate) fn msr(&mut self, msr: u32, value: u64) {
+        self.msrs.insert(msr, value);
+    }
+
+    pub(crate) fn smn(&mut self, index: u8, addr: u32, value: u32) {
+        self.smn.insert((index, addr), value);
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    _env: &mut Vec<Value>,
+) -> Result<Value> {
+    let generation = config.page_table.generation();
+    println!("page table generation {generation}");
+    if config.mounts.is_empty() {
+        println!("ramdisk: not mounted");
+    }
+    for (dir, mounted) in config.mounts.iter() {
+        let buf = mounted.buf();
+        let valid = config.page_table.resolve(buf).is_ok();
+        println!(
+            "ramdisk: {} at {:#x},{:#x} on {dir}, mounted at generation {}{}",
+            mounted.as_str(),
+            buf.addr(),
+            buf.len(),
+            buf.generation(),
+            if valid { "" } else { " (stale)" },
+        );
+    }
+    if let Some(builtin) = &config.builtin {
+        println!("builtin: {} at {}", builtin.as_str(), ramdisk::BUILTIN_DIR);
+    }
+    println!(
+        "regions: transfer and ramdisk regions {}",
+        if config.regions_claimed { "in use" } else { "unused" }
+    );
+    match &config.bootenv {
+        None => println!("bootenv: not set"),
+        Some(env) => println!("bootenv: {:#x} bytes", env.len()),
+    }
+    println!("images: {}", config.images.len());
+    for loaded in config.images.iter() {
+        println!("    {} entry {:p}", loaded.source, loaded.image.entry);
+        for text in loaded.image.text.iter() {
+            println!("        text {:#x}..{:#x}", text.start, text.end);
+        }
+    }
+    println!(
+        "audit: {}, {} accesses logged",
+        if config.access_log.enabled() { "on" } else { "off" },
+        config.access_log.len()
+    );
+    let (dumps, bytes) = config.dumps.usage();
+    println!("dumps: {dumps} saved regions, {bytes:#x} bytes");
+    if let Some(idle) = &config.idle {
+        println!("idle: {idle:?}");
+    }
+    let modified = &config.modified;
+    println!("modified MSRs: {}", modified.msrs.len());
+    for (msr, value) in modified.msrs.iter() {
+        println!("    {msr:#010x} = {value:#x}");
+    }
+    println!("modified SMN registers: {}", modified.smn.len());
+    for ((index, addr), value) in modified.smn.iter() {
+        println!("    smn{index} {addr:#010x} = {value:#x}");
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_write_wins() {
+        let mut modified = Modified::default();
+        modified.msr(0x10, 1);
+        modified.msr(0x10, 2);
+        modified.smn(0, 0x5a000, 3);
+        modified.smn(1, 0x5a000, 4);
+        assert_eq!(modified.msrs.get(&0x10), Some(&2));
+        assert_eq!(modified.smn.len(), 2);
+    }
+}
@@ -0,0 +1,103 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::loader;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::symbols::Symbols;
+use alloc::vec::Vec;
+
+/// Reads the symbol table of an image, replacing the one read
+/// before, or describes the table held.
+pub fn syms(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: syms [<file>]");
+        error
+    };
+    let path = match repl::popenv(env) {
+        Value::Nil => {
+            match config.symbols.is_empty() {
+                true => println!("no symbols"),
+                false => println!(
+                    "{} symbols from {}",
+                    config.symbols.len(),
+                    config.symbols.source()
+                ),
+            }
+            return Ok(Value::Nil);
+        }
+        arg => arg.as_string().map_err(usage)?,
+    };
+    let (fs, inner) = ramdisk::lookup(
+        &config.mounts,
+        config.builtin.as_deref(),
+        &config.page_table,
+        &path,
+    )?;
+    let file = fs.open(&inner)?;
+    let symbols = loader::symbols(file.as_ref())?;
+    config.symbols = Symbols::new(&path, symbols);
+    println!("{} symbols from {path}", config.symbols.len());
+    Ok(Value::Unsigned(config.symbols.len() as u128))
+}
+
+/// Looks up the address of a symbol by name.
+pub fn sym(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: sym <name>");
+        error
+    };
+    let name = repl::popenv(env).as_string().map_err(usage)?;
+    let sym = config.symbols.lookup(&name).ok_or(Error::NoSymbol)?;
+    println!("{name} {:#x} ({:#x} bytes)", sym.addr, sym.size);
+    Ok(Value::Unsigned(sym.addr.into()))
+}
+
+/// Resolves an address to the nearest symbol and an offset.
+pub fn addr2sym(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: addr2sym <addr>");
+        error
+    };
+    let addr = repl::popenv(env).as_num::<u64>().map_err(usage)?;
+    let name = config.symbols.describe(addr).ok_or(Error::NoSymbol)?;
+    println!("{addr:#x} {name}");
+    Ok(Value::Str(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+    use crate::symbols::Symbol;
+    use alloc::string::String;
+
+    #[test]
+    fn lookups() {
+        let mut sim = Sim::new();
+        let out = sim.session("syms\n");
+        assert!(out.contains("no symbols"), "{out}");
+        sim.console("", |config| {
+            let main =
+                Symbol { name: String::from("main"), addr: 0x1000, size: 0x40 };
+            config.symbols = Symbols::new("/unix", alloc::vec![main]);
+        });
+        let out = sim.session("syms\n");
+        assert!(out.contains("1 symbols from /unix"), "{out}");
+        let out = sim.session("sym main\n");
+        assert!(out.ends_with("res: 0x1000\n"), "{out}");
+        let out = sim.session("addr2sym 0x1010\n");
+        assert!(out.contains("0x1010 main+0x10"), "{out}");
+        let out = sim.session("addr2sym 0x1040\n");
+        assert!(out.contains("No such symbol"), "{out}");
+        let out = sim.session("sym nothing\n");
+        assert!(out.contains("No such symbol"), "{out}");
+    }
+}
@@ -0,0 +1,390 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Decoding, and carefully changing, the control registers.
+//!
+//! Only a handful of flags may be changed: those that cannot
+//! affect paging, caching, or the mode of the processor, and so
+//! cannot pull the rug out from under the loader.  Flags and
+//! instructions that not every processor implements are only
+//! used once CPUID says that they are there.
+
+use crate::bldb;
+use crate::cpuid;
+use crate::println;
+use crate::repl::{self, Value, confirm};
+use crate::result::{Error, Result};
+use alloc::format;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+/// A CPUID feature bit: the leaf, the register holding it, from
+/// 0 for EAX to 3 for EDX, and the bit.
+#[derive(Clone, Copy, Debug)]
+struct Feature {
+    leaf: u32,
+    reg: usize,
+    bit: u32,
+}
+
+impl Feature {
+    /// Returns true iff the processor reports the feature.
+    fn present(self) -> bool {
+        let max = cpuid::cpuid(self.leaf & 0x8000_0000, 0).eax;
+        if self.leaf > max {
+            return false;
+        }
+        let r = cpuid::cpuid(self.leaf, 0);
+        [r.eax, r.ebx, r.ecx, r.edx][self.reg] & (1 << self.bit) != 0
+    }
+}
+
+const XSAVE: Feature = Feature { leaf: 1, reg: 2, bit: 26 };
+const FSGSBASE: Feature = Feature { leaf: 7, reg: 1, bit: 0 };
+const UMIP: Feature = Feature { leaf: 7, reg: 2, bit: 2 };
+const SYSCALL: Feature = Feature { leaf: 0x8000_0001, reg: 3, bit: 11 };
+
+/// A named flag in a system register.
+struct Flag {
+    name: &'static str,
+    bit: u32,
+    meaning: &'static str,
+    writable: bool,
+    /// The feature the processor must report before the flag
+    /// may be set.
+    feature: Option<Feature>,
+}
+
+const fn flag(name: &'static str, bit: u32, meaning: &'static str) -> Flag {
+    Flag { name, bit, meaning, writable: false, feature: None }
+}
+
+const fn safe(name: &'static str, bit: u32, meaning: &'static str) -> Flag {
+    Flag { name, bit, meaning, writable: true, feature: None }
+}
+
+const fn safe_with(
+    name: &'static str,
+    bit: u32,
+    meaning: &'static str,
+    feature: Feature,
+) -> Flag {
+    Flag { feature: Some(feature), ..safe(name, bit, meaning) }
+}
+
+const CR0_FLAGS: &[Flag] = &[
+    flag("PE", 0, "protected mode"),
+    safe("MP", 1, "monitor coprocessor"),
+    flag("EM", 2, "x87 emulation"),
+    flag("TS", 3, "task switched"),
+    flag("ET", 4, "extension type"),
+    safe("NE", 5, "native x87 error reporting"),
+    safe("WP", 16, "supervisor write protection"),
+    safe("AM", 18, "alignment checking allowed"),
+    flag("NW", 29, "not write-through"),
+    flag("CD", 30, "cache disable"),
+    flag("PG", 31, "paging"),
+];
+
+const CR3_FLAGS: &[Flag] = &[
+    flag("PWT", 3, "top level table write-through"),
+    flag("PCD", 4, "top level table cache disable"),
+];
+
+const CR4_FLAGS: &[Flag] = &[
+    flag("VME", 0, "virtual 8086 mode extensions"),
+    flag("PVI", 1, "protected mode virtual interrupts"),
+    safe("TSD", 2, "RDTSC restricted to CPL 0"),
+    safe("DE", 3, "debugging extensions"),
+    flag("PSE", 4, "page size extensions"),
+    flag("PAE", 5, "physical address extension"),
+    flag("MCE", 6, "machine check exceptions"),
+    flag("PGE", 7, "global pages"),
+    safe("PCE", 8, "RDPMC allowed at any CPL"),
+    flag("OSFXSR", 9, "FXSAVE and SSE enabled"),
+    flag("OSXMMEXCPT", 10, "SIMD floating point exceptions"),
+    safe_with("UMIP", 11, "user mode instruction prevention", UMIP),
+    flag("LA57", 12, "5-level paging"),
+    safe_with("FSGSBASE", 16, "RDFSBASE and friends enabled", FSGSBASE),
+    flag("PCIDE", 17, "process context identifiers"),
+    flag("OSXSAVE", 18, "XSAVE and XCR0 enabled"),
+    flag("SMEP", 20, "supervisor mode execution prevention"),
+    flag("SMAP", 21, "supervisor mode access prevention"),
+    flag("PKE", 22, "protection keys"),
+    flag("CET", 23, "control-flow enforcement"),
+];
+
+const EFER_FLAGS: &[Flag] = &[
+    safe_with("SCE", 0, "SYSCALL and SYSRET enabled", SYSCALL),
+    flag("LME", 8, "long mode enable"),
+    flag("LMA", 10, "long mode active"),
+    flag("NXE", 11, "no-execute page protection"),
+    flag("SVME", 12, "secure virtual machine enable"),
+    flag("LMSLE", 13, "long mode segment limits"),
+    flag("FFXSR", 14, "fast FXSAVE and FXRSTOR"),
+    flag("TCE", 15, "translation cache extension"),
+];
+
+const XCR0_FLAGS: &[Flag] = &[
+    flag("X87", 0, "x87 state"),
+    flag("SSE", 1, "SSE state"),
+    flag("YMM", 2, "AVX state"),
+    flag("OPMASK", 5, "AVX-512 mask registers"),
+    flag("ZMM_HI256", 6, "upper halves of ZMM0-15"),
+    flag("HI16_ZMM", 7, "ZMM16-31"),
+    flag("PKRU", 9, "protection key rights"),
+];
+
+/// The registers `sysregs` knows about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Reg {
+    Cr0,
+    Cr2,
+    Cr3,
+    Cr4,
+    Cr8,
+    Efer,
+    Xcr0,
+}
+
+impl Reg {
+    const ALL: [Reg; 7] = [
+        Reg::Cr0,
+        Reg::Cr2,
+        Reg::Cr3,
+        Reg::Cr4,
+        Reg::Cr8,
+        Reg::Efer,
+        Reg::Xcr0,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Reg::Cr0 => "cr0",
+            Reg::Cr2 => "cr2",
+            Reg::Cr3 => "cr3",
+            Reg::Cr4 => "cr4",
+            Reg::Cr8 => "cr8",
+            Reg::Efer => "efer",
+            Reg::Xcr0 => "xcr0",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Reg> {
+        Reg::ALL.into_iter().find(|r| r.name().eq_ignore_ascii_case(name))
+    }
+
+    fn flags(self) -> &'static [Flag] {
+        match self {
+            Reg::Cr0 => CR0_FLAGS,
+            Reg::Cr3 => CR3_FLAGS,
+            Reg::Cr4 => CR4_FLAGS,
+            Reg::Efer => EFER_FLAGS,
+            Reg::Xcr0 => XCR0_FLAGS,
+            Reg::Cr2 | Reg::Cr8 => &[],
+        }
+    }
+
+    /// Reads the register.  XCR0 can only be read if the
+    /// processor implements XSAVE and the OS has enabled it, and
+    /// so is `None` otherwise.
+    fn read(self) -> Option<u64> {
+        let value: u64;
+        unsafe {
+            match self {
+                Reg::Cr0 => asm!("mov {}, cr0", out(reg) value),
+                Reg::Cr2 => asm!("mov {}, cr2", out(reg) value),
+                Reg::Cr3 => asm!("mov {}, cr3", out(reg) value),
+                Reg::Cr4 => asm!("mov {}, cr4", out(reg) value),
+                Reg::Cr8 => asm!("mov {}, cr8", out(reg) value),
+                Reg::Efer => value = x86::msr::rdmsr(x86::msr::IA32_EFER),
+                Reg::Xcr0 => {
+                    let osxsave = 1 << 18;
+                    if !XSAVE.present() || Reg::Cr4.read()? & osxsave == 0 {
+                        return None;
+                    }
+                    let (lo, hi): (u32, u32);
+                    asm!(
+                        "xgetbv",
+                        in("ecx") 0,
+                        out("eax") lo,
+                        out("edx") hi,
+                        options(nomem, nostack),
+                    );
+                    value = u64::from(hi) << 32 | u64::from(lo);
+                }
+            }
+        }
+        Some(value)
+    }
+
+    /// Writes the register.
+    ///
+    /// # Safety
+    /// The caller must ensure the new value only changes flags
+    /// that cannot disturb the loader.
+    unsafe fn write(self, value: u64) {
+        unsafe {
+            match self {
+                Reg::Cr0 => asm!("mov cr0, {}", in(reg) value),
+                Reg::Cr4 => asm!("mov cr4, {}", in(reg) value),
+                Reg::Efer => x86::msr::wrmsr(x86::msr::IA32_EFER, value),
+                _ => panic!("{} is not writable", self.name()),
+            }
+        }
+    }
+}
+
+/// Returns the named flag in the register, if it may be
+/// changed.
+fn writable_flag(reg: Reg, name: &str) -> Result<&'static Flag> {
+    let flag = reg
+        .flags()
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name))
+        .ok_or(Error::BadArgs)?;
+    if !flag.writable {
+        println!("{}.{} may not be changed", reg.name(), flag.name);
+        return Err(Error::BadArgs);
+    }
+    Ok(flag)
+}
+
+fn show(reg: Reg) -> Option<u64> {
+    let Some(value) = reg.read() else {
+        println!("{:<4} unavailable (XSAVE not enabled)", reg.name());
+        return None;
+    };
+    println!("{:<4} {value:#018x}", reg.name());
+    match reg {
+        Reg::Cr3 => println!("     top level table at {:#x}", value & !0xfff),
+        Reg::Cr8 => println!("     task priority {}", value & 0xf),
+        _ => {}
+    }
+    for flag in reg.flags() {
+        let set = value & (1 << flag.bit) != 0;
+        println!(
+            "     {:<10} {} {}",
+            flag.name,
+            if set { '1' } else { '0' },
+            flag.meaning
+        );
+    }
+    Some(value)
+}
+
+fn change(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+    set: bool,
+) -> Result<Value> {
+    let force = confirm::take_force(env);
+    let arg = repl::popenv(env);
+    let reg = Reg::from_name(&arg.as_string()?)
+        .ok_or_else(|| arg.bad_arg("register name"))?;
+    let name = repl::popenv(env).as_string()?;
+    let flag = writable_flag(reg, &name)?;
+    if set && flag.feature.is_some_and(|f| !f.present()) {
+        println!("{}.{} is not implemented", reg.name(), flag.name);
+        return Err(Error::BadArgs);
+    }
+    let mask = 1 << flag.bit;
+    let old = reg.read().ok_or(Error::BadArgs)?;
+    let new = if set { old | mask } else { old & !mask };
+    if new != old {
+        let what = format!(
+            "{} {} {:#x} -> {new:#x}",
+            if set { "setting" } else { "clearing" },
+            reg.name(),
+            old
+        );
+        confirm::confirm(config, force, &what)?;
+        unsafe {
+            reg.write(new);
+        }
+        if reg == Reg::Efer {
+            config.modified.msr(x86::msr::IA32_EFER, new);
+        }
+    }
+    Ok(Value::Unsigned(new.into()))
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: sysregs [<reg>]");
+        println!("       sysregs set|clear [-f] <reg> <flag>");
+        error
+    };
+    let arg = repl::popenv(env);
+    match &arg {
+        Value::Nil => {
+            for reg in Reg::ALL {
+                show(reg);
+            }
+            Ok(Value::Nil)
+        }
+        Value::Str(s) if s == "set" => change(config, env, true).map_err(usage),
+        Value::Str(s) if s == "clear" => {
+            change(config, env, false).map_err(usage)
+        }
+        Value::Str(s) => {
+            let reg = Reg::from_name(s)
+                .ok_or_else(|| usage(arg.bad_arg("register name")))?;
+            let value = show(reg).ok_or(Error::BadArgs)?;
+            Ok(Value::Unsigned(value.into()))
+        }
+        _ => Err(usage(arg.bad_arg("register name, set, or clear"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables() {
+        for reg in Reg::ALL {
+            assert_eq!(Reg::from_name(&reg.name().to_uppercase()), Some(reg));
+            let mut seen = 0u64;
+            for flag in reg.flags() {
+                assert!(flag.bit < 64);
+                assert_eq!(seen & (1 << flag.bit), 0, "{}", flag.name);
+                seen |= 1 << flag.bit;
+            }
+        }
+        assert_eq!(Reg::from_name("cr1"), None);
+    }
+
+    #[test]
+    fn writable() {
+        let bit = |reg, name| writable_flag(reg, name).map(|f| f.bit);
+        assert_eq!(bit(Reg::Cr0, "wp"), Ok(16));
+        assert_eq!(bit(Reg::Efer, "SCE"), Ok(0));
+        assert!(writable_flag(Reg::Cr0, "PG").is_err());
+        assert!(writable_flag(Reg::Efer, "NXE").is_err());
+        assert!(writable_flag(Reg::Cr4, "NOPE").is_err());
+        // Only registers that can be written have writable flags.
+        for reg in [Reg::Cr2, Reg::Cr3, Reg::Cr8, Reg::Xcr0] {
+            assert!(reg.flags().iter().all(|f| !f.writable));
+        }
+    }
+
+    #[test]
+    fn features() {
+        // Every x86-64 processor has SYSCALL, and none has a leaf
+        // this far beyond the last.
+        assert!(SYSCALL.present());
+        assert!(!Feature { leaf: 0x7fff_ffff, reg: 0, bit: 0 }.present());
+        assert!(!Feature { leaf: 0x8fff_ffff, reg: 0, bit: 0 }.present());
+        // Only flags that may be set need a feature.
+        for reg in Reg::ALL {
+            for flag in reg.flags() {
+                assert!(flag.writable || flag.feature.is_none());
+            }
+        }
+    }
+}
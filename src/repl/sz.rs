@@ -0,0 +1,155 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sending memory and files via ZMODEM.
+//!
+//! `sz` is the counterpart of `rz`, for getting data off the
+//! machine: a region of memory, such as a dump or a transcript,
+//! or a file on the ramdisk, is sent over the console to be
+//! received by `rz` on the workstation at the other end.
+
+use crate::bldb;
+use crate::io;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart::Uart;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use zmodem2::{Read, Seek};
+
+use core::result::Result as ZResult;
+
+/// Adapts the source of a transfer to the interface of the
+/// ZMODEM sender, which reads it sequentially, but may seek
+/// back to resend data the receiver missed.
+struct Source<'a> {
+    src: &'a dyn io::Read,
+    off: u64,
+}
+
+impl<'a> Source<'a> {
+    fn new(src: &'a dyn io::Read) -> Source<'a> {
+        Source { src, off: 0 }
+    }
+}
+
+impl Read for Source<'_> {
+    fn read_byte(&mut self) -> ZResult<u8, zmodem2::Error> {
+        let mut b = [0u8; 1];
+        match Read::read(self, &mut b)? {
+            0 => Err(zmodem2::Error::UnexpectedEof),
+            _ => Ok(b[0]),
+        }
+    }
+
+    fn read(&mut self, dst: &mut [u8]) -> ZResult<u32, zmodem2::Error> {
+        let nb =
+            self.src.read(self.off, dst).map_err(|_| zmodem2::Error::Read)?;
+        self.off += nb as u64;
+        Ok(nb.try_into().unwrap())
+    }
+}
+
+impl Seek for Source<'_> {
+    fn seek(&mut self, offset: u32) -> ZResult<(), zmodem2::Error> {
+        if offset as usize > self.src.size() {
+            return Err(zmodem2::Error::Read);
+        }
+        self.off = offset.into();
+        Ok(())
+    }
+}
+
+fn sz(uart: &mut Uart, name: &str, src: &dyn io::Read) -> Result<usize> {
+    let size = src.size();
+    let len = u32::try_from(size).map_err(|_| Error::Send)?;
+    println!("sending {name}, {size:#x} bytes");
+    let mut state =
+        Box::new(zmodem2::State::new_file(name, len).map_err(|_| Error::Send)?);
+    let mut source = Source::new(src);
+    while state.stage() != zmodem2::Stage::Done {
+        if let Err(e) = zmodem2::send(uart, &mut source, &mut state) {
+            println!("zmodem error: {e:?}");
+            return Err(Error::Send.context("sent", &[source.off]));
+        }
+    }
+    Ok(size)
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: sz <addr>,<len> | sz <file>");
+        error
+    };
+    let nsent = match repl::popenv(env) {
+        Value::Str(path) => {
+            let (fs, inner) = ramdisk::lookup(
+                &config.mounts,
+                config.builtin.as_deref(),
+                &config.page_table,
+                &path,
+            )?;
+            let file = fs.open(&inner)?;
+            if file.file_type() != ramdisk::FileType::Regular {
+                return Err(Error::FsInvPath);
+            }
+            let name = path.rsplit('/').next().unwrap_or_default();
+            sz(
+                config.xfer.as_mut().unwrap_or(&mut config.cons),
+                name,
+                file.as_ref(),
+            )?
+        }
+        arg => {
+            let src = arg
+                .as_slice(&config.page_table, 0)
+                .and_then(|o| o.ok_or(Error::BadArgs))
+                .map_err(usage)?;
+            let name = memory_name(src.as_ptr().addr(), src.len());
+            sz(config.xfer.as_mut().unwrap_or(&mut config.cons), &name, &src)?
+        }
+    };
+    println!("\n\nSent {nsent} bytes");
+    Ok(Value::Nil)
+}
+
+/// Names a region of memory sent, after its address and length.
+fn memory_name(addr: usize, len: usize) -> String {
+    format!("mem-{addr:x}-{len:x}.bin")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source() {
+        let data: &[u8] = b"abcdef";
+        let mut src = Source::new(&data);
+        let mut buf = [0u8; 4];
+        assert_eq!(Read::read(&mut src, &mut buf).ok(), Some(4));
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(src.read_byte().ok(), Some(b'e'));
+        src.seek(1).unwrap();
+        assert_eq!(Read::read(&mut src, &mut buf).ok(), Some(4));
+        assert_eq!(&buf, b"bcde");
+        assert_eq!(Read::read(&mut src, &mut buf).ok(), Some(1));
+        assert_eq!(Read::read(&mut src, &mut buf).ok(), Some(0));
+        assert!(matches!(src.read_byte(), Err(zmodem2::Error::UnexpectedEof)));
+        assert!(src.seek(6).is_ok());
+        assert!(src.seek(7).is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(memory_name(0x1000, 0x20), "mem-1000-20.bin");
+    }
+}
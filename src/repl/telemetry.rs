@@ -0,0 +1,251 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Power, thermal, and clock telemetry.
+//!
+//! Package power is derived from the RAPL energy counter, the
+//! control temperature (Tctl) is read from the SMU's thermal
+//! block, and the effective clock and C0 residency of the
+//! current core come from the APERF and MPERF counters.  The
+//! rates are computed over a sampling interval.
+//!
+//! The SMU firmware's own readings are also taken from its PM
+//! table, when the table is mapped.  Most of the table's layout
+//! varies between firmware versions, so only its leading
+//! entries, which do not, are shown: the package power tracking
+//! (PPT) limit and value in watts, the current (TDC) limit and
+//! value in amps, and the thermal limit and value in degrees C.
+
+use crate::bldb;
+use crate::clock;
+use crate::mem;
+use crate::mmu;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::smn;
+use crate::smu;
+use alloc::vec::Vec;
+use core::ptr;
+use core::slice;
+use core::time::Duration;
+
+/// The RAPL power unit and package energy counter MSRs.
+const MSR_RAPL_PWR_UNIT: u32 = 0xc001_0299;
+const MSR_PKG_ENERGY_STAT: u32 = 0xc001_029b;
+
+/// The SMN address of the current temperature register in the
+/// SMU thermal block.
+const THM_TCON_CUR_TMP: u32 = 0x5_9800;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// The number of leading PM table entries that are shown.
+const PM_ENTRIES: usize = 6;
+
+/// Decodes the leading entries of the PM table, which are
+/// little-endian single precision floats.
+fn pm_entries(table: &[u8]) -> [f32; PM_ENTRIES] {
+    let mut entries = [0.0; PM_ENTRIES];
+    for (entry, bytes) in entries.iter_mut().zip(table.chunks_exact(4)) {
+        *entry = f32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    entries
+}
+
+/// Has the SMU refresh its PM table at `addr`, and returns the
+/// leading entries.
+fn read_pm_table(
+    page_table: &mmu::LoaderPageTable,
+    addr: u64,
+) -> Result<[f32; PM_ENTRIES]> {
+    let len = PM_ENTRIES * 4;
+    let table = usize::try_from(addr)
+        .ok()
+        .filter(|&addr| mem::is_canonical(addr))
+        .map(ptr::with_exposed_provenance::<u8>)
+        .filter(|&table| {
+            page_table
+                .is_region_readable(mem::page_range_raw(table.cast(), len))
+        })
+        .ok_or(Error::Unmapped.context("SMU PM table addr", &[addr]))?;
+    smu::transfer_pm_table()?;
+    Ok(pm_entries(unsafe { slice::from_raw_parts(table, len) }))
+}
+
+/// A snapshot of the raw counters.
+#[derive(Clone, Copy, Debug, Default)]
+struct Sample {
+    tsc: u64,
+    aperf: u64,
+    mperf: u64,
+    energy: u32,
+    tmp: u32,
+}
+
+impl Sample {
+    fn take() -> Result<Sample> {
+        use x86::msr::{IA32_APERF, IA32_MPERF, rdmsr};
+        let tmp = smn::read(smn::Index::Smn0, THM_TCON_CUR_TMP)?;
+        let (aperf, mperf, energy) = unsafe {
+            (
+                rdmsr(IA32_APERF),
+                rdmsr(IA32_MPERF),
+                rdmsr(MSR_PKG_ENERGY_STAT) as u32,
+            )
+        };
+        Ok(Sample { tsc: clock::rdtsc(), aperf, mperf, energy, tmp })
+    }
+}
+
+/// Telemetry computed from a pair of samples.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Reading {
+    power_mw: u128,
+    tctl_mc: i32,
+    clock_mhz: u128,
+    c0_pct: u128,
+}
+
+/// Converts the raw current temperature register to
+/// millidegrees Celsius.  The temperature is in units of
+/// 0.125C, and if the range select bit is set, is offset by
+/// 49C to allow for temperatures below zero.
+fn tctl_millicelsius(raw: u32) -> i32 {
+    const RANGE_SEL: u32 = 1 << 19;
+    let temp = (raw >> 21) as i32 * 125;
+    if raw & RANGE_SEL != 0 { temp - 49_000 } else { temp }
+}
+
+/// Computes rates over the interval between two samples.  `esu`
+/// is the energy status unit: the energy counter counts in
+/// units of 1/2^esu joules.  `hz` is the TSC frequency, at which
+/// MPERF also counts while the core is in C0.
+fn reading(a: &Sample, b: &Sample, esu: u32, hz: u128) -> Reading {
+    let tsc = u128::from(b.tsc.wrapping_sub(a.tsc));
+    let aperf = u128::from(b.aperf.wrapping_sub(a.aperf));
+    let mperf = u128::from(b.mperf.wrapping_sub(a.mperf));
+    let energy = u128::from(b.energy.wrapping_sub(a.energy));
+    let power_mw = (energy * 1000 * hz).checked_div(tsc << esu).unwrap_or(0);
+    let clock_mhz = (hz * aperf).checked_div(mperf * 1_000_000).unwrap_or(0);
+    let c0_pct = (mperf * 100).checked_div(tsc).unwrap_or(0);
+    Reading { power_mw, tctl_mc: tctl_millicelsius(b.tmp), clock_mhz, c0_pct }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: telemetry [<count> [<interval ms>]]");
+        error
+    };
+    let count = match repl::popenv(env) {
+        Value::Nil => 1,
+        v => v.as_num::<u64>().map_err(usage)?,
+    };
+    let interval = match repl::popenv(env) {
+        Value::Nil => DEFAULT_INTERVAL,
+        v => match v.as_num::<u64>().map_err(usage)? {
+            0 => return Err(usage(v.bad_arg("non-zero interval"))),
+            ms => Duration::from_millis(ms),
+        },
+    };
+    match smu::version() {
+        Ok((major, minor, patch)) => {
+            println!("SMU firmware {major}.{minor}.{patch}")
+        }
+        Err(e) => println!("SMU firmware version unavailable: {e:?}"),
+    }
+    let pm_table = match smu::pm_table() {
+        Ok((version, addr)) => {
+            println!("SMU PM table version {version:#x} at {addr:#x}");
+            Some(addr)
+        }
+        Err(e) => {
+            println!("SMU PM table unavailable: {e:?}");
+            None
+        }
+    };
+    let esu = unsafe { x86::msr::rdmsr(MSR_RAPL_PWR_UNIT) >> 8 } as u32 & 0x1f;
+    let hz = clock::frequency();
+    println!("   power    Tctl  eff clk  C0");
+    let mut prev = Sample::take()?;
+    for _ in 0..count {
+        // Wait for the interval, stopping early on any input.
+        match config.cons.wait_data_ready(interval) {
+            Ok(false) => {}
+            Ok(true) => {
+                let _ = config.cons.try_getb();
+                break;
+            }
+            Err(e) if e.is(Error::UartBreak) => break,
+            Err(e) => return Err(e),
+        }
+        let sample = Sample::take()?;
+        let r = reading(&prev, &sample, esu, hz);
+        println!(
+            "{:>5}.{:03}W {:>4}.{}C {:>5}MHz {:>3}%",
+            r.power_mw / 1000,
+            r.power_mw % 1000,
+            r.tctl_mc / 1000,
+            r.tctl_mc.rem_euclid(1000) / 100,
+            r.clock_mhz,
+            r.c0_pct,
+        );
+        prev = sample;
+        let Some(addr) = pm_table else { continue };
+        match read_pm_table(&config.page_table, addr) {
+            Ok([ppt_limit, ppt, tdc_limit, tdc, thm_limit, thm]) => println!(
+                "    SMU: PPT {ppt:.1}/{ppt_limit:.1}W \
+                 TDC {tdc:.1}/{tdc_limit:.1}A THM {thm:.1}/{thm_limit:.1}C"
+            ),
+            Err(e) => println!("    SMU: {e:?}"),
+        }
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperatures() {
+        assert_eq!(tctl_millicelsius(400 << 21), 50_000);
+        assert_eq!(tctl_millicelsius(401 << 21), 50_125);
+        assert_eq!(tctl_millicelsius(400 << 21 | 1 << 19), 1_000);
+    }
+
+    #[test]
+    fn pm_table_entries() {
+        let table: Vec<u8> = [142.0f32, 87.5, 95.0, 60.25, 95.0, 48.5, 1.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        assert_eq!(pm_entries(&table), [142.0, 87.5, 95.0, 60.25, 95.0, 48.5]);
+    }
+
+    #[test]
+    fn rates() {
+        let hz = 2_000_000_000;
+        let a = Sample { tsc: 0, aperf: 0, mperf: 0, energy: u32::MAX, tmp: 0 };
+        // One second, half of it in C0 at 1.5x the nominal
+        // clock, consuming 100 joules in units of 1/2^16 J.
+        let b = Sample {
+            tsc: 2_000_000_000,
+            aperf: 1_500_000_000,
+            mperf: 1_000_000_000,
+            energy: (100u32 << 16).wrapping_sub(1),
+            tmp: 360 << 21,
+        };
+        let r = reading(&a, &b, 16, hz);
+        assert_eq!(r.power_mw, 100_000);
+        assert_eq!(r.tctl_mc, 45_000);
+        assert_eq!(r.clock_mhz, 3000);
+        assert_eq!(r.c0_pct, 50);
+        let r = reading(&a, &a, 16, hz);
+        assert_eq!((r.power_mw, r.clock_mhz, r.c0_pct), (0, 0, 0));
+    }
+}
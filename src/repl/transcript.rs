@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Records transcripts of sessions.
+//!
+//! `transcript start` records the lines typed at the prompt and
+//! the output that reaches the console, each stamped with the
+//! time since power on, into a memory buffer: either one given
+//! as an argument, or a buffer on the heap that is allocated the
+//! first time it is needed and reused thereafter.  `transcript
+//! stop` returns the transcript as a slice, which may then be
+//! sent to the host.
+
+use crate::bldb;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::sink::{self, TranscriptState};
+use crate::{print, println};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The size of the heap buffer for transcripts.
+const HEAP_LEN: usize = 1024 * 1024;
+
+/// The address of the heap buffer, once allocated.  It is never
+/// freed, so that slices of old transcripts remain valid.
+static HEAP: spin::Once<usize> = spin::Once::new();
+
+/// Returns the heap buffer for transcripts, allocating it if
+/// need be.
+fn heap() -> &'static mut [u8] {
+    let addr =
+        *HEAP.call_once(|| vec![0u8; HEAP_LEN].leak().as_mut_ptr().addr());
+    let ptr = core::ptr::with_exposed_provenance_mut::<u8>(addr);
+    unsafe { core::slice::from_raw_parts_mut(ptr, HEAP_LEN) }
+}
+
+fn show(state: Option<TranscriptState>) {
+    let Some(state) = state else {
+        println!("transcript: none");
+        return;
+    };
+    let verb = if state.recording { "recording" } else { "stopped" };
+    print!("transcript: {verb}, {:#x} bytes at {:#x}", state.len, state.addr);
+    if state.overflowed {
+        print!(", truncated");
+    }
+    println!();
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: transcript [start [<addr>,<len>] | stop]");
+        error
+    };
+    let arg = repl::popenv(env);
+    if let Value::Nil = arg {
+        show(sink::transcript());
+        return Ok(Value::Nil);
+    }
+    match arg.as_string().map_err(usage)?.as_str() {
+        "start" => {
+            let buf = repl::popenv(env)
+                .as_slice_mut(&config.page_table, 0)
+                .map_err(usage)?
+                .unwrap_or_else(heap);
+            let state = sink::transcript();
+            if state.is_some_and(|state| state.recording) {
+                println!("transcript: discarding the transcript in progress");
+            }
+            sink::transcript_start(buf);
+            Ok(Value::Nil)
+        }
+        "stop" => {
+            let state = sink::transcript_stop().ok_or(Error::BadArgs)?;
+            show(Some(state));
+            let ptr = core::ptr::with_exposed_provenance::<u8>(state.addr);
+            let bs = unsafe { core::slice::from_raw_parts(ptr, state.len) };
+            Ok(Value::Slice(config.page_table.buf(bs)))
+        }
+        _ => Err(usage(arg.bad_arg("start or stop"))),
+    }
+}
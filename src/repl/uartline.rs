@@ -0,0 +1,55 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Introspection and control of the console UART's modem lines
+//! and flow control.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::Result;
+use crate::uart;
+use alloc::vec::Vec;
+
+fn parse_onoff(value: Value) -> Result<bool> {
+    match value.as_string()?.as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(value.bad_arg("on or off")),
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: uartline [afc|dtr|rts|xonxoff on|off]...");
+        error
+    };
+    let (mut afc, mut dtr, mut rts, mut xonxoff) = (None, None, None, None);
+    loop {
+        let arg = repl::popenv(env);
+        let line = match &arg {
+            Value::Nil => break,
+            v => v.as_string().map_err(usage)?,
+        };
+        let setting = match line.as_str() {
+            "afc" => &mut afc,
+            "dtr" => &mut dtr,
+            "rts" => &mut rts,
+            "xonxoff" => &mut xonxoff,
+            _ => return Err(usage(arg.bad_arg("afc, dtr, rts, or xonxoff"))),
+        };
+        *setting = Some(parse_onoff(repl::popenv(env)).map_err(usage)?);
+    }
+    if afc.is_some() || dtr.is_some() || rts.is_some() {
+        config.cons.set_modem_control(afc, dtr, rts);
+    }
+    if let Some(enabled) = xonxoff {
+        uart::set_soft_flow(enabled);
+    }
+    println!("{}", config.cons.line_state());
+    Ok(Value::Nil)
+}
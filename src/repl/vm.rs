@@ -6,7 +6,7 @@ use crate::bldb;
 use crate::mem;
 use crate::mmu;
 use crate::println;
-use crate::repl::{self, Value};
+use crate::repl::{self, Value, confirm};
 use crate::result::{Error, Result};
 use alloc::vec::Vec;
 
@@ -63,7 +63,7 @@ pub fn map(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let (pa, len) =
         repl::popenv(env).as_pair().and_then(check_phys_addr).map_err(usage)?;
     let va = repl::popenv(env)
-        .as_ptr::<()>()
+        .as_ptr::<()>(&config.page_table)
         .and_then(|va| check_virt_range(va, len))
         .map_err(usage)?;
     let attrs = repl::popenv(env)
@@ -88,7 +88,8 @@ pub fn mapping(
         println!("usage: mapping <addr>");
         error
     };
-    let ptr = repl::popenv(env).as_ptr::<()>().map_err(usage)?;
+    let ptr =
+        repl::popenv(env).as_ptr::<()>(&config.page_table).map_err(usage)?;
     let pte = config.page_table.lookup(ptr);
     let value = match pte {
         None => {
@@ -121,10 +122,29 @@ pub fn mappings(
 
 pub fn unmap(config: &mut bldb::Config, env: &mut Vec<Value>) -> Result<Value> {
     let usage = |error| {
-        println!("usage: unmap <addr>,<len>");
+        println!("usage: unmap [-f] <addr>,<len>");
         error
     };
-    let slice = repl::popenv(env)
+    let force = confirm::take_force(env);
+    let arg = repl::popenv(env);
+    let (start, len) = match arg {
+        Value::Unsigned(_) | Value::Pointer(_) => (
+            arg.as_ptr::<u8>(&config.page_table).map_err(usage)?,
+            mem::V4KA::SIZE,
+        ),
+        _ => arg.as_ptr_len(&config.page_table).map_err(usage)?,
+    };
+    let loader =
+        config.loader_region.start.addr()..config.loader_region.end.addr();
+    if start.addr() < loader.end && loader.start < start.addr() + len {
+        let what = alloc::format!(
+            "{start:p},{len:#x} overlaps the loader at {:#x}..{:#x}",
+            loader.start,
+            loader.end
+        );
+        confirm::confirm(config, force, &what)?;
+    }
+    let slice = arg
         .as_slice_mut(&config.page_table, mem::V4KA::SIZE)
         .and_then(|o| o.ok_or(Error::BadArgs))
         .map_err(usage)?;
@@ -0,0 +1,319 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hardware watchpoints.
+//!
+//! The four debug address registers, DR0 to DR3, each watch an
+//! aligned range of 1, 2, 4, or 8 bytes, as enabled and
+//! qualified in DR7.  When an access matches, the processor
+//! raises a #DB trap after the instruction that made it, and
+//! records which watchpoint matched in DR6.  The trap handler
+//! reports the watchpoint and the address of the instruction
+//! following the access, and carries on; if the access was made
+//! by code entered with `call`, it stops in the debugger as at a
+//! breakpoint.
+//!
+//! x86 cannot watch for reads alone, so `r` watches for reads
+//! and writes both.
+
+use crate::bldb;
+use crate::idt::TrapFrame;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+use bit_field::BitField;
+
+/// The number of debug address registers.
+const NSLOTS: usize = 4;
+
+/// The accesses a watchpoint matches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Write,
+    ReadWrite,
+}
+
+impl Kind {
+    fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "w" => Some(Kind::Write),
+            "r" | "rw" => Some(Kind::ReadWrite),
+            _ => None,
+        }
+    }
+
+    /// The encoding of the kind in the R/W field of DR7.
+    fn rw(self) -> u64 {
+        match self {
+            Kind::Write => 0b01,
+            Kind::ReadWrite => 0b11,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Write => "w",
+            Kind::ReadWrite => "rw",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Watchpoint {
+    addr: u64,
+    len: usize,
+    kind: Kind,
+}
+
+impl Watchpoint {
+    /// The encoding of the length in the LEN field of DR7.
+    fn len_code(&self) -> u64 {
+        match self.len {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        }
+    }
+}
+
+type Slots = [Option<Watchpoint>; NSLOTS];
+
+#[cfg(not(test))]
+static SLOTS: spin::Mutex<Slots> = spin::Mutex::new([None; NSLOTS]);
+
+#[cfg(test)]
+std::thread_local! {
+    static SLOTS: core::cell::RefCell<Slots> =
+        const { core::cell::RefCell::new([None; NSLOTS]) };
+}
+
+fn with<R>(f: impl FnOnce(&mut Slots) -> R) -> R {
+    #[cfg(not(test))]
+    return f(&mut SLOTS.lock());
+    #[cfg(test)]
+    return SLOTS.with_borrow_mut(f);
+}
+
+/// As `with`, but gives up rather than waiting if the slots are
+/// in use, as they may be by the code a trap interrupted.
+fn try_with<R>(f: impl FnOnce(&mut Slots) -> R) -> Option<R> {
+    #[cfg(not(test))]
+    return SLOTS.try_lock().map(|mut slots| f(&mut slots));
+    #[cfg(test)]
+    return SLOTS
+        .with(|slots| slots.try_borrow_mut().ok().map(|mut s| f(&mut s)));
+}
+
+/// Returns the value of DR7 enabling the given watchpoints.
+fn dr7(slots: &Slots) -> u64 {
+    // LE, which asks for watchpoints to be reported on the
+    // instruction that matched, and bit 10, which is reserved
+    // and set.
+    let mut dr7 = 1 << 8 | 1 << 10;
+    for (k, wp) in slots.iter().enumerate() {
+        let Some(wp) = wp else {
+            continue;
+        };
+        let field = 16 + 4 * k;
+        dr7.set_bit(2 * k, true);
+        dr7.set_bits(field..field + 2, wp.kind.rw());
+        dr7.set_bits(field + 2..field + 4, wp.len_code());
+    }
+    dr7
+}
+
+/// Loads the watchpoints into the debug registers.
+fn program(slots: &Slots) {
+    #[cfg(not(test))]
+    unsafe {
+        use x86::debugregs::{Dr7, dr0_write, dr1_write, dr2_write};
+        use x86::debugregs::{dr3_write, dr7_write};
+        let addr = |k: usize| slots[k].map_or(0, |wp| wp.addr as usize);
+        dr7_write(Dr7(0));
+        dr0_write(addr(0));
+        dr1_write(addr(1));
+        dr2_write(addr(2));
+        dr3_write(addr(3));
+        dr7_write(Dr7(dr7(slots) as usize));
+    }
+    #[cfg(test)]
+    let _ = dr7(slots);
+}
+
+/// Returns the watchpoint status bits of DR6, B0 to B3, and
+/// clears them along with the single step bit, BS, as the
+/// processor never does so itself.
+pub(super) fn status() -> u64 {
+    #[cfg(not(test))]
+    unsafe {
+        use x86::debugregs::{Dr6, dr6, dr6_write};
+        let dr6 = dr6();
+        dr6_write(dr6 - (Dr6::B0 | Dr6::B1 | Dr6::B2 | Dr6::B3 | Dr6::BS));
+        dr6.bits() as u64 & 0b1111
+    }
+    #[cfg(test)]
+    0
+}
+
+/// Reports the watchpoints that the status bits of DR6 say
+/// matched, returning true if any did.
+pub(super) fn hit(status: u64, frame: &TrapFrame) -> bool {
+    let Some(slots) = try_with(|slots| *slots) else {
+        println!(
+            "watch: {status:#06b} hit while being changed; rip {:#x}",
+            frame.rip
+        );
+        return status != 0;
+    };
+    let mut hit = false;
+    for (k, wp) in slots.iter().enumerate() {
+        let Some(wp) = wp else {
+            continue;
+        };
+        if status.get_bit(k) {
+            println!(
+                "watch: [{k}] {:#x},{} ({}) hit; rip {:#x} follows the access",
+                wp.addr,
+                wp.len,
+                wp.kind.as_str(),
+                frame.rip,
+            );
+            hit = true;
+        }
+    }
+    hit
+}
+
+fn list(slots: &Slots) {
+    if slots.iter().all(Option::is_none) {
+        println!("no watchpoints");
+    }
+    for (k, wp) in slots.iter().enumerate() {
+        if let Some(wp) = wp {
+            let kind = wp.kind.as_str();
+            println!("[{k}]: {:#x},{} ({kind})", wp.addr, wp.len);
+        }
+    }
+}
+
+pub(super) fn watch(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: watch [<addr>,<len> [r|w|rw]]");
+        error
+    };
+    let (ptr, len) = match repl::popenv(env) {
+        Value::Nil => {
+            with(|slots| list(slots));
+            return Ok(Value::Nil);
+        }
+        v => v.as_ptr_len(&config.page_table).map_err(usage)?,
+    };
+    let kind = match repl::popenv(env) {
+        Value::Nil => Kind::Write,
+        arg => {
+            let name = arg.as_string().map_err(usage)?;
+            Kind::from_name(&name)
+                .ok_or_else(|| usage(arg.bad_arg("r, w, or rw")))?
+        }
+    };
+    let addr = ptr.addr() as u64;
+    if !matches!(len, 1 | 2 | 4 | 8) {
+        println!("watch: length must be 1, 2, 4, or 8");
+        return Err(Error::BadArgs);
+    }
+    if !addr.is_multiple_of(len as u64) {
+        return Err(Error::PtrAlign);
+    }
+    let wp = Watchpoint { addr, len, kind };
+    with(|slots| {
+        let Some(k) = slots.iter().position(Option::is_none) else {
+            println!("watch: all {NSLOTS} watchpoints are in use");
+            return Err(Error::RegionBusy);
+        };
+        slots[k] = Some(wp);
+        program(slots);
+        println!("watch: [{k}] set");
+        Ok(Value::Nil)
+    })
+}
+
+pub(super) fn watchclear(
+    _config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: watchclear [<addr>]");
+        error
+    };
+    let addr = match repl::popenv(env) {
+        Value::Nil => None,
+        v => Some(v.as_num::<u64>().map_err(usage)?),
+    };
+    with(|slots| {
+        let mut cleared = false;
+        for slot in slots.iter_mut() {
+            if slot.is_some_and(|wp| addr.is_none_or(|addr| wp.addr == addr)) {
+                *slot = None;
+                cleared = true;
+            }
+        }
+        if let (Some(addr), false) = (addr, cleared) {
+            println!("watchclear: no watchpoint at {addr:#x}");
+            return Err(Error::BadArgs);
+        }
+        program(slots);
+        Ok(Value::Nil)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::sim::Sim;
+
+    #[test]
+    fn encoding() {
+        let mut slots = [None; NSLOTS];
+        assert_eq!(dr7(&slots), 0x500);
+        slots[0] = Some(Watchpoint { addr: 0, len: 4, kind: Kind::Write });
+        slots[2] = Some(Watchpoint { addr: 8, len: 8, kind: Kind::ReadWrite });
+        assert_eq!(dr7(&slots), 0x0b0d_0511);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut sim = Sim::new();
+        let out = sim.session(
+            "watch 0x1000,4\nwatch 0x2000,8 rw\nwatch 0x3001,1 r\nwatch\n",
+        );
+        assert!(out.contains("[0]: 0x1000,4 (w)"), "{out}");
+        assert!(out.contains("[1]: 0x2000,8 (rw)"), "{out}");
+        assert!(out.contains("[2]: 0x3001,1 (rw)"), "{out}");
+        let out = sim.session("watch 0x1002,4\nwatch 0x1000,3\npush\n");
+        assert!(out.contains("Pointer misaligned"), "{out}");
+        assert!(out.contains("length must be"), "{out}");
+        let out = sim.session("watch 0x4000,2 x\npush\n");
+        assert!(out.contains("usage: watch"), "{out}");
+
+        let frame = TrapFrame { rip: 0x5555, ..Default::default() };
+        let (hit, out) = sim.console("", |_| super::hit(0b0010, &frame));
+        assert!(hit);
+        assert!(out.contains("[1] 0x2000,8 (rw) hit; rip 0x5555"), "{out}");
+        assert!(!sim.console("", |_| super::hit(0b1000, &frame)).0);
+
+        sim.session("watch 0x4000,2\nwatch 0x5000,1\npush\n");
+        let out = sim.session("watch 0x6000,1\npush\n");
+        assert!(out.contains("all 4 watchpoints are in use"), "{out}");
+        let out = sim.session("watchclear 0x2000\nwatchclear 0x2000\nwatch\n");
+        assert!(out.contains("no watchpoint at 0x2000"), "{out}");
+        assert!(!out.contains("0x2000,8"), "{out}");
+        let out = sim.session("watchclear\nwatch\n");
+        assert!(out.contains("no watchpoints"), "{out}");
+    }
+}
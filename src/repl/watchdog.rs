@@ -0,0 +1,51 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value, confirm};
+use crate::result::Result;
+use crate::wdt;
+use alloc::vec::Vec;
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: watchdog [disable [-f]]");
+        error
+    };
+    match repl::popenv(env) {
+        Value::Nil => {}
+        arg => match arg.as_string().map_err(usage)?.as_str() {
+            "disable" => {
+                let force = confirm::take_force(env);
+                if wdt::petting() {
+                    let what = "the watchdog will no longer reset a hung \
+                                machine";
+                    confirm::confirm(config, force, what)?;
+                    unsafe {
+                        wdt::disable();
+                    }
+                }
+            }
+            _ => return Err(usage(arg.bad_arg("disable"))),
+        },
+    }
+    let state = if wdt::petting() { "running" } else { "not running" };
+    println!("watchdog: {state}");
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn not_running() {
+        let mut sim = crate::repl::sim::Sim::new();
+        let out = sim.session("watchdog\nwatchdog disable\nwatchdog stop\n");
+        assert_eq!(out.matches("watchdog: not running").count(), 2);
+        assert!(out.contains("arg 1: expected disable, got 'stop'"));
+    }
+}
@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Patching files on the ramdisk.
+//!
+//! `write` copies a region of memory over part of a file on the
+//! mounted ramdisk, such as to tweak `/etc/system` before
+//! booting.  Files are overwritten in place, and only where
+//! storage is already allocated to them: they cannot grow.  The
+//! built-in image is part of the loader, and is never written.
+
+use crate::bldb;
+use crate::println;
+use crate::ramdisk;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use alloc::vec::Vec;
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: write <file> <addr>,<len> [<offset>]");
+        error
+    };
+    let path = repl::popenv(env).as_string().map_err(usage)?;
+    let src = repl::popenv(env)
+        .as_slice(&config.page_table, 0)
+        .and_then(|o| o.ok_or(Error::BadArgs))
+        .map_err(usage)?;
+    let offset = match repl::popenv(env) {
+        Value::Nil => 0,
+        v => v.as_num::<usize>().map_err(usage)?,
+    };
+    if ramdisk::builtin_path(&ramdisk::normalize(&path)).is_some() {
+        return Err(Error::FsReadOnly);
+    }
+    let (mounted, inner) = config.mounts.find(&path).ok_or(Error::FsNoRoot)?;
+    let fs = mounted.fs_mut(&config.page_table)?;
+    let len = ramdisk::write(fs, &inner, offset, src)?;
+    println!("write: {len:#x} bytes written to {path} at offset {offset:#x}");
+    Ok(Value::Nil)
+}
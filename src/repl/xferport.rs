@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Selecting the UART over which files are transferred.
+//!
+//! By default, files are sent and received over the console,
+//! which then carries the transfer's protocol and nothing else.
+//! Moving transfers to UART 1 leaves the console for the
+//! progress of a transfer.  Transfers are still synchronous:
+//! the REPL waits for each to finish either way.
+
+use crate::bldb;
+use crate::println;
+use crate::repl::{self, Value};
+use crate::result::{Error, Result};
+use crate::uart;
+use alloc::vec::Vec;
+
+fn describe(config: &bldb::Config) {
+    match &config.xfer {
+        Some(uart) => println!("transfers use uart1 at {:#x}", uart.addr()),
+        None => println!("transfers use the console"),
+    }
+}
+
+pub(super) fn run(
+    config: &mut bldb::Config,
+    env: &mut Vec<Value>,
+) -> Result<Value> {
+    let usage = |error| {
+        println!("usage: xferport [console|uart0|uart1]");
+        error
+    };
+    let arg = repl::popenv(env);
+    let port = match &arg {
+        Value::Nil => {
+            describe(config);
+            return Ok(Value::Nil);
+        }
+        arg => arg.as_string().map_err(usage)?,
+    };
+    match port.as_str() {
+        "console" | "uart0" => {
+            if let Some(uart) = config.xfer.take() {
+                uart::release(uart);
+            }
+        }
+        "uart1" if config.xfer.is_some() => {}
+        "uart1" => {
+            if config.ipcc.is_some() {
+                return Err(Error::Ipcc("IPCC: channel open on UART 1"));
+            }
+            // UART 1 and the IO mux are mapped by `bldb::init`,
+            // and the SP channel is closed.
+            let Some(uart) = (unsafe { uart::xfer() }) else {
+                println!("xferport: cannot route UART 1 on this system");
+                return Err(Error::BadArgs);
+            };
+            config.xfer = Some(uart);
+        }
+        "uart2" | "uart3" => {
+            println!(
+                "xferport: UARTs 2 and 3 share their pins with the flow \
+                 control lines of UARTs 0 and 1, and cannot be used"
+            );
+            return Err(Error::BadArgs);
+        }
+        _ => return Err(usage(arg.bad_arg("console, uart0, or uart1"))),
+    }
+    describe(config);
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::sim::Sim;
+
+    #[test]
+    fn ports() {
+        let mut sim = Sim::new();
+        let out = sim.session("xferport\n");
+        assert!(out.contains("transfers use the console"), "{out}");
+        let out = sim.session("xferport uart0\n");
+        assert!(out.contains("transfers use the console"), "{out}");
+        let out = sim.session("xferport uart2\n");
+        assert!(out.contains("share their pins"), "{out}");
+        let out = sim.session("xferport uart4\n");
+        assert!(out.contains("usage: xferport"), "{out}");
+    }
+}
@@ -3,9 +3,102 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use core::fmt;
+use core::mem;
+
+/// Describes a command argument that could not be parsed: its
+/// position on the command line (if known), what was expected,
+/// and a (possibly truncated) rendering of what was received.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct BadArg {
+    position: usize,
+    expected: &'static str,
+    got: [u8; BadArg::GOT_MAX],
+    got_len: usize,
+    truncated: bool,
+}
+
+impl BadArg {
+    const GOT_MAX: usize = 32;
+
+    /// Returns a new BadArg describing an argument of unknown
+    /// position.  `got` is rendered using its `Debug` impl.
+    pub(crate) fn new(expected: &'static str, got: &dyn fmt::Debug) -> BadArg {
+        let mut arg = BadArg {
+            position: 0,
+            expected,
+            got: [0; Self::GOT_MAX],
+            got_len: 0,
+            truncated: false,
+        };
+        let _ = fmt::write(&mut arg, format_args!("{got:?}"));
+        arg
+    }
+
+    /// Returns the rendering of the received value.
+    fn got(&self) -> &str {
+        // `write_str` only ever truncates on a char boundary.
+        core::str::from_utf8(&self.got[..self.got_len]).unwrap_or("?")
+    }
+}
+
+impl fmt::Write for BadArg {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut buf = [0u8; 4];
+            let bs = c.encode_utf8(&mut buf).as_bytes();
+            let end = self.got_len + bs.len();
+            if end > Self::GOT_MAX {
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+            self.got[self.got_len..end].copy_from_slice(bs);
+            self.got_len = end;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BadArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.position != 0 {
+            write!(f, "arg {}: ", self.position)?;
+        }
+        write!(f, "expected {}, got '{}'", self.expected, self.got())?;
+        if self.truncated {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Context attached to an error, describing what was being
+/// done when it occurred, with up to two numeric payloads such
+/// as an address and length, or an inode number and offset.
+/// The message and kind of the underlying error are retained,
+/// rather than the error itself, so that the whole remains
+/// `Copy`; `Error::is` matches through the kind.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Context {
+    error: &'static str,
+    kind: mem::Discriminant<Error>,
+    what: &'static str,
+    args: [Option<u64>; 2],
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.error, self.what)?;
+        match self.args {
+            [Some(a), Some(b)] => write!(f, " {a:#x},{b:#x}")?,
+            [Some(a), None] => write!(f, " {a:#x}")?,
+            _ => {}
+        }
+        write!(f, ")")
+    }
+}
 
 /// Various errors
-#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub(crate) enum Error {
     UartFifoOverrun,
     UartParity,
@@ -19,11 +112,16 @@ pub(crate) enum Error {
     FsOffset,
     FsInvState,
     FsRead,
+    FsHole,
+    FsReadOnly,
+    FsUnsupported,
     CpioNoFile,
     ElfTruncatedObj,
     ElfParseObject,
     ElfParseHeader,
     ElfParsePHeader,
+    ElfParseSHeader,
+    ElfNoSymbols,
     ElfSegPAlign,
     ElfSegVAlign,
     ElfSegNonCanon,
@@ -43,15 +141,39 @@ pub(crate) enum Error {
     NumRange,
     NoCommand,
     BadArgs,
+    BadArg(BadArg),
+    Context(Context),
     Recv,
+    Send,
     SadBalloon,
     PtrNonCanon,
     Unmapped,
     PtrAlign,
+    AccessWidth,
     PageAlign,
     PtrProvenance,
     Offset,
+    RegionBusy,
+    CallTarget,
+    NotConfirmed,
+    NotStopped,
+    NoSymbol,
+    Trapped,
+    Cancelled,
     Mmu(&'static str),
+    Compress(&'static str),
+    Decompress(&'static str),
+    Handoff(&'static str),
+    #[cfg_attr(not(feature = "cmd-bench"), allow(dead_code))]
+    Smu(&'static str),
+    Ipcc(&'static str),
+    Tpm(&'static str),
+    Entropy(&'static str),
+    Verify,
+    StaleBuf,
+    Redirect,
+    StackDepth,
+    Replay,
 }
 
 impl Error {
@@ -68,12 +190,17 @@ impl Error {
             Self::FsNoFile => "No such file or directory",
             Self::FsOffset => "Invalid file offset (exceeds maximum)",
             Self::FsRead => "Read error",
+            Self::FsHole => "Write to a hole, which would need allocation",
+            Self::FsReadOnly => "File system is read-only",
+            Self::FsUnsupported => "File system uses unsupported features",
             Self::CpioNoFile => "File not found in archive",
             Self::FsInvState => "Invalid UFS filesystem state",
             Self::ElfTruncatedObj => "ELF: Object truncated",
             Self::ElfParseObject => "ELF: Failed to parse object",
             Self::ElfParseHeader => "ELF: Failed to parse ELF header",
             Self::ElfParsePHeader => "ELF: Failed to parse program header",
+            Self::ElfParseSHeader => "ELF: Failed to parse section header",
+            Self::ElfNoSymbols => "ELF: Object has no symbol table",
             Self::ElfSegPAlign => {
                 "ELF: program segment is not physically 4KiB aligned"
             }
@@ -99,26 +226,161 @@ impl Error {
             Self::NumRange => "Parsed number out of range",
             Self::NoCommand => "Unknown command",
             Self::BadArgs => "Bad command arguments",
+            Self::BadArg(_) => "Bad command argument",
+            Self::Context(ctx) => ctx.error,
             Self::Recv => "Receive failed",
+            Self::Send => "Send failed",
             Self::SadBalloon => "Inflate failed",
             Self::PtrNonCanon => "Pointer is non-canonical",
             Self::Unmapped => "Memory region not mapped",
             Self::PageAlign => "Address not page aligned",
             Self::PtrAlign => "Pointer misaligned",
+            Self::AccessWidth => "Access width not accepted by register",
             Self::PtrProvenance => "Pointer has unknown provenance",
             Self::Offset => "Offset out of bounds",
+            Self::RegionBusy => "Region in use; cannot be resized",
+            Self::CallTarget => "Call target is not in any known text",
+            Self::NotConfirmed => "Operation not confirmed",
+            Self::NotStopped => "Not stopped at a breakpoint or step",
+            Self::NoSymbol => "No such symbol",
+            Self::Trapped => "Command took an exception; see `regs`",
+            Self::Cancelled => "Cancelled",
             Self::Mmu(s) => s,
+            Self::Compress(s) => s,
+            Self::Decompress(s) => s,
+            Self::Handoff(s) => s,
+            Self::Smu(s) => s,
+            Self::Ipcc(s) => s,
+            Self::Tpm(s) => s,
+            Self::Entropy(s) => s,
+            Self::Verify => "Integrity verification failed",
+            Self::StaleBuf => {
+                "Buffer's mapping has changed since it was created"
+            }
+            Self::Redirect => "Output overflowed the redirection buffer",
+            Self::StackDepth => "Too few items on the environment stack",
+            Self::Replay => "Malformed replay script",
         }
     }
 }
 
+impl Error {
+    /// Records the command line position of a bad argument.
+    /// Errors that do not describe an argument, or that already
+    /// know their position, are returned unchanged.
+    pub fn at_arg(self, position: usize) -> Error {
+        match self {
+            Self::BadArg(mut arg) if arg.position == 0 => {
+                arg.position = position;
+                Self::BadArg(arg)
+            }
+            e => e,
+        }
+    }
+
+    /// Returns true if this error, or the error it attaches
+    /// context to, is of the same kind as `kind`.  Any payload is
+    /// ignored, so that, e.g., `Error::Mmu("")` matches every MMU
+    /// error.  Callers looking for a particular error should use
+    /// this rather than matching the variant, which context hides.
+    pub fn is(self, kind: Error) -> bool {
+        let theirs = mem::discriminant(&kind);
+        match self {
+            Self::Context(ctx) => ctx.kind == theirs,
+            _ => mem::discriminant(&self) == theirs,
+        }
+    }
+
+    /// Attaches context to an error: a description of what was
+    /// being done, and up to two numeric payloads; any more are
+    /// ignored.  Errors that already carry context or describe a
+    /// bad argument are more specific, and are returned
+    /// unchanged.
+    pub fn context(self, what: &'static str, args: &[u64]) -> Error {
+        match self {
+            Self::BadArg(_) | Self::Context(_) => self,
+            _ => Self::Context(Context {
+                error: self.as_str(),
+                kind: mem::discriminant(&self),
+                what,
+                args: [args.first().copied(), args.get(1).copied()],
+            }),
+        }
+    }
+}
+
+/// Attaches context to the error in a `Result`, if any.
+pub(crate) trait ResultExt<T> {
+    fn context(self, what: &'static str, args: &[u64]) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn context(self, what: &'static str, args: &[u64]) -> Result<T> {
+        self.map_err(|e| e.context(what, args))
+    }
+}
+
 impl fmt::Debug for Error {
     fn fmt(
         &self,
         f: &mut fmt::Formatter<'_>,
     ) -> core::result::Result<(), fmt::Error> {
-        write!(f, "{}", self.as_str())
+        match self {
+            Self::BadArg(arg) => write!(f, "{arg}"),
+            Self::Context(ctx) => write!(f, "{ctx}"),
+            _ => write!(f, "{}", self.as_str()),
+        }
     }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_arg_position_and_value() {
+        let e = Error::BadArg(BadArg::new("number", &"foo")).at_arg(2);
+        assert_eq!(format!("{e:?}"), r#"arg 2: expected number, got '"foo"'"#);
+        assert_eq!(e.at_arg(3), e);
+        assert_eq!(Error::BadArgs.at_arg(1), Error::BadArgs);
+    }
+
+    #[test]
+    fn context_payloads() {
+        let e = Error::FsNoFile.context("lookup in directory inode", &[2]);
+        assert_eq!(
+            format!("{e:?}"),
+            "No such file or directory (lookup in directory inode 0x2)"
+        );
+        let e = e.context("ignored", &[]);
+        assert_eq!(e.as_str(), Error::FsNoFile.as_str());
+        let e = Error::Unmapped.context("range", &[0x1000, 0x2000, 3]);
+        assert_eq!(
+            format!("{e:?}"),
+            "Memory region not mapped (range 0x1000,0x2000)"
+        );
+    }
+
+    #[test]
+    fn kinds_through_context() {
+        let e = Error::Timeout.context("draining uart addr", &[0x1000]);
+        assert_ne!(e, Error::Timeout);
+        assert!(e.is(Error::Timeout));
+        assert!(!e.is(Error::Cancelled));
+        assert!(Error::Cancelled.is(Error::Cancelled));
+        assert!(Error::Mmu("unmap").is(Error::Mmu("")));
+        let e = Error::Mmu("unmap").context("range", &[]);
+        assert!(e.is(Error::Mmu("")) && !e.is(Error::Compress("")));
+    }
+
+    #[test]
+    fn bad_arg_truncates() {
+        let long = "x".repeat(100);
+        let e = Error::BadArg(BadArg::new("number", &long));
+        let s = format!("{e:?}");
+        assert!(s.starts_with("expected number, got '\"xxx"));
+        assert!(s.ends_with("'..."));
+    }
+}
@@ -0,0 +1,533 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Destinations for console output.
+//!
+//! `print!` and `println!` do not write to the UART directly,
+//! but to every enabled sink whose level admits the message:
+//! the console UART, and an in-memory log of recent output that
+//! survives a console that has gone bad.  Which sinks are
+//! enabled, and how verbose each is, may be changed at run
+//! time.  Adding a sink, such as a network console, means
+//! implementing `ConsoleSink` for it and adding it to `Sink`.
+//!
+//! Interactive input and its echo go straight to the console
+//! UART, and are not sent to the sinks.
+//!
+//! Output bound for the console UART may instead be captured
+//! into a memory buffer for the duration of a single command.
+//!
+//! A transcript of a session may also be recorded into a memory
+//! buffer: the lines typed at the prompt, and the output that
+//! reaches the console, each line stamped with the time since
+//! power on, so that a whole session can be archived without
+//! relying on the terminal on the other end to log it.
+
+use crate::clock;
+use crate::uart::{self, Uart};
+use core::fmt::{self, Write};
+
+/// The importance of a message.  Each sink accepts messages at
+/// or above its own level.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub(crate) const ALL: [Level; 4] =
+        [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|l| l.name() == name)
+    }
+}
+
+/// Something that console output may be written to.
+pub(crate) trait ConsoleSink {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+impl ConsoleSink for Uart {
+    fn write(&mut self, bytes: &[u8]) {
+        self.putbs_crnl(bytes);
+    }
+}
+
+/// How much recent output the in-memory log holds: enough for
+/// the whole of a long boot, much of which will have scrolled
+/// out of the terminal on the other end by the time it fails.
+const LOG_LEN: usize = 256 * 1024;
+
+/// A ring buffer holding the most recent output.
+struct Ring {
+    buf: [u8; LOG_LEN],
+    next: usize,
+    wrapped: bool,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { buf: [0; LOG_LEN], next: 0, wrapped: false }
+    }
+
+    /// Returns the number of bytes held.
+    fn len(&self) -> usize {
+        if self.wrapped { LOG_LEN } else { self.next }
+    }
+
+    /// Returns the bytes held, oldest first, as two slices.
+    fn halves(&self) -> (&[u8], &[u8]) {
+        let (newer, older) = self.buf.split_at(self.next);
+        if self.wrapped { (older, newer) } else { (&[], newer) }
+    }
+
+    /// Returns the most recent `len` bytes held, oldest first,
+    /// as two slices.
+    fn tail(&self, len: usize) -> (&[u8], &[u8]) {
+        let (older, newer) = self.halves();
+        let skip = (older.len() + newer.len()).saturating_sub(len);
+        let n = skip.min(older.len());
+        (&older[n..], &newer[skip - n..])
+    }
+
+    fn clear(&mut self) {
+        self.next = 0;
+        self.wrapped = false;
+    }
+}
+
+impl ConsoleSink for Ring {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.buf[self.next] = b;
+            self.next = (self.next + 1) % LOG_LEN;
+            self.wrapped |= self.next == 0;
+        }
+    }
+}
+
+/// A memory buffer that console output is being captured into.
+/// Output that does not fit is dropped, and noted.
+struct Capture {
+    ptr: *mut u8,
+    len: usize,
+    captured: Captured,
+}
+
+// Safety: a `Capture` is only created by `capture`, which holds
+// the borrow of the buffer for as long as the `Capture` exists.
+unsafe impl Send for Capture {}
+
+/// The result of a capture: how many bytes were written to the
+/// buffer, and whether some output did not fit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Captured {
+    pub(crate) len: usize,
+    pub(crate) overflowed: bool,
+}
+
+impl Capture {
+    fn new(buf: &mut [u8]) -> Capture {
+        let captured = Captured::default();
+        Capture { ptr: buf.as_mut_ptr(), len: buf.len(), captured }
+    }
+}
+
+impl ConsoleSink for Capture {
+    fn write(&mut self, bytes: &[u8]) {
+        let written = self.captured.len;
+        let n = bytes.len().min(self.len - written);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.ptr.add(written),
+                n,
+            );
+        }
+        self.captured.len += n;
+        self.captured.overflowed |= n < bytes.len();
+    }
+}
+
+/// A transcript of the session being recorded into a memory
+/// buffer.  Once the buffer is full, the rest of the session is
+/// dropped, and that noted.
+struct Transcript {
+    ptr: *mut u8,
+    len: usize,
+    state: TranscriptState,
+    /// Whether the next byte starts a line, and so is preceded
+    /// by a timestamp.
+    line_start: bool,
+}
+
+// Safety: a `Transcript` is only created by `transcript_start`,
+// from a buffer borrowed for the rest of the loader's life.
+unsafe impl Send for Transcript {}
+
+/// The state of a transcript: where it is, how much has been
+/// recorded, and whether it is still being recorded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct TranscriptState {
+    pub(crate) addr: usize,
+    pub(crate) len: usize,
+    pub(crate) overflowed: bool,
+    pub(crate) recording: bool,
+}
+
+impl Transcript {
+    fn new(buf: &mut [u8]) -> Transcript {
+        let ptr = buf.as_mut_ptr();
+        let state = TranscriptState {
+            addr: ptr.addr(),
+            recording: true,
+            ..Default::default()
+        };
+        Transcript { ptr, len: buf.len(), state, line_start: true }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let written = self.state.len;
+        let n = bytes.len().min(self.len - written);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                self.ptr.add(written),
+                n,
+            );
+        }
+        self.state.len += n;
+        self.state.overflowed |= n < bytes.len();
+    }
+
+    /// Writes the time since power on, to the microsecond.
+    fn stamp(&mut self) {
+        struct Raw<'a>(&'a mut Transcript);
+        impl Write for Raw<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.push(s.as_bytes());
+                Ok(())
+            }
+        }
+        let t = clock::uptime();
+        let (secs, us) = (t.as_secs(), t.subsec_micros());
+        let _ = write!(Raw(self), "[{secs:>6}.{us:06}] ");
+    }
+}
+
+impl ConsoleSink for Transcript {
+    fn write(&mut self, bytes: &[u8]) {
+        if !self.state.recording {
+            return;
+        }
+        for line in bytes.split_inclusive(|&b| b == b'\n') {
+            if self.line_start {
+                self.stamp();
+            }
+            self.push(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+    }
+}
+
+/// The sinks that output may be sent to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Sink {
+    Uart,
+    Log,
+}
+
+impl Sink {
+    pub(crate) const ALL: [Sink; 2] = [Sink::Uart, Sink::Log];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Sink::Uart => "uart",
+            Sink::Log => "log",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Sink> {
+        Sink::ALL.into_iter().find(|s| s.name() == name)
+    }
+}
+
+/// The sinks and the most verbose level each accepts, or `None`
+/// if the sink is disabled, the buffer that output bound for
+/// the UART is being captured into instead, if any, and the
+/// last transcript started, if any.
+struct Sinks {
+    levels: [Option<Level>; Sink::ALL.len()],
+    log: Ring,
+    capture: Option<Capture>,
+    transcript: Option<Transcript>,
+}
+
+impl Sinks {
+    const fn new() -> Sinks {
+        Sinks {
+            levels: [Some(Level::Info), Some(Level::Debug)],
+            log: Ring::new(),
+            capture: None,
+            transcript: None,
+        }
+    }
+
+    fn accepts(&self, sink: Sink, level: Level) -> bool {
+        self.levels[sink as usize].is_some_and(|max| level <= max)
+    }
+
+    fn emit(&mut self, level: Level, args: fmt::Arguments<'_>) {
+        struct Tee<'a> {
+            sinks: &'a mut Sinks,
+            level: Level,
+        }
+        impl Write for Tee<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                for sink in Sink::ALL {
+                    if !self.sinks.accepts(sink, self.level) {
+                        continue;
+                    }
+                    match sink {
+                        Sink::Uart => match &mut self.sinks.capture {
+                            Some(capture) => capture.write(s.as_bytes()),
+                            None => {
+                                uart::cons().write(s.as_bytes());
+                                if let Some(t) = &mut self.sinks.transcript {
+                                    t.write(s.as_bytes());
+                                }
+                            }
+                        },
+                        Sink::Log => self.sinks.log.write(s.as_bytes()),
+                    }
+                }
+                Ok(())
+            }
+        }
+        let _ = Tee { sinks: self, level }.write_fmt(args);
+    }
+}
+
+static SINKS: spin::Mutex<Sinks> = spin::Mutex::new(Sinks::new());
+
+/// Writes a message to every sink that accepts its level.
+pub(crate) fn emit(level: Level, args: fmt::Arguments<'_>) {
+    match SINKS.try_lock() {
+        Some(mut sinks) => sinks.emit(level, args),
+        // We are printing from within a sink, or panicked while
+        // printing: write straight to the console.
+        None => {
+            let _ = uart::cons().write_fmt(args);
+        }
+    }
+}
+
+/// Writes an ordinary message, as from `print!`.
+pub fn print(args: fmt::Arguments<'_>) {
+    emit(Level::Info, args);
+}
+
+/// Returns the level of the given sink, or `None` if disabled.
+pub(crate) fn level(sink: Sink) -> Option<Level> {
+    SINKS.lock().levels[sink as usize]
+}
+
+/// Sets the level of the given sink, or disables it.
+pub(crate) fn set_level(sink: Sink, level: Option<Level>) {
+    SINKS.lock().levels[sink as usize] = level;
+}
+
+/// Returns the number of bytes held in the in-memory log.
+pub(crate) fn logged() -> usize {
+    SINKS.lock().log.len()
+}
+
+/// Calls `f`, capturing the output it would send to the console
+/// UART into `buf` rather than printing it.
+pub(crate) fn capture<R>(
+    buf: &mut [u8],
+    f: impl FnOnce() -> R,
+) -> (R, Captured) {
+    let saved = SINKS.lock().capture.replace(Capture::new(buf));
+    let r = f();
+    let capture = core::mem::replace(&mut SINKS.lock().capture, saved);
+    (r, capture.expect("capture in progress").captured)
+}
+
+/// Starts recording a transcript of the session into `buf`,
+/// replacing any previous transcript.
+pub(crate) fn transcript_start(buf: &'static mut [u8]) {
+    SINKS.lock().transcript = Some(Transcript::new(buf));
+}
+
+/// Stops recording the transcript, and returns its state, or
+/// `None` if no transcript was started.
+pub(crate) fn transcript_stop() -> Option<TranscriptState> {
+    let mut sinks = SINKS.lock();
+    let transcript = sinks.transcript.as_mut()?;
+    transcript.state.recording = false;
+    Some(transcript.state)
+}
+
+/// Returns the state of the transcript, if one was started.
+pub(crate) fn transcript() -> Option<TranscriptState> {
+    SINKS.lock().transcript.as_ref().map(|t| t.state)
+}
+
+/// Records a line typed at the prompt in the transcript, if one
+/// is being recorded.
+pub(crate) fn transcribe_input(line: &str) {
+    if let Some(t) = &mut SINKS.lock().transcript {
+        t.write(b"@");
+        t.write(line.as_bytes());
+        t.write(b"\n");
+    }
+}
+
+/// Calls `f` with the most recent `len` bytes of the in-memory
+/// log, or all of it, oldest first, as two slices.
+pub(crate) fn with_log<R>(
+    len: Option<usize>,
+    f: impl FnOnce(&[u8], &[u8]) -> R,
+) -> R {
+    let sinks = SINKS.lock();
+    let (older, newer) = sinks.log.tail(len.unwrap_or(LOG_LEN));
+    f(older, newer)
+}
+
+/// Like `with_log`, but returns `None` without calling `f` if
+/// the log is in use, so that this may be used when we cannot
+/// afford to wait, as after a failure while printing.
+pub(crate) fn try_log<R>(
+    len: Option<usize>,
+    f: impl FnOnce(&[u8], &[u8]) -> R,
+) -> Option<R> {
+    let sinks = SINKS.try_lock()?;
+    let (older, newer) = sinks.log.tail(len.unwrap_or(LOG_LEN));
+    Some(f(older, newer))
+}
+
+/// Writes a message to the in-memory log alone, as when there
+/// is no UART to send it to.  The message is dropped if the log
+/// is in use, so that this may be used after a failure while
+/// printing.
+pub(crate) fn log(args: fmt::Arguments<'_>) {
+    struct Log<'a>(&'a mut Ring);
+    impl Write for Log<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0.write(s.as_bytes());
+            Ok(())
+        }
+    }
+    if let Some(mut sinks) = SINKS.try_lock() {
+        let _ = Log(&mut sinks.log).write_fmt(args);
+    }
+}
+
+/// Discards the contents of the in-memory log.
+pub(crate) fn clear_log() {
+    SINKS.lock().log.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let mut ring = Ring::new();
+        assert_eq!(ring.len(), 0);
+        ring.write(b"hello");
+        assert_eq!(ring.len(), 5);
+        assert_eq!(&ring.buf[..5], b"hello");
+        ring.write(&[b'x'; LOG_LEN - 2]);
+        assert_eq!(ring.len(), LOG_LEN);
+        assert_eq!(ring.next, 3);
+        assert_eq!(&ring.buf[..4], b"xxxl");
+        let (older, newer) = ring.halves();
+        assert_eq!(older.len() + newer.len(), LOG_LEN);
+        assert_eq!(&older[..3], b"lox");
+        assert_eq!(newer, b"xxx");
+        assert_eq!(ring.tail(5), (&b"xx"[..], &b"xxx"[..]));
+        assert_eq!(ring.tail(2), (&b""[..], &b"xx"[..]));
+        assert_eq!(ring.tail(LOG_LEN + 1).0.len(), LOG_LEN - 3);
+        ring.clear();
+        assert_eq!(ring.len(), 0);
+        ring.write(b"again");
+        assert_eq!(ring.tail(3), (&b""[..], &b"ain"[..]));
+    }
+
+    #[test]
+    fn filtering() {
+        let mut sinks = Sinks::new();
+        sinks.levels[Sink::Uart as usize] = None;
+        assert!(sinks.accepts(Sink::Log, Level::Debug));
+        assert!(!sinks.accepts(Sink::Uart, Level::Error));
+        sinks.emit(Level::Info, format_args!("one"));
+        sinks.levels[Sink::Log as usize] = Some(Level::Warn);
+        sinks.emit(Level::Info, format_args!("two"));
+        sinks.emit(Level::Error, format_args!("{}", 3));
+        assert_eq!(&sinks.log.buf[..sinks.log.len()], b"one3");
+        assert_eq!(Level::from_name("warn"), Some(Level::Warn));
+        assert_eq!(Sink::from_name("log"), Some(Sink::Log));
+        assert_eq!(Sink::from_name("net"), None);
+    }
+
+    #[test]
+    fn capturing() {
+        let mut buf = [0u8; 8];
+        let mut sinks = Sinks::new();
+        sinks.capture = Some(Capture::new(&mut buf));
+        sinks.emit(Level::Info, format_args!("abc"));
+        sinks.emit(Level::Debug, format_args!("hidden"));
+        let captured = sinks.capture.as_ref().unwrap().captured;
+        assert_eq!(captured, Captured { len: 3, overflowed: false });
+        sinks.emit(Level::Warn, format_args!("{}", "defghi"));
+        let captured = sinks.capture.take().unwrap().captured;
+        assert_eq!(captured, Captured { len: 8, overflowed: true });
+        assert_eq!(&sinks.log.buf[..sinks.log.len()], b"abchiddendefghi");
+        assert_eq!(&buf, b"abcdefgh");
+    }
+
+    #[test]
+    fn transcribing() {
+        let mut buf = [0u8; 64];
+        let mut sinks = Sinks::new();
+        sinks.transcript = Some(Transcript::new(&mut buf));
+        sinks.emit(Level::Info, format_args!("one\ntw"));
+        sinks.emit(Level::Debug, format_args!("hidden"));
+        sinks.emit(Level::Info, format_args!("o\n"));
+        let mut capture = [0u8; 8];
+        sinks.capture = Some(Capture::new(&mut capture));
+        sinks.emit(Level::Info, format_args!("captured"));
+        sinks.capture = None;
+        let state = sinks.transcript.as_ref().unwrap().state;
+        assert!(state.recording && !state.overflowed);
+        let text = core::str::from_utf8(&buf[..state.len]).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for (line, expected) in lines.iter().zip(["one", "two"]) {
+            let (stamp, rest) = line.split_once("] ").unwrap();
+            assert!(stamp.starts_with('[') && stamp.contains('.'));
+            assert_eq!(rest, expected);
+        }
+        let transcript = sinks.transcript.as_mut().unwrap();
+        transcript.write(&[b'x'; 64]);
+        assert!(transcript.state.overflowed);
+        assert_eq!(transcript.state.len, 64);
+    }
+}
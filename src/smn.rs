@@ -5,8 +5,10 @@
 use crate::pci;
 use crate::result::{Error, Result};
 use core::convert::TryFrom;
+use core::fmt;
 use spin::Mutex;
 
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum Index {
     Smn0 = 0,
     Smn1 = 1,
@@ -87,3 +89,107 @@ pub(crate) unsafe fn write(k: Index, addr: u32, data: u32) -> Result<()> {
     }
     Ok(())
 }
+
+/// A range of SMN addresses belonging to an IP block, which may
+/// have several instances at regular intervals.
+pub(crate) struct Aperture {
+    name: &'static str,
+    base: u32,
+    size: u32,
+    instances: u32,
+    stride: u32,
+}
+
+impl Aperture {
+    const fn one(name: &'static str, base: u32, size: u32) -> Aperture {
+        Aperture { name, base, size, instances: 1, stride: 0 }
+    }
+
+    const fn many(
+        name: &'static str,
+        base: u32,
+        size: u32,
+        instances: u32,
+        stride: u32,
+    ) -> Aperture {
+        Aperture { name, base, size, instances, stride }
+    }
+}
+
+/// The IP block apertures of the SMN address space.  The bases
+/// and instance strides are those of the register addresses in
+/// the "Processor Programming Reference (PPR) for AMD Family 19h
+/// Model 01h, Revision B1 Processors" (pub. 55898), which are
+/// also encoded in illumos's `usr/src/uts/intel/sys/amdzen`
+/// headers; Linux's k10temp driver reads THM's temperature
+/// register at 0x5_9800, and smu.rs uses the MP1 mailboxes.
+/// Blocks that appear once have no instance number.
+pub(crate) const MAP: &[Aperture] = &[
+    Aperture::many("UMC", 0x0005_0000, 0x2000, 8, 0x0010_0000),
+    Aperture::one("THM", 0x0005_9000, 0x1000),
+    Aperture::one("SMUIO", 0x0005_a000, 0x1000),
+    Aperture::one("FCH", 0x02d0_0000, 0x0002_0000),
+    Aperture::one("MP0", 0x0380_0000, 0x0010_0000),
+    Aperture::one("MP1", 0x03b0_0000, 0x0010_0000),
+    Aperture::many("PCIECORE", 0x1110_0000, 0x0001_0000, 4, 0x0010_0000),
+    Aperture::many("PCIEPORT", 0x1114_0000, 0x0001_0000, 4, 0x0010_0000),
+    Aperture::many("IOHC", 0x13b0_0000, 0x0010_0000, 4, 0x0010_0000),
+    Aperture::many("IOAGR", 0x15b0_0000, 0x0010_0000, 4, 0x0010_0000),
+];
+
+/// An SMN address, decoded as a register in an IP block.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Decoded {
+    block: &'static str,
+    instance: Option<u32>,
+    offset: u32,
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.block)?;
+        if let Some(instance) = self.instance {
+            write!(f, "{instance}")?;
+        }
+        write!(f, "+{:#x}", self.offset)
+    }
+}
+
+/// Decodes an SMN address as an offset into an instance of an
+/// IP block, if it falls in a known aperture.
+pub(crate) fn decode(addr: u32) -> Option<Decoded> {
+    MAP.iter().find_map(|ap| {
+        let rel = addr.checked_sub(ap.base)?;
+        let instance = if ap.instances > 1 { rel / ap.stride } else { 0 };
+        let offset = rel - instance * ap.stride;
+        (instance < ap.instances && offset < ap.size).then_some(Decoded {
+            block: ap.name,
+            instance: (ap.instances > 1).then_some(instance),
+            offset,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apertures() {
+        for ap in MAP {
+            assert!(ap.instances == 1 || ap.size <= ap.stride, "{}", ap.name);
+        }
+        let thm = decode(0x5_9800).unwrap();
+        assert_eq!(
+            thm,
+            Decoded { block: "THM", instance: None, offset: 0x800 }
+        );
+        assert_eq!(alloc::format!("{thm}"), "THM+0x800");
+        let umc = decode(0x35_0104).unwrap();
+        assert_eq!(alloc::format!("{umc}"), "UMC3+0x104");
+        assert_eq!(decode(0x5_2000), None);
+        assert_eq!(decode(0x85_0000), None);
+        assert_eq!(decode(0x13b1_0030).map(|d| d.instance), Some(Some(0)));
+        assert_eq!(decode(0x0), None);
+    }
+}
@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Remote procedure calls to the System Management Unit.
+//!
+//! The SMU (MP1) firmware exposes mailboxes in SMN space, each a
+//! request register, a response register, and six argument
+//! registers.  A call waits for any previous call to complete,
+//! clears the response, writes the arguments and then the
+//! request, and polls until the firmware posts a response, at
+//! which point the argument registers hold the results.
+//!
+//! The MP1 mailbox takes requests from the BIOS.  The RSMU
+//! mailbox serves the power management (PM) table, in which the
+//! firmware publishes what it measures and enforces.  The
+//! mailbox addresses and the PM table requests are those the
+//! ryzen_smu driver (<https://gitlab.com/leogx9r/ryzen_smu>)
+//! uses for Zen 2 and Zen 3 processors.
+
+use crate::clock::Deadline;
+use crate::result::{Error, Result};
+use crate::smn;
+use core::time::Duration;
+
+/// The registers of a mailbox.
+#[derive(Clone, Copy)]
+struct Mailbox {
+    req: u32,
+    resp: u32,
+    arg0: u32,
+}
+
+const MP1: Mailbox =
+    Mailbox { req: 0x3b1_0530, resp: 0x3b1_057c, arg0: 0x3b1_09c4 };
+const RSMU: Mailbox =
+    Mailbox { req: 0x3b1_0524, resp: 0x3b1_0570, arg0: 0x3b1_0a40 };
+const NARGS: usize = 6;
+
+/// How long to wait for the firmware to respond.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Response codes posted by the firmware.
+const RESP_BUSY: u32 = 0x00;
+const RESP_OK: u32 = 0x01;
+const RESP_FAILED: u32 = 0xff;
+const RESP_UNKNOWN_CMD: u32 = 0xfe;
+const RESP_REJECTED_PREREQ: u32 = 0xfd;
+const RESP_REJECTED_BUSY: u32 = 0xfc;
+
+/// Returns the firmware version in the first argument.  This
+/// request is understood by all SMU firmware we know of.
+const REQ_GET_VERSION: u32 = 0x02;
+
+/// RSMU requests: copy the PM table to DRAM, return its
+/// physical address in the first two arguments, low half first,
+/// and return its version in the first argument.
+const RSMU_REQ_TRANSFER_TABLE: u32 = 0x05;
+const RSMU_REQ_GET_TABLE_ADDR: u32 = 0x06;
+const RSMU_REQ_GET_TABLE_VERSION: u32 = 0x08;
+
+fn read(reg: u32) -> Result<u32> {
+    smn::read(smn::Index::Smn0, reg)
+}
+
+fn write(reg: u32, value: u32) -> Result<()> {
+    unsafe { smn::write(smn::Index::Smn0, reg, value) }
+}
+
+/// Polls the response register until the firmware posts a
+/// response or the timeout expires.
+fn wait_response(mailbox: Mailbox) -> Result<u32> {
+    let deadline = Deadline::after(TIMEOUT);
+    loop {
+        let resp = read(mailbox.resp)?;
+        if resp != RESP_BUSY {
+            return Ok(resp);
+        }
+        if deadline.expired() {
+            return Err(Error::Smu("SMU: timed out waiting for response"));
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Issues a request to the SMU through the MP1 mailbox,
+/// returning the contents of the argument registers on success.
+///
+/// # Safety
+/// Depending on the request, the firmware may reconfigure the
+/// hardware in arbitrary ways.
+pub(crate) unsafe fn rpc(req: u32, args: [u32; NARGS]) -> Result<[u32; NARGS]> {
+    unsafe { call(MP1, req, args) }
+}
+
+/// Issues a request through the given mailbox.
+///
+/// # Safety
+/// As for `rpc`.
+unsafe fn call(
+    mailbox: Mailbox,
+    req: u32,
+    args: [u32; NARGS],
+) -> Result<[u32; NARGS]> {
+    wait_response(mailbox)?;
+    write(mailbox.resp, RESP_BUSY)?;
+    for (k, &arg) in args.iter().enumerate() {
+        write(mailbox.arg0 + 4 * k as u32, arg)?;
+    }
+    write(mailbox.req, req)?;
+    match wait_response(mailbox)? {
+        RESP_OK => {}
+        RESP_FAILED => return Err(Error::Smu("SMU: request failed")),
+        RESP_UNKNOWN_CMD => return Err(Error::Smu("SMU: unknown request")),
+        RESP_REJECTED_PREREQ => {
+            return Err(Error::Smu("SMU: request prerequisites unmet"));
+        }
+        RESP_REJECTED_BUSY => return Err(Error::Smu("SMU: firmware busy")),
+        _ => return Err(Error::Smu("SMU: unexpected response")),
+    }
+    let mut results = [0; NARGS];
+    for (k, result) in results.iter_mut().enumerate() {
+        *result = read(mailbox.arg0 + 4 * k as u32)?;
+    }
+    Ok(results)
+}
+
+/// Returns the SMU firmware version as (major, minor, patch).
+pub(crate) fn version() -> Result<(u8, u8, u8)> {
+    let [v, ..] = unsafe { rpc(REQ_GET_VERSION, [0; NARGS])? };
+    Ok(((v >> 16) as u8, (v >> 8) as u8, v as u8))
+}
+
+/// Returns the version and physical address of the PM table.
+pub(crate) fn pm_table() -> Result<(u32, u64)> {
+    let [version, ..] =
+        unsafe { call(RSMU, RSMU_REQ_GET_TABLE_VERSION, [0; NARGS])? };
+    let [lo, hi, ..] =
+        unsafe { call(RSMU, RSMU_REQ_GET_TABLE_ADDR, [0; NARGS])? };
+    Ok((version, u64::from(hi) << 32 | u64::from(lo)))
+}
+
+/// Has the firmware copy the current PM table to DRAM.
+pub(crate) fn transfer_pm_table() -> Result<()> {
+    unsafe { call(RSMU, RSMU_REQ_TRANSFER_TABLE, [0; NARGS])? };
+    Ok(())
+}
@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Symbol tables, for resolving names to addresses and back.
+//!
+//! A table is read from the ELF symbol table of an image with
+//! `syms`, and kept with the configuration.  Addresses are
+//! resolved to the nearest symbol at or below them, as
+//! `name+offset`, provided that they fall within its size;
+//! symbols without a size, such as labels in assembly, cover
+//! everything up to the next.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A named address, and the size of the object or function
+/// there.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Symbol {
+    pub(crate) name: String,
+    pub(crate) addr: u64,
+    pub(crate) size: u64,
+}
+
+/// A symbol table, indexed by address and by name.
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+    /// What the symbols were read from.
+    source: String,
+    /// The symbols, in address order.
+    by_addr: Vec<Symbol>,
+    /// The index of each symbol in `by_addr`, by name.
+    by_name: BTreeMap<String, usize>,
+}
+
+impl Symbols {
+    /// Builds a table from the given symbols.  Where names are
+    /// repeated, as with static functions in different files,
+    /// lookups by name find the first.
+    pub(crate) fn new(source: &str, mut symbols: Vec<Symbol>) -> Symbols {
+        symbols.sort_by(|a, b| (a.addr, &a.name).cmp(&(b.addr, &b.name)));
+        symbols.dedup();
+        let mut by_name = BTreeMap::new();
+        for (k, sym) in symbols.iter().enumerate() {
+            by_name.entry(sym.name.clone()).or_insert(k);
+        }
+        Symbols { source: source.into(), by_addr: symbols, by_name }
+    }
+
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.by_addr.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Returns the symbol with the given name.
+    pub(crate) fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.by_name.get(name).map(|&k| &self.by_addr[k])
+    }
+
+    /// Returns the symbol covering the given address, and the
+    /// offset of the address within it.  Of several symbols at
+    /// the same address, the one with a size that covers it is
+    /// preferred.
+    pub(crate) fn resolve(&self, addr: u64) -> Option<(&Symbol, u64)> {
+        let end = self.by_addr.partition_point(|sym| sym.addr <= addr);
+        let base = self.by_addr[..end].last()?.addr;
+        let start = self.by_addr[..end].partition_point(|sym| sym.addr < base);
+        let candidates = &self.by_addr[start..end];
+        let offset = addr - base;
+        candidates
+            .iter()
+            .find(|sym| offset < sym.size)
+            .or_else(|| candidates.iter().find(|sym| sym.size == 0))
+            .map(|sym| (sym, offset))
+    }
+
+    /// Returns the address as `name+offset`, or just the name if
+    /// the offset is 0, if a symbol covers it.
+    pub(crate) fn describe(&self, addr: u64) -> Option<String> {
+        self.resolve(addr).map(|(sym, offset)| match offset {
+            0 => sym.name.clone(),
+            _ => format!("{}+{offset:#x}", sym.name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(name: &str, addr: u64, size: u64) -> Symbol {
+        Symbol { name: name.into(), addr, size }
+    }
+
+    #[test]
+    fn resolution() {
+        let symbols = Symbols::new(
+            "unix",
+            alloc::vec![
+                sym("main", 0x1000, 0x20),
+                sym("_start", 0x800, 0),
+                sym("alias", 0x1000, 0),
+                sym("data", 0x2000, 8),
+                sym("main", 0x3000, 4),
+            ],
+        );
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.lookup("main").map(|s| s.addr), Some(0x1000));
+        assert_eq!(symbols.lookup("nothing"), None);
+        assert_eq!(symbols.describe(0x7ff), None);
+        assert_eq!(symbols.describe(0x800).as_deref(), Some("_start"));
+        assert_eq!(symbols.describe(0xfff).as_deref(), Some("_start+0x7ff"));
+        assert_eq!(symbols.describe(0x1010).as_deref(), Some("main+0x10"));
+        assert_eq!(symbols.describe(0x1020).as_deref(), Some("alias+0x20"));
+        assert_eq!(symbols.describe(0x2007).as_deref(), Some("data+0x7"));
+        assert_eq!(symbols.describe(0x2008), None);
+        assert!(Symbols::default().resolve(0x1000).is_none());
+    }
+}
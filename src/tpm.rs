@@ -0,0 +1,583 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A minimal TPM 2.0 driver, used for measured boot.
+//!
+//! We speak to locality 0 of a TPM at its standard MMIO address,
+//! using either the FIFO (TIS) or Command Response Buffer (CRB)
+//! interface, as reported by the device.  Only the handful of
+//! commands needed to extend and read SHA-256 PCRs are
+//! implemented.
+//!
+//! Images loaded or called from the REPL are measured into
+//! `IMAGE_PCR`, and every measurement is recorded in a log,
+//! whether or not a TPM is present, so that bring-up flows can
+//! be reconciled with the values a verifier will see.
+
+use crate::clock::Deadline;
+use crate::result::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+use core::time::Duration;
+
+/// The physical (and identity mapped) address of locality 0.
+const TPM_BASE_ADDR: usize = 0xfed4_0000;
+
+/// The PCR into which images are measured.
+pub(crate) const IMAGE_PCR: u32 = 9;
+
+/// The number of PCRs in the SHA-256 bank.
+pub(crate) const NPCRS: u32 = 24;
+
+/// How long to wait for locality and state changes, and for
+/// commands to complete.
+const TIMEOUT: Duration = Duration::from_secs(2);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest command or response we exchange.
+const MAX_LEN: usize = 4096;
+
+// Registers common to both interfaces.
+const INTERFACE_ID: usize = 0x30;
+
+// FIFO interface registers and bits.
+const ACCESS: usize = 0x00;
+const ACCESS_REQUEST_USE: u8 = 1 << 1;
+const ACCESS_ACTIVE_LOCALITY: u8 = 1 << 5;
+const ACCESS_VALID: u8 = 1 << 7;
+const STS: usize = 0x18;
+const STS_DATA_AVAIL: u32 = 1 << 4;
+const STS_GO: u32 = 1 << 5;
+const STS_COMMAND_READY: u32 = 1 << 6;
+const STS_VALID: u32 = 1 << 7;
+const DATA_FIFO: usize = 0x24;
+const DID_VID: usize = 0xf00;
+
+// CRB interface registers and bits.
+const LOC_CTRL: usize = 0x08;
+const LOC_CTRL_REQUEST: u32 = 1 << 0;
+const LOC_STS: usize = 0x0c;
+const LOC_STS_GRANTED: u32 = 1 << 0;
+const CTRL_REQ: usize = 0x40;
+const CTRL_REQ_CMD_READY: u32 = 1 << 0;
+const CTRL_REQ_GO_IDLE: u32 = 1 << 1;
+const CTRL_STS: usize = 0x44;
+const CTRL_STS_ERROR: u32 = 1 << 0;
+const CTRL_START: usize = 0x4c;
+const CTRL_CMD_SIZE: usize = 0x58;
+const CTRL_CMD_LADDR: usize = 0x5c;
+const CTRL_CMD_HADDR: usize = 0x60;
+const CTRL_RSP_SIZE: usize = 0x64;
+const CTRL_RSP_ADDR: usize = 0x68;
+
+// Command and response encoding.
+const ST_NO_SESSIONS: u16 = 0x8001;
+const ST_SESSIONS: u16 = 0x8002;
+const CC_STARTUP: u32 = 0x144;
+const CC_PCR_READ: u32 = 0x17e;
+const CC_PCR_EXTEND: u32 = 0x182;
+const RS_PW: u32 = 0x4000_0009;
+const ALG_SHA256: u16 = 0x000b;
+const SU_CLEAR: u16 = 0;
+const RC_SUCCESS: u32 = 0;
+const RC_INITIALIZE: u32 = 0x100;
+const HEADER_LEN: usize = 10;
+
+/// The interface by which the TPM is accessed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Interface {
+    Fifo,
+    Crb,
+}
+
+/// A TPM at locality 0.
+#[derive(Debug)]
+pub(crate) struct Tpm {
+    interface: Interface,
+    started: bool,
+}
+
+fn reg(offset: usize) -> usize {
+    TPM_BASE_ADDR + offset
+}
+
+fn read8(offset: usize) -> u8 {
+    unsafe { ptr::read_volatile(ptr::with_exposed_provenance(reg(offset))) }
+}
+
+fn write8(offset: usize, value: u8) {
+    let p = ptr::with_exposed_provenance_mut(reg(offset));
+    unsafe { ptr::write_volatile(p, value) }
+}
+
+fn read32(offset: usize) -> u32 {
+    unsafe { ptr::read_volatile(ptr::with_exposed_provenance(reg(offset))) }
+}
+
+fn write32(offset: usize, value: u32) {
+    let p = ptr::with_exposed_provenance_mut(reg(offset));
+    unsafe { ptr::write_volatile(p, value) }
+}
+
+/// Polls until the condition holds or the timeout expires.
+fn wait(
+    timeout: Duration,
+    what: &'static str,
+    mut cond: impl FnMut() -> bool,
+) -> Result<()> {
+    let deadline = Deadline::after(timeout);
+    while !cond() {
+        if deadline.expired() {
+            return Err(Error::Tpm(what));
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+/// Looks for a TPM, returning it if one is present and claiming
+/// locality 0.  Unclaimed MMIO space reads as all ones.  There
+/// is no TPM in tests.
+fn probe() -> Option<Tpm> {
+    if cfg!(test) {
+        return None;
+    }
+    let interface = match read32(INTERFACE_ID) {
+        0xffff_ffff => return None,
+        id if id & 0xf == 1 => Interface::Crb,
+        id if id & 0xf == 0 || id & 0xf == 0xf => Interface::Fifo,
+        _ => return None,
+    };
+    let claimed = match interface {
+        Interface::Fifo => {
+            write8(ACCESS, ACCESS_REQUEST_USE);
+            wait(TIMEOUT, "TPM: locality not granted", || {
+                let access = read8(ACCESS);
+                access & (ACCESS_VALID | ACCESS_ACTIVE_LOCALITY)
+                    == ACCESS_VALID | ACCESS_ACTIVE_LOCALITY
+            })
+        }
+        Interface::Crb => {
+            write32(LOC_CTRL, LOC_CTRL_REQUEST);
+            wait(TIMEOUT, "TPM: locality not granted", || {
+                read32(LOC_STS) & LOC_STS_GRANTED != 0
+            })
+        }
+    };
+    claimed.ok().map(|()| Tpm { interface, started: false })
+}
+
+impl Tpm {
+    pub(crate) fn interface(&self) -> Interface {
+        self.interface
+    }
+
+    /// Returns the vendor and device IDs, if the interface
+    /// reports them.
+    pub(crate) fn ids(&self) -> Option<(u16, u16)> {
+        match self.interface {
+            Interface::Fifo => {
+                let id = read32(DID_VID);
+                Some((id as u16, (id >> 16) as u16))
+            }
+            Interface::Crb => None,
+        }
+    }
+
+    /// Sends a command, returning the response.
+    fn transact(&mut self, cmd: &[u8]) -> Result<Vec<u8>> {
+        assert!(cmd.len() <= MAX_LEN);
+        match self.interface {
+            Interface::Fifo => fifo_transact(cmd),
+            Interface::Crb => crb_transact(cmd),
+        }
+    }
+
+    /// Sends a command, starting the TPM first if firmware has
+    /// not, and returns the body of a successful response.
+    fn command(&mut self, cmd: &[u8]) -> Result<Vec<u8>> {
+        let rsp = self.transact(cmd)?;
+        match response_code(&rsp)? {
+            RC_INITIALIZE if !self.started => {
+                let rsp = self.transact(&startup_cmd())?;
+                check_response(&rsp)?;
+                self.started = true;
+                let rsp = self.transact(cmd)?;
+                check_response(&rsp)?;
+                Ok(rsp[HEADER_LEN..].to_vec())
+            }
+            _ => {
+                check_response(&rsp)?;
+                self.started = true;
+                Ok(rsp[HEADER_LEN..].to_vec())
+            }
+        }
+    }
+
+    /// Extends the given SHA-256 PCR with a digest.
+    pub(crate) fn extend(&mut self, pcr: u32, digest: &[u8; 32]) -> Result<()> {
+        self.command(&extend_cmd(pcr, digest)).map(|_| ())
+    }
+
+    /// Reads the given SHA-256 PCRs.  The TPM returns at most a
+    /// few PCRs per command, so we ask repeatedly for those not
+    /// yet returned.
+    pub(crate) fn read(
+        &mut self,
+        mut pcrs: u32,
+    ) -> Result<Vec<(u32, [u8; 32])>> {
+        let mut values = Vec::new();
+        while pcrs != 0 {
+            let body = self.command(&read_cmd(pcrs))?;
+            let (returned, digests) = parse_read(&body)?;
+            if returned & pcrs == 0 {
+                return Err(Error::Tpm("TPM: PCRs not returned"));
+            }
+            let indices = (0..NPCRS).filter(|k| returned & (1 << k) != 0);
+            values.extend(indices.zip(digests));
+            pcrs &= !returned;
+        }
+        Ok(values)
+    }
+}
+
+fn fifo_sts() -> u32 {
+    read32(STS)
+}
+
+fn fifo_burst() -> usize {
+    ((fifo_sts() >> 8) & 0xffff) as usize
+}
+
+fn fifo_transact(cmd: &[u8]) -> Result<Vec<u8>> {
+    write32(STS, STS_COMMAND_READY);
+    wait(TIMEOUT, "TPM: not ready", || fifo_sts() & STS_COMMAND_READY != 0)?;
+    let mut sent = 0;
+    while sent < cmd.len() {
+        wait(TIMEOUT, "TPM: FIFO stalled", || fifo_burst() != 0)?;
+        let n = usize::min(fifo_burst(), cmd.len() - sent);
+        for &b in &cmd[sent..sent + n] {
+            write8(DATA_FIFO, b);
+        }
+        sent += n;
+    }
+    write32(STS, STS_GO);
+    let avail = STS_VALID | STS_DATA_AVAIL;
+    wait(COMMAND_TIMEOUT, "TPM: command timed out", || {
+        fifo_sts() & avail == avail
+    })?;
+    let mut rsp = Vec::new();
+    let mut len = HEADER_LEN;
+    while rsp.len() < len {
+        wait(TIMEOUT, "TPM: FIFO stalled", || fifo_burst() != 0)?;
+        let n = usize::min(fifo_burst(), len - rsp.len());
+        rsp.extend((0..n).map(|_| read8(DATA_FIFO)));
+        if rsp.len() >= HEADER_LEN && len == HEADER_LEN {
+            len = response_len(&rsp)?;
+        }
+    }
+    write32(STS, STS_COMMAND_READY);
+    Ok(rsp)
+}
+
+/// Returns a slice over a CRB command or response buffer, which
+/// must lie within the identity mapped MMIO region.
+fn crb_buffer(addr: u64, len: u32) -> Result<&'static mut [u8]> {
+    let len = len as usize;
+    let addr = usize::try_from(addr).map_err(|_| Error::NumRange)?;
+    if addr < 0x8000_0000 || addr + len > 0x1_0000_0000 || len > MAX_LEN {
+        return Err(Error::Tpm("TPM: CRB buffer outside MMIO"));
+    }
+    let p = ptr::with_exposed_provenance_mut(addr);
+    Ok(unsafe { core::slice::from_raw_parts_mut(p, len) })
+}
+
+fn crb_transact(cmd: &[u8]) -> Result<Vec<u8>> {
+    write32(CTRL_REQ, CTRL_REQ_CMD_READY);
+    wait(TIMEOUT, "TPM: not ready", || {
+        read32(CTRL_REQ) & CTRL_REQ_CMD_READY == 0
+    })?;
+    let cmd_addr = u64::from(read32(CTRL_CMD_HADDR)) << 32
+        | u64::from(read32(CTRL_CMD_LADDR));
+    let buf = crb_buffer(cmd_addr, read32(CTRL_CMD_SIZE))?;
+    if cmd.len() > buf.len() {
+        return Err(Error::Tpm("TPM: command too large"));
+    }
+    for (dst, &b) in buf.iter_mut().zip(cmd) {
+        unsafe { ptr::write_volatile(dst, b) }
+    }
+    write32(CTRL_START, 1);
+    wait(COMMAND_TIMEOUT, "TPM: command timed out", || {
+        read32(CTRL_START) == 0
+    })?;
+    if read32(CTRL_STS) & CTRL_STS_ERROR != 0 {
+        return Err(Error::Tpm("TPM: fatal error"));
+    }
+    let rsp_addr = u64::from(read32(CTRL_RSP_ADDR + 4)) << 32
+        | u64::from(read32(CTRL_RSP_ADDR));
+    let buf = crb_buffer(rsp_addr, read32(CTRL_RSP_SIZE))?;
+    let read = |k: usize| unsafe { ptr::read_volatile(&buf[k]) };
+    let header = (0..HEADER_LEN.min(buf.len())).map(read).collect::<Vec<_>>();
+    let len = response_len(&header)?;
+    if len > buf.len() {
+        return Err(Error::Tpm("TPM: response too large"));
+    }
+    let rsp = (0..len).map(read).collect();
+    write32(CTRL_REQ, CTRL_REQ_GO_IDLE);
+    Ok(rsp)
+}
+
+/// Begins a command with the given tag and code; its size is
+/// filled in by `finish`.
+fn begin(tag: u16, code: u32) -> Vec<u8> {
+    let mut cmd = Vec::with_capacity(64);
+    cmd.extend_from_slice(&tag.to_be_bytes());
+    cmd.extend_from_slice(&0u32.to_be_bytes());
+    cmd.extend_from_slice(&code.to_be_bytes());
+    cmd
+}
+
+fn finish(mut cmd: Vec<u8>) -> Vec<u8> {
+    let len = cmd.len() as u32;
+    cmd[2..6].copy_from_slice(&len.to_be_bytes());
+    cmd
+}
+
+fn startup_cmd() -> Vec<u8> {
+    let mut cmd = begin(ST_NO_SESSIONS, CC_STARTUP);
+    cmd.extend_from_slice(&SU_CLEAR.to_be_bytes());
+    finish(cmd)
+}
+
+fn extend_cmd(pcr: u32, digest: &[u8; 32]) -> Vec<u8> {
+    let mut cmd = begin(ST_SESSIONS, CC_PCR_EXTEND);
+    cmd.extend_from_slice(&pcr.to_be_bytes());
+    // A single empty password session.
+    cmd.extend_from_slice(&9u32.to_be_bytes());
+    cmd.extend_from_slice(&RS_PW.to_be_bytes());
+    cmd.extend_from_slice(&[0, 0, 0, 0, 0]);
+    // One SHA-256 digest.
+    cmd.extend_from_slice(&1u32.to_be_bytes());
+    cmd.extend_from_slice(&ALG_SHA256.to_be_bytes());
+    cmd.extend_from_slice(digest);
+    finish(cmd)
+}
+
+fn read_cmd(pcrs: u32) -> Vec<u8> {
+    let mut cmd = begin(ST_NO_SESSIONS, CC_PCR_READ);
+    cmd.extend_from_slice(&1u32.to_be_bytes());
+    cmd.extend_from_slice(&ALG_SHA256.to_be_bytes());
+    cmd.push(3);
+    cmd.extend_from_slice(&pcrs.to_le_bytes()[..3]);
+    finish(cmd)
+}
+
+fn response_len(rsp: &[u8]) -> Result<usize> {
+    if rsp.len() < HEADER_LEN {
+        return Err(Error::Tpm("TPM: short response"));
+    }
+    let len = u32::from_be_bytes(rsp[2..6].try_into().unwrap()) as usize;
+    if !(HEADER_LEN..=MAX_LEN).contains(&len) {
+        return Err(Error::Tpm("TPM: bad response size"));
+    }
+    Ok(len)
+}
+
+fn response_code(rsp: &[u8]) -> Result<u32> {
+    response_len(rsp)?;
+    Ok(u32::from_be_bytes(rsp[6..10].try_into().unwrap()))
+}
+
+fn check_response(rsp: &[u8]) -> Result<()> {
+    match response_code(rsp)? {
+        RC_SUCCESS => Ok(()),
+        _ => Err(Error::Tpm("TPM: command failed")),
+    }
+}
+
+/// A cursor over a response body.
+struct Body<'a>(&'a [u8]);
+
+impl Body<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.0.len() < n {
+            return Err(Error::Tpm("TPM: truncated response"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Parses the body of a PCR_Read response, returning the bitmap
+/// of SHA-256 PCRs returned and their values, in order.
+fn parse_read(body: &[u8]) -> Result<(u32, Vec<[u8; 32]>)> {
+    let mut body = Body(body);
+    let _update_counter = body.u32()?;
+    let mut returned = 0;
+    for _ in 0..body.u32()? {
+        let alg = body.u16()?;
+        let len = usize::from(body.u8()?);
+        let select = body.take(len)?;
+        if alg == ALG_SHA256 {
+            for (k, &b) in select.iter().take(4).enumerate() {
+                returned |= u32::from(b) << (8 * k);
+            }
+        }
+    }
+    let count = body.u32()?;
+    if count != returned.count_ones() {
+        return Err(Error::Tpm("TPM: PCR count mismatch"));
+    }
+    let mut digests = Vec::new();
+    for _ in 0..count {
+        let len = usize::from(body.u16()?);
+        let digest = body.take(len)?;
+        digests.push(
+            digest.try_into().map_err(|_| Error::Tpm("TPM: bad digest"))?,
+        );
+    }
+    Ok((returned, digests))
+}
+
+/// Computes the value of a PCR after extending it with a digest.
+pub(crate) fn extended(pcr: &[u8; 32], digest: &[u8; 32]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    let mut sum = Sha256::new();
+    sum.update(pcr);
+    sum.update(digest);
+    sum.finalize().into()
+}
+
+/// A recorded measurement.
+#[derive(Debug)]
+pub(crate) struct Event {
+    pub(crate) pcr: u32,
+    pub(crate) what: String,
+    pub(crate) digest: [u8; 32],
+    /// Whether the digest was extended into a TPM.
+    pub(crate) extended: bool,
+}
+
+/// The measurement state: the TPM, once probed for, and the log
+/// of measurements made.
+#[derive(Debug, Default)]
+pub(crate) struct Measurements {
+    probed: bool,
+    tpm: Option<Tpm>,
+    log: Vec<Event>,
+}
+
+impl Measurements {
+    /// Returns the TPM, probing for it on first use.
+    pub(crate) fn tpm(&mut self) -> Option<&mut Tpm> {
+        if !self.probed {
+            self.probed = true;
+            self.tpm = probe();
+        }
+        self.tpm.as_mut()
+    }
+
+    pub(crate) fn log(&self) -> &[Event] {
+        &self.log
+    }
+
+    /// Records a measurement of the given object, extending
+    /// `IMAGE_PCR` with its digest if there is a TPM.  A failure
+    /// to extend is reported, and noted in the log, but does not
+    /// prevent the object from being used.
+    pub(crate) fn measure(&mut self, what: String, digest: [u8; 32]) {
+        let extended =
+            match self.tpm().map(|tpm| tpm.extend(IMAGE_PCR, &digest)) {
+                Some(Ok(())) => true,
+                Some(Err(e)) => {
+                    crate::println!("measure: cannot extend PCR: {e:?}");
+                    false
+                }
+                None => false,
+            };
+        self.log.push(Event { pcr: IMAGE_PCR, what, digest, extended });
+    }
+
+    /// Returns the value the given PCR would have if it started
+    /// at zero and only the logged measurements that were
+    /// extended into it had been.
+    pub(crate) fn replay(&self, pcr: u32) -> [u8; 32] {
+        self.log
+            .iter()
+            .filter(|event| event.pcr == pcr && event.extended)
+            .fold([0; 32], |value, event| extended(&value, &event.digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let digest = [0xaa; 32];
+        let cmd = extend_cmd(9, &digest);
+        assert_eq!(cmd.len(), 65);
+        assert_eq!(&cmd[..10], &[0x80, 0x02, 0, 0, 0, 65, 0, 0, 0x01, 0x82]);
+        assert_eq!(&cmd[10..14], &9u32.to_be_bytes());
+        assert_eq!(&cmd[31..33], &[0x00, 0x0b]);
+        assert_eq!(&cmd[33..], &digest);
+        let cmd = read_cmd(1 << 9 | 1 << 17);
+        assert_eq!(&cmd[..10], &[0x80, 0x01, 0, 0, 0, 20, 0, 0, 0x01, 0x7e]);
+        assert_eq!(&cmd[14..], &[0x00, 0x0b, 3, 0x00, 0x02, 0x02]);
+        assert_eq!(startup_cmd(), [0x80, 1, 0, 0, 0, 12, 0, 0, 1, 0x44, 0, 0]);
+    }
+
+    #[test]
+    fn responses() {
+        let rsp = [0x80, 0x01, 0, 0, 0, 10, 0, 0, 0x01, 0x00];
+        assert_eq!(response_code(&rsp), Ok(RC_INITIALIZE));
+        assert!(check_response(&rsp).is_err());
+        assert!(response_len(&rsp[..6]).is_err());
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&7u32.to_be_bytes());
+        body.extend_from_slice(&1u32.to_be_bytes());
+        body.extend_from_slice(&[0x00, 0x0b, 3, 0x01, 0x02, 0x00]);
+        body.extend_from_slice(&2u32.to_be_bytes());
+        for b in [0x11, 0x22] {
+            body.extend_from_slice(&32u16.to_be_bytes());
+            body.extend_from_slice(&[b; 32]);
+        }
+        let (returned, digests) = parse_read(&body).unwrap();
+        assert_eq!(returned, 1 << 0 | 1 << 9);
+        assert_eq!(digests, [[0x11; 32], [0x22; 32]]);
+        assert!(parse_read(&body[..body.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn log_replay() {
+        let mut measurements = Measurements::default();
+        measurements.measure(String::from("load a"), [1; 32]);
+        assert!(measurements.tpm().is_none());
+        assert_eq!(measurements.log().len(), 1);
+        assert!(!measurements.log()[0].extended);
+        assert_eq!(measurements.replay(IMAGE_PCR), [0; 32]);
+        measurements.log[0].extended = true;
+        assert_eq!(
+            measurements.replay(IMAGE_PCR),
+            extended(&[0; 32], &[1; 32])
+        );
+        assert_eq!(extended(&[0; 32], &[0; 32])[..4], [0xf5, 0xa5, 0xfd, 0x42]);
+    }
+}
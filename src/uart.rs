@@ -108,28 +108,125 @@ impl bitstruct::IntoRaw<u8, RcvrTrigger> for Fcr {
     }
 }
 
-enum Datas {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Datas {
     Bits5 = 0b00,
     Bits6 = 0b01,
     Bits7 = 0b10,
     Bits8 = 0b11,
 }
 
-enum Parity {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Parity {
     No,
     DisabledEven,
     Odd,
     Even,
 }
 
-enum Stops {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Stops {
     Stop1,
     Stop2,
 }
 
-#[repr(u32)]
-enum Rate {
-    B3M = 3_000_000u32,
+/// The frequency of the UARTs' reference clock.
+const SCLK: u32 = 48_000_000;
+
+/// A UART's line settings: its nominal rate and the divisor of
+/// the reference clock that approximates it, and how characters
+/// are framed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Line {
+    baud: u32,
+    divisor: u16,
+    data: Datas,
+    parity: Parity,
+    stop: Stops,
+}
+
+impl Line {
+    /// 3Mbaud 8N1, at which the console and the SP's UART run.
+    pub(crate) const B3M_8N1: Line = Line::n81(3_000_000, 1);
+    /// 115200 baud 8N1, at which the fallback UART runs.
+    const B115200_8N1: Line = Line::n81(115_200, 26);
+
+    const fn n81(baud: u32, divisor: u16) -> Line {
+        Line {
+            baud,
+            divisor,
+            data: Datas::Bits8,
+            parity: Parity::No,
+            stop: Stops::Stop1,
+        }
+    }
+
+    /// Returns these settings at the given rate, using the
+    /// nearest divisor, or an error if the rate that gives is
+    /// more than 3% away from the one asked for.
+    pub(crate) fn with_baud(self, baud: u32) -> Result<Line> {
+        const CLOCKS: u32 = SCLK / 16;
+        if baud == 0 {
+            return Err(Error::NumRange);
+        }
+        let divisor = (CLOCKS + baud / 2) / baud;
+        let divisor = u16::try_from(divisor)
+            .ok()
+            .filter(|&divisor| divisor != 0)
+            .ok_or(Error::NumRange)?;
+        if (CLOCKS / u32::from(divisor)).abs_diff(baud) * 100 > baud * 3 {
+            return Err(Error::NumRange);
+        }
+        Ok(Line { baud, divisor, ..self })
+    }
+
+    /// Returns these settings with the framing given as data
+    /// bits, parity, and stop bits, as in `8N1` or `7E2`.
+    pub(crate) fn with_framing(self, framing: &str) -> Result<Line> {
+        let &[data, parity, stop] = framing.as_bytes() else {
+            return Err(Error::BadArgs);
+        };
+        let data = match data {
+            b'5' => Datas::Bits5,
+            b'6' => Datas::Bits6,
+            b'7' => Datas::Bits7,
+            b'8' => Datas::Bits8,
+            _ => return Err(Error::BadArgs),
+        };
+        let parity = match parity.to_ascii_uppercase() {
+            b'N' => Parity::No,
+            b'O' => Parity::Odd,
+            b'E' => Parity::Even,
+            _ => return Err(Error::BadArgs),
+        };
+        let stop = match stop {
+            b'1' => Stops::Stop1,
+            b'2' => Stops::Stop2,
+            _ => return Err(Error::BadArgs),
+        };
+        Ok(Line { data, parity, stop, ..self })
+    }
+
+    /// Returns the nominal rate, in baud.
+    pub(crate) fn baud(&self) -> u32 {
+        self.baud
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let data = 5 + self.data as u8;
+        let parity = match self.parity {
+            Parity::No | Parity::DisabledEven => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let stop = match self.stop {
+            Stops::Stop1 => 1,
+            Stops::Stop2 => 2,
+        };
+        write!(f, "{} {data}{parity}{stop}", self.baud())
+    }
 }
 
 bitstruct! {
@@ -183,8 +280,8 @@ impl bitstruct::FromRaw<u8, Parity> for Lcr {
     fn from_raw(raw: u8) -> Parity {
         match raw {
             0b00 => Parity::No,
-            0b01 => Parity::DisabledEven,
-            0b10 => Parity::Odd,
+            0b01 => Parity::Odd,
+            0b10 => Parity::DisabledEven,
             0b11 => Parity::Even,
             _ => panic!("impossible data bits value"),
         }
@@ -195,8 +292,8 @@ impl bitstruct::IntoRaw<u8, Parity> for Lcr {
     fn into_raw(parity: Parity) -> u8 {
         match parity {
             Parity::No => 0b00,
-            Parity::DisabledEven => 0b01,
-            Parity::Odd => 0b10,
+            Parity::Odd => 0b01,
+            Parity::DisabledEven => 0b10,
             Parity::Even => 0b11,
         }
     }
@@ -204,6 +301,7 @@ impl bitstruct::IntoRaw<u8, Parity> for Lcr {
 
 bitstruct! {
     /// Ill-named Modem Control Register
+    #[derive(Clone, Copy)]
     struct Mcr(u32) {
         dtr: bool = 0;
         rts: bool = 1;
@@ -214,6 +312,21 @@ bitstruct! {
     }
 }
 
+bitstruct! {
+    /// Modem Status Register.  Note that reading this register
+    /// clears the delta bits.
+    struct Msr(u32) {
+        dcts: bool = 0;
+        ddsr: bool = 1;
+        teri: bool = 2;
+        ddcd: bool = 3;
+        cts: bool = 4;
+        dsr: bool = 5;
+        ri: bool = 6;
+        dcd: bool = 7;
+    }
+}
+
 bitstruct! {
     /// Line Status Register
     struct Lsr(u32) {
@@ -272,12 +385,19 @@ impl ConfigMmio {
         unsafe { ptr::read_volatile(&self.lcr) }
     }
 
-    /// Sets the line rate on the device.
-    fn set_rate(&mut self, rate: Rate) {
-        const SCLK: u32 = 48_000_000;
-        let divisor = SCLK / (16 * rate as u32);
-        let dll = Dll(divisor & 0xFF);
-        let dlh = Dlh(divisor >> 8);
+    /// Sets the line rate and framing on the device.
+    fn set_line(&mut self, line: Line) {
+        self.set_divisor(line.divisor);
+        self.set_data_bits(line.data);
+        self.set_stop_bits(line.stop);
+        self.set_parity(line.parity);
+    }
+
+    /// Sets the divisor of the reference clock that gives the
+    /// line rate.
+    fn set_divisor(&mut self, divisor: u16) {
+        let dll = Dll(u32::from(divisor & 0xFF));
+        let dlh = Dlh(u32::from(divisor >> 8));
         unsafe {
             let lcr = self.lcr().with_dlab(true);
             ptr::write_volatile(&mut self.lcr, lcr);
@@ -345,9 +465,9 @@ struct MmioRead {
     _ier: u32,        // 0x04
     _iir: u32,        // 0x08
     _lcr: u32,        // 0x0C
-    _mcr: u32,        // 0x10
+    mcr: Mcr,         // 0x10
     lsr: Lsr,         // 0x14
-    _msr: u32,        // 0x18
+    msr: Msr,         // 0x18
     _scr: u32,        // 0x1C
     _lpdll: u32,      // 0x20
     _lpdlh: u32,      // 0x24
@@ -357,9 +477,9 @@ struct MmioRead {
     _far: u32,        // 0x70
     _tfr: u32,        // 0x74
     _rfw: u32,        // 0x78
-    _usr: Usr,        // 0x7C
-    _tfl: u32,        // 0x80
-    _rfl: u32,        // 0x84
+    usr: Usr,         // 0x7C
+    tfl: u32,         // 0x80
+    rfl: u32,         // 0x84
     _srr: u32,        // 0x88
     _srts: u32,       // 0x8C,
     _sbcr: u32,       // 0x90
@@ -383,7 +503,7 @@ struct MmioWrite {
     _ier: u32,        // 0x04
     _iir: u32,        // 0x08
     _lcr: u32,        // 0x0C
-    _mcr: u32,        // 0x10
+    mcr: Mcr,         // 0x10
     lsr: Lsr,         // 0x14
     _msr: u32,        // 0x18
     _scr: u32,        // 0x1C
@@ -419,7 +539,7 @@ const_assert_eq!(core::mem::size_of::<MmioWrite>(), 256);
 #[repr(usize)]
 pub enum Device {
     Uart0 = UART_MMIO_BASE_ADDR,
-    _Uart1 = UART_MMIO_BASE_ADDR + 0x1000,
+    Uart1 = UART_MMIO_BASE_ADDR + 0x1000,
     _Uart2 = UART_MMIO_BASE_ADDR + 0x5000,
     _Uart3 = UART_MMIO_BASE_ADDR + 0x6000,
 }
@@ -429,6 +549,101 @@ static UART1_INITED: AtomicBool = AtomicBool::new(false);
 static UART2_INITED: AtomicBool = AtomicBool::new(false);
 static UART3_INITED: AtomicBool = AtomicBool::new(false);
 
+/// Whether UART 1 has been claimed for the SP's IPCC channel or
+/// for file transfers, and so must be left alone by the fallback
+/// diagnostic path.
+static UART1_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+/// The ASCII DC1 and DC3 control characters, which ask the
+/// other end of a line to resume or pause its output.
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// How many received bytes may be held while looking for XON
+/// and XOFF before further input is dropped.
+const HELD_LEN: usize = 64;
+
+/// Software (XON/XOFF) flow control state for the console, for
+/// rigs whose terminal servers do not do RTS/CTS.  When enabled,
+/// an XOFF from the far end pauses our output until an XON
+/// arrives, and neither is passed on as input.  Other bytes read
+/// while looking for them are held until they are read in turn.
+struct SoftFlow {
+    enabled: bool,
+    paused: bool,
+    held: [u8; HELD_LEN],
+    start: usize,
+    len: usize,
+}
+
+impl SoftFlow {
+    const fn new() -> SoftFlow {
+        SoftFlow {
+            enabled: false,
+            paused: false,
+            held: [0; HELD_LEN],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Acts on a received byte if it is XON or XOFF, or holds it.
+    fn recv(&mut self, b: u8) {
+        match b {
+            XOFF => self.paused = true,
+            XON => self.paused = false,
+            b if self.len < HELD_LEN => {
+                self.held[(self.start + self.len) % HELD_LEN] = b;
+                self.len += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the oldest held byte, if any.
+    fn take(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let b = self.held[self.start];
+        self.start = (self.start + 1) % HELD_LEN;
+        self.len -= 1;
+        Some(b)
+    }
+}
+
+// Tests script the console per thread, so they keep their flow
+// control state per thread, too.
+#[cfg(not(test))]
+static SOFT_FLOW: spin::Mutex<SoftFlow> = spin::Mutex::new(SoftFlow::new());
+
+#[cfg(test)]
+std::thread_local! {
+    static SOFT_FLOW: core::cell::RefCell<SoftFlow> =
+        const { core::cell::RefCell::new(SoftFlow::new()) };
+}
+
+/// Calls `f` with the console's flow control state.  Returns
+/// `None` if the state is in use, as when we panic while
+/// printing.
+fn with_soft_flow<R>(f: impl FnOnce(&mut SoftFlow) -> R) -> Option<R> {
+    #[cfg(not(test))]
+    return SOFT_FLOW.try_lock().map(|mut flow| f(&mut flow));
+    #[cfg(test)]
+    return SOFT_FLOW.with(|flow| Some(f(&mut flow.borrow_mut())));
+}
+
+/// Enables or disables software flow control on the console,
+/// returning whether it was enabled before.  Disabling it also
+/// resumes output if it was paused.
+pub(crate) fn set_soft_flow(enabled: bool) -> bool {
+    with_soft_flow(|flow| {
+        flow.paused &= enabled;
+        core::mem::replace(&mut flow.enabled, enabled)
+    })
+    .unwrap_or(false)
+}
+
 impl Device {
     /// Returns the base virtual address of the device's
     /// MMIO region.
@@ -436,18 +651,25 @@ impl Device {
         self as usize
     }
 
-    fn init(self, rate: Rate, data: Datas, stop: Stops, par: Parity) -> bool {
+    fn init(self, line: Line) -> bool {
         let uart = self.reset();
         uart.config_fifos();
         uart.disable_intrs();
-        uart.set_rate(rate);
-        uart.set_data_bits(data);
-        uart.set_stop_bits(stop);
-        uart.set_parity(par);
+        uart.set_line(line);
         uart.config_flow_control();
         true
     }
 
+    /// Performs a minimal initialization of the device, without
+    /// hardware flow control, so that output cannot stall
+    /// waiting on an absent peer.
+    fn init_minimal(self, line: Line) {
+        let uart = self.reset();
+        uart.config_fifos();
+        uart.disable_intrs();
+        uart.set_line(line);
+    }
+
     fn reset<'a>(self) -> &'a mut ConfigMmio {
         let regs = ptr::with_exposed_provenance_mut::<ConfigMmio>(self.addr());
         let uart = unsafe { &mut *regs };
@@ -458,12 +680,69 @@ impl Device {
     }
 }
 
+/// A snapshot of the state of a UART's modem control and
+/// status lines, its hardware flow control configuration, and
+/// its FIFO levels.
+#[cfg(feature = "cmd-hw")]
+#[derive(Clone, Copy, Debug)]
+pub struct LineState {
+    pub dtr: bool,
+    pub rts: bool,
+    pub auto_flow: bool,
+    pub soft_flow: bool,
+    pub paused: bool,
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub dcd: bool,
+    pub tx_fifo_level: u32,
+    pub rx_fifo_level: u32,
+    pub tx_fifo_full: bool,
+    pub rx_fifo_full: bool,
+}
+
+#[cfg(feature = "cmd-hw")]
+impl fmt::Display for LineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let onoff = |b| if b { "on" } else { "off" };
+        writeln!(
+            f,
+            "MCR: DTR {} RTS {} auto-flow {}",
+            onoff(self.dtr),
+            onoff(self.rts),
+            onoff(self.auto_flow)
+        )?;
+        writeln!(
+            f,
+            "XON/XOFF: {}{}",
+            onoff(self.soft_flow),
+            if self.paused { " (paused)" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "MSR: CTS {} DSR {} RI {} DCD {}",
+            onoff(self.cts),
+            onoff(self.dsr),
+            onoff(self.ri),
+            onoff(self.dcd)
+        )?;
+        write!(
+            f,
+            "FIFO: TX {}{} RX {}{}",
+            self.tx_fifo_level,
+            if self.tx_fifo_full { " (full)" } else { "" },
+            self.rx_fifo_level,
+            if self.rx_fifo_full { " (full)" } else { "" },
+        )
+    }
+}
+
 /// The UART itself.
 pub struct Uart(Device);
 
 impl Uart {
     pub fn uart0() -> Uart {
-        assert!(UART0_INITED.load(Ordering::Acquire));
+        assert!(cfg!(test) || UART0_INITED.load(Ordering::Acquire));
         Uart(Device::Uart0)
     }
 
@@ -471,6 +750,53 @@ impl Uart {
         self.0.addr()
     }
 
+    /// Returns true iff this is the console UART, the only one
+    /// subject to software flow control.
+    pub(crate) fn is_cons(&self) -> bool {
+        matches!(self.0, Device::Uart0)
+    }
+
+    /// Returns true iff software flow control is enabled for
+    /// this UART.
+    fn soft_flow(&self) -> bool {
+        self.is_cons() && with_soft_flow(|flow| flow.enabled).unwrap_or(false)
+    }
+
+    /// Returns the oldest byte held by software flow control.
+    fn take_held(&mut self) -> Option<u8> {
+        if !self.is_cons() {
+            return None;
+        }
+        with_soft_flow(SoftFlow::take).flatten()
+    }
+
+    /// Reads everything that has arrived, acting on any XON and
+    /// XOFF, and holding anything else.
+    fn drain_soft_flow(&mut self) -> Result<()> {
+        while self.line_ready()? {
+            let b = self.rbr();
+            with_soft_flow(|flow| flow.recv(b));
+        }
+        Ok(())
+    }
+
+    /// Waits until the far end is willing to accept output.  A
+    /// BREAK or other line error also resumes output, lest we
+    /// wait forever for an XON that was lost.
+    fn await_xon(&mut self) -> Result<()> {
+        loop {
+            if let Err(e) = self.drain_soft_flow() {
+                with_soft_flow(|flow| flow.paused = false);
+                return Err(e);
+            }
+            if !with_soft_flow(|flow| flow.paused).unwrap_or(false) {
+                return Ok(());
+            }
+            hint::spin_loop();
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
     fn write_mmio_mut(&mut self) -> &mut MmioWrite {
         let regs = ptr::with_exposed_provenance_mut::<MmioWrite>(self.0.addr());
         unsafe { &mut *regs }
@@ -480,11 +806,153 @@ impl Uart {
     // model that by returning a mut ref.  This also means that
     // it is mutually exclusive with a write MMIO structure,
     // as the two share the same register space.
+    #[cfg_attr(test, allow(dead_code))]
     fn read_mmio_mut(&mut self) -> &mut MmioRead {
         let regs = ptr::with_exposed_provenance_mut::<MmioRead>(self.0.addr());
         unsafe { &mut *regs }
     }
 
+    /// Returns a snapshot of the line state.  Note that this
+    /// clears the change indications in the modem status
+    /// register, which we do not otherwise use.
+    #[cfg(feature = "cmd-hw")]
+    pub fn line_state(&mut self) -> LineState {
+        let regs = self.read_mmio_mut();
+        let mcr = unsafe { ptr::read_volatile(&regs.mcr) };
+        let msr = unsafe { ptr::read_volatile(&regs.msr) };
+        let usr = unsafe { ptr::read_volatile(&regs.usr) };
+        let tfl = unsafe { ptr::read_volatile(&regs.tfl) };
+        let rfl = unsafe { ptr::read_volatile(&regs.rfl) };
+        let (soft_flow, paused) = self
+            .is_cons()
+            .then(|| with_soft_flow(|flow| (flow.enabled, flow.paused)))
+            .flatten()
+            .unwrap_or_default();
+        LineState {
+            dtr: mcr.dtr(),
+            rts: mcr.rts(),
+            auto_flow: mcr.auto_flow(),
+            soft_flow,
+            paused,
+            cts: msr.cts(),
+            dsr: msr.dsr(),
+            ri: msr.ri(),
+            dcd: msr.dcd(),
+            tx_fifo_level: tfl,
+            rx_fifo_level: rfl,
+            tx_fifo_full: !usr.tx_fifo_not_full(),
+            rx_fifo_full: usr.rx_fifo_full(),
+        }
+    }
+
+    /// Updates the modem control register, enabling or
+    /// disabling auto flow control, DTR and RTS as requested.
+    /// Note that with auto flow control enabled, RTS must also
+    /// be set for the UART to drive RTS automatically; with it
+    /// clear, only CTS is honored.
+    #[cfg(feature = "cmd-hw")]
+    pub fn set_modem_control(
+        &mut self,
+        auto_flow: Option<bool>,
+        dtr: Option<bool>,
+        rts: Option<bool>,
+    ) {
+        let mcr = unsafe { ptr::read_volatile(&self.read_mmio_mut().mcr) };
+        let mcr = mcr
+            .with_auto_flow(auto_flow.unwrap_or(mcr.auto_flow()))
+            .with_dtr(dtr.unwrap_or(mcr.dtr()))
+            .with_rts(rts.unwrap_or(mcr.rts()));
+        unsafe {
+            ptr::write_volatile(&mut self.write_mmio_mut().mcr, mcr);
+        }
+    }
+
+    /// Changes the UART's rate and framing once everything
+    /// written to it so far has been sent.  Its FIFOs and modem
+    /// control settings are left alone.  If the transmitter does
+    /// not drain in time, the line is left as it was and an
+    /// error is returned.
+    pub(crate) fn set_line(&mut self, line: Line) -> Result<()> {
+        use crate::clock::Deadline;
+        const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+        let deadline = Deadline::after(DRAIN_TIMEOUT);
+        while !self.lsr().xmtr_empty() {
+            if deadline.expired() {
+                return Err(Error::Timeout
+                    .context("draining uart addr", &[self.addr() as u64]));
+            }
+            hint::spin_loop();
+        }
+        self.write_line(line);
+        if self.is_cons() {
+            set_cons_line(line);
+        }
+        Ok(())
+    }
+
+    /// Writes the line settings.  The line control register and
+    /// divisor latch cannot be written while the UART is busy,
+    /// so we wait, briefly, for the far end to stop sending.
+    #[cfg(not(test))]
+    fn write_line(&mut self, line: Line) {
+        use crate::clock::Deadline;
+        let deadline = Deadline::after(Duration::from_millis(100));
+        while unsafe { ptr::read_volatile(&self.read_mmio_mut().usr) }.busy()
+            && !deadline.expired()
+        {
+            hint::spin_loop();
+        }
+        let regs = ptr::with_exposed_provenance_mut::<ConfigMmio>(self.addr());
+        unsafe { &mut *regs }.set_line(line);
+    }
+
+    #[cfg(test)]
+    fn write_line(&mut self, _line: Line) {}
+
+    /// Reads the line status register.  In tests, the status
+    /// of the fake console is returned instead.
+    #[cfg(not(test))]
+    fn lsr(&mut self) -> Lsr {
+        unsafe { ptr::read_volatile(&self.read_mmio_mut().lsr) }
+    }
+
+    #[cfg(test)]
+    fn lsr(&mut self) -> Lsr {
+        let lsr = Lsr(0).with_thr_empty(true).with_xmtr_empty(true);
+        match crate::fakes::uart_status() {
+            Ok(ready) => lsr.with_data_ready(ready),
+            Err(Error::UartBreak) => lsr.with_break_intr(true),
+            Err(Error::UartFifoOverrun) => lsr.with_overrun_err(true),
+            Err(Error::UartFraming) => lsr.with_framing_err(true),
+            Err(Error::UartParity) => lsr.with_parity_err(true),
+            Err(e) => panic!("fake console cannot report {e:?}"),
+        }
+    }
+
+    #[cfg(not(test))]
+    fn rbr(&mut self) -> u8 {
+        let data = unsafe { ptr::read_volatile(&self.read_mmio_mut().rbr) };
+        data.data()
+    }
+
+    #[cfg(test)]
+    fn rbr(&mut self) -> u8 {
+        crate::fakes::uart_recv()
+    }
+
+    #[cfg(not(test))]
+    fn thr(&mut self, b: u8) {
+        let data = Thr(0).with_data(b);
+        unsafe {
+            ptr::write_volatile(&mut self.write_mmio_mut().thr, data);
+        }
+    }
+
+    #[cfg(test)]
+    fn thr(&mut self, b: u8) {
+        crate::fakes::uart_send(b);
+    }
+
     pub fn getb(&mut self) -> u8 {
         loop {
             if let Some(b) = self.getb_timeout(Duration::ZERO) {
@@ -504,8 +972,7 @@ impl Uart {
 
     pub fn try_getb_timeout(&mut self, timeout: Duration) -> Result<u8> {
         if self.wait_data_ready(timeout)? {
-            let data = unsafe { ptr::read_volatile(&self.read_mmio_mut().rbr) };
-            Ok(data.data())
+            Ok(self.take_held().unwrap_or_else(|| self.rbr()))
         } else {
             Err(Error::Timeout)
         }
@@ -517,26 +984,14 @@ impl Uart {
     /// data is available, or `Ok(false)` if no data arrived
     /// before the timeout expired.
     pub fn wait_data_ready(&mut self, timeout: Duration) -> Result<bool> {
-        use crate::clock;
-        let ns = timeout.as_nanos();
-        let cycles = ns * clock::frequency() / clock::NANOS_PER_SEC;
-        let start = u128::from(clock::rdtsc());
-        let end = u64::try_from(start.checked_add(cycles).unwrap()).unwrap();
-        while timeout.is_zero() || clock::rdtsc() < end {
-            let lsr = unsafe { ptr::read_volatile(&self.read_mmio_mut().lsr) };
-            if lsr.break_intr() {
-                return Err(Error::UartBreak);
-            }
-            if lsr.overrun_err() {
-                return Err(Error::UartFifoOverrun);
-            }
-            if lsr.framing_err() {
-                return Err(Error::UartFraming);
-            }
-            if lsr.parity_err() {
-                return Err(Error::UartParity);
-            }
-            if lsr.data_ready() {
+        use crate::clock::Deadline;
+        let deadline = if timeout.is_zero() {
+            Deadline::never()
+        } else {
+            Deadline::after(timeout)
+        };
+        while !deadline.expired() {
+            if self.data_ready()? {
                 return Ok(true);
             }
             hint::spin_loop();
@@ -544,9 +999,46 @@ impl Uart {
         Ok(false)
     }
 
+    /// Checks, without waiting, whether data is available on
+    /// the UART.  Returns an `Err` if the line status register
+    /// reports an error.  With software flow control enabled,
+    /// XON and XOFF are consumed, and are never data.
+    pub fn data_ready(&mut self) -> Result<bool> {
+        if self.soft_flow() {
+            self.drain_soft_flow()?;
+            return Ok(with_soft_flow(|flow| flow.len > 0).unwrap_or(false));
+        }
+        if self.is_cons() && with_soft_flow(|flow| flow.len > 0) == Some(true) {
+            return Ok(true);
+        }
+        self.line_ready()
+    }
+
+    /// Checks the line status register for received data or an
+    /// error.
+    fn line_ready(&mut self) -> Result<bool> {
+        let lsr = self.lsr();
+        if lsr.break_intr() {
+            return Err(Error::UartBreak);
+        }
+        if lsr.overrun_err() {
+            return Err(Error::UartFifoOverrun);
+        }
+        if lsr.framing_err() {
+            return Err(Error::UartFraming);
+        }
+        if lsr.parity_err() {
+            return Err(Error::UartParity);
+        }
+        Ok(lsr.data_ready())
+    }
+
     pub fn try_putb(&mut self, b: u8) -> Result<()> {
+        if self.soft_flow() {
+            self.await_xon()?;
+        }
         while {
-            let lsr = unsafe { ptr::read_volatile(&self.write_mmio_mut().lsr) };
+            let lsr = self.lsr();
             if lsr.break_intr() {
                 return Err(Error::UartBreak);
             }
@@ -555,10 +1047,7 @@ impl Uart {
             // We're not racing against anyone, but, this doesn't hurt.
             hint::spin_loop();
         }
-        let data = Thr(0).with_data(b);
-        unsafe {
-            ptr::write_volatile(&mut self.write_mmio_mut().thr, data);
-        }
+        self.thr(b);
         Ok(())
     }
 
@@ -605,6 +1094,116 @@ pub fn cons() -> Uart {
     Uart::uart0()
 }
 
+// The console's line settings, which may be changed once it is
+// running.  Tests keep theirs per thread, as they do their
+// consoles.
+#[cfg(not(test))]
+static CONS_LINE: spin::Mutex<Line> = spin::Mutex::new(Line::B3M_8N1);
+
+#[cfg(test)]
+std::thread_local! {
+    static CONS_LINE: core::cell::Cell<Line> =
+        const { core::cell::Cell::new(Line::B3M_8N1) };
+}
+
+/// Returns the console's line settings.
+pub(crate) fn cons_line() -> Line {
+    #[cfg(not(test))]
+    return *CONS_LINE.lock();
+    #[cfg(test)]
+    return CONS_LINE.get();
+}
+
+fn set_cons_line(line: Line) {
+    #[cfg(not(test))]
+    {
+        *CONS_LINE.lock() = line;
+    }
+    #[cfg(test)]
+    CONS_LINE.set(line);
+}
+
+/// Returns true iff the console UART has been initialized.
+pub fn cons_inited() -> bool {
+    UART0_INITED.load(Ordering::Acquire)
+}
+
+/// Returns the address of the fallback UART's MMIO registers.
+pub(crate) fn fallback_addr() -> usize {
+    Device::Uart1.addr()
+}
+
+/// Returns the secondary UART, initializing it for 115200 8N1
+/// without flow control if it has not been already.  This is
+/// used as a fallback diagnostic sink on the panic path, and so
+/// does as little as possible.  Returns `None` while the UART is
+/// claimed for the SP or for transfers, as reinitializing it
+/// would break the link on the far end, and if we do not know
+/// how to route its pins on this system.
+///
+/// # Safety
+/// The caller must ensure that MMIO space for the UART and IO
+/// mux are mapped.
+pub unsafe fn fallback() -> Option<Uart> {
+    if UART1_CLAIMED.load(Ordering::Acquire) {
+        return None;
+    }
+    if !UART1_INITED.swap(true, Ordering::AcqRel) {
+        if !unsafe { crate::iomux::init_fallback_uart() } {
+            UART1_INITED.store(false, Ordering::Release);
+            return None;
+        }
+        Device::Uart1.init_minimal(Line::B115200_8N1);
+    }
+    Some(Uart(Device::Uart1))
+}
+
+/// Returns UART 1, initialized at 3Mbaud 8N1 with hardware
+/// flow control, or `None` if we do not know how to route its
+/// pins on this system.  The UART is claimed, and the fallback
+/// diagnostic path leaves it alone until it is released.
+///
+/// # Safety
+/// The caller must ensure that MMIO space for the UART and IO
+/// mux are mapped, and that nothing else is using UART 1.
+unsafe fn uart1() -> Option<Uart> {
+    if !unsafe { crate::iomux::init_fallback_uart() } {
+        return None;
+    }
+    UART1_CLAIMED.store(true, Ordering::Release);
+    UART1_INITED.store(false, Ordering::Release);
+    Device::Uart1.init(Line::B3M_8N1);
+    Some(Uart(Device::Uart1))
+}
+
+/// Releases UART 1, once claimed for transfers, to the fallback
+/// diagnostic path, which will reinitialize it for itself if it
+/// is ever needed.
+pub fn release(uart: Uart) {
+    if let Device::Uart1 = uart.0 {
+        UART1_CLAIMED.store(false, Ordering::Release);
+    }
+}
+
+/// Returns UART 1, initialized for the SP's IPCC channel.
+///
+/// # Safety
+/// As for `uart1`.
+pub unsafe fn sp() -> Option<Uart> {
+    unsafe { uart1() }
+}
+
+/// Returns UART 1, initialized as a channel for file transfers
+/// separate from the console.  UARTs 2 and 3 are not offered, as
+/// their pins are shared with the flow control lines of UARTs 0
+/// and 1.
+///
+/// # Safety
+/// As for `uart1`.
+pub unsafe fn xfer() -> Option<Uart> {
+    unsafe { uart1() }
+}
+
 /// Initializes the console UART.
 ///
 /// # Safety
@@ -612,7 +1211,7 @@ pub fn cons() -> Uart {
 /// properly mapped before calling this.
 pub unsafe fn init() {
     if !UART0_INITED.swap(true, Ordering::AcqRel) {
-        Device::Uart0.init(Rate::B3M, Datas::Bits8, Stops::Stop1, Parity::No);
+        Device::Uart0.init(cons_line());
     }
     UART1_INITED.store(false, Ordering::Release);
     UART2_INITED.store(false, Ordering::Release);
@@ -635,11 +1234,58 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// A simple print!(), writing to the enabled console sinks.
 #[macro_export]
 macro_rules! print {
-    ($($args:tt)*) => ({
-        use core::fmt::Write;
-        let mut cons = $crate::uart::cons();
-        cons.write_fmt(format_args!($($args)*)).unwrap();
-    })
+    ($($args:tt)*) => ($crate::sink::print(format_args!($($args)*)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fakes::{self, Rx};
+
+    #[test]
+    fn lines() {
+        assert_eq!(Line::B3M_8N1.to_string(), "3000000 8N1");
+        assert_eq!(Line::B115200_8N1.to_string(), "115200 8N1");
+        let line = Line::B3M_8N1.with_baud(115200).unwrap();
+        assert_eq!(line, Line::B115200_8N1);
+        let line = line.with_framing("7e2").unwrap();
+        assert_eq!(line.to_string(), "115200 7E2");
+        assert_eq!(Line::B3M_8N1.with_baud(1_500_000).unwrap().divisor, 2);
+        assert_eq!(Line::B3M_8N1.with_baud(9600).unwrap().divisor, 313);
+        // 921600 would be 1Mbaud, 8.5% fast.
+        assert!(Line::B3M_8N1.with_baud(921_600).is_err());
+        assert!(Line::B3M_8N1.with_baud(0).is_err());
+        assert!(Line::B3M_8N1.with_baud(6_000_000).is_err());
+        assert!(Line::B3M_8N1.with_baud(45).is_err());
+        assert!(Line::B3M_8N1.with_framing("9N1").is_err());
+        assert!(Line::B3M_8N1.with_framing("8X1").is_err());
+        assert!(Line::B3M_8N1.with_framing("8N").is_err());
+        assert_eq!(Lcr(0).with_parity(Parity::Odd).0, 1 << 3);
+        assert_eq!(Lcr(0).with_parity(Parity::Even).0, 3 << 3);
+    }
+
+    #[test]
+    fn soft_flow() {
+        let mut uart = fakes::console([
+            Rx::bytes(&[b'a', XOFF]),
+            Rx::Idle(Duration::from_millis(10)),
+            Rx::bytes(&[XON, b'b']),
+        ]);
+        assert!(!set_soft_flow(true));
+        uart.putb(b'x');
+        assert!(fakes::exhausted());
+        assert_eq!(fakes::transmitted(), b"x");
+        assert_eq!(uart.getb(), b'a');
+        assert_eq!(uart.getb(), b'b');
+        assert!(set_soft_flow(false));
+
+        let mut uart = fakes::console([Rx::bytes(&[XOFF, b'c'])]);
+        assert_eq!(uart.getb(), XOFF);
+        uart.putb(b'y');
+        assert_eq!(fakes::transmitted(), b"y");
+        assert_eq!(uart.getb(), b'c');
+    }
 }
@@ -43,7 +43,7 @@
 use crate::io;
 use crate::println;
 use crate::ramdisk::{self, FileType};
-use crate::result::{Error, Result};
+use crate::result::{Error, Result, ResultExt};
 
 use core::cmp;
 use core::fmt::{self, Write};
@@ -53,7 +53,9 @@ use core::ptr;
 
 use alloc::boxed::Box;
 use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::vec;
+use alloc::vec::Vec;
 use bitflags::bitflags;
 use bitstruct::bitstruct;
 use static_assertions::const_assert;
@@ -441,12 +443,21 @@ struct InnerFileSystem {
 pub struct FileSystem(Rc<InnerFileSystem>);
 
 impl FileSystem {
+    /// Returns a read-only file system over the given memory.
     pub fn new(sd: &[u8]) -> Result<FileSystem> {
         let sb = SuperBlock::read(sd)?;
         let sd = unsafe { io::Sd::from_slice(sd) };
         Ok(FileSystem(Rc::new(InnerFileSystem { sd, sb })))
     }
 
+    /// Returns a file system over the given memory, whose files
+    /// may be written in place.
+    pub fn new_mut(sd: &mut [u8]) -> Result<FileSystem> {
+        let sb = SuperBlock::read(sd)?;
+        let sd = unsafe { io::Sd::from_mut_slice(sd) };
+        Ok(FileSystem(Rc::new(InnerFileSystem { sd, sb })))
+    }
+
     pub fn root_inode(&self) -> Inode {
         Inode::new(self, ROOT_INODE).expect("root inode exists")
     }
@@ -542,12 +553,14 @@ impl FileSystem {
                 break;
             }
             let dir = Directory::try_new(ip.clone()).ok_or(Error::FsInvPath)?;
-            let mut tip =
-                if let Some(entry) = dir.iter().find(|d| d.name() == dirname) {
-                    self.inode(entry.ino())
-                } else {
-                    Err(Error::FsNoFile)
-                }?;
+            let mut tip = if let Some(entry) =
+                dir.iter().find(|d| d.name() == dirname)
+            {
+                self.inode(entry.ino())
+            } else {
+                Err(Error::FsNoFile
+                    .context("lookup in directory inode", &[ip.ino().into()]))
+            }?;
             if tip.file_type() == FileType::SymLink {
                 let mut lpath = vec![0u8; tip.size()];
                 tip.read(0, &mut lpath).expect("read symlink");
@@ -606,6 +619,25 @@ impl Block {
             }
         }
     }
+
+    /// Writes to the block in place.  Holes have no storage, and
+    /// so cannot be written, and nor can blocks of a file system
+    /// mounted read-only.
+    fn write(&self, offset: usize, src: &[u8]) -> Result<usize> {
+        match self {
+            Self::Hole(_) => Err(Error::FsHole),
+            Self::Sd(sd) => {
+                let ptr = sd.data_mut().ok_or(Error::FsReadOnly)?;
+                let len = sd.len();
+                assert!(offset < len);
+                let count = cmp::min(src.len(), len - offset);
+                unsafe {
+                    ptr::copy(src.as_ptr(), ptr.wrapping_add(offset), count);
+                }
+                Ok(count)
+            }
+        }
+    }
 }
 
 /// This block of constants provides the traditional Unix names
@@ -620,27 +652,6 @@ const IFSHAD: u8 = 0o13;
 const IFSOCK: u8 = 0o14;
 const IFATTRDIR: u8 = 0o16;
 
-impl FileType {
-    /// Returns a single character that represents the file
-    /// type, such as 'd' for directories, or '-' for regular
-    /// files.  These are mostly the characters one would see in
-    /// the output of `ls -l`.
-    fn as_char(self) -> char {
-        match self {
-            FileType::Unused => 'X',
-            FileType::Fifo => 'p',
-            FileType::Char => 'c',
-            FileType::Dir => 'd',
-            FileType::Block => 'b',
-            FileType::Regular => '-',
-            FileType::SymLink => 'l',
-            FileType::ShadowInode => 'I',
-            FileType::Sock => 's',
-            FileType::AttrDir => 'A',
-        }
-    }
-}
-
 bitstruct! {
     /// The parsed representation of the mode field from an
     /// inode.  Note that each permission bit is broken out into
@@ -758,6 +769,12 @@ impl Inode {
         self.dinode.lsize as usize
     }
 
+    /// Returns the number of bytes of storage allocated to the
+    /// file, including indirect blocks, but not its holes.
+    pub fn allocated(&self) -> usize {
+        self.dinode.blocks as usize * DEV_BLOCK_SIZE
+    }
+
     /// Returns the number of links to this file.
     pub fn nlink(&self) -> u16 {
         self.dinode.nlink
@@ -789,30 +806,105 @@ impl Inode {
     pub fn read(&self, off: u64, buf: &mut [u8]) -> Result<usize> {
         let off = off as usize;
         if off > MAX_OFFSET {
-            return Err(Error::FsOffset);
+            return Err(Error::FsOffset
+                .context("inode,offset", &[self.ino.into(), off as u64]));
         }
         if off > self.size() {
             return Ok(0);
         }
-        let fragsize = self.fs.fragsize();
         let n = core::cmp::min(buf.len(), self.size() - off);
         let mut nread = 0;
         while nread < n {
-            let block = self.bmap((nread + off).try_into().unwrap())?;
-            nread += block.read(off % fragsize, &mut buf[nread..]);
+            let boff = (nread + off).try_into().unwrap();
+            let run = self
+                .bmap_run(boff, n - nread)
+                .context("inode,offset", &[self.ino.into(), boff])?;
+            nread += run.read(0, &mut buf[nread..n]);
         }
         Ok(n)
     }
 
+    /// Writes to an inode, in place.  Only storage already
+    /// allocated to the file is written: the file cannot grow,
+    /// nor can its holes be filled, as either would require
+    /// allocating blocks.  The file's metadata is unchanged.
+    pub fn write(&self, off: u64, buf: &[u8]) -> Result<usize> {
+        let off = off as usize;
+        let context = |e: Error| {
+            e.context("inode,offset", &[self.ino.into(), off as u64])
+        };
+        let end = off
+            .checked_add(buf.len())
+            .filter(|&end| end <= self.size())
+            .ok_or_else(|| context(Error::FsOffset))?;
+        // Look for holes first, so that a write that cannot be
+        // completed is not begun.
+        let fragsize = self.fs.fragsize();
+        let mut frag = off - off % fragsize;
+        while frag < end {
+            if let Block::Hole(_) = self.bmap(frag as u64).map_err(context)? {
+                return Err(context(Error::FsHole));
+            }
+            frag += fragsize;
+        }
+        let mut nwritten = 0;
+        while nwritten < buf.len() {
+            let boff = nwritten + off;
+            let block = self.bmap(boff as u64).map_err(context)?;
+            nwritten += block.write(boff % fragsize, &buf[nwritten..])?;
+        }
+        Ok(nwritten)
+    }
+
     /// Maps a byte offset in some file into a fragment-sized block
     /// from the the storage device.
     fn bmap(&self, off: u64) -> Result<Block> {
         let fs = &self.fs;
-        let lbn = self.fs.logical_blockno(off);
+        let Some(sdbn) = self.bmap_block(fs.logical_blockno(off))? else {
+            return Ok(Block::Hole(fs.fragsize()));
+        };
+        let offset = (sdbn + fs.logical_block_fragno(off)) * fs.fragsize();
+        Ok(Block::Sd(fs.subset(offset, fs.fragsize())))
+    }
+
+    /// Maps a byte offset in some file into the longest run of
+    /// storage, up to `max` bytes, that holds it and what follows
+    /// it contiguously, or into the run of holes that follows it.
+    /// Files are mostly laid out in order, so large reads become
+    /// a few large copies, rather than one per fragment.
+    fn bmap_run(&self, off: u64, max: usize) -> Result<Block> {
+        let fs = &self.fs;
+        let bsize = fs.blocksize();
+        let frags = bsize / fs.fragsize();
+        let lbn = fs.logical_blockno(off);
+        let first = self.bmap_block(lbn)?;
+        let within = off as usize % bsize;
+        let mut len = bsize - within;
+        let mut next = lbn + 1;
+        while len < max
+            && self.bmap_block(next)?
+                == first.map(|sdbn| sdbn + (next - lbn) * frags)
+        {
+            len += bsize;
+            next += 1;
+        }
+        let len = cmp::min(len, max);
+        Ok(match first {
+            None => Block::Hole(len),
+            Some(sdbn) => {
+                Block::Sd(fs.subset(sdbn * fs.fragsize() + within, len))
+            }
+        })
+    }
+
+    /// Maps a logical block number in some file into the number of
+    /// its first fragment on the storage device, or `None` if the
+    /// block is a hole.
+    fn bmap_block(&self, lbn: usize) -> Result<Option<usize>> {
+        let fs = &self.fs;
         if lbn < NDADDR {
             let sdbn = self.dinode.dblocks[lbn] as usize;
-            let offset = (sdbn + fs.logical_block_fragno(off)) * fs.fragsize();
-            return Ok(Block::Sd(fs.subset(offset, fs.fragsize())));
+            return Ok((sdbn != 0).then_some(sdbn));
         }
         let mut lbn = lbn - NDADDR;
         let mut indir_span = 1;
@@ -833,7 +925,7 @@ impl Inode {
         for _ in 0..=indir_depth {
             let dblockno = fs.frags_to_sdblock(nb as usize);
             if dblockno == 0 {
-                return Ok(Block::Hole(fs.fragsize()));
+                return Ok(None);
             }
             indir_span /= fs.indir_span_per_block();
             let dboff = (lbn / indir_span) % fs.indir_span_per_block();
@@ -843,12 +935,10 @@ impl Inode {
             };
             nb = u32::from_ne_bytes([bs[0], bs[1], bs[2], bs[3]]);
             if nb == 0 {
-                return Ok(Block::Hole(fs.fragsize()));
+                return Ok(None);
             }
         }
-        let sdbn = nb as usize;
-        let offset = (sdbn + fs.logical_block_fragno(off)) * fs.fragsize();
-        Ok(Block::Sd(self.fs.subset(offset, fs.fragsize())))
+        Ok(Some(nb as usize))
     }
 
     pub fn mode(&self) -> Mode {
@@ -878,6 +968,26 @@ impl ramdisk::File for Inode {
     fn file_type(&self) -> FileType {
         self.file_type()
     }
+
+    fn write(&self, offset: u64, src: &[u8]) -> Result<usize> {
+        self.write(offset, src)
+    }
+
+    /// Finds the extent by mapping the run of storage or holes
+    /// at the offset.  Runs of storage end where the file's
+    /// blocks are not contiguous, so an extent of data may be
+    /// followed by another.
+    fn extent(&self, offset: u64, max: usize) -> Result<ramdisk::Extent> {
+        let off = offset as usize;
+        let end = usize::min(self.size(), off.saturating_add(max));
+        if off >= end {
+            return Ok(ramdisk::Extent::Data(0));
+        }
+        Ok(match self.bmap_run(offset, end - off)? {
+            Block::Hole(len) => ramdisk::Extent::Hole(len),
+            Block::Sd(sd) => ramdisk::Extent::Data(sd.len()),
+        })
+    }
 }
 
 impl ramdisk::FileSystem for FileSystem {
@@ -885,48 +995,122 @@ impl ramdisk::FileSystem for FileSystem {
         Ok(Box::new(self.namei(path.as_bytes())?))
     }
 
-    fn list(&self, path: &str) -> Result<()> {
-        list(self, path, self.namei(path.as_bytes())?)
+    fn stat(&self, path: &str) -> Result<ramdisk::DirEntry> {
+        Ok(dirent(&self.namei(path.as_bytes())?, path.as_bytes()))
     }
 
-    fn as_str(&self) -> &str {
-        "UFS"
+    fn readdir(&self, path: &str) -> Result<Vec<ramdisk::DirEntry>> {
+        let file = self.namei(path.as_bytes())?;
+        if file.file_type() != FileType::Dir {
+            return Ok(vec![dirent(&file, path.as_bytes())]);
+        }
+        let mut entries = Vec::new();
+        for dentry in Directory::new(file).iter() {
+            let ino = dentry.ino();
+            match self.inode(ino) {
+                Ok(file) => entries.push(dirent(&file, dentry.name())),
+                Err(e) => println!("ls: failed dir ent for ino #{ino}: {e:?}"),
+            }
+        }
+        Ok(entries)
     }
-}
 
-/// Lists a file, in a manner similar to `ls`.
-pub fn list(fs: &FileSystem, path: &str, file: Inode) -> Result<()> {
-    if file.file_type() == FileType::Dir {
-        lsdir(fs, &Directory::new(file));
-    } else {
-        lsfile(&file, path.as_bytes());
+    fn as_str(&self) -> &str {
+        "UFS"
     }
-    Ok(())
 }
 
-fn lsdir(fs: &FileSystem, dir: &Directory) {
-    for dentry in dir.iter() {
-        let ino = dentry.ino();
-        match fs.inode(ino) {
-            Ok(file) => lsfile(&file, dentry.name()),
-            Err(e) => println!("ls: failed dir ent for ino #{ino}: {e:?}"),
-        }
+/// Returns the file system independent metadata for an inode.
+fn dirent(file: &Inode, name: &[u8]) -> ramdisk::DirEntry {
+    ramdisk::DirEntry {
+        name: String::from_utf8_lossy(name).into_owned(),
+        ino: file.ino().into(),
+        file_type: file.file_type(),
+        perms: file.mode().0 & 0o7777,
+        nlink: file.nlink().into(),
+        uid: file.uid(),
+        gid: file.gid(),
+        size: file.size(),
+        allocated: file.allocated(),
     }
 }
 
-fn lsfile(file: &Inode, name: &[u8]) {
-    println!(
-        "#{ino:<4} {mode:?} {nlink:<2} {uid:<3} {gid:<3} {size:>8} {name}",
-        mode = file.mode(),
-        ino = file.ino(),
-        nlink = file.nlink(),
-        uid = file.uid(),
-        gid = file.gid(),
-        size = file.size(),
-        name = unsafe { core::str::from_utf8_unchecked(name) }
-    );
-}
-
 mod dir;
 
 pub use dir::Directory;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_writes() {
+        let mut storage = [0u8; 8];
+        let sd = unsafe { io::Sd::from_mut_slice(&mut storage) };
+        let block = Block::Sd(sd.subset(4, 4));
+        assert_eq!(block.write(1, b"abcdef"), Ok(3));
+        assert_eq!(block.write(0, b"z"), Ok(1));
+        let mut dst = [0u8; 4];
+        assert_eq!(block.read(0, &mut dst), 4);
+        assert_eq!(&dst, b"zabc");
+        assert_eq!(Block::Hole(4).write(0, b"x"), Err(Error::FsHole));
+        assert_eq!(storage, [0, 0, 0, 0, b'z', b'a', b'b', b'c']);
+        let sd = unsafe { io::Sd::from_slice(&storage) };
+        let block = Block::Sd(sd.subset(4, 4));
+        assert_eq!(block.write(0, b"y"), Err(Error::FsReadOnly));
+    }
+
+    #[test]
+    fn runs() {
+        // 1KiB blocks of two fragments; block 3 is a hole, and
+        // the rest are contiguous in runs of blocks 0-2, 4, and
+        // 5-12, with 12 mapped through the indirect block.
+        const FSIZE: usize = DEV_BLOCK_SIZE;
+        let layout = [8, 10, 12, 0, 20, 30, 32, 34, 36, 38, 40, 42, 44, 70];
+        let mut storage =
+            (0..64 * 1024).map(|k| (k % 251) as u8).collect::<Vec<u8>>();
+        let indir = 60 * FSIZE;
+        storage[indir..indir + 4].copy_from_slice(&44u32.to_ne_bytes());
+        storage[indir + 4..indir + 8].copy_from_slice(&70u32.to_ne_bytes());
+        let mut sb: SuperBlock = unsafe { mem::zeroed() };
+        sb.bsize = 1024;
+        sb.fsize = FSIZE as u32;
+        sb.frag = 2;
+        sb.bshift = 10;
+        sb.nindir = 256;
+        let sd = unsafe { io::Sd::from_slice(&storage) };
+        let fs = FileSystem(Rc::new(InnerFileSystem { sd, sb }));
+        let mut dinode: DInode = unsafe { mem::zeroed() };
+        dinode.lsize = (layout.len() * 1024 - 300) as u64;
+        dinode.dblocks.copy_from_slice(&layout[..NDADDR]);
+        dinode.iblocks[0] = 60;
+        let inode = Inode { dinode, ino: 3, fs };
+
+        let size = inode.size();
+        let mut expected = vec![0u8; layout.len() * 1024];
+        for (block, &frag) in layout.iter().enumerate() {
+            if frag != 0 {
+                let src = frag as usize * FSIZE;
+                expected[block * 1024..][..1024]
+                    .copy_from_slice(&storage[src..src + 1024]);
+            }
+        }
+        expected.truncate(size);
+        let mut buf = vec![0xffu8; size + 10];
+        assert_eq!(inode.read(0, &mut buf).unwrap(), size);
+        assert_eq!(&buf[..size], &expected[..]);
+        let mut buf = vec![0xffu8; 5000];
+        assert_eq!(inode.read(700, &mut buf).unwrap(), 5000);
+        assert_eq!(&buf[..], &expected[700..5700]);
+
+        use ramdisk::{Extent, File as _};
+        let extent = |off| inode.extent(off, usize::MAX).unwrap();
+        assert_eq!(extent(0), Extent::Data(3 * 1024));
+        assert_eq!(extent(1000), Extent::Data(3 * 1024 - 1000));
+        assert_eq!(extent(3 * 1024 + 10), Extent::Hole(1024 - 10));
+        assert_eq!(extent(4 * 1024), Extent::Data(1024));
+        assert_eq!(extent(5 * 1024), Extent::Data(8 * 1024));
+        assert_eq!(extent(13 * 1024), Extent::Data(1024 - 300));
+        assert_eq!(inode.extent(100, 50).unwrap(), Extent::Data(50));
+    }
+}
@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The FCH watchdog timer.
+//!
+//! If firmware leaves the watchdog running when we are entered,
+//! it will reset the machine unless it is triggered periodically.
+//! We note whether it is running at init, and if so, long
+//! running operations pet it as they poll the console.  The
+//! registers sit in the ACPI MMIO block, in the same page as the
+//! IO mux; their layout is that used by Linux's sp5100_tco
+//! driver for the FCH in Zen processors.
+
+use crate::bldb;
+use bitstruct::bitstruct;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The offset of the watchdog's registers within the IO mux page.
+const WDT_OFFSET: usize = 0xb00;
+
+bitstruct! {
+    /// The watchdog control register.
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct Control(pub u32) {
+        pub running: bool = 0;
+        pub fired: bool = 1;
+        pub reset_action: bool = 2;
+        pub disabled: bool = 3;
+        pub trigger: bool = 7;
+    }
+}
+
+/// Whether the watchdog was running at init, and so must be
+/// petted.
+static PETTING: AtomicBool = AtomicBool::new(false);
+
+fn control_ptr() -> *mut Control {
+    let addr = bldb::iomux_page_addr().addr() + WDT_OFFSET;
+    ptr::with_exposed_provenance_mut(addr)
+}
+
+/// Notes whether the watchdog is running, and so must be petted.
+///
+/// # Safety
+/// The caller must ensure that the IO mux page is mapped.
+pub(crate) unsafe fn init() {
+    let control = unsafe { ptr::read_volatile(control_ptr()) };
+    PETTING.store(control.running() && !control.disabled(), Ordering::Relaxed);
+}
+
+/// Returns true iff the watchdog was found running at init.
+pub(crate) fn petting() -> bool {
+    PETTING.load(Ordering::Relaxed)
+}
+
+/// Stops the watchdog, after which it is no longer petted.
+///
+/// # Safety
+/// The caller must ensure that the IO mux page is mapped.
+pub(crate) unsafe fn disable() {
+    unsafe {
+        let control = ptr::read_volatile(control_ptr());
+        ptr::write_volatile(control_ptr(), control.with_running(false));
+    }
+    PETTING.store(false, Ordering::Relaxed);
+}
+
+/// Restarts the watchdog's count, if it is being petted.
+pub(crate) fn pet() {
+    if !petting() {
+        return;
+    }
+    unsafe {
+        let control = ptr::read_volatile(control_ptr());
+        ptr::write_volatile(control_ptr(), control.with_trigger(true));
+    }
+}
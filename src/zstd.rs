@@ -0,0 +1,187 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Streaming decompression of zstd-compressed files.
+//!
+//! As with gzip, a `Reader` wraps a compressed file and presents
+//! its decompressed contents through `io::Read`, decompressing
+//! on demand.  Reads that go backwards restart decompression
+//! from the beginning of the frame.  Only files holding a single
+//! frame that records its decompressed size, as the `zstd` tool
+//! writes by default, are supported.
+
+use crate::io::Read;
+use crate::mem;
+use crate::result::{Error, Result, ResultExt};
+use core::cell::RefCell;
+use ruzstd::decoding::{FrameDecoder, StreamingDecoder};
+use ruzstd::io::Read as _;
+use static_assertions::const_assert;
+
+/// The size of the buffer that data skipped over by a forward
+/// seek is decompressed into.
+const SKIP_LEN: usize = 1024;
+const_assert!(SKIP_LEN <= mem::MAX_STACK_BUF);
+
+/// Adapts a file to the reader the decoder consumes, which reads
+/// sequentially.
+struct Source<'a> {
+    file: &'a dyn Read,
+    pos: usize,
+}
+
+impl ruzstd::io::Read for Source<'_> {
+    fn read(
+        &mut self,
+        buf: &mut [u8],
+    ) -> core::result::Result<usize, ruzstd::io::Error> {
+        let n = self.file.read(self.pos as u64, buf).map_err(|_| {
+            ruzstd::io::Error::from(ruzstd::io::ErrorKind::Other)
+        })?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The state of an in-progress decompression.
+struct Stream<'a> {
+    decoder: StreamingDecoder<Source<'a>, FrameDecoder>,
+    /// The offset in the decompressed file of the next byte the
+    /// decoder yields.
+    pos: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn new(file: &'a dyn Read) -> Result<Stream<'a>> {
+        let source = Source { file, pos: 0 };
+        let decoder = StreamingDecoder::new(source)
+            .map_err(|_| Error::Decompress("zstd: bad frame header"))?;
+        Ok(Stream { decoder, pos: 0 })
+    }
+
+    /// Decompresses the next data into `dst`, returning the
+    /// number of bytes written, which is zero at the end of the
+    /// frame.
+    fn fill(&mut self, dst: &mut [u8]) -> Result<usize> {
+        let n = self
+            .decoder
+            .read(dst)
+            .map_err(|_| Error::Decompress("zstd: corrupt data"))
+            .context("decompressing at offset", &[self.pos as u64])?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A reader presenting the decompressed contents of a zstd
+/// file.
+pub(crate) struct Reader<'a> {
+    file: &'a dyn Read,
+    size: usize,
+    stream: RefCell<Stream<'a>>,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(file: &'a dyn Read) -> Result<Reader<'a>> {
+        let stream = Stream::new(file)?;
+        let size = stream.decoder.decoder.content_size();
+        if size == 0 {
+            return Err(Error::Decompress("zstd: no decompressed size"));
+        }
+        let size = usize::try_from(size)
+            .map_err(|_| Error::Decompress("zstd: file too large"))?;
+        Ok(Reader { file, size, stream: RefCell::new(stream) })
+    }
+}
+
+impl Read for Reader<'_> {
+    fn read(&self, off: u64, dst: &mut [u8]) -> Result<usize> {
+        let off = off as usize;
+        let mut s = self.stream.borrow_mut();
+        if off < s.pos {
+            *s = Stream::new(self.file)?;
+        }
+        let mut skip = [0u8; SKIP_LEN];
+        while s.pos < off {
+            let len = usize::min(off - s.pos, SKIP_LEN);
+            if s.fill(&mut skip[..len])? == 0 {
+                return Ok(0);
+            }
+        }
+        let mut nread = 0;
+        while nread < dst.len() {
+            let n = s.fill(&mut dst[nread..])?;
+            if n == 0 {
+                break;
+            }
+            nread += n;
+        }
+        Ok(nread)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use ruzstd::encoding::{CompressionLevel, compress_to_vec};
+
+    /// Frame header descriptor bits giving the width of the
+    /// content size field, and whether the frame is a single
+    /// segment, without a window descriptor.
+    const FCS_4: u8 = 2 << 6;
+    const SINGLE_SEGMENT: u8 = 1 << 5;
+
+    /// Compresses `data`, recording its size in the frame header,
+    /// which the encoder leaves out.
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        let mut zst = compress_to_vec(data, CompressionLevel::Fastest);
+        let fhd = zst[4];
+        assert_eq!(fhd & (FCS_4 | SINGLE_SEGMENT | 3), 0);
+        zst[4] |= FCS_4;
+        let size = (data.len() as u32).to_le_bytes();
+        zst.splice(6..6, size);
+        zst
+    }
+
+    fn data() -> Vec<u8> {
+        (0..100_000u64).map(|k| (k * k % 251) as u8).collect()
+    }
+
+    #[test]
+    fn read_forward_and_back() {
+        let data = data();
+        let zst = zstd(&data);
+        let file = zst.as_slice();
+        let reader = Reader::new(&file).unwrap();
+        assert_eq!(reader.size(), data.len());
+        let mut buf = [0u8; 1000];
+        assert_eq!(reader.read(50_000, &mut buf).unwrap(), buf.len());
+        assert_eq!(&buf[..], &data[50_000..51_000]);
+        assert_eq!(reader.read(10, &mut buf).unwrap(), buf.len());
+        assert_eq!(&buf[..], &data[10..1010]);
+        assert_eq!(reader.read(99_500, &mut buf).unwrap(), 500);
+        assert_eq!(&buf[..500], &data[99_500..]);
+        assert_eq!(reader.read(200_000, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_unsized_and_corrupt() {
+        let data = data();
+        let zst = compress_to_vec(data.as_slice(), CompressionLevel::Fastest);
+        let file = zst.as_slice();
+        assert!(Reader::new(&file).is_err());
+        let mut zst = zstd(&data);
+        let len = zst.len();
+        zst.truncate(len / 2);
+        let file = zst.as_slice();
+        let reader = Reader::new(&file).unwrap();
+        let mut buf = vec![0u8; data.len()];
+        assert!(reader.read(0, &mut buf).is_err());
+    }
+}
@@ -7,7 +7,10 @@
 //!
 use clap::Parser;
 use duct::cmd;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
 use std::path::Path;
 
 #[derive(Parser)]
@@ -33,6 +36,20 @@ enum Command {
         #[clap(flatten)]
         features: Features,
     },
+    /// Runs the host-testable modules' tests under Miri and
+    /// AddressSanitizer
+    CheckUb {
+        #[clap(flatten)]
+        locked: Locked,
+
+        /// Skip the Miri run
+        #[clap(long)]
+        no_miri: bool,
+
+        /// Skip the AddressSanitizer run
+        #[clap(long)]
+        no_asan: bool,
+    },
     /// cargo clean
     Clean,
     /// Run cargo clippy linter
@@ -55,8 +72,24 @@ enum Command {
         #[clap(long)]
         source: bool,
     },
+    /// Build bldb and package it as a raw image, with its
+    /// identity record filled in, and a manifest
+    Dist {
+        #[clap(flatten)]
+        profile: BuildProfile,
+        #[clap(flatten)]
+        locked: Locked,
+        #[clap(flatten)]
+        features: Features,
+    },
     /// Expand macros
     Expand,
+    /// Run the REPL on the host against simulated hardware,
+    /// reading commands from standard input
+    Sim {
+        #[clap(flatten)]
+        features: Features,
+    },
     /// Run unit tests
     Test {
         #[clap(flatten)]
@@ -110,21 +143,28 @@ impl Locked {
     }
 }
 
-/// Cargo `--features` setting.
+/// Cargo `--features` and `--no-default-features` settings.
 #[derive(Parser)]
 struct Features {
     #[clap(long)]
     features: Option<String>,
+    /// Omit the default command groups
+    #[clap(long)]
+    no_default_features: bool,
 }
 
 impl Features {
-    // Returns the cargo argument corresponding to the given
+    // Returns the cargo arguments corresponding to the given
     // features.
     fn to_string(&self) -> String {
-        self.features
+        let features = self
+            .features
             .clone()
             .map(|features| format!("--features={}", features))
-            .unwrap_or("".into())
+            .unwrap_or("".into());
+        let defaults =
+            self.no_default_features.then_some("--no-default-features");
+        format!("{features} {}", defaults.unwrap_or(""))
     }
 }
 
@@ -140,8 +180,15 @@ fn main() {
         Command::Disasm { profile, locked, features, source } => {
             disasm(profile, locked, features, source)
         }
+        Command::Dist { profile, locked, features } => {
+            dist(profile, locked, features)
+        }
         Command::Expand => expand(),
         Command::Clippy { locked, features } => clippy(locked, features),
+        Command::CheckUb { locked, no_miri, no_asan } => {
+            check_ub(locked, !no_miri, !no_asan)
+        }
+        Command::Sim { features } => sim(features),
         Command::Clean => clean(),
     }
 }
@@ -170,6 +217,17 @@ fn test(profile: BuildProfile, locked: Locked, features: Features) {
     cmd(cargo(), args.split_whitespace()).run().expect("test successful");
 }
 
+/// Runs the REPL loopback simulator, which is built with the
+/// tests, interactively.
+fn sim(features: Features) {
+    let features = features.to_string();
+    let args = format!(
+        "test -q {features} --bin bldb repl::sim::tests::interactive \
+            -- --ignored --exact --nocapture"
+    );
+    cmd(cargo(), args.split_whitespace()).run().expect("sim successful");
+}
+
 /// Build and disassemble the bldb binary.
 fn disasm(
     profile: BuildProfile,
@@ -188,6 +246,127 @@ fn disasm(
         .expect("disassembly successful");
 }
 
+/// Marks the identity record in the image.  This must agree
+/// with `src/ident.rs`, as must the record's layout: the magic
+/// number, then the build ID, then the SHA-256 digest of the
+/// loader's text and read-only data.
+const IDENT_MAGIC: &[u8; 16] = b"bldb-ident-v1\0\0\0";
+const IDENT_BUILD_ID: usize = 16;
+const IDENT_DIGEST: usize = 32;
+
+/// Builds bldb and packages it for the ROM or `chain`.
+///
+/// The ELF binary is converted to a raw image spanning the
+/// loader from the start of its text to the end of the reset
+/// vector, which the ROM expects to find in the last 16 bytes.
+/// The identity record is then filled in, in both the raw image
+/// and a copy of the ELF binary, with a build ID derived from the
+/// image as linked, and the digest of the text and read-only
+/// data that `selfsum` checks.  Finally, a manifest describing
+/// the image is written alongside.
+fn dist(profile: BuildProfile, locked: Locked, features: Features) {
+    let feature_args = features.to_string();
+    build(profile.clone(), locked, features);
+    let triple = target();
+    let profile_dir = profile.dir();
+    let elf = Path::new("target").join(&triple).join(profile_dir).join("bldb");
+    let dist = elf.with_file_name("dist");
+    fs::create_dir_all(&dist).expect("created dist directory");
+    let bin = dist.join("bldb.bin");
+    cmd!(objcopy(), "-O", "binary", &elf, &bin)
+        .run()
+        .expect("objcopy successful");
+
+    let syms = symbols(&elf);
+    let sym = |name: &str| -> u64 {
+        *syms.get(name).unwrap_or_else(|| panic!("no symbol {name}"))
+    };
+    let (base, erodata) = (sym("__sloader"), sym("erodata"));
+    let (bootblock, reset, end) =
+        (sym("bootblock"), sym("reset"), sym("__eloader"));
+
+    // The image ends with the reset vector, whose last bytes
+    // objcopy does not emit if they are only fill.
+    let mut image = fs::read(&bin).expect("read raw image");
+    let len = usize::try_from(end - base).expect("image fits in memory");
+    assert!(image.len() <= len, "image overlaps end of loader");
+    image.resize(len, 0xff);
+    assert_eq!(end - reset, 16, "reset vector not at end of image");
+
+    let build_id = Sha256::digest(&image);
+    let readonly = usize::try_from(erodata - base).unwrap();
+    let digest = Sha256::digest(&image[..readonly]);
+    let mut record = [0u8; 64];
+    record[..16].copy_from_slice(IDENT_MAGIC);
+    record[IDENT_BUILD_ID..IDENT_DIGEST].copy_from_slice(&build_id[..16]);
+    record[IDENT_DIGEST..].copy_from_slice(&digest);
+    let offset = fill_ident(&mut image, &record);
+    assert!(offset >= readonly, "identity record in read-only data");
+    fs::write(&bin, &image).expect("wrote raw image");
+
+    let mut binary = fs::read(&elf).expect("read ELF binary");
+    fill_ident(&mut binary, &record);
+    fs::write(dist.join("bldb"), &binary).expect("wrote ELF binary");
+
+    let manifest = format!(
+        "image = \"bldb.bin\"\n\
+         elf = \"bldb\"\n\
+         profile = \"{profile}\"\n\
+         features = \"{features}\"\n\
+         size = {size:#x}\n\
+         load-addr = {base:#x}\n\
+         bootblock = {bootblock:#x}\n\
+         reset-vector = {reset:#x}\n\
+         ident-offset = {offset:#x}\n\
+         build-id = \"{build_id}\"\n\
+         readonly-sha256 = \"{digest}\"\n\
+         image-sha256 = \"{image_sum}\"\n",
+        profile = profile_dir.display(),
+        features =
+            feature_args.split_whitespace().collect::<Vec<_>>().join(" "),
+        size = image.len(),
+        build_id = hex(&build_id[..16]),
+        digest = hex(&digest),
+        image_sum = hex(&Sha256::digest(&image)),
+    );
+    let path = dist.join("bldb.manifest");
+    fs::write(&path, manifest).expect("wrote manifest");
+    println!("packaged {} in {}", hex(&build_id[..16]), dist.display());
+}
+
+/// Fills in the one identity record in the given bytes, and
+/// returns its offset.
+fn fill_ident(bytes: &mut [u8], record: &[u8; 64]) -> usize {
+    let mut found = bytes
+        .windows(IDENT_MAGIC.len())
+        .enumerate()
+        .filter_map(|(k, w)| (w == IDENT_MAGIC).then_some(k));
+    let offset = found.next().expect("identity record present");
+    assert!(found.next().is_none(), "identity record is unique");
+    let empty = &bytes[offset + IDENT_BUILD_ID..offset + record.len()];
+    assert!(empty.iter().all(|&b| b == 0), "identity record is empty");
+    bytes[offset..offset + record.len()].copy_from_slice(record);
+    offset
+}
+
+/// Returns the addresses of the symbols in the given ELF binary.
+fn symbols(elf: &Path) -> BTreeMap<String, u64> {
+    let out = cmd!(nm(), elf).read().expect("nm successful");
+    out.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let name = fields.nth(1)?;
+            Some((name.to_string(), addr))
+        })
+        .collect()
+}
+
+/// Formats bytes as hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
 /// Expands macros.
 fn expand() {
     cmd!(cargo(), "rustc", "--", "-Zunpretty=expanded")
@@ -203,6 +382,53 @@ fn clippy(locked: Locked, features: Features) {
     cmd(cargo(), args.split_whitespace()).run().expect("clippy successful");
 }
 
+/// Modules whose tests run on the host without touching
+/// hardware, and so may be run under Miri and the sanitizers.
+/// These are given to the test harness as filters.
+const UB_MODULES: &[&str] = &[
+    "allocator::",
+    "cpio::",
+    "crc32::",
+    "gzip::",
+    "mem::",
+    "mmu::",
+    "ramdisk::",
+    "repl::reader::",
+    "sink::",
+    "ufs::",
+];
+
+/// Runs the tests of the host-testable modules under Miri, to
+/// catch provenance and aliasing errors in unsafe code, and then
+/// under AddressSanitizer with debug assertions, to catch
+/// out-of-bounds accesses and leaks at full speed.
+fn check_ub(locked: Locked, miri: bool, asan: bool) {
+    let locked = locked.to_str();
+    let filters = UB_MODULES.join(" ");
+    if miri {
+        let flags = env_or("MIRIFLAGS", "-Zmiri-symbolic-alignment-check");
+        let args = format!("miri test {locked} -- {filters}");
+        cmd(cargo(), args.split_whitespace())
+            .env("MIRIFLAGS", flags)
+            .run()
+            .expect("miri successful");
+    }
+    if asan {
+        let host = host_target();
+        let args = format!(
+            "test {locked} \
+                -Z build-std \
+                --target {host} \
+                -- {filters}"
+        );
+        cmd(cargo(), args.split_whitespace())
+            .env("RUSTFLAGS", "-Zsanitizer=address -Cdebug-assertions=on")
+            .env("RUSTDOCFLAGS", "-Zsanitizer=address")
+            .run()
+            .expect("sanitized tests successful");
+    }
+}
+
 /// Runs clean on the project.
 fn clean() {
     cmd!(cargo(), "clean").run().expect("clean successful");
@@ -224,7 +450,23 @@ fn target() -> String {
     env_or("TARGET", "x86_64-oxide-none-elf")
 }
 
+/// Returns the target triple of the host, for sanitized test
+/// builds, which must name the target explicitly.
+fn host_target() -> String {
+    env_or("HOST_TARGET", "x86_64-unknown-linux-gnu")
+}
+
 /// Locates the LLVM objdump binary.
 fn objdump() -> String {
     env_or("OBJDUMP", "llvm-objdump".into())
 }
+
+/// Locates the LLVM objcopy binary.
+fn objcopy() -> String {
+    env_or("OBJCOPY", "llvm-objcopy")
+}
+
+/// Locates the LLVM nm binary.
+fn nm() -> String {
+    env_or("NM", "llvm-nm")
+}